@@ -51,14 +51,7 @@ fn bench_db_search_fts(c: &mut Criterion) {
 
     let options = SearchOptions {
         limit: Some(10),
-        start_time: None,
-        end_time: None,
-        file_types: None,
-        paths: None,
-        min_score: None,
-        recency_weight: None,
-        frequency_weight: None,
-        context_lines: None,
+        ..Default::default()
     };
 
     c.bench_function("db_search_hybrid_100_chunks", |b| {
@@ -90,14 +83,7 @@ fn bench_db_search_enhanced(c: &mut Criterion) {
 
     let options = SearchOptions {
         limit: Some(10),
-        start_time: None,
-        end_time: None,
-        file_types: None,
-        paths: None,
-        min_score: None,
-        recency_weight: None,
-        frequency_weight: None,
-        context_lines: None,
+        ..Default::default()
     };
 
     c.bench_function("db_search_enhanced_200_chunks_384d", |b| {
@@ -0,0 +1,118 @@
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use thiserror::Error;
+
+use crate::files::RangeError;
+use crate::paths::PathError;
+use crate::storage::StorageError;
+
+/// A failed request, answered with the status that fits it.
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    TooManyRequests(String),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Storage(e) => storage_status(e),
+            // Storage errors that passed through other modules keep theirs
+            Self::Other(e) => e
+                .downcast_ref::<StorageError>()
+                .map_or(StatusCode::INTERNAL_SERVER_ERROR, storage_status),
+        }
+    }
+}
+
+/// 503 for failures that may pass, like another process holding the lock,
+/// 507 when the disk is full, else 500.
+fn storage_status(e: &StorageError) -> StatusCode {
+    match e {
+        StorageError::Full(_) => StatusCode::INSUFFICIENT_STORAGE,
+        e if e.is_transient() => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            log::error!("Request failed: {:#}", self);
+        }
+        if status == StatusCode::SERVICE_UNAVAILABLE {
+            return (status, [(header::RETRY_AFTER, "1")], self.to_string()).into_response();
+        }
+        (status, self.to_string()).into_response()
+    }
+}
+
+impl From<PathError> for ApiError {
+    fn from(e: PathError) -> Self {
+        match e {
+            PathError::Forbidden(msg) => Self::Forbidden(msg),
+            PathError::NotFound(msg) => Self::NotFound(msg),
+        }
+    }
+}
+
+impl From<RangeError> for ApiError {
+    fn from(e: RangeError) -> Self {
+        match e {
+            RangeError::Forbidden(msg) => Self::Forbidden(msg),
+            RangeError::NotFound(msg) => Self::NotFound(msg),
+            RangeError::Invalid(msg) => Self::BadRequest(msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        let busy = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+            None,
+        );
+        let locked = ApiError::from(StorageError::from(busy));
+        assert_eq!(locked.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = locked.into_response();
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        // Wrapped on the way up, the storage error still decides
+        let wrapped = anyhow::Error::from(StorageError::WriterStopped).context("Search failed");
+        assert_eq!(
+            ApiError::from(wrapped).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let commit = anyhow::Error::from(StorageError::Commit("database is locked".into()));
+        assert_eq!(
+            ApiError::from(commit).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            ApiError::from(PathError::Forbidden("outside".into())).status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+}
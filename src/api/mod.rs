@@ -1,20 +1,81 @@
-use crate::indexer::embeddings::Embedder;
-use crate::storage::db::Database;
+mod error;
+mod users;
+
+pub use error::ApiError;
+pub use users::User;
+
+use crate::audit::AuditLog;
+use crate::citation::{Citation, Citer};
+use crate::collections::Models;
+use crate::config::{Config, ServerConfig};
+use crate::daemon;
+use crate::documents::{self, Document, IngestedDocument};
+use crate::editor::{self, EditorPosition};
+use crate::explain::{self, Explanation};
+use crate::files::{self, FileRange, RangeRequest};
+use crate::freshness::{self, Freshness};
+use crate::indexer::watcher::{self, WatcherStats};
+use crate::indexer::workers::WorkerPool;
+use crate::limits::{self, MemoryStats};
+use crate::migration::{MigrationProgress, Migrations};
+use crate::paths::{canonicalize, is_virtual_path, PathError, WatchRoots};
+use crate::reindex::{self, StaleFile};
+use crate::report::{self, QuerySummary};
+use crate::scan::{self, ScanProgress};
+use crate::search::{self, Hit, QueryService, SearchMode, SessionOptions};
+use crate::session::{RepeatMode, SessionStore};
+use crate::storage::db::{
+    summarize_tree, CollectionStats, Database, DirSummary, Eviction, Facets, FileHeat, IndexChange,
+    MetadataFilter, QueryProfile, QueryTimings, RankExplanation, SearchOptions, TagEntry,
+    TimingStats,
+};
+use crate::storage::StorageError;
+use crate::transcripts::{self, SavedTranscript, Transcript};
+use anyhow::Context;
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
-    routing::{get, post},
+    body::Body,
+    extract::{Extension, Json, Path, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
     Router,
 };
+use futures_util::stream;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+
+/// Media type that makes `/query` stream one result per line.
+const NDJSON: &str = "application/x-ndjson";
+
+/// Streamed lines buffered ahead of a slow client.
+const STREAM_BUFFER: usize = 32;
+
+/// Related chunks fetched for a user before those outside their scope are
+/// dropped.
+const RELATED_CANDIDATES: usize = 50;
+
+/// Evictions listed by `/stats`.
+const RECENT_EVICTIONS: usize = 10;
+
+/// Why a tag request without exactly one target is rejected.
+const TAG_TARGET: &str = "Give either a path or a chunk_id";
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Arc<Database>,
-    pub embedder: Arc<Embedder>,
+    pub models: Arc<Models>,
+    pub config: Arc<Config>,
+    pub sessions: Arc<SessionStore>,
     pub start_time: u64,
+    /// Set while a `POST /reindex` run is in progress
+    pub reindexing: Arc<AtomicBool>,
+    pub migrations: Arc<Migrations>,
+    pub audit: Arc<AuditLog>,
+    pub workers: Arc<WorkerPool>,
 }
 
 // ============================================================================
@@ -34,17 +95,92 @@ pub struct QueryRequest {
     #[serde(default)]
     pub paths: Option<Vec<String>>,
     pub min_score: Option<f32>,
+    #[serde(default)]
+    pub metadata_filters: Option<Vec<MetadataFilter>>,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// Only return chunks defining this symbol, e.g. `parse_config`
+    pub symbol: Option<String>,
+    /// Include facet counts for the matching chunks
+    #[serde(default)]
+    pub facets: bool,
+    /// Client session; chunks already returned in it are summarized or omitted
+    pub session_id: Option<String>,
+    /// Return chunks already seen in the session in full
+    #[serde(default)]
+    pub include_seen: bool,
+    #[serde(default)]
+    pub repeats: RepeatMode,
+    /// Attach a `path:start_line-end_line @ commit` citation to each result
+    #[serde(default)]
+    pub citations: bool,
+    /// `vector`, `hybrid` or `ensemble` (default: `[search] default_mode`)
+    pub mode: Option<SearchMode>,
+    /// Also search with the query's keywords and fuse the rankings; the same
+    /// as `"mode": "ensemble"`
+    #[serde(default)]
+    pub ensemble: bool,
+    /// A hypothetical answer to search with as well; implies `ensemble`
+    pub hypothetical: Option<String>,
+    /// Search the index as it was at this time (seconds since the epoch);
+    /// needs `[history] enabled`
+    pub as_of: Option<u64>,
+    /// Include where the query's time went, to diagnose slow queries
+    #[serde(default)]
+    pub debug: bool,
+    /// Annotate each result with why it ranked where it did
+    #[serde(default)]
+    pub explain: bool,
+    /// Only search this collection (`default` for files outside every
+    /// configured collection)
+    pub collection: Option<String>,
+    /// Also search chunks moved to the archive, by embedding only
+    #[serde(default)]
+    pub include_archived: bool,
 }
 
 #[derive(Serialize)]
 pub struct QueryResponse {
     pub results: Vec<QueryResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub facets: Option<Facets>,
+    /// The daemon's startup scan is still running, so results may be
+    /// missing; `/stats` shows its progress
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub warming_up: bool,
+    /// How current the index is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freshness: Option<Freshness>,
+    /// Time per search stage, when `debug` was set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<QueryProfileResponse>,
+}
+
+/// Where a `debug` query's time went. Facets are computed afterwards and
+/// not included.
+#[derive(Serialize)]
+pub struct QueryProfileResponse {
+    /// The whole search, including presenting the hits
+    pub total_ms: f64,
+    #[serde(flatten)]
+    pub stages: QueryTimings,
 }
 
 #[derive(Serialize)]
 pub struct QueryResult {
+    pub chunk_id: i64,
     pub content: String,
     pub score: f32,
+    /// Already returned earlier in the session (content is a summary)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub seen: bool,
+    /// Flagged by the staleness policy of the result's collection
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub citation: Option<Citation>,
     // Enhanced metadata
     #[serde(skip_serializing_if = "Option::is_none")]
     pub file_path: Option<String>,
@@ -52,6 +188,204 @@ pub struct QueryResult {
     pub file_type: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_modified: Option<u64>,
+    /// Lines of the chunk in its file, from 1, where known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_line: Option<usize>,
+    /// The chunk's stored metadata, such as its symbol or headings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    /// Why the result ranked where it did, when `explain` was set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<RankExplanation>,
+}
+
+impl From<Hit> for QueryResult {
+    fn from(hit: Hit) -> Self {
+        let (start_line, end_line) = (hit.line_start(), hit.line_end());
+        let r = hit.result;
+        QueryResult {
+            chunk_id: r.id,
+            content: r.content,
+            score: r.score,
+            seen: hit.seen,
+            stale: hit.stale_reason.is_some(),
+            stale_reason: hit.stale_reason,
+            citation: hit.citation,
+            file_path: Some(r.file_path),
+            file_type: Some(r.file_type),
+            last_modified: Some(r.last_modified),
+            start_line,
+            end_line,
+            metadata: r.metadata.and_then(|m| serde_json::from_str(&m).ok()),
+            explanation: r.explanation,
+        }
+    }
+}
+
+// ============================================================================
+// Chunk Types
+// ============================================================================
+
+/// A chunk fetched by its id from `GET /chunks/:id`
+#[derive(Serialize)]
+pub struct ChunkResponse {
+    pub chunk_id: i64,
+    pub file_path: String,
+    pub content: String,
+    pub start_offset: u64,
+    pub end_offset: u64,
+    pub last_modified: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    pub citation: Citation,
+}
+
+/// Body of `POST /open`
+#[derive(Deserialize)]
+pub struct OpenRequest {
+    pub chunk_id: i64,
+    /// Also run `[server] editor_command` on the position
+    #[serde(default)]
+    pub launch: bool,
+}
+
+#[derive(Serialize)]
+pub struct OpenResponse {
+    #[serde(flatten)]
+    pub position: EditorPosition,
+    pub launched: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RelatedParams {
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct RelatedResponse {
+    pub chunk_id: i64,
+    pub related: Vec<QueryResult>,
+}
+
+// ============================================================================
+// Tree Types
+// ============================================================================
+
+#[derive(Deserialize)]
+pub struct TreeParams {
+    /// Subtree to describe (default: everything indexed)
+    pub path: Option<String>,
+    /// Deepest directory level listed below `path`
+    pub depth: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct FileContentParams {
+    pub path: String,
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+    pub start_byte: Option<usize>,
+    pub end_byte: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ExplainParams {
+    pub path: String,
+}
+
+#[derive(Serialize)]
+pub struct TreeResponse {
+    pub path: String,
+    pub directories: Vec<DirSummary>,
+}
+
+// ============================================================================
+// Tag Types
+// ============================================================================
+
+/// Tag a path (file or directory) or a single chunk
+#[derive(Deserialize)]
+pub struct TagRequest {
+    pub tag: String,
+    pub path: Option<String>,
+    pub chunk_id: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct TagsResponse {
+    pub tags: Vec<TagEntry>,
+}
+
+// ============================================================================
+// Reindex Types
+// ============================================================================
+
+#[derive(Serialize)]
+pub struct ReindexPlanResponse {
+    pub files: Vec<StaleFile>,
+}
+
+#[derive(Serialize)]
+pub struct ReindexResponse {
+    /// Files queued for re-indexing
+    pub queued: usize,
+}
+
+/// Body of `POST /prioritize`
+#[derive(Deserialize)]
+pub struct PrioritizeRequest {
+    /// A file, or a directory whose files are all prioritized
+    pub path: String,
+}
+
+#[derive(Serialize)]
+pub struct PrioritizeResponse {
+    /// Files queued ahead of the backfill
+    pub queued: usize,
+}
+
+// ============================================================================
+// Changelog Types
+// ============================================================================
+
+/// Changes returned per request unless `limit` asks for fewer.
+const MAX_CHANGES: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct ChangesParams {
+    /// Return changes after this sequence number (default: from the start)
+    #[serde(default)]
+    pub since: i64,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct ChangesResponse {
+    pub changes: Vec<IndexChange>,
+    /// Pass as `since` to fetch the next page
+    pub next: i64,
+    /// Changes after `since` were pruned before they could be returned, so
+    /// the caller's copy of the index can't be brought up to date from the log
+    pub missed: bool,
+}
+
+/// Most files a heat map lists
+const MAX_HEATMAP_FILES: usize = 500;
+
+#[derive(Deserialize)]
+pub struct HeatmapParams {
+    /// Number of files listed (default: 20)
+    pub limit: Option<usize>,
+    /// Number of chunks listed per file (default: 5)
+    pub chunks: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct HeatmapResponse {
+    /// Files whose chunks queries returned, most returned first
+    pub files: Vec<FileHeat>,
 }
 
 // ============================================================================
@@ -64,6 +398,13 @@ pub struct HealthResponse {
     pub uptime_secs: u64,
 }
 
+#[derive(Serialize)]
+pub struct ReadyResponse {
+    pub ready: bool,
+    /// What keeps the daemon from indexing normally
+    pub reasons: Vec<String>,
+}
+
 #[derive(Serialize)]
 pub struct StatusResponse {
     pub status: String,
@@ -73,11 +414,71 @@ pub struct StatusResponse {
     pub database_size_bytes: u64,
 }
 
+#[derive(Deserialize)]
+pub struct StatsParams {
+    /// Number of slowest files listed (default: 10)
+    pub slowest: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    pub indexed_files: u64,
+    pub total_chunks: u64,
+    /// Chunks in the archive, not counted in `total_chunks`
+    pub archived_chunks: u64,
+    pub database_size_bytes: u64,
+    /// Time spent chunking, embedding and storing, so slow indexing can be
+    /// traced to parsing, inference or storage
+    pub indexing: TimingStats,
+    /// Daemon memory use against `[limits] max_memory_mb`
+    pub memory: MemoryStats,
+    /// Collections being re-embedded after a model change
+    pub migrations: Vec<MigrationProgress>,
+    /// The startup scan, while it runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scan: Option<ScanProgress>,
+    /// File watcher events since the daemon started
+    pub watcher: WatcherStats,
+    /// The caller's own usage, when they sent a user's key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<UserStats>,
+    /// Files most recently evicted by collection limits, newest first
+    pub evictions: Vec<Eviction>,
+    /// Files and chunks per collection
+    pub collections: Vec<CollectionStats>,
+}
+
+#[derive(Serialize)]
+pub struct UserStats {
+    pub name: String,
+    pub queries_today: u64,
+    /// `[server.users.<name>] queries_per_day`, if set
+    pub queries_per_day: Option<u64>,
+    /// Queries over the last 30 days
+    pub last_30_days: QuerySummary,
+}
+
 // ============================================================================
 // Server Setup
 // ============================================================================
 
-pub async fn run_server(db: Database, embedder: Arc<Embedder>, host: &str, port: u16) {
+/// Listen on `[server] host` and `port`; port 0 picks a free one.
+pub async fn bind(config: &ServerConfig) -> anyhow::Result<TcpListener> {
+    let addr = format!("{}:{}", config.host, config.port);
+    TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))
+}
+
+pub async fn run_server(
+    listener: TcpListener,
+    db: Database,
+    models: Arc<Models>,
+    config: Arc<Config>,
+    migrations: Arc<Migrations>,
+    audit: Arc<AuditLog>,
+    workers: Arc<WorkerPool>,
+) {
     let start_time = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -85,22 +486,125 @@ pub async fn run_server(db: Database, embedder: Arc<Embedder>, host: &str, port:
 
     let state = AppState {
         db: Arc::new(db),
-        embedder,
+        models,
+        config: config.clone(),
+        sessions: Arc::new(SessionStore::default()),
         start_time,
+        reindexing: Arc::new(AtomicBool::new(false)),
+        migrations,
+        audit,
+        workers,
     };
 
     let app = Router::new()
         .route("/health", get(handle_health))
+        .route("/health/ready", get(handle_ready))
         .route("/status", get(handle_status))
+        .route("/stats", get(handle_stats))
         .route("/query", post(handle_query))
+        .route("/transcripts", post(handle_save_transcript))
+        .route("/documents", post(handle_ingest_document))
+        .route("/documents/*path_or_id", delete(handle_delete_document))
+        .route("/tree", get(handle_tree))
+        .route("/explain", get(handle_explain))
+        .route("/files/content", get(handle_file_content))
+        .route("/chunks/:id", get(handle_get_chunk))
+        .route("/chunks/:id/related", get(handle_related_chunks))
+        .route("/open", post(handle_open))
+        .route("/reindex", get(handle_reindex_plan).post(handle_reindex))
+        .route("/prioritize", post(handle_prioritize))
+        .route("/changes", get(handle_changes))
+        .route("/analytics/heatmap", get(handle_heatmap))
+        .route(
+            "/tags",
+            get(handle_list_tags)
+                .post(handle_add_tag)
+                .delete(handle_remove_tag),
+        )
+        .nest_service(
+            "/mcp",
+            crate::mcp::router(
+                (*state.db).clone(),
+                state.models.clone(),
+                state.config.clone(),
+                state.audit.clone(),
+            ),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_api_key,
+        ))
         .with_state(state);
 
-    let addr = format!("{}:{}", host, port);
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    println!("API listening on {}", listener.local_addr().unwrap());
+    log::info!("API listening on {}", listener.local_addr().unwrap());
     axum::serve(listener, app).await.unwrap();
 }
 
+/// Reject requests without the configured API key or a user's key, and
+/// requests of users for endpoints beyond their scope. Health checks stay
+/// open so orchestrators can probe the daemon.
+async fn require_api_key(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let server = &state.config.server;
+    if server.api_key.is_none() && server.users.is_empty() {
+        return Ok(next.run(request).await);
+    }
+    if request.uri().path().starts_with("/health") {
+        return Ok(next.run(request).await);
+    }
+    let headers = request.headers();
+    let Some(provided) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok()))
+        .map(str::to_string)
+    else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    if let Some(expected) = server.api_key.as_deref() {
+        if keys_match(&provided, expected) {
+            return Ok(next.run(request).await);
+        }
+    }
+    let Some(user) = User::find(&server.users, &provided) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    if !users::allows_route(request.uri().path()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    request.extensions_mut().insert(user);
+    Ok(next.run(request).await)
+}
+
+/// Whether `user` may see what is stored at `path`: a file none of whose
+/// chunks are outside their scope, or nothing at all.
+async fn visible(db: &Database, user: User, path: String) -> Result<bool, ApiError> {
+    Ok(db
+        .call(move |db| {
+            let Some(file) = db.get_file(&path)? else {
+                return Ok::<_, StorageError>(true);
+            };
+            Ok(db
+                .get_chunks(file.id)?
+                .iter()
+                .all(|chunk| user.allows_chunk(&path, chunk.metadata.as_deref())))
+        })
+        .await?)
+}
+
+/// Compare keys without returning early on the first differing byte.
+fn keys_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
 // ============================================================================
 // Handlers
 // ============================================================================
@@ -113,13 +617,30 @@ async fn handle_health(State(state): State<AppState>) -> Json<HealthResponse> {
     })
 }
 
-async fn handle_status(State(state): State<AppState>) -> Result<Json<StatusResponse>, StatusCode> {
+async fn handle_ready(State(state): State<AppState>) -> (StatusCode, Json<ReadyResponse>) {
+    let mut reasons = Vec::new();
+    if let Some(problem) = limits::disk_problem(&state.config.storage.db_path, &state.config.limits)
+    {
+        reasons.push(format!("indexing paused: {}", problem));
+    }
+    let status = if reasons.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(ReadyResponse {
+            ready: reasons.is_empty(),
+            reasons,
+        }),
+    )
+}
+
+async fn handle_status(State(state): State<AppState>) -> Result<Json<StatusResponse>, ApiError> {
     let uptime = current_time() - state.start_time;
 
-    let stats = state
-        .db
-        .get_stats()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let stats = state.db.call(|db| db.get_stats()).await?;
 
     Ok(Json(StatusResponse {
         status: "ok".to_string(),
@@ -130,59 +651,744 @@ async fn handle_status(State(state): State<AppState>) -> Result<Json<StatusRespo
     }))
 }
 
+async fn handle_stats(
+    State(state): State<AppState>,
+    user: Option<Extension<User>>,
+    Query(params): Query<StatsParams>,
+) -> Result<Json<StatsResponse>, ApiError> {
+    let slowest = params.slowest.unwrap_or(10);
+    let name = user.as_ref().map(|Extension(user)| user.name.clone());
+    let (stats, mut indexing, scan, usage, mut evictions, mut collections) = state
+        .db
+        .call(move |db| {
+            let usage = match name {
+                Some(name) => Some((
+                    db.user_queries_today(&name)?,
+                    report::user_summaries(db, 30)?.remove(&name),
+                )),
+                None => None,
+            };
+            Ok::<_, ApiError>((
+                db.get_stats()?,
+                db.index_timing_stats(slowest)?,
+                scan::progress(db)?,
+                usage,
+                db.recent_evictions(RECENT_EVICTIONS)?,
+                db.collection_stats()?,
+            ))
+        })
+        .await?;
+
+    let user = user.zip(usage).map(|(Extension(user), (today, summary))| {
+        // Other users' files stay out of the slowest list
+        indexing
+            .slowest
+            .retain(|file| user.allows_path(std::path::Path::new(&file.path)));
+        collections.retain(|c| user.config.scope.allows_collection(&c.name));
+        evictions.retain(|e| {
+            let metadata = serde_json::json!({ "collection": e.collection }).to_string();
+            user.allows_chunk(&e.path, Some(&metadata))
+        });
+        UserStats {
+            queries_today: today,
+            queries_per_day: user.config.queries_per_day,
+            last_30_days: summary.unwrap_or_default(),
+            name: user.name,
+        }
+    });
+    Ok(Json(StatsResponse {
+        indexed_files: stats.file_count,
+        total_chunks: stats.chunk_count,
+        archived_chunks: stats.archived_chunk_count,
+        database_size_bytes: stats.db_size,
+        indexing,
+        memory: MemoryStats::current(&state.config.limits),
+        migrations: state.migrations.progress(),
+        scan,
+        watcher: watcher::stats(),
+        user,
+        evictions,
+        collections,
+    }))
+}
+
 async fn handle_query(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    user: Option<Extension<User>>,
     Json(payload): Json<QueryRequest>,
-) -> Json<QueryResponse> {
-    println!("Received query: {}", payload.query);
+) -> Response {
+    log::info!("Received query: {}", payload.query);
+    if payload.as_of.is_some() && !state.config.history.enabled {
+        return ApiError::BadRequest(
+            "as_of needs [history] enabled = true in the config".to_string(),
+        )
+        .into_response();
+    }
+    let user = user.map(|Extension(user)| user);
+    if let Some(user) = &user {
+        if let Err(e) = check_quota(&state, user).await {
+            return e.into_response();
+        }
+    }
 
-    // Embed query
-    let embedding = match state.embedder.embed(&payload.query) {
-        Ok(emb) => emb,
+    // Queries of a user are audited under their name
+    let client = match &user {
+        Some(user) => Some(user.name.clone()),
+        None => client_name(&headers),
+    };
+    let streaming = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains(NDJSON));
+    if streaming {
+        return stream_query(state, payload, client, user);
+    }
+
+    // Embedding and searching block, so they run off the async runtime
+    let response = tokio::task::spawn_blocking(move || {
+        let mut results = Vec::new();
+        let (facets, profile) = run_query(&state, payload, client.as_deref(), user.as_ref(), |r| {
+            results.push(r);
+            true
+        });
+        QueryResponse {
+            results,
+            facets,
+            warming_up: warming_up(&state.db),
+            freshness: freshness(&state.db),
+            profile,
+        }
+    })
+    .await;
+    match response {
+        Ok(response) => Json(response).into_response(),
         Err(e) => {
-            eprintln!("Embedding error: {}", e);
-            return Json(QueryResponse { results: vec![] });
+            log::error!("Query task failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Send each result as its own JSON line as soon as it is ready, followed by
+/// a `{"facets": ...}` line when facets were requested, a
+/// `{"warming_up": true}` line while the startup scan runs, a
+/// `{"freshness": ...}` line and, for `debug` queries, a `{"profile": ...}`
+/// line.
+fn stream_query(
+    state: AppState,
+    payload: QueryRequest,
+    client: Option<String>,
+    user: Option<User>,
+) -> Response {
+    let (tx, rx) = tokio::sync::mpsc::channel::<String>(STREAM_BUFFER);
+    tokio::task::spawn_blocking(move || {
+        let send = |value: serde_json::Result<String>| match value {
+            Ok(line) => tx.blocking_send(line + "\n").is_ok(),
+            Err(e) => {
+                log::error!("Serialization error: {}", e);
+                true
+            }
+        };
+        // A failed send means the client went away: stop ranking
+        let (facets, profile) = run_query(&state, payload, client.as_deref(), user.as_ref(), |r| {
+            send(serde_json::to_string(&r))
+        });
+        if let Some(facets) = facets {
+            send(serde_json::to_string(
+                &serde_json::json!({ "facets": facets }),
+            ));
+        }
+        if warming_up(&state.db) {
+            send(serde_json::to_string(
+                &serde_json::json!({ "warming_up": true }),
+            ));
+        }
+        if let Some(freshness) = freshness(&state.db) {
+            send(serde_json::to_string(
+                &serde_json::json!({ "freshness": freshness }),
+            ));
         }
+        if let Some(profile) = profile {
+            send(serde_json::to_string(
+                &serde_json::json!({ "profile": profile }),
+            ));
+        }
+    });
+
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv()
+            .await
+            .map(|line| (Ok::<_, std::convert::Infallible>(line), rx))
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, NDJSON)
+        .body(Body::from_stream(body))
+        .unwrap()
+}
+
+/// Refuse a query of `user` once today's `queries_per_day` are used up.
+async fn check_quota(state: &AppState, user: &User) -> Result<(), ApiError> {
+    let Some(quota) = user.config.queries_per_day else {
+        return Ok(());
     };
+    let name = user.name.clone();
+    let today = state
+        .db
+        .call(move |db| db.user_queries_today(&name))
+        .await?;
+    if today >= quota {
+        return Err(ApiError::TooManyRequests(format!(
+            "{} used all {} queries for today",
+            user.name, quota
+        )));
+    }
+    Ok(())
+}
 
-    // Search DB
+/// Whether the startup scan is still filling the index.
+fn warming_up(db: &Database) -> bool {
+    scan::progress(db).ok().flatten().is_some()
+}
 
-    let limit = payload.limit.unwrap_or(5);
+fn freshness(db: &Database) -> Option<Freshness> {
+    freshness::current(db)
+        .map_err(|e| log::warn!("Failed to read index freshness: {}", e))
+        .ok()
+}
+
+/// Who sent a request, for the audit log: the `X-Contextd-Client` header,
+/// else the user agent.
+fn client_name(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("x-contextd-client")
+        .or_else(|| headers.get(header::USER_AGENT))
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// Run `payload` through the [`QueryService`] within the scope of `user`,
+/// handing each result to `emit` in rank order until it returns false.
+/// Returns the facets and the profile when they were requested.
+fn run_query(
+    state: &AppState,
+    payload: QueryRequest,
+    client: Option<&str>,
+    user: Option<&User>,
+    mut emit: impl FnMut(QueryResult) -> bool,
+) -> (Option<Facets>, Option<QueryProfileResponse>) {
+    let limit = payload.limit.unwrap_or(state.config.search.default_limit);
     let max_results = payload.max_results.unwrap_or(limit);
+    let scope = user
+        .map(|user| user.config.scope.clone())
+        .unwrap_or_default();
 
-    let options = crate::storage::db::SearchOptions {
-        limit: Some(limit),
+    let options = SearchOptions {
+        limit: Some(limit.min(max_results)),
         start_time: payload.start_time,
         end_time: payload.end_time,
         file_types: payload.file_types,
         paths: payload.paths,
         min_score: payload.min_score,
-        recency_weight: None,   // Use default
-        frequency_weight: None, // Use default
-        context_lines: None,    // Use default
+        metadata_filters: payload.metadata_filters,
+        tags: payload.tags,
+        symbol: payload.symbol,
+        as_of: payload.as_of,
+        profile: payload.debug.then(|| Arc::new(QueryProfile::default())),
+        explain: payload.explain,
+        include_archived: payload.include_archived,
+        roots: scope.narrow(None),
+        collections: scope.narrow_collections(payload.collection.as_deref()),
+        ..Default::default() // Default weights and context
+    };
+    let query = search::Query {
+        text: payload.query,
+        hypothetical: payload.hypothetical,
+        mode: match payload.ensemble {
+            true => Some(SearchMode::Ensemble),
+            false => payload.mode,
+        },
+        options,
+        citations: payload.citations,
+        session: payload.session_id.map(|id| SessionOptions {
+            id,
+            include_seen: payload.include_seen,
+            repeats: payload.repeats,
+        }),
+        ..Default::default()
     };
 
-    let mut results: Vec<QueryResult> = match state.db.search_chunks_enhanced(&embedding, &options)
-    {
-        Ok(res) => res
-            .into_iter()
-            .map(|r| QueryResult {
-                content: r.content,
-                score: r.score,
-                file_path: Some(r.file_path),
-                file_type: Some(r.file_type),
-                last_modified: Some(r.last_modified),
+    let service = QueryService::new("api", &state.db, &state.models, &state.config, &state.audit)
+        .with_sessions(&state.sessions);
+    let mut stopped = false;
+    let started = Instant::now();
+    let searched = service.search_each(&query, client, |hit| {
+        stopped = !emit(QueryResult::from(hit));
+        !stopped
+    });
+    if let Err(e) = &searched {
+        log::error!("Search error: {}", e);
+    }
+    if let Some(user) = user {
+        users::record_query(&state.db, user, started.elapsed(), searched.is_err());
+    }
+    let profile = query
+        .options
+        .profile
+        .as_ref()
+        .map(|profile| QueryProfileResponse {
+            total_ms: started.elapsed().as_micros() as f64 / 1000.0,
+            stages: profile.timings(),
+        });
+
+    // Facets count every chunk the filters let through, not just the page
+    if stopped || !payload.facets {
+        return (None, profile);
+    }
+    match state.db.facets(&query.options, &state.config.watch.paths) {
+        Ok(facets) => (Some(facets), profile),
+        Err(e) => {
+            log::error!("Facet error: {}", e);
+            (None, profile)
+        }
+    }
+}
+
+async fn handle_save_transcript(
+    State(state): State<AppState>,
+    user: Option<Extension<User>>,
+    Json(payload): Json<Transcript>,
+) -> Result<Json<SavedTranscript>, ApiError> {
+    // Transcripts are shared by everyone who sees their collection
+    let metadata = serde_json::json!({ "collection": transcripts::COLLECTION }).to_string();
+    if let Some(Extension(user)) = &user {
+        if !user.allows_chunk(transcripts::PATH_PREFIX, Some(&metadata)) {
+            return Err(ApiError::Forbidden(format!(
+                "{} may not save transcripts",
+                user.name
+            )));
+        }
+    }
+    let models = state.models.clone();
+    state
+        .db
+        .call(move |db| {
+            let _pinned = models.pin();
+            transcripts::save(
+                db,
+                &models.for_collection(Some(transcripts::COLLECTION)),
+                &payload,
+            )
+        })
+        .await
+        .map(Json)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))
+}
+
+async fn handle_ingest_document(
+    State(state): State<AppState>,
+    user: Option<Extension<User>>,
+    Json(mut payload): Json<Document>,
+) -> Result<Json<IngestedDocument>, ApiError> {
+    let user = user.map(|Extension(user)| user);
+    if let Some(user) = &user {
+        // A user's documents go to their first collection unless they pick
+        if !payload.metadata.contains_key("collection") {
+            if let Some(first) = user
+                .config
+                .scope
+                .collections
+                .as_ref()
+                .and_then(|c| c.first())
+            {
+                payload
+                    .metadata
+                    .insert("collection".to_string(), first.clone().into());
+            }
+        }
+        let path = documents::path(&payload.path_or_id)
+            .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+        let metadata = serde_json::Value::Object(payload.metadata.clone()).to_string();
+        if !user.allows_chunk(&path, Some(&metadata)) {
+            return Err(ApiError::Forbidden(format!(
+                "{} may not add documents there",
+                user.name
+            )));
+        }
+        if !visible(&state.db, user.clone(), path).await? {
+            return Err(ApiError::Forbidden(format!(
+                "{} may not replace that document",
+                user.name
+            )));
+        }
+    }
+    let models = state.models.clone();
+    let config = state.config.clone();
+    state
+        .db
+        .call(move |db| documents::ingest(db, &models, &config, &payload))
+        .await
+        .map(Json)
+        // Anything but a storage failure is down to the document
+        .map_err(|e| match e.downcast::<StorageError>() {
+            Ok(e) => ApiError::Storage(e),
+            Err(e) => ApiError::BadRequest(format!("{:#}", e)),
+        })
+}
+
+async fn handle_delete_document(
+    State(state): State<AppState>,
+    user: Option<Extension<User>>,
+    Path(path_or_id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    if let Some(Extension(user)) = user {
+        let path = documents::path(&path_or_id).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+        // Another user's document might as well not exist
+        if !visible(&state.db, user, path).await? {
+            return Err(ApiError::NotFound("No such document".to_string()));
+        }
+    }
+    let deleted = state
+        .db
+        .call(move |db| documents::delete(db, &path_or_id))
+        .await?;
+    match deleted {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(ApiError::NotFound("No such document".to_string())),
+    }
+}
+
+async fn handle_get_chunk(
+    State(state): State<AppState>,
+    user: Option<Extension<User>>,
+    Path(id): Path<i64>,
+) -> Result<Json<ChunkResponse>, ApiError> {
+    let record = state
+        .db
+        .call(move |db| db.get_chunk(id))
+        .await?
+        .filter(|r| {
+            user.as_ref().is_none_or(|Extension(user)| {
+                user.allows_chunk(&r.file_path, r.chunk.metadata.as_deref())
+            })
+        })
+        .ok_or_else(|| ApiError::NotFound("No chunk with that id".to_string()))?;
+    let chunk = record.chunk;
+    Ok(Json(ChunkResponse {
+        chunk_id: chunk.id,
+        citation: Citer::default().cite(&record.file_path, chunk.start, chunk.end),
+        file_path: record.file_path,
+        content: chunk.content,
+        start_offset: chunk.start,
+        end_offset: chunk.end,
+        last_modified: record.last_modified,
+        metadata: chunk.metadata.and_then(|m| serde_json::from_str(&m).ok()),
+    }))
+}
+
+async fn handle_open(
+    State(state): State<AppState>,
+    Json(payload): Json<OpenRequest>,
+) -> Result<Json<OpenResponse>, ApiError> {
+    let chunk_id = payload.chunk_id;
+    let record = state
+        .db
+        .call(move |db| db.get_chunk(chunk_id))
+        .await?
+        .ok_or_else(|| ApiError::NotFound("No chunk with that id".to_string()))?;
+    if !is_virtual_path(&record.file_path) {
+        // Files deleted since indexing still resolve to their first line
+        match watch_roots(&state)
+            .await?
+            .authorize(std::path::Path::new(&record.file_path))
+        {
+            Ok(_) | Err(PathError::NotFound(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let position = EditorPosition::at(&record.file_path, record.chunk.start);
+    if payload.launch {
+        let Some(command) = state.config.server.editor_command.as_deref() else {
+            return Err(ApiError::BadRequest(
+                "No [server] editor_command is configured".to_string(),
+            ));
+        };
+        if is_virtual_path(&record.file_path) {
+            return Err(ApiError::BadRequest(format!(
+                "{} is not a file",
+                record.file_path
+            )));
+        }
+        editor::launch(command, &position)?;
+    }
+    Ok(Json(OpenResponse {
+        position,
+        launched: payload.launch,
+    }))
+}
+
+async fn handle_related_chunks(
+    State(state): State<AppState>,
+    user: Option<Extension<User>>,
+    Path(id): Path<i64>,
+    Query(params): Query<RelatedParams>,
+) -> Result<Json<RelatedResponse>, ApiError> {
+    let limit = params.limit.unwrap_or(5);
+    let user = user.map(|Extension(user)| user);
+    let allows = move |path: &str, metadata: Option<&str>| {
+        user.as_ref()
+            .is_none_or(|user| user.allows_chunk(path, metadata))
+    };
+    let related = state
+        .db
+        .call(move |db| {
+            Ok::<_, StorageError>(match db.get_chunk(id)? {
+                Some(r) if allows(&r.file_path, r.chunk.metadata.as_deref()) => {
+                    // Related chunks the user may not see don't take up the limit
+                    let mut related = db.related_chunks(id, RELATED_CANDIDATES.max(limit))?;
+                    related.retain(|r| allows(&r.file_path, r.metadata.as_deref()));
+                    related.truncate(limit);
+                    Some(related)
+                }
+                _ => None,
             })
-            .collect(),
+        })
+        .await?
+        .ok_or_else(|| ApiError::NotFound("No chunk with that id".to_string()))?;
+    let now = current_time();
+    let related = related
+        .into_iter()
+        .map(|r| QueryResult::from(Hit::new(r, &state.config, now, None)))
+        .collect();
+    Ok(Json(RelatedResponse {
+        chunk_id: id,
+        related,
+    }))
+}
+
+async fn handle_tree(
+    State(state): State<AppState>,
+    Query(params): Query<TreeParams>,
+) -> Result<Json<TreeResponse>, ApiError> {
+    let stats = state.db.call(|db| db.file_stats()).await?;
+    let path = params.path.unwrap_or_else(|| ".".to_string());
+    let requested = std::path::Path::new(&path);
+    let mut directories = summarize_tree(&stats, requested, params.depth);
+    // Files indexed by absolute path are found through the canonical form
+    if directories.is_empty() {
+        if let Ok(canonical) = canonicalize(requested) {
+            directories = summarize_tree(&stats, &canonical, params.depth);
+        }
+    }
+    Ok(Json(TreeResponse { path, directories }))
+}
+
+async fn handle_explain(
+    State(state): State<AppState>,
+    Query(params): Query<ExplainParams>,
+) -> Result<Json<Explanation>, ApiError> {
+    // Missing paths are explained too, as long as they'd be inside a root
+    match watch_roots(&state)
+        .await?
+        .authorize(std::path::Path::new(&params.path))
+    {
+        Ok(_) | Err(PathError::NotFound(_)) => {}
+        Err(e) => return Err(e.into()),
+    }
+    let config = state.config.clone();
+    let explanation = state
+        .db
+        .call(move |db| explain::explain(&config, db, std::path::Path::new(&params.path)))
+        .await?;
+    Ok(Json(explanation))
+}
+
+/// Roots the API may reach files in.
+async fn watch_roots(state: &AppState) -> Result<WatchRoots, ApiError> {
+    let config = state.config.clone();
+    tokio::task::spawn_blocking(move || WatchRoots::load(&config))
+        .await
+        .map_err(|e| ApiError::Other(e.into()))
+}
+
+async fn handle_reindex_plan(
+    State(state): State<AppState>,
+) -> Result<Json<ReindexPlanResponse>, ApiError> {
+    let config = state.config.clone();
+    let files = state.db.call(move |db| reindex::plan(db, &config)).await?;
+    Ok(Json(ReindexPlanResponse { files }))
+}
+
+/// Re-index the planned files in the background. Only one run at a time.
+async fn handle_reindex(
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<ReindexResponse>), ApiError> {
+    if state.reindexing.swap(true, Ordering::SeqCst) {
+        return Err(ApiError::Conflict(
+            "A reindex is already running".to_string(),
+        ));
+    }
+    let config = state.config.clone();
+    let stale = match state.db.call(move |db| reindex::plan(db, &config)).await {
+        Ok(stale) => stale,
         Err(e) => {
-            eprintln!("Search error: {}", e);
-            vec![]
+            state.reindexing.store(false, Ordering::SeqCst);
+            return Err(e.into());
         }
     };
+    let queued = stale.len();
+    log::info!("Re-indexing {} files with changed settings", queued);
+    tokio::spawn(async move {
+        let db = (*state.db).clone();
+        let paths = stale.into_iter().map(|f| f.path).collect();
+        let result = reindex::apply(
+            paths,
+            state.config.clone(),
+            db,
+            state.models.clone(),
+            |_| {},
+        )
+        .await;
+        if let Err(e) = result {
+            log::error!("Reindex failed: {:#}", e);
+        }
+        state.reindexing.store(false, Ordering::SeqCst);
+        log::info!("Reindex finished");
+    });
+    Ok((StatusCode::ACCEPTED, Json(ReindexResponse { queued })))
+}
+
+/// Index a file, or a directory's files, ahead of the initial scan and
+/// other backfills, as when it's opened in an editor.
+async fn handle_prioritize(
+    State(state): State<AppState>,
+    Json(payload): Json<PrioritizeRequest>,
+) -> Result<(StatusCode, Json<PrioritizeResponse>), ApiError> {
+    let path = std::path::PathBuf::from(&payload.path);
+    watch_roots(&state).await?.authorize(&path)?;
+    let watch = &state.config.watch;
+    let files: Vec<_> = if path.is_dir() {
+        daemon::walk(&path, watch)
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.into_path())
+            .filter(|p| p.is_file() && watch.allows(p))
+            .collect()
+    } else {
+        Some(path).filter(|p| watch.allows(p)).into_iter().collect()
+    };
+    let queued = files.len();
+    for path in files {
+        daemon::spawn_index(
+            path,
+            &state.config,
+            &state.db,
+            &state.models,
+            &state.workers,
+            None,
+        );
+    }
+    Ok((StatusCode::ACCEPTED, Json(PrioritizeResponse { queued })))
+}
 
-    results.truncate(max_results);
+async fn handle_changes(
+    State(state): State<AppState>,
+    Query(params): Query<ChangesParams>,
+) -> Result<Json<ChangesResponse>, ApiError> {
+    let limit = params.limit.unwrap_or(MAX_CHANGES).min(MAX_CHANGES);
+    let since = params.since;
+    let ((oldest, latest), changes) = state
+        .db
+        .call(move |db| {
+            Ok::<_, StorageError>((db.change_range()?, db.changes_since(since, limit)?))
+        })
+        .await?;
+    // With everything pruned, the next change kept will be `latest + 1`
+    let first_kept = if oldest == 0 { latest + 1 } else { oldest };
+    Ok(Json(ChangesResponse {
+        next: changes.last().map_or(params.since, |c| c.seq),
+        missed: params.since + 1 < first_kept,
+        changes,
+    }))
+}
+
+async fn handle_heatmap(
+    State(state): State<AppState>,
+    Query(params): Query<HeatmapParams>,
+) -> Result<Json<HeatmapResponse>, ApiError> {
+    let limit = params.limit.unwrap_or(20).min(MAX_HEATMAP_FILES);
+    let per_file = params.chunks.unwrap_or(5);
+    let files = state
+        .db
+        .call(move |db| db.chunk_heat(limit, per_file))
+        .await?;
+    Ok(Json(HeatmapResponse { files }))
+}
 
-    Json(QueryResponse { results })
+async fn handle_file_content(
+    State(state): State<AppState>,
+    Query(params): Query<FileContentParams>,
+) -> Result<Json<FileRange>, ApiError> {
+    let roots = watch_roots(&state).await?;
+    let range = RangeRequest {
+        start_line: params.start_line,
+        end_line: params.end_line,
+        start_byte: params.start_byte,
+        end_byte: params.end_byte,
+    };
+    let range = files::read_range(&roots, std::path::Path::new(&params.path), &range)?;
+    Ok(Json(range))
+}
+
+async fn handle_list_tags(State(state): State<AppState>) -> Result<Json<TagsResponse>, ApiError> {
+    let tags = state.db.call(|db| db.list_tags()).await?;
+    Ok(Json(TagsResponse { tags }))
+}
+
+async fn handle_add_tag(
+    State(state): State<AppState>,
+    Json(payload): Json<TagRequest>,
+) -> Result<StatusCode, ApiError> {
+    match (payload.path, payload.chunk_id) {
+        (Some(path), None) => {
+            state
+                .db
+                .call(move |db| db.add_path_tag(&path, &payload.tag))
+                .await?
+        }
+        (None, Some(chunk_id)) => {
+            state
+                .db
+                .call(move |db| db.add_chunk_tag(chunk_id, &payload.tag))
+                .await?
+        }
+        _ => return Err(ApiError::BadRequest(TAG_TARGET.to_string())),
+    };
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn handle_remove_tag(
+    State(state): State<AppState>,
+    Json(payload): Json<TagRequest>,
+) -> Result<StatusCode, ApiError> {
+    let removed = match (payload.path, payload.chunk_id) {
+        (Some(path), None) => {
+            state
+                .db
+                .call(move |db| db.remove_path_tag(&path, &payload.tag))
+                .await?
+        }
+        (None, Some(chunk_id)) => {
+            state
+                .db
+                .call(move |db| db.remove_chunk_tag(chunk_id, &payload.tag))
+                .await?
+        }
+        _ => return Err(ApiError::BadRequest(TAG_TARGET.to_string())),
+    };
+    match removed {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(ApiError::NotFound("No such tag".to_string())),
+    }
 }
 
 fn current_time() -> u64 {
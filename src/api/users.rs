@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::UserConfig;
+use crate::storage::db::Database;
+
+use super::keys_match;
+
+/// A request authenticated with the key of a `[server.users]` entry rather
+/// than the admin `[server] api_key`.
+#[derive(Clone, Debug)]
+pub struct User {
+    pub name: String,
+    pub config: UserConfig,
+}
+
+impl User {
+    /// The user whose key is `key`. Every key is compared, so the time taken
+    /// doesn't tell which one nearly matched.
+    pub fn find(users: &HashMap<String, UserConfig>, key: &str) -> Option<Self> {
+        users
+            .iter()
+            .filter(|(_, user)| keys_match(key, &user.api_key))
+            .fold(None, |found, (name, user)| {
+                found.or_else(|| {
+                    Some(Self {
+                        name: name.clone(),
+                        config: user.clone(),
+                    })
+                })
+            })
+    }
+
+    /// Whether the user may see a chunk of `file_path` with chunk `metadata`.
+    pub fn allows_chunk(&self, file_path: &str, metadata: Option<&str>) -> bool {
+        self.config.scope.allows_chunk(file_path, metadata)
+    }
+
+    /// Whether the user may see a file or directory.
+    pub fn allows_path(&self, path: &Path) -> bool {
+        self.config.scope.allows_path(path)
+    }
+}
+
+/// Whether a user may call the endpoint at `path`. The others, like the file
+/// tree and the changelog, span the whole index, so only the admin key
+/// reaches them.
+pub fn allows_route(path: &str) -> bool {
+    matches!(path, "/status" | "/stats" | "/query" | "/transcripts")
+        || path == "/documents"
+        || path.starts_with("/documents/")
+        || path.starts_with("/chunks/")
+}
+
+/// Count a query from `user`. Like other usage tracking this never fails
+/// the query; errors are only logged.
+pub fn record_query(db: &Database, user: &User, elapsed: Duration, failed: bool) {
+    if let Err(e) = db.record_user_query(&user.name, elapsed.as_micros() as u64, failed) {
+        log::warn!("Failed to record query usage of {}: {}", user.name, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_users() {
+        let users: HashMap<String, UserConfig> = toml::from_str(
+            r#"
+            [ana]
+            api_key = "k1"
+            collections = ["ana"]
+
+            [ben]
+            api_key = "k2"
+            paths = ["/srv/ben"]
+            "#,
+        )
+        .unwrap();
+        let ana = User::find(&users, "k1").unwrap();
+        assert_eq!(ana.name, "ana");
+        assert!(User::find(&users, "k3").is_none());
+        assert!(ana.allows_chunk("document://a", Some(r#"{"collection": "ana"}"#)));
+        assert!(!ana.allows_chunk("document://b", Some(r#"{"collection": "ben"}"#)));
+
+        let ben = User::find(&users, "k2").unwrap();
+        assert!(ben.allows_path(Path::new("/srv/ben/notes.md")));
+        assert!(!ben.allows_chunk("/srv/ana/notes.md", None));
+
+        assert!(allows_route("/query"));
+        assert!(allows_route("/documents/tickets/42"));
+        assert!(allows_route("/chunks/7/related"));
+        assert!(!allows_route("/tree"));
+        assert!(!allows_route("/changes"));
+        assert!(!allows_route("/files/content"));
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::migration::Migrations;
+use crate::storage::db::Database;
+
+/// How often the daemon archives chunks queries stopped returning.
+const ARCHIVE_INTERVAL: Duration = Duration::from_secs(86_400);
+
+/// Chunks archived per write, so indexing isn't held up for long.
+const BATCH_SIZE: usize = 500;
+
+/// `[archive]` config: move chunks no query has returned for a while to a
+/// compressed archive that only queries with `include_archived` search.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    pub enabled: bool,
+    /// Archive chunks not returned by any query for this many days since
+    /// they were indexed
+    pub after_days: u64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            after_days: 90,
+        }
+    }
+}
+
+/// Archive the chunks not returned since `after_days` before `now`
+/// (seconds since the epoch). Returns how many were archived.
+pub fn run(db: &Database, config: &ArchiveConfig, now: u64) -> Result<usize> {
+    let cutoff = now.saturating_sub(config.after_days * 86_400);
+    let mut archived = 0;
+    loop {
+        let batch = db.archive_chunks(cutoff, BATCH_SIZE)?;
+        archived += batch;
+        if batch < BATCH_SIZE {
+            return Ok(archived);
+        }
+    }
+}
+
+/// Count `chunk_ids` as returned by a query, bringing archived ones back.
+/// Like other usage tracking this never fails the query; errors are only
+/// logged.
+pub fn record_returned(db: &Database, chunk_ids: &[i64]) {
+    if let Err(e) = db.mark_chunks_returned(chunk_ids) {
+        log::warn!("Failed to record returned chunks: {}", e);
+    }
+}
+
+/// Archive chunks once a day while the daemon runs, if `[archive]` is
+/// enabled. Skipped while collections are re-embedded, since archived
+/// chunks keep their old vectors.
+pub fn spawn_archiving(db: Database, config: ArchiveConfig, migrations: Arc<Migrations>) {
+    if !config.enabled {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ARCHIVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !migrations.progress().is_empty() {
+                continue;
+            }
+            let config = config.clone();
+            let now = chrono::Utc::now().timestamp() as u64;
+            match db.call(move |db| run(db, &config, now)).await {
+                Ok(0) => {}
+                Ok(archived) => log::info!("Archived {} chunks no query returned", archived),
+                Err(e) => log::warn!("Failed to archive chunks: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::db::SearchOptions;
+
+    #[test]
+    fn test_archive() {
+        let db = Database::new(":memory:").unwrap();
+        let file = db.add_or_update_file("/notes/old.md", 100).unwrap();
+        db.add_chunk(file, 0, 4, "cold", Some(&[1.0; 384]), Some("{}"))
+            .unwrap();
+        db.add_chunk(file, 4, 8, "warm", Some(&[0.5; 384]), None)
+            .unwrap();
+        db.mark_indexed(file).unwrap();
+        let ids: Vec<i64> = db.get_chunks(file).unwrap().iter().map(|c| c.id).collect();
+        db.mark_chunks_returned(&ids[1..]).unwrap();
+
+        let config = ArchiveConfig::default();
+        let later = chrono::Utc::now().timestamp() as u64 + 86_400;
+        assert_eq!(run(&db, &config, later).unwrap(), 0);
+        let much_later = later + 90 * 86_400;
+        assert_eq!(run(&db, &config, much_later).unwrap(), 2);
+        let stats = db.get_stats().unwrap();
+        assert_eq!((stats.chunk_count, stats.archived_chunk_count), (0, 2));
+
+        let search = |include_archived: bool| -> Vec<(i64, String)> {
+            let options = SearchOptions {
+                include_archived,
+                recency_weight: Some(0.0),
+                ..Default::default()
+            };
+            db.search_chunks_enhanced(&[1.0; 384], &options)
+                .unwrap()
+                .into_iter()
+                .map(|r| (r.id, r.content))
+                .collect()
+        };
+        assert!(search(false).is_empty());
+        assert_eq!(search(true).len(), 2);
+
+        // New chunks don't take the archived ones' ids
+        let other = db.add_or_update_file("/notes/new.md", 100).unwrap();
+        db.add_chunk(other, 0, 3, "new", None, None).unwrap();
+        assert!(db.get_chunks(other).unwrap()[0].id > ids[1]);
+
+        // Returning a chunk brings it back under its id
+        db.mark_chunks_returned(&ids[..1]).unwrap();
+        assert_eq!(search(false), vec![(ids[0], "cold".to_string())]);
+        assert_eq!(db.restore_archived().unwrap(), 1);
+        assert_eq!(db.get_stats().unwrap().archived_chunk_count, 0);
+
+        // Re-indexing a file drops its archived chunks
+        assert_eq!(run(&db, &config, much_later + 90 * 86_400).unwrap(), 2);
+        db.clear_chunks(file).unwrap();
+        assert_eq!(db.get_stats().unwrap().archived_chunk_count, 0);
+    }
+}
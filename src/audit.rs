@@ -0,0 +1,171 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::logging::{RotateInterval, RotatingFile};
+
+/// `[audit]` config: a JSON-lines record of every search query and the files
+/// its results came from.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Appended to, never rewritten; relative paths follow `CONTEXTD_DATA_DIR`
+    pub file: PathBuf,
+    /// Rotate the file once it grows past this many megabytes (0 disables)
+    pub max_size_mb: u64,
+    /// Also rotate the file when a new UTC hour or day begins
+    pub rotate: RotateInterval,
+    /// Rotated files kept as `<file>.1` (newest) to `<file>.<keep>`
+    pub keep: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            file: PathBuf::from("query-audit.jsonl"),
+            max_size_mb: 50,
+            rotate: RotateInterval::default(),
+            keep: 10,
+        }
+    }
+}
+
+/// One line of the query audit log. Results are listed by file, without
+/// their content.
+#[derive(Serialize, Debug)]
+pub struct QueryRecord<'a> {
+    pub timestamp: String,
+    /// Interface the query came in through: `api`, `mcp`, `lsp` or `cli`
+    pub source: &'a str,
+    /// Who sent it, as far as the interface can tell
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<&'a str>,
+    pub query: &'a str,
+    /// Files of the returned chunks, in rank order without repeats
+    pub results: Vec<&'a str>,
+}
+
+/// The query audit log, or a no-op when auditing is off.
+#[derive(Default)]
+pub struct AuditLog {
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditConfig) -> Result<Self> {
+        if !config.enabled {
+            return Ok(Self::default());
+        }
+        let file = RotatingFile::open(
+            &config.file,
+            config.max_size_mb * 1024 * 1024,
+            config.rotate,
+            config.keep,
+        )?;
+        Ok(Self {
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    /// Record that `query` from `client` over `source` returned chunks of
+    /// `paths`. Failures are logged rather than failing the query.
+    pub fn record<'a>(
+        &self,
+        source: &str,
+        client: Option<&str>,
+        query: &str,
+        paths: impl IntoIterator<Item = &'a str>,
+    ) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mut results: Vec<&str> = Vec::new();
+        for path in paths {
+            if !results.contains(&path) {
+                results.push(path);
+            }
+        }
+        let record = QueryRecord {
+            timestamp: chrono::Utc::now()
+                .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+                .to_string(),
+            source,
+            client,
+            query,
+            results,
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("Failed to serialize audit record: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = file.lock().unwrap().write_line(&line) {
+            log::warn!("Failed to write query audit log: {}", e);
+        }
+    }
+}
+
+/// Whether `path` is the audit log or one of its rotated copies, which the
+/// daemon must not index.
+pub fn is_audit_file(config: &AuditConfig, path: &Path) -> bool {
+    let (Some(name), Some(log)) = (
+        path.file_name().and_then(|n| n.to_str()),
+        config.file.file_name().and_then(|n| n.to_str()),
+    ) else {
+        return false;
+    };
+    config.enabled
+        && name.strip_prefix(log).is_some_and(|rest| {
+            rest.is_empty()
+                || rest
+                    .strip_prefix('.')
+                    .is_some_and(|n| n.parse::<usize>().is_ok())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AuditConfig {
+            enabled: true,
+            file: dir.path().join("audit.jsonl"),
+            ..Default::default()
+        };
+        let log = AuditLog::open(&config).unwrap();
+        log.record(
+            "mcp",
+            Some("cursor"),
+            "auth flow",
+            ["/a.rs", "/b.rs", "/a.rs"],
+        );
+        log.record("api", None, "nothing", []);
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&config.file)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["client"], "cursor");
+        assert_eq!(lines[0]["results"], serde_json::json!(["/a.rs", "/b.rs"]));
+        assert!(lines[1].get("client").is_none());
+
+        assert!(is_audit_file(&config, Path::new("/x/audit.jsonl.3")));
+        assert!(!is_audit_file(&config, Path::new("/x/audit.jsonl.bak")));
+        // Nothing is written, or skipped, when auditing is off
+        let off = AuditConfig::default();
+        AuditLog::open(&off)
+            .unwrap()
+            .record("cli", None, "q", ["/a.rs"]);
+        assert!(!is_audit_file(&off, Path::new("query-audit.jsonl")));
+    }
+}
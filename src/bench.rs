@@ -0,0 +1,283 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, WatchConfig};
+use crate::daemon;
+use crate::indexer::chunker;
+use crate::indexer::embeddings::{BackendKind, Embedder};
+use crate::storage::db::{Database, SearchOptions};
+
+/// Queries used when measuring search latency.
+const BENCH_QUERIES: &[&str] = &[
+    "error handling",
+    "parse configuration file",
+    "database connection",
+    "http request handler",
+    "retry with backoff",
+    "cache eviction policy",
+    "user authentication",
+    "serialize to json",
+];
+
+/// A document fed through the benchmark pipeline.
+struct BenchDoc {
+    path: String,
+    ext: String,
+    content: String,
+}
+
+/// Timing for one pipeline stage.
+struct StageResult {
+    name: &'static str,
+    items: usize,
+    elapsed: Duration,
+}
+
+impl StageResult {
+    fn per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.items as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+pub async fn handle_bench(
+    config: &Config,
+    path: Option<&Path>,
+    files: usize,
+    queries: usize,
+) -> Result<()> {
+    let docs = match path {
+        Some(p) => load_corpus(p, &config.watch),
+        None => synthetic_corpus(files),
+    };
+    let total_bytes: usize = docs.iter().map(|d| d.content.len()).sum();
+    println!(
+        "Benchmarking {} documents ({:.2} MB)",
+        docs.len(),
+        total_bytes as f64 / 1024.0 / 1024.0
+    );
+
+    if config.embeddings.backend == BackendKind::Onnx {
+        crate::download::ensure_model_files(&config.storage.model_path, &config.storage.model_type)
+            .await?;
+    }
+    let embedder = Embedder::new(&config.storage, &config.embeddings)?;
+    // Scratch database so the real index is never touched
+    let db = Database::new(":memory:")?;
+
+    // 1. Chunking
+    let start = Instant::now();
+    let mut chunked = Vec::new();
+    for doc in &docs {
+        if let Ok(chunks) = chunker::chunk_by_type(&doc.content, &doc.ext) {
+            chunked.push((doc, chunks));
+        }
+    }
+    let chunk_count: usize = chunked.iter().map(|(_, c)| c.len()).sum();
+    let chunking = StageResult {
+        name: "chunking",
+        items: chunk_count,
+        elapsed: start.elapsed(),
+    };
+
+    // 2. Embedding
+    let start = Instant::now();
+    let mut embedded = Vec::with_capacity(chunk_count);
+    for (doc, chunks) in &chunked {
+        for chunk in chunks {
+            embedded.push((doc, chunk, embedder.embed(&chunk.content).ok()));
+        }
+    }
+    let embedding = StageResult {
+        name: "embedding",
+        items: embedded.len(),
+        elapsed: start.elapsed(),
+    };
+
+    // 3. Insert
+    let start = Instant::now();
+    let mut file_ids = std::collections::HashMap::new();
+    for (doc, chunk, emb) in &embedded {
+        let file_id = match file_ids.get(&doc.path) {
+            Some(id) => *id,
+            None => {
+                let id = db.add_or_update_file(&doc.path, 0)?;
+                file_ids.insert(doc.path.clone(), id);
+                id
+            }
+        };
+        db.add_chunk(
+            file_id,
+            chunk.start,
+            chunk.end,
+            &chunk.content,
+            emb.as_deref(),
+            chunk.metadata.as_deref(),
+        )?;
+    }
+    let insert = StageResult {
+        name: "insert",
+        items: embedded.len(),
+        elapsed: start.elapsed(),
+    };
+
+    println!(
+        "\n{:<12} {:>10} {:>12} {:>14}",
+        "stage", "items", "total ms", "items/sec"
+    );
+    for stage in [&chunking, &embedding, &insert] {
+        println!(
+            "{:<12} {:>10} {:>12.1} {:>14.1}",
+            stage.name,
+            stage.items,
+            stage.elapsed.as_secs_f64() * 1000.0,
+            stage.per_sec()
+        );
+    }
+
+    // 4. Query latency
+    let options = SearchOptions {
+        limit: Some(10),
+        ..Default::default()
+    };
+    let mut vector_ms = Vec::with_capacity(queries);
+    let mut hybrid_ms = Vec::with_capacity(queries);
+    for i in 0..queries {
+        let query = BENCH_QUERIES[i % BENCH_QUERIES.len()];
+
+        let start = Instant::now();
+        let emb = embedder.embed(query)?;
+        db.search_chunks_enhanced(&emb, &options)?;
+        vector_ms.push(start.elapsed().as_secs_f64() * 1000.0);
+
+        let start = Instant::now();
+        let emb = embedder.embed(query)?;
+        db.search_chunks_hybrid(query, &emb, &options)?;
+        hybrid_ms.push(start.elapsed().as_secs_f64() * 1000.0);
+    }
+
+    println!(
+        "\n{:<12} {:>10} {:>10} {:>10}",
+        "query mode", "p50 ms", "p95 ms", "p99 ms"
+    );
+    for (name, samples) in [("vector", &mut vector_ms), ("hybrid", &mut hybrid_ms)] {
+        println!(
+            "{:<12} {:>10.2} {:>10.2} {:>10.2}",
+            name,
+            percentile(samples, 50.0),
+            percentile(samples, 95.0),
+            percentile(samples, 99.0)
+        );
+    }
+
+    Ok(())
+}
+
+/// Read every indexable file under `path`.
+fn load_corpus(path: &Path, watch: &WatchConfig) -> Vec<BenchDoc> {
+    let files: Vec<PathBuf> = if path.is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        daemon::walk(path, watch)
+            .filter_map(|r| r.ok())
+            .filter(|e| e.path().is_file())
+            .map(|e| e.into_path())
+            .collect()
+    };
+
+    files
+        .into_iter()
+        .filter_map(|p| {
+            let content = std::fs::read_to_string(&p).ok()?;
+            let ext = p
+                .extension()
+                .and_then(|s| s.to_str())
+                .unwrap_or("")
+                .to_string();
+            Some(BenchDoc {
+                path: p.to_string_lossy().to_string(),
+                ext,
+                content,
+            })
+        })
+        .collect()
+}
+
+/// Generate `files` documents alternating between Rust source and markdown.
+fn synthetic_corpus(files: usize) -> Vec<BenchDoc> {
+    (0..files)
+        .map(|i| {
+            if i % 2 == 0 {
+                let content = (0..10)
+                    .map(|j| {
+                        format!(
+                            "/// Handles request {i}-{j} and retries on failure.\n\
+                             fn handle_request_{i}_{j}(input: &str) -> Result<String, Error> {{\n    \
+                             let parsed = parse_config(input)?;\n    \
+                             Ok(format!(\"{{}}-{j}\", parsed))\n}}\n\n"
+                        )
+                    })
+                    .collect();
+                BenchDoc {
+                    path: format!("/bench/src/module_{i}.rs"),
+                    ext: "rs".to_string(),
+                    content,
+                }
+            } else {
+                let content = (0..5)
+                    .map(|j| {
+                        format!(
+                            "## Section {j} of guide {i}\n\n\
+                             The cache eviction policy keeps the database connection pool \
+                             warm while user authentication tokens are refreshed.\n\n"
+                        )
+                    })
+                    .collect();
+                BenchDoc {
+                    path: format!("/bench/docs/guide_{i}.md"),
+                    ext: "md".to_string(),
+                    content,
+                }
+            }
+        })
+        .collect()
+}
+
+/// Nearest-rank percentile of `samples` (sorted in place).
+fn percentile(samples: &mut [f64], pct: f64) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    samples.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let rank = ((pct / 100.0) * samples.len() as f64).ceil() as usize;
+    samples[rank.clamp(1, samples.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let mut samples: Vec<f64> = (1..=100).map(|v| v as f64).collect();
+        assert_eq!(percentile(&mut samples, 50.0), 50.0);
+        assert_eq!(percentile(&mut samples, 95.0), 95.0);
+        assert_eq!(percentile(&mut samples, 100.0), 100.0);
+        assert_eq!(percentile(&mut [], 50.0), 0.0);
+    }
+
+    #[test]
+    fn test_synthetic_corpus_chunks() {
+        let docs = synthetic_corpus(4);
+        assert_eq!(docs.len(), 4);
+        for doc in &docs {
+            let chunks = chunker::chunk_by_type(&doc.content, &doc.ext).unwrap();
+            assert!(!chunks.is_empty(), "{} produced no chunks", doc.path);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::storage::db::Database;
+
+/// How often the daemon drops changes older than the retention period.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(86_400);
+
+/// `[changelog]` config. Every file and chunk added, changed or removed is
+/// logged with an increasing sequence number, so consumers can follow the
+/// index from where they left off.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ChangelogConfig {
+    /// Drop changes older than this many days (0 keeps them)
+    pub retention_days: u64,
+}
+
+impl Default for ChangelogConfig {
+    fn default() -> Self {
+        Self { retention_days: 30 }
+    }
+}
+
+/// Drop expired changes now and then once a day while the daemon runs.
+pub fn spawn_pruning(db: Database, config: ChangelogConfig) {
+    if config.retention_days == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let config = config.clone();
+            if let Err(e) = db.call(move |db| prune(db, &config)).await {
+                log::warn!("Failed to prune the index changelog: {}", e);
+            }
+        }
+    });
+}
+
+fn prune(db: &Database, config: &ChangelogConfig) -> Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let dropped = db.prune_changes(now.saturating_sub(config.retention_days * 86_400))?;
+    if dropped > 0 {
+        log::info!("Dropped {} changelog entries past the retention", dropped);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let db = Database::new(":memory:").unwrap();
+        let file = db.add_or_update_file("/docs/a.md", 100).unwrap();
+        db.add_chunk(file, 0, 3, "one", Some(&[1.0; 384]), None)
+            .unwrap();
+        let chunk = db.get_chunks(file).unwrap()[0].id;
+        db.mark_indexed(file).unwrap();
+        // Re-upserting without a new mtime isn't a change
+        db.add_or_update_file("/docs/a.md", 100).unwrap();
+        db.add_or_update_file("/docs/a.md", 200).unwrap();
+        db.clear_chunks(file).unwrap();
+        db.delete_file(file).unwrap();
+
+        let ops: Vec<(String, String, Option<i64>)> = db
+            .changes_since(0, 100)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.kind, c.op, c.chunk_id))
+            .collect();
+        let entry = |kind: &str, op: &str, chunk_id| (kind.to_string(), op.to_string(), chunk_id);
+        assert_eq!(
+            ops,
+            vec![
+                entry("file", "add", None),
+                entry("chunk", "add", Some(chunk)),
+                entry("file", "update", None),
+                entry("chunk", "delete", Some(chunk)),
+                entry("file", "delete", None),
+            ]
+        );
+
+        let after = db.changes_since(3, 1).unwrap();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].seq, 4);
+        assert_eq!(after[0].path, "/docs/a.md");
+
+        // Pruned numbers aren't reused
+        assert_eq!(db.prune_changes(u32::MAX as u64).unwrap(), 5);
+        assert_eq!(db.change_range().unwrap(), (0, 5));
+        db.add_or_update_file("/docs/b.md", 100).unwrap();
+        assert_eq!(db.change_range().unwrap(), (6, 6));
+    }
+}
@@ -0,0 +1,184 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths::canonicalize;
+
+/// Length of the abbreviated commit hash in citations.
+const SHORT_COMMIT: usize = 12;
+
+/// Where a chunk came from, in a form agents can quote back.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Citation {
+    /// `path:start_line-end_line @ commit`, dropping the parts that are unknown
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_start: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_end: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+}
+
+/// Builds citations, caching the HEAD commit of each repository it meets.
+#[derive(Default)]
+pub struct Citer {
+    commits: HashMap<PathBuf, Option<String>>,
+}
+
+impl Citer {
+    /// Cite the byte range `start..end` of `file_path`. Paths inside a git
+    /// repository are made relative to its root and pinned to its HEAD.
+    pub fn cite(&mut self, file_path: &str, start: u64, end: u64) -> Citation {
+        let path = Path::new(file_path);
+        let lines = fs::read_to_string(path).ok().map(|content| {
+            (
+                line_at(&content, start as usize),
+                line_at(&content, end.saturating_sub(1).max(start) as usize),
+            )
+        });
+
+        let repo = canonicalize(path).ok().and_then(|p| {
+            p.ancestors()
+                .find(|d| d.join(".git").exists())
+                .map(Path::to_path_buf)
+        });
+        let (display, commit) = match repo {
+            Some(repo) => {
+                let relative = canonicalize(path)
+                    .ok()
+                    .and_then(|p| p.strip_prefix(&repo).ok().map(Path::to_path_buf))
+                    .unwrap_or_else(|| path.to_path_buf());
+                let commit = self
+                    .commits
+                    .entry(repo.clone())
+                    .or_insert_with(|| head_commit(&repo.join(".git")))
+                    .clone();
+                (relative.to_string_lossy().to_string(), commit)
+            }
+            None => (file_path.trim_start_matches("./").to_string(), None),
+        };
+
+        let mut text = display;
+        if let Some((first, last)) = lines {
+            text.push_str(&format!(":{}-{}", first, last));
+        }
+        if let Some(commit) = &commit {
+            text.push_str(&format!(" @ {}", commit));
+        }
+        Citation {
+            text,
+            line_start: lines.map(|(first, _)| first),
+            line_end: lines.map(|(_, last)| last),
+            commit,
+        }
+    }
+}
+
+/// 1-based line number containing byte `offset` of `content`.
+pub fn line_at(content: &str, offset: usize) -> usize {
+    let end = offset.min(content.len());
+    content.as_bytes()[..end]
+        .iter()
+        .filter(|b| **b == b'\n')
+        .count()
+        + 1
+}
+
+/// 1-based column, in characters, of byte `offset` within its line of
+/// `content`.
+pub fn column_at(content: &str, offset: usize) -> usize {
+    let mut end = offset.min(content.len());
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    let line_start = content[..end].rfind('\n').map_or(0, |i| i + 1);
+    content[line_start..end].chars().count() + 1
+}
+
+/// Abbreviated commit checked out in `git_dir`, read from the ref files so no
+/// `git` binary is needed.
+fn head_commit(git_dir: &Path) -> Option<String> {
+    // Worktrees and submodules use a `.git` file pointing at the real dir
+    let git_dir = if git_dir.is_file() {
+        let pointer = fs::read_to_string(git_dir).ok()?;
+        let target = pointer.trim().strip_prefix("gitdir:")?.trim();
+        git_dir.parent()?.join(target)
+    } else {
+        git_dir.to_path_buf()
+    };
+
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    let full = match head.strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            match fs::read_to_string(git_dir.join(reference)) {
+                Ok(hash) => hash.trim().to_string(),
+                Err(_) => packed_ref(&git_dir, reference)?,
+            }
+        }
+        None => head.to_string(),
+    };
+    Some(full.chars().take(SHORT_COMMIT).collect())
+}
+
+fn packed_ref(git_dir: &Path, reference: &str) -> Option<String> {
+    let packed = fs::read_to_string(git_dir.join("packed-refs")).ok()?;
+    packed.lines().find_map(|line| {
+        let (hash, name) = line.split_once(' ')?;
+        (name == reference).then(|| hash.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_line_at() {
+        let content = "one\ntwo\nthree";
+        assert_eq!(line_at(content, 0), 1);
+        assert_eq!(line_at(content, 4), 2);
+        assert_eq!(line_at(content, 8), 3);
+        assert_eq!(line_at(content, 1000), 3);
+    }
+
+    #[test]
+    fn test_column_at() {
+        let content = "one\n  é two";
+        assert_eq!(column_at(content, 0), 1);
+        assert_eq!(column_at(content, 6), 3);
+        // After the two-byte 'é'
+        assert_eq!(column_at(content, 9), 5);
+    }
+
+    #[test]
+    fn test_cite_in_repo() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        fs::create_dir_all(repo.join(".git/refs/heads")).unwrap();
+        fs::write(repo.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(
+            repo.join(".git/packed-refs"),
+            "# pack-refs with: peeled\n0123456789abcdef0123 refs/heads/main\n",
+        )
+        .unwrap();
+        fs::create_dir(repo.join("src")).unwrap();
+        let file = repo.join("src/lib.rs");
+        fs::write(&file, "fn a() {}\n\nfn b() {\n}\n").unwrap();
+
+        let citation = Citer::default().cite(&file.to_string_lossy(), 11, 22);
+        assert_eq!(citation.text, "src/lib.rs:3-4 @ 0123456789ab");
+        assert_eq!(citation.line_start, Some(3));
+    }
+
+    #[test]
+    fn test_cite_virtual_document() {
+        let citation = Citer::default().cite("conversation://c1", 0, 10);
+        assert_eq!(citation.text, "conversation://c1");
+        assert_eq!(citation.commit, None);
+    }
+}
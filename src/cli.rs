@@ -1,16 +1,48 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Subcommand;
+use ignore::WalkBuilder;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::config::Config;
-use crate::indexer::embeddings::Embedder;
+use crate::audit::AuditLog;
+use crate::citation::line_at;
+use crate::collections::{self, Models};
+use crate::config::{Config, WatchConfig};
+use crate::daemon;
+use crate::indexer::{chunker, moves};
+use crate::paths::canonicalize;
+use crate::search::{self, QueryService, SearchMode};
 use crate::storage::db::{Database, SearchOptions};
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Run as a daemon (default)
-    Daemon,
-    /// Run as an MCP server
-    Mcp,
+    Daemon {
+        /// Listen on this address instead of `[server] host`; anything but
+        /// loopback requires `[server] api_key`
+        #[arg(long)]
+        bind: Option<String>,
+        /// Run in a container: data under /data unless CONTEXTD_DATA_DIR is
+        /// set, and no shutdown when stdin closes
+        #[arg(long, default_value_t = false)]
+        container: bool,
+    },
+    /// Run as an MCP server, or manage MCP client configuration
+    Mcp {
+        #[command(subcommand)]
+        action: Option<McpAction>,
+        /// Client name to scope access by in `[mcp.clients]`, instead of
+        /// the name the client sends (also CONTEXTD_MCP_CLIENT)
+        #[arg(long)]
+        client: Option<String>,
+    },
+    /// Run as a Language Server over stdio, for editor integration
+    Lsp,
+    /// Set up contextd interactively: directories, model, MCP client and a
+    /// first index
+    Init,
     /// Setup models
     Setup,
     /// Query the index
@@ -19,6 +51,124 @@ pub enum Commands {
         /// Number of context lines to show before/after match
         #[arg(short, long, default_value = "0")]
         context: usize,
+        /// How to match the query (defaults to `[search] default_mode`)
+        #[arg(long, value_enum)]
+        mode: Option<SearchMode>,
+    },
+    /// Index a path once without running the daemon
+    Index {
+        path: PathBuf,
+        /// Only report what would be indexed
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// List files indexed with settings that have since changed, or re-index
+    /// them
+    Reindex {
+        /// List stale files and what changed (default)
+        #[arg(long, default_value_t = false)]
+        plan: bool,
+        /// Re-index the stale files, in the running daemon when there is one
+        #[arg(long, default_value_t = false, conflicts_with = "plan")]
+        apply: bool,
+    },
+    /// Re-chunk files split by an older version of a language's chunker
+    Rechunk {
+        /// Chunker language (rust, python, javascript, typescript, go,
+        /// markdown, text, pdf)
+        #[arg(long)]
+        lang: String,
+    },
+    /// Show how an indexed file was chunked
+    Show { path: PathBuf },
+    /// Split a file with its built-in chunker, without indexing it
+    Chunk {
+        path: PathBuf,
+        /// Print every chunk in full, in the form of the chunker snapshots
+        /// under tests/fixtures/chunker
+        #[arg(long)]
+        debug: bool,
+    },
+    /// Explain why a path is or isn't indexed
+    Explain { path: PathBuf },
+    /// Show the size and state of the index without the daemon
+    Status,
+    /// Check that chunks, embeddings and search indexes agree with each other
+    Fsck {
+        /// Fix what can be fixed; files with bad chunks are re-indexed by the
+        /// daemon on its next start
+        #[arg(long, default_value_t = false)]
+        repair: bool,
+    },
+    /// Delete data nothing refers to any more and expired usage analytics
+    Gc {
+        /// Shrink the database file afterwards; blocks writes while it runs
+        #[arg(long, default_value_t = false)]
+        vacuum: bool,
+    },
+    /// Remove files that no longer exist on disk from the index
+    Prune {
+        /// Delete without asking for confirmation
+        #[arg(long, default_value_t = false)]
+        yes: bool,
+    },
+    /// Remove entries from the index
+    Purge {
+        /// Only remove files whose path matches this glob
+        #[arg(long)]
+        path: Option<String>,
+        /// Only remove files last modified longer ago than this (e.g. 90d, 12h)
+        #[arg(long, value_parser = parse_age)]
+        older_than: Option<u64>,
+        /// Only remove files in this collection (`default` for files outside
+        /// every configured one)
+        #[arg(long)]
+        collection: Option<String>,
+        /// Delete without asking for confirmation
+        #[arg(long, default_value_t = false)]
+        yes: bool,
+    },
+    /// Measure chunking, embedding, insert and query throughput
+    Bench {
+        /// Benchmark this path instead of a synthetic corpus
+        path: Option<PathBuf>,
+        /// Number of synthetic files to generate
+        #[arg(long, default_value = "200")]
+        files: usize,
+        /// Number of queries per search mode
+        #[arg(long, default_value = "50")]
+        queries: usize,
+    },
+    /// Summarize index growth, query volume and latency, most-queried areas
+    /// and errors from local usage data
+    Report {
+        /// Cover this many days
+        #[arg(long, default_value = "30")]
+        days: u32,
+        /// Print JSON instead of markdown
+        #[arg(long, default_value_t = false)]
+        json: bool,
+        /// Write the report to this file
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Measure retrieval quality against labeled queries
+    Eval {
+        /// JSONL file of {"query": ..., "expected_paths": [...]} lines
+        queries: PathBuf,
+        /// Cutoff for recall@k
+        #[arg(short, long, default_value = "10")]
+        k: usize,
+    },
+    /// Manage tags on files, directories and chunks
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    /// Check, back up or repair the index database
+    Db {
+        #[command(subcommand)]
+        action: DbAction,
     },
     /// Configure MCP for compatible AI tools
     Connect {
@@ -26,27 +176,91 @@ pub enum Commands {
         #[arg(long, default_value_t = false)]
         all: bool,
     },
+    /// Install or remove the daemon as a Windows service
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum McpAction {
+    /// Add contextd to the Claude Desktop, Cursor and Windsurf MCP configs
+    Install {
+        /// Only configure these clients (claude-desktop, cursor, windsurf)
+        #[arg(long)]
+        tool: Vec<String>,
+        /// Print the resulting configs without writing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TagAction {
+    /// Tag a file, directory, or `chunk:<id>`
+    Add { target: String, tag: String },
+    /// Remove a tag from a file, directory, or `chunk:<id>`
+    Remove { target: String, tag: String },
+    /// List all tags
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ServiceAction {
+    /// Register the daemon to start at boot with this config, resolving
+    /// relative paths from the current directory (run as administrator)
+    Install,
+    /// Stop the service and remove it (run as administrator)
+    Uninstall,
+    /// Run as the service; started by the service control manager
+    #[command(hide = true)]
+    Run {
+        /// Directory relative paths in the config resolve from
+        #[arg(long)]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbAction {
+    /// Check the index for corruption
+    Check {
+        /// Skip cross-checking indexes against their tables
+        #[arg(long, default_value_t = false)]
+        quick: bool,
+    },
+    /// Copy the index to a new file, safely while the daemon runs
+    Backup { path: PathBuf },
+    /// Replace a corrupt index with a backup, or with a new one to re-index
+    Repair {
+        /// Restore this backup, made by `contextd db backup`
+        #[arg(long)]
+        from: Option<PathBuf>,
+    },
 }
 
 pub async fn handle_setup(config: &Config) -> Result<()> {
     println!("Setting up model: {}", config.storage.model_type);
     println!("Target directory: {:?}", config.storage.model_path);
 
-    crate::download::ensure_model_files(&config.storage.model_path, &config.storage.model_type)
-        .await?;
+    collections::ensure_models(config).await?;
 
     println!("Model setup complete.");
     Ok(())
 }
 
-pub async fn handle_query(config: &Config, query: &str, context_lines: usize) -> Result<()> {
+pub async fn handle_query(
+    config: &Config,
+    query: &str,
+    context_lines: usize,
+    mode: Option<SearchMode>,
+) -> Result<()> {
     let db = Database::new(&config.storage.db_path)?;
-    let embedder = Embedder::new(&config.storage)?;
-
-    let embedding = embedder.embed(query)?;
+    let models = Models::load(config)?;
+    crate::migration::prepare(&db, config, &models)?;
 
     let options = SearchOptions {
-        limit: Some(10),
         context_lines: if context_lines > 0 {
             Some(context_lines)
         } else {
@@ -55,11 +269,29 @@ pub async fn handle_query(config: &Config, query: &str, context_lines: usize) ->
         ..Default::default()
     };
 
-    let results = db.search_chunks_hybrid(query, &embedding, &options)?;
+    let audit = AuditLog::open(&config.audit)?;
+    let query = search::Query {
+        text: query.to_string(),
+        mode,
+        options,
+        ..Default::default()
+    };
+    let page = QueryService::new("cli", &db, &models, config, &audit)
+        .search(&query, std::env::var("USER").ok().as_deref())?;
 
-    println!("Found {} results for '{}':", results.len(), query);
-    for (i, res) in results.iter().enumerate() {
-        println!("\n{}. {} (Score: {:.4})", i + 1, res.file_path, res.score);
+    println!("Found {} results for '{}':", page.hits.len(), query.text);
+    for (i, hit) in page.hits.iter().enumerate() {
+        let res = &hit.result;
+        println!(
+            "\n{}. {} (Score: {:.4}) [chunk {}]",
+            i + 1,
+            res.file_path,
+            res.score,
+            res.id
+        );
+        if let Some(reason) = &hit.stale_reason {
+            println!("   [stale: {}]", reason);
+        }
         println!(
             "   {}...",
             res.content
@@ -72,3 +304,713 @@ pub async fn handle_query(config: &Config, query: &str, context_lines: usize) ->
 
     Ok(())
 }
+
+/// Maximum number of ignored files listed by `index --dry-run`.
+const MAX_LISTED_IGNORED: usize = 50;
+
+pub async fn handle_index(config: &Config, path: &Path, dry_run: bool) -> Result<()> {
+    if !path.exists() {
+        anyhow::bail!("Path {:?} does not exist", path);
+    }
+
+    // Index repositories under their canonical root so they line up with the
+    // registered workspace the daemon will watch
+    let workspace_root = crate::workspace::find_root(path);
+    let path = match &workspace_root {
+        Some(_) => canonicalize(path)?,
+        None => path.to_path_buf(),
+    };
+    let path = path.as_path();
+    let mut files = scan_files(path, &config.watch);
+    files.retain(|f| config.watch.allows(f));
+
+    if dry_run {
+        println!("Would index {} files under {:?}", files.len(), path);
+        for (ext, count) in extension_breakdown(&files) {
+            println!("  {:<12} {}", ext, count);
+        }
+
+        let ignored = ignored_files(path, &files);
+        println!("\nIgnored {} files", ignored.len());
+        for file in ignored.iter().take(MAX_LISTED_IGNORED) {
+            println!("  {}", file.display());
+        }
+        if ignored.len() > MAX_LISTED_IGNORED {
+            println!("  ... and {} more", ignored.len() - MAX_LISTED_IGNORED);
+        }
+        return Ok(());
+    }
+
+    collections::ensure_models(config).await?;
+
+    let db = Database::new(&config.storage.db_path)?;
+    let mut config = config.clone();
+    if let Some(root) = workspace_root.filter(|_| config.watch.register_workspaces) {
+        if db.register_workspace(&root)? {
+            println!("Registered workspace {:?}", root);
+        }
+        // Index into the collection the daemon will put it in
+        crate::workspace::add_to_config(&mut config, &root);
+    }
+    let models = Arc::new(Models::load(&config)?);
+    // Embed with the model a collection is on until the daemon migrates it
+    crate::migration::prepare(&db, &config, &models)?;
+    let config = Arc::new(config);
+
+    let pb = ProgressBar::new(files.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} {msg}")?,
+    );
+    for file in files {
+        pb.set_message(format!("{:?}", file.file_name().unwrap_or_default()));
+        daemon::index_file(file, config.clone(), db.clone(), models.clone()).await;
+        pb.inc(1);
+    }
+    pb.finish_with_message("done");
+
+    let stats = db.get_stats()?;
+    println!(
+        "Index now holds {} files and {} chunks.",
+        stats.file_count, stats.chunk_count
+    );
+    Ok(())
+}
+
+pub async fn handle_reindex(config: &Config, apply: bool) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+    let stale = crate::reindex::plan(&db, config)?;
+    if stale.is_empty() {
+        println!("All indexed files are up to date with the current settings.");
+        return Ok(());
+    }
+    if !apply {
+        println!(
+            "{} files were indexed with different settings:",
+            stale.len()
+        );
+        for file in &stale {
+            println!("  {}", file.path);
+            for reason in &file.reasons {
+                println!("    {}", reason);
+            }
+        }
+        println!("\nRun `contextd reindex --apply` to re-index them.");
+        return Ok(());
+    }
+
+    if let Some(queued) = request_daemon_reindex(config).await? {
+        println!(
+            "The daemon is re-indexing {} files in the background.",
+            queued
+        );
+        return Ok(());
+    }
+
+    collections::ensure_models(config).await?;
+    let models = Arc::new(Models::load(config)?);
+    // Embed with the model a collection is on until the daemon migrates it
+    crate::migration::prepare(&db, config, &models)?;
+    let config = Arc::new(config.clone());
+    let pb = ProgressBar::new(stale.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} {msg}")?,
+    );
+    let paths = stale.into_iter().map(|f| f.path).collect();
+    crate::reindex::apply(paths, config, db, models, |path| {
+        pb.set_message(path.to_string());
+        pb.inc(1);
+    })
+    .await?;
+    pb.finish_with_message("done");
+    Ok(())
+}
+
+pub async fn handle_rechunk(config: &Config, language: &str) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+    let outdated = crate::reindex::outdated_chunks(&db, config, language).map_err(|e| {
+        let known: Vec<&str> = chunker::CHUNKER_VERSIONS.iter().map(|(l, _)| *l).collect();
+        e.context(format!("Known languages: {}", known.join(", ")))
+    })?;
+    if outdated.is_empty() {
+        println!("No {} files were chunked by an older chunker.", language);
+        return Ok(());
+    }
+
+    collections::ensure_models(config).await?;
+    let models = Arc::new(Models::load(config)?);
+    // Embed with the model a collection is on until the daemon migrates it
+    crate::migration::prepare(&db, config, &models)?;
+    let config = Arc::new(config.clone());
+    let pb = ProgressBar::new(outdated.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} {msg}")?,
+    );
+    crate::reindex::apply(outdated, config, db, models, |path| {
+        pb.set_message(path.to_string());
+        pb.inc(1);
+    })
+    .await?;
+    pb.finish_with_message("done");
+    Ok(())
+}
+
+/// Ask a running daemon to re-index in the background. Returns the number of
+/// files it queued, or `None` when no daemon is listening.
+async fn request_daemon_reindex(config: &Config) -> Result<Option<usize>> {
+    let host = match config.server.host.as_str() {
+        "0.0.0.0" | "::" => "127.0.0.1",
+        host => host,
+    };
+    let url = format!("http://{}:{}/reindex", host, config.server.port);
+    let mut request = reqwest::Client::new().post(&url);
+    if let Some(key) = &config.server.api_key {
+        request = request.bearer_auth(key);
+    }
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) if e.is_connect() => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "Daemon refused to re-index ({}): {}",
+            response.status(),
+            response.text().await.unwrap_or_default()
+        );
+    }
+    let body: serde_json::Value = response.json().await?;
+    Ok(Some(body["queued"].as_u64().unwrap_or(0) as usize))
+}
+
+pub fn handle_explain(config: &Config, path: &Path) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+    let report = crate::explain::explain(config, &db, path)?;
+
+    println!("{}: {}", report.path.display(), report.verdict);
+    match &report.watch_root {
+        Some(root) => println!("  Watch root: {}", root.display()),
+        None => println!("  Watch root: none"),
+    }
+    println!(
+        "  Ignored:    {}",
+        report.ignored_by.as_deref().unwrap_or("no")
+    );
+    if let Some(size) = report.size {
+        println!(
+            "  Size:       {} bytes{}",
+            size,
+            if report.binary {
+                " (not UTF-8 text)"
+            } else {
+                ""
+            }
+        );
+    }
+    if let Some(handler) = &report.handler {
+        println!("  Handler:    {}", handler);
+    }
+    match &report.index {
+        Some(status) => println!(
+            "  Index:      {} chunks as {}, last indexed {}{}",
+            status.chunks,
+            status.indexed_path,
+            format_timestamp(status.last_indexed),
+            if status.up_to_date {
+                ""
+            } else {
+                " (out of date)"
+            }
+        ),
+        None => println!("  Index:      not indexed"),
+    }
+    if let Some(error) = &report.last_error {
+        println!("  Last error: {}", error);
+    }
+    if let Some(eviction) = &report.evicted {
+        println!(
+            "  Evicted:    {} chunks from collection {} {}, {}",
+            eviction.chunks,
+            eviction.collection,
+            format_timestamp(Some(eviction.evicted_at)),
+            eviction.reason
+        );
+    }
+    Ok(())
+}
+
+pub fn handle_show(config: &Config, path: &Path) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+    let path_str = path.to_string_lossy().to_string();
+
+    let file = match db.get_file(&path_str)? {
+        Some(file) => file,
+        None => {
+            let mut matches = db.find_files_by_suffix(&path_str)?;
+            match matches.len() {
+                0 => anyhow::bail!("{:?} is not in the index", path),
+                1 => matches.remove(0),
+                _ => {
+                    println!("{:?} matches several indexed files:", path);
+                    for m in matches {
+                        println!("  {}", m.path);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    let chunks = db.get_chunks(file.id)?;
+    // Line numbers are derived from the current on-disk content
+    let on_disk = std::fs::read_to_string(&file.path).ok();
+
+    println!("File: {} (id {})", file.path, file.id);
+    println!(
+        "Last modified: {}",
+        format_timestamp(Some(file.last_modified))
+    );
+    println!("Last indexed:  {}", format_timestamp(file.last_indexed));
+    println!("{} chunks", chunks.len());
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let lines = match &on_disk {
+            Some(content) => format!(
+                "lines {}-{}",
+                line_at(content, chunk.start as usize),
+                line_at(content, chunk.end.saturating_sub(1) as usize)
+            ),
+            None => "lines ?".to_string(),
+        };
+        println!(
+            "\n#{} [chunk {}] bytes {}..{}, {}, embedding: {}",
+            i + 1,
+            chunk.id,
+            chunk.start,
+            chunk.end,
+            lines,
+            if chunk.has_embedding { "yes" } else { "no" }
+        );
+        if let Some(meta) = &chunk.metadata {
+            println!("   metadata: {}", meta);
+        }
+        println!(
+            "   {}...",
+            chunk
+                .content
+                .replace('\n', " ")
+                .chars()
+                .take(100)
+                .collect::<String>()
+        );
+    }
+
+    Ok(())
+}
+
+pub fn handle_chunk(path: &Path, debug: bool) -> Result<()> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+    let chunks = chunker::chunk_by_type(&content, ext)?;
+    if debug {
+        print!("{}", chunker::debug_snapshot(&content, ext, &chunks));
+        return Ok(());
+    }
+
+    println!(
+        "{}: {} chunks ({} chunker)",
+        path.display(),
+        chunks.len(),
+        chunker::chunker_name(ext)
+    );
+    for (i, chunk) in chunks.iter().enumerate() {
+        println!(
+            "#{} bytes {}..{}, lines {}-{}: {}",
+            i + 1,
+            chunk.start,
+            chunk.end,
+            line_at(&content, chunk.start as usize),
+            line_at(&content, chunk.end.saturating_sub(1) as usize),
+            chunk.content.lines().next().unwrap_or_default().trim()
+        );
+    }
+    Ok(())
+}
+
+pub fn handle_tag(config: &Config, action: TagAction) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+
+    match action {
+        TagAction::Add { target, tag } => {
+            match parse_chunk_target(&target)? {
+                Some(chunk_id) => db.add_chunk_tag(chunk_id, &tag)?,
+                None => db.add_path_tag(&target, &tag)?,
+            }
+            println!("Tagged {} with '{}'", target, tag);
+        }
+        TagAction::Remove { target, tag } => {
+            let removed = match parse_chunk_target(&target)? {
+                Some(chunk_id) => db.remove_chunk_tag(chunk_id, &tag)?,
+                None => db.remove_path_tag(&target, &tag)?,
+            };
+            if removed {
+                println!("Removed '{}' from {}", tag, target);
+            } else {
+                println!("{} was not tagged '{}'", target, tag);
+            }
+        }
+        TagAction::List => {
+            let tags = db.list_tags()?;
+            if tags.is_empty() {
+                println!("No tags.");
+            }
+            for entry in tags {
+                match (entry.path, entry.chunk_id) {
+                    (Some(path), _) if path.is_empty() => {
+                        println!("{:<20} (everything)", entry.tag)
+                    }
+                    (Some(path), _) => println!("{:<20} {}", entry.tag, path),
+                    (None, Some(id)) => println!("{:<20} chunk:{}", entry.tag, id),
+                    (None, None) => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `chunk:<id>` targets a single chunk; anything else is a path.
+fn parse_chunk_target(target: &str) -> Result<Option<i64>> {
+    match target.strip_prefix("chunk:") {
+        Some(id) => {
+            Ok(Some(id.parse().map_err(|_| {
+                anyhow::anyhow!("Invalid chunk id '{}'", id)
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Maximum number of files listed in the purge summary.
+const MAX_LISTED_PURGE: usize = 50;
+
+pub fn handle_status(config: &Config) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+    let stats = db.get_stats()?;
+    println!(
+        "Index: {} ({:.1} MB)",
+        config.storage.db_path.display(),
+        stats.db_size as f64 / 1024.0 / 1024.0
+    );
+    println!("Files: {}", stats.file_count);
+    println!("Chunks: {}", stats.chunk_count);
+    if stats.archived_chunk_count > 0 {
+        println!("Archived chunks: {}", stats.archived_chunk_count);
+    }
+    let failing = db.index_error_count()?;
+    if failing > 0 {
+        println!(
+            "Failing to index: {} files (see `contextd explain <path>`)",
+            failing
+        );
+    }
+    let missing = moves::missing_files(&db, &moves::index_roots(&db, config)?)?.len();
+    if missing > 0 {
+        println!(
+            "No longer on disk: {} files (see `contextd prune`)",
+            missing
+        );
+    }
+    if let Some(scan) = crate::scan::progress(&db)? {
+        println!("{}", scan.describe());
+    }
+    Ok(())
+}
+
+pub fn handle_prune(config: &Config, yes: bool) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+    let missing = moves::missing_files(&db, &moves::index_roots(&db, config)?)?;
+    if missing.is_empty() {
+        println!("Every indexed file still exists.");
+        return Ok(());
+    }
+
+    println!("{} indexed files no longer exist:", missing.len());
+    for file in missing.iter().take(MAX_LISTED_PURGE) {
+        println!("  {}", file.path);
+    }
+    if missing.len() > MAX_LISTED_PURGE {
+        println!("  ... and {} more", missing.len() - MAX_LISTED_PURGE);
+    }
+
+    if !yes {
+        println!("\nRe-run with --yes to remove them.");
+        return Ok(());
+    }
+
+    for file in &missing {
+        db.remove_file(&file.path)?;
+    }
+    println!("Pruned {} files.", missing.len());
+    Ok(())
+}
+
+pub fn handle_purge(
+    config: &Config,
+    path_glob: Option<&str>,
+    older_than: Option<u64>,
+    collection: Option<&str>,
+    yes: bool,
+) -> Result<()> {
+    if let Some(name) = collection {
+        let names = collections::collection_names(config);
+        if !names.contains(name) {
+            anyhow::bail!(
+                "Unknown collection '{}' (expected one of: {})",
+                name,
+                names.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+    let db = Database::new(&config.storage.db_path)?;
+
+    let matcher = path_glob
+        .map(|g| globset::Glob::new(g).map(|g| g.compile_matcher()))
+        .transpose()?;
+    let in_collection = collection
+        .map(|name| db.collection_file_ids(name))
+        .transpose()?;
+    let cutoff = older_than.map(|age| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .saturating_sub(age)
+    });
+
+    let targets: Vec<_> = db
+        .list_files()?
+        .into_iter()
+        .filter(|f| {
+            matcher
+                .as_ref()
+                .is_none_or(|m| m.is_match(&f.path) || m.is_match(f.path.trim_start_matches("./")))
+        })
+        .filter(|f| cutoff.is_none_or(|c| f.last_modified < c))
+        .filter(|f| in_collection.as_ref().is_none_or(|ids| ids.contains(&f.id)))
+        .collect();
+
+    if targets.is_empty() {
+        println!("Nothing to purge.");
+        return Ok(());
+    }
+
+    let mut chunk_total = 0;
+    for file in &targets {
+        chunk_total += db.count_chunks(file.id)?;
+    }
+
+    println!(
+        "Will remove {} files ({} chunks):",
+        targets.len(),
+        chunk_total
+    );
+    for file in targets.iter().take(MAX_LISTED_PURGE) {
+        println!("  {}", file.path);
+    }
+    if targets.len() > MAX_LISTED_PURGE {
+        println!("  ... and {} more", targets.len() - MAX_LISTED_PURGE);
+    }
+
+    if !yes {
+        println!("\nRe-run with --yes to delete.");
+        return Ok(());
+    }
+
+    for file in &targets {
+        db.delete_file(file.id)?;
+    }
+    println!("Purged {} files.", targets.len());
+    Ok(())
+}
+
+/// Parse an age like `90d`, `12h`, `30m`, `2w` or plain seconds into seconds.
+pub fn parse_age(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, "s"),
+    };
+    let value: u64 = num
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", s))?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => {
+            return Err(format!(
+                "unknown duration unit '{}' (use s, m, h, d or w)",
+                unit
+            ))
+        }
+    };
+    Ok(value * multiplier)
+}
+
+fn format_timestamp(ts: Option<u64>) -> String {
+    ts.and_then(|t| chrono::DateTime::from_timestamp(t as i64, 0))
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| "never".to_string())
+}
+
+/// Collect the files under `path` that the daemon would index.
+fn scan_files(path: &Path, watch: &WatchConfig) -> Vec<PathBuf> {
+    if path.is_file() {
+        return vec![path.to_path_buf()];
+    }
+
+    let mut files = Vec::new();
+    for result in daemon::walk(path, watch) {
+        match result {
+            Ok(entry) if entry.path().is_file() => files.push(entry.into_path()),
+            Ok(_) => {}
+            Err(err) => eprintln!("Error during scan: {}", err),
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Files under `path` that exist on disk but are skipped by the ignore rules.
+fn ignored_files(path: &Path, indexed: &[PathBuf]) -> Vec<PathBuf> {
+    if path.is_file() {
+        return Vec::new();
+    }
+
+    let indexed: HashSet<&PathBuf> = indexed.iter().collect();
+    let mut ignored: Vec<PathBuf> = WalkBuilder::new(path)
+        .standard_filters(false)
+        .filter_entry(|e| e.file_name() != ".git")
+        .build()
+        .filter_map(|r| r.ok())
+        .filter(|e| e.path().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| !indexed.contains(p))
+        .collect();
+    ignored.sort();
+    ignored
+}
+
+/// Count files per extension (files without one are grouped under `(none)`).
+fn extension_breakdown(files: &[PathBuf]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for file in files {
+        let ext = file
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or("(none)")
+            .to_string();
+        *counts.entry(ext).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scan_respects_contextignore() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".contextignore"), "*.log\n").unwrap();
+        fs::write(root.join("main.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("notes.md"), "# Notes").unwrap();
+        fs::write(root.join("debug.log"), "noise").unwrap();
+        fs::create_dir(root.join("vendor")).unwrap();
+        fs::write(root.join("vendor/.contextignore"), "*.rs\n").unwrap();
+        fs::write(root.join("vendor/dep.rs"), "fn dep() {}").unwrap();
+
+        let files = scan_files(root, &WatchConfig::default());
+        assert!(files.iter().any(|f| f.ends_with("main.rs")));
+        assert!(!files.iter().any(|f| f.ends_with("debug.log")));
+        assert!(!files.iter().any(|f| f.ends_with("vendor/dep.rs")));
+
+        let ignored = ignored_files(root, &files);
+        assert!(ignored.iter().any(|f| f.ends_with("debug.log")));
+        assert!(!ignored.iter().any(|f| f.ends_with("main.rs")));
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90d"), Ok(90 * 86400));
+        assert_eq!(parse_age("12h"), Ok(12 * 3600));
+        assert_eq!(parse_age("2w"), Ok(14 * 86400));
+        assert_eq!(parse_age("45"), Ok(45));
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
+
+    #[test]
+    fn test_purge_collection() {
+        let dir = tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.db_path = dir.path().join("contextd.db");
+        config
+            .collections
+            .insert("notes".to_string(), Default::default());
+        let db = Database::new(&config.storage.db_path).unwrap();
+        let notes = db.add_or_update_file("/notes/a.md", 100).unwrap();
+        db.add_chunk(
+            notes,
+            0,
+            5,
+            "notes",
+            None,
+            Some(r#"{"collection": "notes"}"#),
+        )
+        .unwrap();
+        let code = db.add_or_update_file("/src/lib.rs", 100).unwrap();
+        db.add_chunk(code, 0, 4, "code", None, None).unwrap();
+
+        assert!(handle_purge(&config, None, None, Some("music"), true).is_err());
+        handle_purge(&config, None, None, Some("notes"), true).unwrap();
+        let paths: Vec<String> = db
+            .list_files()
+            .unwrap()
+            .into_iter()
+            .map(|f| f.path)
+            .collect();
+        assert_eq!(paths, vec!["/src/lib.rs"]);
+
+        handle_purge(&config, None, None, Some("default"), true).unwrap();
+        assert!(db.list_files().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_chunk_target() {
+        assert_eq!(parse_chunk_target("chunk:42").unwrap(), Some(42));
+        assert_eq!(parse_chunk_target("src/payments/").unwrap(), None);
+        assert!(parse_chunk_target("chunk:abc").is_err());
+    }
+
+    #[test]
+    fn test_extension_breakdown() {
+        let files = vec![
+            PathBuf::from("a.rs"),
+            PathBuf::from("b.rs"),
+            PathBuf::from("README"),
+        ];
+        let counts = extension_breakdown(&files);
+        assert_eq!(counts.get("rs"), Some(&2));
+        assert_eq!(counts.get("(none)"), Some(&1));
+    }
+}
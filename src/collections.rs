@@ -0,0 +1,385 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
+
+use crate::config::{Config, StorageConfig};
+use crate::indexer::embeddings::{BackendKind, Embedder};
+use crate::paths::canonicalize;
+use crate::staleness::DEFAULT_COLLECTION;
+use crate::storage::db::{
+    Database, MetadataFilter, QueryStage, SearchOptions, SearchResult, EMBEDDING_DIMENSIONS,
+};
+
+/// `[collections.<name>]` config: files under `paths` form a collection with
+/// its own model, chunking and search defaults. Unset fields fall back to
+/// `[storage]`, `[indexing]` and the built-in search weights.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CollectionConfig {
+    /// Files under these directories belong to the collection
+    pub paths: Vec<PathBuf>,
+    /// Embedding model for the collection's chunks and queries against them
+    pub model_type: Option<String>,
+    /// Where that model is kept (default: `<storage.model_path>/<model_type>`)
+    pub model_path: Option<PathBuf>,
+    /// Overrides `[indexing] max_chunk_chars`
+    pub max_chunk_chars: Option<usize>,
+    pub recency_weight: Option<f32>,
+    pub frequency_weight: Option<f32>,
+    /// Drop the collection's results scoring below this
+    pub min_score: Option<f32>,
+    /// Evict files once the collection holds more chunks than this
+    pub max_chunks: Option<u64>,
+    /// Evict files once the collection's chunks take up more megabytes than
+    /// this
+    pub max_db_mb: Option<u64>,
+    /// Evict files not modified for this many days
+    pub max_age_days: Option<u64>,
+    /// Which files go first when over `max_chunks` or `max_db_mb`
+    pub eviction: EvictionOrder,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionOrder {
+    /// Least recently modified
+    #[default]
+    Oldest,
+    /// Least recently returned by a query
+    Lru,
+}
+
+impl CollectionConfig {
+    fn has_search_defaults(&self) -> bool {
+        self.recency_weight.is_some() || self.frequency_weight.is_some() || self.min_score.is_some()
+    }
+}
+
+/// The configured collection `path` belongs to: the one whose path is the
+/// longest prefix of it.
+pub fn collection_for<'a>(config: &'a Config, path: &Path) -> Option<&'a str> {
+    let path = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    config
+        .collections
+        .iter()
+        .flat_map(|(name, c)| c.paths.iter().map(move |root| (name.as_str(), root)))
+        .filter_map(|(name, root)| {
+            let root = canonicalize(root).unwrap_or_else(|_| root.clone());
+            path.starts_with(&root)
+                .then(|| (root.components().count(), Reverse(name)))
+        })
+        .max()
+        .map(|(_, Reverse(name))| name)
+}
+
+/// `[storage]` with a collection's model settings applied.
+pub fn storage_for(config: &Config, collection: Option<&str>) -> StorageConfig {
+    let Some(c) = collection.and_then(|name| config.collections.get(name)) else {
+        return config.storage.clone();
+    };
+    let mut storage = match &c.model_type {
+        Some(model_type) => storage_with_model(config, model_type),
+        None => config.storage.clone(),
+    };
+    if let Some(model_path) = &c.model_path {
+        storage.model_path = model_path.clone();
+    }
+    storage
+}
+
+/// `[storage]` with `model_type` as the model. Models other than the default
+/// one are kept in `<model_path>/<model_type>`, apart from its files.
+pub fn storage_with_model(config: &Config, model_type: &str) -> StorageConfig {
+    let mut storage = config.storage.clone();
+    if model_type != storage.model_type {
+        storage.model_path = storage.model_path.join(model_type);
+        storage.model_type = model_type.to_string();
+    }
+    storage
+}
+
+/// Longest chunk, in characters, for files of a collection.
+pub fn max_chunk_chars(config: &Config, collection: Option<&str>) -> Option<usize> {
+    collection
+        .and_then(|name| config.collections.get(name))
+        .and_then(|c| c.max_chunk_chars)
+        .or(config.indexing.max_chunk_chars)
+}
+
+/// Download the default model and each collection's where missing. Returns
+/// whether anything was downloaded; remote backends need no files.
+pub async fn ensure_models(config: &Config) -> Result<bool> {
+    if config.embeddings.backend != BackendKind::Onnx {
+        return Ok(false);
+    }
+    let mut downloaded =
+        crate::download::ensure_model_files(&config.storage.model_path, &config.storage.model_type)
+            .await?;
+    for name in config.collections.keys() {
+        let storage = storage_for(config, Some(name));
+        downloaded |=
+            crate::download::ensure_model_files(&storage.model_path, &storage.model_type).await?;
+    }
+    Ok(downloaded)
+}
+
+/// Embedding models for the default collection and every configured one,
+/// each distinct model loaded once. A collection's model can be switched
+/// while the daemon runs, see [`crate::migration`].
+pub struct Models {
+    embedders: RwLock<Embedders>,
+    /// Held shared while a file's chunks are embedded and stored, and
+    /// exclusively while a collection switches models
+    switching: RwLock<()>,
+}
+
+struct Embedders {
+    default: Arc<Embedder>,
+    collections: HashMap<String, Arc<Embedder>>,
+}
+
+impl Models {
+    pub fn load(config: &Config) -> Result<Self> {
+        let default = Arc::new(Embedder::new(&config.storage, &config.embeddings)?);
+        let mut loaded: HashMap<(String, PathBuf), Arc<Embedder>> = HashMap::new();
+        loaded.insert(
+            (
+                config.storage.model_type.clone(),
+                config.storage.model_path.clone(),
+            ),
+            default.clone(),
+        );
+
+        let mut collections = HashMap::new();
+        for name in config.collections.keys() {
+            let storage = storage_for(config, Some(name));
+            let key = (storage.model_type.clone(), storage.model_path.clone());
+            let embedder = match loaded.get(&key) {
+                Some(embedder) => embedder.clone(),
+                None => {
+                    let embedder = Arc::new(
+                        Embedder::new(&storage, &config.embeddings).with_context(|| {
+                            format!(
+                                "Failed to load model {} for collection '{}' from {:?}",
+                                storage.model_type, name, storage.model_path
+                            )
+                        })?,
+                    );
+                    if embedder.dimensions() != EMBEDDING_DIMENSIONS {
+                        anyhow::bail!(
+                            "Collection '{}' uses {}, which produces {}-dimension vectors; the index stores {}",
+                            name,
+                            storage.model_type,
+                            embedder.dimensions(),
+                            EMBEDDING_DIMENSIONS
+                        );
+                    }
+                    loaded.insert(key, embedder.clone());
+                    embedder
+                }
+            };
+            collections.insert(name.clone(), embedder);
+        }
+        Ok(Self::with_embedders(default, collections))
+    }
+
+    /// Embed every collection with `embedder`, loading no models. Used with
+    /// [`Embedder::mock`] in tests.
+    pub fn single(embedder: Embedder) -> Self {
+        Self::with_embedders(Arc::new(embedder), HashMap::new())
+    }
+
+    fn with_embedders(default: Arc<Embedder>, collections: HashMap<String, Arc<Embedder>>) -> Self {
+        Self {
+            embedders: RwLock::new(Embedders {
+                default,
+                collections,
+            }),
+            switching: RwLock::new(()),
+        }
+    }
+
+    /// The model for `[storage]`, used for files outside any collection.
+    pub fn default_model(&self) -> Arc<Embedder> {
+        self.embedders.read().unwrap().default.clone()
+    }
+
+    pub fn for_collection(&self, collection: Option<&str>) -> Arc<Embedder> {
+        let embedders = self.embedders.read().unwrap();
+        collection
+            .and_then(|name| embedders.collections.get(name))
+            .unwrap_or(&embedders.default)
+            .clone()
+    }
+
+    /// Embed and search `collection` with `embedder` from now on.
+    pub fn set_active(&self, collection: &str, embedder: Arc<Embedder>) {
+        let mut embedders = self.embedders.write().unwrap();
+        if collection == DEFAULT_COLLECTION {
+            embedders.default = embedder;
+        } else {
+            embedders
+                .collections
+                .insert(collection.to_string(), embedder);
+        }
+    }
+
+    /// Keep every collection on its current model until the guard drops.
+    pub fn pin(&self) -> RwLockReadGuard<'_, ()> {
+        self.switching.read().unwrap()
+    }
+
+    /// Wait until no file is being embedded, then hold off new ones until the
+    /// guard drops.
+    pub fn lock_for_switch(&self) -> RwLockWriteGuard<'_, ()> {
+        self.switching.write().unwrap()
+    }
+}
+
+/// The default collection and every configured one, in name order.
+pub fn collection_names(config: &Config) -> std::collections::BTreeSet<&str> {
+    std::iter::once(DEFAULT_COLLECTION)
+        .chain(config.collections.keys().map(String::as_str))
+        .collect()
+}
+
+/// Search every collection for `query`. Collections with their own model or
+/// search defaults get a search of their own, embedded with their model; the
+/// results are merged by score. Options set by the caller win over
+/// collection defaults, which win over `[search]`.
+pub fn search(
+    db: &Database,
+    models: &Models,
+    config: &Config,
+    query: &str,
+    options: &SearchOptions,
+) -> Result<Vec<SearchResult>> {
+    let default_model = models.default_model();
+    let started = Instant::now();
+    let default_embedding = default_model.embed(query)?;
+    options.record(QueryStage::Embedding, started);
+    let separate: Vec<(&String, &CollectionConfig)> = config
+        .collections
+        .iter()
+        .filter(|(name, c)| {
+            c.has_search_defaults()
+                || !Arc::ptr_eq(&models.for_collection(Some(name)), &default_model)
+        })
+        .collect();
+    let search_one = |embedding: &[f32], options: &SearchOptions| {
+        if options.hybrid {
+            db.search_chunks_hybrid(query, embedding, options)
+        } else {
+            db.search_chunks_enhanced(embedding, options)
+        }
+    };
+    if separate.is_empty() {
+        return Ok(search_one(
+            &default_embedding,
+            &config.search.resolve(options, None),
+        )?);
+    }
+
+    let mut results = Vec::new();
+    let mut rest_filters = options.metadata_filters.clone().unwrap_or_default();
+    for (name, collection) in separate {
+        let embedder = models.for_collection(Some(name));
+        let embedding = if Arc::ptr_eq(&embedder, &default_model) {
+            default_embedding.clone()
+        } else {
+            let started = Instant::now();
+            let embedding = embedder.embed(query)?;
+            options.record(QueryStage::Embedding, started);
+            embedding
+        };
+        let mut filters = options.metadata_filters.clone().unwrap_or_default();
+        filters.push(MetadataFilter {
+            key: "collection".to_string(),
+            eq: Some(name.as_str().into()),
+            ..Default::default()
+        });
+        let collection_options = SearchOptions {
+            metadata_filters: Some(filters),
+            ..config.search.resolve(options, Some(collection))
+        };
+        results.extend(search_one(&embedding, &collection_options)?);
+
+        rest_filters.push(MetadataFilter {
+            key: "collection".to_string(),
+            ne: Some(name.as_str().into()),
+            ..Default::default()
+        });
+    }
+    let rest_options = SearchOptions {
+        metadata_filters: Some(rest_filters),
+        ..config.search.resolve(options, None)
+    };
+    results.extend(search_one(&default_embedding, &rest_options)?);
+
+    let started = Instant::now();
+    results.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    results.truncate(rest_options.limit.unwrap_or_default());
+    options.record(QueryStage::Rerank, started);
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_collection_for() {
+        let config = config(
+            r#"
+            [collections.notes]
+            paths = ["/home/me/notes"]
+            [collections.journal]
+            paths = ["/home/me/notes/journal"]
+            "#,
+        );
+        let of = |p: &str| collection_for(&config, Path::new(p));
+        assert_eq!(of("/home/me/notes/todo.md"), Some("notes"));
+        assert_eq!(of("/home/me/notes/journal/2026.md"), Some("journal"));
+        assert_eq!(of("/home/me/notesbook/a.md"), None);
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let config = config(
+            r#"
+            [storage]
+            model_path = "models"
+            [indexing]
+            max_chunk_chars = 2000
+            [collections.code]
+            paths = ["src"]
+            [collections.notes]
+            paths = ["notes"]
+            model_type = "bge-small-en-v1.5"
+            max_chunk_chars = 800
+            "#,
+        );
+        let code = storage_for(&config, Some("code"));
+        assert_eq!(code.model_type, config.storage.model_type);
+        assert_eq!(code.model_path, PathBuf::from("models"));
+        let notes = storage_for(&config, Some("notes"));
+        assert_eq!(notes.model_type, "bge-small-en-v1.5");
+        assert_eq!(notes.model_path, PathBuf::from("models/bge-small-en-v1.5"));
+
+        assert_eq!(max_chunk_chars(&config, Some("code")), Some(2000));
+        assert_eq!(max_chunk_chars(&config, Some("notes")), Some(800));
+        assert_eq!(max_chunk_chars(&config, None), Some(2000));
+    }
+}
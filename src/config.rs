@@ -1,39 +1,512 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-#[derive(Deserialize, Debug, Clone)]
+use crate::archive::ArchiveConfig;
+use crate::audit::AuditConfig;
+use crate::changelog::ChangelogConfig;
+use crate::collections::CollectionConfig;
+use crate::digest::DigestConfig;
+use crate::gc::GcConfig;
+use crate::history::HistoryConfig;
+use crate::indexer::chunker::ChunkingConfig;
+use crate::indexer::embeddings::EmbeddingsConfig;
+use crate::indexer::sniff::SniffingConfig;
+use crate::limits::LimitsConfig;
+use crate::logging::LoggingConfig;
+use crate::mcp::{ClientScope, McpConfig};
+use crate::power::OnBattery;
+use crate::search::SearchConfig;
+use crate::staleness::StalenessConfig;
+
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct Config {
+    #[serde(default)]
     pub server: ServerConfig,
+    #[serde(default)]
     pub storage: StorageConfig,
+    #[serde(default)]
+    pub embeddings: EmbeddingsConfig,
+    #[serde(default)]
     pub watch: WatchConfig,
     #[serde(default)]
-    pub plugins: HashMap<String, Vec<String>>,
+    pub plugins: HashMap<String, PluginConfig>,
+    #[serde(default)]
+    pub sniffing: SniffingConfig,
+    #[serde(default)]
+    pub staleness: StalenessConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub indexing: IndexingConfig,
+    #[serde(default)]
+    pub chunking: ChunkingConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub collections: HashMap<String, CollectionConfig>,
+    #[serde(default)]
+    pub mcp: McpConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub changelog: ChangelogConfig,
+    #[serde(default)]
+    pub digest: DigestConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub gc: GcConfig,
+    #[serde(default)]
+    pub search: SearchConfig,
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Require `Authorization: Bearer <key>` (or `X-API-Key`) on every
+    /// endpoint except the health checks
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Command `POST /open` runs to show a result, e.g.
+    /// `code --goto {location}`; `{file}`, `{line}` and `{column}` are also
+    /// filled in
+    #[serde(default)]
+    pub editor_command: Option<String>,
+    /// Users of a shared daemon, keyed by name, each with their own key and
+    /// scope; `api_key` stays an admin key with full access
+    #[serde(default)]
+    pub users: HashMap<String, UserConfig>,
+    /// Browser origins besides localhost allowed to call `/mcp`, e.g.
+    /// `https://agent.example.com`
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 3030,
+            api_key: None,
+            editor_command: None,
+            users: HashMap::new(),
+            allowed_origins: Vec::new(),
+        }
+    }
+}
+
+/// `[server.users.<name>]`: one API user of a shared daemon.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct UserConfig {
+    pub api_key: String,
+    /// Collections and directories the user may search and read, as for an
+    /// MCP client in `[mcp.clients]`
+    #[serde(flatten)]
+    pub scope: ClientScope,
+    /// Queries answered per UTC day; unset for no limit
+    #[serde(default)]
+    pub queries_per_day: Option<u64>,
+}
+
+impl ServerConfig {
+    /// Refuse to serve the index beyond this machine without an API key, or
+    /// users without keys of their own.
+    pub fn check_exposure(&self) -> Result<()> {
+        let loopback = self.host == "localhost"
+            || self
+                .host
+                .trim_matches(|c| c == '[' || c == ']')
+                .parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback());
+        let mut keys: Vec<&str> = self.api_key.iter().map(String::as_str).collect();
+        for (name, user) in &self.users {
+            if user.api_key.is_empty() || keys.contains(&user.api_key.as_str()) {
+                anyhow::bail!("[server.users.{}] needs an api_key of its own", name);
+            }
+            keys.push(&user.api_key);
+        }
+        if !loopback && keys.iter().all(|key| key.is_empty()) {
+            anyhow::bail!(
+                "Refusing to listen on {} without an API key; set [server] api_key (or CONTEXTD_SERVER__API_KEY)",
+                self.host
+            );
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct StorageConfig {
     pub db_path: PathBuf,
     pub model_path: PathBuf,
     #[serde(default = "default_model_type")]
     pub model_type: String,
+    /// Read all stored vectors at startup so the first query doesn't hit cold pages
+    #[serde(default)]
+    pub preload_vectors: bool,
+    /// ORT threads used within a single operator
+    #[serde(default = "default_intra_threads")]
+    pub intra_threads: usize,
+    /// ORT threads used to run independent operators in parallel
+    #[serde(default = "default_inter_threads")]
+    pub inter_threads: usize,
+    /// Use ORT's CPU arena allocator (faster, but holds on to peak memory)
+    #[serde(default = "default_true")]
+    pub memory_arena: bool,
+    /// Let ORT pre-plan allocations based on previous runs
+    #[serde(default = "default_true")]
+    pub memory_pattern: bool,
+    /// Graph optimization level: "disable", "level1", "level2" or "level3"
+    #[serde(default = "default_optimization_level")]
+    pub optimization_level: String,
+    /// JSON-lines log of every plugin invocation and its outcome
+    #[serde(default = "default_plugin_audit_log")]
+    pub plugin_audit_log: PathBuf,
+}
+
+impl StorageConfig {
+    /// Resolve relative database, model and audit log paths against `dir`.
+    pub fn place_in(&mut self, dir: &Path) {
+        for path in [
+            &mut self.db_path,
+            &mut self.model_path,
+            &mut self.plugin_audit_log,
+        ] {
+            if path.is_relative() {
+                *path = dir.join(&*path);
+            }
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            db_path: PathBuf::from("contextd.db"),
+            model_path: PathBuf::from("models"),
+            model_type: default_model_type(),
+            preload_vectors: false,
+            intra_threads: default_intra_threads(),
+            inter_threads: default_inter_threads(),
+            memory_arena: true,
+            memory_pattern: true,
+            optimization_level: default_optimization_level(),
+            plugin_audit_log: default_plugin_audit_log(),
+        }
+    }
 }
 
 fn default_model_type() -> String {
     "all-minilm-l6-v2".to_string()
 }
 
+fn default_intra_threads() -> usize {
+    4
+}
+
+fn default_inter_threads() -> usize {
+    1
+}
+
+fn default_optimization_level() -> String {
+    "level3".to_string()
+}
+
+fn default_plugin_audit_log() -> PathBuf {
+    PathBuf::from("plugin-audit.jsonl")
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// `[indexing]` config: how many files are chunked and embedded at once, and
+/// how large chunks may grow.
 #[derive(Deserialize, Debug, Clone)]
+pub struct IndexingConfig {
+    /// Workers kept even when the CPU is busy
+    #[serde(default = "default_min_workers")]
+    pub min_workers: usize,
+    /// Most workers added while files are queued (default: CPU count)
+    #[serde(default = "default_max_workers")]
+    pub max_workers: usize,
+    /// Workers are added below this system-wide CPU use and removed above it
+    #[serde(default = "default_target_cpu_percent")]
+    pub target_cpu_percent: u8,
+    /// Split chunks longer than this many characters at line breaks; unset
+    /// keeps chunks whole
+    #[serde(default)]
+    pub max_chunk_chars: Option<usize>,
+    /// Throttle or pause indexing while on battery power
+    #[serde(default)]
+    pub on_battery: OnBattery,
+}
+
+impl Default for IndexingConfig {
+    fn default() -> Self {
+        Self {
+            min_workers: default_min_workers(),
+            max_workers: default_max_workers(),
+            target_cpu_percent: default_target_cpu_percent(),
+            max_chunk_chars: None,
+            on_battery: OnBattery::default(),
+        }
+    }
+}
+
+fn default_min_workers() -> usize {
+    1
+}
+
+fn default_max_workers() -> usize {
+    std::thread::available_parallelism().map_or(4, |n| n.get())
+}
+
+fn default_target_cpu_percent() -> u8 {
+    80
+}
+
+/// An external command converting files of one extension to text. Written
+/// either as the bare command array, as a pipeline of commands, or as a table
+/// with limits:
+///
+/// ```toml
+/// [plugins]
+/// rtf = ["pandoc", "-t", "plain"]
+/// docx = [["pandoc", "-t", "markdown"], ["./cleanup.sh"]]
+/// odt = { command = ["pandoc", "-t", "markdown"], format = "md" }
+/// pdf = { command = ["./pdftotext.sh"], timeout_secs = 60, clear_env = true }
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "PluginEntry")]
+pub struct PluginConfig {
+    /// Program and arguments; the file path is appended
+    pub command: Vec<String>,
+    /// Later pipeline steps, each reading the previous step's output on stdin
+    pub pipe_to: Vec<Vec<String>>,
+    /// Extension of the format the plugin outputs (e.g. `md`), selecting the
+    /// chunker for the converted content
+    pub format: Option<String>,
+    /// Kill the plugin after this many seconds
+    pub timeout_secs: u64,
+    /// Fail (and kill the plugin) if it writes more than this to stdout
+    pub max_output_bytes: usize,
+    /// Run the plugin in this directory instead of the daemon's
+    pub working_dir: Option<PathBuf>,
+    /// Start the plugin with an empty environment except `env_allowlist`
+    pub clear_env: bool,
+    pub env_allowlist: Vec<String>,
+}
+
+impl PluginConfig {
+    /// A plugin running `command` with the default limits.
+    pub fn from_command(command: Vec<String>) -> Self {
+        Self {
+            command,
+            pipe_to: Vec::new(),
+            format: None,
+            timeout_secs: 30,
+            max_output_bytes: 16 * 1024 * 1024,
+            working_dir: None,
+            clear_env: false,
+            env_allowlist: default_env_allowlist(),
+        }
+    }
+
+    /// A plugin running `steps` as a pipeline with the default limits.
+    fn from_steps(steps: CommandSpec) -> Self {
+        match steps {
+            CommandSpec::Single(command) => Self::from_command(command),
+            CommandSpec::Pipeline(mut steps) => {
+                let rest = if steps.is_empty() {
+                    Vec::new()
+                } else {
+                    steps.split_off(1)
+                };
+                Self {
+                    pipe_to: rest,
+                    ..Self::from_command(steps.pop().unwrap_or_default())
+                }
+            }
+        }
+    }
+
+    /// Every command of the pipeline, in order.
+    pub fn steps(&self) -> impl Iterator<Item = &Vec<String>> {
+        std::iter::once(&self.command).chain(&self.pipe_to)
+    }
+
+    /// Extension whose chunker handles the plugin's output: the declared
+    /// `format`, else markdown for pipelines, else the input's own.
+    pub fn output_format<'a>(&'a self, ext: &'a str) -> &'a str {
+        match &self.format {
+            Some(format) => format.trim_start_matches('.'),
+            None if !self.pipe_to.is_empty() => "md",
+            None => ext,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CommandSpec {
+    Single(Vec<String>),
+    Pipeline(Vec<Vec<String>>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PluginEntry {
+    Command(CommandSpec),
+    Table(PluginTable),
+}
+
+#[derive(Deserialize)]
+struct PluginTable {
+    command: CommandSpec,
+    format: Option<String>,
+    timeout_secs: Option<u64>,
+    max_output_bytes: Option<usize>,
+    working_dir: Option<PathBuf>,
+    #[serde(default)]
+    clear_env: bool,
+    #[serde(default = "default_env_allowlist")]
+    env_allowlist: Vec<String>,
+}
+
+impl From<PluginEntry> for PluginConfig {
+    fn from(entry: PluginEntry) -> Self {
+        match entry {
+            PluginEntry::Command(command) => PluginConfig::from_steps(command),
+            PluginEntry::Table(table) => {
+                let defaults = PluginConfig::from_steps(table.command);
+                PluginConfig {
+                    format: table.format,
+                    timeout_secs: table.timeout_secs.unwrap_or(defaults.timeout_secs),
+                    max_output_bytes: table.max_output_bytes.unwrap_or(defaults.max_output_bytes),
+                    working_dir: table.working_dir,
+                    clear_env: table.clear_env,
+                    env_allowlist: table.env_allowlist,
+                    ..defaults
+                }
+            }
+        }
+    }
+}
+
+fn default_env_allowlist() -> Vec<String> {
+    ["PATH", "HOME", "LANG", "TMPDIR"]
+        .iter()
+        .map(|v| v.to_string())
+        .collect()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct WatchConfig {
     pub paths: Vec<PathBuf>,
+    /// Only index files with these extensions (e.g. `["rs", "md"]`); all
+    /// others are skipped before being read
+    #[serde(default)]
+    pub extensions: Option<Vec<String>>,
+    /// How long a path must go without changes before it is reindexed, in
+    /// milliseconds
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+    /// On macOS, skip `~/Library`, the Trash, Spotlight and FSEvents
+    /// metadata and Photos libraries below the watched roots
+    #[serde(default = "default_true")]
+    pub system_exclusions: bool,
+    /// How long macOS collects file changes before reporting them together,
+    /// in milliseconds
+    #[serde(default = "default_fsevents_latency_ms")]
+    pub fsevents_latency_ms: u64,
+    /// How often the daemon removes files that no longer exist from the
+    /// index, in seconds; 0 turns it off
+    #[serde(default = "default_sweep_interval_secs")]
+    pub sweep_interval_secs: u64,
+    /// Skip `node_modules`, `__pycache__` and `venv` directories even
+    /// without an ignore rule for them
+    #[serde(default)]
+    pub default_ignores: bool,
+    /// Watch workspaces registered by `contextd index` and language server
+    /// clients, each as a collection of its own. Off by default: they are
+    /// only registered and watched when this is set.
+    #[serde(default)]
+    pub register_workspaces: bool,
+    /// Registered workspaces [`crate::workspace::add_to_config`] added to
+    /// `paths`, which file access is never confined to
+    #[serde(skip)]
+    pub workspaces: Vec<PathBuf>,
+}
+
+/// ReadDirectoryChangesW reports a single save as several writes spread
+/// over a longer window, so Windows waits a little longer.
+fn default_debounce_ms() -> u64 {
+    if cfg!(windows) {
+        3000
+    } else {
+        2000
+    }
+}
+
+fn default_fsevents_latency_ms() -> u64 {
+    500
+}
+
+fn default_sweep_interval_secs() -> u64 {
+    3600
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        WatchConfig {
+            paths: vec![PathBuf::from(".")],
+            extensions: None,
+            debounce_ms: default_debounce_ms(),
+            system_exclusions: true,
+            fsevents_latency_ms: default_fsevents_latency_ms(),
+            sweep_interval_secs: default_sweep_interval_secs(),
+            default_ignores: false,
+            register_workspaces: false,
+            workspaces: Vec::new(),
+        }
+    }
+}
+
+impl WatchConfig {
+    /// `paths` without the registered workspaces added to them: the
+    /// directories REST and MCP clients may reach files in.
+    pub fn confined_paths(&self) -> Vec<PathBuf> {
+        self.paths
+            .iter()
+            .filter(|p| !self.workspaces.contains(p))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the extension allowlist, if any, admits `path`.
+    pub fn allows(&self, path: &Path) -> bool {
+        let Some(allowed) = &self.extensions else {
+            return true;
+        };
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        allowed
+            .iter()
+            .any(|a| a.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+    }
 }
 
 impl Config {
@@ -42,26 +515,122 @@ impl Config {
         let config: Config = toml::from_str(&content)?;
         Ok(config)
     }
-}
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            server: ServerConfig {
-                host: "127.0.0.1".to_string(),
-                port: 3030,
-            },
-            storage: StorageConfig {
-                db_path: PathBuf::from("contextd.db"),
-                model_path: PathBuf::from("models"),
-                model_type: default_model_type(),
-            },
-            watch: WatchConfig {
-                paths: vec![PathBuf::from(".")],
-            },
-            plugins: HashMap::new(),
+    /// Load `path` if it exists, else the defaults, then apply `CONTEXTD_*`
+    /// overrides from `env`.
+    pub fn load_with_env(
+        path: &Path,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self> {
+        let mut table = if path.exists() {
+            fs::read_to_string(path)?.parse::<toml::Table>()?
+        } else {
+            toml::Table::new()
+        };
+        let mut data_dir = None;
+        for (name, value) in env {
+            if name == DATA_DIR_VAR {
+                data_dir = Some(PathBuf::from(value));
+            } else if let Some(key) = env_key(&name) {
+                set_key(&mut table, &key, parse_env_value(&value))
+                    .with_context(|| format!("Invalid {}", name))?;
+            }
+        }
+        let mut config: Config = toml::Value::Table(table).try_into()?;
+        if let Some(dir) = data_dir {
+            config.storage.place_in(&dir);
+            if config.audit.file.is_relative() {
+                config.audit.file = dir.join(&config.audit.file);
+            }
+            if let Some(file) = config.digest.file.as_mut().filter(|f| f.is_relative()) {
+                *file = dir.join(&*file);
+            }
         }
+        Ok(config)
+    }
+}
+
+/// Directory that relative storage paths are resolved against.
+pub const DATA_DIR_VAR: &str = "CONTEXTD_DATA_DIR";
+
+/// Config file read when `--config` isn't given.
+pub const DEFAULT_CONFIG: &str = "contextd.toml";
+
+/// `$XDG_CONFIG_HOME/contextd`, falling back to `~/.config/contextd`.
+pub fn user_config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_DATA_HOME/contextd`, falling back to `~/.local/share/contextd`.
+pub fn user_data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = std::env::var_os(var)
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .or_else(|| std::env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(fallback))
+        })?;
+    Some(base.join("contextd"))
+}
+
+/// The config file to use without `--config`: `contextd.toml` in the
+/// current directory, else the one `contextd init` writes to the user
+/// config directory.
+pub fn default_config_path() -> PathBuf {
+    let local = PathBuf::from(DEFAULT_CONFIG);
+    if local.exists() {
+        return local;
+    }
+    user_config_dir()
+        .map(|dir| dir.join(DEFAULT_CONFIG))
+        .filter(|path| path.exists())
+        .unwrap_or(local)
+}
+
+/// Config key addressed by an environment variable:
+/// `CONTEXTD_STORAGE__DB_PATH` sets `storage.db_path`. The older
+/// `CONTEXTD_DB_PATH` and `CONTEXTD_MODEL_PATH` are accepted too.
+fn env_key(name: &str) -> Option<Vec<String>> {
+    let rest = name.strip_prefix("CONTEXTD_")?;
+    match rest {
+        "DB_PATH" => return Some(vec!["storage".into(), "db_path".into()]),
+        "MODEL_PATH" => return Some(vec!["storage".into(), "model_path".into()]),
+        _ => {}
+    }
+    // Anything else without a section, like plugin secrets, is not config
+    if !rest.contains("__") {
+        return None;
+    }
+    Some(rest.split("__").map(|part| part.to_lowercase()).collect())
+}
+
+/// A TOML value if the text parses as one (`3030`, `true`, `["a", "b"]`),
+/// else the text as a string.
+fn parse_env_value(value: &str) -> toml::Value {
+    format!("v = {}", value)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+fn set_key(table: &mut toml::Table, key: &[String], value: toml::Value) -> Result<()> {
+    let (last, parents) = key.split_last().context("empty key")?;
+    let mut table = table;
+    for part in parents {
+        table = table
+            .entry(part.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .with_context(|| format!("`{}` is not a table", part))?;
     }
+    table.insert(last.clone(), value);
+    Ok(())
 }
 
 #[cfg(test)]
@@ -96,6 +665,9 @@ paths = ["/tmp"]
 
 [plugins]
 test = ["echo"]
+pdf = {{ command = ["pdftotext", "-"], timeout_secs = 5, clear_env = true }}
+docx = [["pandoc", "-t", "markdown"], ["./cleanup.sh"]]
+odt = {{ command = ["pandoc", "-t", "markdown"], format = "md" }}
 "#
         )?;
 
@@ -103,9 +675,100 @@ test = ["echo"]
         assert_eq!(config.server.port, 8080);
         assert_eq!(config.server.host, "0.0.0.0");
         assert_eq!(config.storage.db_path, PathBuf::from("test.db"));
+        assert_eq!(config.storage.intra_threads, 4);
+        assert_eq!(config.storage.optimization_level, "level3");
         assert_eq!(config.watch.paths[0], PathBuf::from("/tmp"));
-        assert!(config.plugins.contains_key("test"));
+        assert_eq!(config.plugins["test"].command, vec!["echo"]);
+        assert_eq!(config.plugins["test"].timeout_secs, 30);
+        let pdf = &config.plugins["pdf"];
+        assert_eq!(pdf.timeout_secs, 5);
+        assert!(pdf.clear_env);
+        assert!(pdf.env_allowlist.contains(&"PATH".to_string()));
+        let docx = &config.plugins["docx"];
+        assert_eq!(docx.command, vec!["pandoc", "-t", "markdown"]);
+        assert_eq!(docx.pipe_to, vec![vec!["./cleanup.sh".to_string()]]);
+        assert_eq!(docx.output_format("docx"), "md");
+        assert_eq!(pdf.output_format("pdf"), "pdf");
+        assert_eq!(config.plugins["odt"].output_format("odt"), "md");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_overrides() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        writeln!(file, "[server]\nhost = \"127.0.0.1\"\nport = 8080")?;
+        let env = [
+            ("CONTEXTD_SERVER__PORT", "9000"),
+            ("CONTEXTD_SERVER__API_KEY", "s3cret"),
+            ("CONTEXTD_WATCH__PATHS", r#"["/workspace"]"#),
+            ("CONTEXTD_LIMITS__MAX_DB_MB", "512"),
+            ("CONTEXTD_DB_PATH", "index/contextd.db"),
+            ("CONTEXTD_DATA_DIR", "/data"),
+            ("CONTEXTD_PLUGIN_SECRET", "ignored"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+
+        let config = Config::load_with_env(file.path(), env)?;
+        assert_eq!(config.server.host, "127.0.0.1");
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.server.api_key.as_deref(), Some("s3cret"));
+        assert_eq!(config.watch.paths, vec![PathBuf::from("/workspace")]);
+        assert_eq!(config.limits.max_db_mb, Some(512));
+        assert_eq!(
+            config.storage.db_path,
+            PathBuf::from("/data/index/contextd.db")
+        );
+        assert_eq!(config.storage.model_path, PathBuf::from("/data/models"));
 
+        // Without a file, env vars alone make the config
+        let missing = Path::new("/nonexistent/contextd.toml");
+        let config = Config::load_with_env(missing, Vec::new())?;
+        assert_eq!(config.server.port, 3030);
         Ok(())
     }
+
+    #[test]
+    fn test_check_exposure() {
+        let mut server = ServerConfig::default();
+        assert!(server.check_exposure().is_ok());
+        server.host = "0.0.0.0".to_string();
+        assert!(server.check_exposure().is_err());
+        server.api_key = Some("s3cret".to_string());
+        assert!(server.check_exposure().is_ok());
+        server.host = "[::1]".to_string();
+        server.api_key = None;
+        assert!(server.check_exposure().is_ok());
+
+        let users: ServerConfig = toml::from_str(
+            r#"
+            host = "0.0.0.0"
+            [users.ana]
+            api_key = "k1"
+            collections = ["ana"]
+            queries_per_day = 100
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            users.users["ana"].scope.collections,
+            Some(vec!["ana".to_string()])
+        );
+        assert!(users.check_exposure().is_ok());
+        let mut shared = users.clone();
+        shared.api_key = Some("k1".to_string());
+        assert!(shared.check_exposure().is_err());
+    }
+
+    #[test]
+    fn test_extension_allowlist() {
+        let mut watch = Config::default().watch;
+        assert!(watch.allows(Path::new("anything.bin")));
+
+        watch.extensions = Some(vec!["rs".to_string(), ".MD".to_string()]);
+        assert!(watch.allows(Path::new("src/main.rs")));
+        assert!(watch.allows(Path::new("README.md")));
+        assert!(!watch.allows(Path::new("Cargo.lock")));
+        assert!(!watch.allows(Path::new("Makefile")));
+    }
 }
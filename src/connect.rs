@@ -1,9 +1,12 @@
 use anyhow::{Context, Result};
 use serde_json::{json, Value};
 use std::collections::BTreeMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{env, fs};
 
+use crate::config::Config;
+use crate::paths::canonicalize;
+
 pub struct Tool {
     pub id: &'static str,
     pub name: &'static str,
@@ -194,6 +197,18 @@ const TOOLS: &[Tool] = &[
         config_paths: || vec![PathBuf::from(&home_dir()).join(".antigravity/plugins/mcp.json")],
         generate_config: |binary| json!({ "mcpServers": { "contextd": { "command": binary, "args": ["mcp"] } } }),
     },
+    Tool {
+        id: "windsurf",
+        name: "Windsurf",
+        detect: || {
+            binary_in_path("windsurf")
+                || PathBuf::from(&home_dir())
+                    .join(".codeium/windsurf")
+                    .exists()
+        },
+        config_paths: || vec![PathBuf::from(&home_dir()).join(".codeium/windsurf/mcp_config.json")],
+        generate_config: |binary| json!({ "mcpServers": { "contextd": { "command": binary, "args": ["mcp"] } } }),
+    },
     Tool {
         id: "codex",
         name: "Codex",
@@ -213,6 +228,11 @@ const TOOLS: &[Tool] = &[
     },
 ];
 
+/// Whether the client with this id seems to be installed.
+pub fn is_detected(id: &str) -> bool {
+    TOOLS.iter().any(|t| t.id == id && (t.detect)())
+}
+
 fn home_dir() -> String {
     env::var("HOME")
         .or_else(|_| env::var("USERPROFILE"))
@@ -274,19 +294,7 @@ pub async fn handle_connect(all: bool) -> Result<()> {
             }
 
             let is_toml = cfg_path.extension().and_then(|e| e.to_str()) == Some("toml");
-
-            let existing: Value = match fs::read_to_string(cfg_path) {
-                Ok(content) => {
-                    if is_toml {
-                        toml::from_str(&content)
-                            .unwrap_or_else(|_| Value::Object(Default::default()))
-                    } else {
-                        serde_json::from_str(&content)
-                            .unwrap_or_else(|_| Value::Object(Default::default()))
-                    }
-                }
-                Err(_) => Value::Object(Default::default()),
-            };
+            let existing = read_existing(cfg_path, is_toml)?;
 
             let merged = tool.merge(&existing, &binary);
 
@@ -313,6 +321,180 @@ pub async fn handle_connect(all: bool) -> Result<()> {
     Ok(())
 }
 
+/// Parse an existing client config, treating a missing or blank file as
+/// empty. A file that can't be read or parsed is an error rather than being
+/// overwritten.
+fn read_existing(cfg_path: &Path, is_toml: bool) -> Result<Value> {
+    let content = match fs::read_to_string(cfg_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {:?}", cfg_path));
+        }
+    };
+    if content.trim().is_empty() {
+        return Ok(Value::Object(Default::default()));
+    }
+    let parsed = if is_toml {
+        toml::from_str(&content).map_err(anyhow::Error::from)
+    } else {
+        serde_json::from_str(&content).map_err(anyhow::Error::from)
+    };
+    parsed.with_context(|| {
+        format!(
+            "Failed to parse {:?}; fix or move it, then try again",
+            cfg_path
+        )
+    })
+}
+
+/// Desktop clients `mcp install` knows how to configure. They launch servers
+/// from an unspecified working directory, so entries use absolute paths.
+const INSTALL_TARGETS: &[&str] = &["claude-desktop", "cursor", "windsurf"];
+
+/// Write the contextd entry into the global MCP config of each detected (or
+/// requested) desktop client, after checking the binary and models resolve.
+pub fn handle_mcp_install(
+    config: &Config,
+    config_path: &Path,
+    tools: &[String],
+    dry_run: bool,
+) -> Result<()> {
+    if let Some(unknown) = tools
+        .iter()
+        .find(|id| !INSTALL_TARGETS.contains(&id.as_str()))
+    {
+        anyhow::bail!(
+            "Unknown --tool {:?}; expected one of: {}",
+            unknown,
+            INSTALL_TARGETS.join(", ")
+        );
+    }
+
+    let binary = env::current_exe()
+        .and_then(|p| canonicalize(&p))
+        .context("Could not resolve the contextd binary path")?;
+    println!("  ✓ binary: {}", binary.display());
+
+    let config_file = canonicalize(config_path).ok();
+    match &config_file {
+        Some(path) => println!("  ✓ config: {}", path.display()),
+        None => println!(
+            "  - config: {:?} not found, the server will use defaults",
+            config_path
+        ),
+    }
+
+    let model_dir = absolute(&config.storage.model_path);
+    if config.storage.model_type == crate::indexer::embeddings::MOCK_MODEL {
+        println!("  ✓ models: mock embedder, no files needed");
+    } else if crate::download::model_files_exist(&model_dir) {
+        println!("  ✓ models: {}", model_dir.display());
+    } else {
+        println!(
+            "  ✗ models: not found in {} (run `contextd setup` first)",
+            model_dir.display()
+        );
+    }
+    for (name, path) in [
+        ("model_path", &config.storage.model_path),
+        ("db_path", &config.storage.db_path),
+    ] {
+        if path.is_relative() {
+            println!(
+                "  ! storage.{} {:?} is relative; MCP clients may start contextd elsewhere, \
+                 so set it to {:?}",
+                name,
+                path,
+                absolute(path)
+            );
+        }
+    }
+
+    let targets: Vec<&Tool> = TOOLS
+        .iter()
+        .filter(|t| INSTALL_TARGETS.contains(&t.id))
+        .filter(|t| {
+            if tools.is_empty() {
+                (t.detect)()
+            } else {
+                tools.iter().any(|id| id == t.id)
+            }
+        })
+        .collect();
+    if targets.is_empty() {
+        println!(
+            "\nNo supported MCP clients detected (looked for {}). Use --tool to pick one.",
+            INSTALL_TARGETS.join(", ")
+        );
+        return Ok(());
+    }
+
+    let binary = binary.to_string_lossy().to_string();
+    let args = server_args(config_file.as_deref());
+    println!();
+    for tool in targets {
+        // The last path is the client's global config
+        let Some(cfg_path) = (tool.config_paths)().pop() else {
+            continue;
+        };
+        let merged = install_entry(tool, &read_existing(&cfg_path, false)?, &binary, &args);
+        let serialized = serde_json::to_string_pretty(&merged)
+            .with_context(|| format!("Failed to serialize JSON for {:?}", cfg_path))?;
+
+        if dry_run {
+            println!("{} ({:?}):\n{}\n", tool.name, cfg_path, serialized);
+            continue;
+        }
+        if let Some(parent) = cfg_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+        fs::write(&cfg_path, &serialized)
+            .with_context(|| format!("Failed to write config to {:?}", cfg_path))?;
+        println!("  ✓ {} configured at {:?}", tool.name, cfg_path);
+    }
+
+    if !dry_run {
+        println!("\nRestart the client to pick up the new server.");
+    }
+    Ok(())
+}
+
+/// Arguments for the server entry, pinning the config file when there is one.
+fn server_args(config_file: Option<&Path>) -> Vec<String> {
+    match config_file {
+        Some(path) => vec![
+            "--config".to_string(),
+            path.to_string_lossy().to_string(),
+            "mcp".to_string(),
+        ],
+        None => vec!["mcp".to_string()],
+    }
+}
+
+/// Merge the contextd entry into `existing`, replacing its arguments.
+fn install_entry(tool: &Tool, existing: &Value, binary: &str, args: &[String]) -> Value {
+    let mut merged = tool.merge(existing, binary);
+    if let Some(entry) = merged
+        .get_mut("mcpServers")
+        .and_then(|s| s.get_mut("contextd"))
+    {
+        entry["args"] = json!(args);
+    }
+    merged
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir()
+            .map(|cwd| cwd.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -320,7 +502,7 @@ mod tests {
     #[test]
     fn test_tool_trait_exists() {
         assert!(!TOOLS.is_empty());
-        assert_eq!(TOOLS.len(), 9);
+        assert_eq!(TOOLS.len(), 10);
     }
 
     #[test]
@@ -437,4 +619,68 @@ mod tests {
     fn test_binary_in_path_not_found() {
         assert!(!binary_in_path("this_binary_does_not_exist_xyz123"));
     }
+
+    #[test]
+    fn test_install_entry_pins_config() {
+        let tool = TOOLS.iter().find(|t| t.id == "windsurf").unwrap();
+        let existing =
+            json!({ "mcpServers": { "contextd": { "command": "/old", "args": ["mcp"] } } });
+        let args = server_args(Some(Path::new("/home/me/contextd.toml")));
+        let merged = install_entry(tool, &existing, "/usr/local/bin/contextd", &args);
+
+        let entry = &merged["mcpServers"]["contextd"];
+        assert_eq!(entry["command"], "/usr/local/bin/contextd");
+        assert_eq!(
+            entry["args"],
+            json!(["--config", "/home/me/contextd.toml", "mcp"])
+        );
+        assert_eq!(server_args(None), vec!["mcp"]);
+    }
+
+    #[test]
+    fn test_read_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mcp.json");
+        assert_eq!(read_existing(&path, false).unwrap(), json!({}));
+        fs::write(&path, "\n").unwrap();
+        assert_eq!(read_existing(&path, false).unwrap(), json!({}));
+        fs::write(&path, r#"{"mcpServers": {}}"#).unwrap();
+        assert_eq!(
+            read_existing(&path, false).unwrap(),
+            json!({ "mcpServers": {} })
+        );
+
+        // A broken config is reported, not replaced
+        fs::write(&path, r#"{"mcpServers": {"#).unwrap();
+        let err = read_existing(&path, false).unwrap_err();
+        assert!(format!("{:#}", err).contains("mcp.json"), "{:#}", err);
+        let toml = dir.path().join("config.toml");
+        fs::write(&toml, "[mcp").unwrap();
+        assert!(read_existing(&toml, true).is_err());
+    }
+
+    #[test]
+    fn test_install_unknown_tool() {
+        let err = handle_mcp_install(
+            &Config::default(),
+            Path::new("contextd.toml"),
+            &["vscode".to_string()],
+            true,
+        )
+        .unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("\"vscode\""), "{}", message);
+        assert!(
+            message.contains("claude-desktop, cursor, windsurf"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_install_targets_exist() {
+        for id in INSTALL_TARGETS {
+            assert!(TOOLS.iter().any(|t| t.id == *id), "{} missing", id);
+        }
+    }
 }
@@ -1,124 +1,305 @@
 use crate::api;
-use crate::indexer::{chunker, embeddings::Embedder, plugins, watcher};
-use crate::storage::db::Database;
+use crate::archive;
+use crate::audit::{self, AuditLog};
+use crate::collections::{self, Models};
+use crate::indexer::ignore::global_ignore_path;
+use crate::indexer::workers::{Priority, WorkerPool};
+use crate::indexer::{
+    chunker, keywords::KeywordExtractor, moves, plugins, sniff, watcher, IndexError,
+};
+use crate::storage::db::{ChunkSpan, Database, StageTimings};
 use anyhow::Result;
 use ignore::WalkBuilder;
-use std::sync::{mpsc, Arc};
-
-use crate::config::Config;
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::changelog;
+use crate::config::{Config, WatchConfig};
+use crate::digest;
+use crate::faults;
+use crate::freshness::WatchTracker;
+use crate::gc;
+use crate::history;
+use crate::limits::{DiskGuard, MemoryGuard};
+use crate::migration;
+use crate::power;
+use crate::reindex;
+use crate::report;
+use crate::retention;
+use crate::scan::{Resume, ScanTracker};
+use crate::workspace;
 
 use indicatif::{ProgressBar, ProgressStyle};
-use tokio::sync::Semaphore;
+use sha2::{Digest, Sha256};
 
-pub async fn run(config: Config) -> Result<()> {
-    // 0. Monitor stdin for EOF to handle graceful exit if parent dies (e.g., VS Code extension)
-    tokio::spawn(async {
-        let mut buf = [0; 1];
-        use tokio::io::AsyncReadExt;
-        if let Ok(0) = tokio::io::stdin().read(&mut buf).await {
-            eprintln!("Stdin closed (parent died). Shutting down daemon.");
-            std::process::exit(0);
-        }
-    });
+/// How often deferred changes are retried while no events arrive.
+const DEFERRED_POLL: Duration = Duration::from_secs(1);
 
-    // 1. Initialize Storage
-    let db = Database::new(&config.storage.db_path)?;
-    println!("Database initialized at {:?}", config.storage.db_path);
+/// How often newly registered workspaces are looked for, with `[watch]
+/// register_workspaces`.
+const WORKSPACE_POLL: Duration = Duration::from_secs(10);
 
-    // 2. Ensure model files exist (auto-download if missing)
-    if crate::download::ensure_model_files(&config.storage.model_path, &config.storage.model_type)
-        .await?
-    {
-        println!("Model files were downloaded.");
-    }
+/// Time in-flight indexing gets to finish after SIGTERM or Ctrl-C.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
 
-    // 3. Initialize Embedder
-    let embedder = Arc::new(Embedder::new(&config.storage)?);
-    println!("Embedder initialized from {:?}", config.storage.model_path);
+/// Times a file is stored before a transient failure, like another process
+/// holding the database lock, is recorded as its index error.
+const STORE_ATTEMPTS: u32 = 3;
 
-    let config = Arc::new(config);
-    let semaphore = Arc::new(Semaphore::new(4)); // Limit concurrency
+/// Wait before storing a file again, multiplied by the attempts so far.
+const STORE_RETRY_DELAY: Duration = Duration::from_secs(1);
 
-    // 4. Initial Scan
-    println!("Performing initial scan of {:?}", config.watch.paths);
-    let pb = ProgressBar::new_spinner();
-    pb.set_style(ProgressStyle::default_spinner().template("{spinner:.green} {msg}")?);
-    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+/// Run the daemon. With `watch_stdin`, exit when stdin closes, as it does
+/// when a parent process such as an editor extension dies.
+pub async fn run(config: Config, watch_stdin: bool) -> Result<()> {
+    // 0. Monitor stdin for EOF to handle graceful exit if parent dies (e.g., VS Code extension)
+    if watch_stdin {
+        tokio::spawn(async {
+            let mut buf = [0; 1];
+            use tokio::io::AsyncReadExt;
+            if let Ok(0) = tokio::io::stdin().read(&mut buf).await {
+                log::info!("Stdin closed (parent died). Shutting down daemon.");
+                std::process::exit(0);
+            }
+        });
+    }
 
-    for path in &config.watch.paths {
-        let walker = WalkBuilder::new(path)
-            .standard_filters(true)
-            .add_custom_ignore_filename(".contextignore")
-            .build();
+    run_until(config, shutdown_signal()).await?;
+    log::logger().flush();
+    std::process::exit(0);
+}
 
-        for result in walker {
-            match result {
-                Ok(entry) => {
-                    let path = entry.path();
-                    if path.is_file() {
-                        let config = config.clone();
-                        let db = db.clone();
-                        let embedder = embedder.clone();
-                        let path = path.to_path_buf();
-                        let semaphore = semaphore.clone();
-                        let pb = pb.clone();
+/// Load the models and [`serve`] on the configured address until `stop`
+/// completes.
+pub async fn run_until(
+    config: Config,
+    stop: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    config.server.check_exposure()?;
+
+    // Ensure model files exist (auto-download if missing)
+    if collections::ensure_models(&config).await? {
+        log::info!("Model files were downloaded.");
+    }
+    let models = Arc::new(Models::load(&config)?);
+    log::info!("Embedder initialized from {:?}", config.storage.model_path);
 
-                        // Acquire permit before spawning to limit active tasks
-                        // For initial scan, we want backpressure
-                        let permit = semaphore.acquire_owned().await.unwrap();
+    let listener = api::bind(&config.server).await?;
+    serve(config, models, listener, stop).await
+}
 
-                        tokio::spawn(async move {
-                            pb.set_message(format!(
-                                "Indexing {:?}",
-                                path.file_name().unwrap_or_default()
-                            ));
-                            index_file(path, config, db, embedder).await;
-                            drop(permit);
-                            pb.inc(1);
-                        });
-                    }
-                }
-                Err(err) => eprintln!("Error during scan: {}", err),
-            }
+/// Index and watch the configured paths with `models`, answering API
+/// requests on `listener`, until `stop` completes. In-flight indexing then
+/// gets [`SHUTDOWN_GRACE`] to finish before this returns.
+pub async fn serve(
+    config: Config,
+    models: Arc<Models>,
+    listener: TcpListener,
+    stop: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    // 1. Initialize Storage
+    let db = Database::new(&config.storage.db_path)?;
+    crate::maintenance::check_on_start(&db, &config.storage.db_path)?;
+    log::info!("Database initialized at {:?}", config.storage.db_path);
+    history::init(&db, &config.history)?;
+    history::spawn_pruning(db.clone(), config.history.clone());
+    changelog::spawn_pruning(db.clone(), config.changelog.clone());
+    gc::spawn_collection(
+        db.clone(),
+        config.gc.clone(),
+        moves::index_roots(&db, &config)?,
+    );
+    digest::spawn_digests(db.clone(), config.clone());
+
+    // 2. Prepare Embedders
+    let migrations = migration::prepare(&db, &config, &models)?;
+    let audit = Arc::new(AuditLog::open(&config.audit)?);
+
+    // Pay tokenizer/ORT warm-up cost now rather than on the first query
+    match models.default_model().warm_up() {
+        Ok(elapsed) => log::info!("Embedder warmed up in {:?}", elapsed),
+        Err(e) => log::warn!("Embedder warm-up failed: {}", e),
+    }
+    if config.storage.preload_vectors {
+        let start = std::time::Instant::now();
+        match db.call(|db| db.preload_vectors()).await {
+            Ok(bytes) => log::info!(
+                "Preloaded {:.2} MB of vectors in {:?}",
+                bytes as f64 / 1024.0 / 1024.0,
+                start.elapsed()
+            ),
+            Err(e) => log::warn!("Vector preload failed: {}", e),
         }
     }
-    pb.finish_with_message("Initial scan complete.");
 
-    // 5. Start Watcher
-    let (tx, rx) = mpsc::channel();
-    let _watcher = watcher::watch(&config.watch.paths, tx)?;
-    println!("Watching {:?}", config.watch.paths);
-
-    // 6. Start API Server in background
+    // Watch registered workspaces alongside the configured roots, each as a
+    // collection of its own
+    let mut config = config;
+    let mut workspaces = HashSet::new();
+    for ws in new_workspaces(&db, &config, &workspaces).await {
+        add_workspace(&mut config, &ws);
+        workspaces.insert(ws);
+    }
+    let mut last_workspace_poll = Instant::now();
+
+    let mut config = Arc::new(config);
+    let workers = WorkerPool::new(&config.indexing);
+    workers.spawn_autoscaler();
+    power::spawn_monitor(workers.clone(), config.indexing.on_battery);
+    let mut stopped = spawn_shutdown_handler(db.clone(), workers.clone(), stop);
+    let watch = WatchTracker::spawn(db.clone(), workers.clone());
+    let memory = MemoryGuard::new(&config.limits);
+    memory.spawn(db.clone(), workers.clone());
+    let disk = DiskGuard::new(&config.storage.db_path, &config.limits);
+    disk.spawn();
+    // Over the memory budget or short of disk space: leave indexing for later
+    let paused = || memory.over_budget() || disk.problem().is_some();
+    // Files left unindexed while paused, indexed once the pause lifts
+    let mut deferred = HashSet::new();
+
+    // 3. Start API Server in background, so queries during the initial scan
+    // can report its progress, re-embedding collections whose model changed
+    // alongside it
+    let migrations = migration::spawn(migrations, db.clone(), &config, models.clone());
+    archive::spawn_archiving(db.clone(), config.archive.clone(), migrations.clone());
+    report::spawn_snapshots(db.clone());
     let db_clone = db.clone();
-    let embedder_clone = embedder.clone();
-    let host = config.server.host.clone();
-    let port = config.server.port;
-    tokio::spawn(async move {
-        api::run_server(db_clone, embedder_clone, &host, port).await;
+    let models_clone = models.clone();
+    let config_clone = config.clone();
+    let workers_clone = workers.clone();
+    let server = tokio::spawn(async move {
+        api::run_server(
+            listener,
+            db_clone,
+            models_clone,
+            config_clone,
+            migrations,
+            audit,
+            workers_clone,
+        )
+        .await;
     });
 
+    // 4. Initial Scan, in the background so changes to files being worked
+    // on are indexed ahead of it
+    let mut scanning = Some(tokio::spawn(initial_scan(
+        config.clone(),
+        db.clone(),
+        models.clone(),
+        workers.clone(),
+        disk.clone(),
+    )));
+
+    // 5. Start Watcher
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = watcher::watch(&config.watch, move |result| {
+        let _ = tx.send(result);
+    })?;
+    log::info!("Watching {:?}", config.watch.paths);
+
     // Initialize Ignore Checkers for Watcher
-    let ignore_checkers: Vec<crate::indexer::ignore::IgnoreChecker> = config
+    let mut ignore_checkers: Vec<crate::indexer::ignore::IgnoreChecker> = config
         .watch
         .paths
         .iter()
-        .map(|p| crate::indexer::ignore::IgnoreChecker::new(p))
+        .map(|p| {
+            crate::indexer::ignore::IgnoreChecker::new(p)
+                .with_system_exclusions(&config.watch)
+                .with_default_ignores(&config.watch)
+        })
         .collect();
 
-    // 7. Main Loop: Process File Events
-    println!("Daemon main loop starting...");
-    for result in rx {
+    // 6. Main Loop: Process File Events
+    log::info!("Daemon main loop starting...");
+    loop {
+        let received = tokio::select! {
+            _ = &mut stopped => break,
+            scanned = async { scanning.as_mut().unwrap().await }, if scanning.is_some() => {
+                scanning = None;
+                match scanned {
+                    Ok(Ok(left)) => {
+                        deferred.extend(left);
+                        watch.set_deferred(deferred.len());
+                    }
+                    Ok(Err(e)) => log::error!("Initial scan failed: {:#}", e),
+                    Err(e) => log::error!("Initial scan failed: {}", e),
+                }
+                continue;
+            }
+            received = tokio::time::timeout(DEFERRED_POLL, rx.recv()) => received,
+        };
+        let result = match received {
+            Ok(Some(result)) => result,
+            Ok(None) => break,
+            Err(_) => {
+                if config.watch.register_workspaces
+                    && last_workspace_poll.elapsed() >= WORKSPACE_POLL
+                {
+                    last_workspace_poll = Instant::now();
+                    let added = new_workspaces(&db, &config, &workspaces).await;
+                    if !added.is_empty() {
+                        let mut updated = (*config).clone();
+                        for ws in &added {
+                            if add_workspace(&mut updated, ws) {
+                                if let Err(e) = watcher
+                                    .watcher()
+                                    .watch(ws, notify::RecursiveMode::Recursive)
+                                {
+                                    log::error!("Failed to watch {:?}: {}", ws, e);
+                                }
+                                ignore_checkers.push(
+                                    crate::indexer::ignore::IgnoreChecker::new(ws)
+                                        .with_system_exclusions(&updated.watch)
+                                        .with_default_ignores(&updated.watch),
+                                );
+                            }
+                        }
+                        config = Arc::new(updated);
+                        for ws in added {
+                            let files = walk(&ws, &config.watch)
+                                .filter_map(|entry| entry.ok())
+                                .map(|entry| entry.into_path())
+                                .filter(|p| p.is_file() && config.watch.allows(p));
+                            for path in files {
+                                spawn_index(path, &config, &db, &models, &workers, Some(&watch));
+                            }
+                            workspaces.insert(ws);
+                        }
+                    }
+                }
+                if !paused() && !deferred.is_empty() {
+                    log::info!("Indexing {} deferred files", deferred.len());
+                    for path in deferred.drain().filter(|p: &PathBuf| p.exists()) {
+                        spawn_index(path, &config, &db, &models, &workers, Some(&watch));
+                    }
+                    watch.set_deferred(0);
+                }
+                continue;
+            }
+        };
         match result {
             Ok(events) => {
-                let mut unique_paths = std::collections::HashSet::new();
-                for event in events {
-                    unique_paths.insert(event.path);
-                }
+                watcher::delivered(events.len());
+                let unique_paths = watcher::coalesce(events.into_iter().map(|e| e.path));
 
                 let db_path_str = config.storage.db_path.to_string_lossy().to_string();
+                let audit_log = config.storage.plugin_audit_log.file_name();
 
+                // Deleted or moved away, and what exists now: a path among
+                // these may be where one that's gone was moved to
+                let mut gone = Vec::new();
+                let mut appeared = Vec::new();
                 for path in unique_paths {
+                    if faults::drops_event() {
+                        log::debug!("Dropping the event for {:?} (fault injection)", path);
+                        watcher::dropped();
+                        continue;
+                    }
                     let path_str = path.to_string_lossy().to_string();
 
                     // Explicitly ignore database files to prevent infinite watcher loops
@@ -128,6 +309,13 @@ pub async fn run(config: Config) -> Result<()> {
                     {
                         continue;
                     }
+                    // Nor index our own audit logs and digests as they grow
+                    if (audit_log.is_some() && path.file_name() == audit_log)
+                        || audit::is_audit_file(&config.audit, &path)
+                        || digest::is_digest_file(&config.digest, &path)
+                    {
+                        continue;
+                    }
 
                     // Enforce hard skips for heavy system directories
                     if path_str.contains("/.git/")
@@ -138,48 +326,351 @@ pub async fn run(config: Config) -> Result<()> {
                         continue;
                     }
 
+                    if !path.exists() {
+                        gone.push(path);
+                        continue;
+                    }
                     let is_dir = path.is_dir();
                     let is_ignored = ignore_checkers.iter().any(|c| c.is_ignored(&path, is_dir));
+                    if !is_ignored {
+                        appeared.push(path);
+                    }
+                }
 
-                    if !is_ignored && path.exists() {
-                        if path.is_dir() {
-                            continue;
+                if !gone.is_empty() {
+                    let config = config.clone();
+                    let appeared = appeared.clone();
+                    match db
+                        .call(move |db| moves::apply(db, &config, &gone, &appeared))
+                        .await
+                    {
+                        Ok(moves) => {
+                            for path in moves.removed {
+                                log::info!("Removed {} from the index", path);
+                            }
+                            for (from, to) in moves.renamed {
+                                log::info!("Moved {} to {} in the index", from, to);
+                            }
                         }
+                        Err(e) => log::error!("Failed to remove deleted files: {}", e),
+                    }
+                }
+
+                for path in appeared {
+                    // Files moved in with a directory raise no events of
+                    // their own; moved files are skipped as unchanged
+                    let files: Vec<PathBuf> = if path.is_dir() {
+                        walk(&path, &config.watch)
+                            .filter_map(|entry| entry.ok())
+                            .map(|entry| entry.into_path())
+                            .filter(|p| p.is_file() && config.watch.allows(p))
+                            .collect()
+                    } else {
+                        vec![path]
+                    };
+                    for path in files {
                         // Temporary fix for infinite loop on .gitignore
                         if path.file_name().and_then(|s| s.to_str()) == Some(".gitignore") {
                             continue;
                         }
 
+                        if paused() {
+                            deferred.insert(path);
+                            watch.set_deferred(deferred.len());
+                            continue;
+                        }
+                        spawn_index(path, &config, &db, &models, &workers, Some(&watch));
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("Watch error: {:?}", e);
+                watcher::dropped();
+            }
+        }
+    }
+
+    server.abort();
+    Ok(())
+}
+
+/// Registered workspaces not in `known` that exist, with `[watch]
+/// register_workspaces`.
+async fn new_workspaces(db: &Database, config: &Config, known: &HashSet<PathBuf>) -> Vec<PathBuf> {
+    if !config.watch.register_workspaces {
+        return Vec::new();
+    }
+    match db.call(|db| db.list_workspaces()).await {
+        Ok(workspaces) => workspaces
+            .into_iter()
+            .filter(|ws| !known.contains(ws) && ws.is_dir())
+            .collect(),
+        Err(e) => {
+            log::warn!("Failed to load workspaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Add the registered workspace `ws` to `config`. Returns whether it is a
+/// new root to watch rather than inside one already watched.
+fn add_workspace(config: &mut Config, ws: &Path) -> bool {
+    let watched = config.watch.paths.len();
+    let collection = workspace::add_to_config(config, ws);
+    log::info!(
+        "Watching registered workspace {:?} as collection {}",
+        ws,
+        collection
+            .as_deref()
+            .unwrap_or(crate::staleness::DEFAULT_COLLECTION)
+    );
+    config.watch.paths.len() > watched
+}
+
+/// Index what changed in the watched directories since the daemon last ran,
+/// at low priority so files changed meanwhile go first. Returns the files
+/// left for later while the disk is short of space.
+async fn initial_scan(
+    config: Arc<Config>,
+    db: Database,
+    models: Arc<Models>,
+    workers: Arc<WorkerPool>,
+    disk: Arc<DiskGuard>,
+) -> Result<HashSet<PathBuf>> {
+    log::info!("Performing initial scan of {:?}", config.watch.paths);
+    let discovered = config
+        .watch
+        .paths
+        .iter()
+        .flat_map(|path| walk(path, &config.watch))
+        .filter_map(|result| result.ok())
+        .filter(|entry| entry.path().is_file() && config.watch.allows(entry.path()))
+        .count() as u64;
+    // A scan cut short skips what it had indexed by its checkpoint
+    let roots = config.watch.paths.clone();
+    let resume = match db.call(move |db| Resume::load(db, &roots)).await {
+        Ok(resume) => resume,
+        Err(e) => {
+            log::warn!("Failed to load the last scan's checkpoint: {}", e);
+            None
+        }
+    };
+    if let Some(resume) = &resume {
+        log::info!(
+            "Resuming the interrupted initial scan after {:?}",
+            resume.checkpoint
+        );
+    }
+    let checkpoint = resume.as_ref().map(|resume| resume.checkpoint.clone());
+    let scan = ScanTracker::start(db.clone(), discovered, checkpoint).await?;
+    // Left unindexed while the disk is short of space
+    let mut deferred = HashSet::new();
+    let pb = ProgressBar::new(discovered);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")?,
+    );
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+
+    for path in &config.watch.paths {
+        for result in walk(path, &config.watch) {
+            match result {
+                Ok(entry) => {
+                    let path = entry.path();
+                    let is_file = path.is_file();
+                    if resume.as_ref().is_some_and(|resume| resume.skips(path)) {
+                        if is_file && config.watch.allows(path) {
+                            scan.skipped();
+                            pb.inc(1);
+                        }
+                        continue;
+                    }
+                    scan.walking(path, path.is_dir());
+                    if is_file && config.watch.allows(path) {
+                        if disk.problem().is_some() {
+                            deferred.insert(path.to_path_buf());
+                            scan.left();
+                            continue;
+                        }
                         let config = config.clone();
                         let db = db.clone();
-                        let embedder = embedder.clone();
+                        let models = models.clone();
                         let path = path.to_path_buf();
-                        let semaphore = semaphore.clone();
+                        let pb = pb.clone();
+                        let scan = scan.clone();
+                        let workers = workers.clone();
+
+                        // Acquire permit before spawning to limit active tasks
+                        // For initial scan, we want backpressure
+                        let Some(permit) = workers.acquire(Priority::Low).await else {
+                            scan.left();
+                            break;
+                        };
+                        let file = scan.queued();
 
                         tokio::spawn(async move {
-                            // Acquire permit inside spawn for watcher events to avoid blocking the loop
-                            // (Though blocking loop is also fine for backpressure, but let's be non-blocking for events)
-                            let _permit = semaphore.acquire_owned().await.unwrap();
-                            index_file(path, config, db, embedder).await;
+                            pb.set_message(format!(
+                                "Indexing {:?}",
+                                path.file_name().unwrap_or_default()
+                            ));
+                            // Unless a change to it is being indexed already
+                            if workers.claim(&path) {
+                                loop {
+                                    index_file(
+                                        path.clone(),
+                                        config.clone(),
+                                        db.clone(),
+                                        models.clone(),
+                                    )
+                                    .await;
+                                    if !workers.release(&path) {
+                                        break;
+                                    }
+                                }
+                            }
+                            drop(permit);
+                            pb.inc(1);
+                            scan.indexed(file);
                         });
                     }
                 }
+                Err(err) => log::warn!("Error during scan: {}", err),
             }
-            Err(e) => println!("Watch error: {:?}", e),
         }
     }
+    scan.walked();
+    pb.finish_with_message("Initial scan complete.");
+    moves::spawn_sweeps(
+        db.clone(),
+        config.watch.paths.clone(),
+        Duration::from_secs(config.watch.sweep_interval_secs),
+    );
+    retention::spawn_enforcement(db.clone(), (*config).clone());
+    Ok(deferred)
+}
 
-    Ok(())
+/// Once `stop` completes, stop taking new files, let in-flight ones finish
+/// and fold the write-ahead log into the database. The returned receiver
+/// completes when that's done.
+fn spawn_shutdown_handler(
+    db: Database,
+    workers: Arc<WorkerPool>,
+    stop: impl Future<Output = ()> + Send + 'static,
+) -> oneshot::Receiver<()> {
+    let (done, stopped) = oneshot::channel();
+    tokio::spawn(async move {
+        stop.await;
+        log::info!("Shutting down; waiting for in-flight indexing");
+        if !workers.shutdown(SHUTDOWN_GRACE).await {
+            log::warn!(
+                "Indexing still running after {:?}; exiting anyway",
+                SHUTDOWN_GRACE
+            );
+        }
+        if let Err(e) = db.call(|db| db.checkpoint()).await {
+            log::warn!("Failed to checkpoint database: {}", e);
+        }
+        let _ = done.send(());
+    });
+    stopped
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = term.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
 
-async fn index_file(
+/// Index `path` in the background once a worker is free, ahead of the
+/// initial scan. `watch` tracks the changes the watcher reported.
+pub fn spawn_index(
+    path: PathBuf,
+    config: &Arc<Config>,
+    db: &Database,
+    models: &Arc<Models>,
+    workers: &Arc<WorkerPool>,
+    watch: Option<&WatchTracker>,
+) {
+    let config = config.clone();
+    let db = db.clone();
+    let models = models.clone();
+    let workers = workers.clone();
+    let watch = watch.cloned();
+    // Already being indexed: it's indexed again once that's done
+    if !workers.claim(&path) {
+        return;
+    }
+    tokio::spawn(async move {
+        // Acquire permit inside spawn for watcher events to avoid blocking the loop
+        let Some(_permit) = workers.acquire(Priority::High).await else {
+            workers.release(&path);
+            return;
+        };
+        loop {
+            index_file(path.clone(), config.clone(), db.clone(), models.clone()).await;
+            if !workers.release(&path) {
+                break;
+            }
+        }
+        if let Some(watch) = watch {
+            watch.event_processed();
+        }
+    });
+}
+
+/// Build the filtered walker used to scan a watch root (respects `.gitignore`,
+/// `.contextignore`, the global ignore file and `watch`'s system exclusions
+/// and default ignores).
+pub fn walk(root: &Path, watch: &WatchConfig) -> ignore::Walk {
+    let mut builder = WalkBuilder::new(root);
+    let watch = watch.clone();
+    let walk_root = root.to_path_buf();
+    // In a stable order, which the startup scan's checkpoints rely on
+    builder
+        .standard_filters(true)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .add_custom_ignore_filename(".contextignore")
+        .filter_entry(move |entry| {
+            let skipped = entry.depth() > 0
+                && entry.file_type().is_some_and(|t| t.is_dir())
+                && (workspace::is_default_ignored(&watch, &entry.file_name().to_string_lossy())
+                    || workspace::system_exclusion(&watch, &walk_root, entry.path()).is_some());
+            !skipped
+        });
+    if let Some(global) = global_ignore_path().filter(|p| p.is_file()) {
+        if let Some(err) = builder.add_ignore(&global) {
+            log::warn!("Error loading {:?}: {}", global, err);
+        }
+    }
+    builder.build()
+}
+
+/// Chunk, embed and store a single file, skipping it if it is unchanged.
+pub async fn index_file(
     path: std::path::PathBuf,
     config: Arc<Config>,
     db: Database,
-    embedder: Arc<Embedder>,
+    models: Arc<Models>,
 ) {
-    // Check extension
-    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+    if !config.watch.allows(&path) {
+        return;
+    }
 
     // Check if needs reindexing
     let metadata = std::fs::metadata(&path).ok();
@@ -191,84 +682,236 @@ async fn index_file(
         .unwrap_or(0);
 
     let path_str = path.to_string_lossy().to_string();
-    if let Ok(false) = db.needs_reindexing(&path_str, modified) {
-        // println!("Skipping {:?} (unchanged)", path);
+    if let Ok(false) = db
+        .call(move |db| db.needs_reindexing(&path_str, modified))
+        .await
+    {
+        log::debug!("Skipping {:?} (unchanged)", path);
         return;
     }
 
-    let chunks_result = if let Some(cmd) = config.plugins.get(ext) {
-        println!("Using plugin {:?} for {:?}", cmd, path);
-        match plugins::run_parser(cmd, &path).await {
-            Ok(content) => chunker::chunk_by_type(&content, ext),
+    // A changed mtime with the same content (e.g. after a git checkout)
+    // only needs the new mtime recorded
+    let hash = std::fs::read(&path).ok().map(|bytes| content_hash(&bytes));
+    if let Some(hash) = hash.clone() {
+        let path_str = path.to_string_lossy().to_string();
+        if let Ok(true) = db
+            .call(move |db| db.touch_if_unchanged(&path_str, modified, &hash))
+            .await
+        {
+            log::debug!("Skipping {:?} (content unchanged)", path);
+            return;
+        }
+    }
+
+    // A file moved while the daemon wasn't running, or whose old path went
+    // in an earlier batch of events, keeps its chunks and embeddings
+    if let Some(hash) = hash.clone() {
+        let (moved, adopt_config) = (path.clone(), config.clone());
+        if let Ok(Some(from)) = db
+            .call(move |db| moves::adopt(db, &adopt_config, &moved, modified, &hash))
+            .await
+        {
+            log::info!("Moved {} to {:?} in the index", from, path);
+            return;
+        }
+    }
+
+    // Files with unknown extensions go to the handler of what they look like
+    let ext = sniff::detect_extension(&config, &path).await;
+    let ext = ext.as_str();
+
+    let chunking_started = Instant::now();
+    let chunks_result = if let Some(plugin) = config.plugins.get(ext) {
+        log::info!("Using plugin {:?} for {:?}", plugin.command, path);
+        match plugins::run_audited(plugin, &path, &config.storage.plugin_audit_log).await {
+            Ok(content) => chunker::chunk_by_type(&content, plugin.output_format(ext)),
             Err(e) => Err(e),
         }
     } else if ext == "pdf" {
         chunker::chunk_pdf(&path)
+    } else if ext == "docx" {
+        chunker::chunk_docx(&path)
     } else {
         let content = std::fs::read_to_string(&path).unwrap_or_default();
-        chunker::chunk_by_type(&content, ext)
+        chunker::chunk_source(&content, ext)
     };
+    let collection = collections::collection_for(&config, &path);
+    let chunks_result = chunks_result.map(|chunks| {
+        let chunks = match collections::max_chunk_chars(&config, collection) {
+            Some(max_chars) => chunker::split_long(chunks, max_chars),
+            None => chunks,
+        };
+        let embedder = models.for_collection(collection);
+        chunker::split_tokens(chunks, &config.chunking, |text| embedder.tokens(text))
+    });
+    let chunking = chunking_started.elapsed();
 
-    if let Ok(chunks) = chunks_result {
-        // Store
+    // Embedding and storing block, so they run off the async runtime
+    let ext = ext.to_string();
+    let task = tokio::task::spawn_blocking(move || {
         let path_str = path.to_string_lossy().to_string();
-        let metadata = std::fs::metadata(&path).ok();
-        let modified = metadata
-            .and_then(|m| m.modified().ok())
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs())
-            .unwrap_or(0);
-
-        // Collect metadata
-        let file_meta = std::fs::metadata(&path).ok();
-        let size = file_meta.as_ref().map(|m| m.len()).unwrap_or(0);
-        let created = file_meta
-            .as_ref()
-            .and_then(|m| m.created().ok())
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs())
-            .unwrap_or(0);
-
-        let file_metadata = serde_json::json!({
-            "size": size,
-            "created": created,
-            "modified": modified,
-            "extension": ext
-        });
+        let chunks = match chunks_result {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                log::error!("Error chunking file {:?}: {:?}", path, e);
+                let _ = db.record_index_error(&path_str, &format!("{:#}", IndexError::Chunk(e)));
+                return;
+            }
+        };
+        let mut attempt = 1;
+        loop {
+            match store_file(&path, &ext, &config, &db, &models, &chunks, chunking) {
+                Ok(count) => {
+                    if let Some(hash) = &hash {
+                        let _ = db.record_content_hash(&path_str, hash);
+                    }
+                    log::info!("Indexed {} chunks for {:?}", count, path);
+                    return;
+                }
+                Err(e) if e.is_transient() && attempt < STORE_ATTEMPTS => {
+                    log::warn!("Storing {:?} failed, retrying: {}", path, e);
+                    std::thread::sleep(STORE_RETRY_DELAY * attempt);
+                    attempt += 1;
+                }
+                Err(e) => {
+                    log::error!("Error storing file {:?}: {}", path, e);
+                    let _ = db.record_index_error(&path_str, &format!("{:#}", e));
+                    return;
+                }
+            }
+        }
+    });
+    if let Err(e) = task.await {
+        log::error!("Indexing task failed: {}", e);
+    }
+}
 
-        if let Ok(file_id) = db.add_or_update_file(&path_str, modified) {
-            let count = chunks.len();
-            let _ = db.clear_chunks(file_id);
-            for chunk in chunks {
-                // Merge chunk metadata if present
-                let mut final_metadata = file_metadata.clone();
-                if let Some(cm) = &chunk.metadata {
-                    if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(cm) {
-                        if let Some(obj) = final_metadata.as_object_mut() {
-                            if let Some(parsed_obj) = parsed.as_object() {
-                                for (k, v) in parsed_obj {
-                                    obj.insert(k.clone(), v.clone());
-                                }
-                            }
+/// Hex SHA-256 of a file's content, recorded to tell whether it changed.
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Embed and store the chunks of a file, returning how many there were.
+fn store_file(
+    path: &Path,
+    ext: &str,
+    config: &Config,
+    db: &Database,
+    models: &Models,
+    chunks: &[chunker::Chunk],
+    chunking: Duration,
+) -> Result<usize, IndexError> {
+    let collection = collections::collection_for(config, path);
+    // A collection switching models waits until this file is stored
+    let _pinned = models.pin();
+    let embedder = models.for_collection(collection);
+
+    let path_str = path.to_string_lossy().to_string();
+    let metadata = std::fs::metadata(path).ok();
+    let modified = metadata
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    // Collect metadata
+    let file_meta = std::fs::metadata(path).ok();
+    let size = file_meta.as_ref().map(|m| m.len()).unwrap_or(0);
+    let created = file_meta
+        .as_ref()
+        .and_then(|m| m.created().ok())
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut file_metadata = serde_json::json!({
+        "size": size,
+        "created": created,
+        "modified": modified,
+        "extension": ext
+    });
+    if let Some(collection) = collection {
+        file_metadata["collection"] = collection.into();
+    }
+    let language = reindex::language_of(config, ext);
+    if let Some(version) = chunker::chunker_version(language) {
+        file_metadata["chunker_version"] = version.into();
+    }
+
+    let storage_started = Instant::now();
+    let file_id = db.add_or_update_file(&path_str, modified)?;
+    // Unchanged chunks keep their embeddings unless the model changed
+    let previous = if reindex::model_unchanged(db, config, path) {
+        db.chunk_embeddings(file_id).unwrap_or_default()
+    } else {
+        std::collections::HashMap::new()
+    };
+    db.clear_chunks(file_id)?;
+    let extractor =
+        KeywordExtractor::new(chunks.iter().map(|c| c.content.as_str())).with_manifest(path);
+
+    // Embed the chunks whose text changed together, in as few requests as
+    // the backend allows
+    let embedding_started = Instant::now();
+    let changed: Vec<&str> = chunks
+        .iter()
+        .map(|c| c.content.as_str())
+        .filter(|content| !previous.contains_key(*content))
+        .collect();
+    let mut embedded = embedder.embed_batch(&changed).into_iter();
+    let embedding = embedding_started.elapsed();
+    for chunk in chunks {
+        // Merge chunk metadata if present
+        let mut final_metadata = file_metadata.clone();
+        if let Some(cm) = &chunk.metadata {
+            if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(cm) {
+                if let Some(obj) = final_metadata.as_object_mut() {
+                    if let Some(parsed_obj) = parsed.as_object() {
+                        for (k, v) in parsed_obj {
+                            obj.insert(k.clone(), v.clone());
                         }
                     }
                 }
-
-                // Embed chunk
-                let embedding = embedder.embed(&chunk.content).ok();
-                let _ = db.add_chunk(
-                    file_id,
-                    chunk.start,
-                    chunk.end,
-                    &chunk.content,
-                    embedding.as_deref(),
-                    Some(&final_metadata.to_string()),
-                );
             }
-            let _ = db.mark_indexed(file_id);
-            println!("Indexed {} chunks for {:?}", count, path);
         }
-    } else if let Err(e) = chunks_result {
-        eprintln!("Error chunking file {:?}: {:?}", path, e);
+        if let Some(obj) = final_metadata.as_object_mut() {
+            extractor.tags(&chunk.content).merge_into(obj);
+        }
+
+        // A chunk that failed to embed is still found by keyword
+        let vector = match previous.get(&chunk.content) {
+            Some(vector) => Some(vector.clone()),
+            None => embedded.next().and_then(|result| {
+                result
+                    .map_err(|e| log::warn!("Failed to embed a chunk of {:?}: {}", path, e))
+                    .ok()
+            }),
+        };
+        let span = ChunkSpan {
+            start: chunk.start,
+            end: chunk.end,
+            lines: chunk.lines,
+        };
+        db.add_chunk_spanning(
+            file_id,
+            span,
+            &chunk.content,
+            vector.as_deref(),
+            Some(&final_metadata.to_string()),
+        )?;
     }
+    db.mark_indexed(file_id)?;
+    let _ = db.record_index_settings(&path_str, &reindex::fingerprint(config, path));
+    let _ = db.clear_index_error(&path_str);
+    let timings = StageTimings {
+        chunking_us: chunking.as_micros() as u64,
+        embedding_us: embedding.as_micros() as u64,
+        storage_us: storage_started
+            .elapsed()
+            .saturating_sub(embedding)
+            .as_micros() as u64,
+    };
+    let _ = db.record_index_timing(&path_str, &timings);
+    Ok(chunks.len())
 }
@@ -0,0 +1,346 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::report;
+use crate::storage::db::Database;
+
+/// Entries listed per section of a digest.
+const TOP: usize = 10;
+
+/// `[digest]` config: a summary of what changed in the index, sent at the
+/// end of every period to a file, a webhook or both, for teams sharing a
+/// daemon. Off unless `file` or `webhook` is set.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DigestConfig {
+    /// Hours each digest covers, counted from the Unix epoch, so the default
+    /// of 24 sends one at midnight UTC
+    pub interval_hours: u64,
+    /// Append each digest to this Markdown file; relative paths follow
+    /// `CONTEXTD_DATA_DIR`
+    pub file: Option<PathBuf>,
+    /// POST each digest here as JSON, with the Markdown in a `text` field for
+    /// chat webhooks
+    pub webhook: Option<String>,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        Self {
+            interval_hours: 24,
+            file: None,
+            webhook: None,
+        }
+    }
+}
+
+impl DigestConfig {
+    pub fn enabled(&self) -> bool {
+        self.file.is_some() || self.webhook.is_some()
+    }
+}
+
+/// Whether `path` is the digest file, which isn't indexed.
+pub fn is_digest_file(config: &DigestConfig, path: &Path) -> bool {
+    config
+        .file
+        .as_deref()
+        .is_some_and(|file| path.ends_with(file))
+}
+
+/// What changed in the index over one period.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Digest {
+    /// Start of the period, seconds since the epoch
+    pub from: u64,
+    /// End of the period, exclusive
+    pub to: u64,
+    pub files_added: usize,
+    pub files_changed: usize,
+    pub files_removed: usize,
+    /// Chunks added, changed or removed
+    pub chunk_changes: u64,
+    /// Some of the added files, relative to their watch root
+    pub new_files: Vec<String>,
+    /// Directories with the most chunk changes, relative to their watch
+    /// root
+    pub edited_areas: Vec<(String, u64)>,
+    /// Tags first seen since the previous digest
+    pub new_tags: Vec<NewTag>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct NewTag {
+    pub tag: String,
+    /// `user` for tags added with `contextd tag`, else the metadata key it
+    /// was extracted into (`frameworks`, `markers`)
+    pub source: String,
+}
+
+/// Summarize the changelog between `from` and `to`. New tags are those not
+/// seen by the previous digest, and are recorded as seen.
+pub fn build(db: &Database, config: &Config, from: u64, to: u64) -> Result<Digest> {
+    let roots = report::roots(db, config)?;
+    let mut added = BTreeSet::new();
+    let mut changed = BTreeSet::new();
+    let mut removed = BTreeSet::new();
+    let mut chunk_changes = 0;
+    let mut areas: HashMap<String, u64> = HashMap::new();
+    for change in db.changes_between(from, to)? {
+        match (change.kind.as_str(), change.op.as_str()) {
+            ("file", "add") => {
+                added.insert(change.path);
+            }
+            ("file", "update") => {
+                changed.insert(change.path);
+            }
+            ("file", _) => {
+                removed.insert(change.path);
+            }
+            _ => {
+                chunk_changes += 1;
+                *areas.entry(report::area(&change.path)).or_default() += 1;
+            }
+        }
+    }
+
+    // Count each file by where it ended up
+    let indexed = |path: &String| db.get_file(path).map(|f| f.is_some());
+    let mut new_files = Vec::new();
+    for path in &added {
+        if indexed(path)? {
+            new_files.push(path.clone());
+        }
+    }
+    let mut files_changed = 0;
+    for path in changed.difference(&added) {
+        if indexed(path)? {
+            files_changed += 1;
+        }
+    }
+    let mut files_removed = 0;
+    for path in removed.difference(&added) {
+        if !indexed(path)? {
+            files_removed += 1;
+        }
+    }
+
+    let mut edited_areas: Vec<(String, u64)> = areas.into_iter().collect();
+    edited_areas.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    edited_areas.truncate(TOP);
+
+    Ok(Digest {
+        from,
+        to,
+        files_added: new_files.len(),
+        files_changed,
+        files_removed,
+        chunk_changes,
+        new_files: new_files
+            .iter()
+            .take(TOP)
+            .map(|path| report::relative_area(path, &roots))
+            .collect(),
+        edited_areas: edited_areas
+            .into_iter()
+            .map(|(area, changes)| (report::relative_area(&area, &roots), changes))
+            .collect(),
+        new_tags: db
+            .take_new_tags()?
+            .into_iter()
+            .map(|(source, tag)| NewTag { tag, source })
+            .collect(),
+    })
+}
+
+impl Digest {
+    /// Whether nothing changed, in which case no digest is sent.
+    pub fn is_empty(&self) -> bool {
+        self.files_added == 0
+            && self.files_changed == 0
+            && self.files_removed == 0
+            && self.chunk_changes == 0
+            && self.new_tags.is_empty()
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let time = |secs: u64| {
+            chrono::DateTime::from_timestamp(secs as i64, 0)
+                .map(|dt| dt.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or_default()
+        };
+        let mut md = format!(
+            "# contextd digest, {} to {}\n\n",
+            time(self.from),
+            time(self.to)
+        );
+        md.push_str(&format!(
+            "{} files added, {} changed and {} removed; {} chunk changes.\n\n",
+            self.files_added, self.files_changed, self.files_removed, self.chunk_changes
+        ));
+
+        if !self.new_files.is_empty() {
+            md.push_str("## New Files\n\n");
+            for path in &self.new_files {
+                md.push_str(&format!("- `{}`\n", path));
+            }
+            if self.files_added > self.new_files.len() {
+                md.push_str(&format!(
+                    "- and {} more\n",
+                    self.files_added - self.new_files.len()
+                ));
+            }
+            md.push('\n');
+        }
+
+        if !self.edited_areas.is_empty() {
+            md.push_str("## Most-Edited Areas\n\n");
+            for (area, changes) in &self.edited_areas {
+                md.push_str(&format!("- `{}`: {} chunk changes\n", area, changes));
+            }
+            md.push('\n');
+        }
+
+        if !self.new_tags.is_empty() {
+            md.push_str("## New Tags\n\n");
+            for tag in self.new_tags.iter().take(TOP) {
+                md.push_str(&format!("- `{}` ({})\n", tag.tag, tag.source));
+            }
+            if self.new_tags.len() > TOP {
+                md.push_str(&format!("- and {} more\n", self.new_tags.len() - TOP));
+            }
+            md.push('\n');
+        }
+        md
+    }
+}
+
+/// Send a digest at the end of every period while the daemon runs, when
+/// `[digest]` has somewhere to send it.
+pub fn spawn_digests(db: Database, config: Config) {
+    if !config.digest.enabled() {
+        return;
+    }
+    let period = config.digest.interval_hours.max(1) * 3600;
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        loop {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let to = (now / period + 1) * period;
+            tokio::time::sleep(Duration::from_secs(to - now)).await;
+
+            let build_config = config.clone();
+            let digest = match db
+                .call(move |db| build(db, &build_config, to - period, to))
+                .await
+            {
+                Ok(digest) if digest.is_empty() => continue,
+                Ok(digest) => digest,
+                Err(e) => {
+                    log::warn!("Failed to build the index digest: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = send(&client, &config.digest, &digest).await {
+                log::warn!("Failed to send the index digest: {}", e);
+            }
+        }
+    });
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    digest: &'a Digest,
+    text: String,
+}
+
+async fn send(client: &reqwest::Client, config: &DigestConfig, digest: &Digest) -> Result<()> {
+    let text = digest.to_markdown();
+    if let Some(path) = &config.file {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {:?}", path))?;
+        writeln!(file, "{}", text)?;
+    }
+    if let Some(url) = &config.webhook {
+        client
+            .post(url)
+            .json(&Payload { digest, text })
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let db = Database::new(":memory:").unwrap();
+        let mut config = Config::default();
+        config.watch.paths = vec![PathBuf::from("/home/me/src/app")];
+        let metadata = r#"{"frameworks": ["tokio"], "markers": ["TODO"]}"#;
+        let old = db
+            .add_or_update_file("/home/me/src/app/old.rs", 100)
+            .unwrap();
+        db.add_chunk(old, 0, 1, "old", None, Some(metadata))
+            .unwrap();
+        // Seeds the tags seen so far
+        assert!(db.take_new_tags().unwrap().is_empty());
+        // and the period starts after these changes
+        db.prune_changes(u32::MAX as u64).unwrap();
+        let period = (0, u32::MAX as u64);
+
+        db.add_or_update_file("/home/me/src/app/old.rs", 200)
+            .unwrap();
+        let new = db
+            .add_or_update_file("/home/me/src/app/api/new.rs", 100)
+            .unwrap();
+        let metadata = r#"{"frameworks": ["tokio", "axum"]}"#;
+        db.add_chunk(new, 0, 1, "a", None, Some(metadata)).unwrap();
+        db.add_chunk(new, 1, 2, "b", None, None).unwrap();
+        let gone = db
+            .add_or_update_file("/home/me/src/app/gone.rs", 100)
+            .unwrap();
+        db.delete_file(gone).unwrap();
+        db.add_path_tag("/home/me/src/app/api", "billing").unwrap();
+
+        let digest = build(&db, &config, period.0, period.1).unwrap();
+        assert_eq!(digest.files_added, 1);
+        assert_eq!(digest.files_changed, 1);
+        // Added and removed within the period
+        assert_eq!(digest.files_removed, 0);
+        assert_eq!(digest.chunk_changes, 2);
+        assert_eq!(digest.new_files, vec!["app/api/new.rs"]);
+        assert_eq!(digest.edited_areas, vec![("app/api".to_string(), 2)]);
+        let tags: Vec<(&str, &str)> = digest
+            .new_tags
+            .iter()
+            .map(|t| (t.source.as_str(), t.tag.as_str()))
+            .collect();
+        assert_eq!(tags, vec![("frameworks", "axum"), ("user", "billing")]);
+
+        let md = digest.to_markdown();
+        assert!(md.contains("1 files added, 1 changed and 0 removed; 2 chunk changes."));
+        assert!(md.contains("- `app/api`: 2 chunk changes"));
+        assert!(md.contains("- `axum` (frameworks)"));
+
+        // Tags are new only once
+        let digest = build(&db, &config, period.0, period.1).unwrap();
+        assert!(digest.new_tags.is_empty());
+    }
+}
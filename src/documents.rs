@@ -0,0 +1,193 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::collections::{self, Models};
+use crate::config::Config;
+use crate::indexer::chunker;
+use crate::indexer::keywords::KeywordExtractor;
+use crate::indexer::sniff;
+use crate::storage::db::{ChunkSpan, Database};
+
+/// Path prefix of documents pushed through the API; they have no file on
+/// disk.
+pub const PATH_PREFIX: &str = "document://";
+
+/// Content to index as if it were a watched file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Document {
+    /// Id to store the document under, optionally with the
+    /// `document://` prefix; ingesting again replaces it
+    pub path_or_id: String,
+    pub content: String,
+    /// MIME type or file extension picking the chunker, e.g.
+    /// `text/markdown` or `rs`; else the id's extension, else plain text
+    pub content_type: Option<String>,
+    /// Merged into the metadata of every chunk; a `collection` key picks
+    /// that collection's model and chunk size
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct IngestedDocument {
+    pub path: String,
+    pub chunks: usize,
+}
+
+/// Where the document with `path_or_id` is stored.
+pub fn path(path_or_id: &str) -> Result<String> {
+    let id = path_or_id.strip_prefix(PATH_PREFIX).unwrap_or(path_or_id);
+    if id.trim().is_empty() {
+        bail!("Document id is empty");
+    }
+    Ok(format!("{}{}", PATH_PREFIX, id))
+}
+
+/// Chunk, embed and store `document`, replacing any earlier version.
+pub fn ingest(
+    db: &Database,
+    models: &Models,
+    config: &Config,
+    document: &Document,
+) -> Result<IngestedDocument> {
+    let path = path(&document.path_or_id)?;
+    if document.content.trim().is_empty() {
+        bail!("Document has no content");
+    }
+    let ext = extension(document.content_type.as_deref(), &path);
+    let collection = document.metadata.get("collection").and_then(Value::as_str);
+
+    let mut chunks = chunker::chunk_source(&document.content, &ext)?;
+    if let Some(max_chars) = collections::max_chunk_chars(config, collection) {
+        chunks = chunker::split_long(chunks, max_chars);
+    }
+
+    // A collection switching models waits until this document is stored
+    let _pinned = models.pin();
+    let embedder = models.for_collection(collection);
+    let chunks = chunker::split_tokens(chunks, &config.chunking, |text| embedder.tokens(text));
+    let now = chrono::Utc::now().timestamp() as u64;
+    let mut file_metadata = serde_json::json!({
+        "size": document.content.len(),
+        "created": now,
+        "modified": now,
+        "extension": ext,
+    });
+    if let Some(obj) = file_metadata.as_object_mut() {
+        obj.extend(document.metadata.clone());
+    }
+
+    let file_id = db.add_or_update_file(&path, now)?;
+    db.clear_chunks(file_id)?;
+    let extractor = KeywordExtractor::new(chunks.iter().map(|c| c.content.as_str()));
+    let contents: Vec<&str> = chunks.iter().map(|c| c.content.as_str()).collect();
+    let embeddings = embedder.embed_batch(&contents);
+    for (chunk, embedding) in chunks.iter().zip(embeddings) {
+        let mut metadata = file_metadata.clone();
+        if let Some(obj) = metadata.as_object_mut() {
+            let parsed = chunk
+                .metadata
+                .as_deref()
+                .and_then(|m| serde_json::from_str::<Map<String, Value>>(m).ok());
+            if let Some(parsed) = parsed {
+                obj.extend(parsed);
+            }
+            extractor.tags(&chunk.content).merge_into(obj);
+        }
+        // One that fails to embed is still found by keyword
+        let embedding = embedding
+            .map_err(|e| log::warn!("Failed to embed a chunk of {}: {}", path, e))
+            .ok();
+        let span = ChunkSpan {
+            start: chunk.start,
+            end: chunk.end,
+            lines: chunk.lines,
+        };
+        db.add_chunk_spanning(
+            file_id,
+            span,
+            &chunk.content,
+            embedding.as_deref(),
+            Some(&metadata.to_string()),
+        )?;
+    }
+    db.mark_indexed(file_id)?;
+
+    Ok(IngestedDocument {
+        path,
+        chunks: chunks.len(),
+    })
+}
+
+/// Remove the document with `path_or_id`, returning whether there was one.
+pub fn delete(db: &Database, path_or_id: &str) -> Result<bool> {
+    match db.get_file(&path(path_or_id)?)? {
+        Some(file) => {
+            db.delete_file(file.id)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// The extension whose chunker handles a document of `content_type` stored
+/// at `path`.
+fn extension(content_type: Option<&str>, path: &str) -> String {
+    let ext = match content_type.map(|t| t.split(';').next().unwrap_or(t).trim()) {
+        Some(t) => sniff::mime_extension(t).unwrap_or(t.trim_start_matches('.')),
+        None => path
+            .rsplit('/')
+            .next()
+            .and_then(|name| name.rsplit_once('.'))
+            .map_or("txt", |(_, ext)| ext),
+    };
+    ext.to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::embeddings::Embedder;
+    use crate::storage::db::EMBEDDING_DIMENSIONS;
+
+    #[test]
+    fn test_ingest_and_delete() {
+        assert_eq!(path("tickets/42.md").unwrap(), "document://tickets/42.md");
+        assert_eq!(path("document://42").unwrap(), "document://42");
+        assert!(path(" ").is_err());
+        assert_eq!(extension(Some("text/markdown; charset=utf-8"), "x"), "md");
+        assert_eq!(extension(Some(".RS"), "x"), "rs");
+        assert_eq!(extension(None, "document://tickets/42.py"), "py");
+        assert_eq!(extension(None, "document://v1.2/notes"), "txt");
+
+        let db = Database::new(":memory:").unwrap();
+        let models = Models::single(Embedder::mock(EMBEDDING_DIMENSIONS));
+        let config = Config::default();
+        let mut document = Document {
+            path_or_id: "tickets/42".to_string(),
+            content: "# Login fails\n\nUsers see a 500.\n\n# Fix\n\nRotate the key.\n".to_string(),
+            content_type: Some("text/markdown".to_string()),
+            metadata: serde_json::from_str(r#"{"source": "jira"}"#).unwrap(),
+        };
+        let ingested = ingest(&db, &models, &config, &document).unwrap();
+        assert_eq!(ingested.path, "document://tickets/42");
+        assert!(ingested.chunks >= 2, "{}", ingested.chunks);
+
+        let file = db.get_file(&ingested.path).unwrap().unwrap();
+        let chunks = db.get_chunks(file.id).unwrap();
+        let metadata: Value = serde_json::from_str(chunks[0].metadata.as_ref().unwrap()).unwrap();
+        assert_eq!(metadata["source"], "jira");
+        assert_eq!(metadata["extension"], "md");
+
+        // Ingesting again replaces the chunks
+        document.content = "Resolved.".to_string();
+        document.content_type = None;
+        assert_eq!(ingest(&db, &models, &config, &document).unwrap().chunks, 1);
+        assert_eq!(db.get_chunks(file.id).unwrap().len(), 1);
+
+        assert!(delete(&db, "document://tickets/42").unwrap());
+        assert!(db.get_file(&ingested.path).unwrap().is_none());
+        assert!(!delete(&db, "tickets/42").unwrap());
+    }
+}
@@ -5,6 +5,8 @@ use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
+use crate::indexer::embeddings::MOCK_MODEL;
+
 pub async fn download_file(url: &str, path: &PathBuf) -> Result<()> {
     if path.exists() {
         println!("File {:?} already exists, skipping.", path);
@@ -45,6 +47,10 @@ pub fn model_files_exist(model_dir: &Path) -> bool {
 }
 
 pub async fn ensure_model_files(model_dir: &Path, model_type: &str) -> Result<bool> {
+    // The mock embedder has no files
+    if model_type == MOCK_MODEL {
+        return Ok(false);
+    }
     if !model_dir.exists() {
         fs::create_dir_all(model_dir)?;
     }
@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+
+use crate::citation::{column_at, line_at};
+use crate::paths::canonicalize;
+
+/// Where a chunk starts, for "open in editor" links.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EditorPosition {
+    pub file: String,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    /// `file:line:column`, as most editors accept on their command line
+    pub location: String,
+}
+
+impl EditorPosition {
+    /// Byte `offset` of `file_path`, reading the file to turn it into a line
+    /// and column. Files that can't be read point at their first line.
+    pub fn at(file_path: &str, offset: u64) -> Self {
+        let path = Path::new(file_path);
+        let file = canonicalize(path)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| file_path.to_string());
+        let (line, column) = std::fs::read_to_string(path)
+            .map(|content| {
+                let offset = offset as usize;
+                (line_at(&content, offset), column_at(&content, offset))
+            })
+            .unwrap_or((1, 1));
+        Self {
+            location: format!("{}:{}:{}", file, line, column),
+            file,
+            line,
+            column,
+        }
+    }
+}
+
+/// Arguments of the configured editor command with `{file}`, `{line}`,
+/// `{column}` and `{location}` filled in. The template is split on
+/// whitespace before substituting, so paths with spaces stay one argument
+/// and are never seen by a shell.
+pub fn editor_args(template: &str, position: &EditorPosition) -> Vec<String> {
+    template
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{file}", &position.file)
+                .replace("{line}", &position.line.to_string())
+                .replace("{column}", &position.column.to_string())
+                .replace("{location}", &position.location)
+        })
+        .collect()
+}
+
+/// Start the editor command without waiting for it to exit.
+pub fn launch(template: &str, position: &EditorPosition) -> Result<()> {
+    let args = editor_args(template, position);
+    let (program, args) = args
+        .split_first()
+        .context("[server] editor_command is empty")?;
+    tokio::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run editor command {:?}", program))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_and_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("my lib.rs");
+        std::fs::write(&file, "fn a() {}\n\n    fn b() {\n}\n").unwrap();
+        let position = EditorPosition::at(&file.to_string_lossy(), 15);
+        assert_eq!((position.line, position.column), (3, 5));
+        assert!(position.location.ends_with("my lib.rs:3:5"));
+
+        let args = editor_args("code --goto {location}", &position);
+        assert_eq!(args.len(), 3);
+        assert_eq!(args[2], position.location);
+        let args = editor_args("vim +{line} {file}", &position);
+        assert_eq!(args[1], "+3");
+        assert_eq!(args[2], position.file);
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::storage::db::{QueryStage, SearchOptions, SearchResult};
+
+/// Reciprocal Rank Fusion constant, as in hybrid search.
+const RRF_K: f32 = 60.0;
+
+/// Matches fetched for each variant before fusing.
+const CANDIDATES_PER_VARIANT: usize = 50;
+
+/// Words that make a query read as a question without saying what it is
+/// about.
+const FILLER_WORDS: &[&str] = &[
+    "a", "an", "the", "how", "why", "what", "where", "which", "who", "when", "do", "does", "did",
+    "is", "are", "was", "can", "could", "should", "would", "i", "we", "my", "our", "you", "it",
+    "to", "of", "in", "on", "for", "with", "and", "or", "there", "this", "that", "get", "find",
+];
+
+/// The texts embedded for an ensemble query: the query itself, its keywords
+/// without question filler, and a hypothetical answer when the caller wrote
+/// one. Duplicates are dropped.
+pub fn variants(query: &str, hypothetical: Option<&str>) -> Vec<String> {
+    let mut variants = vec![query.trim().to_string()];
+    let keywords: Vec<&str> = query
+        .split(|c: char| c.is_whitespace() || matches!(c, '?' | '!' | ',' | ';' | '"' | '\''))
+        .filter(|w| !w.is_empty() && !FILLER_WORDS.contains(&w.to_lowercase().as_str()))
+        .collect();
+    variants.push(keywords.join(" "));
+    if let Some(hypothetical) = hypothetical {
+        variants.push(hypothetical.trim().to_string());
+    }
+
+    let mut distinct: Vec<String> = Vec::new();
+    for variant in variants {
+        if !variant.is_empty() && !distinct.iter().any(|d| d.eq_ignore_ascii_case(&variant)) {
+            distinct.push(variant);
+        }
+    }
+    distinct
+}
+
+/// Run `search` with every variant of `query` and fuse the rankings.
+pub fn search(
+    query: &str,
+    hypothetical: Option<&str>,
+    options: &SearchOptions,
+    search: impl Fn(&str, &SearchOptions) -> Result<Vec<SearchResult>>,
+) -> Result<Vec<SearchResult>> {
+    let limit = options.limit.unwrap_or(10);
+    let candidates = SearchOptions {
+        limit: Some(limit.max(CANDIDATES_PER_VARIANT)),
+        ..options.clone()
+    };
+    let rankings = variants(query, hypothetical)
+        .iter()
+        .map(|variant| search(variant, &candidates))
+        .collect::<Result<Vec<_>>>()?;
+    let started = Instant::now();
+    let fused = fuse(rankings, limit);
+    options.record(QueryStage::Rerank, started);
+    Ok(fused)
+}
+
+/// Order chunks by Reciprocal Rank Fusion over `rankings`. Each keeps the
+/// best score any variant gave it, so scores stay comparable with a plain
+/// search, and its explanation from that variant's ranking.
+fn fuse(rankings: Vec<Vec<SearchResult>>, limit: usize) -> Vec<SearchResult> {
+    // RRF total, best result and its rank in its variant's ranking
+    let mut fused: HashMap<i64, (f32, SearchResult, usize)> = HashMap::new();
+    for ranking in rankings {
+        for (rank, result) in ranking.into_iter().enumerate() {
+            let rrf = 1.0 / (RRF_K + rank as f32 + 1.0);
+            match fused.get_mut(&result.id) {
+                Some((total, best, best_rank)) => {
+                    *total += rrf;
+                    if result.score > best.score {
+                        *best = result;
+                        *best_rank = rank;
+                    }
+                }
+                None => {
+                    fused.insert(result.id, (rrf, result, rank));
+                }
+            }
+        }
+    }
+
+    let mut results: Vec<(f32, SearchResult, usize)> = fused.into_values().collect();
+    results.sort_by(|a, b| {
+        b.0.partial_cmp(&a.0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| {
+                b.1.score
+                    .partial_cmp(&a.1.score)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+    });
+    results
+        .into_iter()
+        .take(limit)
+        .enumerate()
+        .map(|(fused_rank, (_, mut result, rank))| {
+            if let Some(explanation) = &mut result.explanation {
+                explanation.rerank_delta = rank as i64 - fused_rank as i64;
+            }
+            result
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variants() {
+        assert_eq!(
+            variants("How does the watcher debounce events?", None),
+            vec![
+                "How does the watcher debounce events?",
+                "watcher debounce events"
+            ]
+        );
+        // Nothing to strip: the keyword variant is the query again
+        assert_eq!(variants("debounce", Some("fn debounce()")).len(), 2);
+    }
+
+    #[test]
+    fn test_fuse() {
+        let hit = |id: i64, score: f32| SearchResult {
+            id,
+            score,
+            explanation: Some(Default::default()),
+            ..Default::default()
+        };
+        // 2 ranks well in both lists, 1 tops only one
+        let fused = fuse(
+            vec![
+                vec![hit(1, 0.9), hit(2, 0.8), hit(3, 0.7)],
+                vec![hit(2, 0.85), hit(4, 0.6)],
+            ],
+            3,
+        );
+        let ids: Vec<i64> = fused.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![2, 1, 4]);
+        assert_eq!(fused[0].score, 0.85);
+        // Measured from the rank in the ranking the best score came from
+        let deltas: Vec<i64> = fused
+            .iter()
+            .map(|r| r.explanation.as_ref().unwrap().rerank_delta)
+            .collect();
+        assert_eq!(deltas, vec![0, -1, -1]);
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::indexer::embeddings::Embedder;
+use crate::storage::db::{Database, SearchOptions};
+
+/// One labeled query from the evaluation file.
+#[derive(Deserialize, Debug)]
+pub struct EvalCase {
+    pub query: String,
+    pub expected_paths: Vec<String>,
+}
+
+/// Retrieval quality for a single query.
+#[derive(Debug, PartialEq)]
+pub struct EvalScore {
+    pub recall: f64,
+    pub reciprocal_rank: f64,
+}
+
+pub async fn handle_eval(config: &Config, queries_path: &Path, k: usize) -> Result<()> {
+    let content = std::fs::read_to_string(queries_path)
+        .with_context(|| format!("Failed to read {:?}", queries_path))?;
+    let cases = parse_cases(&content)?;
+    if cases.is_empty() {
+        anyhow::bail!("{:?} contains no queries", queries_path);
+    }
+
+    let db = Database::new(&config.storage.db_path)?;
+    let embedder = Embedder::new(&config.storage, &config.embeddings)?;
+
+    // Fetch extra chunks so that k distinct files are usually available
+    let options = SearchOptions {
+        limit: Some(k * 5),
+        ..Default::default()
+    };
+
+    println!(
+        "{:<50} {:>10} {:>8}",
+        "query",
+        format!("recall@{}", k),
+        "RR"
+    );
+    let mut total_recall = 0.0;
+    let mut total_rr = 0.0;
+    for case in &cases {
+        let embedding = embedder.embed(&case.query)?;
+        let results = db.search_chunks_hybrid(&case.query, &embedding, &options)?;
+        let ranked = distinct_paths(results.iter().map(|r| r.file_path.as_str()));
+        let score = score_case(&ranked, &case.expected_paths, k);
+
+        println!(
+            "{:<50} {:>10.3} {:>8.3}",
+            case.query.chars().take(50).collect::<String>(),
+            score.recall,
+            score.reciprocal_rank
+        );
+        total_recall += score.recall;
+        total_rr += score.reciprocal_rank;
+    }
+
+    let n = cases.len() as f64;
+    println!(
+        "\n{} queries: mean recall@{} = {:.3}, MRR = {:.3}",
+        cases.len(),
+        k,
+        total_recall / n,
+        total_rr / n
+    );
+    Ok(())
+}
+
+/// Parse a JSONL file of `{"query": ..., "expected_paths": [...]}` lines.
+pub fn parse_cases(content: &str) -> Result<Vec<EvalCase>> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).with_context(|| format!("Invalid query on line {}", i + 1))
+        })
+        .collect()
+}
+
+/// File paths in rank order with duplicates (multiple chunks per file) removed.
+fn distinct_paths<'a>(paths: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut seen = std::collections::HashSet::new();
+    paths.filter(|p| seen.insert(*p)).collect()
+}
+
+/// An indexed path matches an expected one if it is equal or ends with it,
+/// so labels can be written relative to the project root.
+fn path_matches(indexed: &str, expected: &str) -> bool {
+    let indexed = indexed.trim_start_matches("./");
+    let expected = expected.trim_start_matches("./");
+    indexed == expected || indexed.ends_with(&format!("/{}", expected))
+}
+
+/// Recall@k and reciprocal rank of `ranked` against `expected`.
+pub fn score_case(ranked: &[&str], expected: &[String], k: usize) -> EvalScore {
+    if expected.is_empty() {
+        return EvalScore {
+            recall: 0.0,
+            reciprocal_rank: 0.0,
+        };
+    }
+
+    let top_k = &ranked[..ranked.len().min(k)];
+    let found = expected
+        .iter()
+        .filter(|e| top_k.iter().any(|r| path_matches(r, e)))
+        .count();
+
+    let reciprocal_rank = ranked
+        .iter()
+        .position(|r| expected.iter().any(|e| path_matches(r, e)))
+        .map(|pos| 1.0 / (pos as f64 + 1.0))
+        .unwrap_or(0.0);
+
+    EvalScore {
+        recall: found as f64 / expected.len() as f64,
+        reciprocal_rank,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cases() {
+        let content = r#"{"query": "open db", "expected_paths": ["src/storage/db.rs"]}
+
+{"query": "chunk rust", "expected_paths": ["src/indexer/chunker.rs", "README.md"]}
+"#;
+        let cases = parse_cases(content).unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[1].expected_paths.len(), 2);
+
+        let err = parse_cases("{\"query\": 1}").unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+    }
+
+    #[test]
+    fn test_score_case() {
+        let ranked = vec!["./src/main.rs", "./src/storage/db.rs", "./README.md"];
+        let expected = vec!["src/storage/db.rs".to_string(), "docs/x.md".to_string()];
+
+        let score = score_case(&ranked, &expected, 10);
+        assert_eq!(score.recall, 0.5);
+        assert_eq!(score.reciprocal_rank, 0.5);
+
+        // Relevant file outside the cutoff does not count towards recall
+        let score = score_case(&ranked, &expected, 1);
+        assert_eq!(score.recall, 0.0);
+        assert_eq!(score.reciprocal_rank, 0.5);
+    }
+
+    #[test]
+    fn test_distinct_paths_and_matching() {
+        let ranked = distinct_paths(["a.rs", "b.rs", "a.rs"].into_iter());
+        assert_eq!(ranked, vec!["a.rs", "b.rs"]);
+        assert!(path_matches("/repo/src/db.rs", "src/db.rs"));
+        assert!(!path_matches("/repo/src/mydb.rs", "db.rs"));
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::{Config, WatchConfig};
+use crate::indexer::chunker;
+use crate::indexer::ignore::IgnoreChecker;
+use crate::paths::canonicalize;
+use crate::storage::db::{Database, Eviction};
+
+/// Bytes sniffed when deciding whether a file is text.
+const SNIFF_BYTES: usize = 8192;
+
+/// Why a path is or isn't in the index.
+#[derive(Serialize, Debug)]
+pub struct Explanation {
+    pub path: PathBuf,
+    /// One-line answer
+    pub verdict: String,
+    pub exists: bool,
+    /// Watched root or registered workspace containing the path
+    pub watch_root: Option<PathBuf>,
+    /// Rule that excludes the path from scanning and watching
+    pub ignored_by: Option<String>,
+    pub size: Option<u64>,
+    /// Content is not UTF-8 text, so the text chunkers read it as empty
+    pub binary: bool,
+    /// Plugin or chunker that would process the file
+    pub handler: Option<String>,
+    pub index: Option<IndexStatus>,
+    /// Error from the last failed attempt to index the path
+    pub last_error: Option<String>,
+    /// Why the path's collection last evicted it, if it is not indexed
+    pub evicted: Option<Eviction>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct IndexStatus {
+    /// Path as stored in the index
+    pub indexed_path: String,
+    pub chunks: u64,
+    pub last_modified: u64,
+    pub last_indexed: Option<u64>,
+    /// Whether the file is unchanged since it was indexed
+    pub up_to_date: bool,
+}
+
+/// Explain how the daemon treats `path`.
+pub fn explain(config: &Config, db: &Database, path: &Path) -> Result<Explanation> {
+    let canonical = canonicalize(path).ok();
+    let absolute = canonical.clone().unwrap_or_else(|| path.to_path_buf());
+    let exists = canonical.is_some();
+    let is_dir = absolute.is_dir();
+
+    let mut roots = config.watch.paths.clone();
+    roots.extend(db.list_workspaces()?);
+    let watch_root = roots
+        .into_iter()
+        .find(|root| root_contains(root, path, canonical.as_deref()));
+
+    let ignored_by = watch_root
+        .as_ref()
+        .and_then(|root| ignore_reason(root, &absolute, is_dir, &config.watch))
+        .or_else(|| {
+            (!is_dir && !config.watch.allows(&absolute))
+                .then(|| "extension not in `[watch] extensions`".to_string())
+        });
+
+    let ext = absolute
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_string();
+    let plugin = config.plugins.get(&ext);
+    let (size, binary, handler) = if exists && !is_dir {
+        let handler = match plugin {
+            Some(plugin) => {
+                let steps: Vec<String> = plugin
+                    .steps()
+                    .map(|cmd| format!("`{}`", cmd.join(" ")))
+                    .collect();
+                format!(
+                    "plugin {}, output chunked as {}",
+                    steps.join(" | "),
+                    chunker::chunker_name(plugin.output_format(&ext))
+                )
+            }
+            None if ext == "pdf" || ext == "docx" => format!("{} text extraction", ext),
+            None => format!("{} chunker", chunker::chunker_name(&ext)),
+        };
+        let size = fs::metadata(&absolute).map(|m| m.len()).ok();
+        // Plugins and the PDF and Word extractors read the raw file themselves
+        let binary = plugin.is_none()
+            && !matches!(ext.as_str(), "pdf" | "docx")
+            && !looks_like_text(&absolute);
+        (size, binary, Some(handler))
+    } else {
+        (None, false, None)
+    };
+
+    let candidates = stored_path_candidates(path, canonical.as_deref(), watch_root.as_deref());
+    let mut index = None;
+    let mut last_error = None;
+    let mut evicted = None;
+    for candidate in &candidates {
+        if index.is_none() {
+            if let Some(file) = db.get_file(candidate)? {
+                let on_disk = fs::metadata(&absolute)
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs());
+                index = Some(IndexStatus {
+                    indexed_path: file.path.clone(),
+                    chunks: db.count_chunks(file.id)?,
+                    last_modified: file.last_modified,
+                    last_indexed: file.last_indexed,
+                    up_to_date: file.last_indexed.is_some()
+                        && on_disk.is_none_or(|m| m <= file.last_modified),
+                });
+            }
+        }
+        if last_error.is_none() {
+            last_error = db.get_index_error(candidate)?;
+        }
+        if evicted.is_none() {
+            evicted = db.eviction(candidate)?;
+        }
+    }
+    if index.is_some() {
+        evicted = None;
+    }
+
+    let verdict = if !exists {
+        match &index {
+            Some(_) => "does not exist on disk; its index entry is stale".to_string(),
+            None => "does not exist".to_string(),
+        }
+    } else if is_dir {
+        match (&watch_root, &ignored_by) {
+            (None, _) => "directory outside every watched root".to_string(),
+            (_, Some(rule)) => format!("directory ignored by {}", rule),
+            _ => "watched directory; explain a file inside it for details".to_string(),
+        }
+    } else if watch_root.is_none() {
+        "not under any watched root or registered workspace".to_string()
+    } else if let Some(rule) = &ignored_by {
+        format!("not indexed: ignored by {}", rule)
+    } else if let Some(error) = &last_error {
+        format!("indexing failed: {}", error)
+    } else {
+        match &index {
+            None => match &evicted {
+                Some(e) => format!(
+                    "not indexed: evicted from collection {} ({}); it returns when it changes",
+                    e.collection, e.reason
+                ),
+                None => "not indexed yet; it will be on the next scan or change".to_string(),
+            },
+            Some(status) if !status.up_to_date => {
+                "indexed, but changed since; it will be re-indexed".to_string()
+            }
+            Some(_) if binary => {
+                "indexed, but the content is not UTF-8 text so nothing is searchable".to_string()
+            }
+            Some(status) => format!("indexed ({} chunks)", status.chunks),
+        }
+    };
+
+    Ok(Explanation {
+        path: absolute,
+        verdict,
+        exists,
+        watch_root,
+        ignored_by,
+        size,
+        binary,
+        handler,
+        index,
+        last_error,
+        evicted,
+    })
+}
+
+fn root_contains(root: &Path, path: &Path, canonical: Option<&Path>) -> bool {
+    match (canonicalize(root).ok(), canonical) {
+        (Some(root), Some(path)) => path.starts_with(root),
+        _ => path.starts_with(root),
+    }
+}
+
+/// The rule that keeps `path` out of the scan of `root`, mirroring the
+/// filters of the daemon's walker and watcher.
+fn ignore_reason(root: &Path, path: &Path, is_dir: bool, watch: &WatchConfig) -> Option<String> {
+    let root = canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let relative = path.strip_prefix(&root).ok()?;
+    for component in relative.components() {
+        if let Component::Normal(name) = component {
+            let name = name.to_string_lossy();
+            if name == ".git" {
+                return Some("built-in rule `.git`".to_string());
+            }
+            if name.starts_with('.') {
+                return Some(format!("hidden path `{}` (skipped by the scan)", name));
+            }
+        }
+    }
+    IgnoreChecker::new(&root)
+        .with_system_exclusions(watch)
+        .with_default_ignores(watch)
+        .matched_rule(path, is_dir)
+        .map(|rule| rule.to_string())
+}
+
+/// Spellings under which the daemon may have stored `path`: as given, as
+/// canonical, and relative to its watch root as the walker produces it.
+fn stored_path_candidates(
+    path: &Path,
+    canonical: Option<&Path>,
+    root: Option<&Path>,
+) -> Vec<String> {
+    let mut candidates = vec![path.to_string_lossy().to_string()];
+    if let Some(canonical) = canonical {
+        candidates.push(canonical.to_string_lossy().to_string());
+        if let Some(root) = root {
+            let relative = canonicalize(root)
+                .ok()
+                .and_then(|r| canonical.strip_prefix(r).ok().map(Path::to_path_buf));
+            if let Some(relative) = relative {
+                candidates.push(root.join(relative).to_string_lossy().to_string());
+            }
+        }
+    }
+    candidates.dedup();
+    candidates
+}
+
+/// Whether the start of a file is UTF-8 text without NUL bytes.
+fn looks_like_text(path: &Path) -> bool {
+    let mut buf = Vec::with_capacity(SNIFF_BYTES);
+    let read = fs::File::open(path).and_then(|f| f.take(SNIFF_BYTES as u64).read_to_end(&mut buf));
+    if read.is_err() {
+        return false;
+    }
+    if buf.contains(&0) {
+        return false;
+    }
+    // A multi-byte character may be cut off at the end of the sample
+    match std::str::from_utf8(&buf) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_explain() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::write(root.join(".contextignore"), "*.log\n").unwrap();
+        fs::write(root.join("main.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("debug.log"), "trace").unwrap();
+        fs::write(root.join("blob.bin"), b"\x00\x01\x02").unwrap();
+        let mut config = Config::default();
+        config.watch.paths = vec![root.clone()];
+        let db = Database::new(":memory:").unwrap();
+
+        let ignored = explain(&config, &db, &root.join("debug.log")).unwrap();
+        assert!(ignored.ignored_by.unwrap().contains("`*.log`"));
+        assert!(ignored.verdict.starts_with("not indexed"));
+
+        let main = root.join("main.rs");
+        let fresh = explain(&config, &db, &main).unwrap();
+        assert_eq!(fresh.watch_root.as_deref(), Some(root.as_path()));
+        assert_eq!(fresh.handler.as_deref(), Some("rust (tree-sitter) chunker"));
+        assert!(fresh.verdict.contains("not indexed yet"));
+
+        let path = main.to_string_lossy();
+        db.record_index_error(&path, "parse failed").unwrap();
+        let failed = explain(&config, &db, &main).unwrap();
+        assert_eq!(failed.verdict, "indexing failed: parse failed");
+
+        db.clear_index_error(&path).unwrap();
+        let file_id = db.add_or_update_file(&path, 4_000_000_000).unwrap();
+        db.add_chunk(file_id, 0, 12, "fn main() {}", None, None)
+            .unwrap();
+        db.mark_indexed(file_id).unwrap();
+        let indexed = explain(&config, &db, &main).unwrap();
+        assert_eq!(indexed.verdict, "indexed (1 chunks)");
+
+        assert!(
+            explain(&config, &db, &root.join("blob.bin"))
+                .unwrap()
+                .binary
+        );
+        let outside = explain(&config, &db, Path::new("/")).unwrap();
+        assert!(outside.watch_root.is_none());
+    }
+}
@@ -0,0 +1,131 @@
+// Fault injection for robustness tests, turned on with the hidden
+// `--fault-injection` flag. Nothing here runs unless a spec was installed.
+use anyhow::{bail, Context, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Faults to inject, parsed from a spec like
+/// `embed=0.3,write_delay_ms=200,drop_events=0.5,seed=7`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    /// Share of embeddings that fail, 0 to 1
+    pub embed_failure_rate: f64,
+    /// Added before each batch of database writes
+    pub write_delay: Duration,
+    /// Share of file watcher events dropped, 0 to 1
+    pub drop_event_rate: f64,
+    /// Seeds the random choices, so a failing run can be repeated
+    pub seed: u64,
+}
+
+static FAULTS: OnceLock<Faults> = OnceLock::new();
+static RNG: AtomicU64 = AtomicU64::new(0);
+
+/// Parse a `--fault-injection` spec.
+pub fn parse(spec: &str) -> Result<Faults> {
+    let mut faults = Faults {
+        seed: 0x5eed,
+        ..Default::default()
+    };
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = part
+            .split_once('=')
+            .with_context(|| format!("Expected key=value, got '{}'", part))?;
+        let invalid = || format!("Invalid value for {}: '{}'", key, value);
+        match key {
+            "embed" => faults.embed_failure_rate = rate(value).with_context(invalid)?,
+            "write_delay_ms" => {
+                faults.write_delay = Duration::from_millis(value.parse().with_context(invalid)?)
+            }
+            "drop_events" => faults.drop_event_rate = rate(value).with_context(invalid)?,
+            "seed" => faults.seed = value.parse().with_context(invalid)?,
+            _ => bail!(
+                "Unknown fault '{}' (expected embed, write_delay_ms, drop_events or seed)",
+                key
+            ),
+        }
+    }
+    Ok(faults)
+}
+
+fn rate(value: &str) -> Result<f64> {
+    let rate: f64 = value.parse()?;
+    if !(0.0..=1.0).contains(&rate) {
+        bail!("a rate must be between 0 and 1");
+    }
+    Ok(rate)
+}
+
+/// Inject `faults` for the rest of the process. Only the first call counts.
+pub fn install(faults: Faults) {
+    log::warn!("Fault injection enabled: {:?}", faults);
+    RNG.store(faults.seed, Ordering::Relaxed);
+    let _ = FAULTS.set(faults);
+}
+
+/// Whether this embedding should fail.
+pub fn embedding_fails() -> bool {
+    FAULTS
+        .get()
+        .is_some_and(|f| roll(&RNG, f.embed_failure_rate))
+}
+
+/// How long to hold up the next batch of writes, if at all.
+pub fn write_delay() -> Option<Duration> {
+    FAULTS.get().map(|f| f.write_delay).filter(|d| !d.is_zero())
+}
+
+/// Whether this watcher event should be dropped.
+pub fn drops_event() -> bool {
+    FAULTS.get().is_some_and(|f| roll(&RNG, f.drop_event_rate))
+}
+
+/// True with probability `rate`, drawing from the splitmix64 sequence in
+/// `state`.
+fn roll(state: &AtomicU64, rate: f64) -> bool {
+    if rate <= 0.0 {
+        return false;
+    }
+    let mut z = state
+        .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    ((z >> 11) as f64 / (1u64 << 53) as f64) < rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faults() {
+        let faults = parse("embed=0.25, write_delay_ms=150,drop_events=1,seed=7").unwrap();
+        assert_eq!(
+            faults,
+            Faults {
+                embed_failure_rate: 0.25,
+                write_delay: Duration::from_millis(150),
+                drop_event_rate: 1.0,
+                seed: 7,
+            }
+        );
+        assert_eq!(parse("").unwrap().write_delay, Duration::ZERO);
+        assert!(parse("embed=2").is_err());
+        assert!(parse("network=0.5").is_err());
+        assert!(parse("embed").is_err());
+
+        let state = AtomicU64::new(7);
+        assert!((0..100).all(|_| roll(&state, 1.0)));
+        assert!((0..100).all(|_| !roll(&state, 0.0)));
+        let failures = (0..10_000).filter(|_| roll(&state, 0.25)).count();
+        assert!((2_000..3_000).contains(&failures), "{}", failures);
+
+        // The same seed fails the same calls
+        let (a, b) = (AtomicU64::new(42), AtomicU64::new(42));
+        let run = |state: &AtomicU64| (0..50).map(|_| roll(state, 0.5)).collect::<Vec<_>>();
+        assert_eq!(run(&a), run(&b));
+    }
+}
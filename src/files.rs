@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+use crate::citation::line_at;
+use crate::paths::{PathError, WatchRoots};
+
+/// Most lines returned by one request; longer ranges are cut short.
+pub const MAX_LINES: usize = 1000;
+
+/// The part of a file to read: 1-based inclusive lines, or a byte range
+/// ending before `end_byte`. Nothing set means the start of the file.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct RangeRequest {
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+    pub start_byte: Option<usize>,
+    pub end_byte: Option<usize>,
+}
+
+/// Current on-disk content of part of a file.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FileRange {
+    pub path: String,
+    /// First and last line of `content`, 1-based
+    pub start_line: usize,
+    pub end_line: usize,
+    pub total_lines: usize,
+    pub content: String,
+    /// Whether the range was cut to `MAX_LINES` lines
+    pub truncated: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RangeError {
+    /// The file isn't under a watch root
+    Forbidden(String),
+    NotFound(String),
+    /// A bad range, or a file that isn't text
+    Invalid(String),
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Forbidden(msg) | Self::NotFound(msg) | Self::Invalid(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for RangeError {}
+
+impl From<PathError> for RangeError {
+    fn from(e: PathError) -> Self {
+        match e {
+            PathError::Forbidden(msg) => Self::Forbidden(msg),
+            PathError::NotFound(msg) => Self::NotFound(msg),
+        }
+    }
+}
+
+/// Read `range` of `path`, which must resolve to a file under `roots`.
+pub fn read_range(
+    roots: &WatchRoots,
+    path: &Path,
+    range: &RangeRequest,
+) -> Result<FileRange, RangeError> {
+    let canonical = roots.authorize(path)?;
+    if !canonical.is_file() {
+        return Err(RangeError::Invalid(format!(
+            "{} is not a file",
+            path.display()
+        )));
+    }
+    let content = std::fs::read_to_string(&canonical)
+        .map_err(|_| RangeError::Invalid(format!("{} is not a text file", path.display())))?;
+    let mut file_range = slice(&content, range)?;
+    file_range.path = canonical.to_string_lossy().to_string();
+    Ok(file_range)
+}
+
+fn slice(content: &str, range: &RangeRequest) -> Result<FileRange, RangeError> {
+    let total_lines = content.lines().count();
+    let by_bytes = range.start_byte.is_some() || range.end_byte.is_some();
+    let by_lines = range.start_line.is_some() || range.end_line.is_some();
+    let (start, mut end) = match (by_bytes, by_lines) {
+        (true, true) => {
+            return Err(RangeError::Invalid(
+                "Give a line range or a byte range, not both".to_string(),
+            ))
+        }
+        (true, false) => byte_bounds(content, range)?,
+        (false, _) => line_bounds(content, total_lines, range)?,
+    };
+
+    let mut truncated = false;
+    if let Some((newline, _)) = content[start..end].match_indices('\n').nth(MAX_LINES - 1) {
+        if start + newline + 1 < end {
+            end = start + newline + 1;
+            truncated = true;
+        }
+    }
+    let start_line = line_at(content, start);
+    Ok(FileRange {
+        path: String::new(),
+        start_line,
+        end_line: if end > start {
+            line_at(content, end - 1)
+        } else {
+            start_line
+        },
+        total_lines,
+        content: content[start..end].to_string(),
+        truncated,
+    })
+}
+
+fn byte_bounds(content: &str, range: &RangeRequest) -> Result<(usize, usize), RangeError> {
+    let start = range.start_byte.unwrap_or(0);
+    let end = range.end_byte.unwrap_or(content.len()).min(content.len());
+    if start > end {
+        return Err(RangeError::Invalid(format!(
+            "start_byte {} is past end_byte {}",
+            start, end
+        )));
+    }
+    if !content.is_char_boundary(start) || !content.is_char_boundary(end) {
+        return Err(RangeError::Invalid(
+            "Byte range splits a UTF-8 character".to_string(),
+        ));
+    }
+    Ok((start, end))
+}
+
+fn line_bounds(
+    content: &str,
+    total_lines: usize,
+    range: &RangeRequest,
+) -> Result<(usize, usize), RangeError> {
+    let start = range.start_line.unwrap_or(1);
+    let end = range.end_line.unwrap_or(total_lines).min(total_lines);
+    if start == 0 || start > total_lines.max(1) {
+        return Err(RangeError::Invalid(format!(
+            "start_line {} is outside the file's {} lines",
+            start, total_lines
+        )));
+    }
+    if start > end && total_lines > 0 {
+        return Err(RangeError::Invalid(format!(
+            "start_line {} is past end_line {}",
+            start, end
+        )));
+    }
+    // Byte offset just past each line
+    let line_ends: Vec<usize> = content
+        .split_inclusive('\n')
+        .scan(0, |pos, line| {
+            *pos += line.len();
+            Some(*pos)
+        })
+        .collect();
+    let offset_after = |line: usize| if line == 0 { 0 } else { line_ends[line - 1] };
+    Ok((offset_after(start - 1), offset_after(end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(start: Option<usize>, end: Option<usize>) -> RangeRequest {
+        RangeRequest {
+            start_line: start,
+            end_line: end,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_read_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("lib.rs");
+        std::fs::write(&file, "one\ntwo\nthree\nfour\n").unwrap();
+        let roots = WatchRoots::new([dir.path().to_path_buf()]);
+
+        let range = read_range(&roots, &file, &lines(Some(2), Some(3))).unwrap();
+        assert_eq!(range.content, "two\nthree\n");
+        assert_eq!(
+            (range.start_line, range.end_line, range.total_lines),
+            (2, 3, 4)
+        );
+        // The end is clamped to the file
+        let range = read_range(&roots, &file, &lines(Some(4), Some(99))).unwrap();
+        assert_eq!(range.content, "four\n");
+
+        let bytes = RangeRequest {
+            start_byte: Some(4),
+            end_byte: Some(7),
+            ..Default::default()
+        };
+        let range = read_range(&roots, &file, &bytes).unwrap();
+        assert_eq!(range.content, "two");
+        assert_eq!((range.start_line, range.end_line), (2, 2));
+
+        assert!(matches!(
+            read_range(&roots, &file, &lines(Some(5), None)),
+            Err(RangeError::Invalid(_))
+        ));
+        assert!(matches!(
+            read_range(&roots, &file, &lines(Some(3), Some(2))),
+            Err(RangeError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_outside_roots() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let secret = outside.path().join("secret.txt");
+        std::fs::write(&secret, "key").unwrap();
+        let roots = WatchRoots::new([root.path().to_path_buf()]);
+
+        assert!(matches!(
+            read_range(&roots, &secret, &RangeRequest::default()),
+            Err(RangeError::Forbidden(_))
+        ));
+        // Escaping through `..` is caught after resolving the path
+        let escape = root
+            .path()
+            .join("..")
+            .join(outside.path().file_name().unwrap())
+            .join("secret.txt");
+        assert!(matches!(
+            read_range(&roots, &escape, &RangeRequest::default()),
+            Err(RangeError::Forbidden(_))
+        ));
+        assert!(matches!(
+            read_range(
+                &roots,
+                &root.path().join("missing.rs"),
+                &RangeRequest::default()
+            ),
+            Err(RangeError::NotFound(_))
+        ));
+    }
+}
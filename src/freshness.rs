@@ -0,0 +1,136 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::indexer::workers::WorkerPool;
+use crate::storage::db::{Database, WatchStatus};
+
+/// How often the daemon records its backlog.
+const RECORD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A backlog not recorded for this many seconds means no daemon is running.
+const STALE_AFTER: u64 = 30;
+
+/// How current the index is, sent with every query response so agents can
+/// decide whether to trust results or wait.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Freshness {
+    /// A daemon is watching the files; without one, changes aren't indexed
+    pub watching: bool,
+    /// When the daemon last finished indexing a changed file (seconds since
+    /// the epoch), if it has since it started
+    pub last_event_at: Option<u64>,
+    /// Files waiting to be indexed or being indexed
+    pub pending: u64,
+    /// Sequence number of the latest index change, see `/changes`. It grows
+    /// with every change, so equal generations mean an unchanged index.
+    pub generation: i64,
+}
+
+impl Freshness {
+    fn at(status: Option<WatchStatus>, generation: i64, now: u64) -> Self {
+        let status = status.filter(|s| now.saturating_sub(s.updated_at) <= STALE_AFTER);
+        Self {
+            watching: status.is_some(),
+            last_event_at: status.as_ref().and_then(|s| s.last_event_at),
+            pending: status.map(|s| s.pending).unwrap_or(0),
+            generation,
+        }
+    }
+}
+
+/// How current the index in `db` is.
+pub fn current(db: &Database) -> Result<Freshness> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (_, generation) = db.change_range()?;
+    Ok(Freshness::at(db.watch_status()?, generation, now))
+}
+
+/// The daemon's side: counts what it has left to index and records it
+/// every few seconds while it runs.
+#[derive(Clone, Default)]
+pub struct WatchTracker {
+    inner: Arc<Backlog>,
+}
+
+#[derive(Default)]
+struct Backlog {
+    /// Seconds since the epoch, 0 before the first
+    last_event_at: AtomicU64,
+    /// Files put off while indexing is paused
+    deferred: AtomicUsize,
+}
+
+impl WatchTracker {
+    pub fn spawn(db: Database, workers: Arc<WorkerPool>) -> Self {
+        let tracker = Self::default();
+        let backlog = tracker.inner.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RECORD_INTERVAL);
+            loop {
+                interval.tick().await;
+                let last_event_at =
+                    Some(backlog.last_event_at.load(Ordering::Relaxed)).filter(|&t| t > 0);
+                let pending = (workers.pending() + backlog.deferred.load(Ordering::Relaxed)) as u64;
+                if let Err(e) = db
+                    .call(move |db| db.record_watch_status(last_event_at, pending))
+                    .await
+                {
+                    log::warn!("Failed to record index freshness: {}", e);
+                }
+            }
+        });
+        tracker
+    }
+
+    /// A changed file has been indexed.
+    pub fn event_processed(&self) {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.inner.last_event_at.store(now, Ordering::Relaxed);
+    }
+
+    /// `count` files are put off until indexing resumes.
+    pub fn set_deferred(&self, count: usize) {
+        self.inner.deferred.store(count, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freshness() {
+        let db = Database::new(":memory:").unwrap();
+        let before = current(&db).unwrap();
+        assert!(!before.watching);
+        assert_eq!(before.pending, 0);
+
+        db.record_watch_status(Some(1_700_000_000), 3).unwrap();
+        db.add_or_update_file("/a.rs", 100).unwrap();
+        let now = current(&db).unwrap();
+        assert_eq!(
+            now,
+            Freshness {
+                watching: true,
+                last_event_at: Some(1_700_000_000),
+                pending: 3,
+                generation: before.generation + 1,
+            }
+        );
+
+        // A daemon that stopped recording isn't watching, whatever it left
+        let status = db.watch_status().unwrap().unwrap();
+        let stopped = Freshness::at(Some(status.clone()), 1, status.updated_at + STALE_AFTER + 1);
+        assert!(!stopped.watching);
+        assert_eq!(stopped.pending, 0);
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::indexer::moves;
+use crate::storage::db::{Database, Garbage};
+
+/// How often the daemon collects garbage.
+const GC_INTERVAL: Duration = Duration::from_secs(86_400);
+
+/// `[gc]` config: delete data nothing refers to any more, like chunks left
+/// behind by files removed mid-index, and usage analytics past their
+/// retention.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GcConfig {
+    /// Collect garbage once a day while the daemon runs
+    pub enabled: bool,
+    /// Drop query and index size analytics older than this many days (0
+    /// keeps them)
+    pub analytics_retention_days: u64,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            analytics_retention_days: 365,
+        }
+    }
+}
+
+/// What [`collect`] removed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Collected {
+    /// Files that never finished indexing and no longer exist
+    pub files: u64,
+    pub garbage: Garbage,
+}
+
+/// Collect garbage now and then once a day while the daemon runs. `roots`
+/// are the directories files are indexed from.
+pub fn spawn_collection(db: Database, config: GcConfig, roots: Vec<PathBuf>) {
+    if !config.enabled {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            let config = config.clone();
+            let roots = roots.clone();
+            match db.call(move |db| collect(db, &config, &roots, now())).await {
+                Ok(collected) => log_collected(&collected),
+                Err(e) => log::warn!("Failed to collect garbage in the index: {}", e),
+            }
+        }
+    });
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Remove files under `roots` that failed before their first index and
+/// have since been deleted, then everything [`Database::collect_garbage`]
+/// finds. `now` is in seconds since the epoch.
+pub fn collect(db: &Database, config: &GcConfig, roots: &[PathBuf], now: u64) -> Result<Collected> {
+    let mut files = 0;
+    for file in moves::missing_files(db, roots)? {
+        if file.last_indexed.is_none() && db.remove_file(&file.path)? {
+            files += 1;
+        }
+    }
+    let analytics_before = match config.analytics_retention_days {
+        0 => None,
+        days => Some(now.saturating_sub(days * 86_400)),
+    };
+    let garbage = db.collect_garbage(analytics_before)?;
+    Ok(Collected { files, garbage })
+}
+
+fn log_collected(collected: &Collected) {
+    let garbage = &collected.garbage;
+    if collected.files + garbage.chunks + garbage.index_rows + garbage.analytics_rows == 0 {
+        return;
+    }
+    log::info!(
+        "Collected garbage: {} files, {} chunks, {} index rows, {} analytics rows ({} KiB freed)",
+        collected.files,
+        garbage.chunks,
+        garbage.index_rows,
+        garbage.analytics_rows,
+        garbage.freed_bytes / 1024
+    );
+}
+
+pub fn handle_gc(config: &Config, vacuum: bool) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+    let roots = moves::index_roots(&db, config)?;
+    let collected = collect(&db, &config.gc, &roots, now())?;
+    let garbage = &collected.garbage;
+    println!("Removed:");
+    println!(
+        "  {} files that failed to index and no longer exist",
+        collected.files
+    );
+    println!("  {} chunks of removed files", garbage.chunks);
+    println!("  {} leftover index rows", garbage.index_rows);
+    println!("  {} models of empty collections", garbage.collections);
+    println!("  {} expired analytics rows", garbage.analytics_rows);
+
+    let size = || std::fs::metadata(&config.storage.db_path).map_or(0, |m| m.len());
+    if vacuum {
+        let before = size();
+        db.checkpoint()?;
+        db.vacuum()?;
+        db.checkpoint()?;
+        println!(
+            "Reclaimed {} KiB; the index is now {} KiB.",
+            before.saturating_sub(size()) / 1024,
+            size() / 1024
+        );
+    } else {
+        println!(
+            "Freed {} KiB inside the index. Run with --vacuum to shrink the file.",
+            garbage.freed_bytes / 1024
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path().join("index.db")).unwrap();
+        let kept = dir.path().join("kept.rs");
+        std::fs::write(&kept, "fn main() {}").unwrap();
+        let kept = kept.to_string_lossy().to_string();
+        let file_id = db.add_or_update_file(&kept, 100).unwrap();
+        db.add_chunk(file_id, 0, 12, "fn main() {}", None, None)
+            .unwrap();
+        db.record_content_hash(&kept, "abc").unwrap();
+        // Failed before its first index, then deleted
+        let failed = dir.path().join("failed.rs").to_string_lossy().to_string();
+        db.add_or_update_file(&failed, 100).unwrap();
+        db.record_content_hash(&failed, "def").unwrap();
+        db.record_query_stats("mcp", 5000, false, &["src".to_string()])
+            .unwrap();
+
+        let config = GcConfig::default();
+        let roots = [dir.path().to_path_buf()];
+        let collected = collect(&db, &config, &roots, now()).unwrap();
+        assert_eq!(collected.files, 1);
+        assert_eq!(collected.garbage.chunks, 0);
+        assert_eq!(collected.garbage.analytics_rows, 0);
+        assert!(db.get_file(&failed).unwrap().is_none());
+        assert_eq!(db.get_chunks(file_id).unwrap().len(), 1);
+
+        // Analytics expire, the file being indexed stays
+        let later = now() + 400 * 86_400;
+        let collected = collect(&db, &config, &roots, later).unwrap();
+        assert_eq!(collected.files, 0);
+        assert_eq!(collected.garbage.analytics_rows, 2);
+        assert!(db.get_file(&kept).unwrap().is_some());
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::storage::db::Database;
+
+/// How often the daemon drops versions older than the retention period.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(86_400);
+
+/// `[history]` config: keep earlier versions of chunks so queries can search
+/// the index as it was at a past time. Off by default, since every changed
+/// chunk is kept again with its embedding.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// Drop versions replaced longer ago than this many days (0 keeps them)
+    pub retention_days: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_days: 90,
+        }
+    }
+}
+
+/// Start or stop recording chunk history in `db` to match `config`, deleting
+/// the history when it is off, and prune expired versions.
+pub fn init(db: &Database, config: &HistoryConfig) -> Result<()> {
+    db.set_history(config.enabled)?;
+    if config.enabled {
+        prune(db, config)?;
+    }
+    Ok(())
+}
+
+/// Drop expired versions once a day while the daemon runs.
+pub fn spawn_pruning(db: Database, config: HistoryConfig) {
+    if !config.enabled || config.retention_days == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        // The first tick is immediate, and `init` just pruned
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let config = config.clone();
+            if let Err(e) = db.call(move |db| prune(db, &config)).await {
+                log::warn!("Failed to prune chunk history: {}", e);
+            }
+        }
+    });
+}
+
+fn prune(db: &Database, config: &HistoryConfig) -> Result<()> {
+    if config.retention_days == 0 {
+        return Ok(());
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let dropped = db.prune_history(now.saturating_sub(config.retention_days * 86_400))?;
+    if dropped > 0 {
+        log::info!(
+            "Dropped {} chunk versions past the history retention",
+            dropped
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::db::SearchOptions;
+
+    #[test]
+    fn test_as_of() {
+        let db = Database::new(":memory:").unwrap();
+        let file = db.add_or_update_file("/docs/auth.md", 100).unwrap();
+        db.add_chunk(file, 0, 3, "old", Some(&[1.0; 384]), None)
+            .unwrap();
+        db.mark_indexed(file).unwrap();
+        // Chunks indexed before the history was on count from their indexing
+        let config = HistoryConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        init(&db, &config).unwrap();
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        std::thread::sleep(Duration::from_millis(1100));
+        db.clear_chunks(file).unwrap();
+        db.add_chunk(file, 0, 3, "new", Some(&[1.0; 384]), None)
+            .unwrap();
+
+        let search = |as_of: Option<u64>| -> Vec<String> {
+            let options = SearchOptions {
+                as_of,
+                ..Default::default()
+            };
+            db.search_chunks_enhanced(&[1.0; 384], &options)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.content)
+                .collect()
+        };
+        assert_eq!(search(Some(now)), vec!["old"]);
+        assert_eq!(search(Some(now + 60)), vec!["new"]);
+        assert_eq!(search(None), vec!["new"]);
+        assert!(search(Some(0)).is_empty());
+
+        // Turning the history off deletes it
+        init(&db, &HistoryConfig::default()).unwrap();
+        db.add_chunk(file, 3, 6, "newer", Some(&[1.0; 384]), None)
+            .unwrap();
+        assert!(search(Some(now + 60)).is_empty());
+    }
+}
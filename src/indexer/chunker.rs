@@ -1,11 +1,58 @@
 use anyhow::Result;
-use tree_sitter::Parser;
+use serde::Deserialize;
+use std::ops::Range;
+use tree_sitter::{Node, Parser};
+
+use crate::citation::line_at;
+
+/// `[chunking]` config: keep chunks within the embedding model's input
+/// window, which longer ones are silently truncated to.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ChunkingConfig {
+    /// Split chunks of more tokens than this (0 keeps chunks whole)
+    pub max_chunk_size: usize,
+    /// Tokens each piece of a split chunk repeats from the end of the one
+    /// before it
+    pub overlap: usize,
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self {
+            max_chunk_size: 512,
+            overlap: 50,
+        }
+    }
+}
 
 pub struct Chunk {
     pub start: u64,
     pub end: u64,
     pub content: String,
     pub metadata: Option<String>,
+    /// First and last line, from 1, for chunks of a file's own text; see
+    /// [`chunk_source`]
+    pub lines: Option<(usize, usize)>,
+}
+
+impl Chunk {
+    /// The piece at byte `range` of the chunk's content, with its metadata.
+    fn piece(&self, range: Range<usize>) -> Chunk {
+        let text = &self.content[range.clone()];
+        let lines = self.lines.map(|(first, _)| {
+            let first = first + self.content[..range.start].matches('\n').count();
+            let breaks = text.trim_end_matches('\n').matches('\n').count();
+            (first, first + breaks)
+        });
+        Chunk {
+            start: self.start + range.start as u64,
+            end: self.start + range.end as u64,
+            content: text.to_string(),
+            metadata: self.metadata.clone(),
+            lines,
+        }
+    }
 }
 
 pub fn chunk_by_type(content: &str, ext: &str) -> Result<Vec<Chunk>> {
@@ -15,15 +62,623 @@ pub fn chunk_by_type(content: &str, ext: &str) -> Result<Vec<Chunk>> {
         "js" | "jsx" => chunk_javascript(content),
         "ts" | "tsx" => chunk_typescript(content),
         "go" => chunk_go(content),
+        "java" => chunk_java(content),
+        "c" => chunk_c(content),
+        "cpp" | "cc" | "cxx" | "h" | "hpp" | "hh" | "hxx" => chunk_cpp(content),
+        "cs" => chunk_csharp(content),
+        "rb" => chunk_ruby(content),
+        "php" => chunk_php(content),
         "md" | "markdown" => chunk_markdown(content),
+        "html" | "htm" | "xhtml" => chunk_html(content),
         _ => chunk_text(content),
     }
 }
 
-pub fn chunk_rust(content: &str) -> Result<Vec<Chunk>> {
+/// [`chunk_by_type`] for a file's own text, recording the lines each chunk
+/// spans unless the chunker splits text extracted from it.
+pub fn chunk_source(content: &str, ext: &str) -> Result<Vec<Chunk>> {
+    let mut chunks = chunk_by_type(content, ext)?;
+    if !extracts_text(ext) {
+        let breaks: Vec<usize> = content.match_indices('\n').map(|(i, _)| i).collect();
+        let line = |offset: u64| breaks.partition_point(|&b| b < offset as usize) + 1;
+        for chunk in &mut chunks {
+            let last = chunk.end.saturating_sub(1).max(chunk.start);
+            chunk.lines = Some((line(chunk.start), line(last)));
+        }
+    }
+    Ok(chunks)
+}
+
+/// Name of the chunker [`chunk_by_type`] uses for an extension.
+pub fn chunker_name(ext: &str) -> &'static str {
+    match ext {
+        "rs" => "rust (tree-sitter)",
+        "py" => "python (tree-sitter)",
+        "js" | "jsx" => "javascript (tree-sitter)",
+        "ts" | "tsx" => "typescript (tree-sitter)",
+        "go" => "go (tree-sitter)",
+        "java" => "java (tree-sitter)",
+        "c" => "c (tree-sitter)",
+        "cpp" | "cc" | "cxx" | "h" | "hpp" | "hh" | "hxx" => "cpp (tree-sitter)",
+        "cs" => "csharp (tree-sitter)",
+        "rb" => "ruby (tree-sitter)",
+        "php" => "php (tree-sitter)",
+        "md" | "markdown" => "markdown sections",
+        "html" | "htm" | "xhtml" => "html sections",
+        _ => "plain text",
+    }
+}
+
+/// Whether files of extension `ext` are chunked from text extracted from
+/// them, so chunk offsets don't point into the file itself.
+pub fn extracts_text(ext: &str) -> bool {
+    matches!(ext, "pdf" | "docx") || chunker_language(ext) == "html"
+}
+
+/// Implementation version of each language's chunker. Bump a language's
+/// version when its chunker changes how files are split, so
+/// `contextd rechunk --lang <language>` picks its files up.
+pub const CHUNKER_VERSIONS: &[(&str, u32)] = &[
+    ("rust", 3),
+    ("python", 3),
+    ("javascript", 3),
+    ("typescript", 3),
+    ("go", 2),
+    ("java", 1),
+    ("c", 1),
+    ("cpp", 1),
+    ("csharp", 1),
+    ("ruby", 1),
+    ("php", 1),
+    ("markdown", 1),
+    ("text", 1),
+    ("pdf", 1),
+    ("html", 1),
+    ("docx", 1),
+];
+
+/// Language of the chunker [`chunk_by_type`] uses for an extension.
+pub fn chunker_language(ext: &str) -> &'static str {
+    match ext {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "jsx" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "go" => "go",
+        "java" => "java",
+        "c" => "c",
+        "cpp" | "cc" | "cxx" | "h" | "hpp" | "hh" | "hxx" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "md" | "markdown" => "markdown",
+        "html" | "htm" | "xhtml" => "html",
+        _ => "text",
+    }
+}
+
+/// Current version of a language's chunker, or `None` for unknown languages.
+pub fn chunker_version(language: &str) -> Option<u32> {
+    CHUNKER_VERSIONS
+        .iter()
+        .find(|(name, _)| *name == language)
+        .map(|(_, version)| *version)
+}
+
+/// Split chunks longer than `max_chars` characters at line breaks, or
+/// mid-line when a single line is longer. Pieces keep their chunk's metadata.
+pub fn split_long(chunks: Vec<Chunk>, max_chars: usize) -> Vec<Chunk> {
+    let max_chars = max_chars.max(1);
+    let mut pieces = Vec::new();
+    for chunk in chunks {
+        if chunk.content.chars().count() <= max_chars {
+            pieces.push(chunk);
+            continue;
+        }
+        let content = &chunk.content;
+        let mut push = |range: std::ops::Range<usize>| {
+            if !content[range.clone()].trim().is_empty() {
+                pieces.push(chunk.piece(range));
+            }
+        };
+        let mut piece_start = 0;
+        let mut piece_chars = 0;
+        // Byte index just past the last line break in the current piece
+        let mut last_break = None;
+        for (i, c) in content.char_indices() {
+            if piece_chars == max_chars {
+                let cut = last_break.unwrap_or(i);
+                push(piece_start..cut);
+                piece_start = cut;
+                piece_chars = content[cut..i].chars().count();
+                last_break = None;
+            }
+            piece_chars += 1;
+            if c == '\n' {
+                last_break = Some(i + 1);
+            }
+        }
+        push(piece_start..content.len());
+    }
+    pieces
+}
+
+/// Split chunks of more than `config.max_chunk_size` tokens into pieces of
+/// at most that many, each starting `config.overlap` tokens before the end
+/// of the one before. Pieces end at a line break in their second half if
+/// there is one, and keep their chunk's metadata. `tokens` gives the byte
+/// ranges of the tokens in a text.
+pub fn split_tokens(
+    chunks: Vec<Chunk>,
+    config: &ChunkingConfig,
+    tokens: impl Fn(&str) -> Vec<Range<usize>>,
+) -> Vec<Chunk> {
+    let max = config.max_chunk_size;
+    if max == 0 {
+        return chunks;
+    }
+    // Each piece has to get further than the one before
+    let overlap = config.overlap.min(max / 2);
+    let mut pieces = Vec::new();
+    for chunk in chunks {
+        let spans = in_order(tokens(&chunk.content), chunk.content.len());
+        if spans.len() <= max {
+            pieces.push(chunk);
+            continue;
+        }
+        let content = &chunk.content;
+        let mut first = 0;
+        loop {
+            let mut end = (first + max).min(spans.len());
+            if end < spans.len() {
+                let line_end = (first + max / 2 + 1..end)
+                    .rev()
+                    .find(|&i| content[spans[i - 1].end..spans[i].start].contains('\n'));
+                end = line_end.unwrap_or(end);
+            }
+            pieces.push(chunk.piece(spans[first].start..spans[end - 1].end));
+            if end == spans.len() {
+                break;
+            }
+            first = end.saturating_sub(overlap).max(first + 1);
+        }
+    }
+    pieces
+}
+
+/// Token `spans` that follow one another within `len` bytes, so the text
+/// between two of them can be sliced. Tokenizers can give special and merged
+/// tokens offsets overlapping their neighbours: each start is moved up to
+/// the end before it, and tokens left empty or out of range are skipped.
+fn in_order(spans: Vec<Range<usize>>, len: usize) -> Vec<Range<usize>> {
+    let mut end = 0;
+    spans
+        .into_iter()
+        .filter_map(|span| {
+            let span = span.start.max(end)..span.end.min(len);
+            if span.is_empty() {
+                return None;
+            }
+            end = span.end;
+            Some(span)
+        })
+        .collect()
+}
+
+/// Byte ranges of the words and punctuation in `text`, as an estimate of
+/// the tokens a model would see in it when its tokenizer isn't at hand.
+pub fn approximate_tokens(text: &str) -> Vec<Range<usize>> {
+    let mut tokens: Vec<Range<usize>> = Vec::new();
+    let mut in_word = false;
+    for (i, c) in text.char_indices() {
+        let word = c.is_alphanumeric() || c == '_';
+        match tokens.last_mut() {
+            Some(last) if word && in_word => last.end = i + c.len_utf8(),
+            _ if !c.is_whitespace() => tokens.push(i..i + c.len_utf8()),
+            _ => {}
+        }
+        in_word = word;
+    }
+    tokens
+}
+
+/// Which nodes of a language's syntax tree hold definitions of their own.
+struct Grammar {
+    /// As in [`CHUNKER_VERSIONS`]
+    language: &'static str,
+    /// Definitions inside a container that get chunks of their own
+    members: &'static [&'static str],
+    /// Definitions whose bodies are chunked member by member
+    containers: &'static [&'static str],
+    /// Comments, attached to the definition after them
+    comments: &'static [&'static str],
+}
+
+const RUST: Grammar = Grammar {
+    language: "rust",
+    members: &[
+        "function_item",
+        "impl_item",
+        "struct_item",
+        "enum_item",
+        "mod_item",
+        "trait_item",
+    ],
+    containers: &["impl_item", "trait_item", "mod_item"],
+    comments: &["line_comment", "block_comment"],
+};
+
+const PYTHON: Grammar = Grammar {
+    language: "python",
+    members: &[
+        "function_definition",
+        "class_definition",
+        "decorated_definition",
+    ],
+    containers: &["class_definition"],
+    comments: &["comment"],
+};
+
+const JAVASCRIPT: Grammar = Grammar {
+    language: "javascript",
+    members: &["method_definition"],
+    containers: &["class_declaration"],
+    comments: &["comment"],
+};
+
+const TYPESCRIPT: Grammar = Grammar {
+    language: "typescript",
+    members: &["method_definition"],
+    containers: &["class_declaration", "abstract_class_declaration"],
+    comments: &["comment"],
+};
+
+const GO: Grammar = Grammar {
+    language: "go",
+    members: &[],
+    containers: &[],
+    comments: &["comment"],
+};
+
+const JAVA: Grammar = Grammar {
+    language: "java",
+    members: &[
+        "class_declaration",
+        "interface_declaration",
+        "enum_declaration",
+        "record_declaration",
+        "annotation_type_declaration",
+        "method_declaration",
+        "constructor_declaration",
+    ],
+    containers: &[
+        "class_declaration",
+        "interface_declaration",
+        "record_declaration",
+    ],
+    comments: &["line_comment", "block_comment"],
+};
+
+const C: Grammar = Grammar {
+    language: "c",
+    members: &[
+        "function_definition",
+        "struct_specifier",
+        "union_specifier",
+        "enum_specifier",
+        "type_definition",
+    ],
+    containers: &[],
+    comments: &["comment"],
+};
+
+const CPP: Grammar = Grammar {
+    language: "cpp",
+    members: &[
+        "function_definition",
+        "class_specifier",
+        "struct_specifier",
+        "union_specifier",
+        "enum_specifier",
+        "type_definition",
+        "template_declaration",
+        "namespace_definition",
+        "linkage_specification",
+    ],
+    containers: &[
+        "class_specifier",
+        "struct_specifier",
+        "namespace_definition",
+        "linkage_specification",
+    ],
+    comments: &["comment"],
+};
+
+const CSHARP: Grammar = Grammar {
+    language: "csharp",
+    members: &[
+        "namespace_declaration",
+        "class_declaration",
+        "interface_declaration",
+        "struct_declaration",
+        "record_declaration",
+        "enum_declaration",
+        "method_declaration",
+        "constructor_declaration",
+    ],
+    containers: &[
+        "namespace_declaration",
+        "class_declaration",
+        "interface_declaration",
+        "struct_declaration",
+        "record_declaration",
+    ],
+    comments: &["comment"],
+};
+
+const RUBY: Grammar = Grammar {
+    language: "ruby",
+    members: &[
+        "method",
+        "singleton_method",
+        "class",
+        "module",
+        "singleton_class",
+    ],
+    containers: &["class", "module", "singleton_class"],
+    comments: &["comment"],
+};
+
+const PHP: Grammar = Grammar {
+    language: "php",
+    members: &[
+        "function_definition",
+        "class_declaration",
+        "interface_declaration",
+        "trait_declaration",
+        "enum_declaration",
+        "namespace_definition",
+        "method_declaration",
+    ],
+    containers: &[
+        "class_declaration",
+        "interface_declaration",
+        "trait_declaration",
+        "namespace_definition",
+    ],
+    comments: &["comment"],
+};
+
+/// Preprocessor conditionals, whose definitions are chunked as if they were
+/// outside them.
+const PREPROCESSOR_BLOCKS: &[&str] = &[
+    "preproc_if",
+    "preproc_ifdef",
+    "preproc_else",
+    "preproc_elif",
+    "preproc_elifdef",
+];
+
+/// Push the chunks of a definition starting at `start`, which is before
+/// `node` when comments lead it. A container with members, like an impl
+/// block or a class, becomes a summary chunk of its text with each member
+/// cut down to its signature, followed by the members' own chunks, which
+/// record the container as their `parent`. Every chunk's metadata records
+/// its language and the symbol it defines with that symbol's signature.
+fn push_definition(
+    content: &str,
+    node: Node,
+    start: usize,
+    parent: Option<&str>,
+    grammar: &Grammar,
+    chunks: &mut Vec<Chunk>,
+) {
+    let end = node.end_byte();
+    let metadata = Some(definition_metadata(content, node, parent, grammar));
+    let body = container_body(node, grammar);
+    let mut members = body.map(|body| members(body, grammar)).unwrap_or_default();
+    let Some(body) = body.filter(|_| !members.is_empty()) else {
+        chunks.push(Chunk {
+            start: start as u64,
+            end: end as u64,
+            content: content[start..end].to_string(),
+            metadata,
+            lines: None,
+        });
+        return;
+    };
+
+    // Grammars like Ruby's leave the comments before a body's first
+    // statement outside the body; they lead its first member
+    let mut comments_start = None;
+    let mut cursor = node.walk();
+    for child in definition(node).children(&mut cursor) {
+        if child.id() == body.id() {
+            break;
+        }
+        comments_start = match grammar.comments.contains(&child.kind()) {
+            true => comments_start.or(Some(child.start_byte())),
+            false => None,
+        };
+    }
+    if let Some(comments_start) = comments_start {
+        if body.named_child(0).map(|c| c.id()) == Some(members[0].1.id()) {
+            members[0].0 = comments_start;
+        }
+    }
+    let header_end = comments_start.unwrap_or(body.start_byte());
+
+    let mut summary = String::new();
+    let mut pos = start;
+    for (member_start, member) in &members {
+        summary.push_str(&content[pos..*member_start]);
+        summary.push_str(signature(content, *member));
+        pos = member.end_byte();
+    }
+    summary.push_str(&content[pos..end]);
+    chunks.push(Chunk {
+        start: start as u64,
+        end: end as u64,
+        content: summary,
+        metadata,
+        lines: None,
+    });
+
+    // The container's declaration up to its body, as in `impl Foo` or
+    // `class Foo(Base)`
+    let header = content[definition(node).start_byte()..header_end]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let header = header.trim_end_matches(':');
+    for (member_start, member) in members {
+        push_definition(content, member, member_start, Some(header), grammar, chunks);
+    }
+}
+
+/// The definition `node` declares, looking through decorators, exports and
+/// C++ templates.
+fn definition(node: Node) -> Node {
+    let inner = match node.kind() {
+        "template_declaration" => node.named_child(node.named_child_count().saturating_sub(1)),
+        _ => node
+            .child_by_field_name("definition")
+            .or_else(|| node.child_by_field_name("declaration")),
+    };
+    inner.map_or(node, definition)
+}
+
+/// The body of `node` if it's a container.
+fn container_body<'a>(node: Node<'a>, grammar: &Grammar) -> Option<Node<'a>> {
+    let definition = definition(node);
+    if !grammar.containers.contains(&definition.kind()) {
+        return None;
+    }
+    definition.child_by_field_name("body")
+}
+
+/// Definitions in a container's body, each with where it starts including
+/// the comments leading it.
+fn members<'a>(body: Node<'a>, grammar: &Grammar) -> Vec<(usize, Node<'a>)> {
+    let mut members = Vec::new();
+    let mut pending_comments_start = None;
+    let mut cursor = body.walk();
+    for child in body.children(&mut cursor) {
+        if grammar.comments.contains(&child.kind()) {
+            pending_comments_start.get_or_insert(child.start_byte());
+            continue;
+        }
+        if PREPROCESSOR_BLOCKS.contains(&child.kind()) {
+            members.extend(self::members(child, grammar));
+        } else if grammar.members.contains(&child.kind()) {
+            members.push((pending_comments_start.unwrap_or(child.start_byte()), child));
+        }
+        pending_comments_start = None;
+    }
+    members
+}
+
+/// A definition's text up to its body, or its first line when it has none.
+fn signature<'a>(content: &'a str, node: Node) -> &'a str {
+    let text = match definition(node).child_by_field_name("body") {
+        Some(body) => &content[node.start_byte()..body.start_byte()],
+        None => content[node.byte_range()].lines().next().unwrap_or(""),
+    };
+    text.trim_end()
+}
+
+/// Name of the symbol a definition declares, if it has one.
+fn symbol<'a>(content: &'a str, node: Node) -> Option<&'a str> {
+    let definition = definition(node);
+    let mut cursor = definition.walk();
+    let mut name = definition
+        .child_by_field_name("name")
+        // C's `int *add(int a, int b)` or `typedef struct {...} Point`
+        .or_else(|| declared_name(definition))
+        // The type of `impl Foo` or `impl Display for Foo`
+        .or_else(|| definition.child_by_field_name("type"))
+        // `const foo = ...` and Go's `type Foo struct`
+        .or_else(|| {
+            definition
+                .named_children(&mut cursor)
+                .find(|c| matches!(c.kind(), "variable_declarator" | "type_spec"))?
+                .child_by_field_name("name")
+        })?;
+    // `Foo` of `impl<T> Foo<T>`, `Foo::bar` or `Outer::Inner`
+    loop {
+        let inner = match name.kind() {
+            "generic_type" => name.child_by_field_name("type"),
+            _ => name.child_by_field_name("name"),
+        };
+        match inner {
+            Some(inner) => name = inner,
+            None => break,
+        }
+    }
+    Some(&content[name.byte_range()])
+}
+
+/// The name at the bottom of a C declarator, which wraps it in pointers,
+/// parameters and arrays.
+fn declared_name(node: Node) -> Option<Node> {
+    let mut declarator = node.child_by_field_name("declarator")?;
+    while let Some(inner) = declarator.child_by_field_name("declarator") {
+        declarator = inner;
+    }
+    Some(declarator)
+}
+
+/// Chunk the definitions at the top level of `content` with `grammar`,
+/// falling back to plain text when it has none.
+fn chunk_definitions(
+    content: &str,
+    language: &tree_sitter::Language,
+    grammar: &Grammar,
+) -> Result<Vec<Chunk>> {
     let mut parser = Parser::new();
-    let language = tree_sitter_rust::language();
     parser.set_language(language)?;
+    let tree = parser
+        .parse(content, None)
+        .ok_or_else(|| anyhow::anyhow!("Failed to parse {} code", grammar.language))?;
+
+    let mut chunks = Vec::new();
+    for (start, node) in members(tree.root_node(), grammar) {
+        push_definition(content, node, start, None, grammar, &mut chunks);
+    }
+    if chunks.is_empty() && !content.trim().is_empty() {
+        return chunk_text(content);
+    }
+    Ok(chunks)
+}
+
+/// Metadata of a definition's chunk, see [`push_definition`].
+fn definition_metadata(
+    content: &str,
+    node: Node,
+    parent: Option<&str>,
+    grammar: &Grammar,
+) -> String {
+    let mut metadata = serde_json::Map::new();
+    if let Some(parent) = parent {
+        metadata.insert("parent".to_string(), parent.into());
+    }
+    if let Some(symbol) = symbol(content, node) {
+        let signature = signature(content, definition(node))
+            .trim_end_matches(['{', ':'])
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        metadata.insert("symbol".to_string(), symbol.into());
+        metadata.insert("signature".to_string(), signature.into());
+    }
+    metadata.insert("language".to_string(), grammar.language.into());
+    serde_json::Value::Object(metadata).to_string()
+}
+
+pub fn chunk_rust(content: &str) -> Result<Vec<Chunk>> {
+    let mut parser = Parser::new();
+    let language = tree_sitter_rust::LANGUAGE.into();
+    parser.set_language(&language)?;
 
     let tree = parser
         .parse(content, None)
@@ -50,19 +705,9 @@ pub fn chunk_rust(content: &str) -> Result<Vec<Chunk>> {
             kind,
             "function_item" | "impl_item" | "struct_item" | "enum_item" | "mod_item" | "trait_item"
         ) {
-            let start_byte = pending_comments_start.unwrap_or(child.start_byte()) as u64;
-            let end_byte = child.end_byte() as u64;
-
             // Ensure we capture from the start of comments if present
             let chunk_start = pending_comments_start.unwrap_or(child.start_byte());
-            let chunk_content = &content[chunk_start..child.end_byte()];
-
-            chunks.push(Chunk {
-                start: start_byte,
-                end: end_byte,
-                content: chunk_content.to_string(),
-                metadata: None,
-            });
+            push_definition(content, child, chunk_start, None, &RUST, &mut chunks);
 
             pending_comments_start = None;
         } else {
@@ -86,8 +731,8 @@ pub fn chunk_rust(content: &str) -> Result<Vec<Chunk>> {
 /// Semantic chunking for Python using Tree-sitter
 pub fn chunk_python(content: &str) -> Result<Vec<Chunk>> {
     let mut parser = Parser::new();
-    let language = tree_sitter_python::language();
-    parser.set_language(language)?;
+    let language = tree_sitter_python::LANGUAGE.into();
+    parser.set_language(&language)?;
 
     let tree = parser
         .parse(content, None)
@@ -103,16 +748,14 @@ pub fn chunk_python(content: &str) -> Result<Vec<Chunk>> {
             kind,
             "function_definition" | "class_definition" | "decorated_definition"
         ) {
-            let start_byte = child.start_byte() as u64;
-            let end_byte = child.end_byte() as u64;
-            let chunk_content = &content[child.start_byte()..child.end_byte()];
-
-            chunks.push(Chunk {
-                start: start_byte,
-                end: end_byte,
-                content: chunk_content.to_string(),
-                metadata: None,
-            });
+            push_definition(
+                content,
+                child,
+                child.start_byte(),
+                None,
+                &PYTHON,
+                &mut chunks,
+            );
         }
     }
 
@@ -126,8 +769,8 @@ pub fn chunk_python(content: &str) -> Result<Vec<Chunk>> {
 /// Semantic chunking for JavaScript using Tree-sitter
 pub fn chunk_javascript(content: &str) -> Result<Vec<Chunk>> {
     let mut parser = Parser::new();
-    let language = tree_sitter_javascript::language();
-    parser.set_language(language)?;
+    let language = tree_sitter_javascript::LANGUAGE.into();
+    parser.set_language(&language)?;
 
     let tree = parser
         .parse(content, None)
@@ -152,16 +795,14 @@ pub fn chunk_javascript(content: &str) -> Result<Vec<Chunk>> {
                 continue;
             }
 
-            let start_byte = child.start_byte() as u64;
-            let end_byte = child.end_byte() as u64;
-            let chunk_content = &content[child.start_byte()..child.end_byte()];
-
-            chunks.push(Chunk {
-                start: start_byte,
-                end: end_byte,
-                content: chunk_content.to_string(),
-                metadata: None,
-            });
+            push_definition(
+                content,
+                child,
+                child.start_byte(),
+                None,
+                &JAVASCRIPT,
+                &mut chunks,
+            );
         }
     }
 
@@ -175,8 +816,8 @@ pub fn chunk_javascript(content: &str) -> Result<Vec<Chunk>> {
 /// Semantic chunking for TypeScript using Tree-sitter
 pub fn chunk_typescript(content: &str) -> Result<Vec<Chunk>> {
     let mut parser = Parser::new();
-    let language = tree_sitter_typescript::language_typescript();
-    parser.set_language(language)?;
+    let language = tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into();
+    parser.set_language(&language)?;
 
     let tree = parser
         .parse(content, None)
@@ -197,16 +838,14 @@ pub fn chunk_typescript(content: &str) -> Result<Vec<Chunk>> {
                 | "export_statement"
                 | "lexical_declaration"
         ) {
-            let start_byte = child.start_byte() as u64;
-            let end_byte = child.end_byte() as u64;
-            let chunk_content = &content[child.start_byte()..child.end_byte()];
-
-            chunks.push(Chunk {
-                start: start_byte,
-                end: end_byte,
-                content: chunk_content.to_string(),
-                metadata: None,
-            });
+            push_definition(
+                content,
+                child,
+                child.start_byte(),
+                None,
+                &TYPESCRIPT,
+                &mut chunks,
+            );
         }
     }
 
@@ -220,8 +859,8 @@ pub fn chunk_typescript(content: &str) -> Result<Vec<Chunk>> {
 /// Semantic chunking for Go using Tree-sitter
 pub fn chunk_go(content: &str) -> Result<Vec<Chunk>> {
     let mut parser = Parser::new();
-    let language = tree_sitter_go::language();
-    parser.set_language(language)?;
+    let language = tree_sitter_go::LANGUAGE.into();
+    parser.set_language(&language)?;
 
     let tree = parser
         .parse(content, None)
@@ -241,16 +880,7 @@ pub fn chunk_go(content: &str) -> Result<Vec<Chunk>> {
                 | "const_declaration"
                 | "var_declaration"
         ) {
-            let start_byte = child.start_byte() as u64;
-            let end_byte = child.end_byte() as u64;
-            let chunk_content = &content[child.start_byte()..child.end_byte()];
-
-            chunks.push(Chunk {
-                start: start_byte,
-                end: end_byte,
-                content: chunk_content.to_string(),
-                metadata: None,
-            });
+            push_definition(content, child, child.start_byte(), None, &GO, &mut chunks);
         }
     }
 
@@ -261,6 +891,37 @@ pub fn chunk_go(content: &str) -> Result<Vec<Chunk>> {
     Ok(chunks)
 }
 
+/// Semantic chunking for Java using Tree-sitter
+pub fn chunk_java(content: &str) -> Result<Vec<Chunk>> {
+    chunk_definitions(content, &tree_sitter_java::LANGUAGE.into(), &JAVA)
+}
+
+/// Semantic chunking for C using Tree-sitter
+pub fn chunk_c(content: &str) -> Result<Vec<Chunk>> {
+    chunk_definitions(content, &tree_sitter_c::LANGUAGE.into(), &C)
+}
+
+/// Semantic chunking for C++ using Tree-sitter. Headers are parsed as C++,
+/// which C headers mostly are too.
+pub fn chunk_cpp(content: &str) -> Result<Vec<Chunk>> {
+    chunk_definitions(content, &tree_sitter_cpp::LANGUAGE.into(), &CPP)
+}
+
+/// Semantic chunking for C# using Tree-sitter
+pub fn chunk_csharp(content: &str) -> Result<Vec<Chunk>> {
+    chunk_definitions(content, &tree_sitter_c_sharp::LANGUAGE.into(), &CSHARP)
+}
+
+/// Semantic chunking for Ruby using Tree-sitter
+pub fn chunk_ruby(content: &str) -> Result<Vec<Chunk>> {
+    chunk_definitions(content, &tree_sitter_ruby::LANGUAGE.into(), &RUBY)
+}
+
+/// Semantic chunking for PHP using Tree-sitter
+pub fn chunk_php(content: &str) -> Result<Vec<Chunk>> {
+    chunk_definitions(content, &tree_sitter_php::LANGUAGE_PHP.into(), &PHP)
+}
+
 pub fn chunk_markdown(content: &str) -> Result<Vec<Chunk>> {
     let mut chunks = Vec::new();
     let mut current_chunk_start = 0;
@@ -283,6 +944,7 @@ pub fn chunk_markdown(content: &str) -> Result<Vec<Chunk>> {
                     end: (current_chunk_start + current_chunk_content.len()) as u64,
                     content: current_chunk_content.clone(),
                     metadata,
+                    lines: None,
                 });
             }
 
@@ -320,6 +982,7 @@ pub fn chunk_markdown(content: &str) -> Result<Vec<Chunk>> {
             end: (current_chunk_start + current_chunk_content.len()) as u64,
             content: current_chunk_content,
             metadata,
+            lines: None,
         });
     }
 
@@ -348,6 +1011,7 @@ pub fn chunk_text(content: &str) -> Result<Vec<Chunk>> {
             end: start + len,
             content: paragraph.to_string(),
             metadata: None,
+            lines: None,
         });
 
         start += len + 2; // content + \n\n
@@ -391,6 +1055,7 @@ pub fn chunk_pdf(path: &std::path::Path) -> Result<Vec<Chunk>> {
             end: start + len,
             content: clean_para.to_string(),
             metadata: None, // Could add page number if we tracked it
+            lines: None,
         });
 
         start += len + 2;
@@ -399,6 +1064,244 @@ pub fn chunk_pdf(path: &std::path::Path) -> Result<Vec<Chunk>> {
     Ok(chunks)
 }
 
+/// Chunk an HTML page by its headings, as [`chunk_markdown`] chunks
+/// markdown, leaving out scripts, styles and markup. Offsets are into the
+/// page's text, not its source.
+pub fn chunk_html(content: &str) -> Result<Vec<Chunk>> {
+    let document = scraper::Html::parse_document(content);
+    let mut outline = Outline::default();
+    html_outline(*document.root_element(), &mut outline);
+    chunk_markdown(&outline.finish())
+}
+
+/// Chunk a Word document by its heading paragraphs, as [`chunk_html`]
+/// chunks a page.
+pub fn chunk_docx(path: &std::path::Path) -> Result<Vec<Chunk>> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+    let mut xml = String::new();
+    std::io::Read::read_to_string(&mut archive.by_name("word/document.xml")?, &mut xml)?;
+    chunk_markdown(&docx_outline(&xml)?)
+}
+
+/// A document's text written as markdown, with its headings as `#` lines so
+/// [`chunk_markdown`] splits it into sections.
+#[derive(Default)]
+struct Outline {
+    text: String,
+    /// The paragraph being collected
+    block: String,
+}
+
+impl Outline {
+    fn push_text(&mut self, text: &str) {
+        self.block.push_str(text);
+    }
+
+    /// Write the paragraph collected so far, with its whitespace collapsed.
+    fn end_block(&mut self) {
+        let block = std::mem::take(&mut self.block);
+        let words: Vec<&str> = block.split_whitespace().collect();
+        if !words.is_empty() {
+            self.push_line(&words.join(" "));
+            self.text.push('\n');
+        }
+    }
+
+    /// Write preformatted text as it is.
+    fn push_preformatted(&mut self, text: &str) {
+        self.end_block();
+        for line in text.trim_matches('\n').lines() {
+            self.push_line(line);
+        }
+        self.text.push('\n');
+    }
+
+    fn heading(&mut self, level: usize, title: &str) {
+        self.end_block();
+        let title: Vec<&str> = title.split_whitespace().collect();
+        if !title.is_empty() {
+            let level = level.clamp(1, 6);
+            self.text
+                .push_str(&format!("{} {}\n\n", "#".repeat(level), title.join(" ")));
+        }
+    }
+
+    fn push_line(&mut self, line: &str) {
+        // Only headings may start a line with `#`
+        if line.starts_with('#') {
+            self.text.push('\\');
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn finish(mut self) -> String {
+        self.end_block();
+        self.text
+    }
+}
+
+/// Elements whose content isn't part of the page's text.
+const HTML_SKIPPED: &[&str] = &[
+    "head", "script", "style", "noscript", "template", "svg", "canvas", "iframe",
+];
+
+/// Elements that end the paragraph before them.
+const HTML_BLOCKS: &[&str] = &[
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "header",
+    "footer",
+    "nav",
+    "aside",
+    "blockquote",
+    "ul",
+    "ol",
+    "li",
+    "dl",
+    "dt",
+    "dd",
+    "table",
+    "tr",
+    "figure",
+    "figcaption",
+    "form",
+    "br",
+    "hr",
+    "address",
+    "details",
+    "summary",
+];
+
+fn html_outline(node: ego_tree::NodeRef<scraper::Node>, outline: &mut Outline) {
+    for child in node.children() {
+        match child.value() {
+            scraper::Node::Text(text) => outline.push_text(text),
+            scraper::Node::Element(element) => {
+                let name = element.name();
+                if HTML_SKIPPED.contains(&name) {
+                    continue;
+                }
+                let text = || -> String {
+                    scraper::ElementRef::wrap(child)
+                        .map(|e| e.text().collect())
+                        .unwrap_or_default()
+                };
+                match name.as_bytes() {
+                    [b'h', level @ b'1'..=b'6'] => {
+                        outline.heading((level - b'0') as usize, &text());
+                    }
+                    b"pre" => outline.push_preformatted(&text()),
+                    b"td" | b"th" => {
+                        outline.push_text(" ");
+                        html_outline(child, outline);
+                        outline.push_text(" ");
+                    }
+                    _ if HTML_BLOCKS.contains(&name) => {
+                        outline.end_block();
+                        html_outline(child, outline);
+                        outline.end_block();
+                    }
+                    _ => html_outline(child, outline),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The text of a Word document's `word/document.xml`, with paragraphs in
+/// heading styles (or with an outline level) as headings.
+fn docx_outline(xml: &str) -> Result<String> {
+    use quick_xml::events::Event;
+
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut outline = Outline::default();
+    let mut paragraph = String::new();
+    let mut level = None;
+    let mut in_text = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                b"p" => {
+                    paragraph.clear();
+                    level = None;
+                }
+                b"t" => in_text = true,
+                b"tab" => paragraph.push('\t'),
+                b"br" | b"cr" => paragraph.push('\n'),
+                b"pStyle" => {
+                    if let Some(style) = e.try_get_attribute("w:val")? {
+                        level = level.or(heading_style_level(&style.unescape_value()?));
+                    }
+                }
+                b"outlineLvl" => {
+                    if let Some(value) = e.try_get_attribute("w:val")? {
+                        let value: Option<usize> = value.unescape_value()?.parse().ok();
+                        // Level 9 is body text
+                        level = level.or(value.filter(|&v| v < 9).map(|v| v + 1));
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(text) if in_text => paragraph.push_str(&text.unescape()?),
+            Event::End(e) => match e.local_name().as_ref() {
+                b"t" => in_text = false,
+                b"p" => match level {
+                    Some(level) => outline.heading(level, &paragraph),
+                    None => {
+                        outline.push_text(&paragraph);
+                        outline.end_block();
+                    }
+                },
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(outline.finish())
+}
+
+/// Heading level of a paragraph style such as `Heading2` or `Title`.
+fn heading_style_level(style: &str) -> Option<usize> {
+    let style = style.to_ascii_lowercase().replace(' ', "");
+    if style == "title" {
+        return Some(1);
+    }
+    style.strip_prefix("heading")?.parse().ok()
+}
+
+/// Every chunk of `content` with its byte and line range, metadata and full
+/// text, as `contextd chunk --debug` prints it. The chunker snapshots in
+/// `tests/fixtures/chunker` are in this form.
+pub fn debug_snapshot(content: &str, ext: &str, chunks: &[Chunk]) -> String {
+    use std::fmt::Write;
+    let mut out = format!("# {}: {} chunks\n", chunker_name(ext), chunks.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "\n--- chunk {}: bytes {}..{}, lines {}-{}",
+            i + 1,
+            chunk.start,
+            chunk.end,
+            line_at(content, chunk.start as usize),
+            line_at(content, chunk.end.saturating_sub(1) as usize)
+        );
+        if let Some(metadata) = &chunk.metadata {
+            let _ = writeln!(out, "metadata: {}", metadata);
+        }
+        out.push_str(&chunk.content);
+        if !chunk.content.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -412,6 +1315,99 @@ mod tests {
         assert_eq!(chunks[1].content, "Para 2");
     }
 
+    #[test]
+    fn test_split_long() {
+        let chunk = Chunk {
+            start: 100,
+            end: 121,
+            content: "aaaa\nbbbb\ncccccccccc".to_string(),
+            metadata: Some("{}".to_string()),
+            lines: Some((3, 5)),
+        };
+        let pieces = split_long(vec![chunk], 8);
+        let contents: Vec<&str> = pieces.iter().map(|p| p.content.as_str()).collect();
+        // Breaks after a line where possible, mid-line otherwise
+        assert_eq!(contents, vec!["aaaa\n", "bbbb\n", "cccccccc", "cc"]);
+        assert_eq!((pieces[1].start, pieces[1].end), (105, 110));
+        assert!(pieces.iter().all(|p| p.metadata.is_some()));
+        let lines: Vec<_> = pieces.iter().map(|p| p.lines.unwrap()).collect();
+        assert_eq!(lines, vec![(3, 3), (4, 4), (5, 5), (5, 5)]);
+    }
+
+    #[test]
+    fn test_split_tokens() {
+        assert_eq!(approximate_tokens("fn foo_bar(x) {}").len(), 7);
+        let chunk = Chunk {
+            start: 10,
+            end: 25,
+            content: "a b c\nd e f\ng h".to_string(),
+            metadata: Some("{}".to_string()),
+            lines: Some((1, 3)),
+        };
+        let config = ChunkingConfig {
+            max_chunk_size: 4,
+            overlap: 1,
+        };
+        let pieces = split_tokens(vec![chunk], &config, approximate_tokens);
+        let contents: Vec<&str> = pieces.iter().map(|p| p.content.as_str()).collect();
+        // Ends at a line break where possible, then backs up by the overlap
+        assert_eq!(contents, vec!["a b c", "c\nd e f", "f\ng h"]);
+        assert_eq!((pieces[1].start, pieces[1].end), (14, 21));
+        assert!(pieces.iter().all(|p| p.metadata.is_some()));
+        let lines: Vec<_> = pieces.iter().map(|p| p.lines.unwrap()).collect();
+        assert_eq!(lines, vec![(1, 1), (1, 2), (2, 3)]);
+
+        let short = Chunk {
+            start: 0,
+            end: 5,
+            content: "a b c".to_string(),
+            metadata: None,
+            lines: None,
+        };
+        assert_eq!(
+            split_tokens(vec![short], &config, approximate_tokens).len(),
+            1
+        );
+
+        // Overlapping, repeated and out-of-order offsets don't panic
+        let chunk = Chunk {
+            start: 0,
+            end: 15,
+            content: "a b c\nd e f\ng h".to_string(),
+            metadata: None,
+            lines: None,
+        };
+        // Clean up to the tokens approximate_tokens finds
+        let tokens = |_: &str| {
+            vec![
+                0..1,
+                0..1,
+                2..3,
+                1..3,
+                4..5,
+                6..7,
+                8..9,
+                6..9,
+                10..11,
+                12..13,
+                14..20,
+            ]
+        };
+        let pieces = split_tokens(vec![chunk], &config, tokens);
+        let contents: Vec<&str> = pieces.iter().map(|p| p.content.as_str()).collect();
+        assert_eq!(contents, vec!["a b c", "c\nd e f", "f\ng h"]);
+    }
+
+    #[test]
+    fn test_chunk_source() {
+        let chunks = chunk_source("one\n\ntwo\nthree\n\nfour", "txt").unwrap();
+        let lines: Vec<_> = chunks.iter().map(|c| c.lines.unwrap()).collect();
+        assert_eq!(lines, vec![(1, 1), (3, 4), (6, 6)]);
+        // Offsets into a page's text aren't lines of its source
+        let chunks = chunk_source("<h1>Title</h1>\n<p>Text</p>", "html").unwrap();
+        assert!(chunks.iter().all(|c| c.lines.is_none()));
+    }
+
     #[test]
     fn test_chunk_text_empty() {
         let content = "";
@@ -436,6 +1432,47 @@ struct Bar {
         assert!(chunks[1].content.contains("struct Bar"));
     }
 
+    #[test]
+    fn test_chunk_rust_impl() {
+        let content = r#"
+impl Foo {
+    const LIMIT: usize = 10;
+
+    /// Makes a Foo.
+    fn new() -> Self {
+        Foo
+    }
+
+    fn run(&self) {}
+}
+"#;
+        let chunks = chunk_rust(content).unwrap();
+        assert_eq!(chunks.len(), 3);
+        // The summary keeps the rest of the impl and only the methods'
+        // signatures
+        assert_eq!(
+            chunks[0].content,
+            "impl Foo {\n    const LIMIT: usize = 10;\n\n    fn new() -> Self\n\n    fn run(&self)\n}"
+        );
+        assert!(chunks[1].content.starts_with("/// Makes a Foo."));
+        let metadata: serde_json::Value =
+            serde_json::from_str(chunks[1].metadata.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            metadata,
+            serde_json::json!({
+                "parent": "impl Foo",
+                "symbol": "new",
+                "signature": "fn new() -> Self",
+                "language": "rust",
+            })
+        );
+        let metadata: serde_json::Value =
+            serde_json::from_str(chunks[0].metadata.as_deref().unwrap()).unwrap();
+        assert_eq!(metadata["symbol"], "Foo");
+        assert_eq!(metadata.get("parent"), None);
+        assert_eq!(chunks[2].content, "fn run(&self) {}");
+    }
+
     #[test]
     fn test_chunk_markdown() {
         let content = r#"# Header 1
@@ -450,6 +1487,86 @@ More text.
         assert!(chunks[1].content.contains("## Header 2"));
     }
 
+    #[test]
+    fn test_chunk_html() {
+        let content = r#"<!DOCTYPE html>
+<html><head><title>Guide</title><style>p { color: red }</style></head>
+<body>
+  <h1>Guide</h1>
+  <p>Read this <b>first</b>.</p>
+  <script>var skipped = 1;</script>
+  <h2>Install</h2>
+  <ul><li>#1 Download</li><li>Run</li></ul>
+  <pre>cargo install
+  contextd</pre>
+  <h3>From <em>source</em></h3>
+  <table><tr><td>cargo</td><td>build</td></tr></table>
+  <h2>Usage</h2>
+  <p>Start the daemon.</p>
+</body></html>"#;
+        let chunks = chunk_html(content).unwrap();
+        let headers: Vec<serde_json::Value> = chunks
+            .iter()
+            .map(|c| serde_json::from_str(c.metadata.as_deref().unwrap()).unwrap())
+            .map(|m: serde_json::Value| m["headers"].clone())
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                serde_json::json!(["Guide"]),
+                serde_json::json!(["Guide", "Install"]),
+                serde_json::json!(["Guide", "Install", "From source"]),
+                serde_json::json!(["Guide", "Usage"]),
+            ]
+        );
+        assert_eq!(chunks[0].content, "# Guide\n\nRead this first.\n\n");
+        assert_eq!(
+            chunks[1].content,
+            "## Install\n\n\\#1 Download\n\nRun\n\ncargo install\n  contextd\n\n"
+        );
+        assert!(chunks[2].content.contains("cargo build"));
+        assert!(!chunks.iter().any(|c| c.content.contains("skipped")));
+    }
+
+    #[test]
+    fn test_chunk_docx() {
+        use std::io::Write;
+
+        let document = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+<w:body>
+<w:p><w:pPr><w:pStyle w:val="Title"/></w:pPr><w:r><w:t>Handbook</w:t></w:r></w:p>
+<w:p><w:r><w:t xml:space="preserve">Welcome to the </w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t>team</w:t></w:r><w:r><w:t>.</w:t></w:r></w:p>
+<w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:t>Expenses &amp; travel</w:t></w:r></w:p>
+<w:p><w:r><w:t>Book through the portal.</w:t></w:r></w:p>
+<w:p><w:pPr><w:outlineLvl w:val="1"/></w:pPr><w:r><w:t>Holidays</w:t></w:r></w:p>
+<w:p><w:r><w:t>Ask your manager.</w:t></w:r></w:p>
+</w:body>
+</w:document>"#;
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut archive = zip::ZipWriter::new(file.reopen().unwrap());
+        archive
+            .start_file(
+                "word/document.xml",
+                zip::write::SimpleFileOptions::default(),
+            )
+            .unwrap();
+        archive.write_all(document.as_bytes()).unwrap();
+        archive.finish().unwrap();
+
+        let chunks = chunk_docx(file.path()).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].content, "# Handbook\n\nWelcome to the team.\n\n");
+        assert_eq!(
+            chunks[1].content,
+            "## Expenses & travel\n\nBook through the portal.\n\n"
+        );
+        assert_eq!(
+            chunks[2].metadata.as_deref(),
+            Some(r#"{"headers":["Handbook","Holidays"]}"#)
+        );
+    }
+
     #[test]
     fn test_chunk_pdf_logic() {
         // Simulate PDF content with Form Feed characters
@@ -464,6 +1581,7 @@ More text.
                 end: start + len,
                 content: page.to_string(),
                 metadata: None,
+                lines: None,
             });
             start += len + 1;
         }
@@ -485,9 +1603,15 @@ class Greeter:
         return "Hi"
 "#;
         let chunks = chunk_python(content).unwrap();
-        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks.len(), 3);
         assert!(chunks[0].content.contains("def hello"));
         assert!(chunks[1].content.contains("class Greeter"));
+        assert!(chunks[2].content.starts_with("def greet"));
+        let metadata: serde_json::Value =
+            serde_json::from_str(chunks[2].metadata.as_deref().unwrap()).unwrap();
+        assert_eq!(metadata["parent"], "class Greeter");
+        assert_eq!(metadata["symbol"], "greet");
+        assert_eq!(metadata["signature"], "def greet(self)");
     }
 
     #[test]
@@ -504,9 +1628,10 @@ class Person {
 }
 "#;
         let chunks = chunk_javascript(content).unwrap();
-        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks.len(), 3);
         assert!(chunks[0].content.contains("function greet"));
         assert!(chunks[1].content.contains("class Person"));
+        assert!(chunks[2].content.starts_with("constructor(name)"));
     }
 
     #[test]
@@ -554,4 +1679,180 @@ func (p Person) Greet() string {
         assert!(chunks.iter().any(|c| c.content.contains("func hello")));
         assert!(chunks.iter().any(|c| c.content.contains("type Person")));
     }
+
+    /// `(symbol, parent)` of each chunk's metadata.
+    fn symbols(chunks: &[Chunk]) -> Vec<(String, Option<String>)> {
+        chunks
+            .iter()
+            .map(|c| {
+                let metadata: serde_json::Value =
+                    serde_json::from_str(c.metadata.as_deref().unwrap()).unwrap();
+                (
+                    metadata["symbol"].as_str().unwrap_or_default().to_string(),
+                    metadata["parent"].as_str().map(String::from),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunk_java() {
+        let content = r#"
+package ledger;
+
+/** Running balance */
+public class Ledger {
+    private long total;
+
+    public void deposit(long cents) {
+        total += cents;
+    }
+}
+
+enum Currency { EUR, USD }
+"#;
+        let chunks = chunk_java(content).unwrap();
+        assert_eq!(
+            symbols(&chunks),
+            vec![
+                ("Ledger".to_string(), None),
+                (
+                    "deposit".to_string(),
+                    Some("public class Ledger".to_string())
+                ),
+                ("Currency".to_string(), None),
+            ]
+        );
+        assert!(chunks[0].content.starts_with("/** Running balance */"));
+        assert!(chunks[0]
+            .content
+            .contains("public void deposit(long cents)\n}"));
+    }
+
+    #[test]
+    fn test_chunk_c() {
+        let content = r#"
+struct point { int x; int y; };
+
+// Twice the point
+static struct point *twice(struct point *p) {
+    p->x *= 2;
+    return p;
+}
+
+#ifndef NDEBUG
+void dump(void) {}
+#endif
+"#;
+        let chunks = chunk_c(content).unwrap();
+        let names: Vec<_> = symbols(&chunks).into_iter().map(|(s, _)| s).collect();
+        assert_eq!(names, vec!["point", "twice", "dump"]);
+        assert!(chunks[1].content.starts_with("// Twice the point"));
+    }
+
+    #[test]
+    fn test_chunk_cpp() {
+        let content = r#"
+namespace shapes {
+
+class Circle {
+public:
+    double area() const { return 3.14 * r * r; }
+    double r;
+};
+
+template <typename T>
+T twice(T value) { return value * 2; }
+
+}
+
+double shapes::Circle::diameter() const { return 2 * r; }
+"#;
+        let chunks = chunk_cpp(content).unwrap();
+        assert_eq!(
+            symbols(&chunks),
+            vec![
+                ("shapes".to_string(), None),
+                ("Circle".to_string(), Some("namespace shapes".to_string())),
+                ("area".to_string(), Some("class Circle".to_string())),
+                ("twice".to_string(), Some("namespace shapes".to_string())),
+                ("diameter".to_string(), None),
+            ]
+        );
+        assert!(chunks[3].content.starts_with("template <typename T>"));
+    }
+
+    #[test]
+    fn test_chunk_csharp() {
+        let content = r#"
+namespace Shop;
+
+public class Cart
+{
+    public Cart() { }
+
+    public decimal Total() => 0m;
+}
+"#;
+        let chunks = chunk_csharp(content).unwrap();
+        assert_eq!(
+            symbols(&chunks),
+            vec![
+                ("Cart".to_string(), None),
+                ("Cart".to_string(), Some("public class Cart".to_string())),
+                ("Total".to_string(), Some("public class Cart".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunk_ruby() {
+        let content = r#"
+module Shop
+  class Cart
+    # Sum of the items
+    def total
+      @items.sum
+    end
+  end
+end
+
+def self.helper; end
+"#;
+        let chunks = chunk_ruby(content).unwrap();
+        assert_eq!(
+            symbols(&chunks),
+            vec![
+                ("Shop".to_string(), None),
+                ("Cart".to_string(), Some("module Shop".to_string())),
+                ("total".to_string(), Some("class Cart".to_string())),
+                ("helper".to_string(), None),
+            ]
+        );
+        assert!(chunks[2].content.starts_with("# Sum of the items"));
+    }
+
+    #[test]
+    fn test_chunk_php() {
+        let content = r#"<?php
+trait Greets
+{
+    public function greet(): string
+    {
+        return 'hi';
+    }
+}
+
+function helper() {}
+"#;
+        let chunks = chunk_php(content).unwrap();
+        assert_eq!(
+            symbols(&chunks),
+            vec![
+                ("Greets".to_string(), None),
+                ("greet".to_string(), Some("trait Greets".to_string())),
+                ("helper".to_string(), None),
+            ]
+        );
+    }
 }
@@ -1,19 +1,218 @@
-use anyhow::Result;
+use ort::execution_providers::CPUExecutionProvider;
 use ort::session::{builder::GraphOptimizationLevel, Session};
 use ort::value::Value;
+use serde::Deserialize;
+use std::ops::Range;
 use std::sync::Mutex;
+use thiserror::Error;
 use tokenizers::Tokenizer;
 
+use super::chunker;
+use super::remote::RemoteBackend;
 use crate::config::StorageConfig;
+use crate::faults;
 
+type Result<T, E = EmbedError> = std::result::Result<T, E>;
+
+/// `model_type` selecting [`Embedder::mock`] instead of an ONNX model, in
+/// builds with the `mock-embedder` feature.
+pub const MOCK_MODEL: &str = "mock";
+
+/// Length of [`MOCK_MODEL`] vectors, matching the default model.
+const MOCK_DIMENSIONS: usize = 384;
+
+/// `[embeddings]` config: where vectors come from. Local ONNX models need no
+/// network; the remote backends send text to an embeddings API and use
+/// `model_type` as the name of the model there.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EmbeddingsConfig {
+    pub backend: BackendKind,
+    /// Base URL of the API (default: `https://api.openai.com/v1` for
+    /// `openai`, `http://localhost:11434` for `ollama`)
+    pub url: Option<String>,
+    /// Sent as `Authorization: Bearer <key>`
+    pub api_key: Option<String>,
+    /// Texts sent in one request
+    pub batch_size: usize,
+    /// Times a request is retried after a connection error, rate limit or
+    /// server error
+    pub max_retries: u32,
+    pub timeout_secs: u64,
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        Self {
+            backend: BackendKind::Onnx,
+            url: None,
+            api_key: None,
+            batch_size: 32,
+            max_retries: 3,
+            timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// A model in `model_path`, run in-process
+    #[default]
+    Onnx,
+    /// An OpenAI-compatible `/embeddings` endpoint
+    OpenAi,
+    /// Ollama's `/api/embed`
+    Ollama,
+}
+
+/// Why a model couldn't be loaded or run. Only [`EmbedError::Remote`] may
+/// pass on its own; retrying the same text otherwise fails the same way.
+#[derive(Debug, Error)]
+pub enum EmbedError {
+    #[error("Failed to load the tokenizer: {0}")]
+    Tokenizer(String),
+    #[error("Failed to tokenize: {0}")]
+    Tokenize(String),
+    #[error("Unknown optimization_level '{0}' (expected disable, level1, level2 or level3)")]
+    OptimizationLevel(String),
+    #[error(transparent)]
+    Runtime(#[from] ort::Error),
+    /// Failed on purpose by `--fault-injection`
+    #[error("Injected embedding failure")]
+    Injected,
+    #[error("model_type \"mock\" needs contextd built with the mock-embedder feature")]
+    MockDisabled,
+    /// The embeddings API failed or couldn't be reached, after retries
+    #[error("Embedding request failed: {0}")]
+    Remote(String),
+    #[error("{model} returned {got}-dimension vectors; the index stores {expected}")]
+    Dimensions {
+        model: String,
+        got: usize,
+        expected: usize,
+    },
+}
+
+/// A way of turning text into vectors.
+pub trait EmbeddingBackend: Send + Sync {
+    /// Length of the vectors this backend produces.
+    fn dimensions(&self) -> usize;
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>>;
+
+    /// Embed `texts`, together where the backend can. Returns one result
+    /// per text, in order.
+    fn embed_batch(&self, texts: &[&str]) -> Vec<Result<Vec<f32>>> {
+        texts.iter().map(|text| self.embed(text)).collect()
+    }
+
+    /// Byte ranges of the tokens the model sees in `text`, if the backend
+    /// has its tokenizer.
+    fn tokens(&self, _text: &str) -> Option<Vec<Range<usize>>> {
+        None
+    }
+}
+
+/// The embedding model of a collection, on whichever backend runs it.
 pub struct Embedder {
-    tokenizer: Tokenizer,
+    backend: Box<dyn EmbeddingBackend>,
+}
+
+impl Embedder {
+    /// Load `config`'s model on the backend `embeddings` selects.
+    pub fn new(config: &StorageConfig, embeddings: &EmbeddingsConfig) -> Result<Self> {
+        if config.model_type == MOCK_MODEL {
+            return if cfg!(feature = "mock-embedder") {
+                Ok(Self::mock(MOCK_DIMENSIONS))
+            } else {
+                Err(EmbedError::MockDisabled)
+            };
+        }
+        Ok(match embeddings.backend {
+            BackendKind::Onnx => Self::with_backend(OnnxBackend::new(config)?),
+            BackendKind::OpenAi | BackendKind::Ollama => {
+                Self::with_backend(RemoteBackend::new(embeddings, &config.model_type)?)
+            }
+        })
+    }
+
+    pub fn with_backend(backend: impl EmbeddingBackend + 'static) -> Self {
+        Self {
+            backend: Box::new(backend),
+        }
+    }
+
+    /// An embedder that needs no model files, for tests. Each word is hashed
+    /// into one of `dimensions` buckets, so texts sharing words are similar
+    /// and the same text always embeds the same way.
+    pub fn mock(dimensions: usize) -> Self {
+        Self::with_backend(MockBackend { dimensions })
+    }
+
+    /// Length of the vectors this model produces.
+    pub fn dimensions(&self) -> usize {
+        self.backend.dimensions()
+    }
+
+    /// Byte ranges of the tokens the model sees in `text`, leaving out special
+    /// tokens. Without the model's tokenizer, words and punctuation count.
+    pub fn tokens(&self, text: &str) -> Vec<Range<usize>> {
+        self.backend
+            .tokens(text)
+            .unwrap_or_else(|| chunker::approximate_tokens(text))
+    }
+
+    /// Run a throwaway embedding so tokenizer and ORT session setup costs, or
+    /// a remote model's loading, are paid before the first real query.
+    /// Returns how long it took.
+    pub fn warm_up(&self) -> Result<std::time::Duration> {
+        let start = std::time::Instant::now();
+        self.embed("contextd warm-up")?;
+        Ok(start.elapsed())
+    }
+
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        if faults::embedding_fails() {
+            return Err(EmbedError::Injected);
+        }
+        self.backend.embed(text)
+    }
+
+    /// Embed `texts`, in as few requests as the backend allows. Returns one
+    /// result per text, so a text that fails doesn't fail the others.
+    pub fn embed_batch(&self, texts: &[&str]) -> Vec<Result<Vec<f32>>> {
+        let injected: Vec<bool> = texts.iter().map(|_| faults::embedding_fails()).collect();
+        let kept: Vec<&str> = texts
+            .iter()
+            .zip(&injected)
+            .filter(|(_, &injected)| !injected)
+            .map(|(text, _)| *text)
+            .collect();
+        let mut embedded = self.backend.embed_batch(&kept).into_iter();
+        injected
+            .into_iter()
+            .map(|injected| match injected {
+                true => Err(EmbedError::Injected),
+                false => embedded
+                    .next()
+                    .unwrap_or_else(|| Err(EmbedError::Remote("Missing embedding".to_string()))),
+            })
+            .collect()
+    }
+}
+
+/// A model in `model_path`, run in-process with ONNX Runtime.
+struct OnnxBackend {
+    tokenizer: Box<Tokenizer>,
+    /// The tokenizer without truncation or padding, to count every token
+    counter: Box<Tokenizer>,
     session: Mutex<Session>,
     hidden_size: usize,
 }
 
-impl Embedder {
-    pub fn new(config: &StorageConfig) -> Result<Self> {
+impl OnnxBackend {
+    fn new(config: &StorageConfig) -> Result<Self> {
         let model_dir = &config.model_path;
         let model_type = &config.model_type;
 
@@ -28,26 +227,44 @@ impl Embedder {
         let tokenizer_path = model_dir.join("tokenizer.json");
         let model_path = model_dir.join("model.onnx");
 
-        let tokenizer = Tokenizer::from_file(tokenizer_path).map_err(|e| anyhow::anyhow!(e))?;
+        let tokenizer = Tokenizer::from_file(tokenizer_path)
+            .map_err(|e| EmbedError::Tokenizer(e.to_string()))?;
+        let mut counter = tokenizer.clone();
+        counter
+            .with_truncation(None)
+            .map_err(|e| EmbedError::Tokenizer(e.to_string()))?
+            .with_padding(None);
 
         let session = Session::builder()?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_intra_threads(4)?
+            .with_optimization_level(parse_optimization_level(&config.optimization_level)?)?
+            .with_intra_threads(config.intra_threads)?
+            .with_inter_threads(config.inter_threads)?
+            .with_memory_pattern(config.memory_pattern)?
+            .with_execution_providers([CPUExecutionProvider::default()
+                .with_arena_allocator(config.memory_arena)
+                .build()])?
             .commit_from_file(model_path)?;
 
         Ok(Self {
-            tokenizer,
+            tokenizer: Box::new(tokenizer),
+            counter: Box::new(counter),
             session: Mutex::new(session),
             hidden_size,
         })
     }
+}
 
-    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+impl EmbeddingBackend for OnnxBackend {
+    fn dimensions(&self) -> usize {
+        self.hidden_size
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
         // Tokenize
         let encoding = self
             .tokenizer
             .encode(text, true)
-            .map_err(|e| anyhow::anyhow!(e))?;
+            .map_err(|e| EmbedError::Tokenize(e.to_string()))?;
 
         let input_ids: Vec<i64> = encoding.get_ids().iter().map(|&x| x as i64).collect();
         let attention_mask: Vec<i64> = encoding
@@ -118,6 +335,63 @@ impl Embedder {
 
         Ok(pooled)
     }
+
+    fn tokens(&self, text: &str) -> Option<Vec<Range<usize>>> {
+        let encoding = self.counter.encode(text, false).ok()?;
+        Some(encoding.get_offsets().iter().map(|&(s, e)| s..e).collect())
+    }
+}
+
+/// No model: see [`Embedder::mock`]
+struct MockBackend {
+    dimensions: usize,
+}
+
+impl EmbeddingBackend for MockBackend {
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        Ok(hash_words(text, self.dimensions))
+    }
+}
+
+/// Sum a signed FNV-1a hash of each lowercased word into `dimensions`
+/// buckets and normalize.
+fn hash_words(text: &str, dimensions: usize) -> Vec<f32> {
+    let mut vector = vec![0.0f32; dimensions];
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        let hash = word
+            .to_lowercase()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+                (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+            });
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(hash % dimensions as u64) as usize] += sign;
+    }
+    let norm: f32 = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 1e-6 {
+        for val in &mut vector {
+            *val /= norm;
+        }
+    }
+    vector
+}
+
+/// Map the `optimization_level` config value to ORT's enum.
+pub fn parse_optimization_level(level: &str) -> Result<GraphOptimizationLevel> {
+    match level.to_lowercase().as_str() {
+        "disable" | "none" => Ok(GraphOptimizationLevel::Disable),
+        "level1" | "basic" => Ok(GraphOptimizationLevel::Level1),
+        "level2" | "extended" => Ok(GraphOptimizationLevel::Level2),
+        "level3" | "all" => Ok(GraphOptimizationLevel::Level3),
+        other => Err(EmbedError::OptimizationLevel(other.to_string())),
+    }
 }
 
 #[cfg(test)]
@@ -132,8 +406,9 @@ mod tests {
             db_path: PathBuf::from("test.db"),
             model_path: PathBuf::from("non_existent_path"),
             model_type: "all-minilm-l6-v2".to_string(),
+            ..Default::default()
         };
-        let result = Embedder::new(&config);
+        let result = Embedder::new(&config, &EmbeddingsConfig::default());
         assert!(result.is_err());
     }
 
@@ -148,12 +423,50 @@ mod tests {
             db_path: PathBuf::from("test.db"),
             model_path: PathBuf::from(model_dir),
             model_type: "all-minilm-l6-v2".to_string(),
+            ..Default::default()
         };
-        let embedder = Embedder::new(&config).expect("Failed to create embedder");
+        let embedder = Embedder::new(&config, &EmbeddingsConfig::default())
+            .expect("Failed to create embedder");
         let vec = embedder.embed("hello world").expect("Failed to embed");
         assert_eq!(vec.len(), 384);
     }
 
+    #[test]
+    fn test_mock_embedder() {
+        let config = StorageConfig {
+            model_path: PathBuf::from("non_existent_path"),
+            model_type: MOCK_MODEL.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Embedder::new(&config, &EmbeddingsConfig::default()).is_ok(),
+            cfg!(feature = "mock-embedder")
+        );
+
+        let embedder = Embedder::mock(384);
+        let a = embedder.embed("parse the config file").unwrap();
+        assert_eq!(a.len(), 384);
+        assert_eq!(a, embedder.embed("Parse the CONFIG file").unwrap());
+
+        let cosine = |x: &[f32], y: &[f32]| x.iter().zip(y).map(|(a, b)| a * b).sum::<f32>();
+        let near = embedder.embed("parse config").unwrap();
+        let far = embedder.embed("render widgets quickly").unwrap();
+        assert!(cosine(&a, &near) > cosine(&a, &far));
+    }
+
+    #[test]
+    fn test_parse_optimization_level() {
+        assert!(matches!(
+            parse_optimization_level("level1"),
+            Ok(GraphOptimizationLevel::Level1)
+        ));
+        assert!(matches!(
+            parse_optimization_level("Disable"),
+            Ok(GraphOptimizationLevel::Disable)
+        ));
+        assert!(parse_optimization_level("turbo").is_err());
+    }
+
     #[test]
     fn test_model_dimension_selection() {
         // Test that hidden_size is correctly selected based on model_type
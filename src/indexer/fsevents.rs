@@ -0,0 +1,255 @@
+// A file watcher for macOS on FSEvents directly. notify's own creates its
+// stream with no latency and no way to exclude paths, so a watched home
+// directory reports every write to ~/Library the moment it happens.
+
+use fsevent_sys as fs;
+use fsevent_sys::core_foundation as cf;
+use notify_debouncer_mini::notify::event::{EventKind, Flag};
+use notify_debouncer_mini::notify::{
+    self, Event, EventHandler, RecursiveMode, Watcher, WatcherKind,
+};
+use std::ffi::{c_void, CStr, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Most paths a stream can exclude.
+const MAX_EXCLUSIONS: usize = 8;
+
+/// Default latency, until [`FsEventsWatcher::configure`] sets one.
+const DEFAULT_LATENCY: Duration = Duration::from_millis(500);
+
+extern "C" {
+    fn CFRunLoopIsWaiting(runloop: cf::CFRunLoopRef) -> cf::Boolean;
+}
+
+/// A Core Foundation reference moved to or from the run loop thread, which
+/// Core Foundation allows.
+struct SendRef(cf::CFRef);
+
+unsafe impl Send for SendRef {}
+
+/// Watches its roots recursively with one FSEvents stream, which delivers
+/// changes in batches once `latency` has passed and skips the excluded
+/// directories altogether.
+pub struct FsEventsWatcher {
+    handler: Arc<Mutex<dyn EventHandler>>,
+    roots: Vec<PathBuf>,
+    latency: Duration,
+    exclusions: Vec<PathBuf>,
+    /// The stream's run loop and the thread running it
+    running: Option<(SendRef, JoinHandle<()>)>,
+}
+
+impl FsEventsWatcher {
+    /// Set the stream's latency and the directories it ignores, of which
+    /// FSEvents takes the first eight. Restarts the stream if it's running.
+    pub fn configure(&mut self, latency: Duration, exclusions: Vec<PathBuf>) -> notify::Result<()> {
+        if exclusions.len() > MAX_EXCLUSIONS {
+            log::debug!(
+                "FSEvents excludes at most {} paths, not {:?}",
+                MAX_EXCLUSIONS,
+                &exclusions[MAX_EXCLUSIONS..]
+            );
+        }
+        self.latency = latency;
+        self.exclusions = exclusions;
+        self.restart()
+    }
+
+    fn restart(&mut self) -> notify::Result<()> {
+        self.stop();
+        if self.roots.is_empty() {
+            return Ok(());
+        }
+        self.start()
+    }
+
+    fn start(&mut self) -> notify::Result<()> {
+        let paths = cf_paths(&self.roots)?;
+        let exclusions = cf_paths(&self.exclusions[..self.exclusions.len().min(MAX_EXCLUSIONS)]);
+        // Owned by the stream from here on and freed when it's released
+        let handler = Box::into_raw(Box::new(self.handler.clone()));
+        let context = fs::FSEventStreamContext {
+            version: 0,
+            info: handler as *mut c_void,
+            retain: None,
+            release: Some(release_handler),
+            copy_description: None,
+        };
+        let stream = unsafe {
+            let stream = fs::FSEventStreamCreate(
+                cf::kCFAllocatorDefault,
+                callback,
+                &context,
+                paths,
+                fs::kFSEventStreamEventIdSinceNow,
+                self.latency.as_secs_f64(),
+                fs::kFSEventStreamCreateFlagFileEvents,
+            );
+            cf::CFRelease(paths);
+            match exclusions {
+                Ok(exclusions) => {
+                    fs::FSEventStreamSetExclusionPaths(stream, exclusions);
+                    cf::CFRelease(exclusions);
+                }
+                Err(e) => log::warn!("Failed to exclude paths from the file watcher: {}", e),
+            }
+            SendRef(stream)
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("contextd fsevents".to_string())
+            .spawn(move || {
+                let stream = stream;
+                unsafe {
+                    let runloop = cf::CFRunLoopGetCurrent();
+                    fs::FSEventStreamScheduleWithRunLoop(
+                        stream.0,
+                        runloop,
+                        cf::kCFRunLoopDefaultMode,
+                    );
+                    fs::FSEventStreamStart(stream.0);
+                    let _ = tx.send(SendRef(runloop));
+                    // Until `stop` stops the run loop
+                    cf::CFRunLoopRun();
+                    fs::FSEventStreamStop(stream.0);
+                    fs::FSEventStreamInvalidate(stream.0);
+                    fs::FSEventStreamRelease(stream.0);
+                }
+            })?;
+        let runloop = rx
+            .recv()
+            .map_err(|_| notify::Error::generic("The FSEvents thread exited"))?;
+        self.running = Some((runloop, thread));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        let Some((runloop, thread)) = self.running.take() else {
+            return;
+        };
+        unsafe {
+            // Stopping a run loop before it waits for events is lost
+            while CFRunLoopIsWaiting(runloop.0) == 0 {
+                thread::yield_now();
+            }
+            cf::CFRunLoopStop(runloop.0);
+        }
+        let _ = thread.join();
+    }
+}
+
+/// A CFArray of `paths`, to be released by the caller.
+fn cf_paths(paths: &[PathBuf]) -> notify::Result<cf::CFMutableArrayRef> {
+    unsafe {
+        let array =
+            cf::CFArrayCreateMutable(cf::kCFAllocatorDefault, 0, &cf::kCFTypeArrayCallBacks);
+        for path in paths {
+            let mut err: cf::CFErrorRef = std::ptr::null_mut();
+            let cf_path = path.to_str().map_or(std::ptr::null_mut(), |p| {
+                cf::str_path_to_cfstring_ref(p, &mut err)
+            });
+            if cf_path.is_null() {
+                if !err.is_null() {
+                    cf::CFRelease(err as cf::CFRef);
+                }
+                cf::CFRelease(array);
+                return Err(notify::Error::path_not_found().add_path(path.clone()));
+            }
+            cf::CFArrayAppendValue(array, cf_path);
+            cf::CFRelease(cf_path);
+        }
+        Ok(array)
+    }
+}
+
+extern "C" fn release_handler(info: *const c_void) {
+    // Only called once, when the stream is deallocated
+    unsafe { drop(Box::from_raw(info as *mut Arc<Mutex<dyn EventHandler>>)) }
+}
+
+extern "C" fn callback(
+    _stream: fs::FSEventStreamRef,
+    info: *mut c_void,
+    num_events: usize,
+    event_paths: *mut c_void,
+    event_flags: *const fs::FSEventStreamEventFlags,
+    _event_ids: *const fs::FSEventStreamEventId,
+) {
+    let handler = unsafe { &*(info as *const Arc<Mutex<dyn EventHandler>>) };
+    let event_paths = event_paths as *const *const std::ffi::c_char;
+    for i in 0..num_events {
+        let (path, flags) = unsafe {
+            let path = CStr::from_ptr(*event_paths.add(i));
+            (
+                PathBuf::from(OsStr::from_bytes(path.to_bytes())),
+                *event_flags.add(i),
+            )
+        };
+        if flags & fs::kFSEventStreamEventFlagHistoryDone != 0 {
+            continue;
+        }
+        // The daemon reads what changed from the file system, so the kind
+        // of change doesn't matter
+        let mut event = Event::new(EventKind::Any).add_path(path);
+        let lost = fs::kFSEventStreamEventFlagMustScanSubDirs
+            | fs::kFSEventStreamEventFlagUserDropped
+            | fs::kFSEventStreamEventFlagKernelDropped;
+        if flags & lost != 0 {
+            event = event.set_flag(Flag::Rescan);
+        }
+        if let Ok(mut handler) = handler.lock() {
+            handler.handle_event(Ok(event));
+        }
+    }
+}
+
+impl Watcher for FsEventsWatcher {
+    fn new<F: EventHandler>(event_handler: F, _config: notify::Config) -> notify::Result<Self> {
+        Ok(Self {
+            handler: Arc::new(Mutex::new(event_handler)),
+            roots: Vec::new(),
+            latency: DEFAULT_LATENCY,
+            exclusions: Vec::new(),
+            running: None,
+        })
+    }
+
+    /// Watch `path` and everything below it; FSEvents streams are always
+    /// recursive.
+    fn watch(&mut self, path: &Path, _recursive_mode: RecursiveMode) -> notify::Result<()> {
+        if !path.exists() {
+            return Err(notify::Error::path_not_found().add_path(path.to_path_buf()));
+        }
+        self.roots.push(path.to_path_buf());
+        let result = self.restart();
+        if result.is_err() {
+            self.roots.pop();
+            let _ = self.restart();
+        }
+        result
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        let count = self.roots.len();
+        self.roots.retain(|root| root != path);
+        if self.roots.len() == count {
+            return Err(notify::Error::watch_not_found().add_path(path.to_path_buf()));
+        }
+        self.restart()
+    }
+
+    fn kind() -> WatcherKind {
+        WatcherKind::Fsevent
+    }
+}
+
+impl Drop for FsEventsWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
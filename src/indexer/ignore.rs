@@ -1,62 +1,199 @@
 use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
+use crate::config::WatchConfig;
+use crate::workspace;
+
+/// The rule that excludes a path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IgnoreRule {
+    /// Ignore file the pattern came from; `None` for built-in rules
+    pub source: Option<PathBuf>,
+    pub pattern: String,
+}
+
+impl fmt::Display for IgnoreRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "pattern `{}` in {}", self.pattern, source.display()),
+            None => write!(f, "built-in rule `{}`", self.pattern),
+        }
+    }
+}
+
+/// Per-directory ignore files, read in this order (later rules win).
+const IGNORE_FILES: &[&str] = &[".contextignore", ".gitignore"];
+
+/// The user-level ignore file applied to every watch root, like git's
+/// `core.excludesFile`: `$XDG_CONFIG_HOME/contextd/ignore`, falling back to
+/// `~/.config/contextd/ignore`.
+pub fn global_ignore_path() -> Option<PathBuf> {
+    Some(crate::config::user_config_dir()?.join("ignore"))
+}
+
+/// Applies `.contextignore` and `.gitignore` files found anywhere below a
+/// watch root, with gitignore precedence: rules in deeper directories
+/// override those above them, and nothing below an ignored directory can be
+/// re-included. The global ignore file has the lowest precedence.
 pub struct IgnoreChecker {
-    gitignore: Gitignore,
     root: PathBuf,
+    /// Matchers by directory relative to the root, loaded on first use
+    matchers: Mutex<HashMap<PathBuf, Gitignore>>,
+    global: Gitignore,
+    /// Skip macOS system directories, see [`workspace::macos_exclusion`]
+    system_exclusions: bool,
+    /// Skip [`workspace::DEFAULT_IGNORES`] directories
+    default_ignores: bool,
 }
 
 impl IgnoreChecker {
     pub fn new(root: &Path) -> Self {
-        let mut builder = GitignoreBuilder::new(root);
+        Self::with_global_ignore(root, global_ignore_path().as_deref())
+    }
 
-        // Add .contextignore
-        if let Some(err) = builder.add(root.join(".contextignore")) {
-            if !err.is_io() {
-                // Ignore IO errors (missing file)
-                eprintln!("Error loading .contextignore: {}", err);
+    pub fn with_global_ignore(root: &Path, global: Option<&Path>) -> Self {
+        let mut builder = GitignoreBuilder::new(root);
+        if let Some(global) = global {
+            if let Some(err) = builder.add(global) {
+                if !err.is_io() {
+                    log::warn!("Error loading {:?}: {}", global, err);
+                }
             }
         }
+        let checker = Self {
+            root: root.to_path_buf(),
+            matchers: Mutex::new(HashMap::new()),
+            global: builder.build().unwrap_or_else(|_| Gitignore::empty()),
+            system_exclusions: false,
+            default_ignores: false,
+        };
+        // Surface errors in the root ignore files at startup
+        checker.matcher(Path::new(""));
+        checker
+    }
+
+    /// Also skip the macOS system directories `watch` excludes.
+    pub fn with_system_exclusions(mut self, watch: &WatchConfig) -> Self {
+        self.system_exclusions = cfg!(target_os = "macos") && watch.system_exclusions;
+        self
+    }
+
+    /// Also skip dependency directories, with `watch`'s `default_ignores`.
+    pub fn with_default_ignores(mut self, watch: &WatchConfig) -> Self {
+        self.default_ignores = watch.default_ignores;
+        self
+    }
+
+    fn is_default_ignored(&self, name: &str) -> bool {
+        self.default_ignores && workspace::DEFAULT_IGNORES.contains(&name)
+    }
 
-        // Add .gitignore
-        if let Some(err) = builder.add(root.join(".gitignore")) {
-            if !err.is_io() {
-                eprintln!("Error loading .gitignore: {}", err);
+    fn matcher(&self, dir: &Path) -> Gitignore {
+        let mut matchers = self.matchers.lock().unwrap();
+        matchers
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let abs_dir = self.root.join(dir);
+                let mut builder = GitignoreBuilder::new(&abs_dir);
+                for name in IGNORE_FILES {
+                    if let Some(err) = builder.add(abs_dir.join(name)) {
+                        // IO errors just mean there is no such file
+                        if !err.is_io() {
+                            log::warn!("Error loading {:?}: {}", abs_dir.join(name), err);
+                        }
+                    }
+                }
+                builder.build().unwrap_or_else(|_| Gitignore::empty())
+            })
+            .clone()
+    }
+
+    /// The rule deciding `relative` (relative to the root): the deepest
+    /// ignore file with a matching pattern wins, a negated one re-including,
+    /// and the global file decides paths no other file mentions.
+    fn match_in_tree(&self, relative: &Path, is_dir: bool) -> Option<IgnoreRule> {
+        let rule = |glob: &ignore::gitignore::Glob| IgnoreRule {
+            source: glob.from().map(Path::to_path_buf),
+            pattern: glob.original().to_string(),
+        };
+        let parent = relative.parent().unwrap_or(Path::new(""));
+        for dir in parent.ancestors() {
+            let within = relative.strip_prefix(dir).unwrap_or(relative);
+            match self.matcher(dir).matched(within, is_dir) {
+                ignore::Match::Ignore(glob) => return Some(rule(glob)),
+                ignore::Match::Whitelist(_) => return None,
+                ignore::Match::None => {}
             }
         }
-
-        Self {
-            gitignore: builder.build().unwrap(),
-            root: root.to_path_buf(),
+        match self.global.matched(relative, is_dir) {
+            ignore::Match::Ignore(glob) => Some(rule(glob)),
+            _ => None,
         }
     }
 
     pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
-        let relative_path = path.strip_prefix(&self.root).unwrap_or(path);
+        self.matched_rule(path, is_dir).is_some()
+    }
 
-        // Check the path itself
-        if self.gitignore.matched(relative_path, is_dir).is_ignore() {
-            return true;
+    /// The rule excluding `path`: one excluding any of its parent
+    /// directories, outermost first, or else one matching the path itself.
+    pub fn matched_rule(&self, path: &Path, is_dir: bool) -> Option<IgnoreRule> {
+        let relative_path = path.strip_prefix(&self.root).unwrap_or(path);
+        if self.system_exclusions {
+            let home = workspace::home_dir();
+            let absolute = self.root.join(relative_path);
+            if let Some(pattern) =
+                workspace::macos_exclusion(&self.root, &absolute, home.as_deref())
+            {
+                return Some(IgnoreRule {
+                    source: None,
+                    pattern,
+                });
+            }
         }
 
-        // Check parents
-        for parent in relative_path.ancestors() {
-            if parent == Path::new("") {
-                continue;
+        let mut parents: Vec<&Path> = relative_path
+            .ancestors()
+            .skip(1)
+            .filter(|p| *p != Path::new(""))
+            .collect();
+        parents.reverse();
+        for parent in parents {
+            // Dependency directories are skipped even without an ignore rule
+            let is_dependency_dir = parent
+                .file_name()
+                .is_some_and(|n| self.is_default_ignored(&n.to_string_lossy()));
+            if is_dependency_dir {
+                return Some(IgnoreRule {
+                    source: None,
+                    pattern: parent.file_name()?.to_string_lossy().to_string(),
+                });
             }
-            if self.gitignore.matched(parent, true).is_ignore() {
-                return true;
+            if let Some(matched) = self.match_in_tree(parent, true) {
+                return Some(matched);
             }
         }
 
-        false
+        if is_dir {
+            let name = relative_path.file_name().map(|n| n.to_string_lossy());
+            if let Some(name) = name.filter(|n| self.is_default_ignored(n)) {
+                return Some(IgnoreRule {
+                    source: None,
+                    pattern: name.to_string(),
+                });
+            }
+        }
+        self.match_in_tree(relative_path, is_dir)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::io::Write;
     use tempfile::tempdir;
 
@@ -80,5 +217,86 @@ mod tests {
         // Test allowed files
         assert!(!checker.is_ignored(&root.join("test.txt"), false));
         assert!(!checker.is_ignored(&root.join("src/main.rs"), false));
+
+        // Built-in dependency directories need no rule, once turned on
+        assert!(!checker.is_ignored(&root.join("app/__pycache__/mod.pyc"), false));
+        let watch = WatchConfig {
+            default_ignores: true,
+            ..Default::default()
+        };
+        let checker = IgnoreChecker::new(root).with_default_ignores(&watch);
+        assert!(checker.is_ignored(&root.join("app/__pycache__/mod.pyc"), false));
+        assert!(!checker.is_ignored(&root.join("venv.md"), false));
+    }
+
+    #[test]
+    fn test_matched_rule() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "build/\n").unwrap();
+        let watch = WatchConfig {
+            default_ignores: true,
+            ..Default::default()
+        };
+        let checker = IgnoreChecker::new(root).with_default_ignores(&watch);
+
+        let rule = checker
+            .matched_rule(&root.join("build/out.js"), false)
+            .unwrap();
+        assert_eq!(rule.pattern, "build/");
+        assert_eq!(rule.source, Some(root.join(".gitignore")));
+
+        let rule = checker
+            .matched_rule(&root.join("web/node_modules/x/index.js"), false)
+            .unwrap();
+        assert_eq!(rule.to_string(), "built-in rule `node_modules`");
+        assert!(checker
+            .matched_rule(&root.join("src/lib.rs"), false)
+            .is_none());
+    }
+
+    #[test]
+    fn test_global_ignore() {
+        let dir = tempdir().unwrap();
+        let global = dir.path().join("global-ignore");
+        fs::write(&global, "target/\n*.lock\n").unwrap();
+        let root = dir.path().join("project");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join(".contextignore"), "!Cargo.lock\n").unwrap();
+        let checker = IgnoreChecker::with_global_ignore(&root, Some(&global));
+
+        let rule = checker
+            .matched_rule(&root.join("target/debug/app.d"), false)
+            .unwrap();
+        assert_eq!(rule.source, Some(global.clone()));
+        assert!(checker.is_ignored(&root.join("yarn.lock"), false));
+        // Project ignore files take precedence
+        assert!(!checker.is_ignored(&root.join("Cargo.lock"), false));
+        assert!(!checker.is_ignored(&root.join("src/main.rs"), false));
+    }
+
+    #[test]
+    fn test_nested_contextignore() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join(".contextignore"), "*.log\n").unwrap();
+        fs::create_dir_all(root.join("vendor/lib")).unwrap();
+        fs::write(root.join("vendor/lib/.contextignore"), "*\n").unwrap();
+        fs::create_dir_all(root.join("tools")).unwrap();
+        fs::write(root.join("tools/.contextignore"), "!keep.log\ngen/\n").unwrap();
+        let checker = IgnoreChecker::new(root);
+
+        // A vendored subtree excludes itself
+        let rule = checker
+            .matched_rule(&root.join("vendor/lib/src/a.rs"), false)
+            .unwrap();
+        assert_eq!(rule.source, Some(root.join("vendor/lib/.contextignore")));
+        assert!(!checker.is_ignored(&root.join("vendor/README.md"), false));
+
+        // Deeper files override the root, and apply only below themselves
+        assert!(!checker.is_ignored(&root.join("tools/keep.log"), false));
+        assert!(checker.is_ignored(&root.join("tools/other.log"), false));
+        assert!(checker.is_ignored(&root.join("tools/gen/out.rs"), false));
+        assert!(!checker.is_ignored(&root.join("gen/out.rs"), false));
     }
 }
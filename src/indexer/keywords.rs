@@ -0,0 +1,505 @@
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Number of TF-IDF keywords kept per chunk.
+pub const KEYWORDS_PER_CHUNK: usize = 5;
+
+/// Words that carry no meaning on their own: English filler plus common
+/// keywords from the languages we chunk.
+const STOPWORDS: &[&str] = &[
+    "the",
+    "and",
+    "for",
+    "are",
+    "but",
+    "not",
+    "you",
+    "all",
+    "any",
+    "can",
+    "has",
+    "had",
+    "was",
+    "one",
+    "our",
+    "out",
+    "use",
+    "this",
+    "that",
+    "with",
+    "from",
+    "have",
+    "will",
+    "into",
+    "when",
+    "then",
+    "than",
+    "they",
+    "them",
+    "there",
+    "their",
+    "what",
+    "which",
+    "while",
+    "where",
+    "each",
+    "only",
+    "also",
+    "some",
+    "such",
+    "more",
+    "most",
+    "other",
+    "over",
+    "should",
+    "would",
+    "could",
+    "been",
+    "being",
+    "does",
+    "just",
+    "here",
+    "about",
+    "after",
+    "before",
+    "these",
+    "those",
+    "via",
+    "fn",
+    "let",
+    "mut",
+    "pub",
+    "mod",
+    "impl",
+    "self",
+    "struct",
+    "enum",
+    "trait",
+    "type",
+    "match",
+    "return",
+    "true",
+    "false",
+    "none",
+    "string",
+    "str",
+    "vec",
+    "ok",
+    "err",
+    "result",
+    "option",
+    "async",
+    "await",
+    "crate",
+    "super",
+    "const",
+    "static",
+    "def",
+    "class",
+    "import",
+    "elif",
+    "else",
+    "pass",
+    "lambda",
+    "function",
+    "var",
+    "new",
+    "null",
+    "undefined",
+    "export",
+    "default",
+    "public",
+    "private",
+    "protected",
+    "void",
+    "int",
+    "func",
+    "package",
+    "interface",
+    "nil",
+    "unwrap",
+    "clone",
+    "println",
+    "format",
+    "http",
+    "https",
+    "www",
+];
+
+/// Framework and library names detected by exact token match.
+const FRAMEWORKS: &[&str] = &[
+    "actix",
+    "angular",
+    "axum",
+    "django",
+    "express",
+    "fastapi",
+    "flask",
+    "gin",
+    "jest",
+    "laravel",
+    "numpy",
+    "pandas",
+    "pytest",
+    "pytorch",
+    "rails",
+    "react",
+    "rocket",
+    "rusqlite",
+    "serde",
+    "spring",
+    "svelte",
+    "tensorflow",
+    "tokio",
+    "vue",
+];
+
+/// Work markers recorded verbatim when they appear as whole words.
+const MARKERS: &[&str] = &["TODO", "FIXME", "HACK", "XXX", "BUG"];
+
+/// Lightweight tags derived from a chunk's text.
+#[derive(Debug, Default, PartialEq)]
+pub struct ChunkTags {
+    pub keywords: Vec<String>,
+    pub frameworks: Vec<String>,
+    pub markers: Vec<String>,
+}
+
+impl ChunkTags {
+    /// Insert the non-empty tag lists into a chunk's metadata object.
+    pub fn merge_into(self, metadata: &mut serde_json::Map<String, serde_json::Value>) {
+        for (key, values) in [
+            ("keywords", self.keywords),
+            ("frameworks", self.frameworks),
+            ("markers", self.markers),
+        ] {
+            if !values.is_empty() {
+                metadata.insert(key.to_string(), serde_json::json!(values));
+            }
+        }
+    }
+}
+
+/// Scores terms by TF-IDF, using the chunks of one file as the corpus so
+/// that terms shared by every chunk (imports, the type being implemented)
+/// rank below the ones that distinguish a chunk from its neighbours.
+pub struct KeywordExtractor {
+    doc_freq: HashMap<String, usize>,
+    docs: usize,
+    /// Words of the dependency names when the file is a build manifest;
+    /// only these can be tagged as frameworks.
+    dependencies: Option<HashSet<String>>,
+}
+
+impl KeywordExtractor {
+    pub fn new<'a>(docs: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut doc_freq = HashMap::new();
+        let mut count = 0;
+        for doc in docs {
+            count += 1;
+            let unique: HashSet<String> = tokenize(doc).collect();
+            for term in unique {
+                *doc_freq.entry(term).or_insert(0) += 1;
+            }
+        }
+        Self {
+            doc_freq,
+            docs: count,
+            dependencies: None,
+        }
+    }
+
+    /// Tag frameworks in `path` only by its dependency names when it is a
+    /// build manifest, so a description or script mentioning one is not a hit.
+    pub fn with_manifest(mut self, path: &Path) -> Self {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if MANIFESTS.contains(&name) {
+            let content = std::fs::read_to_string(path).unwrap_or_default();
+            self.dependencies = Some(manifest_dependencies(name, &content));
+        }
+        self
+    }
+
+    /// Keywords, frameworks and markers for one chunk of the corpus.
+    pub fn tags(&self, text: &str) -> ChunkTags {
+        ChunkTags {
+            keywords: self.keywords(text, KEYWORDS_PER_CHUNK),
+            frameworks: match &self.dependencies {
+                Some(dependencies) => frameworks(text)
+                    .into_iter()
+                    .filter(|f| dependencies.contains(f))
+                    .collect(),
+                None => frameworks(text),
+            },
+            markers: markers(text),
+        }
+    }
+
+    /// The `n` highest scoring terms of `text`.
+    pub fn keywords(&self, text: &str, n: usize) -> Vec<String> {
+        let mut term_freq: HashMap<String, usize> = HashMap::new();
+        for term in tokenize(text) {
+            *term_freq.entry(term).or_insert(0) += 1;
+        }
+
+        let mut scored: Vec<(String, f64)> = term_freq
+            .into_iter()
+            .map(|(term, tf)| {
+                let df = self.doc_freq.get(&term).copied().unwrap_or(0);
+                // Smoothed so terms in every chunk still score above zero
+                let idf = ((1 + self.docs) as f64 / (1 + df) as f64).ln() + 1.0;
+                let score = tf as f64 * idf;
+                (term, score)
+            })
+            .collect();
+        scored.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        scored.into_iter().take(n).map(|(term, _)| term).collect()
+    }
+}
+
+/// Lowercased identifier-like words, excluding stopwords, numbers and
+/// anything shorter than three characters.
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .map(|w| w.trim_matches('_').to_lowercase())
+        .filter(|w| {
+            w.len() >= 3
+                && w.len() <= 40
+                && !w.chars().all(|c| c.is_ascii_digit())
+                && !STOPWORDS.contains(&w.as_str())
+        })
+}
+
+/// Known frameworks mentioned in `text`, in list order.
+pub fn frameworks(text: &str) -> Vec<String> {
+    let words: HashSet<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .map(|w| w.to_lowercase())
+        .collect();
+    FRAMEWORKS
+        .iter()
+        .filter(|f| words.contains(**f))
+        .map(|f| f.to_string())
+        .collect()
+}
+
+/// Build manifests whose frameworks come from their dependency names.
+const MANIFESTS: &[&str] = &[
+    "Cargo.toml",
+    "package.json",
+    "composer.json",
+    "pyproject.toml",
+    "requirements.txt",
+    "go.mod",
+];
+
+/// Lowercased words of the dependency names declared in a manifest, so
+/// `react-dom` or `@angular/core` count for `react` and `angular`.
+fn manifest_dependencies(name: &str, content: &str) -> HashSet<String> {
+    let mut names: Vec<String> = Vec::new();
+    match name {
+        "Cargo.toml" => {
+            let manifest: toml::Table = toml::from_str(content).unwrap_or_default();
+            let mut tables = vec![&manifest];
+            if let Some(toml::Value::Table(workspace)) = manifest.get("workspace") {
+                tables.push(workspace);
+            }
+            if let Some(toml::Value::Table(targets)) = manifest.get("target") {
+                tables.extend(targets.values().filter_map(|t| t.as_table()));
+            }
+            for table in tables {
+                for section in ["dependencies", "dev-dependencies", "build-dependencies"] {
+                    if let Some(toml::Value::Table(deps)) = table.get(section) {
+                        names.extend(deps.keys().cloned());
+                    }
+                }
+            }
+        }
+        "package.json" | "composer.json" => {
+            let manifest: Value = serde_json::from_str(content).unwrap_or_default();
+            for section in [
+                "dependencies",
+                "devDependencies",
+                "peerDependencies",
+                "optionalDependencies",
+                "require",
+                "require-dev",
+            ] {
+                if let Some(deps) = manifest.get(section).and_then(Value::as_object) {
+                    names.extend(deps.keys().cloned());
+                }
+            }
+        }
+        "pyproject.toml" => {
+            let manifest: toml::Table = toml::from_str(content).unwrap_or_default();
+            let project = manifest.get("project");
+            let mut requirements: Vec<&toml::Value> = Vec::new();
+            if let Some(deps) = project.and_then(|p| p.get("dependencies")) {
+                requirements.push(deps);
+            }
+            if let Some(toml::Value::Table(extras)) =
+                project.and_then(|p| p.get("optional-dependencies"))
+            {
+                requirements.extend(extras.values());
+            }
+            for spec in requirements.iter().filter_map(|r| r.as_array()).flatten() {
+                names.extend(spec.as_str().map(requirement_name));
+            }
+            let poetry = manifest.get("tool").and_then(|t| t.get("poetry"));
+            let mut tables: Vec<&toml::Value> = poetry.into_iter().collect();
+            if let Some(toml::Value::Table(groups)) = poetry.and_then(|p| p.get("group")) {
+                tables.extend(groups.values());
+            }
+            for table in tables {
+                for section in ["dependencies", "dev-dependencies"] {
+                    if let Some(toml::Value::Table(deps)) = table.get(section) {
+                        names.extend(deps.keys().cloned());
+                    }
+                }
+            }
+        }
+        "requirements.txt" => {
+            names.extend(
+                content
+                    .lines()
+                    .map(|l| l.split('#').next().unwrap_or("").trim())
+                    .filter(|l| !l.is_empty() && !l.starts_with('-'))
+                    .map(requirement_name),
+            );
+        }
+        "go.mod" => {
+            let mut in_block = false;
+            for line in content
+                .lines()
+                .map(|l| l.split("//").next().unwrap_or("").trim())
+            {
+                let module = if in_block {
+                    in_block = line != ")";
+                    line
+                } else if line == "require (" {
+                    in_block = true;
+                    continue;
+                } else if let Some(rest) = line.strip_prefix("require ") {
+                    rest
+                } else {
+                    continue;
+                };
+                names.extend(module.split_whitespace().next().map(str::to_string));
+            }
+        }
+        _ => {}
+    }
+    names
+        .iter()
+        .flat_map(|n| n.split(|c: char| !c.is_alphanumeric()))
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+/// The project name at the start of a PEP 508 requirement such as
+/// `django>=4.2; python_version > "3.8"`.
+fn requirement_name(spec: &str) -> String {
+    spec.trim()
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .collect()
+}
+
+/// TODO/FIXME style markers present in `text`, in list order.
+pub fn markers(text: &str) -> Vec<String> {
+    let words: HashSet<&str> = text.split(|c: char| !c.is_alphanumeric()).collect();
+    MARKERS
+        .iter()
+        .filter(|m| words.contains(**m))
+        .map(|m| m.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keywords_prefer_distinctive_terms() {
+        let docs = [
+            "fn connect_pool(config) { retry_backoff() }",
+            "fn parse_settings(config) { validate() }",
+            "fn render(config) { template() }",
+        ];
+        let extractor = KeywordExtractor::new(docs);
+        let keywords = extractor.keywords(docs[0], 2);
+        assert!(keywords.contains(&"connect_pool".to_string()));
+        assert!(!keywords.contains(&"config".to_string()));
+        assert!(extractor.keywords("fn let pub 42", 5).is_empty());
+    }
+
+    #[test]
+    fn test_frameworks_and_markers() {
+        let text = "use tokio::sync::Mutex;\n// TODO: handle errors\n// todo is not a marker";
+        assert_eq!(frameworks(text), vec!["tokio"]);
+        assert_eq!(markers(text), vec!["TODO"]);
+        assert!(markers("TODOS and FIXMEs").is_empty());
+    }
+
+    #[test]
+    fn test_manifest_frameworks() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cargo = dir.path().join("Cargo.toml");
+        let text = "[package]\ndescription = \"Like express, for tokio\"\n\n\
+                    [dependencies]\ntokio = \"1\"\n\n\
+                    [target.'cfg(unix)'.dev-dependencies]\nserde = \"1\"\n";
+        std::fs::write(&cargo, text).unwrap();
+        let extractor = KeywordExtractor::new([text]).with_manifest(&cargo);
+        assert_eq!(extractor.tags(text).frameworks, vec!["serde", "tokio"]);
+
+        // Scripts and descriptions mention names that are not dependencies
+        let package = dir.path().join("package.json");
+        let text = r#"{"description": "Not a vue app", "scripts": {"test": "jest"},
+                       "dependencies": {"react-dom": "^18"}}"#;
+        std::fs::write(&package, text).unwrap();
+        let extractor = KeywordExtractor::new([text]).with_manifest(&package);
+        assert_eq!(extractor.tags(text).frameworks, vec!["react"]);
+        assert!(extractor.tags(r#""test": "jest""#).frameworks.is_empty());
+
+        assert_eq!(
+            manifest_dependencies(
+                "pyproject.toml",
+                "[project]\ndependencies = [\"Django>=4.2\"]\n\
+                 [tool.poetry.group.dev.dependencies]\npytest = \"^8\"\n"
+            ),
+            HashSet::from(["django".to_string(), "pytest".to_string()])
+        );
+        assert_eq!(
+            manifest_dependencies("requirements.txt", "# flask for the api\nnumpy==1.26\n"),
+            HashSet::from(["numpy".to_string()])
+        );
+        let deps = manifest_dependencies(
+            "go.mod",
+            "module example.com/app\n\nrequire (\n\tgithub.com/gin-gonic/gin v1.9.1\n)\n",
+        );
+        assert!(deps.contains("gin") && !deps.contains("example"));
+
+        // Other files still match any mention
+        let extractor = KeywordExtractor::new(["use tokio;"]).with_manifest(Path::new("main.rs"));
+        assert_eq!(extractor.tags("use tokio;").frameworks, vec!["tokio"]);
+    }
+
+    #[test]
+    fn test_merge_skips_empty() {
+        let tags = ChunkTags {
+            keywords: vec!["pool".to_string()],
+            frameworks: vec![],
+            markers: vec!["FIXME".to_string()],
+        };
+        let mut metadata = serde_json::Map::new();
+        tags.merge_into(&mut metadata);
+        assert_eq!(metadata.len(), 2);
+        assert!(!metadata.contains_key("frameworks"));
+    }
+}
@@ -1,5 +1,38 @@
 pub mod chunker;
 pub mod embeddings;
+#[cfg(target_os = "macos")]
+pub mod fsevents;
 pub mod ignore;
+pub mod keywords;
+pub mod moves;
 pub mod plugins;
+pub mod remote;
+pub mod sniff;
 pub mod watcher;
+pub mod workers;
+
+use thiserror::Error;
+
+use crate::storage::StorageError;
+
+/// Why a file couldn't be indexed.
+#[derive(Debug, Error)]
+pub enum IndexError {
+    /// The file couldn't be read or split into chunks, by the chunker or its
+    /// plugin
+    #[error(transparent)]
+    Chunk(anyhow::Error),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+}
+
+impl IndexError {
+    /// Whether indexing the file again later may succeed. A file that can't
+    /// be chunked fails the same way until it changes.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Chunk(_) => false,
+            Self::Storage(e) => e.is_transient(),
+        }
+    }
+}
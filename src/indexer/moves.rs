@@ -0,0 +1,426 @@
+// Index entries of files that disappeared from a watched root: removed, or
+// moved along when a path that appeared in the same batch of watcher events
+// is the same file under a new name. A move keeps the file's chunks, their
+// embeddings and ids, and what was recorded about them, like query hits.
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::daemon;
+use crate::paths::is_virtual_path;
+use crate::reindex;
+use crate::storage::db::{Database, FileRecord};
+
+/// What [`apply`] did to the index.
+#[derive(Debug, Default, PartialEq)]
+pub struct Moves {
+    /// Files removed from the index
+    pub removed: Vec<String>,
+    /// Files whose chunks moved to a new path, as `(from, to)`
+    pub renamed: Vec<(String, String)>,
+}
+
+/// Update the index for `gone`, paths that no longer exist, given
+/// `appeared`, the paths from the same batch of events that do. A file
+/// moves to an appeared file with the modification time it was indexed
+/// with, or failing that the content, preferring one with the same name;
+/// a directory moves to an
+/// appeared directory with the same name, or the only one. Files whose
+/// indexing settings would differ at the new path, e.g. another collection,
+/// are removed instead, to be indexed afresh.
+pub fn apply(
+    db: &Database,
+    config: &Config,
+    gone: &[PathBuf],
+    appeared: &[PathBuf],
+) -> Result<Moves> {
+    let mut moves = Moves::default();
+    let mut taken: HashSet<PathBuf> = HashSet::new();
+    for path in gone {
+        let path_str = path.to_string_lossy().to_string();
+        if let Some(file) = db.get_file(&path_str)? {
+            let target = file_target(db, path, &file, appeared, &taken);
+            if let Some(target) = &target {
+                taken.insert(target.clone());
+            }
+            move_or_remove(db, config, &mut moves, path, target.as_deref())?;
+            continue;
+        }
+
+        // Otherwise a directory, whose files went with it
+        let children: Vec<PathBuf> = db
+            .list_files()?
+            .iter()
+            .map(|f| PathBuf::from(&f.path))
+            .filter(|f| f.starts_with(path) && f != path)
+            .collect();
+        if children.is_empty() {
+            continue;
+        }
+        let target_dir = dir_target(path, appeared, &taken);
+        if let Some(dir) = &target_dir {
+            taken.insert(dir.clone());
+        }
+        for child in children {
+            let target = target_dir
+                .as_ref()
+                .and_then(|dir| Some(dir.join(child.strip_prefix(path).ok()?)))
+                .filter(|target| target.is_file());
+            move_or_remove(db, config, &mut moves, &child, target.as_deref())?;
+        }
+    }
+    Ok(moves)
+}
+
+fn move_or_remove(
+    db: &Database,
+    config: &Config,
+    moves: &mut Moves,
+    from: &Path,
+    to: Option<&Path>,
+) -> Result<()> {
+    let from_str = from.to_string_lossy().to_string();
+    match to {
+        Some(to) if reindex::fingerprint(config, from) == reindex::fingerprint(config, to) => {
+            let to_str = to.to_string_lossy().to_string();
+            if db.rename_file(&from_str, &to_str)? {
+                moves.renamed.push((from_str, to_str));
+            }
+        }
+        _ => {
+            if db.remove_file(&from_str)? {
+                moves.removed.push(from_str);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Take over the index entry of the file `path` was moved from, if it was
+/// moved while the daemon wasn't running or its old path went in an
+/// earlier batch of events: an indexed file whose content hashed to `hash`,
+/// that no longer exists and would be indexed with the same settings.
+/// `modified` is the file's modification time. Returns the old path.
+pub fn adopt(
+    db: &Database,
+    config: &Config,
+    path: &Path,
+    modified: u64,
+    hash: &str,
+) -> Result<Option<String>> {
+    let fingerprint = reindex::fingerprint(config, path);
+    let candidates: Vec<PathBuf> = db
+        .files_with_content_hash(hash)?
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|p| {
+            // Documents and transcripts have no file on disk
+            !is_virtual_path(&p.to_string_lossy())
+                && p != path
+                && !p.exists()
+                && reindex::fingerprint(config, p) == fingerprint
+        })
+        .collect();
+    let Some(from) = candidates
+        .iter()
+        .find(|p| p.file_name() == path.file_name())
+        .or(candidates.first())
+    else {
+        return Ok(None);
+    };
+    let (from, to) = (from.to_string_lossy(), path.to_string_lossy());
+    if !db.rename_file(&from, &to)? {
+        return Ok(None);
+    }
+    db.touch_if_unchanged(&to, modified, hash)?;
+    Ok(Some(from.to_string()))
+}
+
+/// The appeared file that `file`, indexed at `path`, was most likely moved
+/// to.
+fn file_target(
+    db: &Database,
+    path: &Path,
+    file: &FileRecord,
+    appeared: &[PathBuf],
+    taken: &HashSet<PathBuf>,
+) -> Option<PathBuf> {
+    let candidates: Vec<&PathBuf> = appeared
+        .iter()
+        .filter(|p| !taken.contains(*p) && p.is_file())
+        .collect();
+    // A move keeps the modification time; an unknown one matches nothing
+    let (same_mtime, others): (Vec<&PathBuf>, Vec<&PathBuf>) = candidates
+        .into_iter()
+        .partition(|p| file.last_modified != 0 && modified(p) == Some(file.last_modified));
+    if let Some(target) = best_match(path, &same_mtime) {
+        return Some(target);
+    }
+    // Copied and then deleted, or touched on the way
+    let same_content: Vec<&PathBuf> = others
+        .into_iter()
+        .filter(|p| has_content_of(db, p, &file.path))
+        .collect();
+    best_match(path, &same_content)
+}
+
+/// The candidate with the same name as `path`, or the only one.
+fn best_match(path: &Path, candidates: &[&PathBuf]) -> Option<PathBuf> {
+    candidates
+        .iter()
+        .find(|p| p.file_name() == path.file_name())
+        .or(match candidates.len() {
+            1 => candidates.first(),
+            _ => None,
+        })
+        .map(|p| p.to_path_buf())
+}
+
+/// Whether the file at `path` has the content the file indexed at `indexed`
+/// had when it was indexed.
+fn has_content_of(db: &Database, path: &Path, indexed: &str) -> bool {
+    let Ok(bytes) = std::fs::read(path) else {
+        return false;
+    };
+    db.files_with_content_hash(&daemon::content_hash(&bytes))
+        .is_ok_and(|paths| paths.iter().any(|p| p == indexed))
+}
+
+/// The appeared directory that the directory at `path` was most likely
+/// moved to.
+fn dir_target(path: &Path, appeared: &[PathBuf], taken: &HashSet<PathBuf>) -> Option<PathBuf> {
+    let dirs: Vec<&PathBuf> = appeared
+        .iter()
+        .filter(|p| !taken.contains(*p) && p.is_dir())
+        .collect();
+    dirs.iter()
+        .find(|p| p.file_name() == path.file_name())
+        .or(match dirs.len() {
+            1 => dirs.first(),
+            _ => None,
+        })
+        .map(|p| p.to_path_buf())
+}
+
+fn modified(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(
+        modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_secs(),
+    )
+}
+
+/// The directories files are indexed from: `[watch] paths`, and with
+/// `[watch] register_workspaces` the registered workspaces.
+pub fn index_roots(db: &Database, config: &Config) -> Result<Vec<PathBuf>> {
+    let mut roots = config.watch.paths.clone();
+    if config.watch.register_workspaces {
+        roots.extend(db.list_workspaces()?);
+    }
+    Ok(roots)
+}
+
+/// Indexed files under `roots` that no longer exist on disk. A path is
+/// looked for in the root it was indexed under, so `./src/main.rs` in the
+/// current directory when the root is `.`. Files under no root, or under a
+/// root that is missing itself like an unmounted drive, aren't reported.
+pub fn missing_files(db: &Database, roots: &[PathBuf]) -> Result<Vec<FileRecord>> {
+    let roots: Vec<(&PathBuf, PathBuf)> = roots
+        .iter()
+        .filter_map(|root| Some((root, root.canonicalize().ok()?)))
+        .collect();
+    Ok(db
+        .list_files()?
+        .into_iter()
+        // Documents and transcripts have no file on disk
+        .filter(|file| !is_virtual_path(&file.path))
+        .filter(|file| {
+            let path = Path::new(&file.path);
+            roots.iter().any(|(root, resolved)| {
+                path.strip_prefix(root)
+                    .is_ok_and(|relative| !resolved.join(relative).exists())
+            })
+        })
+        .collect())
+}
+
+/// Remove the index entries of files under `roots` that no longer exist,
+/// e.g. deleted while no daemon was running or whose events were lost.
+/// Returns how many were removed.
+pub fn sweep_orphans(db: &Database, roots: &[PathBuf]) -> Result<usize> {
+    let mut removed = 0;
+    for file in missing_files(db, roots)? {
+        if db.remove_file(&file.path)? {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Sweep orphans under `roots` now and then every `interval` while the
+/// daemon runs; a zero interval turns sweeping off.
+pub fn spawn_sweeps(db: Database, roots: Vec<PathBuf>, interval: Duration) {
+    if interval.is_zero() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            let roots = roots.clone();
+            match db.call(move |db| sweep_orphans(db, &roots)).await {
+                Ok(0) => {}
+                Ok(removed) => log::info!("Removed {} deleted files from the index", removed),
+                Err(e) => log::warn!("Failed to sweep deleted files from the index: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn index(db: &Database, path: &Path) {
+        let file_id = db
+            .add_or_update_file(&path.to_string_lossy(), modified(path).unwrap())
+            .unwrap();
+        db.add_chunk(file_id, 0, 1, "fn x() {}", None, None)
+            .unwrap();
+        db.mark_indexed(file_id).unwrap();
+    }
+
+    #[test]
+    fn test_moves() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        let db = Database::new(":memory:").unwrap();
+        let config = Config::default();
+        for name in ["a.rs", "b.rs", "old/c.rs", "old/d.rs", "gone/e.rs"] {
+            let path = root.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, name).unwrap();
+            index(&db, &path);
+        }
+        let chunk_id = |path: &Path| {
+            let file = db.get_file(&path.to_string_lossy()).unwrap().unwrap();
+            db.get_chunks(file.id).unwrap()[0].id
+        };
+        let c_chunk = chunk_id(&root.join("old/c.rs"));
+
+        fs::rename(root.join("a.rs"), root.join("renamed.rs")).unwrap();
+        fs::remove_file(root.join("b.rs")).unwrap();
+        fs::rename(root.join("old"), root.join("new")).unwrap();
+        fs::remove_dir_all(root.join("gone")).unwrap();
+        let gone = ["a.rs", "b.rs", "old", "gone"].map(|p| root.join(p));
+        let appeared = ["renamed.rs", "new"].map(|p| root.join(p));
+        let moves = apply(&db, &config, &gone, &appeared).unwrap();
+
+        let s = |p: &str| root.join(p).to_string_lossy().to_string();
+        assert_eq!(
+            moves.renamed,
+            vec![
+                (s("a.rs"), s("renamed.rs")),
+                (s("old/c.rs"), s("new/c.rs")),
+                (s("old/d.rs"), s("new/d.rs")),
+            ]
+        );
+        assert_eq!(moves.removed, vec![s("b.rs"), s("gone/e.rs")]);
+        // Moved chunks keep their ids, and followers see them move
+        assert_eq!(chunk_id(&root.join("new/c.rs")), c_chunk);
+        let moved: Vec<(String, String)> = db
+            .changes_since(0, 1000)
+            .unwrap()
+            .into_iter()
+            .filter(|c| c.chunk_id == Some(c_chunk))
+            .map(|c| (c.op, c.path))
+            .collect();
+        assert_eq!(
+            moved[1..],
+            [
+                ("delete".to_string(), s("old/c.rs")),
+                ("add".to_string(), s("new/c.rs")),
+            ]
+        );
+        let paths: Vec<String> = db
+            .list_files()
+            .unwrap()
+            .into_iter()
+            .map(|f| f.path)
+            .collect();
+        assert_eq!(paths, vec![s("new/c.rs"), s("new/d.rs"), s("renamed.rs")]);
+
+        // Deleted while nobody watched
+        fs::remove_file(root.join("renamed.rs")).unwrap();
+        db.add_or_update_file("document://notes", 1).unwrap();
+        db.add_or_update_file("/elsewhere/missing.rs", 1).unwrap();
+        let roots = std::slice::from_ref(&root);
+        assert_eq!(missing_files(&db, roots).unwrap().len(), 1);
+        assert_eq!(sweep_orphans(&db, roots).unwrap(), 1);
+        assert!(db.get_file(&s("renamed.rs")).unwrap().is_none());
+        assert!(db.get_file("document://notes").unwrap().is_some());
+        assert!(db.get_file("/elsewhere/missing.rs").unwrap().is_some());
+
+        // Moved with a new modification time: matched on content
+        for name in ["new/c.rs", "new/d.rs"] {
+            let hash = daemon::content_hash(&fs::read(root.join(name)).unwrap());
+            db.record_content_hash(&s(name), &hash).unwrap();
+        }
+        fs::rename(root.join("new/c.rs"), root.join("c2.rs")).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(root.join("c2.rs"))
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + Duration::from_secs(1000))
+            .unwrap();
+        let moves = apply(
+            &db,
+            &config,
+            &[root.join("new/c.rs")],
+            &[root.join("c2.rs")],
+        )
+        .unwrap();
+        assert_eq!(moves.renamed, vec![(s("new/c.rs"), s("c2.rs"))]);
+        assert_eq!(chunk_id(&root.join("c2.rs")), c_chunk);
+
+        // Moved while nobody watched: taken over when the new path is indexed
+        let d_chunk = chunk_id(&root.join("new/d.rs"));
+        fs::rename(root.join("new/d.rs"), root.join("d2.rs")).unwrap();
+        let moved = root.join("d2.rs");
+        let hash = daemon::content_hash(&fs::read(&moved).unwrap());
+        let mtime = modified(&moved).unwrap();
+        let from = adopt(&db, &config, &moved, mtime, &hash).unwrap();
+        assert_eq!(from, Some(s("new/d.rs")));
+        assert_eq!(chunk_id(&moved), d_chunk);
+        assert!(!db.needs_reindexing(&s("d2.rs"), mtime).unwrap());
+        // Only from a path that's gone
+        fs::write(root.join("d3.rs"), "old/d.rs").unwrap();
+        let copy = adopt(&db, &config, &root.join("d3.rs"), mtime, &hash).unwrap();
+        assert_eq!(copy, None);
+    }
+
+    #[test]
+    fn test_missing_relative_files() {
+        // Tests run in the package root, so `./src` is this crate's source
+        let db = Database::new(":memory:").unwrap();
+        for path in ["./src/lib.rs", "./src/gone.rs", "./absent/gone.rs"] {
+            db.add_or_update_file(path, 1).unwrap();
+        }
+        db.add_or_update_file("conversation://session/1", 1)
+            .unwrap();
+        let roots = [PathBuf::from("./src"), PathBuf::from("./absent")];
+        let missing: Vec<String> = missing_files(&db, &roots)
+            .unwrap()
+            .into_iter()
+            .map(|f| f.path)
+            .collect();
+        assert_eq!(missing, ["./src/gone.rs"]);
+        assert!(missing_files(&db, &[]).unwrap().is_empty());
+    }
+}
@@ -1,41 +1,197 @@
 use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::path::Path;
-use std::time::Duration;
+use std::process::Stdio;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio::process::Command;
 use tokio::time::timeout;
 
+use crate::config::PluginConfig;
+
+/// Bytes of a failing plugin's stderr kept for the error message.
+const MAX_STDERR_BYTES: usize = 4096;
+
+/// Run `cmd` on `file_path` with the default plugin limits.
 pub async fn run_parser(cmd: &[String], file_path: &Path) -> Result<String> {
-    if cmd.is_empty() {
+    run_plugin(&PluginConfig::from_command(cmd.to_vec()), file_path).await
+}
+
+/// Run a plugin on `file_path` and return its stdout, enforcing the
+/// plugin's timeout, output cap, working directory and environment. For a
+/// pipeline, each step's stdout is fed to the next one's stdin and the last
+/// step's output is returned; the timeout covers the whole pipeline.
+pub async fn run_plugin(plugin: &PluginConfig, file_path: &Path) -> Result<String> {
+    if plugin.steps().any(|cmd| cmd.is_empty()) {
         return Err(anyhow::anyhow!("Empty plugin command"));
     }
 
+    // Execute with timeout; dropping a child on timeout kills it
+    let run = async {
+        let mut output = run_step(plugin, &plugin.command, Some(file_path), None).await?;
+        for cmd in &plugin.pipe_to {
+            output = run_step(plugin, cmd, None, Some(output)).await?;
+        }
+        Ok::<_, anyhow::Error>(output)
+    };
+    let output = timeout(Duration::from_secs(plugin.timeout_secs), run)
+        .await
+        .with_context(|| {
+            format!(
+                "Plugin execution timed out after {} seconds",
+                plugin.timeout_secs
+            )
+        })??;
+
+    let stdout = String::from_utf8(output).context("Plugin output is not valid UTF-8")?;
+
+    Ok(stdout)
+}
+
+/// Run one command, passing it `file_path` as its last argument and/or
+/// `input` on stdin, and return its stdout.
+async fn run_step(
+    plugin: &PluginConfig,
+    cmd: &[String],
+    file_path: Option<&Path>,
+    input: Option<Vec<u8>>,
+) -> Result<Vec<u8>> {
     let program = &cmd[0];
     let args = &cmd[1..];
 
     // Prepare command
     let mut command = Command::new(program);
-    command.args(args);
-    command.arg(file_path);
+    command
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(path) = file_path {
+        command.arg(path);
+    }
+    if let Some(dir) = &plugin.working_dir {
+        command.current_dir(dir);
+    }
+    if plugin.clear_env {
+        command.env_clear();
+        for name in &plugin.env_allowlist {
+            if let Some(value) = std::env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+    }
 
-    // Execute with timeout
-    let output_result = timeout(Duration::from_secs(30), command.output())
-        .await
-        .context("Plugin execution timed out after 30 seconds")?;
+    let mut child = command
+        .spawn()
+        .context("Failed to execute plugin command")?;
+    let mut stdout = child.stdout.take().context("Plugin stdout unavailable")?;
+    let mut stderr = child.stderr.take().context("Plugin stderr unavailable")?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        // A step may exit without reading all of its input
+        tokio::spawn(async move {
+            let _ = stdin.write_all(&input).await;
+        });
+    }
+    // Drain stderr separately so a chatty plugin can't block on it
+    let stderr_task = tokio::spawn(async move { read_head(&mut stderr, MAX_STDERR_BYTES).await });
 
-    let output = output_result.context("Failed to execute plugin command")?;
+    let limit = plugin.max_output_bytes;
+    let mut output = Vec::new();
+    (&mut stdout)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut output)
+        .await
+        .context("Failed to read plugin output")?;
+    if output.len() > limit {
+        let _ = child.kill().await;
+        return Err(anyhow::anyhow!(
+            "Plugin output exceeded the limit of {} bytes",
+            limit
+        ));
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
+    let status = child.wait().await.context("Failed to wait for plugin")?;
+    if !status.success() {
+        let stderr = stderr_task
+            .await
+            .ok()
+            .and_then(|r| r.ok())
+            .unwrap_or_default();
         return Err(anyhow::anyhow!(
             "Plugin command failed with status {}: {}",
-            output.status,
-            stderr
+            status,
+            String::from_utf8_lossy(&stderr)
         ));
     }
+    Ok(output)
+}
 
-    let stdout = String::from_utf8(output.stdout).context("Plugin output is not valid UTF-8")?;
+/// Read `reader` to the end, keeping only its first `max` bytes.
+async fn read_head<R: AsyncRead + Unpin>(reader: &mut R, max: usize) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(head);
+        }
+        let room = max.saturating_sub(head.len());
+        head.extend_from_slice(&buf[..n.min(room)]);
+    }
+}
 
-    Ok(stdout)
+/// One line of the plugin audit log.
+#[derive(Serialize, Debug)]
+pub struct AuditRecord<'a> {
+    pub timestamp: u64,
+    pub file: &'a Path,
+    pub command: &'a [String],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub pipe_to: &'a [Vec<String>],
+    pub duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Run a plugin like [`run_plugin`] and append the invocation to `audit_log`.
+pub async fn run_audited(
+    plugin: &PluginConfig,
+    file_path: &Path,
+    audit_log: &Path,
+) -> Result<String> {
+    let started = Instant::now();
+    let result = run_plugin(plugin, file_path).await;
+    let record = AuditRecord {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        file: file_path,
+        command: &plugin.command,
+        pipe_to: &plugin.pipe_to,
+        duration_ms: started.elapsed().as_millis(),
+        output_bytes: result.as_ref().ok().map(|out| out.len()),
+        error: result.as_ref().err().map(|e| format!("{:#}", e)),
+    };
+    if let Err(e) = append_audit(audit_log, &record) {
+        log::warn!("Failed to write plugin audit log {:?}: {}", audit_log, e);
+    }
+    result
+}
+
+fn append_audit(log: &Path, record: &AuditRecord) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(log)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -151,4 +307,73 @@ mod tests {
         let result = run_parser(&cmd, Path::new("/nonexistent/path/to/file.txt")).await;
         assert!(result.is_err(), "Should fail when file doesn't exist");
     }
+
+    #[tokio::test]
+    async fn test_plugin_output_limit() {
+        let mut plugin = PluginConfig::from_command(vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "yes test | head -n 100000".to_string(),
+        ]);
+        plugin.max_output_bytes = 1000;
+        let result = run_plugin(&plugin, Path::new("dummy.txt")).await;
+        assert!(result.unwrap_err().to_string().contains("exceeded"));
+    }
+
+    #[tokio::test]
+    async fn test_plugin_env_and_working_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::env::set_var("CONTEXTD_PLUGIN_SECRET", "hunter2");
+        let mut plugin = PluginConfig::from_command(vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "pwd; echo \"secret=$CONTEXTD_PLUGIN_SECRET\"".to_string(),
+        ]);
+        plugin.working_dir = Some(dir.path().to_path_buf());
+        plugin.clear_env = true;
+
+        let output = run_plugin(&plugin, Path::new("dummy.txt")).await.unwrap();
+        let cwd = dir.path().canonicalize().unwrap();
+        assert!(output.contains(&*cwd.to_string_lossy()));
+        assert!(output.contains("secret=\n"));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("audit.jsonl");
+        let ok = PluginConfig::from_command(vec!["echo".to_string()]);
+        let failing = PluginConfig::from_command(vec!["false".to_string()]);
+        run_audited(&ok, Path::new("a.docx"), &log).await.unwrap();
+        assert!(run_audited(&failing, Path::new("b.docx"), &log)
+            .await
+            .is_err());
+
+        let lines: Vec<serde_json::Value> = fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["file"], "a.docx");
+        assert!(lines[0]["output_bytes"].as_u64().unwrap() > 0);
+        assert!(lines[1]["error"].as_str().unwrap().contains("failed"));
+    }
+
+    #[tokio::test]
+    async fn test_plugin_pipeline() {
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(temp_file.path(), "hello pipeline\n").unwrap();
+        let mut plugin = PluginConfig::from_command(vec!["cat".to_string()]);
+        plugin.pipe_to = vec![
+            vec!["tr".to_string(), "a-z".to_string(), "A-Z".to_string()],
+            vec!["sed".to_string(), "s/^/# /".to_string()],
+        ];
+
+        let output = run_plugin(&plugin, temp_file.path()).await.unwrap();
+        assert_eq!(output, "# HELLO PIPELINE\n");
+
+        plugin.pipe_to.push(vec!["false".to_string()]);
+        assert!(run_plugin(&plugin, temp_file.path()).await.is_err());
+    }
 }
@@ -0,0 +1,333 @@
+// Embeddings from an HTTP API: an OpenAI-compatible `/embeddings` endpoint
+// or Ollama's `/api/embed`. Texts are sent in batches and requests retried
+// with backoff, since a rate limit or a restarting server shouldn't leave
+// chunks without vectors.
+
+use reqwest::blocking::{Client, Response};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use super::embeddings::{BackendKind, EmbedError, EmbeddingBackend, EmbeddingsConfig};
+use crate::storage::db::EMBEDDING_DIMENSIONS;
+
+type Result<T, E = EmbedError> = std::result::Result<T, E>;
+
+const OPENAI_URL: &str = "https://api.openai.com/v1";
+const OLLAMA_URL: &str = "http://localhost:11434";
+
+/// Wait before the first retry, doubled for each one after.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest wait between retries, including one a `Retry-After` header asks
+/// for.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+pub struct RemoteBackend {
+    kind: BackendKind,
+    endpoint: String,
+    model: String,
+    api_key: Option<String>,
+    batch_size: usize,
+    max_retries: u32,
+    timeout: Duration,
+    /// Built on first use, off any async runtime, which the blocking client
+    /// refuses to start inside
+    client: OnceLock<Client>,
+}
+
+/// How a request failed: worth retrying or not.
+enum Failure {
+    Transient(String),
+    Permanent(String),
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct OllamaResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+impl RemoteBackend {
+    pub fn new(config: &EmbeddingsConfig, model: &str) -> Result<Self> {
+        let (default_url, path) = match config.backend {
+            BackendKind::OpenAi => (OPENAI_URL, "/embeddings"),
+            BackendKind::Ollama => (OLLAMA_URL, "/api/embed"),
+            BackendKind::Onnx => {
+                return Err(EmbedError::Remote(
+                    "ONNX models run in-process, not over HTTP".to_string(),
+                ))
+            }
+        };
+        let url = config.url.as_deref().unwrap_or(default_url);
+        Ok(Self {
+            kind: config.backend,
+            endpoint: format!("{}{}", url.trim_end_matches('/'), path),
+            model: model.to_string(),
+            api_key: config.api_key.clone(),
+            batch_size: config.batch_size.max(1),
+            max_retries: config.max_retries,
+            timeout: Duration::from_secs(config.timeout_secs),
+            client: OnceLock::new(),
+        })
+    }
+
+    /// Embed one batch, retrying transient failures. Runs on a thread of its
+    /// own so callers on an async runtime's threads can block on it too.
+    fn request(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, Failure> {
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let mut attempt = 0;
+                    loop {
+                        match self.send(texts) {
+                            Err((Failure::Transient(e), retry_after))
+                                if attempt < self.max_retries =>
+                            {
+                                let delay = retry_after
+                                    .unwrap_or_else(|| backoff(attempt))
+                                    .min(MAX_RETRY_DELAY);
+                                log::debug!("Retrying embedding request in {:?}: {}", delay, e);
+                                std::thread::sleep(delay);
+                                attempt += 1;
+                            }
+                            result => return result.map_err(|(failure, _)| failure),
+                        }
+                    }
+                })
+                .join()
+                .unwrap_or_else(|_| Err(Failure::Permanent("Request thread panicked".to_string())))
+        })
+    }
+
+    fn send(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, (Failure, Option<Duration>)> {
+        let client = self.client.get_or_init(|| {
+            Client::builder()
+                .timeout(self.timeout)
+                .build()
+                .unwrap_or_default()
+        });
+        let mut request = client.post(&self.endpoint).json(&json!({
+            "model": self.model,
+            "input": texts,
+            "dimensions": EMBEDDING_DIMENSIONS,
+        }));
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let response = request.send().map_err(|e| {
+            let failure = match e.is_connect() || e.is_timeout() {
+                true => Failure::Transient(e.to_string()),
+                false => Failure::Permanent(e.to_string()),
+            };
+            (failure, None)
+        })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = retry_after(&response);
+            let message = format!("{}: {}", status, response.text().unwrap_or_default());
+            let failure = match status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            {
+                true => Failure::Transient(message),
+                false => Failure::Permanent(message),
+            };
+            return Err((failure, retry_after));
+        }
+        let body = response
+            .text()
+            .map_err(|e| (Failure::Transient(e.to_string()), None))?;
+        parse_response(self.kind, &body).map_err(|e| (Failure::Permanent(e), None))
+    }
+
+    fn check(&self, vector: Vec<f32>) -> Result<Vec<f32>> {
+        if vector.len() != EMBEDDING_DIMENSIONS {
+            return Err(EmbedError::Dimensions {
+                model: self.model.clone(),
+                got: vector.len(),
+                expected: EMBEDDING_DIMENSIONS,
+            });
+        }
+        Ok(normalize(vector))
+    }
+}
+
+impl EmbeddingBackend for RemoteBackend {
+    /// The index's, which the API is asked for and held to.
+    fn dimensions(&self) -> usize {
+        EMBEDDING_DIMENSIONS
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed_batch(&[text])
+            .pop()
+            .unwrap_or_else(|| Err(EmbedError::Remote("Missing embedding".to_string())))
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Vec<Result<Vec<f32>>> {
+        let mut results = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(self.batch_size) {
+            match self.request(batch) {
+                Ok(vectors) if vectors.len() == batch.len() => {
+                    results.extend(vectors.into_iter().map(|v| self.check(v)));
+                }
+                Ok(vectors) => results.extend(batch.iter().map(|_| {
+                    Err(EmbedError::Remote(format!(
+                        "Got {} embeddings for {} texts",
+                        vectors.len(),
+                        batch.len()
+                    )))
+                })),
+                // One text the API rejects, such as one too long for the
+                // model, shouldn't fail the rest of its batch
+                Err(Failure::Permanent(_)) if batch.len() > 1 => {
+                    results.extend(batch.iter().map(|text| self.embed(text)));
+                }
+                Err(Failure::Transient(e) | Failure::Permanent(e)) => {
+                    results.extend(batch.iter().map(|_| Err(EmbedError::Remote(e.clone()))));
+                }
+            }
+        }
+        results
+    }
+}
+
+/// The vectors in an API response, in the order of the texts sent.
+fn parse_response(kind: BackendKind, body: &str) -> Result<Vec<Vec<f32>>, String> {
+    match kind {
+        BackendKind::Ollama => serde_json::from_str::<OllamaResponse>(body)
+            .map(|r| r.embeddings)
+            .map_err(|e| format!("Unexpected response: {}", e)),
+        _ => {
+            let mut response = serde_json::from_str::<OpenAiResponse>(body)
+                .map_err(|e| format!("Unexpected response: {}", e))?;
+            response.data.sort_by_key(|d| d.index);
+            Ok(response.data.into_iter().map(|d| d.embedding).collect())
+        }
+    }
+}
+
+/// Wait before retry `attempt` (from 0) when the server doesn't say:
+/// [`RETRY_DELAY`] doubled each time, up to [`MAX_RETRY_DELAY`].
+fn backoff(attempt: u32) -> Duration {
+    2u32.checked_pow(attempt)
+        .map_or(MAX_RETRY_DELAY, |factor| RETRY_DELAY.saturating_mul(factor))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Seconds in a `Retry-After` header.
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Scale to unit length, as local models are, so scores are comparable.
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm: f32 = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 1e-6 {
+        for val in &mut vector {
+            *val /= norm;
+        }
+    }
+    vector
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use serde_json::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// An OpenAI-compatible endpoint that is unavailable on its first
+    /// request and rejects texts containing "reject".
+    async fn embeddings(
+        State(requests): State<Arc<AtomicUsize>>,
+        Json(body): Json<Value>,
+    ) -> (StatusCode, Json<Value>) {
+        if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+            return (StatusCode::SERVICE_UNAVAILABLE, Json(json!({})));
+        }
+        let input = body["input"].as_array().unwrap();
+        if input.iter().any(|t| t.as_str().unwrap().contains("reject")) {
+            return (StatusCode::BAD_REQUEST, Json(json!({"error": "too long"})));
+        }
+        let data: Vec<Value> = input
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, _)| {
+                let mut embedding = vec![0.0; body["dimensions"].as_u64().unwrap() as usize];
+                embedding[i] = 2.0;
+                json!({"index": i, "embedding": embedding})
+            })
+            .collect();
+        (StatusCode::OK, Json(json!({"data": data})))
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), RETRY_DELAY);
+        assert_eq!(backoff(2), RETRY_DELAY * 4);
+        assert_eq!(backoff(10), MAX_RETRY_DELAY);
+        assert_eq!(backoff(32), MAX_RETRY_DELAY);
+        assert_eq!(backoff(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_remote_backend() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/v1/embeddings", post(embeddings))
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = EmbeddingsConfig {
+            backend: BackendKind::OpenAi,
+            url: Some(format!("http://{}/v1/", addr)),
+            batch_size: 2,
+            ..Default::default()
+        };
+        let backend = RemoteBackend::new(&config, "text-embedding-3-small").unwrap();
+        let results =
+            tokio::task::spawn_blocking(move || backend.embed_batch(&["a", "b", "c", "reject me"]))
+                .await
+                .unwrap();
+
+        // Retried once, then two batches, then the rejected batch text by text
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+        let vectors: Vec<Vec<f32>> = results[..3]
+            .iter()
+            .map(|r| r.as_ref().unwrap().clone())
+            .collect();
+        assert_eq!(vectors[0][0], 1.0);
+        assert_eq!(vectors[1][1], 1.0);
+        assert_eq!(vectors[2][0], 1.0);
+        assert!(matches!(results[3], Err(EmbedError::Remote(_))));
+
+        let ollama = parse_response(BackendKind::Ollama, r#"{"embeddings": [[1.0], [2.0]]}"#);
+        assert_eq!(ollama.unwrap(), vec![vec![1.0], vec![2.0]]);
+    }
+}
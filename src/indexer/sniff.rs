@@ -0,0 +1,244 @@
+// Choosing a handler for files whose extension doesn't name one, such as
+// extensionless scripts or JSON saved as `.out`, from what their content
+// looks like, rather than chunking them as plain paragraphs.
+
+use serde::Deserialize;
+use std::io::Read;
+use std::path::Path;
+
+use crate::config::{Config, PluginConfig};
+use crate::indexer::{chunker, plugins};
+
+/// Bytes read from the start of a file to tell its type.
+const HEAD_BYTES: u64 = 8192;
+
+/// `[sniffing]` config: how files with unknown extensions are typed.
+///
+/// ```toml
+/// [sniffing]
+/// plugin = ["file", "--brief", "--mime-type"]
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SniffingConfig {
+    /// Tell the type of files with unknown extensions from their content
+    pub enabled: bool,
+    /// A command asked before the built-in detection, printing a MIME type
+    /// or an extension for the file path appended to it
+    pub plugin: Option<PluginConfig>,
+}
+
+impl Default for SniffingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            plugin: None,
+        }
+    }
+}
+
+/// The extension whose handler indexes `path`: its own, or for a file no
+/// plugin or chunker takes, that of the type its content looks like.
+pub fn extension(config: &Config, path: &Path) -> String {
+    let ext = own_extension(path);
+    if !needs_sniffing(config, &ext) {
+        return ext;
+    }
+    read_head(path)
+        .and_then(|head| detect(&head))
+        .map_or(ext, str::to_string)
+}
+
+/// [`extension`], asking the sniffing plugin first if one is configured.
+pub async fn detect_extension(config: &Config, path: &Path) -> String {
+    if let Some(plugin) = &config.sniffing.plugin {
+        if needs_sniffing(config, &own_extension(path)) {
+            match plugins::run_audited(plugin, path, &config.storage.plugin_audit_log).await {
+                Ok(output) => {
+                    if let Some(ext) = parse_output(&output) {
+                        return ext;
+                    }
+                }
+                Err(e) => log::warn!("Sniffing plugin failed for {:?}: {:#}", path, e),
+            }
+        }
+    }
+    extension(config, path)
+}
+
+/// The extension of files of MIME type `mime`, for the types contextd has a
+/// handler for or a name to give.
+pub fn mime_extension(mime: &str) -> Option<&'static str> {
+    let ext = match mime.split(';').next().unwrap_or(mime).trim() {
+        "text/markdown" | "text/x-markdown" => "md",
+        "text/x-rust" => "rs",
+        "text/x-python" | "text/x-script.python" => "py",
+        "text/javascript" | "application/javascript" => "js",
+        "application/typescript" | "text/x-typescript" => "ts",
+        "text/x-go" => "go",
+        "text/x-java" | "text/x-java-source" => "java",
+        "text/x-c" | "text/x-csrc" => "c",
+        "text/x-c++" | "text/x-c++src" | "text/x-c++hdr" | "text/x-chdr" => "cpp",
+        "text/x-csharp" => "cs",
+        "text/x-ruby" | "application/x-ruby" => "rb",
+        "application/x-php" | "text/x-php" => "php",
+        "application/json" | "text/json" => "json",
+        "application/xml" | "text/xml" => "xml",
+        "text/html" | "application/xhtml+xml" => "html",
+        "application/pdf" => "pdf",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => "docx",
+        "text/x-shellscript" | "application/x-sh" => "sh",
+        "text/plain" => "txt",
+        _ => return None,
+    };
+    Some(ext)
+}
+
+fn own_extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// Whether files with extension `ext` are sniffed: sniffing is on and no
+/// plugin, chunker or the text and PDF readers take them.
+fn needs_sniffing(config: &Config, ext: &str) -> bool {
+    config.sniffing.enabled
+        && !matches!(ext, "txt" | "pdf" | "docx")
+        && !config.plugins.contains_key(ext)
+        && chunker::chunker_language(ext) == "text"
+}
+
+fn read_head(path: &Path) -> Option<Vec<u8>> {
+    let mut head = Vec::new();
+    std::fs::File::open(path)
+        .ok()?
+        .take(HEAD_BYTES)
+        .read_to_end(&mut head)
+        .ok()?;
+    Some(head)
+}
+
+/// The extension of the type `head`, the start of a file, looks like.
+fn detect(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"%PDF-") {
+        return Some("pdf");
+    }
+    // Other binary files are left to their extension
+    if head.contains(&0) {
+        return None;
+    }
+    let text = String::from_utf8_lossy(head);
+    if let Some(line) = text.strip_prefix("#!") {
+        return interpreter_extension(line.lines().next().unwrap_or(""));
+    }
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    let start: String = text.chars().take(16).collect::<String>().to_lowercase();
+    if start.starts_with("<?php") {
+        Some("php")
+    } else if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        Some("html")
+    } else if start.starts_with("<?xml") {
+        Some("xml")
+    } else if looks_like_json(text) {
+        Some("json")
+    } else {
+        None
+    }
+}
+
+/// The extension of scripts for the interpreter a `#!` line runs, as in
+/// `/usr/bin/python3 -u` or `/usr/bin/env -S node --no-warnings`.
+fn interpreter_extension(line: &str) -> Option<&'static str> {
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+    }
+    // python3.12 and the like
+    match program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+        "python" | "pypy" => Some("py"),
+        "node" | "nodejs" | "deno" | "bun" => Some("js"),
+        "ts-node" | "tsx" => Some("ts"),
+        "ruby" => Some("rb"),
+        "php" => Some("php"),
+        "sh" | "bash" | "zsh" | "dash" | "ksh" => Some("sh"),
+        _ => None,
+    }
+}
+
+/// Whether `text` is JSON, or the start of a JSON document cut off where
+/// the head ends.
+fn looks_like_json(text: &str) -> bool {
+    if !text.starts_with(['{', '[']) {
+        return false;
+    }
+    match serde_json::from_str::<serde::de::IgnoredAny>(text) {
+        Ok(_) => true,
+        Err(e) => e.is_eof(),
+    }
+}
+
+/// The extension a sniffing plugin's output names: a MIME type such as
+/// `application/json`, or an extension such as `json`.
+fn parse_output(output: &str) -> Option<String> {
+    let answer = output.lines().next()?.trim();
+    let ext = match answer.contains('/') {
+        true => mime_extension(answer)?,
+        false => answer.trim_start_matches('.'),
+    };
+    (!ext.is_empty()).then(|| ext.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(detect(b"#!/usr/bin/env python3\nimport os\n"), Some("py"));
+        assert_eq!(
+            detect(b"#!/usr/bin/env -S node --no-warnings\n"),
+            Some("js")
+        );
+        assert_eq!(detect(b"#!/bin/bash -e\nset -u\n"), Some("sh"));
+        assert_eq!(detect(b"#!/opt/bin/python3.12\n"), Some("py"));
+        assert_eq!(detect(b"#!/usr/bin/awk -f\n"), None);
+        assert_eq!(detect(b"%PDF-1.7\n\x00\x01"), Some("pdf"));
+        assert_eq!(detect(b"\xef\xbb\xbf<?xml version=\"1.0\"?>"), Some("xml"));
+        assert_eq!(detect(b"\n<!DOCTYPE html>\n<html>"), Some("html"));
+        assert_eq!(detect(b"<?php echo 1;"), Some("php"));
+        assert_eq!(
+            detect(b"{\"name\": \"contextd\", \"tags\": [1, 2]}"),
+            Some("json")
+        );
+        // Cut off by the head
+        assert_eq!(detect(b"[{\"id\": 1}, {\"id\": \"tru"), Some("json"));
+        // INI sections and prose aren't JSON
+        assert_eq!(detect(b"[core]\nbare = false\n"), None);
+        assert_eq!(detect(b"{ not json }"), None);
+        assert_eq!(detect(b"Dear diary"), None);
+        assert_eq!(detect(b"ELF\x00\x01"), None);
+
+        assert_eq!(
+            parse_output("application/json; charset=utf-8\n"),
+            Some("json".to_string())
+        );
+        assert_eq!(parse_output(".RB\n"), Some("rb".to_string()));
+        assert_eq!(parse_output("application/octet-stream\n"), None);
+        assert_eq!(parse_output(""), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("deploy");
+        std::fs::write(&script, "#!/usr/bin/env python3\nprint('hi')\n").unwrap();
+        let notes = dir.path().join("notes.rs");
+        std::fs::write(&notes, "{\"not\": \"rust\"}").unwrap();
+        let mut config = Config::default();
+        assert_eq!(extension(&config, &script), "py");
+        // A known extension is trusted
+        assert_eq!(extension(&config, &notes), "rs");
+        config.sniffing.enabled = false;
+        assert_eq!(extension(&config, &script), "");
+    }
+}
@@ -1,19 +1,261 @@
-use notify_debouncer_mini::notify::{self, RecursiveMode};
-use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
-use std::path::PathBuf;
-use std::sync::mpsc::Sender;
+use notify_debouncer_mini::notify::{
+    self, Event, EventHandler, RecursiveMode, Watcher, WatcherKind,
+};
+use notify_debouncer_mini::{new_debouncer_opt, Config, DebounceEventHandler, Debouncer};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-pub fn watch(
-    paths: &[PathBuf],
-    tx: Sender<DebounceEventResult>,
-) -> notify::Result<Debouncer<notify::RecommendedWatcher>> {
-    // Watcher configuration with 2000ms debounce
-    let mut debouncer = new_debouncer(Duration::from_millis(2000), tx)?;
+use crate::config::WatchConfig;
+
+#[cfg(target_os = "macos")]
+type PlatformWatcher = super::fsevents::FsEventsWatcher;
+#[cfg(not(target_os = "macos"))]
+type PlatformWatcher = notify::RecommendedWatcher;
+
+// Counted since the process started, see [`stats`]
+static RECEIVED: AtomicU64 = AtomicU64::new(0);
+static RECEIVED_PATHS: AtomicU64 = AtomicU64::new(0);
+static DELIVERED: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static OVERFLOWS: AtomicU64 = AtomicU64::new(0);
+
+/// What the file watcher has seen since the daemon started.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct WatcherStats {
+    /// Events the OS reported
+    pub events_received: u64,
+    /// Changes merged with another to the same path before being handled
+    pub coalesced: u64,
+    /// Events lost to watcher errors
+    pub dropped: u64,
+    /// Times the OS event queue overflowed, each followed by a rescan
+    pub overflows: u64,
+}
+
+pub fn stats() -> WatcherStats {
+    let paths = RECEIVED_PATHS.load(Ordering::Relaxed);
+    WatcherStats {
+        events_received: RECEIVED.load(Ordering::Relaxed),
+        coalesced: paths.saturating_sub(DELIVERED.load(Ordering::Relaxed)),
+        dropped: DROPPED.load(Ordering::Relaxed),
+        overflows: OVERFLOWS.load(Ordering::Relaxed),
+    }
+}
+
+/// The daemon got `paths` changed paths from the debouncer.
+pub fn delivered(paths: usize) {
+    DELIVERED.fetch_add(paths as u64, Ordering::Relaxed);
+}
+
+/// An event was lost.
+pub fn dropped() {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
 
+/// Suffixes of the files editors write before renaming them over the file
+/// being saved, or move the old version to: `notes.md.tmp`, `notes.md~`.
+const SAVE_SUFFIXES: &[&str] = &[
+    ".tmp",
+    ".temp",
+    "~",
+    ".crswap",
+    "___jb_tmp___",
+    "___jb_old___",
+];
+
+/// Suffixes and prefixes of editor swap, lock and scratch files, which never
+/// become the saved file.
+const SCRATCH_SUFFIXES: &[&str] = &[".swp", ".swx", ".swo", ".kate-swp"];
+const SCRATCH_PREFIXES: &[&str] = &[".#", ".goutputstream-", ".tmp"];
+
+/// Whether `path` is a file an editor writes while saving or editing
+/// another, rather than a file of its own.
+pub fn is_temp_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    // vim checks a directory is writable by creating 4913
+    name == "4913"
+        || (name.starts_with('#') && name.ends_with('#') && name.len() > 1)
+        || SCRATCH_PREFIXES.iter().any(|p| name.starts_with(p))
+        || SCRATCH_SUFFIXES
+            .iter()
+            .chain(SAVE_SUFFIXES)
+            .any(|s| name.len() > s.len() && name.ends_with(s))
+}
+
+/// The file an editor saves through the temporary file at `path`, when its
+/// name tells: `notes.md` for `notes.md.tmp` or `notes.md~`.
+pub fn saved_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let saved = SAVE_SUFFIXES
+        .iter()
+        .find_map(|s| name.strip_suffix(s))
+        .filter(|saved| !saved.is_empty())?;
+    Some(path.with_file_name(saved))
+}
+
+/// The paths to handle for a batch of changed `paths`. An editor saving
+/// atomically writes a temporary file and renames it over the original, so
+/// temporary files are left out and stand for the file they were saved
+/// as, which is then handled once however many of the steps were seen.
+pub fn coalesce(paths: impl IntoIterator<Item = PathBuf>) -> HashSet<PathBuf> {
+    let mut coalesced = HashSet::new();
     for path in paths {
+        if !is_temp_file(&path) {
+            coalesced.insert(path);
+        } else if let Some(saved) = saved_path(&path).filter(|p| p.is_file()) {
+            coalesced.insert(saved);
+        }
+    }
+    coalesced
+}
+
+/// Watch `watch.paths`, passing changes on to `tx` once they've settled for
+/// `watch.debounce_ms`.
+pub fn watch(
+    watch: &WatchConfig,
+    tx: impl DebounceEventHandler,
+) -> notify::Result<Debouncer<CountingWatcher>> {
+    let config = Config::default().with_timeout(Duration::from_millis(watch.debounce_ms));
+    let mut debouncer = new_debouncer_opt::<_, CountingWatcher>(config, tx)?;
+
+    // Before any path is watched, so the stream starts configured
+    #[cfg(target_os = "macos")]
+    debouncer.watcher().inner.configure(
+        Duration::from_millis(watch.fsevents_latency_ms),
+        crate::workspace::system_exclusion_dirs(watch),
+    )?;
+
+    for path in &watch.paths {
         debouncer.watcher().watch(path, RecursiveMode::Recursive)?;
     }
 
     Ok(debouncer)
 }
+
+/// The platform's watcher, counting the events it reports. The debouncer
+/// drops events without paths, so an overflow is passed on as a change to
+/// each root it affects, which the daemon then rescans.
+pub struct CountingWatcher {
+    inner: PlatformWatcher,
+    roots: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl Watcher for CountingWatcher {
+    fn new<F: EventHandler>(mut event_handler: F, config: notify::Config) -> notify::Result<Self> {
+        let roots = Arc::new(Mutex::new(Vec::new()));
+        let watched = roots.clone();
+        let inner = PlatformWatcher::new(
+            move |result: notify::Result<Event>| {
+                let result = result.map(|event| count(event, &watched.lock().unwrap()));
+                event_handler.handle_event(result);
+            },
+            config,
+        )?;
+        Ok(Self { inner, roots })
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        self.inner.watch(path, recursive_mode)?;
+        self.roots.lock().unwrap().push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        self.inner.unwatch(path)?;
+        self.roots.lock().unwrap().retain(|root| root != path);
+        Ok(())
+    }
+
+    fn kind() -> WatcherKind {
+        PlatformWatcher::kind()
+    }
+}
+
+/// Count `event`. An overflow becomes an event for the `roots` it affects:
+/// those under its paths, or all of them when it has none.
+fn count(mut event: Event, roots: &[PathBuf]) -> Event {
+    RECEIVED.fetch_add(1, Ordering::Relaxed);
+    if event.need_rescan() {
+        OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+        let affected: Vec<PathBuf> = roots
+            .iter()
+            .filter(|root| {
+                event.paths.is_empty()
+                    || event
+                        .paths
+                        .iter()
+                        .any(|path| path.starts_with(root) || root.starts_with(path))
+            })
+            .cloned()
+            .collect();
+        log::warn!("File watcher events were lost; rescanning {:?}", affected);
+        event.paths = affected;
+    }
+    RECEIVED_PATHS.fetch_add(event.paths.len() as u64, Ordering::Relaxed);
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{EventKind, Flag};
+
+    #[test]
+    fn test_coalesce() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.md");
+        std::fs::write(&notes, "# Notes").unwrap();
+        let temp = |name: &str| dir.path().join(name);
+        for name in [
+            "notes.md.tmp",
+            "notes.md~",
+            ".notes.md.swp",
+            ".#notes.md",
+            "4913",
+        ] {
+            assert!(is_temp_file(&temp(name)), "{}", name);
+        }
+        assert!(!is_temp_file(&notes));
+        assert!(!is_temp_file(&temp("~")));
+        assert_eq!(saved_path(&temp("notes.md.tmp")), Some(notes.clone()));
+        assert_eq!(saved_path(&temp(".notes.md.swp")), None);
+
+        // Written, backed up and renamed over the original: one change
+        let paths = [
+            "notes.md.tmp",
+            "notes.md~",
+            "notes.md",
+            ".notes.md.swp",
+            "4913",
+        ];
+        let coalesced = coalesce(paths.map(temp));
+        assert_eq!(coalesced, HashSet::from([notes]));
+        // Saved under a name that's gone again: nothing
+        assert!(coalesce([temp("draft.md.tmp")]).is_empty());
+    }
+
+    #[test]
+    fn test_overflow() {
+        let roots = vec![PathBuf::from("/repo/a"), PathBuf::from("/repo/b")];
+        let before = stats();
+
+        let overflow = Event::new(EventKind::Other).set_flag(Flag::Rescan);
+        assert_eq!(count(overflow, &roots).paths, roots);
+        let partial = Event::new(EventKind::Other)
+            .set_flag(Flag::Rescan)
+            .add_path(PathBuf::from("/repo/b/src"));
+        assert_eq!(count(partial, &roots).paths, vec![roots[1].clone()]);
+        let change = Event::new(EventKind::Any).add_path(PathBuf::from("/repo/a/x.rs"));
+        assert_eq!(count(change, &roots).paths.len(), 1);
+
+        let after = stats();
+        assert!(after.events_received - before.events_received >= 3);
+        assert_eq!(after.overflows - before.overflows, 2);
+    }
+}
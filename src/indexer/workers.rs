@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::config::IndexingConfig;
+
+/// Workers the pool starts with before the autoscaler has measured anything.
+const INITIAL_WORKERS: usize = 4;
+
+/// How often the autoscaler samples CPU use and backlog.
+const ADJUST_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Which files a free worker takes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Files being worked on: changed on disk or asked for through
+    /// `POST /prioritize`
+    High,
+    /// A bulk backfill, like the initial scan, which can be paused
+    Low,
+}
+
+/// Bounds the number of files indexed at once. The limit moves between
+/// `min_workers` and `max_workers` as the autoscaler sees CPU headroom and
+/// queued files.
+pub struct WorkerPool {
+    semaphore: Arc<Semaphore>,
+    limit: AtomicUsize,
+    /// Tasks waiting for a permit
+    waiting: AtomicUsize,
+    /// [`Priority::High`] tasks waiting for a permit, which low priority
+    /// ones give way to
+    high_waiting: AtomicUsize,
+    /// Low priority tasks wait for permits until resumed
+    backfill_paused: AtomicBool,
+    /// Notified when low priority tasks may get permits again: the last high
+    /// priority task got its permit or the backfill resumed
+    low_turn: Notify,
+    /// Over the memory budget: hold the pool at its minimum
+    pressure: AtomicBool,
+    /// On battery power: hold the pool at its minimum
+    battery: AtomicBool,
+    /// Workers currently indexing
+    active: Arc<AtomicUsize>,
+    /// Shutting down: hand out no more permits
+    stopping: AtomicBool,
+    /// Files being indexed, and whether they changed again meanwhile
+    claimed: Mutex<HashMap<PathBuf, bool>>,
+    min: usize,
+    max: usize,
+    target_cpu: f32,
+}
+
+impl WorkerPool {
+    pub fn new(config: &IndexingConfig) -> Arc<Self> {
+        let max = config.max_workers.max(1);
+        let min = config.min_workers.clamp(1, max);
+        let limit = INITIAL_WORKERS.clamp(min, max);
+        Arc::new(Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            limit: AtomicUsize::new(limit),
+            waiting: AtomicUsize::new(0),
+            high_waiting: AtomicUsize::new(0),
+            backfill_paused: AtomicBool::new(false),
+            low_turn: Notify::new(),
+            pressure: AtomicBool::new(false),
+            battery: AtomicBool::new(false),
+            active: Arc::new(AtomicUsize::new(0)),
+            stopping: AtomicBool::new(false),
+            claimed: Mutex::new(HashMap::new()),
+            min,
+            max,
+            target_cpu: config.target_cpu_percent as f32 / 100.0,
+        })
+    }
+
+    /// Wait for a free worker slot, held until the permit is dropped. `None`
+    /// once the pool is shutting down. Low priority tasks only get a slot
+    /// while no high priority one is waiting and the backfill isn't paused.
+    pub async fn acquire(&self, priority: Priority) -> Option<WorkerPermit> {
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let permit = match priority {
+            Priority::High => {
+                self.high_waiting.fetch_add(1, Ordering::SeqCst);
+                let permit = self.semaphore.clone().acquire_owned().await.unwrap();
+                if self.high_waiting.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    self.low_turn.notify_waiters();
+                }
+                permit
+            }
+            Priority::Low => loop {
+                let turn = self.low_turn.notified();
+                if !self.low_may_run() {
+                    turn.await;
+                    continue;
+                }
+                let permit = self.semaphore.clone().acquire_owned().await.unwrap();
+                // Handed back, it goes to the high priority task queued
+                // behind this one
+                if self.low_may_run() {
+                    break permit;
+                }
+            },
+        };
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        if self.stopping.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Some(WorkerPermit {
+            _permit: permit,
+            active: self.active.clone(),
+        })
+    }
+
+    fn low_may_run(&self) -> bool {
+        self.high_waiting.load(Ordering::SeqCst) == 0
+            && !self.backfill_paused.load(Ordering::SeqCst)
+    }
+
+    /// Hand low priority tasks no permits while `paused` is set; high
+    /// priority ones still get them.
+    pub fn pause_backfill(&self, paused: bool) {
+        self.backfill_paused.store(paused, Ordering::SeqCst);
+        if !paused {
+            self.low_turn.notify_waiters();
+        }
+    }
+
+    /// Stop handing out permits and wait up to `grace` for running workers.
+    /// Returns whether they all finished.
+    pub async fn shutdown(&self, grace: Duration) -> bool {
+        self.stopping.store(true, Ordering::SeqCst);
+        let deadline = tokio::time::Instant::now() + grace;
+        while self.active.load(Ordering::SeqCst) > 0 {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        true
+    }
+
+    /// Claim `path` to index it. Returns `false` if it's already claimed,
+    /// and [`release`](Self::release) then asks for it to be indexed again,
+    /// so a file saved twice in a row isn't indexed twice at once.
+    pub fn claim(&self, path: &Path) -> bool {
+        let mut claimed = self.claimed.lock().unwrap();
+        match claimed.get_mut(path) {
+            Some(changed) => {
+                *changed = true;
+                false
+            }
+            None => {
+                claimed.insert(path.to_path_buf(), false);
+                true
+            }
+        }
+    }
+
+    /// Done indexing `path`. Returns `true`, keeping the claim, if it was
+    /// claimed again meanwhile and needs indexing once more.
+    pub fn release(&self, path: &Path) -> bool {
+        let mut claimed = self.claimed.lock().unwrap();
+        match claimed.get_mut(path) {
+            Some(changed) if *changed => {
+                *changed = false;
+                true
+            }
+            _ => {
+                claimed.remove(path);
+                false
+            }
+        }
+    }
+
+    /// Files waiting for a worker or being indexed.
+    pub fn pending(&self) -> usize {
+        self.waiting.load(Ordering::SeqCst) + self.active.load(Ordering::SeqCst)
+    }
+
+    /// Current worker limit.
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::SeqCst)
+    }
+
+    /// Drop to the minimum number of workers, and keep it there, while
+    /// `pressure` is set.
+    pub fn set_pressure(&self, pressure: bool) {
+        self.pressure.store(pressure, Ordering::SeqCst);
+        if pressure {
+            self.resize(self.min);
+        }
+    }
+
+    /// Drop to the minimum number of workers, and keep it there, while on
+    /// `battery` power.
+    pub fn set_battery(&self, battery: bool) {
+        self.battery.store(battery, Ordering::SeqCst);
+        if battery {
+            self.resize(self.min);
+        }
+    }
+
+    fn resize(&self, limit: usize) {
+        let current = self.limit.swap(limit, Ordering::SeqCst);
+        if limit > current {
+            self.semaphore.add_permits(limit - current);
+        } else if limit < current {
+            // Retire permits as running workers hand them back
+            let semaphore = self.semaphore.clone();
+            tokio::spawn(async move {
+                if let Ok(permits) = semaphore.acquire_many_owned((current - limit) as u32).await {
+                    permits.forget();
+                }
+            });
+        }
+    }
+
+    /// Periodically resize the pool until the runtime shuts down.
+    pub fn spawn_autoscaler(self: &Arc<Self>) {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            let mut cpu = CpuSampler::new();
+            let mut interval = tokio::time::interval(ADJUST_INTERVAL);
+            loop {
+                interval.tick().await;
+                let current = pool.limit();
+                let backlog = pool.waiting.load(Ordering::SeqCst);
+                let busy = cpu.sample();
+                if pool.pressure.load(Ordering::SeqCst) || pool.battery.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let next = next_limit(current, backlog, busy, pool.target_cpu, pool.min, pool.max);
+                if next != current {
+                    log::debug!(
+                        "Indexing workers {} -> {} (backlog {})",
+                        current,
+                        next,
+                        backlog
+                    );
+                    pool.resize(next);
+                }
+            }
+        });
+    }
+}
+
+/// A worker slot, released on drop.
+pub struct WorkerPermit {
+    _permit: OwnedSemaphorePermit,
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for WorkerPermit {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The worker limit to move to: one more while files are queued and the
+/// CPU has headroom, one fewer once it is busier than the target. Without a
+/// CPU reading the backlog alone decides.
+fn next_limit(
+    current: usize,
+    backlog: usize,
+    cpu: Option<f32>,
+    target: f32,
+    min: usize,
+    max: usize,
+) -> usize {
+    let next = match cpu {
+        Some(busy) if busy > target => current.saturating_sub(1),
+        _ if backlog > 0 => current + 1,
+        _ => current,
+    };
+    next.clamp(min, max)
+}
+
+/// System-wide CPU utilization from `/proc/stat`, between calls.
+struct CpuSampler {
+    last: Option<(u64, u64)>,
+}
+
+impl CpuSampler {
+    fn new() -> Self {
+        let mut sampler = Self { last: None };
+        sampler.sample();
+        sampler
+    }
+
+    /// Busy fraction since the previous sample; `None` where `/proc/stat` is
+    /// unavailable (non-Linux) or on the first call.
+    fn sample(&mut self) -> Option<f32> {
+        let stat = std::fs::read_to_string("/proc/stat").ok()?;
+        let (busy, total) = parse_cpu_line(stat.lines().next()?)?;
+        let (last_busy, last_total) = self.last.replace((busy, total))?;
+        let elapsed = total.checked_sub(last_total).filter(|t| *t > 0)?;
+        Some(busy.saturating_sub(last_busy) as f32 / elapsed as f32)
+    }
+}
+
+/// Busy and total jiffies from the aggregate `cpu` line of `/proc/stat`.
+fn parse_cpu_line(line: &str) -> Option<(u64, u64)> {
+    let mut fields = line.split_whitespace();
+    if fields.next()? != "cpu" {
+        return None;
+    }
+    let values: Vec<u64> = fields.filter_map(|f| f.parse().ok()).collect();
+    if values.len() < 4 {
+        return None;
+    }
+    // user nice system idle iowait irq softirq steal ...; guest time is
+    // already counted in user
+    let idle = values[3] + values.get(4).copied().unwrap_or(0);
+    let total: u64 = values.iter().take(8).sum();
+    Some((total - idle, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_limit() {
+        // Backlog with headroom grows the pool, up to the maximum
+        assert_eq!(next_limit(4, 10, Some(0.3), 0.8, 1, 8), 5);
+        assert_eq!(next_limit(8, 10, Some(0.3), 0.8, 1, 8), 8);
+        // A busy machine shrinks it, down to the minimum
+        assert_eq!(next_limit(4, 10, Some(0.95), 0.8, 1, 8), 3);
+        assert_eq!(next_limit(1, 10, Some(0.95), 0.8, 1, 8), 1);
+        // Nothing queued: stay put
+        assert_eq!(next_limit(4, 0, Some(0.1), 0.8, 1, 8), 4);
+        assert_eq!(next_limit(4, 3, None, 0.8, 1, 8), 5);
+    }
+
+    #[test]
+    fn test_claim() {
+        let pool = WorkerPool::new(&IndexingConfig::default());
+        let path = Path::new("/repo/notes.md");
+        assert!(pool.claim(path));
+        // Saved again mid-index: indexed once more afterwards
+        assert!(!pool.claim(path));
+        assert!(!pool.claim(path));
+        assert!(pool.release(path));
+        assert!(!pool.release(path));
+        assert!(pool.claim(path));
+    }
+
+    #[test]
+    fn test_parse_cpu_line() {
+        let line = "cpu  100 0 50 800 50 0 0 0 0 0";
+        assert_eq!(parse_cpu_line(line), Some((150, 1000)));
+        assert_eq!(parse_cpu_line("cpu0 1 2 3 4"), None);
+    }
+
+    #[tokio::test]
+    async fn test_resize() {
+        let config = IndexingConfig {
+            min_workers: 1,
+            max_workers: 2,
+            target_cpu_percent: 80,
+            ..Default::default()
+        };
+        let pool = WorkerPool::new(&config);
+        assert_eq!(pool.limit(), 2);
+
+        let held = pool.acquire(Priority::High).await.unwrap();
+        pool.resize(1);
+        drop(held);
+        tokio::task::yield_now().await;
+        let _only = pool.acquire(Priority::High).await.unwrap();
+        assert_eq!(pool.semaphore.available_permits(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_priority() {
+        let config = IndexingConfig {
+            min_workers: 1,
+            max_workers: 1,
+            ..Default::default()
+        };
+        let pool = WorkerPool::new(&config);
+        let held = pool.acquire(Priority::Low).await.unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for (name, priority) in [("backfill", Priority::Low), ("edited", Priority::High)] {
+            let pool = pool.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _permit = pool.acquire(priority).await.unwrap();
+                tx.send(name).unwrap();
+            });
+            // Queued in this order
+            tokio::task::yield_now().await;
+        }
+
+        // Unplugged: the backfill waits even with a worker free
+        pool.pause_backfill(true);
+        drop(held);
+        assert_eq!(rx.recv().await, Some("edited"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(rx.try_recv().is_err());
+        pool.pause_backfill(false);
+        assert_eq!(rx.recv().await, Some("backfill"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let pool = WorkerPool::new(&IndexingConfig::default());
+        let held = pool.acquire(Priority::High).await.unwrap();
+        assert!(!pool.shutdown(Duration::from_millis(100)).await);
+        drop(held);
+        assert!(pool.shutdown(Duration::from_millis(100)).await);
+        assert!(pool.acquire(Priority::High).await.is_none());
+    }
+}
@@ -1,9 +1,45 @@
 pub mod api;
+pub mod archive;
+pub mod audit;
+pub mod bench;
+pub mod changelog;
+pub mod citation;
 pub mod cli;
+pub mod collections;
 pub mod config;
 pub mod connect;
 pub mod daemon;
+pub mod digest;
+pub mod documents;
 pub mod download;
+pub mod editor;
+pub mod ensemble;
+pub mod eval;
+pub mod explain;
+pub mod faults;
+pub mod files;
+pub mod freshness;
+pub mod gc;
+pub mod history;
 pub mod indexer;
+pub mod limits;
+pub mod logging;
+pub mod lsp;
+pub mod maintenance;
 pub mod mcp;
+pub mod migration;
+pub mod onboarding;
+pub mod paths;
+pub mod power;
+pub mod reindex;
+pub mod report;
+pub mod retention;
+pub mod scan;
+pub mod search;
+pub mod service;
+pub mod session;
+pub mod staleness;
 pub mod storage;
+pub mod test_support;
+pub mod transcripts;
+pub mod workspace;
@@ -0,0 +1,313 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::indexer::workers::WorkerPool;
+use crate::storage::db::Database;
+
+/// How often the daemon checks its memory use and disk space.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+const MB: u64 = 1024 * 1024;
+
+/// Fraction of the budget usage has to fall below before indexing resumes at
+/// full speed, so the daemon doesn't flap around the limit.
+const RELIEF: f64 = 0.9;
+
+/// `[limits]` config.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LimitsConfig {
+    /// Resident memory above which the daemon sheds caches, drops to the
+    /// minimum number of indexing workers and defers watcher-triggered indexing
+    #[serde(default)]
+    pub max_memory_mb: Option<u64>,
+    /// Pause indexing once the database (with its write-ahead log) reaches
+    /// this size
+    #[serde(default)]
+    pub max_db_mb: Option<u64>,
+    /// Pause indexing when less than this is free on the database's disk
+    #[serde(default = "default_min_free_disk_mb")]
+    pub min_free_disk_mb: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_memory_mb: None,
+            max_db_mb: None,
+            min_free_disk_mb: default_min_free_disk_mb(),
+        }
+    }
+}
+
+fn default_min_free_disk_mb() -> u64 {
+    256
+}
+
+/// Memory use against the budget, as reported by `/stats`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MemoryStats {
+    /// Resident set size; `None` where the OS doesn't report it
+    pub resident_bytes: Option<u64>,
+    pub budget_bytes: Option<u64>,
+    pub over_budget: bool,
+}
+
+impl MemoryStats {
+    pub fn current(config: &LimitsConfig) -> Self {
+        let resident_bytes = resident_bytes();
+        let budget_bytes = config.max_memory_mb.map(|mb| mb * MB);
+        Self {
+            resident_bytes,
+            budget_bytes,
+            over_budget: matches!((resident_bytes, budget_bytes), (Some(r), Some(b)) if r > b),
+        }
+    }
+}
+
+/// Resident set size of this process, from `/proc/self/status` (Linux only).
+pub fn resident_bytes() -> Option<u64> {
+    parse_vm_rss(&std::fs::read_to_string("/proc/self/status").ok()?)
+}
+
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Tracks whether the daemon is over its memory budget.
+pub struct MemoryGuard {
+    budget: Option<u64>,
+    over: AtomicBool,
+}
+
+impl MemoryGuard {
+    pub fn new(config: &LimitsConfig) -> Arc<Self> {
+        Arc::new(Self {
+            budget: config.max_memory_mb.map(|mb| mb * MB),
+            over: AtomicBool::new(false),
+        })
+    }
+
+    /// Whether non-urgent indexing should wait.
+    pub fn over_budget(&self) -> bool {
+        self.over.load(Ordering::SeqCst)
+    }
+
+    /// Record a usage reading. Returns the new state when it flips.
+    fn update(&self, resident: u64) -> Option<bool> {
+        let budget = self.budget?;
+        let over = self.over_budget();
+        let next = if over {
+            resident as f64 >= budget as f64 * RELIEF
+        } else {
+            resident > budget
+        };
+        self.over.store(next, Ordering::SeqCst);
+        (next != over).then_some(next)
+    }
+
+    /// Check memory use periodically, shedding load while over budget. Does
+    /// nothing without a budget.
+    pub fn spawn(self: &Arc<Self>, db: Database, workers: Arc<WorkerPool>) {
+        let Some(budget) = self.budget else {
+            return;
+        };
+        let guard = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(resident) = resident_bytes() else {
+                    log::warn!(
+                        "Memory use is not reported on this platform; ignoring max_memory_mb"
+                    );
+                    return;
+                };
+                match guard.update(resident) {
+                    Some(true) => {
+                        log::warn!(
+                            "Memory use {} MB is over the {} MB budget; shrinking caches and deferring indexing",
+                            resident / 1024 / 1024,
+                            budget / 1024 / 1024
+                        );
+                        workers.set_pressure(true);
+                        if let Err(e) = db.call(|db| db.shrink_memory()).await {
+                            log::warn!("Failed to shrink database cache: {}", e);
+                        }
+                    }
+                    Some(false) => {
+                        log::info!(
+                            "Memory use back to {} MB; resuming indexing",
+                            resident / 1024 / 1024
+                        );
+                        workers.set_pressure(false);
+                    }
+                    None => {}
+                }
+            }
+        });
+    }
+}
+
+/// Why indexing has to pause to protect the disk holding `db_path`, if it
+/// has to.
+pub fn disk_problem(db_path: &Path, config: &LimitsConfig) -> Option<String> {
+    if let Some(max) = config.max_db_mb {
+        let size = db_size(db_path);
+        if size >= max * MB {
+            return Some(format!(
+                "index is {} MB, at the [limits] max_db_mb cap of {} MB",
+                size / MB,
+                max
+            ));
+        }
+    }
+    let dir = match db_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match free_space(dir) {
+        Some(free) if free < config.min_free_disk_mb * MB => Some(format!(
+            "only {} MB free on the disk holding {:?} ([limits] min_free_disk_mb = {})",
+            free / MB,
+            db_path,
+            config.min_free_disk_mb
+        )),
+        _ => None,
+    }
+}
+
+/// Size of the database and its write-ahead log.
+fn db_size(db_path: &Path) -> u64 {
+    let mut wal = db_path.as_os_str().to_owned();
+    wal.push("-wal");
+    [db_path.to_path_buf(), PathBuf::from(wal)]
+        .iter()
+        .filter_map(|p| std::fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `dir`.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // the field types differ between platforms
+fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out-pointer
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// Tracks whether indexing is paused to protect the disk.
+pub struct DiskGuard {
+    db_path: PathBuf,
+    config: LimitsConfig,
+    problem: Mutex<Option<String>>,
+}
+
+impl DiskGuard {
+    pub fn new(db_path: &Path, config: &LimitsConfig) -> Arc<Self> {
+        let problem = disk_problem(db_path, config);
+        if let Some(problem) = &problem {
+            log::warn!("Indexing paused: {}", problem);
+        }
+        Arc::new(Self {
+            db_path: db_path.to_path_buf(),
+            config: config.clone(),
+            problem: Mutex::new(problem),
+        })
+    }
+
+    /// Why indexing is paused, if it is.
+    pub fn problem(&self) -> Option<String> {
+        self.problem.lock().unwrap().clone()
+    }
+
+    /// Re-check the disk periodically, logging when indexing pauses or resumes.
+    pub fn spawn(self: &Arc<Self>) {
+        let guard = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let problem = disk_problem(&guard.db_path, &guard.config);
+                let mut current = guard.problem.lock().unwrap();
+                match (&*current, &problem) {
+                    (None, Some(problem)) => log::warn!("Indexing paused: {}", problem),
+                    (Some(_), None) => log::info!("Disk space recovered; resuming indexing"),
+                    _ => {}
+                }
+                *current = problem;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_rss() {
+        let status = "Name:\tcontextd\nVmPeak:\t  900 kB\nVmRSS:\t  2048 kB\n";
+        assert_eq!(parse_vm_rss(status), Some(2048 * 1024));
+        assert_eq!(parse_vm_rss("Name:\tcontextd\n"), None);
+    }
+
+    #[test]
+    fn test_budget_hysteresis() {
+        let guard = MemoryGuard::new(&LimitsConfig {
+            max_memory_mb: Some(100),
+            ..Default::default()
+        });
+        let mb = 1024 * 1024;
+        assert_eq!(guard.update(80 * mb), None);
+        assert_eq!(guard.update(120 * mb), Some(true));
+        assert!(guard.over_budget());
+        // Still within 10% of the budget: stay deferred
+        assert_eq!(guard.update(95 * mb), None);
+        assert_eq!(guard.update(85 * mb), Some(false));
+
+        let unlimited = MemoryGuard::new(&LimitsConfig::default());
+        assert_eq!(unlimited.update(u64::MAX), None);
+        assert!(!unlimited.over_budget());
+    }
+
+    #[test]
+    fn test_disk_problem() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("contextd.db");
+        std::fs::write(&db_path, vec![0u8; 1024 * 1024]).unwrap();
+        std::fs::write(dir.path().join("contextd.db-wal"), vec![0u8; 1024 * 1024]).unwrap();
+
+        let mut config = LimitsConfig {
+            min_free_disk_mb: 0,
+            ..Default::default()
+        };
+        assert_eq!(disk_problem(&db_path, &config), None);
+        config.max_db_mb = Some(2);
+        let problem = disk_problem(&db_path, &config).unwrap();
+        assert!(problem.contains("index is 2 MB"));
+
+        config.max_db_mb = None;
+        config.min_free_disk_mb = u64::MAX / MB;
+        if free_space(dir.path()).is_some() {
+            assert!(disk_problem(&db_path, &config)
+                .unwrap()
+                .contains("free on the disk"));
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use anyhow::{bail, Context, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// `[logging]` config.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoggingConfig {
+    /// Levels as `default,module=level,...`, e.g.
+    /// `"info,contextd::indexer=debug,ort=warn"`
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Also append log lines to this file, rotating it as configured
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Rotate the file once it grows past this many megabytes (0 disables)
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+    /// Also rotate the file when a new UTC hour or day begins
+    #[serde(default)]
+    pub rotate: RotateInterval,
+    /// Rotated files kept as `<file>.1` (newest) to `<file>.<keep>`; older
+    /// ones are deleted
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            file: None,
+            max_size_mb: default_max_size_mb(),
+            rotate: RotateInterval::default(),
+            keep: default_keep(),
+        }
+    }
+}
+
+fn default_max_size_mb() -> u64 {
+    10
+}
+
+fn default_keep() -> usize {
+    5
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RotateInterval {
+    Hourly,
+    Daily,
+    #[default]
+    Never,
+}
+
+impl RotateInterval {
+    fn seconds(self) -> Option<u64> {
+        match self {
+            RotateInterval::Hourly => Some(3600),
+            RotateInterval::Daily => Some(86_400),
+            RotateInterval::Never => None,
+        }
+    }
+}
+
+/// A log file that is renamed aside and reopened when it grows too large or
+/// its period ends.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    /// Start of the period the current file covers, in seconds
+    period_start: u64,
+    max_bytes: u64,
+    interval: RotateInterval,
+    keep: usize,
+}
+
+impl RotatingFile {
+    pub fn open(
+        path: &Path,
+        max_bytes: u64,
+        interval: RotateInterval,
+        keep: usize,
+    ) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open log file {:?}", path))?;
+        let metadata = file.metadata()?;
+        // An existing file belongs to the period it was last written in
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or_else(now);
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size: metadata.len(),
+            period_start: period_start(modified, interval),
+            max_bytes,
+            interval,
+            keep,
+        })
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.write_line_at(line, now())
+    }
+
+    fn write_line_at(&mut self, line: &str, now: u64) -> io::Result<()> {
+        let too_big = self.max_bytes > 0
+            && self.size > 0
+            && self.size + line.len() as u64 + 1 > self.max_bytes;
+        let new_period = period_start(now, self.interval) != self.period_start;
+        if too_big || (new_period && self.size > 0) {
+            self.rotate()?;
+        }
+        self.period_start = period_start(now, self.interval);
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Shift `<file>.N` to `<file>.N+1`, dropping the oldest, and start afresh.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+}
+
+fn period_start(secs: u64, interval: RotateInterval) -> u64 {
+    match interval.seconds() {
+        Some(period) => secs - secs % period,
+        None => 0,
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Per-module log levels; the longest matching module path wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    /// Level for modules no directive names, if the filter sets one
+    pub default: Option<LevelFilter>,
+    pub directives: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut filter = Filter {
+            default: None,
+            directives: Vec::new(),
+        };
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                Some((module, level)) => {
+                    let level = parse_level(level)?;
+                    filter.directives.push((module.trim().to_string(), level));
+                }
+                None => match parse_level(part) {
+                    Ok(level) => filter.default = Some(level),
+                    // A bare module name enables everything it logs
+                    Err(_) => filter
+                        .directives
+                        .push((part.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+        Ok(filter)
+    }
+
+    fn level_for(&self, target: &str, default: LevelFilter) -> LevelFilter {
+        self.directives
+            .iter()
+            .filter(|(module, _)| {
+                target == module
+                    || target
+                        .strip_prefix(module.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(default)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    match level.trim().parse() {
+        Ok(level) => Ok(level),
+        Err(_) => bail!(
+            "Unknown log level '{}' (expected off, error, warn, info, debug or trace)",
+            level.trim()
+        ),
+    }
+}
+
+/// `level` raised by `verbosity` steps (or lowered, if negative).
+pub fn adjust(level: LevelFilter, verbosity: i8) -> LevelFilter {
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace,
+    ];
+    let index = (level as i64 + verbosity as i64).clamp(0, LEVELS.len() as i64 - 1);
+    LEVELS[index as usize]
+}
+
+struct Logger {
+    level: LevelFilter,
+    filter: Filter,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level_for(metadata.target(), self.level)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        eprintln!("{}", record.args());
+        if let Some(file) = &self.file {
+            let line = format!(
+                "{} {:<5} {}: {}",
+                chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            // Nowhere left to report a failing log file but stderr
+            if let Err(e) = file.lock().unwrap().write_line(&line) {
+                eprintln!("Failed to write log file: {}", e);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().file.flush();
+        }
+    }
+}
+
+/// Install the process-wide logger: messages go to stderr and, if
+/// configured, to the rotating log file. Modules the filter doesn't name log
+/// at its default level, else `base`, shifted by `verbosity` (`-v`/`-q`).
+pub fn init(config: &LoggingConfig, base: LevelFilter, verbosity: i8) -> Result<()> {
+    let filter = match &config.filter {
+        Some(spec) => Filter::parse(spec).context("Invalid [logging] filter")?,
+        None => Filter::parse("")?,
+    };
+    let level = adjust(filter.default.unwrap_or(base), verbosity);
+    let file = match &config.file {
+        Some(path) => Some(Mutex::new(RotatingFile::open(
+            path,
+            config.max_size_mb * 1024 * 1024,
+            config.rotate,
+            config.keep,
+        )?)),
+        None => None,
+    };
+    let max_level = filter
+        .directives
+        .iter()
+        .map(|(_, level)| *level)
+        .fold(level, Ord::max);
+    log::set_boxed_logger(Box::new(Logger {
+        level,
+        filter,
+        file,
+    }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_filter() {
+        let filter =
+            Filter::parse("warn, contextd::indexer=debug, contextd::indexer::plugins=error, ort")
+                .unwrap();
+        assert_eq!(filter.default, Some(LevelFilter::Warn));
+        let default = LevelFilter::Warn;
+        assert_eq!(
+            filter.level_for("contextd::indexer::chunker", default),
+            LevelFilter::Debug
+        );
+        assert_eq!(
+            filter.level_for("contextd::indexer::plugins", default),
+            LevelFilter::Error
+        );
+        assert_eq!(
+            filter.level_for("contextd::indexers", default),
+            LevelFilter::Warn
+        );
+        assert_eq!(
+            filter.level_for("ort::session", default),
+            LevelFilter::Trace
+        );
+        assert!(Filter::parse("contextd=loud").is_err());
+    }
+
+    #[test]
+    fn test_adjust() {
+        assert_eq!(adjust(LevelFilter::Info, 1), LevelFilter::Debug);
+        assert_eq!(adjust(LevelFilter::Info, 5), LevelFilter::Trace);
+        assert_eq!(adjust(LevelFilter::Info, -1), LevelFilter::Warn);
+        assert_eq!(adjust(LevelFilter::Error, -3), LevelFilter::Off);
+    }
+
+    #[test]
+    fn test_size_rotation() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("logs/contextd.log");
+        let mut file = RotatingFile::open(&path, 20, RotateInterval::Never, 2).unwrap();
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            file.write_line_at(line, 0).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth line\n");
+        let newest = dir.path().join("logs/contextd.log.1");
+        assert_eq!(fs::read_to_string(newest).unwrap(), "third line\n");
+        assert!(dir.path().join("logs/contextd.log.2").exists());
+        assert!(!dir.path().join("logs/contextd.log.3").exists());
+    }
+
+    #[test]
+    fn test_daily_rotation() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("contextd.log");
+        let mut file = RotatingFile::open(&path, 0, RotateInterval::Daily, 5).unwrap();
+        let day = 86_400;
+        file.write_line_at("monday", 10 * day + 5).unwrap();
+        file.write_line_at("still monday", 11 * day - 1).unwrap();
+        file.write_line_at("tuesday", 11 * day).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "tuesday\n");
+        let rotated = fs::read_to_string(dir.path().join("contextd.log.1")).unwrap();
+        assert_eq!(rotated, "monday\nstill monday\n");
+    }
+}
@@ -0,0 +1,6 @@
+// LSP (Language Server Protocol) Server Implementation
+// Lets editors search the index through `workspace/symbol` and `contextd/search`
+
+mod server;
+
+pub use server::run_lsp_server;
@@ -0,0 +1,448 @@
+use crate::audit::AuditLog;
+use crate::citation::Citer;
+use crate::collections::Models;
+use crate::config::Config;
+use crate::paths::{canonicalize, is_virtual_path};
+use crate::search::{self, QueryService};
+use crate::storage::db::{Database, SearchOptions, SearchResult};
+use crate::workspace;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+
+/// Results returned for `workspace/symbol`; `contextd/search` defaults to
+/// `[search] default_limit`.
+const SYMBOL_LIMIT: usize = 20;
+
+/// Longest symbol name shown for a chunk.
+const MAX_NAME_CHARS: usize = 80;
+
+// LSP error codes
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const INTERNAL_ERROR: i32 = -32603;
+const PARSE_ERROR: i32 = -32700;
+
+// LSP SymbolKind values
+const KIND_FILE: u8 = 1;
+const KIND_NAMESPACE: u8 = 3;
+const KIND_CLASS: u8 = 5;
+const KIND_METHOD: u8 = 6;
+const KIND_ENUM: u8 = 10;
+const KIND_INTERFACE: u8 = 11;
+const KIND_FUNCTION: u8 = 12;
+const KIND_STRING: u8 = 15;
+const KIND_STRUCT: u8 = 23;
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    id: Option<Value>,
+    method: Option<String>,
+    params: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchParams {
+    query: String,
+    limit: Option<usize>,
+    #[serde(default, rename = "fileTypes")]
+    file_types: Option<Vec<String>>,
+    #[serde(rename = "minScore")]
+    min_score: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct Position {
+    line: usize,
+    character: usize,
+}
+
+#[derive(Serialize)]
+struct Range {
+    start: Position,
+    end: Position,
+}
+
+#[derive(Serialize)]
+struct Location {
+    uri: String,
+    range: Range,
+}
+
+#[derive(Serialize)]
+struct SymbolInformation {
+    name: String,
+    kind: u8,
+    location: Location,
+    #[serde(rename = "containerName")]
+    container_name: String,
+}
+
+/// One hit of a `contextd/search` request.
+#[derive(Serialize)]
+struct SearchHit {
+    #[serde(rename = "chunkId")]
+    chunk_id: i64,
+    location: Location,
+    score: f32,
+    content: String,
+}
+
+struct LspServer {
+    db: Database,
+    models: Arc<Models>,
+    config: Config,
+    shutting_down: bool,
+    /// `clientInfo` name the editor sent in `initialize`
+    client: Option<String>,
+    audit: AuditLog,
+}
+
+impl LspServer {
+    /// The response to `message`, if it is a request.
+    fn handle(&mut self, message: Message) -> Option<Value> {
+        let method = message.method.unwrap_or_default();
+        let id = message.id?;
+        let params = message.params.unwrap_or(Value::Null);
+
+        let result = match method.as_str() {
+            "initialize" => {
+                self.client = params["clientInfo"]["name"].as_str().map(String::from);
+                Ok(self.initialize(&params))
+            }
+            "shutdown" => {
+                self.shutting_down = true;
+                Ok(Value::Null)
+            }
+            "workspace/symbol" => {
+                let query = params.get("query").and_then(|q| q.as_str()).unwrap_or("");
+                self.workspace_symbol(query)
+            }
+            "contextd/search" => match serde_json::from_value::<SearchParams>(params) {
+                Ok(params) => self.search(params),
+                Err(e) => Err((INVALID_PARAMS, e.to_string())),
+            },
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+        };
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message }
+            }),
+        })
+    }
+
+    fn initialize(&self, params: &Value) -> Value {
+        // Have the daemon index the editor's workspace folders, if allowed
+        let folders = params
+            .get("workspaceFolders")
+            .and_then(|f| f.as_array())
+            .map(|folders| folders.iter().filter_map(|f| f.get("uri")).collect())
+            .unwrap_or_else(|| params.get("rootUri").into_iter().collect::<Vec<_>>());
+        let register = self.config.watch.register_workspaces;
+        for uri in folders
+            .into_iter()
+            .filter_map(|u| u.as_str())
+            .filter(|_| register)
+        {
+            if let Some(path) = workspace::path_from_uri(uri).and_then(|p| canonicalize(&p).ok()) {
+                if let Err(e) = self.db.register_workspace(&path) {
+                    log::warn!("Failed to register workspace {:?}: {}", path, e);
+                }
+            }
+        }
+
+        json!({
+            "capabilities": {
+                "workspaceSymbolProvider": true,
+                "experimental": { "contextdSearch": true }
+            },
+            "serverInfo": {
+                "name": "contextd",
+                "version": env!("CARGO_PKG_VERSION")
+            }
+        })
+    }
+
+    fn workspace_symbol(&self, query: &str) -> Result<Value, (i32, String)> {
+        // Editors send an empty query to list everything; a semantic index has
+        // no meaningful answer to that
+        if query.trim().is_empty() {
+            return Ok(json!([]));
+        }
+        let options = SearchOptions {
+            limit: Some(SYMBOL_LIMIT),
+            ..Default::default()
+        };
+        let hits = self.query(query, &options)?;
+        let mut citer = Citer::default();
+        let symbols: Vec<SymbolInformation> = hits
+            .into_iter()
+            .map(|hit| SymbolInformation {
+                name: symbol_name(&hit.content),
+                kind: symbol_kind(&hit),
+                container_name: hit.file_path.trim_start_matches("./").to_string(),
+                location: location(&mut citer, &hit),
+            })
+            .collect();
+        Ok(serde_json::to_value(symbols).unwrap_or_default())
+    }
+
+    fn search(&self, params: SearchParams) -> Result<Value, (i32, String)> {
+        let options = SearchOptions {
+            limit: params.limit,
+            file_types: params.file_types,
+            min_score: params.min_score,
+            ..Default::default()
+        };
+        let hits = self.query(&params.query, &options)?;
+        let mut citer = Citer::default();
+        let results: Vec<SearchHit> = hits
+            .into_iter()
+            .map(|hit| SearchHit {
+                chunk_id: hit.id,
+                location: location(&mut citer, &hit),
+                score: hit.score,
+                content: hit.content,
+            })
+            .collect();
+        Ok(json!({ "results": results }))
+    }
+
+    fn query(
+        &self,
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>, (i32, String)> {
+        let query = search::Query {
+            text: query.to_string(),
+            options: options.clone(),
+            ..Default::default()
+        };
+        let page = QueryService::new("lsp", &self.db, &self.models, &self.config, &self.audit)
+            .search(&query, self.client.as_deref())
+            .map_err(|e| (INTERNAL_ERROR, format!("Search failed: {}", e)))?;
+        Ok(page.hits.into_iter().map(|hit| hit.result).collect())
+    }
+}
+
+/// Where a hit is, as whole lines of its file. Virtual documents such as
+/// transcripts keep their own URI.
+fn location(citer: &mut Citer, hit: &SearchResult) -> Location {
+    let citation = citer.cite(&hit.file_path, hit.start_offset, hit.end_offset);
+    let first = citation.line_start.unwrap_or(1) - 1;
+    let last = citation.line_end.unwrap_or(first + 1);
+    let uri = if is_virtual_path(&hit.file_path) {
+        hit.file_path.clone()
+    } else {
+        let path = Path::new(&hit.file_path);
+        workspace::uri_from_path(&canonicalize(path).unwrap_or_else(|_| path.to_path_buf()))
+    };
+    Location {
+        uri,
+        range: Range {
+            start: Position {
+                line: first,
+                character: 0,
+            },
+            // The start of the line after the chunk ends
+            end: Position {
+                line: last,
+                character: 0,
+            },
+        },
+    }
+}
+
+/// The chunk's first meaningful line, such as a function signature.
+fn symbol_name(content: &str) -> String {
+    let line = content
+        .lines()
+        .map(str::trim)
+        // Skip comments and attributes, but keep markdown headings
+        .find(|l| {
+            l.starts_with("# ") || (!l.is_empty() && !l.starts_with("//") && !l.starts_with('#'))
+        })
+        .unwrap_or("");
+    let line = line.trim_end_matches('{').trim_end_matches(':').trim();
+    let mut name: String = line.chars().take(MAX_NAME_CHARS).collect();
+    if line.chars().count() > MAX_NAME_CHARS {
+        name.push('…');
+    }
+    name
+}
+
+/// A symbol kind guessed from the chunk's first line.
+fn symbol_kind(hit: &SearchResult) -> u8 {
+    let name = symbol_name(&hit.content);
+    let words: Vec<&str> = name
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|w| !w.is_empty())
+        .take(4)
+        .collect();
+    let has = |keywords: &[&str]| words.iter().any(|w| keywords.contains(w));
+    if has(&["fn", "func", "function", "def"]) {
+        if name.contains("self") || name.contains("this") {
+            KIND_METHOD
+        } else {
+            KIND_FUNCTION
+        }
+    } else if has(&["struct", "type"]) {
+        KIND_STRUCT
+    } else if has(&["class"]) {
+        KIND_CLASS
+    } else if has(&["enum"]) {
+        KIND_ENUM
+    } else if has(&["trait", "interface", "impl"]) {
+        KIND_INTERFACE
+    } else if has(&["mod", "module", "namespace", "package"]) || name.starts_with('#') {
+        KIND_NAMESPACE
+    } else if matches!(
+        hit.file_type.as_str(),
+        "md" | "txt" | "pdf" | "docx" | "html"
+    ) {
+        KIND_STRING
+    } else {
+        KIND_FILE
+    }
+}
+
+/// The body of the next `Content-Length` framed message, or `None` at EOF.
+async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Value,
+) -> std::io::Result<()> {
+    let body = message.to_string();
+    writer
+        .write_all(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes())
+        .await?;
+    writer.flush().await
+}
+
+pub async fn run_lsp_server(db: Database, models: Arc<Models>, config: Config, audit: AuditLog) {
+    let mut server = LspServer {
+        db,
+        models,
+        config,
+        shutting_down: false,
+        client: None,
+        audit,
+    };
+    log::info!("contextd LSP server starting on stdio...");
+
+    let mut reader = BufReader::new(tokio::io::stdin());
+    let mut stdout = tokio::io::stdout();
+    loop {
+        let body = match read_message(&mut reader).await {
+            Ok(Some(body)) => body,
+            Ok(None) => break,
+            Err(e) => {
+                log::error!("Failed to read LSP message: {}", e);
+                break;
+            }
+        };
+        let response = match serde_json::from_slice::<Message>(&body) {
+            Ok(message) if message.method.as_deref() == Some("exit") => break,
+            Ok(message) => server.handle(message),
+            Err(e) => Some(json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": PARSE_ERROR, "message": format!("Parse error: {}", e) }
+            })),
+        };
+        if let Some(response) = response {
+            if let Err(e) = write_message(&mut stdout, &response).await {
+                log::error!("Failed to write LSP message: {}", e);
+                break;
+            }
+        }
+    }
+
+    if !server.shutting_down {
+        log::warn!("LSP client exited without a shutdown request");
+    }
+    log::info!("LSP server stdin closed, exiting.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_message_framing() {
+        let mut out = Vec::new();
+        write_message(
+            &mut out,
+            &json!({"jsonrpc": "2.0", "id": 1, "result": null}),
+        )
+        .await
+        .unwrap();
+        write_message(&mut out, &json!({"jsonrpc": "2.0", "method": "exit"}))
+            .await
+            .unwrap();
+
+        let mut reader = out.as_slice();
+        let first = read_message(&mut reader).await.unwrap().unwrap();
+        let first: Value = serde_json::from_slice(&first).unwrap();
+        assert_eq!(first["id"], 1);
+        let second = read_message(&mut reader).await.unwrap().unwrap();
+        assert!(String::from_utf8(second).unwrap().contains("exit"));
+        assert!(read_message(&mut reader).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_symbols() {
+        let hit = |content: &str, file_type: &str| SearchResult {
+            content: content.to_string(),
+            file_type: file_type.to_string(),
+            ..Default::default()
+        };
+        let function = hit("/// Connect\npub fn connect(url: &str) -> Pool {\n}", "rs");
+        assert_eq!(
+            symbol_name(&function.content),
+            "pub fn connect(url: &str) -> Pool"
+        );
+        assert_eq!(symbol_kind(&function), KIND_FUNCTION);
+        assert_eq!(
+            symbol_kind(&hit("def save(self, path):", "py")),
+            KIND_METHOD
+        );
+        assert_eq!(symbol_kind(&hit("pub struct Pool {", "rs")), KIND_STRUCT);
+        assert_eq!(
+            symbol_kind(&hit("# Installation\n\nRun it", "md")),
+            KIND_NAMESPACE
+        );
+        assert_eq!(symbol_kind(&hit("Some prose.", "md")), KIND_STRING);
+    }
+}
@@ -1,10 +1,13 @@
 use clap::Parser;
 use std::path::PathBuf;
 
+use contextd::audit::AuditLog;
 use contextd::cli;
-use contextd::config::Config;
+use contextd::collections::Models;
+use contextd::config::{default_config_path, Config, DATA_DIR_VAR, DEFAULT_CONFIG};
 use contextd::daemon;
-use contextd::indexer::embeddings::Embedder;
+use contextd::faults::{self, Faults};
+use contextd::indexer::embeddings::MOCK_MODEL;
 use contextd::mcp;
 use contextd::storage::db::Database;
 use std::sync::Arc;
@@ -14,9 +17,23 @@ use std::sync::Arc;
 #[command(about = "A local-first semantic context daemon for AI agents")]
 #[command(version)]
 struct Cli {
-    /// Path to the configuration file
-    #[arg(short, long, default_value = "contextd.toml")]
-    config: PathBuf,
+    /// Path to the configuration file (default: ./contextd.toml, else the
+    /// one `contextd init` wrote to ~/.config/contextd)
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Log more (-v for debug, -vv for trace)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Log less (-q for warnings and errors only, -qq for errors only)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true, conflicts_with = "verbose")]
+    quiet: u8,
+
+    /// Fail embeddings, delay writes and drop watcher events on purpose, for
+    /// robustness tests: `embed=0.3,write_delay_ms=200,drop_events=0.5,seed=7`
+    #[arg(long, global = true, hide = true, value_parser = faults::parse)]
+    fault_injection: Option<Faults>,
 
     #[command(subcommand)]
     command: Option<cli::Commands>,
@@ -26,37 +43,206 @@ struct Cli {
 async fn main() -> anyhow::Result<()> {
     let args = Cli::parse();
 
-    let config = if args.config.exists() {
-        Config::load(&args.config)?
-    } else {
-        Config::default()
+    let container = matches!(
+        args.command,
+        Some(cli::Commands::Daemon {
+            container: true,
+            ..
+        })
+    );
+    let mut env: Vec<(String, String)> = std::env::vars_os()
+        .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+        .collect();
+    // Containers keep the index and models on the /data volume
+    if container && !env.iter().any(|(k, _)| k == DATA_DIR_VAR) {
+        env.push((DATA_DIR_VAR.to_string(), "/data".to_string()));
+    }
+    // Services start in the system directory
+    if let Some(cli::Commands::Service {
+        action: cli::ServiceAction::Run { dir },
+    }) = &args.command
+    {
+        std::env::set_current_dir(dir)?;
+    }
+    let config_path = args.config.clone().unwrap_or_else(default_config_path);
+    let mut config = Config::load_with_env(&config_path, env)?;
+    // MCP hosts and editors show stderr to users, so keep it to errors there
+    let base_level = match args.command {
+        Some(cli::Commands::Mcp { action: None, .. }) | Some(cli::Commands::Lsp) => {
+            log::LevelFilter::Error
+        }
+        _ => log::LevelFilter::Info,
     };
+    contextd::logging::init(
+        &config.logging,
+        base_level,
+        args.verbose as i8 - args.quiet as i8,
+    )?;
+    if let Some(faults) = args.fault_injection.clone() {
+        faults::install(faults);
+    }
 
-    match args.command.unwrap_or(cli::Commands::Daemon) {
-        cli::Commands::Daemon => {
-            println!("contextd starting in daemon mode...");
-            daemon::run(config).await?;
+    let default_command = cli::Commands::Daemon {
+        bind: None,
+        container: false,
+    };
+    match args.command.unwrap_or(default_command) {
+        cli::Commands::Daemon { bind, container } => {
+            if let Some(host) = bind {
+                config.server.host = host;
+            }
+            log::info!("contextd starting in daemon mode...");
+            // A container's stdin is closed from the start
+            daemon::run(config, !container).await?;
+        }
+        cli::Commands::Mcp {
+            action: Some(cli::McpAction::Install { tool, dry_run }),
+            ..
+        } => {
+            contextd::connect::handle_mcp_install(&config, &config_path, &tool, dry_run)?;
         }
-        cli::Commands::Mcp => {
-            eprintln!("contextd starting in MCP mode...");
-            if !contextd::download::model_files_exist(&config.storage.model_path) {
-                eprintln!("Models not found in {:?}. Please run 'contextd daemon' or start the VS Code extension to initialize them before using MCP.", config.storage.model_path);
+        cli::Commands::Mcp {
+            action: None,
+            client,
+        } => {
+            log::info!("contextd starting in MCP mode...");
+            if config.storage.model_type != MOCK_MODEL
+                && !contextd::download::model_files_exist(&config.storage.model_path)
+            {
+                log::error!("Models not found in {:?}. Please run 'contextd daemon' or start the VS Code extension to initialize them before using MCP.", config.storage.model_path);
                 std::process::exit(1);
             }
 
             let db = Database::new(&config.storage.db_path)?;
-            let embedder = Arc::new(Embedder::new(&config.storage)?);
-            mcp::run_mcp_server(db, embedder, config).await;
+            let models = Arc::new(Models::load(&config)?);
+            contextd::migration::prepare(&db, &config, &models)?;
+            match models.default_model().warm_up() {
+                Ok(elapsed) => log::info!("Embedder warmed up in {:?}", elapsed),
+                Err(e) => log::warn!("Embedder warm-up failed: {}", e),
+            }
+            let client = client.or_else(|| std::env::var("CONTEXTD_MCP_CLIENT").ok());
+            let audit = AuditLog::open(&config.audit)?;
+            mcp::run_mcp_server(db, models, config, client, audit).await;
+        }
+        cli::Commands::Lsp => {
+            log::info!("contextd starting in LSP mode...");
+            if config.storage.model_type != MOCK_MODEL
+                && !contextd::download::model_files_exist(&config.storage.model_path)
+            {
+                log::error!("Models not found in {:?}. Please run 'contextd setup' before starting the language server.", config.storage.model_path);
+                std::process::exit(1);
+            }
+
+            let db = Database::new(&config.storage.db_path)?;
+            let models = Arc::new(Models::load(&config)?);
+            contextd::migration::prepare(&db, &config, &models)?;
+            let audit = AuditLog::open(&config.audit)?;
+            contextd::lsp::run_lsp_server(db, models, config, audit).await;
+        }
+        cli::Commands::Init => {
+            let path = args.config.clone().unwrap_or_else(|| {
+                contextd::config::user_config_dir()
+                    .map(|dir| dir.join(DEFAULT_CONFIG))
+                    .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG))
+            });
+            contextd::onboarding::handle_init(&path).await?;
         }
         cli::Commands::Setup => {
             cli::handle_setup(&config).await?;
         }
-        cli::Commands::Query { query, context } => {
-            cli::handle_query(&config, &query, context).await?;
+        cli::Commands::Query {
+            query,
+            context,
+            mode,
+        } => {
+            cli::handle_query(&config, &query, context, mode).await?;
+        }
+        cli::Commands::Index { path, dry_run } => {
+            cli::handle_index(&config, &path, dry_run).await?;
+        }
+        cli::Commands::Reindex { plan: _, apply } => {
+            cli::handle_reindex(&config, apply).await?;
         }
+        cli::Commands::Rechunk { lang } => {
+            cli::handle_rechunk(&config, &lang).await?;
+        }
+        cli::Commands::Show { path } => {
+            cli::handle_show(&config, &path)?;
+        }
+        cli::Commands::Chunk { path, debug } => {
+            cli::handle_chunk(&path, debug)?;
+        }
+        cli::Commands::Explain { path } => {
+            cli::handle_explain(&config, &path)?;
+        }
+        cli::Commands::Status => {
+            cli::handle_status(&config)?;
+        }
+        cli::Commands::Fsck { repair } => {
+            contextd::maintenance::handle_fsck(&config, repair)?;
+        }
+        cli::Commands::Gc { vacuum } => {
+            contextd::gc::handle_gc(&config, vacuum)?;
+        }
+        cli::Commands::Prune { yes } => {
+            cli::handle_prune(&config, yes)?;
+        }
+        cli::Commands::Purge {
+            path,
+            older_than,
+            collection,
+            yes,
+        } => {
+            cli::handle_purge(
+                &config,
+                path.as_deref(),
+                older_than,
+                collection.as_deref(),
+                yes,
+            )?;
+        }
+        cli::Commands::Bench {
+            path,
+            files,
+            queries,
+        } => {
+            contextd::bench::handle_bench(&config, path.as_deref(), files, queries).await?;
+        }
+        cli::Commands::Report { days, json, output } => {
+            contextd::report::handle_report(&config, days, json, output.as_deref())?;
+        }
+        cli::Commands::Eval { queries, k } => {
+            contextd::eval::handle_eval(&config, &queries, k).await?;
+        }
+        cli::Commands::Tag { action } => {
+            cli::handle_tag(&config, action)?;
+        }
+        cli::Commands::Db { action } => match action {
+            cli::DbAction::Check { quick } => {
+                contextd::maintenance::handle_check(&config, quick)?;
+            }
+            cli::DbAction::Backup { path } => {
+                contextd::maintenance::handle_backup(&config, &path)?;
+            }
+            cli::DbAction::Repair { from } => {
+                contextd::maintenance::handle_repair(&config, from.as_deref())?;
+            }
+        },
         cli::Commands::Connect { all } => {
             contextd::connect::handle_connect(all).await?;
         }
+        cli::Commands::Service { action } => match action {
+            cli::ServiceAction::Install => {
+                contextd::service::handle_install(&config_path)?;
+            }
+            cli::ServiceAction::Uninstall => {
+                contextd::service::handle_uninstall()?;
+            }
+            cli::ServiceAction::Run { .. } => {
+                log::info!("contextd starting as a service...");
+                contextd::service::run(config)?;
+            }
+        },
     }
 
     Ok(())
@@ -0,0 +1,375 @@
+use anyhow::{bail, Context, Result};
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::indexer::chunker;
+use crate::storage::db::{Database, Inconsistencies};
+
+/// Tables `contextd db repair` carries over from a corrupt index when it
+/// rebuilds: what re-indexing can't bring back. Chunk tags are lost with the
+/// chunks they were on.
+const SALVAGED_TABLES: &[&str] = &[
+    "path_tags",
+    "workspaces",
+    "query_stats",
+    "query_areas",
+    "index_history",
+];
+
+/// Problems found in the database on the most thorough check, listed by
+/// `contextd db check`, at most this many.
+const MAX_PROBLEMS: usize = 10;
+
+/// What `contextd db repair` did.
+#[derive(Debug, PartialEq)]
+pub enum Repair {
+    /// The database passed the integrity check, so nothing was touched
+    Healthy,
+    /// The backup was copied into place
+    Restored { moved_to: PathBuf },
+    /// A new, empty index was created, keeping what could be read of the
+    /// old one's tables in [`SALVAGED_TABLES`], with row counts
+    Rebuilt {
+        moved_to: PathBuf,
+        salvaged: Vec<(&'static str, usize)>,
+    },
+}
+
+/// Fail the daemon's start on a corrupt index rather than serve or extend
+/// it. Uses the quick check, which reads every page but doesn't cross-check
+/// indexes.
+pub fn check_on_start(db: &Database, path: &Path) -> Result<()> {
+    let problems = db.integrity_check(true)?;
+    if problems.is_empty() {
+        return Ok(());
+    }
+    bail!(
+        "The index at {} is corrupt ({}). Run `contextd db repair` to restore it from a backup or rebuild it",
+        path.display(),
+        problems.join("; ")
+    )
+}
+
+/// Restore `db_path` from `backup`, or rebuild it empty when there is none,
+/// moving the damaged files aside. A sound database is only replaced when a
+/// backup is given.
+pub fn repair(db_path: &Path, backup: Option<&Path>) -> Result<Repair> {
+    if let Some(backup) = backup {
+        let problems = Database::check_file(backup)
+            .with_context(|| format!("Failed to read backup {}", backup.display()))?;
+        if !problems.is_empty() {
+            bail!(
+                "The backup {} is damaged too: {}",
+                backup.display(),
+                problems.join("; ")
+            );
+        }
+        let moved_to = move_aside(db_path)?;
+        std::fs::copy(backup, db_path)
+            .with_context(|| format!("Failed to copy {}", backup.display()))?;
+        return Ok(Repair::Restored { moved_to });
+    }
+
+    if db_path.exists() && Database::check_file(db_path).is_ok_and(|p| p.is_empty()) {
+        return Ok(Repair::Healthy);
+    }
+    let moved_to = move_aside(db_path)?;
+    let db = Database::new(db_path)?;
+    drop(db);
+    let salvaged = salvage(&moved_to, db_path)?;
+    Ok(Repair::Rebuilt { moved_to, salvaged })
+}
+
+/// Rename the database and its WAL files to `<name>.corrupt-<time>`,
+/// returning the new database path.
+fn move_aside(db_path: &Path) -> Result<PathBuf> {
+    let suffix = chrono::Local::now().format("corrupt-%Y%m%d-%H%M%S");
+    let moved_to = PathBuf::from(format!("{}.{}", db_path.display(), suffix));
+    for (from, to) in [
+        (db_path.to_path_buf(), moved_to.clone()),
+        (sidecar(db_path, "-wal"), sidecar(&moved_to, "-wal")),
+        (sidecar(db_path, "-shm"), sidecar(&moved_to, "-shm")),
+    ] {
+        if from.exists() {
+            std::fs::rename(&from, &to)
+                .with_context(|| format!("Failed to move {} aside", from.display()))?;
+        }
+    }
+    Ok(moved_to)
+}
+
+fn sidecar(db_path: &Path, suffix: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}", db_path.display(), suffix))
+}
+
+/// Copy what can still be read of [`SALVAGED_TABLES`] from the damaged
+/// database at `old` into the new one at `new`. Unreadable tables are
+/// skipped.
+fn salvage(old: &Path, new: &Path) -> Result<Vec<(&'static str, usize)>> {
+    let conn = Connection::open(new)?;
+    if conn
+        .execute(
+            "ATTACH DATABASE ?1 AS old",
+            [format!("file:{}?mode=ro", old.display())],
+        )
+        .is_err()
+    {
+        return Ok(Vec::new());
+    }
+    let mut salvaged = Vec::new();
+    for table in SALVAGED_TABLES {
+        match conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO main.{0} SELECT * FROM old.{0}",
+                table
+            ),
+            [],
+        ) {
+            Ok(rows) => salvaged.push((*table, rows)),
+            Err(e) => log::warn!("Could not salvage {}: {}", table, e),
+        }
+    }
+    Ok(salvaged)
+}
+
+pub fn handle_check(config: &Config, quick: bool) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+    let problems = db.integrity_check(quick)?;
+    if problems.is_empty() {
+        println!("{}: no problems found.", config.storage.db_path.display());
+        return Ok(());
+    }
+    println!(
+        "{} is damaged ({} problems):",
+        config.storage.db_path.display(),
+        problems.len()
+    );
+    for problem in problems.iter().take(MAX_PROBLEMS) {
+        println!("  {}", problem);
+    }
+    println!("\nRun `contextd db repair` to restore it from a backup or rebuild it.");
+    std::process::exit(1);
+}
+
+/// [`Database::check_consistency`], plus files whose chunks reach past
+/// their end. Offsets of PDFs, Word documents, HTML pages and plugin output
+/// are into extracted text, and
+/// files changed since they were indexed are due for re-indexing anyway, so
+/// neither is checked.
+pub fn check_consistency(db: &Database, config: &Config) -> Result<Inconsistencies> {
+    let mut found = db.check_consistency()?;
+    for (path, modified, end) in db.chunk_extents()? {
+        let path = PathBuf::from(path);
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        if chunker::extracts_text(ext) || config.plugins.contains_key(ext) {
+            continue;
+        }
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        let unchanged = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .is_some_and(|d| d.as_secs() == modified);
+        if unchanged && end > metadata.len() {
+            found
+                .truncated_files
+                .push(path.to_string_lossy().to_string());
+        }
+    }
+    Ok(found)
+}
+
+pub fn handle_fsck(config: &Config, repair: bool) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+    let found = check_consistency(&db, config)?;
+    if found.is_empty() {
+        println!(
+            "{}: the index is consistent.",
+            config.storage.db_path.display()
+        );
+        return Ok(());
+    }
+
+    let problems = [
+        (found.orphaned.len(), "chunks without a file"),
+        (
+            found.bad_embeddings.len(),
+            "chunks with an embedding of the wrong size",
+        ),
+        (found.bad_offsets.len(), "chunks ending before they start"),
+        (
+            found.truncated_files.len(),
+            "files shorter than their chunks",
+        ),
+        (
+            found.missing_fts.len(),
+            "chunks missing from the full-text index",
+        ),
+        (found.stray_fts.len(), "full-text entries without a chunk"),
+        (
+            found.missing_vectors.len(),
+            "chunks missing from the vector index",
+        ),
+        (found.stray_vectors.len(), "vector entries without a chunk"),
+    ];
+    for (count, problem) in problems.iter().filter(|(count, _)| *count > 0) {
+        println!("  {} {}", count, problem);
+    }
+    for path in found.truncated_files.iter().take(MAX_PROBLEMS) {
+        println!("    {}", path);
+    }
+
+    if !repair {
+        println!("\nRun `contextd fsck --repair` to fix them.");
+        std::process::exit(1);
+    }
+    db.repair_consistency(&found)?;
+    println!("\nRepaired. Files with bad chunks are re-indexed when the daemon starts.");
+    Ok(())
+}
+
+pub fn handle_backup(config: &Config, target: &Path) -> Result<()> {
+    if target.exists() {
+        bail!("{} already exists", target.display());
+    }
+    let db = Database::new(&config.storage.db_path)?;
+    db.backup_to(target)?;
+    println!("Backed up the index to {}", target.display());
+    Ok(())
+}
+
+pub fn handle_repair(config: &Config, backup: Option<&Path>) -> Result<()> {
+    let db_path = &config.storage.db_path;
+    match repair(db_path, backup)? {
+        Repair::Healthy => {
+            println!(
+                "{} passed the integrity check; nothing to repair. Pass --from to restore a backup anyway.",
+                db_path.display()
+            );
+        }
+        Repair::Restored { moved_to } => {
+            println!(
+                "Restored {} from {}. The old file is at {}.",
+                db_path.display(),
+                backup.unwrap_or(db_path).display(),
+                moved_to.display()
+            );
+            println!("Files changed since the backup are re-indexed when the daemon starts.");
+        }
+        Repair::Rebuilt { moved_to, salvaged } => {
+            println!(
+                "Created a new index at {}. The damaged file is at {}.",
+                db_path.display(),
+                moved_to.display()
+            );
+            for (table, rows) in salvaged {
+                println!("  kept {} rows of {}", rows, table);
+            }
+            println!("Everything is re-indexed when the daemon starts.");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repair() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        let backup = dir.path().join("backup.db");
+        {
+            let db = Database::new(&path).unwrap();
+            db.add_or_update_file("/a.rs", 100).unwrap();
+            db.add_path_tag("src", "core").unwrap();
+            db.backup_to(&backup).unwrap();
+            db.add_path_tag("docs", "guide").unwrap();
+            assert_eq!(db.integrity_check(false).unwrap(), Vec::<String>::new());
+        }
+        assert_eq!(repair(&path, None).unwrap(), Repair::Healthy);
+
+        // Keep the tables that can't be re-indexed while rebuilding
+        let rebuilt = dir.path().join("rebuilt.db");
+        drop(Database::new(&rebuilt).unwrap());
+        let salvaged = salvage(&path, &rebuilt).unwrap();
+        assert!(salvaged.contains(&("path_tags", 2)));
+
+        std::fs::write(&path, vec![0x5a; 8192]).unwrap();
+        std::fs::remove_file(sidecar(&path, "-wal")).ok();
+        std::fs::remove_file(sidecar(&path, "-shm")).ok();
+        let error = Database::new(&path).err().unwrap().to_string();
+        assert!(error.contains("contextd db repair"), "{}", error);
+
+        let Repair::Restored { moved_to } = repair(&path, Some(&backup)).unwrap() else {
+            panic!("expected a restore");
+        };
+        assert!(moved_to.exists());
+        let db = Database::new(&path).unwrap();
+        assert!(db.get_file("/a.rs").unwrap().is_some());
+        assert_eq!(db.list_tags().unwrap().len(), 1);
+        drop(db);
+
+        std::fs::write(&path, vec![0x5a; 8192]).unwrap();
+        assert!(matches!(
+            repair(&path, None).unwrap(),
+            Repair::Rebuilt { salvaged, .. } if salvaged.is_empty()
+        ));
+        assert!(Database::new(&path)
+            .unwrap()
+            .list_files()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_truncated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, "short").unwrap();
+        let modified = std::fs::metadata(&file)
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let db = Database::new(":memory:").unwrap();
+        let path = file.to_string_lossy().to_string();
+        let file_id = db.add_or_update_file(&path, modified).unwrap();
+        db.add_chunk(file_id, 0, 50, "short", None, None).unwrap();
+        let config = Config::default();
+        assert_eq!(
+            check_consistency(&db, &config).unwrap().truncated_files,
+            vec![path.clone()]
+        );
+
+        // A file changed since it was indexed is only due for re-indexing
+        db.add_or_update_file(&path, modified + 10).unwrap();
+        assert!(check_consistency(&db, &config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        drop(Database::new(&path).unwrap());
+        // A stale process that never let go of the file
+        let holder = Connection::open(&path).unwrap();
+        holder
+            .execute_batch("PRAGMA locking_mode = EXCLUSIVE; BEGIN EXCLUSIVE")
+            .unwrap();
+
+        let error = Database::open(&path, std::time::Duration::from_millis(300))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("locked by another process"), "{}", error);
+        drop(holder);
+        assert!(Database::open(&path, std::time::Duration::from_millis(300)).is_ok());
+    }
+}
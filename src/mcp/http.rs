@@ -0,0 +1,246 @@
+// Streamable HTTP transport for the MCP server, served by the daemon at /mcp
+// so remote agents share its index and embedder instead of each spawning a
+// stdio server of their own.
+
+use super::server::ContextdServer;
+use crate::audit::AuditLog;
+use crate::collections::Models;
+use crate::config::Config;
+use crate::storage::db::Database;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+    routing::post,
+    Json, Router,
+};
+use reqwest::Url;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Header carrying the session id assigned in response to `initialize`.
+pub const SESSION_HEADER: &str = "mcp-session-id";
+
+/// Sessions idle for longer than this are forgotten.
+const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+
+struct Session {
+    server: Arc<ContextdServer>,
+    last_used: Instant,
+}
+
+#[derive(Clone)]
+struct HttpState {
+    db: Database,
+    models: Arc<Models>,
+    config: Arc<Config>,
+    audit: Arc<AuditLog>,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl HttpState {
+    /// Start a session with a server of its own, so per-client state such as
+    /// the active project and log level is not shared between clients.
+    fn start_session(&self) -> (String, Arc<ContextdServer>) {
+        let server = Arc::new(ContextdServer::new(
+            self.db.clone(),
+            self.models.clone(),
+            (*self.config).clone(),
+            None,
+            self.audit.clone(),
+        ));
+        let id = new_session_id();
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, s| now.duration_since(s.last_used) < SESSION_TTL);
+        sessions.insert(
+            id.clone(),
+            Session {
+                server: server.clone(),
+                last_used: now,
+            },
+        );
+        (id, server)
+    }
+
+    fn session(&self, id: &str) -> Option<Arc<ContextdServer>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(id)?;
+        session.last_used = Instant::now();
+        Some(session.server.clone())
+    }
+}
+
+/// Routes of the transport, to be nested under the path clients connect to.
+pub fn router(
+    db: Database,
+    models: Arc<Models>,
+    config: Arc<Config>,
+    audit: Arc<AuditLog>,
+) -> Router {
+    Router::new()
+        .route("/", post(handle_post).get(handle_get).delete(handle_delete))
+        .with_state(HttpState {
+            db,
+            models,
+            config,
+            audit,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        })
+}
+
+/// 128 random bits from the OS, hex encoded.
+fn new_session_id() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("OS random number generator failed");
+    bytes.iter().fold(String::with_capacity(32), |mut id, b| {
+        let _ = write!(id, "{:02x}", b);
+        id
+    })
+}
+
+/// Whether a request may come from its `Origin`: none (not a browser),
+/// localhost, or one of `[server] allowed_origins`. Anything else could be a
+/// page using DNS rebinding to reach a loopback daemon.
+fn origin_allowed(headers: &HeaderMap, allowed: &[String]) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return true;
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    if allowed
+        .iter()
+        .any(|a| a.trim_end_matches('/').eq_ignore_ascii_case(origin))
+    {
+        return true;
+    }
+    let Ok(url) = Url::parse(origin) else {
+        return false;
+    };
+    match url.host_str() {
+        Some("localhost") => true,
+        Some(host) => host
+            .trim_matches(|c| c == '[' || c == ']')
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback()),
+        None => false,
+    }
+}
+
+fn error(status: StatusCode, code: i32, message: &str) -> Response {
+    (
+        status,
+        Json(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": {"code": code, "message": message}
+        })),
+    )
+        .into_response()
+}
+
+/// Handle a JSON-RPC message or batch. Replies are a JSON body, or an SSE
+/// stream when the client accepts one and there are notifications or
+/// requests to send along with the responses.
+async fn handle_post(State(state): State<HttpState>, headers: HeaderMap, body: String) -> Response {
+    if !origin_allowed(&headers, &state.config.server.allowed_origins) {
+        return error(StatusCode::FORBIDDEN, INVALID_REQUEST, "Origin not allowed");
+    }
+    let (messages, batch) = match serde_json::from_str::<Value>(&body) {
+        Ok(Value::Array(messages)) if !messages.is_empty() => (messages, true),
+        Ok(Value::Array(_)) => {
+            return error(StatusCode::BAD_REQUEST, INVALID_REQUEST, "Empty batch")
+        }
+        Ok(message) => (vec![message], false),
+        Err(_) => return error(StatusCode::BAD_REQUEST, PARSE_ERROR, "Parse error"),
+    };
+
+    let session_id = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok());
+    let initialize = messages.iter().any(|m| m["method"] == "initialize");
+    let (session_id, server) = match session_id {
+        Some(id) => match state.session(id) {
+            Some(server) => (id.to_string(), server),
+            None => return error(StatusCode::NOT_FOUND, INVALID_REQUEST, "Session not found"),
+        },
+        None if initialize => state.start_session(),
+        None => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                INVALID_REQUEST,
+                "Missing Mcp-Session-Id header",
+            )
+        }
+    };
+
+    let mut outgoing = Vec::new();
+    for message in &messages {
+        outgoing.extend(server.handle_message(&message.to_string()).await);
+    }
+
+    let mut response = if !messages
+        .iter()
+        .any(|m| m.get("method").is_some() && m.get("id").is_some())
+    {
+        // Only notifications and responses: nothing to answer
+        StatusCode::ACCEPTED.into_response()
+    } else if accepts_sse(&headers) && outgoing.iter().any(|m| m.get("method").is_some()) {
+        let events = outgoing
+            .into_iter()
+            .map(|m| Ok::<_, Infallible>(Event::default().data(m.to_string())));
+        Sse::new(futures_util::stream::iter(events)).into_response()
+    } else {
+        // Plain JSON can only carry the responses themselves
+        let mut responses: Vec<Value> = outgoing
+            .into_iter()
+            .filter(|m| m.get("method").is_none())
+            .collect();
+        if batch {
+            Json(Value::Array(responses)).into_response()
+        } else {
+            Json(responses.pop().unwrap_or(Value::Null)).into_response()
+        }
+    };
+    if let Ok(value) = HeaderValue::from_str(&session_id) {
+        response.headers_mut().insert(SESSION_HEADER, value);
+    }
+    response
+}
+
+fn accepts_sse(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"))
+}
+
+/// The server never sends messages outside a response, so there is no
+/// stream to listen on.
+async fn handle_get() -> StatusCode {
+    StatusCode::METHOD_NOT_ALLOWED
+}
+
+/// End a session.
+async fn handle_delete(State(state): State<HttpState>, headers: HeaderMap) -> StatusCode {
+    if !origin_allowed(&headers, &state.config.server.allowed_origins) {
+        return StatusCode::FORBIDDEN;
+    }
+    let Some(id) = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok()) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    match state.sessions.lock().unwrap().remove(id) {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
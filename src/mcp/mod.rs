@@ -1,6 +1,10 @@
 // MCP (Model Context Protocol) Server Implementation
 // Exposes contextd's semantic search capabilities to AI assistants like Claude
 
+mod http;
+mod scope;
 mod server;
 
+pub use http::{router, SESSION_HEADER};
+pub use scope::{ClientScope, McpConfig};
 pub use server::run_mcp_server;
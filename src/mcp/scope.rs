@@ -0,0 +1,177 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::paths::canonicalize;
+use crate::staleness::DEFAULT_COLLECTION;
+
+/// Entry in `[mcp.clients]` applying to clients without one of their own.
+const ANY_CLIENT: &str = "*";
+
+/// `[mcp]` config.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct McpConfig {
+    /// What each client may access, keyed by the name it identifies as
+    pub clients: HashMap<String, ClientScope>,
+}
+
+impl McpConfig {
+    /// The scope of the client named `client`: its own entry, else the `*`
+    /// entry, else no restrictions.
+    pub fn scope(&self, client: Option<&str>) -> ClientScope {
+        client
+            .and_then(|name| self.clients.get(name))
+            .or_else(|| self.clients.get(ANY_CLIENT))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// `[mcp.clients.<name>]`: the collections and directories one MCP client may
+/// search and read. An unset field leaves that side unrestricted.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ClientScope {
+    /// Collections whose chunks the client sees; files outside every
+    /// configured collection are in `default`
+    pub collections: Option<Vec<String>>,
+    /// Directories whose files the client sees. Virtual documents such as
+    /// saved conversations are under none of them.
+    pub paths: Option<Vec<PathBuf>>,
+}
+
+impl ClientScope {
+    pub fn allows_collection(&self, collection: &str) -> bool {
+        self.collections
+            .as_ref()
+            .is_none_or(|allowed| allowed.iter().any(|c| c == collection))
+    }
+
+    pub fn allows_path(&self, path: &Path) -> bool {
+        let Some(allowed) = &self.paths else {
+            return true;
+        };
+        let canonical = canonicalize(path).ok();
+        allowed.iter().any(|root| {
+            path.starts_with(root)
+                || canonical
+                    .as_ref()
+                    .zip(canonicalize(root).ok())
+                    .is_some_and(|(path, root)| path.starts_with(root))
+        })
+    }
+
+    /// Whether a chunk of `file_path` with chunk `metadata` is visible.
+    pub fn allows_chunk(&self, file_path: &str, metadata: Option<&str>) -> bool {
+        let collection = metadata
+            .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+            .and_then(|m| m["collection"].as_str().map(String::from))
+            .unwrap_or_else(|| DEFAULT_COLLECTION.to_string());
+        self.allows_collection(&collection) && self.allows_path(Path::new(file_path))
+    }
+
+    /// The collections a search asking for `requested` (every collection
+    /// when `None`) may cover: none if it asks for one outside the scope.
+    pub fn narrow_collections(&self, requested: Option<&str>) -> Option<Vec<String>> {
+        match requested {
+            Some(name) if self.allows_collection(name) => Some(vec![name.to_string()]),
+            Some(_) => Some(Vec::new()),
+            None => self.collections.clone(),
+        }
+    }
+
+    /// The parts of `roots` (everything when `None`) inside the allowed
+    /// paths: a root under an allowed path is kept whole, and an allowed
+    /// path under a root replaces it.
+    pub fn narrow(&self, roots: Option<Vec<PathBuf>>) -> Option<Vec<PathBuf>> {
+        let Some(allowed) = &self.paths else {
+            return roots;
+        };
+        let Some(roots) = roots else {
+            return Some(allowed.clone());
+        };
+        let mut narrowed: Vec<PathBuf> = Vec::new();
+        for root in &roots {
+            for path in allowed {
+                let inside = if root.starts_with(path) {
+                    root
+                } else if path.starts_with(root) {
+                    path
+                } else {
+                    continue;
+                };
+                if !narrowed.contains(inside) {
+                    narrowed.push(inside.clone());
+                }
+            }
+        }
+        Some(narrowed)
+    }
+
+    /// Whether any part of `root` is inside the allowed paths.
+    pub fn overlaps(&self, root: &Path) -> bool {
+        self.narrow(Some(vec![root.to_path_buf()]))
+            .is_none_or(|roots| !roots.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope() {
+        let config: McpConfig = toml::from_str(
+            r#"
+            [clients.cursor]
+            collections = ["docs"]
+            paths = ["/repo/docs", "/other"]
+
+            [clients."*"]
+            collections = ["default"]
+            "#,
+        )
+        .unwrap();
+
+        let cursor = config.scope(Some("cursor"));
+        assert!(cursor.allows_collection("docs"));
+        assert!(!cursor.allows_collection("default"));
+        assert!(cursor.allows_chunk("/repo/docs/a.md", Some(r#"{"collection": "docs"}"#)));
+        assert!(!cursor.allows_chunk("/repo/docs/a.md", None));
+        assert!(!cursor.allows_chunk("/repo/src/a.md", Some(r#"{"collection": "docs"}"#)));
+
+        // Unknown and unnamed clients get the `*` entry
+        assert_eq!(config.scope(Some("windsurf")), config.scope(None));
+        assert!(config.scope(None).allows_chunk("/anywhere/x.rs", None));
+        assert_eq!(
+            McpConfig::default().scope(Some("cursor")),
+            ClientScope::default()
+        );
+
+        assert_eq!(
+            cursor.narrow(Some(vec![PathBuf::from("/repo")])),
+            Some(vec![PathBuf::from("/repo/docs")])
+        );
+        assert_eq!(
+            cursor.narrow(Some(vec![PathBuf::from("/other/sub")])),
+            Some(vec![PathBuf::from("/other/sub")])
+        );
+        assert!(!cursor.overlaps(Path::new("/elsewhere")));
+        assert_eq!(
+            cursor.narrow(None),
+            Some(vec![PathBuf::from("/repo/docs"), PathBuf::from("/other")])
+        );
+
+        assert_eq!(
+            cursor.narrow_collections(None),
+            Some(vec!["docs".to_string()])
+        );
+        assert_eq!(
+            cursor.narrow_collections(Some("docs")),
+            Some(vec!["docs".to_string()])
+        );
+        assert_eq!(cursor.narrow_collections(Some("default")), Some(vec![]));
+        assert_eq!(ClientScope::default().narrow_collections(None), None);
+    }
+}
@@ -1,9 +1,23 @@
+use crate::audit::AuditLog;
+use crate::citation::Citer;
+use crate::collections::{self, Models};
 use crate::config::Config;
-use crate::indexer::embeddings::Embedder;
-use crate::storage::db::Database;
+use crate::daemon;
+use crate::documents::{self, Document};
+use crate::files::{self, RangeError, RangeRequest};
+use crate::mcp::ClientScope;
+use crate::paths::{canonicalize, WatchRoots};
+use crate::search::{self, QueryService, SearchMode, SessionOptions};
+use crate::session::SessionStore;
+use crate::staleness;
+use crate::storage::db::{Database, RankExplanation, TimingStats};
+use crate::storage::StorageError;
+use crate::transcripts::{self, Transcript};
+use crate::workspace;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 
 // JSON-RPC Types
@@ -11,9 +25,12 @@ use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 struct JsonRpcRequest {
     #[allow(dead_code)]
     jsonrpc: String,
+    /// Empty for responses to requests we sent the client
+    #[serde(default)]
     method: String,
     params: Option<Value>,
     id: Option<Value>,
+    result: Option<Value>,
 }
 
 #[derive(Debug, Serialize)]
@@ -30,6 +47,21 @@ struct JsonRpcError {
     message: String,
 }
 
+impl JsonRpcError {
+    /// `what` failed with `e`: [`INDEX_BUSY`] when trying again may succeed,
+    /// else an internal error.
+    fn failed(what: &str, e: impl Into<anyhow::Error>) -> Self {
+        let e = e.into();
+        let transient = e
+            .downcast_ref::<StorageError>()
+            .is_some_and(StorageError::is_transient);
+        Self {
+            code: if transient { INDEX_BUSY } else { -32603 },
+            message: format!("{}: {}", what, e),
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct ServerInfo {
     name: String,
@@ -52,6 +84,8 @@ struct Tool {
     description: String,
     #[serde(rename = "inputSchema")]
     input_schema: Value,
+    #[serde(rename = "outputSchema", skip_serializing_if = "Option::is_none")]
+    output_schema: Option<Value>,
     annotations: ToolAnnotations,
 }
 
@@ -85,38 +119,258 @@ struct CallToolResult {
     content: Vec<Content>,
     #[serde(rename = "isError")]
     is_error: bool,
+    #[serde(rename = "nextCursor", skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+    #[serde(rename = "structuredContent", skip_serializing_if = "Option::is_none")]
+    structured_content: Option<Value>,
 }
 
+/// One search hit in the structured search_context result.
+#[derive(Serialize)]
+struct SearchHit {
+    chunk_id: i64,
+    file_path: String,
+    file_type: String,
+    score: f32,
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line_start: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line_end: Option<usize>,
+    /// Already returned earlier in the session (content is a summary)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    seen: bool,
+    /// Why the staleness policy flagged this hit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stale_reason: Option<String>,
+    /// `path:start_line-end_line @ commit`, when citations were requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    citation: Option<String>,
+    /// Why the hit ranked where it did, when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<RankExplanation>,
+}
+
+/// Syslog severities used by the MCP logging capability, lowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LogLevel {
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+/// A watched root that searches can be scoped to.
+#[derive(Serialize)]
+struct Project {
+    name: String,
+    root: PathBuf,
+    files: usize,
+    active: bool,
+}
+
+/// Id of the `roots/list` request sent to clients.
+const ROOTS_REQUEST_ID: &str = "contextd-roots";
+
+/// Server error for requests that failed because the index is busy, e.g.
+/// locked by another process; the client may retry.
+const INDEX_BUSY: i32 = -32001;
+
+/// Upper bound on `page_size` for search_context.
+const MAX_PAGE_SIZE: u64 = 50;
+
+/// Most files one index_path call indexes.
+const MAX_INDEX_PATH_FILES: usize = 10_000;
+
+/// `source` metadata of notes stored with remember, and the prefix of
+/// their document ids.
+const NOTE_SOURCE: &str = "agent";
+
 pub struct ContextdServer {
     db: Database,
-    embedder: Arc<Embedder>,
-    #[allow(dead_code)]
+    models: Arc<Models>,
     config: Config,
+    /// Root that search_context is scoped to, set via `set_active_project`
+    active_project: Mutex<Option<PathBuf>>,
+    /// Minimum level forwarded to the client, set via `logging/setLevel`
+    log_level: Mutex<LogLevel>,
+    /// Notifications queued while handling a request, flushed before its response
+    outbox: Mutex<Vec<Value>>,
+    /// Whether the client declared the `roots` capability
+    client_roots: Mutex<bool>,
+    /// Whether a `roots/list` request is awaiting the client's reply
+    roots_requested: Mutex<bool>,
+    /// Workspace roots the client reported. They only narrow searches to the
+    /// projects the client works in, and never widen what it may read.
+    workspace_roots: Mutex<Option<Vec<PathBuf>>>,
+    sessions: SessionStore,
+    /// Name the client is scoped by in `[mcp.clients]`: given on the command
+    /// line, else the `clientInfo` name it sent in `initialize`
+    client: Mutex<Option<String>>,
+    audit: Arc<AuditLog>,
 }
 
 impl ContextdServer {
-    pub fn new(db: Database, embedder: Arc<Embedder>, config: Config) -> Self {
+    pub fn new(
+        db: Database,
+        models: Arc<Models>,
+        config: Config,
+        client: Option<String>,
+        audit: Arc<AuditLog>,
+    ) -> Self {
         Self {
             db,
-            embedder,
+            models,
             config,
+            active_project: Mutex::new(None),
+            log_level: Mutex::new(LogLevel::Info),
+            outbox: Mutex::new(Vec::new()),
+            client_roots: Mutex::new(false),
+            roots_requested: Mutex::new(false),
+            workspace_roots: Mutex::new(None),
+            sessions: SessionStore::default(),
+            client: Mutex::new(client),
+            audit,
         }
     }
 
-    async fn handle_request(&self, req: JsonRpcRequest) -> Option<JsonRpcResponse> {
+    /// What the current client may access.
+    fn scope(&self) -> ClientScope {
+        self.config
+            .mcp
+            .scope(self.client.lock().unwrap().as_deref())
+    }
+
+    /// Ask the client for its workspace roots, if it supports them.
+    fn request_roots(&self) {
+        if *self.client_roots.lock().unwrap() {
+            *self.roots_requested.lock().unwrap() = true;
+            self.outbox.lock().unwrap().push(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": ROOTS_REQUEST_ID,
+                "method": "roots/list"
+            }));
+        }
+    }
+
+    /// Take the client's reply to our `roots/list` request. Replies nobody
+    /// asked for are ignored.
+    fn set_workspace_roots(&self, result: &Value) {
+        if !std::mem::take(&mut *self.roots_requested.lock().unwrap())
+            || !*self.client_roots.lock().unwrap()
+        {
+            return;
+        }
+        let roots: Vec<PathBuf> = result
+            .get("roots")
+            .and_then(|r| r.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|root| root.get("uri").and_then(|u| u.as_str()))
+            .filter_map(workspace::path_from_uri)
+            .filter_map(|p| canonicalize(&p).ok())
+            .collect();
+        self.log(
+            LogLevel::Debug,
+            format!("Client workspace roots: {:?}", roots),
+        );
+        *self.workspace_roots.lock().unwrap() = Some(roots);
+    }
+
+    /// Queue a log message for the client if it meets the negotiated level.
+    fn log(&self, level: LogLevel, message: impl Into<String>) {
+        if level >= *self.log_level.lock().unwrap() {
+            self.outbox
+                .lock()
+                .unwrap()
+                .push(log_notification(level, &message.into()));
+        }
+    }
+
+    fn take_notifications(&self) -> Vec<Value> {
+        std::mem::take(&mut *self.outbox.lock().unwrap())
+    }
+
+    /// Handle one JSON-RPC message, returning what to send back: the
+    /// notifications it queued, then its response if it needs one.
+    pub(super) async fn handle_message(self: &Arc<Self>, message: &str) -> Vec<Value> {
+        let response = match serde_json::from_str::<JsonRpcRequest>(message) {
+            Ok(req) => self.handle_request(req).await,
+            Err(e) => {
+                self.log(
+                    LogLevel::Error,
+                    format!("Failed to parse JSON-RPC: {} (line: {})", e, message),
+                );
+                // Send parse error
+                Some(JsonRpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    id: None,
+                    result: None,
+                    error: Some(JsonRpcError {
+                        code: -32700,
+                        message: "Parse error".to_string(),
+                    }),
+                })
+            }
+        };
+
+        // Log notifications go out ahead of the response they relate to
+        let mut messages = self.take_notifications();
+        if let Some(resp) = response {
+            messages.push(serde_json::to_value(&resp).unwrap());
+        }
+        messages
+    }
+
+    async fn handle_request(self: &Arc<Self>, req: JsonRpcRequest) -> Option<JsonRpcResponse> {
         let id = req.id.clone();
 
+        // Responses to our own requests
+        if req.method.is_empty() {
+            if id == Some(serde_json::json!(ROOTS_REQUEST_ID)) {
+                if let Some(result) = req.result {
+                    self.set_workspace_roots(&result);
+                }
+            }
+            return None;
+        }
+
         // Handle notifications (no id)
         if id.is_none() {
-            if req.method == "notifications/initialized" || req.method == "initialized" {
-                eprintln!("MCP initialized notification received");
+            match req.method.as_str() {
+                "notifications/initialized" | "initialized" => {
+                    self.log(LogLevel::Debug, "initialized notification received");
+                    self.request_roots();
+                }
+                "notifications/roots/list_changed" => self.request_roots(),
+                _ => {}
             }
             return None;
         }
 
         let result = match req.method.as_str() {
             "initialize" => {
-                eprintln!("MCP initialize request received");
+                self.log(LogLevel::Debug, "initialize request received");
+                *self.client_roots.lock().unwrap() = req
+                    .params
+                    .as_ref()
+                    .and_then(|p| p.get("capabilities"))
+                    .and_then(|c| c.get("roots"))
+                    .is_some();
+                let mut client = self.client.lock().unwrap();
+                if client.is_none() {
+                    *client = req
+                        .params
+                        .as_ref()
+                        .and_then(|p| p["clientInfo"]["name"].as_str())
+                        .map(String::from);
+                }
+                drop(client);
                 Ok(serde_json::to_value(InitializeResult {
                     protocol_version: "2024-11-05".to_string(),
                     capabilities: {
@@ -125,6 +379,7 @@ impl ContextdServer {
                             "tools".to_string(),
                             serde_json::json!({"listChanged": true}),
                         );
+                        caps.insert("logging".to_string(), serde_json::json!({}));
                         caps
                     },
                     server_info: ServerInfo {
@@ -135,7 +390,7 @@ impl ContextdServer {
                 }).unwrap())
             }
             "tools/list" => {
-                eprintln!("MCP tools/list request received");
+                self.log(LogLevel::Debug, "tools/list request received");
                 Ok(serde_json::to_value(ListToolsResult {
                     tools: vec![
                         Tool {
@@ -145,13 +400,46 @@ impl ContextdServer {
                                 "type": "object",
                                 "properties": {
                                     "query": { "type": "string", "description": "The search query" },
-                                    "limit": { "type": "integer", "description": "Max results (default 5)" },
+                                    "page_size": { "type": "integer", "description": "Results per page (default 5, max 50)" },
+                                    "cursor": { "type": "string", "description": "nextCursor from a previous call, to fetch the following page" },
+                                    "limit": { "type": "integer", "description": "Deprecated alias for page_size" },
                                     "file_types": { "type": "array", "items": { "type": "string" }, "description": "Filter by file extension" },
-                                    "min_score": { "type": "number", "description": "Minimum similarity score (0.0-1.0)" }
+                                    "min_score": { "type": "number", "description": "Minimum similarity score (0.0-1.0)" },
+                                    "metadata_filters": {
+                                        "type": "array",
+                                        "description": "Predicates on chunk metadata (e.g. {\"key\": \"size\", \"lt\": 10000} or {\"key\": \"headers\", \"contains\": \"Install\"})",
+                                        "items": {
+                                            "type": "object",
+                                            "properties": {
+                                                "key": { "type": "string" },
+                                                "eq": {},
+                                                "ne": {},
+                                                "gt": { "type": "number" },
+                                                "gte": { "type": "number" },
+                                                "lt": { "type": "number" },
+                                                "lte": { "type": "number" },
+                                                "contains": {}
+                                            },
+                                            "required": ["key"]
+                                        }
+                                    },
+                                    "tags": { "type": "array", "items": { "type": "string" }, "description": "Only return chunks carrying one of these tags (directly or via a tagged file/directory)" },
+                                    "symbol": { "type": "string", "description": "Only return code chunks defining this symbol: a function, method, struct, class or module name such as 'parse_config' (exact match)" },
+                                    "session_id": { "type": "string", "description": "Conversation id; chunks already returned in this session are summarized instead of repeated" },
+                                    "include_seen": { "type": "boolean", "description": "Return already-seen chunks in full (default false)" },
+                                    "repeats": { "type": "string", "enum": ["summarize", "omit"], "description": "How to handle already-seen chunks (default summarize)" },
+                                    "citations": { "type": "boolean", "description": "Attach a 'path:start_line-end_line @ commit' citation to each result (default false)" },
+                                    "mode": { "type": "string", "enum": ["vector", "hybrid", "ensemble"], "description": "vector: embedding similarity; hybrid: fused with full-text matches, good for exact identifiers; ensemble: also search with the query's keywords (defaults to the server's configured mode)" },
+                                    "ensemble": { "type": "boolean", "description": "Also search with the query's keywords and fuse the results; improves recall for vague questions. Same as mode 'ensemble' (default false)" },
+                                    "hypothetical": { "type": "string", "description": "A short hypothetical answer or code snippet to search with as well; implies ensemble" },
+                                    "explain": { "type": "boolean", "description": "Annotate each result with why it ranked where it did: dense and BM25 scores, boosts, rerank delta and filters passed (default false)" },
+                                    "include_archived": { "type": "boolean", "description": "Also search chunks archived after no query returned them for a while, by embedding similarity only (default false)" },
+                                    "collection": { "type": "string", "description": "Only search this collection, e.g. one project of several; get_status lists them. Files outside every configured collection are in 'default'" }
                                 },
                                 "required": ["query"],
                                 "additionalProperties": false
                             }),
+                            output_schema: Some(search_output_schema()),
                             annotations: ToolAnnotations {
                                 title: "Search Context".to_string(),
                                 read_only_hint: true,
@@ -162,12 +450,13 @@ impl ContextdServer {
                         },
                         Tool {
                             name: "get_status".to_string(),
-                            description: "Get current indexing status: number of indexed files, total chunks, and database size. Use this to check if indexing is complete before searching, or to monitor the daemon's state.".to_string(),
+                            description: "Get current indexing status: number of indexed files, total chunks, database size, and time spent chunking, embedding and storing. Use this to check if indexing is complete before searching, or to monitor the daemon's state.".to_string(),
                             input_schema: serde_json::json!({
                                 "type": "object",
                                 "properties": {},
                                 "additionalProperties": false
                             }),
+                            output_schema: None,
                             annotations: ToolAnnotations {
                                 title: "Get Status".to_string(),
                                 read_only_hint: true,
@@ -176,117 +465,236 @@ impl ContextdServer {
                                 open_world_hint: true,
                             },
                         },
+                        Tool {
+                            name: "get_chunk".to_string(),
+                            description: "Fetch a single chunk by the chunk_id returned from search_context, e.g. to re-read a source cited earlier.".to_string(),
+                            input_schema: serde_json::json!({
+                                "type": "object",
+                                "properties": {
+                                    "chunk_id": { "type": "integer", "description": "Chunk id from a search result" }
+                                },
+                                "required": ["chunk_id"],
+                                "additionalProperties": false
+                            }),
+                            output_schema: None,
+                            annotations: ToolAnnotations {
+                                title: "Get Chunk".to_string(),
+                                read_only_hint: true,
+                                destructive_hint: false,
+                                idempotent_hint: true,
+                                open_world_hint: false,
+                            },
+                        },
+                        Tool {
+                            name: "read_file_range".to_string(),
+                            description: "Read current lines of a file under a watched directory, e.g. the code around a search hit. Give start_line/end_line (1-based, inclusive) or start_byte/end_byte; at most 1000 lines are returned.".to_string(),
+                            input_schema: serde_json::json!({
+                                "type": "object",
+                                "properties": {
+                                    "path": { "type": "string", "description": "File path, e.g. file_path from a search result" },
+                                    "start_line": { "type": "integer", "minimum": 1 },
+                                    "end_line": { "type": "integer", "minimum": 1 },
+                                    "start_byte": { "type": "integer", "minimum": 0 },
+                                    "end_byte": { "type": "integer", "minimum": 0 }
+                                },
+                                "required": ["path"],
+                                "additionalProperties": false
+                            }),
+                            output_schema: None,
+                            annotations: ToolAnnotations {
+                                title: "Read File Range".to_string(),
+                                read_only_hint: true,
+                                destructive_hint: false,
+                                idempotent_hint: true,
+                                open_world_hint: false,
+                            },
+                        },
+                        Tool {
+                            name: "list_projects".to_string(),
+                            description: "List the projects (watched roots) in the index, with file counts and which one is active. search_context is scoped to the active project.".to_string(),
+                            input_schema: serde_json::json!({
+                                "type": "object",
+                                "properties": {},
+                                "additionalProperties": false
+                            }),
+                            output_schema: None,
+                            annotations: ToolAnnotations {
+                                title: "List Projects".to_string(),
+                                read_only_hint: true,
+                                destructive_hint: false,
+                                idempotent_hint: true,
+                                open_world_hint: false,
+                            },
+                        },
+                        Tool {
+                            name: "set_active_project".to_string(),
+                            description: "Scope subsequent search_context calls to one project, by name or root path. Pass null or omit project to search everything again.".to_string(),
+                            input_schema: serde_json::json!({
+                                "type": "object",
+                                "properties": {
+                                    "project": { "type": ["string", "null"], "description": "Project name or root path from list_projects" }
+                                },
+                                "additionalProperties": false
+                            }),
+                            output_schema: None,
+                            annotations: ToolAnnotations {
+                                title: "Set Active Project".to_string(),
+                                read_only_hint: false,
+                                destructive_hint: false,
+                                idempotent_hint: true,
+                                open_world_hint: false,
+                            },
+                        },
+                        Tool {
+                            name: "save_conversation".to_string(),
+                            description: "Save a conversation transcript (role-tagged turns) to the conversations collection so future sessions can search what was discussed and decided. Saving again with the same conversation_id replaces it.".to_string(),
+                            input_schema: serde_json::json!({
+                                "type": "object",
+                                "properties": {
+                                    "conversation_id": { "type": "string", "description": "Stable id; generated if omitted" },
+                                    "title": { "type": "string" },
+                                    "turns": {
+                                        "type": "array",
+                                        "items": {
+                                            "type": "object",
+                                            "properties": {
+                                                "role": { "type": "string" },
+                                                "content": { "type": "string" },
+                                                "timestamp": { "type": "integer", "description": "Unix timestamp" }
+                                            },
+                                            "required": ["role", "content"]
+                                        }
+                                    }
+                                },
+                                "required": ["turns"],
+                                "additionalProperties": false
+                            }),
+                            output_schema: None,
+                            annotations: ToolAnnotations {
+                                title: "Save Conversation".to_string(),
+                                read_only_hint: false,
+                                destructive_hint: false,
+                                idempotent_hint: true,
+                                open_world_hint: false,
+                            },
+                        },
+                        Tool {
+                            name: "remember".to_string(),
+                            description: "Store a note in the index so later search_context calls, in this or future sessions, can find it: a decision, a finding, a user preference. Notes are marked source 'agent' with the time they were saved. Saving again with the same id replaces the note.".to_string(),
+                            input_schema: serde_json::json!({
+                                "type": "object",
+                                "properties": {
+                                    "text": { "type": "string", "description": "The note; markdown is fine" },
+                                    "id": { "type": "string", "description": "Stable id to replace the note by later; generated if omitted" },
+                                    "collection": { "type": "string", "description": "Collection to store it in (default: the client's first, else 'default')" },
+                                    "metadata": { "type": "object", "description": "Extra metadata to store with the note, e.g. {\"topic\": \"auth\"}" }
+                                },
+                                "required": ["text"],
+                                "additionalProperties": false
+                            }),
+                            output_schema: None,
+                            annotations: ToolAnnotations {
+                                title: "Remember".to_string(),
+                                read_only_hint: false,
+                                destructive_hint: false,
+                                idempotent_hint: false,
+                                open_world_hint: false,
+                            },
+                        },
+                        Tool {
+                            name: "index_path".to_string(),
+                            description: "Index a file or directory under a watched project now instead of waiting for the file watcher, e.g. right after writing or generating files. Unchanged files are skipped unless force is set.".to_string(),
+                            input_schema: serde_json::json!({
+                                "type": "object",
+                                "properties": {
+                                    "path": { "type": "string", "description": "File or directory to index" },
+                                    "force": { "type": "boolean", "description": "Re-index files even if unchanged (default false)" }
+                                },
+                                "required": ["path"],
+                                "additionalProperties": false
+                            }),
+                            output_schema: None,
+                            annotations: ToolAnnotations {
+                                title: "Index Path".to_string(),
+                                read_only_hint: false,
+                                destructive_hint: false,
+                                idempotent_hint: true,
+                                open_world_hint: false,
+                            },
+                        },
+                        Tool {
+                            name: "list_tags".to_string(),
+                            description: "List all tags and the files, directories or chunks they are attached to. Tags can be passed to search_context to scope a search.".to_string(),
+                            input_schema: serde_json::json!({
+                                "type": "object",
+                                "properties": {},
+                                "additionalProperties": false
+                            }),
+                            output_schema: None,
+                            annotations: ToolAnnotations {
+                                title: "List Tags".to_string(),
+                                read_only_hint: true,
+                                destructive_hint: false,
+                                idempotent_hint: true,
+                                open_world_hint: false,
+                            },
+                        },
+                        Tool {
+                            name: "update_tags".to_string(),
+                            description: "Add or remove tags on a file, a directory, or a single chunk. Tags on a directory apply to every file below it.".to_string(),
+                            input_schema: serde_json::json!({
+                                "type": "object",
+                                "properties": {
+                                    "path": { "type": "string", "description": "File or directory to tag" },
+                                    "chunk_id": { "type": "integer", "description": "Chunk to tag (instead of path)" },
+                                    "add": { "type": "array", "items": { "type": "string" }, "description": "Tags to add" },
+                                    "remove": { "type": "array", "items": { "type": "string" }, "description": "Tags to remove" }
+                                },
+                                "additionalProperties": false
+                            }),
+                            output_schema: None,
+                            annotations: ToolAnnotations {
+                                title: "Update Tags".to_string(),
+                                read_only_hint: false,
+                                destructive_hint: false,
+                                idempotent_hint: true,
+                                open_world_hint: false,
+                            },
+                        },
                     ],
                 }).unwrap())
             }
+            "logging/setLevel" => {
+                let level = req
+                    .params
+                    .as_ref()
+                    .and_then(|p| p.get("level"))
+                    .cloned()
+                    .and_then(|v| serde_json::from_value::<LogLevel>(v).ok());
+                match level {
+                    Some(level) => {
+                        *self.log_level.lock().unwrap() = level;
+                        Ok(serde_json::json!({}))
+                    }
+                    None => Err(JsonRpcError {
+                        code: -32602,
+                        message: "Invalid or missing log level".to_string(),
+                    }),
+                }
+            }
             "tools/call" => {
-                eprintln!("MCP tools/call request received");
                 if let Some(params) = req.params {
                     let name = params.get("name").and_then(|v| v.as_str()).unwrap_or("");
+                    self.log(LogLevel::Debug, format!("tools/call {}", name));
                     let args = params
                         .get("arguments")
                         .unwrap_or(&serde_json::json!({}))
                         .clone();
 
-                    match name {
-                        "search_context" => {
-                            let query = args.get("query").and_then(|v| v.as_str()).unwrap_or("");
-                            let limit =
-                                args.get("limit").and_then(|v| v.as_u64()).unwrap_or(5) as usize;
-                            let min_score = args
-                                .get("min_score")
-                                .and_then(|v| v.as_f64())
-                                .map(|v| v as f32);
-
-                            // Parse file_types
-                            let file_types =
-                                args.get("file_types")
-                                    .and_then(|v| v.as_array())
-                                    .map(|arr| {
-                                        arr.iter()
-                                            .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                                            .collect::<Vec<_>>()
-                                    });
-
-                            eprintln!("Executing search: '{}' (limit: {})", query, limit);
-
-                            // Embed query
-                            let embedding_result = self.embedder.embed(query);
-
-                            match embedding_result {
-                                Ok(embedding) => {
-                                    // Use existing search logic
-                                    let options = crate::storage::db::SearchOptions {
-                                        limit: Some(limit),
-                                        min_score,
-                                        file_types,
-                                        paths: None,
-                                        ..Default::default()
-                                    };
-
-                                    let results =
-                                        self.db.search_chunks_enhanced(&embedding, &options);
-
-                                    match results {
-                                        Ok(hits) => {
-                                            let mut text = String::new();
-                                            for hit in hits {
-                                                text.push_str(&format!(
-                                                    "File: {}\nScore: {:.2}\n\n{}\n\n---\n\n",
-                                                    hit.file_path, hit.score, hit.content
-                                                ));
-                                            }
-                                            if text.is_empty() {
-                                                text = "No results found.".to_string();
-                                            }
-                                            Ok(serde_json::to_value(CallToolResult {
-                                                content: vec![Content {
-                                                    kind: "text".to_string(),
-                                                    text,
-                                                }],
-                                                is_error: false,
-                                            })
-                                            .unwrap())
-                                        }
-                                        Err(e) => Err(JsonRpcError {
-                                            code: -32603,
-                                            message: format!("Search failed: {}", e),
-                                        }),
-                                    }
-                                }
-                                Err(e) => Err(JsonRpcError {
-                                    code: -32603,
-                                    message: format!("Embedding failed: {}", e),
-                                }),
-                            }
-                        }
-                        "get_status" => match self.db.get_stats() {
-                            Ok(stats) => {
-                                let text = format!(
-                                    "Indexed Files: {}\nTotal Chunks: {}\nDatabase Size: {:.2} MB",
-                                    stats.file_count,
-                                    stats.chunk_count,
-                                    stats.db_size as f64 / 1024.0 / 1024.0
-                                );
-                                Ok(serde_json::to_value(CallToolResult {
-                                    content: vec![Content {
-                                        kind: "text".to_string(),
-                                        text,
-                                    }],
-                                    is_error: false,
-                                })
-                                .unwrap())
-                            }
-                            Err(e) => Err(JsonRpcError {
-                                code: -32603,
-                                message: format!("Failed to get stats: {}", e),
-                            }),
-                        },
-                        _ => Err(JsonRpcError {
-                            code: -32601,
-                            message: format!("Unknown tool: {}", name),
-                        }),
-                    }
+                    let name = name.to_string();
+                    self.blocking(move |server| server.call_tool(&name, args))
+                        .await
+                        .and_then(|result| result)
                 } else {
                     Err(JsonRpcError {
                         code: -32602,
@@ -315,12 +723,895 @@ impl ContextdServer {
             }),
         }
     }
+
+    /// Run a `tools/call` request.
+    fn call_tool(&self, name: &str, args: Value) -> Result<Value, JsonRpcError> {
+        match name {
+            "search_context" => self.search_context(&args),
+            "get_status" => match self.db.get_stats() {
+                Ok(stats) => {
+                    let mut text = format!(
+                        "Indexed Files: {}\nTotal Chunks: {}\nDatabase Size: {:.2} MB",
+                        stats.file_count,
+                        stats.chunk_count,
+                        stats.db_size as f64 / 1024.0 / 1024.0
+                    );
+                    if let Ok(collections) = self.db.collection_stats() {
+                        let scope = self.scope();
+                        for c in collections {
+                            if scope.allows_collection(&c.name) {
+                                text.push_str(&format!(
+                                    "\nCollection {}: {} files, {} chunks",
+                                    c.name, c.files, c.chunks
+                                ));
+                            }
+                        }
+                    }
+                    if let Ok(Some(scan)) = crate::scan::progress(&self.db) {
+                        text.push_str(&format!("\n{}", scan.describe()));
+                    }
+                    if let Ok(timings) = self.db.index_timing_stats(3) {
+                        text.push_str(&format_timings(&timings));
+                    }
+                    Ok(text_result(text))
+                }
+                Err(e) => Err(JsonRpcError::failed("Failed to get stats", e)),
+            },
+            "list_tags" => match self.db.list_tags() {
+                Ok(tags) => {
+                    let mut text = String::new();
+                    for entry in tags {
+                        let target = match (entry.path, entry.chunk_id) {
+                            (Some(path), _) if path.is_empty() => "(everything)".to_string(),
+                            (Some(path), _) if self.scope().allows_path(Path::new(&path)) => path,
+                            (None, Some(id)) if self.chunk_in_scope(id) => {
+                                format!("chunk:{}", id)
+                            }
+                            _ => continue,
+                        };
+                        text.push_str(&format!("{}: {}\n", entry.tag, target));
+                    }
+                    if text.is_empty() {
+                        text = "No tags.".to_string();
+                    }
+                    Ok(text_result(text))
+                }
+                Err(e) => Err(JsonRpcError::failed("Failed to list tags", e)),
+            },
+            "update_tags" => self.update_tags(&args),
+            "get_chunk" => self.get_chunk(&args),
+            "read_file_range" => self.read_file_range(args),
+            "list_projects" => self.list_projects(),
+            "save_conversation" => self.save_conversation(args),
+            "remember" => self.remember(&args),
+            "index_path" => self.index_path(&args),
+            "set_active_project" => self.set_active_project(&args),
+            _ => Err(JsonRpcError {
+                code: -32601,
+                message: format!("Unknown tool: {}", name),
+            }),
+        }
+    }
+
+    /// Run `f` on tokio's blocking pool, as tools query the database and
+    /// embed text, which would otherwise stall the runtime.
+    async fn blocking<T: Send + 'static>(
+        self: &Arc<Self>,
+        f: impl FnOnce(&Self) -> T + Send + 'static,
+    ) -> Result<T, JsonRpcError> {
+        let server = self.clone();
+        tokio::task::spawn_blocking(move || f(&server))
+            .await
+            .map_err(|e| JsonRpcError {
+                code: -32603,
+                message: format!("Internal error: {}", e),
+            })
+    }
+
+    fn search_context(&self, args: &Value) -> Result<Value, JsonRpcError> {
+        let query = args.get("query").and_then(|v| v.as_str()).unwrap_or("");
+        // `limit` is the older name for `page_size`
+        let page_size = args
+            .get("page_size")
+            .or_else(|| args.get("limit"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(self.config.search.default_limit as u64)
+            .clamp(1, MAX_PAGE_SIZE) as usize;
+        let offset = match args.get("cursor").and_then(|v| v.as_str()) {
+            Some(cursor) => decode_cursor(cursor).ok_or_else(|| JsonRpcError {
+                code: -32602,
+                message: format!("Invalid cursor: {}", cursor),
+            })?,
+            None => 0,
+        };
+        let min_score = args
+            .get("min_score")
+            .and_then(|v| v.as_f64())
+            .map(|v| v as f32);
+
+        // Parse file_types
+        let file_types = args
+            .get("file_types")
+            .and_then(|v| v.as_array())
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                    .collect::<Vec<_>>()
+            });
+
+        let metadata_filters = optional_arg(args, "metadata_filters")?;
+        let tags = optional_arg(args, "tags")?;
+        let symbol = args
+            .get("symbol")
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        let citations = args
+            .get("citations")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let explain = args
+            .get("explain")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let include_archived = args
+            .get("include_archived")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let collection = args.get("collection").and_then(|v| v.as_str());
+        let hypothetical = args
+            .get("hypothetical")
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        let mode = if args.get("ensemble").and_then(|v| v.as_bool()) == Some(true) {
+            Some(SearchMode::Ensemble)
+        } else {
+            optional_arg(args, "mode")?
+        };
+        let repeats = optional_arg(args, "repeats")?.unwrap_or_default();
+        let session = args
+            .get("session_id")
+            .and_then(|v| v.as_str())
+            .map(|id| SessionOptions {
+                id: id.to_string(),
+                include_seen: args
+                    .get("include_seen")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false),
+                repeats,
+            });
+
+        self.log(
+            LogLevel::Info,
+            format!(
+                "Executing search: '{}' (page size: {}, offset: {})",
+                query, page_size, offset
+            ),
+        );
+
+        let scope = self.scope();
+        let query = search::Query {
+            text: query.to_string(),
+            hypothetical,
+            mode,
+            options: crate::storage::db::SearchOptions {
+                limit: Some(page_size),
+                min_score,
+                file_types,
+                metadata_filters,
+                tags,
+                symbol,
+                roots: scope.narrow(
+                    self.active_project
+                        .lock()
+                        .unwrap()
+                        .clone()
+                        .map(|r| vec![r])
+                        .or_else(|| self.client_projects()),
+                ),
+                collections: scope.narrow_collections(collection),
+                explain,
+                include_archived,
+                ..Default::default()
+            },
+            offset,
+            citations,
+            session,
+        };
+        let client = self.client.lock().unwrap().clone();
+        let service = QueryService::new("mcp", &self.db, &self.models, &self.config, &self.audit)
+            .with_sessions(&self.sessions);
+        let page = service
+            .search(&query, client.as_deref())
+            .map_err(|e| JsonRpcError::failed("Search failed", e))?;
+        let next_cursor = page.next_offset.map(encode_cursor);
+        let page: Vec<SearchHit> = page
+            .hits
+            .into_iter()
+            .map(|hit| SearchHit {
+                line_start: hit.line_start(),
+                line_end: hit.line_end(),
+                seen: hit.seen,
+                stale_reason: hit.stale_reason,
+                citation: hit.citation.map(|c| c.text),
+                chunk_id: hit.result.id,
+                file_path: hit.result.file_path,
+                file_type: hit.result.file_type,
+                score: hit.result.score,
+                content: hit.result.content,
+                explanation: hit.result.explanation,
+            })
+            .collect();
+
+        let content: Vec<Content> = page
+            .iter()
+            .map(|hit| {
+                let mut text = format!("File: {}\nScore: {:.2}\n", hit.file_path, hit.score);
+                if let Some(citation) = &hit.citation {
+                    text.push_str(&format!("Cite: {}\n", citation));
+                }
+                if let Some(reason) = &hit.stale_reason {
+                    text.push_str(&format!("Stale: {}\n", reason));
+                }
+                if let Some(explanation) = &hit.explanation {
+                    text.push_str(&format!("Ranked: {}\n", describe_rank(explanation)));
+                }
+                text.push('\n');
+                text.push_str(&hit.content);
+                Content {
+                    kind: "text".to_string(),
+                    text,
+                }
+            })
+            .collect();
+        // Results may be missing while the daemon's startup scan runs
+        let scan = crate::scan::progress(&self.db).ok().flatten();
+        let mut structured = serde_json::json!({
+            "results": page,
+            "nextCursor": next_cursor,
+        });
+        if scan.is_some() {
+            structured["warmingUp"] = serde_json::json!(true);
+        }
+        if let Ok(freshness) = crate::freshness::current(&self.db) {
+            structured["freshness"] = serde_json::json!(freshness);
+        }
+
+        let mut content = if content.is_empty() {
+            vec![Content {
+                kind: "text".to_string(),
+                text: "No results found.".to_string(),
+            }]
+        } else {
+            content
+        };
+        if let Some(scan) = scan {
+            content.push(Content {
+                kind: "text".to_string(),
+                text: format!("Note: {}; results may be incomplete.", scan.describe()),
+            });
+        }
+
+        Ok(serde_json::to_value(CallToolResult {
+            content,
+            is_error: false,
+            next_cursor,
+            structured_content: Some(structured),
+        })
+        .unwrap())
+    }
+
+    /// Configured watch roots, leaving out those entirely outside the
+    /// client's scope. Workspaces registered by `contextd index` or reported
+    /// by clients are not among them, so they never widen what a client may
+    /// read.
+    fn project_roots(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut roots = self.config.watch.confined_paths();
+        let scope = self.scope();
+        roots.retain(|root| scope.overlaps(root));
+        Ok(roots)
+    }
+
+    /// The project roots overlapping the workspace roots the client
+    /// reported, which searches without an active project are narrowed to.
+    /// `None` when the client reported none of the indexed projects.
+    fn client_projects(&self) -> Option<Vec<PathBuf>> {
+        let reported = self.workspace_roots.lock().unwrap().clone()?;
+        let projects: Vec<PathBuf> = self
+            .project_roots()
+            .ok()?
+            .into_iter()
+            .filter(|root| {
+                let root = canonicalize(root).unwrap_or_else(|_| root.clone());
+                reported
+                    .iter()
+                    .any(|ws| ws.starts_with(&root) || root.starts_with(ws))
+            })
+            .collect();
+        (!projects.is_empty()).then_some(projects)
+    }
+
+    /// Whether chunk `id` exists and the client may see it.
+    fn chunk_in_scope(&self, id: i64) -> bool {
+        matches!(
+            self.db.get_chunk(id),
+            Ok(Some(record)) if self
+                .scope()
+                .allows_chunk(&record.file_path, record.chunk.metadata.as_deref())
+        )
+    }
+
+    fn projects(&self) -> anyhow::Result<Vec<Project>> {
+        let files = self.db.list_files()?;
+        let active = self.active_project.lock().unwrap().clone();
+        Ok(self
+            .project_roots()?
+            .iter()
+            .map(|root| Project {
+                name: project_name(root),
+                root: root.clone(),
+                files: files
+                    .iter()
+                    .filter(|f| Path::new(&f.path).starts_with(root))
+                    .count(),
+                active: active.as_ref() == Some(root),
+            })
+            .collect())
+    }
+
+    fn list_projects(&self) -> Result<Value, JsonRpcError> {
+        let projects = self
+            .projects()
+            .map_err(|e| JsonRpcError::failed("Failed to list projects", e))?;
+        let mut text = String::new();
+        for p in &projects {
+            text.push_str(&format!(
+                "{}{} ({}): {} files\n",
+                if p.active { "* " } else { "" },
+                p.name,
+                p.root.display(),
+                p.files
+            ));
+        }
+        if text.is_empty() {
+            text = "No projects.".to_string();
+        }
+        let mut result = text_result(text);
+        result["structuredContent"] = serde_json::json!({ "projects": projects });
+        Ok(result)
+    }
+
+    fn set_active_project(&self, args: &Value) -> Result<Value, JsonRpcError> {
+        let requested = args
+            .get("project")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty());
+        let Some(requested) = requested else {
+            *self.active_project.lock().unwrap() = None;
+            return Ok(text_result(
+                "Cleared active project; searching all projects".to_string(),
+            ));
+        };
+
+        let roots = self
+            .project_roots()
+            .map_err(|e| JsonRpcError::failed("Failed to list projects", e))?;
+        let root = roots
+            .into_iter()
+            .find(|root| project_name(root) == requested || Path::new(requested) == *root)
+            .ok_or_else(|| JsonRpcError {
+                code: -32602,
+                message: format!("Unknown project: {}", requested),
+            })?;
+        let text = format!(
+            "Active project: {} ({})",
+            project_name(&root),
+            root.display()
+        );
+        *self.active_project.lock().unwrap() = Some(root);
+        Ok(text_result(text))
+    }
+
+    fn save_conversation(&self, args: Value) -> Result<Value, JsonRpcError> {
+        let transcript: Transcript = serde_json::from_value(args).map_err(|e| JsonRpcError {
+            code: -32602,
+            message: format!("Invalid transcript: {}", e),
+        })?;
+        if !self.scope().allows_collection(transcripts::COLLECTION) {
+            return Err(JsonRpcError {
+                code: -32602,
+                message: format!(
+                    "This client may not write to the '{}' collection",
+                    transcripts::COLLECTION
+                ),
+            });
+        }
+        let _pinned = self.models.pin();
+        let saved = transcripts::save(
+            &self.db,
+            &self.models.for_collection(Some(transcripts::COLLECTION)),
+            &transcript,
+        )
+        .map_err(|e| JsonRpcError::failed("Failed to save conversation", e))?;
+        Ok(text_result(format!(
+            "Saved {} turns as {}",
+            saved.chunks, saved.path
+        )))
+    }
+
+    fn remember(&self, args: &Value) -> Result<Value, JsonRpcError> {
+        let invalid = |message: String| JsonRpcError {
+            code: -32602,
+            message,
+        };
+        let text = args
+            .get("text")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid("text is required".to_string()))?;
+        let now = chrono::Utc::now();
+        let id = args
+            .get("id")
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .unwrap_or_else(|| now.format("%Y%m%dT%H%M%S%.3fZ").to_string());
+        let mut metadata = match args.get("metadata") {
+            Some(Value::Object(metadata)) => metadata.clone(),
+            Some(_) => return Err(invalid("metadata must be an object".to_string())),
+            None => serde_json::Map::new(),
+        };
+        metadata.insert("source".to_string(), NOTE_SOURCE.into());
+        metadata.insert("timestamp".to_string(), now.timestamp().into());
+        let scope = self.scope();
+        let collection = args
+            .get("collection")
+            .and_then(|v| v.as_str())
+            .or_else(|| {
+                scope
+                    .collections
+                    .as_ref()
+                    .and_then(|c| c.first())
+                    .map(String::as_str)
+            })
+            .map(String::from);
+        if let Some(collection) = collection {
+            metadata.insert("collection".to_string(), collection.into());
+        }
+
+        let document = Document {
+            path_or_id: format!("{}/{}", NOTE_SOURCE, id),
+            content: text.to_string(),
+            content_type: Some("text/markdown".to_string()),
+            metadata,
+        };
+        let path = documents::path(&document.path_or_id).map_err(|e| invalid(e.to_string()))?;
+        let chunk_metadata = Value::Object(document.metadata.clone()).to_string();
+        if !scope.allows_chunk(&path, Some(&chunk_metadata)) {
+            return Err(invalid(
+                "This client may not write to that collection".to_string(),
+            ));
+        }
+        let saved = documents::ingest(&self.db, &self.models, &self.config, &document)
+            .map_err(|e| JsonRpcError::failed("Failed to store the note", e))?;
+        Ok(text_result(format!(
+            "Remembered as {} ({} chunks)",
+            saved.path, saved.chunks
+        )))
+    }
+
+    fn index_path(&self, args: &Value) -> Result<Value, JsonRpcError> {
+        let invalid = |message: String| JsonRpcError {
+            code: -32602,
+            message,
+        };
+        let path = args
+            .get("path")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid("path is required".to_string()))?;
+        let force = args.get("force").and_then(|v| v.as_bool()).unwrap_or(false);
+        let scope = self.scope();
+        let project_roots = self
+            .project_roots()
+            .map_err(|e| JsonRpcError::failed("Failed to list projects", e))?;
+        let roots = WatchRoots::new(scope.narrow(Some(project_roots)).unwrap_or_default());
+        let path = roots
+            .authorize(Path::new(path))
+            .map_err(|e| invalid(e.to_string()))?;
+        let runtime = tokio::runtime::Handle::try_current()
+            .map_err(|e| JsonRpcError::failed("Failed to index", e))?;
+
+        let mut files: Vec<PathBuf> = if path.is_dir() {
+            daemon::walk(&path, &self.config.watch)
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+                .map(|entry| entry.into_path())
+                .take(MAX_INDEX_PATH_FILES + 1)
+                .collect()
+        } else {
+            vec![path.clone()]
+        };
+        let truncated = files.len() > MAX_INDEX_PATH_FILES;
+        files.truncate(MAX_INDEX_PATH_FILES);
+        files.retain(|file| {
+            let collection = collections::collection_for(&self.config, file)
+                .unwrap_or(staleness::DEFAULT_COLLECTION);
+            self.config.watch.allows(file) && scope.allows_collection(collection)
+        });
+
+        let config = Arc::new(self.config.clone());
+        let mut failed = Vec::new();
+        for file in &files {
+            let file_str = file.to_string_lossy().to_string();
+            if force {
+                self.db
+                    .mark_unindexed(&file_str)
+                    .map_err(|e| JsonRpcError::failed("Failed to index", e))?;
+            }
+            runtime.block_on(daemon::index_file(
+                file.clone(),
+                config.clone(),
+                self.db.clone(),
+                self.models.clone(),
+            ));
+            if let Ok(Some(error)) = self.db.get_index_error(&file_str) {
+                failed.push(format!("{}: {}", file_str, error));
+            }
+        }
+
+        let mut text = format!(
+            "Checked {} files under {}; changed ones were indexed",
+            files.len(),
+            path.display()
+        );
+        if truncated {
+            text.push_str(&format!(
+                "\nStopped after {} files; index subdirectories separately",
+                MAX_INDEX_PATH_FILES
+            ));
+        }
+        if !failed.is_empty() {
+            text.push_str(&format!(
+                "\n{} failed:\n{}",
+                failed.len(),
+                failed.join("\n")
+            ));
+        }
+        Ok(text_result(text))
+    }
+
+    fn get_chunk(&self, args: &Value) -> Result<Value, JsonRpcError> {
+        let chunk_id = args
+            .get("chunk_id")
+            .and_then(|v| v.as_i64())
+            .ok_or_else(|| JsonRpcError {
+                code: -32602,
+                message: "chunk_id is required".to_string(),
+            })?;
+        let record = self
+            .db
+            .get_chunk(chunk_id)
+            .map_err(|e| JsonRpcError::failed("Failed to fetch chunk", e))?
+            // Chunks out of scope look the same as missing ones
+            .filter(|record| {
+                self.scope()
+                    .allows_chunk(&record.file_path, record.chunk.metadata.as_deref())
+            })
+            .ok_or_else(|| JsonRpcError {
+                code: -32602,
+                message: format!("No chunk with id {}", chunk_id),
+            })?;
+
+        let chunk = record.chunk;
+        let citation = Citer::default().cite(&record.file_path, chunk.start, chunk.end);
+        let mut result = text_result(format!(
+            "File: {}\nCite: {}\n\n{}",
+            record.file_path, citation.text, chunk.content
+        ));
+        result["structuredContent"] = serde_json::json!({
+            "chunk_id": chunk.id,
+            "file_path": record.file_path,
+            "content": chunk.content,
+            "line_start": citation.line_start,
+            "line_end": citation.line_end,
+            "citation": citation.text,
+            "metadata": chunk.metadata.and_then(|m| serde_json::from_str::<Value>(&m).ok()),
+        });
+        Ok(result)
+    }
+
+    fn read_file_range(&self, args: Value) -> Result<Value, JsonRpcError> {
+        let invalid = |message: String| JsonRpcError {
+            code: -32602,
+            message,
+        };
+        let path = args
+            .get("path")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid("path is required".to_string()))?
+            .to_string();
+        let range: RangeRequest =
+            serde_json::from_value(args).map_err(|e| invalid(format!("Invalid range: {}", e)))?;
+        let scope = self.scope();
+        let project_roots = self
+            .project_roots()
+            .map_err(|e| JsonRpcError::failed("Failed to list projects", e))?;
+        let roots = WatchRoots::new(scope.narrow(Some(project_roots)).unwrap_or_default());
+        let collection = collections::collection_for(&self.config, Path::new(&path))
+            .unwrap_or(staleness::DEFAULT_COLLECTION);
+        let file_range = if scope.allows_collection(collection) {
+            files::read_range(&roots, Path::new(&path), &range)
+        } else {
+            Err(RangeError::Forbidden(format!(
+                "{} is in a collection this client may not read",
+                path
+            )))
+        }
+        .map_err(|e| invalid(e.to_string()))?;
+
+        let mut text = format!(
+            "File: {} (lines {}-{} of {})\n\n{}",
+            file_range.path,
+            file_range.start_line,
+            file_range.end_line,
+            file_range.total_lines,
+            file_range.content
+        );
+        if file_range.truncated {
+            text.push_str(&format!(
+                "\n[Cut to {} lines; continue from line {}]",
+                files::MAX_LINES,
+                file_range.end_line + 1
+            ));
+        }
+        let mut result = text_result(text);
+        result["structuredContent"] = serde_json::to_value(&file_range).unwrap_or_default();
+        Ok(result)
+    }
+
+    fn update_tags(&self, args: &Value) -> Result<Value, JsonRpcError> {
+        let path = args.get("path").and_then(|v| v.as_str());
+        let chunk_id = args.get("chunk_id").and_then(|v| v.as_i64());
+        if path.is_some() == chunk_id.is_some() {
+            return Err(JsonRpcError {
+                code: -32602,
+                message: "Exactly one of path or chunk_id is required".to_string(),
+            });
+        }
+        let tag_list = |key: &str| -> Vec<String> {
+            args.get(key)
+                .and_then(|v| v.as_array())
+                .map(|arr| {
+                    arr.iter()
+                        .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let (add, remove) = (tag_list("add"), tag_list("remove"));
+        let in_scope = match (path, chunk_id) {
+            (Some(p), _) => self.scope().allows_path(Path::new(p)),
+            (_, Some(id)) => self.chunk_in_scope(id),
+            _ => unreachable!(),
+        };
+        if !in_scope {
+            return Err(JsonRpcError {
+                code: -32602,
+                message: "That path or chunk is outside this client's scope".to_string(),
+            });
+        }
+
+        let apply = || -> anyhow::Result<()> {
+            for tag in &add {
+                match (path, chunk_id) {
+                    (Some(p), _) => self.db.add_path_tag(p, tag)?,
+                    (_, Some(id)) => self.db.add_chunk_tag(id, tag)?,
+                    _ => unreachable!(),
+                }
+            }
+            for tag in &remove {
+                match (path, chunk_id) {
+                    (Some(p), _) => self.db.remove_path_tag(p, tag)?,
+                    (_, Some(id)) => self.db.remove_chunk_tag(id, tag)?,
+                    _ => unreachable!(),
+                };
+            }
+            Ok(())
+        };
+
+        match apply() {
+            Ok(()) => Ok(text_result(format!(
+                "Added {} and removed {} tag(s)",
+                add.len(),
+                remove.len()
+            ))),
+            Err(e) => Err(JsonRpcError::failed("Failed to update tags", e)),
+        }
+    }
+}
+
+/// Wrap plain text as a successful tool result.
+fn text_result(text: String) -> Value {
+    serde_json::to_value(CallToolResult {
+        content: vec![Content {
+            kind: "text".to_string(),
+            text,
+        }],
+        is_error: false,
+        next_cursor: None,
+        structured_content: None,
+    })
+    .unwrap()
+}
+
+/// Where indexing time went, for `get_status`.
+fn format_timings(stats: &TimingStats) -> String {
+    if stats.files == 0 {
+        return String::new();
+    }
+    let ms = |us: u64| us as f64 / 1000.0;
+    let mut text = format!(
+        "\nIndexing Time ({} files): chunking {:.0} ms, embedding {:.0} ms, storage {:.0} ms\nPer File: chunking {:.1} ms, embedding {:.1} ms, storage {:.1} ms",
+        stats.files,
+        ms(stats.total.chunking_us),
+        ms(stats.total.embedding_us),
+        ms(stats.total.storage_us),
+        ms(stats.average.chunking_us),
+        ms(stats.average.embedding_us),
+        ms(stats.average.storage_us),
+    );
+    text.push_str("\nSlowest Files:");
+    for file in &stats.slowest {
+        text.push_str(&format!(
+            "\n  {} ({:.0} ms: chunking {:.0}, embedding {:.0}, storage {:.0})",
+            file.path,
+            ms(file.timings.total_us()),
+            ms(file.timings.chunking_us),
+            ms(file.timings.embedding_us),
+            ms(file.timings.storage_us),
+        ));
+    }
+    text
+}
+
+/// A `notifications/message` log notification.
+fn log_notification(level: LogLevel, message: &str) -> Value {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "notifications/message",
+        "params": {
+            "level": level,
+            "logger": "contextd",
+            "data": message
+        }
+    })
+}
+
+/// One line on why a hit ranked where it did, e.g. `dense 0.81 (#3), bm25
+/// 4.20 (#1), recency +0.02, frequency +0.00, rerank +2, filters file_types`.
+fn describe_rank(explanation: &RankExplanation) -> String {
+    let mut parts = Vec::new();
+    if let (Some(score), Some(rank)) = (explanation.dense_score, explanation.dense_rank) {
+        parts.push(format!("dense {:.2} (#{})", score, rank));
+    }
+    if let (Some(score), Some(rank)) = (explanation.bm25_score, explanation.keyword_rank) {
+        parts.push(format!("bm25 {:.2} (#{})", score, rank));
+    }
+    parts.push(format!("recency {:+.2}", explanation.recency_boost));
+    parts.push(format!("frequency {:+.2}", explanation.frequency_boost));
+    parts.push(format!("rerank {:+}", explanation.rerank_delta));
+    if !explanation.filters_passed.is_empty() {
+        parts.push(format!("filters {}", explanation.filters_passed.join(", ")));
+    }
+    parts.join(", ")
+}
+
+/// JSON schema of the structured search_context result.
+fn search_output_schema() -> Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "results": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "chunk_id": { "type": "integer" },
+                        "file_path": { "type": "string" },
+                        "file_type": { "type": "string" },
+                        "score": { "type": "number" },
+                        "content": { "type": "string" },
+                        "line_start": { "type": "integer" },
+                        "line_end": { "type": "integer" },
+                        "seen": { "type": "boolean" },
+                        "stale_reason": { "type": "string" },
+                        "citation": { "type": "string" },
+                        "explanation": {
+                            "type": "object",
+                            "properties": {
+                                "dense_score": { "type": "number" },
+                                "dense_rank": { "type": "integer" },
+                                "bm25_score": { "type": "number" },
+                                "keyword_rank": { "type": "integer" },
+                                "recency_boost": { "type": "number" },
+                                "frequency_boost": { "type": "number" },
+                                "rerank_delta": { "type": "integer" },
+                                "filters_passed": { "type": "array", "items": { "type": "string" } }
+                            }
+                        }
+                    },
+                    "required": ["chunk_id", "file_path", "file_type", "score", "content"]
+                }
+            },
+            "nextCursor": { "type": ["string", "null"] },
+            "warmingUp": { "type": "boolean" },
+            "freshness": {
+                "type": "object",
+                "properties": {
+                    "watching": { "type": "boolean" },
+                    "last_event_at": { "type": ["integer", "null"] },
+                    "pending": { "type": "integer" },
+                    "generation": { "type": "integer" }
+                }
+            }
+        },
+        "required": ["results"]
+    })
+}
+
+/// Display name of a watched root: its directory name, resolving `.` and
+/// similar relative roots against the current directory.
+fn project_name(root: &Path) -> String {
+    canonicalize(root)
+        .ok()
+        .as_deref()
+        .unwrap_or(root)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| root.display().to_string())
+}
+
+/// The argument `name` of a tool call, if given; one of the wrong shape is
+/// rejected with the reason rather than ignored.
+fn optional_arg<T: serde::de::DeserializeOwned>(
+    args: &Value,
+    name: &str,
+) -> Result<Option<T>, JsonRpcError> {
+    match args.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => T::deserialize(value).map(Some).map_err(|e| JsonRpcError {
+            code: -32602,
+            message: format!("Invalid {}: {}", name, e),
+        }),
+    }
+}
+
+/// Cursors are opaque to clients; they encode the offset of the next page.
+fn encode_cursor(offset: usize) -> String {
+    format!("offset:{}", offset)
+}
+
+/// The offset `cursor` encodes, or `None` if it is invalid or past
+/// [`search::MAX_OFFSET`].
+fn decode_cursor(cursor: &str) -> Option<usize> {
+    let offset = cursor.strip_prefix("offset:")?.parse().ok()?;
+    (offset <= search::MAX_OFFSET).then_some(offset)
 }
 
-/// Run the MCP server over stdio (manual implementation)
-pub async fn run_mcp_server(db: Database, embedder: Arc<Embedder>, config: Config) {
-    let server = ContextdServer::new(db, embedder, config);
-    eprintln!("contextd MCP server starting on stdio (manual)...");
+/// Run the MCP server over stdio (manual implementation). `client` names the
+/// client's `[mcp.clients]` entry, overriding the name it sends.
+pub async fn run_mcp_server(
+    db: Database,
+    models: Arc<Models>,
+    config: Config,
+    client: Option<String>,
+    audit: AuditLog,
+) {
+    let server = Arc::new(ContextdServer::new(
+        db,
+        models,
+        config,
+        client,
+        Arc::new(audit),
+    ));
+    log::info!("contextd MCP server starting on stdio (manual)...");
 
     let stdin = tokio::io::stdin();
     let mut reader = BufReader::new(stdin).lines();
@@ -331,33 +1622,238 @@ pub async fn run_mcp_server(db: Database, embedder: Arc<Embedder>, config: Confi
             continue;
         }
 
-        // Parse request
-        match serde_json::from_str::<JsonRpcRequest>(&line) {
-            Ok(req) => {
-                if let Some(resp) = server.handle_request(req).await {
-                    let json = serde_json::to_string(&resp).unwrap();
-                    eprintln!("Sending response: {}", json);
-                    println!("{}", json);
-                }
-            }
-            Err(e) => {
-                eprintln!("Failed to parse JSON-RPC: {} (line: {})", e, line);
-                // Send parse error
-                let error_resp = JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: None,
-                    result: None,
-                    error: Some(JsonRpcError {
-                        code: -32700,
-                        message: "Parse error".to_string(),
-                    }),
-                };
-                let json = serde_json::to_string(&error_resp).unwrap();
-                let _ = stdout.write_all(format!("{}\n", json).as_bytes()).await;
-                let _ = stdout.flush().await;
-            }
+        for json in server.handle_message(&line).await {
+            let _ = stdout.write_all(format!("{}\n", json).as_bytes()).await;
         }
+        let _ = stdout.flush().await;
+    }
+
+    log::info!("MCP server stdin closed, exiting.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        assert_eq!(decode_cursor(&encode_cursor(15)), Some(15));
+        assert_eq!(decode_cursor("15"), None);
+        assert_eq!(decode_cursor("offset:-1"), None);
+        assert_eq!(
+            decode_cursor(&encode_cursor(search::MAX_OFFSET)),
+            Some(search::MAX_OFFSET)
+        );
+        assert_eq!(decode_cursor(&encode_cursor(search::MAX_OFFSET + 1)), None);
+        assert_eq!(decode_cursor(&format!("offset:{}", u128::MAX)), None);
+    }
+
+    #[test]
+    fn test_optional_arg() {
+        let args = serde_json::json!({
+            "tags": ["api"],
+            "metadata_filters": { "lang": 3 },
+            "mode": null,
+        });
+        let tags: Option<Vec<String>> = optional_arg(&args, "tags").unwrap();
+        assert_eq!(tags, Some(vec!["api".to_string()]));
+        assert_eq!(optional_arg::<SearchMode>(&args, "mode").unwrap(), None);
+        assert_eq!(optional_arg::<SearchMode>(&args, "absent").unwrap(), None);
+        let err =
+            optional_arg::<Vec<crate::storage::db::MetadataFilter>>(&args, "metadata_filters")
+                .unwrap_err();
+        assert_eq!(err.code, -32602);
+        assert!(
+            err.message
+                .starts_with("Invalid metadata_filters: invalid type"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_search_hit_serialization() {
+        let hit = SearchHit {
+            chunk_id: 7,
+            file_path: "src/main.rs".to_string(),
+            file_type: "rs".to_string(),
+            score: 0.5,
+            content: "fn main() {}".to_string(),
+            line_start: None,
+            line_end: None,
+            seen: false,
+            stale_reason: None,
+            citation: None,
+            explanation: None,
+        };
+        let value = serde_json::to_value(&hit).unwrap();
+        assert_eq!(value["chunk_id"], 7);
+        assert!(value.get("line_start").is_none());
+    }
+
+    #[test]
+    fn test_log_levels() {
+        let level: LogLevel = serde_json::from_value(serde_json::json!("warning")).unwrap();
+        assert_eq!(level, LogLevel::Warning);
+        assert!(LogLevel::Error > LogLevel::Warning);
+        assert!(LogLevel::Debug < LogLevel::Info);
+        assert!(serde_json::from_value::<LogLevel>(serde_json::json!("verbose")).is_err());
+
+        let notification = log_notification(LogLevel::Info, "indexed");
+        assert_eq!(notification["method"], "notifications/message");
+        assert_eq!(notification["params"]["level"], "info");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = canonicalize(dir.path()).unwrap().join("project");
+        let other = canonicalize(dir.path()).unwrap().join("other");
+        std::fs::create_dir_all(&project).unwrap();
+        std::fs::create_dir_all(&other).unwrap();
+        let mut config = Config::default();
+        config.watch.paths = vec![project.clone(), other.clone()];
+        let db = Database::new(dir.path().join("contextd.db").to_str().unwrap()).unwrap();
+        let server = Arc::new(ContextdServer::new(
+            db.clone(),
+            Arc::new(Models::single(crate::indexer::embeddings::Embedder::mock(
+                384,
+            ))),
+            config.clone(),
+            None,
+            Arc::new(AuditLog::open(&config.audit).unwrap()),
+        ));
+        let reply = |uris: &[&str]| {
+            let roots: Vec<Value> = uris
+                .iter()
+                .map(|u| serde_json::json!({ "uri": u }))
+                .collect();
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": ROOTS_REQUEST_ID,
+                "result": { "roots": roots }
+            })
+            .to_string()
+        };
+        let project_uri = workspace::uri_from_path(&project);
+
+        // A reply to a request never sent is ignored
+        server.handle_message(&reply(&["file:///"])).await;
+        assert!(server.workspace_roots.lock().unwrap().is_none());
+
+        let initialize = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": { "capabilities": { "roots": {} }, "clientInfo": { "name": "ide" } }
+        });
+        server.handle_message(&initialize.to_string()).await;
+        let sent = server
+            .handle_message(r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#)
+            .await;
+        assert!(sent.iter().any(|m| m["method"] == "roots/list"));
+        server.handle_message(&reply(&[&project_uri])).await;
+        assert_eq!(
+            *server.workspace_roots.lock().unwrap(),
+            Some(vec![project.clone()])
+        );
+        // Only the one reply is taken
+        server.handle_message(&reply(&["file:///"])).await;
+        assert_eq!(
+            *server.workspace_roots.lock().unwrap(),
+            Some(vec![project.clone()])
+        );
+
+        // Reported roots narrow searches but never become readable roots
+        assert_eq!(server.client_projects(), Some(vec![project.clone()]));
+        assert_eq!(server.project_roots().unwrap(), vec![project, other]);
+        assert!(db.list_workspaces().unwrap().is_empty());
     }
 
-    eprintln!("MCP server stdin closed, exiting.");
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_remember_and_index_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        std::fs::create_dir_all(project.join("docs")).unwrap();
+        std::fs::write(project.join("docs/a.md"), "# Alpha\n\nFirst doc").unwrap();
+        std::fs::write(project.join("b.md"), "# Beta").unwrap();
+        let mut config = Config::default();
+        config.watch.paths = vec![project.clone()];
+        let server = Arc::new(ContextdServer::new(
+            Database::new(dir.path().join("contextd.db").to_str().unwrap()).unwrap(),
+            Arc::new(Models::single(crate::indexer::embeddings::Embedder::mock(
+                384,
+            ))),
+            config.clone(),
+            None,
+            Arc::new(AuditLog::open(&config.audit).unwrap()),
+        ));
+        let call = |name: &'static str, args: Value| {
+            let server = server.clone();
+            tokio::task::spawn_blocking(move || server.call_tool(name, args))
+        };
+
+        call(
+            "remember",
+            serde_json::json!({ "text": "We chose SQLite", "id": "db-choice" }),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let note = server
+            .db
+            .get_file("document://agent/db-choice")
+            .unwrap()
+            .unwrap();
+        let chunks = server.db.get_chunks(note.id).unwrap();
+        let metadata: Value = serde_json::from_str(chunks[0].metadata.as_deref().unwrap()).unwrap();
+        assert_eq!(metadata["source"], "agent");
+        assert!(metadata["timestamp"].is_i64());
+
+        call(
+            "index_path",
+            serde_json::json!({ "path": project.join("docs") }),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let indexed = |name: &str| {
+            let path = project.canonicalize().unwrap().join(name);
+            server
+                .db
+                .get_file(&path.to_string_lossy())
+                .unwrap()
+                .is_some()
+        };
+        assert!(indexed("docs/a.md"));
+        assert!(!indexed("b.md"));
+
+        // Only paths under a watched project can be indexed
+        let outside = call("index_path", serde_json::json!({ "path": dir.path() }))
+            .await
+            .unwrap();
+        assert!(outside.is_err());
+
+        // Malformed filters are rejected, not dropped
+        let err = call(
+            "search_context",
+            serde_json::json!({ "query": "alpha", "tags": "docs" }),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        assert_eq!(err.code, -32602);
+        assert!(err.message.starts_with("Invalid tags:"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_project_name() {
+        assert_eq!(project_name(Path::new("/nonexistent/work/api")), "api");
+        assert_eq!(project_name(Path::new("/")), "/");
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            project_name(Path::new(".")),
+            cwd.file_name().unwrap().to_string_lossy()
+        );
+    }
 }
@@ -0,0 +1,249 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::collections::{self, Models};
+use crate::config::Config;
+use crate::indexer::embeddings::Embedder;
+use crate::staleness::DEFAULT_COLLECTION;
+use crate::storage::db::Database;
+use crate::storage::StorageError;
+
+/// Chunks re-embedded between pauses.
+const BATCH_SIZE: usize = 16;
+
+/// Pause between batches, leaving the CPU to indexing and queries.
+const BATCH_PAUSE: Duration = Duration::from_millis(250);
+
+/// A collection whose chunks were embedded with a model other than the one
+/// it is configured for.
+pub struct Migration {
+    pub collection: String,
+    pub from: String,
+    pub to: String,
+    target: Arc<Embedder>,
+}
+
+/// Re-embedding progress of one collection, as reported by `/stats`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MigrationProgress {
+    pub collection: String,
+    pub from: String,
+    pub to: String,
+    /// Chunks embedded with the new model so far
+    pub done: u64,
+    pub total: u64,
+}
+
+/// Progress of every collection still switching models.
+#[derive(Default)]
+pub struct Migrations {
+    progress: Mutex<Vec<MigrationProgress>>,
+}
+
+impl Migrations {
+    pub fn progress(&self) -> Vec<MigrationProgress> {
+        self.progress.lock().unwrap().clone()
+    }
+
+    fn update(&self, progress: MigrationProgress) {
+        let mut all = self.progress.lock().unwrap();
+        all.retain(|p| p.collection != progress.collection);
+        all.push(progress);
+    }
+
+    fn finish(&self, collection: &str) {
+        self.progress
+            .lock()
+            .unwrap()
+            .retain(|p| p.collection != collection);
+    }
+}
+
+/// Compare the model each collection is configured for with the one its
+/// chunks were embedded with, recording it for collections seen for the
+/// first time. Collections whose model changed keep being embedded and
+/// searched with the old model, so queries still match their vectors, and
+/// are returned for [`spawn`] to migrate.
+pub fn prepare(db: &Database, config: &Config, models: &Models) -> Result<Vec<Migration>> {
+    let mut old_models: HashMap<String, Arc<Embedder>> = HashMap::new();
+    let mut migrations = Vec::new();
+    for name in collections::collection_names(config) {
+        let model = collections::storage_for(config, Some(name)).model_type;
+        let target = models.for_collection(Some(name));
+        let stored = match db.collection_model(name)? {
+            None => {
+                db.set_collection_model(name, &model, target.dimensions())?;
+                continue;
+            }
+            Some((stored, _)) if stored != model => stored,
+            Some(_) => continue,
+        };
+
+        let old = match old_models.get(&stored) {
+            Some(old) => old.clone(),
+            None => match Embedder::new(
+                &collections::storage_with_model(config, &stored),
+                &config.embeddings,
+            ) {
+                Ok(old) => {
+                    let old = Arc::new(old);
+                    old_models.insert(stored.clone(), old.clone());
+                    old
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Collection '{}' was indexed with {} but is configured for {}, and {} can't be loaded to re-embed it in the background ({}); re-index its files so queries compare like with like",
+                        name, stored, model, stored, e
+                    );
+                    continue;
+                }
+            },
+        };
+        models.set_active(name, old);
+        migrations.push(Migration {
+            collection: name.to_string(),
+            from: stored,
+            to: model,
+            target,
+        });
+    }
+    // Archived chunks are re-embedded with the rest
+    if !migrations.is_empty() {
+        let restored = db.restore_archived()?;
+        if restored > 0 {
+            log::info!("Restored {} archived chunks to re-embed them", restored);
+        }
+    }
+    Ok(migrations)
+}
+
+/// Re-embed each migrating collection's chunks with its new model in the
+/// background, a small batch at a time. Queries keep using the old vectors
+/// until all of a collection's chunks have new ones; then they are swapped in
+/// and the collection switches models at once.
+pub fn spawn(
+    migrations: Vec<Migration>,
+    db: Database,
+    config: &Config,
+    models: Arc<Models>,
+) -> Arc<Migrations> {
+    let tracker = Arc::new(Migrations::default());
+    if migrations.is_empty() {
+        return tracker;
+    }
+    // Listed from the start, so nothing takes them for finished
+    for migration in &migrations {
+        tracker.update(MigrationProgress {
+            collection: migration.collection.clone(),
+            from: migration.from.clone(),
+            to: migration.to.clone(),
+            done: 0,
+            total: 0,
+        });
+    }
+    let configured: Vec<String> = config.collections.keys().cloned().collect();
+    let progress = tracker.clone();
+    tokio::spawn(async move {
+        for migration in migrations {
+            log::info!(
+                "Re-embedding collection '{}' from {} to {} in the background",
+                migration.collection,
+                migration.from,
+                migration.to
+            );
+            let collection = migration.collection.clone();
+            let result = migrate(&migration, &db, &configured, &models, &progress).await;
+            progress.finish(&collection);
+            match result {
+                Ok(()) => log::info!("Collection '{}' now uses {}", collection, migration.to),
+                Err(e) => log::error!("Re-embedding collection '{}' failed: {:#}", collection, e),
+            }
+        }
+    });
+    tracker
+}
+
+async fn migrate(
+    migration: &Migration,
+    db: &Database,
+    configured: &[String],
+    models: &Arc<Models>,
+    progress: &Migrations,
+) -> Result<()> {
+    let collection = Some(migration.collection.as_str()).filter(|c| *c != DEFAULT_COLLECTION);
+    loop {
+        let (name, listed) = (collection.map(str::to_string), configured.to_vec());
+        let ((done, total), batch) = db
+            .call(move |db| {
+                Ok::<_, StorageError>((
+                    db.reembedding_progress(name.as_deref(), &listed)?,
+                    db.unstaged_chunks(name.as_deref(), &listed, BATCH_SIZE)?,
+                ))
+            })
+            .await?;
+        progress.update(MigrationProgress {
+            collection: migration.collection.clone(),
+            from: migration.from.clone(),
+            to: migration.to.clone(),
+            done,
+            total,
+        });
+
+        if batch.is_empty() {
+            if switch(migration, db, collection, configured, models).await? {
+                return Ok(());
+            }
+            continue;
+        }
+
+        let target = migration.target.clone();
+        let db = db.clone();
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let contents: Vec<&str> = batch.iter().map(|(_, content)| content.as_str()).collect();
+            for ((chunk_id, _), embedding) in batch.iter().zip(target.embed_batch(&contents)) {
+                db.stage_embedding(*chunk_id, &embedding?)?;
+            }
+            Ok(())
+        })
+        .await??;
+        tokio::time::sleep(BATCH_PAUSE).await;
+    }
+}
+
+/// Swap in the staged embeddings and switch the collection to its new model,
+/// unless chunks indexed meanwhile still have only old vectors.
+async fn switch(
+    migration: &Migration,
+    db: &Database,
+    collection: Option<&str>,
+    configured: &[String],
+    models: &Arc<Models>,
+) -> Result<bool> {
+    let (db, models, target) = (db.clone(), models.clone(), migration.target.clone());
+    let collection = collection.map(str::to_string);
+    let configured = configured.to_vec();
+    let (name, from, to) = (
+        migration.collection.clone(),
+        migration.from.clone(),
+        migration.to.clone(),
+    );
+    tokio::task::spawn_blocking(move || {
+        // No file is half embedded with the old model while switching
+        let _switching = models.lock_for_switch();
+        let switched = db.commit_reembedding(
+            collection.as_deref(),
+            &configured,
+            &from,
+            &to,
+            target.dimensions(),
+        )?;
+        if switched {
+            models.set_active(&name, target);
+        }
+        Ok(switched)
+    })
+    .await?
+}
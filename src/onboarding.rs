@@ -0,0 +1,247 @@
+use anyhow::{bail, Context, Result};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::paths::canonicalize;
+
+/// Models `contextd init` offers, with what choosing each costs. Only models
+/// with the index's 384 dimensions can be used.
+const MODELS: &[(&str, &str)] = &[
+    (
+        "all-minilm-l6-v2",
+        "~80 MB, fastest indexing, good for general text",
+    ),
+    (
+        "bge-small-en-v1.5",
+        "~130 MB, about half as fast, better retrieval quality",
+    ),
+];
+
+/// Answers to the `contextd init` questions.
+#[derive(Debug, PartialEq)]
+pub struct Answers {
+    pub paths: Vec<PathBuf>,
+    pub model_type: String,
+}
+
+/// Ask `question` on stdout and read the answer from `input`, or `default`
+/// when the answer is empty or input has ended.
+fn ask(input: &mut impl BufRead, question: &str, default: &str) -> Result<String> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let answer = line.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_string())
+}
+
+fn confirm(input: &mut impl BufRead, question: &str, default: bool) -> Result<bool> {
+    let answer = ask(input, question, if default { "Y/n" } else { "y/N" })?;
+    Ok(match answer.to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
+
+/// Ask which directories to index and which model to use.
+pub fn ask_answers(input: &mut impl BufRead, cwd: &Path) -> Result<Answers> {
+    let paths = loop {
+        let answer = ask(
+            input,
+            "Directories to index, separated by commas",
+            &cwd.to_string_lossy(),
+        )?;
+        match parse_paths(&answer, cwd) {
+            Ok(paths) => break paths,
+            Err(e) => println!("  {}", e),
+        }
+    };
+
+    println!("\nEmbedding models:");
+    for (i, (name, tradeoff)) in MODELS.iter().enumerate() {
+        println!("  {}. {} ({})", i + 1, name, tradeoff);
+    }
+    let model_type = loop {
+        let answer = ask(input, "Model", "1")?;
+        match pick_model(&answer) {
+            Some(model) => break model.to_string(),
+            None => println!("  Enter 1 to {} or a model name", MODELS.len()),
+        }
+    };
+    Ok(Answers { paths, model_type })
+}
+
+/// Comma-separated directories, relative ones resolved against `cwd` and
+/// `~` against the home directory. Every one must exist.
+fn parse_paths(answer: &str, cwd: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for part in answer.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let path = match part.strip_prefix("~/") {
+            Some(rest) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(rest),
+            None => cwd.join(part),
+        };
+        if !path.is_dir() {
+            bail!("{} is not a directory", path.display());
+        }
+        let path = canonicalize(&path)?;
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        bail!("Enter at least one directory");
+    }
+    Ok(paths)
+}
+
+/// The model for a menu number or a model name.
+fn pick_model(answer: &str) -> Option<&'static str> {
+    if let Ok(n) = answer.parse::<usize>() {
+        return MODELS.get(n.checked_sub(1)?).map(|(name, _)| *name);
+    }
+    MODELS
+        .iter()
+        .map(|(name, _)| *name)
+        .find(|name| name.eq_ignore_ascii_case(answer))
+}
+
+/// The config file for `answers`, keeping the index and models in
+/// `data_dir` so they are found wherever contextd is started from.
+pub fn render_config(answers: &Answers, data_dir: &Path) -> Result<String> {
+    let mut watch = toml::Table::new();
+    watch.insert(
+        "paths".into(),
+        toml::Value::Array(
+            answers
+                .paths
+                .iter()
+                .map(|p| toml::Value::String(p.to_string_lossy().to_string()))
+                .collect(),
+        ),
+    );
+    let mut storage = toml::Table::new();
+    for (key, value) in [
+        ("db_path", data_dir.join("contextd.db")),
+        ("model_path", data_dir.join("models")),
+    ] {
+        storage.insert(
+            key.into(),
+            toml::Value::String(value.to_string_lossy().to_string()),
+        );
+    }
+    storage.insert(
+        "model_type".into(),
+        toml::Value::String(answers.model_type.clone()),
+    );
+    let mut table = toml::Table::new();
+    table.insert("watch".into(), toml::Value::Table(watch));
+    table.insert("storage".into(), toml::Value::Table(storage));
+    Ok(format!(
+        "# Written by `contextd init`. See the configuration docs for every setting.\n\n{}",
+        toml::to_string(&table)?
+    ))
+}
+
+/// Walk through first-time setup: ask what to index and with which model,
+/// write the config to `config_path`, download the model, optionally add
+/// contextd to Claude Desktop, then index the directories once.
+pub async fn handle_init(config_path: &Path) -> Result<()> {
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    println!("Setting up contextd. Press Enter to accept the default in brackets.\n");
+
+    if config_path.exists()
+        && !confirm(
+            &mut input,
+            &format!("{} already exists. Replace it?", config_path.display()),
+            false,
+        )?
+    {
+        println!("Left {} as it is.", config_path.display());
+        return Ok(());
+    }
+
+    let cwd = std::env::current_dir()?;
+    let answers = ask_answers(&mut input, &cwd)?;
+    let data_dir = crate::config::user_data_dir().unwrap_or_else(|| cwd.clone());
+    let text = render_config(&answers, &data_dir)?;
+    if let Some(parent) = config_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::create_dir_all(&data_dir)
+        .with_context(|| format!("Failed to create {}", data_dir.display()))?;
+    std::fs::write(config_path, text)
+        .with_context(|| format!("Failed to write {}", config_path.display()))?;
+    println!("\nWrote {}", config_path.display());
+
+    let config = Config::load(config_path)?;
+    crate::collections::ensure_models(&config).await?;
+    println!("Model {} is ready.", config.storage.model_type);
+
+    if crate::connect::is_detected("claude-desktop") {
+        println!();
+        if confirm(&mut input, "Add contextd to Claude Desktop?", true)? {
+            crate::connect::handle_mcp_install(
+                &config,
+                config_path,
+                &["claude-desktop".to_string()],
+                false,
+            )?;
+        }
+    } else {
+        println!("Claude Desktop not found; run `contextd mcp install` to add contextd to an MCP client later.");
+    }
+
+    println!();
+    if confirm(&mut input, "Index the directories now?", true)? {
+        for path in &config.watch.paths {
+            println!("Indexing {}", path.display());
+            crate::cli::handle_index(&config, path, false).await?;
+        }
+    }
+    println!("\nDone. Run `contextd daemon` to keep the index up to date as files change.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answers() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("notes")).unwrap();
+        let cwd = dir.path().canonicalize().unwrap();
+
+        // A missing directory and an unknown model are asked again
+        let mut input = "missing\nnotes, .\n9\nbge-small-en-v1.5\n".as_bytes();
+        let answers = ask_answers(&mut input, &cwd).unwrap();
+        assert_eq!(
+            answers,
+            Answers {
+                paths: vec![cwd.join("notes"), cwd.clone()],
+                model_type: "bge-small-en-v1.5".to_string(),
+            }
+        );
+        // Empty answers and ended input take the defaults
+        let answers = ask_answers(&mut "\n".as_bytes(), &cwd).unwrap();
+        assert_eq!(answers.paths, vec![cwd.clone()]);
+        assert_eq!(answers.model_type, "all-minilm-l6-v2");
+
+        let text = render_config(&answers, Path::new("/data/contextd")).unwrap();
+        let config: Config = toml::from_str(&text).unwrap();
+        assert_eq!(config.watch.paths, vec![cwd]);
+        assert_eq!(
+            config.storage.db_path,
+            PathBuf::from("/data/contextd/contextd.db")
+        );
+        assert_eq!(config.storage.model_type, "all-minilm-l6-v2");
+    }
+}
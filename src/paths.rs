@@ -0,0 +1,288 @@
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::Config;
+
+/// Longest path the Windows APIs accept without the `\\?\` prefix.
+const MAX_PATH: usize = 260;
+
+/// Device names Windows reserves in every directory.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The directories REST and MCP clients may reach files in: the configured
+/// watch roots. Every API operation on a
+/// client-supplied or indexed path goes through [`WatchRoots::authorize`].
+#[derive(Debug, Clone)]
+pub struct WatchRoots {
+    /// Canonical paths of the roots that exist
+    roots: Vec<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PathError {
+    /// The path resolves outside every root
+    Forbidden(String),
+    /// The path is inside a root but doesn't exist
+    NotFound(String),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Forbidden(msg) | Self::NotFound(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+impl WatchRoots {
+    pub fn new(roots: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self {
+            roots: roots
+                .into_iter()
+                .filter_map(|root| canonicalize(&root).ok())
+                .collect(),
+        }
+    }
+
+    /// `[watch] paths`. Registered workspaces the daemon watches besides
+    /// them and roots reported by clients are left out: a client must never
+    /// be able to widen what it may read.
+    pub fn load(config: &Config) -> Self {
+        Self::new(config.watch.confined_paths())
+    }
+
+    /// Resolve `path`, following symlinks, and check it lies under a root.
+    /// Returns the canonical path. Paths outside every root are refused
+    /// whether or not they exist, so callers learn nothing about them.
+    pub fn authorize(&self, path: &Path) -> Result<PathBuf, PathError> {
+        let forbidden = || {
+            PathError::Forbidden(format!(
+                "{} is not under a watched directory",
+                path.display()
+            ))
+        };
+        let (resolved, exists) = resolve(path).ok_or_else(forbidden)?;
+        if !self.roots.iter().any(|root| resolved.starts_with(root)) {
+            return Err(forbidden());
+        }
+        if !exists {
+            return Err(PathError::NotFound(format!(
+                "{} does not exist",
+                path.display()
+            )));
+        }
+        Ok(resolved)
+    }
+}
+
+/// Whether the indexed `path` names a virtual document, a saved conversation
+/// or an ingested document, rather than a file on disk.
+pub fn is_virtual_path(path: &str) -> bool {
+    path.starts_with(crate::transcripts::PATH_PREFIX)
+        || path.starts_with(crate::documents::PATH_PREFIX)
+}
+
+/// [`Path::canonicalize`], except that on Windows the `\\?\` prefix is
+/// dropped where the path works without it, so canonical paths compare equal
+/// to the ones users, configs and file watchers give. Use it for every path
+/// that is stored or compared.
+pub fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    let canonical = path.canonicalize()?;
+    if !cfg!(windows) {
+        return Ok(canonical);
+    }
+    Ok(match canonical.to_str() {
+        Some(s) => PathBuf::from(simplify_windows(s)),
+        None => canonical,
+    })
+}
+
+/// A Windows path in the form people write it: `\\?\C:\src` as `C:\src`
+/// and `\\?\UNC\server\share` as `\\server\share`, with the drive letter
+/// in upper case. Paths that only work verbatim keep the prefix: those
+/// longer than [`MAX_PATH`], and those with components Windows would alter
+/// otherwise, like trailing dots or device names.
+fn simplify_windows(path: &str) -> String {
+    let Some(verbatim) = path.strip_prefix(r"\\?\") else {
+        return upper_drive(path);
+    };
+    let plain = if let Some(share) = verbatim.strip_prefix(r"UNC\") {
+        format!(r"\\{}", share)
+    } else if has_drive(verbatim) {
+        verbatim.to_string()
+    } else {
+        // Volume GUID paths and the like have no plain form
+        return path.to_string();
+    };
+    if plain.len() >= MAX_PATH || plain.split('\\').any(needs_verbatim) {
+        return path.to_string();
+    }
+    upper_drive(&plain)
+}
+
+fn has_drive(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+fn upper_drive(path: &str) -> String {
+    if has_drive(path) {
+        path[..1].to_ascii_uppercase() + &path[1..]
+    } else {
+        path.to_string()
+    }
+}
+
+/// Whether Windows would change `component` of a path without the `\\?\`
+/// prefix: it strips trailing dots and spaces, and maps reserved names to
+/// devices, with or without an extension.
+fn needs_verbatim(component: &str) -> bool {
+    if component.ends_with(['.', ' ']) {
+        return true;
+    }
+    let stem = component.split('.').next().unwrap_or("");
+    RESERVED_NAMES
+        .iter()
+        .any(|name| stem.trim_end().eq_ignore_ascii_case(name))
+}
+
+/// Canonicalize `path`, or for a path that doesn't exist, its deepest
+/// existing ancestor with the rest appended. `None` if the missing part
+/// climbs with `..`, which can't be resolved without the directories.
+fn resolve(path: &Path) -> Option<(PathBuf, bool)> {
+    if let Ok(canonical) = canonicalize(path) {
+        return Some((canonical, true));
+    }
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().ok()?.join(path)
+    };
+    let mut missing = Vec::new();
+    let mut ancestor = absolute.as_path();
+    let canonical = loop {
+        if let Ok(canonical) = canonicalize(ancestor) {
+            break canonical;
+        }
+        missing.push(ancestor.file_name()?);
+        ancestor = ancestor.parent()?;
+    };
+    let rest: PathBuf = missing.into_iter().rev().collect();
+    if rest
+        .components()
+        .any(|c| matches!(c, Component::ParentDir | Component::CurDir))
+    {
+        return None;
+    }
+    Some((canonical.join(rest), false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_virtual_path() {
+        assert!(is_virtual_path("conversation://c1"));
+        assert!(is_virtual_path("document://agent/db-choice"));
+        assert!(!is_virtual_path("/src/main.rs"));
+        assert!(!is_virtual_path("./docs/file://notes.md"));
+        assert!(!is_virtual_path(r"C:\src\main.rs"));
+    }
+
+    #[test]
+    fn test_authorize() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("src")).unwrap();
+        std::fs::write(root.path().join("src/lib.rs"), "").unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "").unwrap();
+        let roots = WatchRoots::new([root.path().to_path_buf()]);
+
+        assert!(roots.authorize(&root.path().join("src/lib.rs")).is_ok());
+        assert!(roots
+            .authorize(&root.path().join("src/../src/lib.rs"))
+            .is_ok());
+        assert!(matches!(
+            roots.authorize(&root.path().join("src/missing.rs")),
+            Err(PathError::NotFound(_))
+        ));
+
+        let forbidden =
+            |path: PathBuf| matches!(roots.authorize(&path), Err(PathError::Forbidden(_)));
+        assert!(forbidden(outside.path().join("secret.txt")));
+        // Missing files outside look the same as existing ones
+        assert!(forbidden(outside.path().join("missing.txt")));
+        let escape = root
+            .path()
+            .join("..")
+            .join(outside.path().file_name().unwrap())
+            .join("secret.txt");
+        assert!(forbidden(escape));
+        assert!(forbidden(root.path().join("missing/../../etc/passwd")));
+        // A sibling sharing the root's name as a prefix isn't inside it
+        let sibling = PathBuf::from(format!("{}2", root.path().display()));
+        assert!(forbidden(sibling.join("x.rs")));
+        assert!(WatchRoots::new([])
+            .authorize(&root.path().join("src/lib.rs"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_simplify_windows() {
+        assert_eq!(simplify_windows(r"\\?\C:\src\lib.rs"), r"C:\src\lib.rs");
+        assert_eq!(simplify_windows(r"\\?\d:\src"), r"D:\src");
+        assert_eq!(simplify_windows(r"c:\src"), r"C:\src");
+        assert_eq!(
+            simplify_windows(r"\\?\UNC\server\share\repo"),
+            r"\\server\share\repo"
+        );
+        // Only valid verbatim
+        for path in [
+            r"\\?\C:\src\trailing.",
+            r"\\?\C:\src\nul.txt",
+            r"\\?\Volume{0b1c}\src",
+        ] {
+            assert_eq!(simplify_windows(path), path);
+        }
+        let long = format!(r"\\?\C:\{}", "a\\".repeat(MAX_PATH / 2));
+        assert_eq!(simplify_windows(&long), long);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "").unwrap();
+        std::fs::write(root.path().join("lib.rs"), "").unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("escape")).unwrap();
+        std::os::unix::fs::symlink(root.path().join("lib.rs"), root.path().join("alias.rs"))
+            .unwrap();
+        let roots = WatchRoots::new([root.path().to_path_buf()]);
+
+        assert!(matches!(
+            roots.authorize(&root.path().join("escape/secret.txt")),
+            Err(PathError::Forbidden(_))
+        ));
+        assert!(matches!(
+            roots.authorize(&root.path().join("escape/missing.txt")),
+            Err(PathError::Forbidden(_))
+        ));
+        assert_eq!(
+            roots.authorize(&root.path().join("alias.rs")).unwrap(),
+            root.path().join("lib.rs").canonicalize().unwrap()
+        );
+
+        // A root reached through a symlink covers the files it points at
+        let link = outside.path().join("root-link");
+        std::os::unix::fs::symlink(root.path(), &link).unwrap();
+        let roots = WatchRoots::new([link]);
+        assert!(roots.authorize(&root.path().join("lib.rs")).is_ok());
+    }
+}
@@ -0,0 +1,162 @@
+// Indexing on battery power. A laptop unplugged mid-scan shouldn't spend its
+// charge embedding a backlog, so the daemon watches the power source and
+// holds the worker pool back until it's plugged in again.
+
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::indexer::workers::WorkerPool;
+
+/// How often the daemon checks the power source.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `[indexing] on_battery`: what indexing does while the machine runs on
+/// battery.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnBattery {
+    /// Hold the pool at `min_workers`
+    #[default]
+    Throttle,
+    /// Hold the pool at `min_workers` and leave the initial scan until the
+    /// machine is plugged in; changed files are still indexed
+    Pause,
+    /// Index as on mains power
+    Ignore,
+}
+
+/// Check the power source every [`CHECK_INTERVAL`] and throttle or pause
+/// `workers` while on battery.
+pub fn spawn_monitor(workers: Arc<WorkerPool>, on_battery: OnBattery) {
+    if on_battery == OnBattery::Ignore {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        let mut was_on_battery = false;
+        loop {
+            interval.tick().await;
+            let Ok(Some(battery)) = tokio::task::spawn_blocking(self::on_battery).await else {
+                log::debug!("The power source is not reported on this machine");
+                return;
+            };
+            if battery == was_on_battery {
+                continue;
+            }
+            was_on_battery = battery;
+            match (battery, on_battery) {
+                (true, OnBattery::Pause) => {
+                    log::info!("On battery power; pausing the initial scan")
+                }
+                (true, _) => log::info!("On battery power; indexing with fewer workers"),
+                (false, _) => log::info!("On mains power; indexing at full speed"),
+            }
+            workers.set_battery(battery);
+            workers.pause_backfill(battery && on_battery == OnBattery::Pause);
+        }
+    });
+}
+
+/// Whether the machine runs on battery, or `None` where that isn't known.
+#[cfg(target_os = "linux")]
+fn on_battery() -> Option<bool> {
+    sysfs_on_battery(Path::new("/sys/class/power_supply"))
+}
+
+#[cfg(target_os = "macos")]
+fn on_battery() -> Option<bool> {
+    // "Now drawing from 'Battery Power'" or "... 'AC Power'"
+    let output = std::process::Command::new("pmset")
+        .args(["-g", "ps"])
+        .output()
+        .ok()?;
+    let source = String::from_utf8_lossy(&output.stdout);
+    Some(source.lines().next()?.contains("'Battery Power'"))
+}
+
+#[cfg(windows)]
+fn on_battery() -> Option<bool> {
+    use windows_sys::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+    // SAFETY: `status` is a valid out-pointer
+    let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+    if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+        return None;
+    }
+    match status.ACLineStatus {
+        0 => Some(true),
+        1 => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn on_battery() -> Option<bool> {
+    None
+}
+
+/// Whether the supplies under a `/sys/class/power_supply` directory run the
+/// machine on battery: one of its batteries discharges and no charger is
+/// online. Batteries of devices like wireless mice don't count.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn sysfs_on_battery(dir: &Path) -> Option<bool> {
+    let read = |supply: &Path, name: &str| {
+        std::fs::read_to_string(supply.join(name))
+            .map(|value| value.trim().to_string())
+            .unwrap_or_default()
+    };
+    let mut discharging = false;
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let supply = entry.path();
+        match read(&supply, "type").as_str() {
+            "Mains" | "USB" if read(&supply, "online") == "1" => return Some(false),
+            "Battery" if read(&supply, "scope") != "Device" => {
+                discharging |= read(&supply, "status") == "Discharging";
+            }
+            _ => {}
+        }
+    }
+    Some(discharging)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sysfs_on_battery() {
+        let dir = tempfile::tempdir().unwrap();
+        let supply = |name: &str, files: &[(&str, &str)]| {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            for (file, value) in files {
+                std::fs::write(path.join(file), format!("{}\n", value)).unwrap();
+            }
+        };
+        // A desktop without a battery
+        assert_eq!(sysfs_on_battery(dir.path()), Some(false));
+
+        supply("BAT0", &[("type", "Battery"), ("status", "Discharging")]);
+        supply("AC", &[("type", "Mains"), ("online", "0")]);
+        assert_eq!(sysfs_on_battery(dir.path()), Some(true));
+
+        supply("AC", &[("type", "Mains"), ("online", "1")]);
+        assert_eq!(sysfs_on_battery(dir.path()), Some(false));
+
+        // Only a wireless mouse discharging
+        supply("AC", &[("type", "Mains"), ("online", "0")]);
+        supply("BAT0", &[("type", "Battery"), ("status", "Full")]);
+        supply(
+            "hidpp_battery_0",
+            &[
+                ("type", "Battery"),
+                ("scope", "Device"),
+                ("status", "Discharging"),
+            ],
+        );
+        assert_eq!(sysfs_on_battery(dir.path()), Some(false));
+
+        assert_eq!(sysfs_on_battery(&dir.path().join("missing")), None);
+    }
+}
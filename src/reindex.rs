@@ -0,0 +1,255 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::collections::{self, Models};
+use crate::config::Config;
+use crate::daemon;
+use crate::indexer::{chunker, sniff};
+use crate::paths::is_virtual_path;
+use crate::storage::db::Database;
+
+/// The settings `path` is indexed with under `config`, as a JSON object of
+/// setting names to values.
+pub fn fingerprint(config: &Config, path: &Path) -> String {
+    serde_json::to_string(&settings(config, path)).unwrap_or_default()
+}
+
+/// Language of the chunker that splits `path`: the one for its plugin's
+/// output format if a plugin converts it.
+pub fn language(config: &Config, path: &Path) -> &'static str {
+    language_of(config, &sniff::extension(config, path))
+}
+
+/// Language of the chunker that splits files handled as extension `ext`.
+pub fn language_of(config: &Config, ext: &str) -> &'static str {
+    match config.plugins.get(ext) {
+        Some(plugin) => chunker::chunker_language(plugin.output_format(ext)),
+        None if ext == "pdf" => "pdf",
+        None if ext == "docx" => "docx",
+        None => chunker::chunker_language(ext),
+    }
+}
+
+/// Whether `path` was last indexed with the embedding model it would be
+/// embedded with now, so its old embeddings can be reused.
+pub fn model_unchanged(db: &Database, config: &Config, path: &Path) -> bool {
+    let Ok(Some(recorded)) = db.index_settings_for(&path.to_string_lossy()) else {
+        return false;
+    };
+    let recorded: BTreeMap<String, String> = serde_json::from_str(&recorded).unwrap_or_default();
+    recorded.get("model") == settings(config, path).get("model")
+}
+
+fn settings(config: &Config, path: &Path) -> BTreeMap<&'static str, String> {
+    let ext = sniff::extension(config, path);
+    let collection = collections::collection_for(config, path);
+    let plugin = config.plugins.get(&ext);
+    let language = language_of(config, &ext);
+
+    let mut settings = BTreeMap::new();
+    settings.insert(
+        "model",
+        collections::storage_for(config, collection).model_type,
+    );
+    settings.insert(
+        "chunker",
+        format!(
+            "{} v{}",
+            language,
+            chunker::chunker_version(language).unwrap_or(0)
+        ),
+    );
+    settings.insert(
+        "max_chunk_chars",
+        collections::max_chunk_chars(config, collection)
+            .map_or_else(|| "none".to_string(), |n| n.to_string()),
+    );
+    settings.insert(
+        "max_chunk_size",
+        format!(
+            "{} tokens, {} overlap",
+            config.chunking.max_chunk_size, config.chunking.overlap
+        ),
+    );
+    settings.insert(
+        "plugin",
+        plugin.map_or_else(
+            || "none".to_string(),
+            |p| {
+                p.steps()
+                    .map(|step| step.join(" "))
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            },
+        ),
+    );
+    settings.insert(
+        "collection",
+        collection
+            .unwrap_or(crate::staleness::DEFAULT_COLLECTION)
+            .to_string(),
+    );
+    settings
+}
+
+/// An indexed file whose settings changed since it was indexed.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StaleFile {
+    pub path: String,
+    /// What changed, e.g. `model: all-minilm-l6-v2 -> bge-small-en-v1.5`
+    pub reasons: Vec<String>,
+}
+
+/// Indexed files that would be chunked differently today. Model changes are
+/// left to the daemon's background re-embedding ([`crate::migration`]), and
+/// virtual documents and files gone from disk are left out.
+pub fn plan(db: &Database, config: &Config) -> Result<Vec<StaleFile>> {
+    let recorded = db.index_settings()?;
+    let mut stale = Vec::new();
+    for file in db.list_files()? {
+        let path = Path::new(&file.path);
+        if is_virtual_path(&file.path) || !path.is_file() {
+            continue;
+        }
+        let reasons = match recorded.get(&file.path) {
+            Some(recorded) => changes(recorded, &settings(config, path)),
+            None => vec!["indexed before settings were recorded".to_string()],
+        };
+        if !reasons.is_empty() {
+            stale.push(StaleFile {
+                path: file.path,
+                reasons,
+            });
+        }
+    }
+    Ok(stale)
+}
+
+/// Indexed files split by `language`'s chunker with an older version of it
+/// than the current one.
+pub fn outdated_chunks(db: &Database, config: &Config, language: &str) -> Result<Vec<String>> {
+    let current = chunker::chunker_version(language)
+        .with_context(|| format!("Unknown chunker language '{}'", language))?;
+    let mut outdated = Vec::new();
+    for file in db.list_files()? {
+        let path = Path::new(&file.path);
+        if is_virtual_path(&file.path)
+            || !path.is_file()
+            || self::language(config, path) != language
+        {
+            continue;
+        }
+        let chunked_with_older = db.get_chunks(file.id)?.iter().any(|chunk| {
+            chunk
+                .metadata
+                .as_deref()
+                .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+                .and_then(|m| m["chunker_version"].as_u64())
+                .unwrap_or(0)
+                != current as u64
+        });
+        if chunked_with_older {
+            outdated.push(file.path);
+        }
+    }
+    Ok(outdated)
+}
+
+/// One line per setting other than the model that differs between a recorded
+/// fingerprint and the current settings.
+fn changes(recorded: &str, current: &BTreeMap<&'static str, String>) -> Vec<String> {
+    let recorded: BTreeMap<String, String> = serde_json::from_str(recorded).unwrap_or_default();
+    current
+        .iter()
+        .filter(|(key, _)| **key != "model")
+        .filter_map(|(key, now)| {
+            let before = recorded.get(*key).map_or("unknown", String::as_str);
+            (before != now).then(|| format!("{}: {} -> {}", key, before, now))
+        })
+        .collect()
+}
+
+/// Re-index `paths` even if unchanged, one at a time, calling `progress`
+/// after each. Chunks whose content didn't change keep their embeddings.
+pub async fn apply(
+    paths: Vec<String>,
+    config: Arc<Config>,
+    db: Database,
+    models: Arc<Models>,
+    mut progress: impl FnMut(&str),
+) -> Result<()> {
+    for path in paths {
+        db.mark_unindexed(&path)?;
+        daemon::index_file(
+            path.clone().into(),
+            config.clone(),
+            db.clone(),
+            models.clone(),
+        )
+        .await;
+        progress(&path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.md");
+        std::fs::write(&file, "# Notes").unwrap();
+        let path = file.to_string_lossy().to_string();
+
+        let db = Database::new(":memory:").unwrap();
+        db.add_or_update_file(&path, 0).unwrap();
+        let mut config = Config::default();
+        let plan_reasons = |config: &Config| -> Vec<Vec<String>> {
+            plan(&db, config)
+                .unwrap()
+                .into_iter()
+                .map(|f| f.reasons)
+                .collect()
+        };
+        assert_eq!(
+            plan_reasons(&config),
+            vec![vec!["indexed before settings were recorded".to_string()]]
+        );
+
+        db.record_index_settings(&path, &fingerprint(&config, &file))
+            .unwrap();
+        assert!(plan_reasons(&config).is_empty());
+
+        config.indexing.max_chunk_chars = Some(1000);
+        assert_eq!(
+            plan_reasons(&config),
+            vec![vec!["max_chunk_chars: none -> 1000".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_outdated_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(":memory:").unwrap();
+        let config = Config::default();
+        let current = chunker::chunker_version("rust").unwrap();
+        for (name, version) in [("old.rs", 0), ("new.rs", current), ("notes.md", 0)] {
+            let file = dir.path().join(name);
+            std::fs::write(&file, "fn main() {}").unwrap();
+            let id = db.add_or_update_file(&file.to_string_lossy(), 0).unwrap();
+            let metadata = serde_json::json!({ "chunker_version": version }).to_string();
+            db.add_chunk(id, 0, 12, "fn main() {}", None, Some(&metadata))
+                .unwrap();
+        }
+
+        let outdated = outdated_chunks(&db, &config, "rust").unwrap();
+        assert_eq!(outdated.len(), 1);
+        assert!(outdated[0].ends_with("old.rs"));
+        assert!(outdated_chunks(&db, &config, "cobol").is_err());
+    }
+}
@@ -0,0 +1,354 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::paths::{canonicalize, is_virtual_path};
+use crate::storage::db::{Database, IndexSnapshot};
+
+/// Areas listed under "most-queried areas".
+const TOP_AREAS: usize = 10;
+
+/// How often the daemon records the index size for the growth history.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Count a query answered in `elapsed` whose results came from `paths`.
+/// Usage tracking never fails the query; errors are only logged.
+pub fn record_query<'a>(
+    db: &Database,
+    source: &str,
+    elapsed: Duration,
+    paths: impl IntoIterator<Item = &'a str>,
+) {
+    let paths: Vec<&str> = paths.into_iter().collect();
+    let mut areas: Vec<String> = Vec::new();
+    for path in &paths {
+        let area = area(path);
+        if !areas.contains(&area) {
+            areas.push(area);
+        }
+    }
+    if let Err(e) = db.record_query_stats(source, elapsed.as_micros() as u64, false, &areas) {
+        log::warn!("Failed to record query usage: {}", e);
+    }
+    // For collections evicting the least recently used files
+    if let Err(e) = db.mark_returned(&paths) {
+        log::warn!("Failed to record query usage: {}", e);
+    }
+}
+
+/// Count a query that failed after `elapsed`.
+pub fn record_failed_query(db: &Database, source: &str, elapsed: Duration) {
+    if let Err(e) = db.record_query_stats(source, elapsed.as_micros() as u64, true, &[]) {
+        log::warn!("Failed to record query usage: {}", e);
+    }
+}
+
+/// The directory a result lies in, or the scheme of a virtual document.
+pub(crate) fn area(path: &str) -> String {
+    if let Some((scheme, _)) = path.split_once("://").filter(|_| is_virtual_path(path)) {
+        return format!("{}://", scheme);
+    }
+    Path::new(path)
+        .parent()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Record the index size now and then every hour, building the history
+/// `contextd report` shows growth from.
+pub fn spawn_snapshots(db: Database) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = db.call(|db| db.record_index_snapshot()).await {
+                log::warn!("Failed to record index size: {}", e);
+            }
+        }
+    });
+}
+
+/// Usage summary over the last `days` days.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Report {
+    pub days: u32,
+    pub growth: Vec<IndexSnapshot>,
+    pub queries: QuerySummary,
+    /// Per interface (`api`, `mcp`, `lsp`, `cli`)
+    pub by_source: BTreeMap<String, QuerySummary>,
+    /// Per API user, see `[server.users]`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub by_user: BTreeMap<String, QuerySummary>,
+    /// Directories results came from most often, relative to their watch
+    /// root, with hit counts. Counted since the database was created.
+    pub top_areas: Vec<(String, u64)>,
+    pub indexing: IndexingSummary,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct QuerySummary {
+    pub queries: u64,
+    pub failed: u64,
+    pub average_latency_ms: f64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct IndexingSummary {
+    pub files: u64,
+    pub chunks: u64,
+    /// Mean time to index a file
+    pub average_ms_per_file: f64,
+    /// Files whose last indexing attempt failed
+    pub failing_files: u64,
+}
+
+/// Build the report from the local analytics tables.
+pub fn build(db: &Database, config: &Config, days: u32) -> Result<Report> {
+    let mut queries = Totals::default();
+    let mut by_source: BTreeMap<String, Totals> = BTreeMap::new();
+    for day in db.query_stats(days)? {
+        for totals in [&mut queries, by_source.entry(day.source).or_default()] {
+            totals.queries += day.queries;
+            totals.failed += day.failed;
+            totals.us += day.total_us;
+        }
+    }
+    let stats = db.get_stats()?;
+    let timings = db.index_timing_stats(0)?;
+    let roots = roots(db, config)?;
+
+    Ok(Report {
+        days,
+        growth: db.index_history(days)?,
+        queries: queries.summary(),
+        by_source: by_source
+            .into_iter()
+            .map(|(source, totals)| (source, totals.summary()))
+            .collect(),
+        by_user: user_summaries(db, days)?,
+        top_areas: db
+            .top_query_areas(TOP_AREAS)?
+            .into_iter()
+            .map(|(area, hits)| (relative_area(&area, &roots), hits))
+            .collect(),
+        indexing: IndexingSummary {
+            files: stats.file_count,
+            chunks: stats.chunk_count,
+            average_ms_per_file: timings.average.total_us() as f64 / 1000.0,
+            failing_files: db.index_error_count()?,
+        },
+    })
+}
+
+/// Queries of each API user over the last `days` days.
+pub fn user_summaries(db: &Database, days: u32) -> Result<BTreeMap<String, QuerySummary>> {
+    let mut by_user: BTreeMap<String, Totals> = BTreeMap::new();
+    for day in db.user_query_stats(days)? {
+        let totals = by_user.entry(day.source).or_default();
+        totals.queries += day.queries;
+        totals.failed += day.failed;
+        totals.us += day.total_us;
+    }
+    Ok(by_user
+        .into_iter()
+        .map(|(user, totals)| (user, totals.summary()))
+        .collect())
+}
+
+#[derive(Default)]
+struct Totals {
+    queries: u64,
+    failed: u64,
+    us: u64,
+}
+
+impl Totals {
+    fn summary(&self) -> QuerySummary {
+        QuerySummary {
+            queries: self.queries,
+            failed: self.failed,
+            average_latency_ms: if self.queries == 0 {
+                0.0
+            } else {
+                self.us as f64 / self.queries as f64 / 1000.0
+            },
+        }
+    }
+}
+
+/// Watched directories and registered workspaces, which paths are shown
+/// relative to.
+pub(crate) fn roots(db: &Database, config: &Config) -> Result<Vec<PathBuf>> {
+    let mut roots = config.watch.paths.clone();
+    roots.extend(db.list_workspaces()?);
+    Ok(roots)
+}
+
+/// `area` as `<root name>/<path within root>`, so the report doesn't reveal
+/// where projects live on this machine.
+pub(crate) fn relative_area(area: &str, roots: &[PathBuf]) -> String {
+    let path = Path::new(area);
+    let Some(root) = roots
+        .iter()
+        .filter(|root| path.starts_with(root))
+        .max_by_key(|root| root.components().count())
+    else {
+        return area.to_string();
+    };
+    let name = canonicalize(root)
+        .unwrap_or_else(|_| root.clone())
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let rest = path.strip_prefix(root).unwrap_or(path);
+    Path::new(&name).join(rest).to_string_lossy().to_string()
+}
+
+impl Report {
+    pub fn to_markdown(&self) -> String {
+        let mut md = format!("# contextd usage, last {} days\n\n", self.days);
+
+        md.push_str("## Index\n\n");
+        md.push_str(&format!(
+            "{} files, {} chunks. Indexing takes {:.1} ms per file on average; {} files currently fail to index.\n\n",
+            self.indexing.files,
+            self.indexing.chunks,
+            self.indexing.average_ms_per_file,
+            self.indexing.failing_files
+        ));
+        if self.growth.is_empty() {
+            md.push_str("No index size history yet; the daemon records it hourly.\n\n");
+        } else {
+            md.push_str(
+                "| Day | Files | Chunks | Database |\n|-----|------:|-------:|---------:|\n",
+            );
+            for day in &self.growth {
+                md.push_str(&format!(
+                    "| {} | {} | {} | {:.1} MB |\n",
+                    day.day,
+                    day.files,
+                    day.chunks,
+                    day.db_size as f64 / 1024.0 / 1024.0
+                ));
+            }
+            md.push('\n');
+        }
+
+        md.push_str("## Queries\n\n");
+        md.push_str("| Interface | Queries | Failed | Average latency |\n|-----------|--------:|-------:|----------------:|\n");
+        for (source, summary) in self
+            .by_source
+            .iter()
+            .map(|(s, q)| (s.as_str(), q))
+            .chain([("**total**", &self.queries)])
+        {
+            md.push_str(&format!(
+                "| {} | {} | {} | {:.1} ms |\n",
+                source, summary.queries, summary.failed, summary.average_latency_ms
+            ));
+        }
+        md.push('\n');
+
+        if !self.by_user.is_empty() {
+            md.push_str("## Users\n\n");
+            md.push_str("| User | Queries | Failed | Average latency |\n|------|--------:|-------:|----------------:|\n");
+            for (user, summary) in &self.by_user {
+                md.push_str(&format!(
+                    "| {} | {} | {} | {:.1} ms |\n",
+                    user, summary.queries, summary.failed, summary.average_latency_ms
+                ));
+            }
+            md.push('\n');
+        }
+
+        md.push_str("## Most-Queried Areas\n\n");
+        if self.top_areas.is_empty() {
+            md.push_str("No queries recorded yet.\n");
+        }
+        for (area, hits) in &self.top_areas {
+            md.push_str(&format!("- `{}`: {} queries\n", area, hits));
+        }
+        md
+    }
+}
+
+pub fn handle_report(config: &Config, days: u32, json: bool, output: Option<&Path>) -> Result<()> {
+    let db = Database::new(&config.storage.db_path)?;
+    let report = build(&db, config, days)?;
+    let text = if json {
+        serde_json::to_string_pretty(&report)? + "\n"
+    } else {
+        report.to_markdown()
+    };
+    match output {
+        Some(path) => {
+            std::fs::write(path, text).with_context(|| format!("Failed to write {:?}", path))?;
+            println!("Wrote usage report to {}", path.display());
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let db = Database::new(":memory:").unwrap();
+        let mut config = Config::default();
+        config.watch.paths = vec![PathBuf::from("/home/me/src/app")];
+        let ms = Duration::from_millis;
+        record_query(
+            &db,
+            "mcp",
+            ms(10),
+            [
+                "/home/me/src/app/auth/token.rs",
+                "/home/me/src/app/auth/session.rs",
+            ],
+        );
+        record_query(&db, "mcp", ms(30), ["/home/me/src/app/auth/token.rs"]);
+        record_query(&db, "api", ms(5), ["conversation://abc", "/etc/hosts"]);
+        record_failed_query(&db, "api", ms(1));
+        db.record_user_query("ana", 4000, false).unwrap();
+        db.record_user_query("ana", 2000, true).unwrap();
+        db.record_index_snapshot().unwrap();
+
+        let report = build(&db, &config, 7).unwrap();
+        assert_eq!(
+            report.queries,
+            QuerySummary {
+                queries: 4,
+                failed: 1,
+                average_latency_ms: 11.5,
+            }
+        );
+        assert_eq!(report.by_source["mcp"].average_latency_ms, 20.0);
+        // Each query counts once per area
+        assert_eq!(report.top_areas[0], ("app/auth".to_string(), 2));
+        assert!(report
+            .top_areas
+            .contains(&("conversation://".to_string(), 1)));
+        assert!(report.top_areas.contains(&("/etc".to_string(), 1)));
+        assert_eq!(report.growth.len(), 1);
+        assert_eq!(
+            report.by_user["ana"],
+            QuerySummary {
+                queries: 2,
+                failed: 1,
+                average_latency_ms: 3.0,
+            }
+        );
+        assert_eq!(db.user_queries_today("ana").unwrap(), 2);
+
+        let md = report.to_markdown();
+        assert!(md.contains("| mcp | 2 | 0 | 20.0 ms |"));
+        assert!(md.contains("- `app/auth`: 2 queries"));
+        assert!(md.contains("| ana | 2 | 1 | 3.0 ms |"));
+    }
+}
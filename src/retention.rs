@@ -0,0 +1,200 @@
+use anyhow::Result;
+use std::time::Duration;
+
+use crate::collections::{CollectionConfig, EvictionOrder};
+use crate::config::Config;
+use crate::storage::db::Database;
+
+/// How often the daemon checks collections against their limits.
+const ENFORCE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// A file [`enforce`] removed from the index.
+#[derive(Debug, PartialEq)]
+pub struct Evicted {
+    pub collection: String,
+    pub path: String,
+    pub chunks: u64,
+    pub reason: String,
+}
+
+/// Evict files from collections over their `max_age_days`, `max_chunks` or
+/// `max_db_mb`. Files past their age go first, then files in `eviction`
+/// order until the collection fits. Evicted files stay out of the index
+/// until they change. `now` is in seconds since the epoch.
+pub fn enforce(db: &Database, config: &Config, now: u64) -> Result<Vec<Evicted>> {
+    let mut evicted = Vec::new();
+    let mut names: Vec<&String> = config.collections.keys().collect();
+    names.sort();
+    for name in names {
+        let collection = &config.collections[name];
+        if !has_limits(collection) {
+            continue;
+        }
+        let mut files = db.collection_files(name)?;
+        match collection.eviction {
+            EvictionOrder::Oldest => files.sort_by_key(|f| (f.last_modified, f.last_used)),
+            EvictionOrder::Lru => files.sort_by_key(|f| (f.last_used, f.last_modified)),
+        }
+        let mut chunks: u64 = files.iter().map(|f| f.chunks).sum();
+        let mut bytes: u64 = files.iter().map(|f| f.bytes).sum();
+        let max_bytes = collection.max_db_mb.map(|mb| mb * 1024 * 1024);
+        for file in files {
+            let reason = match collection.max_age_days {
+                Some(days) if file.last_modified + days * 86400 < now => {
+                    format!("older than max_age_days = {}", days)
+                }
+                _ => match collection.max_chunks {
+                    Some(max) if chunks > max => format!("over max_chunks = {}", max),
+                    _ if max_bytes.is_some_and(|max| bytes > max) => format!(
+                        "over max_db_mb = {}",
+                        collection.max_db_mb.unwrap_or_default()
+                    ),
+                    // Files past their age may still be further on
+                    _ if collection.max_age_days.is_some() => continue,
+                    _ => break,
+                },
+            };
+            if db.evict_file(&file.path, name, &reason)? {
+                chunks -= file.chunks;
+                bytes -= file.bytes;
+                evicted.push(Evicted {
+                    collection: name.clone(),
+                    path: file.path,
+                    chunks: file.chunks,
+                    reason,
+                });
+            }
+        }
+    }
+    Ok(evicted)
+}
+
+fn has_limits(collection: &CollectionConfig) -> bool {
+    collection.max_chunks.is_some()
+        || collection.max_db_mb.is_some()
+        || collection.max_age_days.is_some()
+}
+
+/// Log what [`enforce`] evicted: a line per collection and limit, and each
+/// file at debug level.
+fn log_evictions(evicted: &[Evicted]) {
+    let mut groups: Vec<(&str, &str)> = evicted
+        .iter()
+        .map(|e| (e.collection.as_str(), e.reason.as_str()))
+        .collect();
+    groups.dedup();
+    for (collection, reason) in groups {
+        let files: Vec<&Evicted> = evicted
+            .iter()
+            .filter(|e| e.collection == collection && e.reason == reason)
+            .collect();
+        let chunks: u64 = files.iter().map(|e| e.chunks).sum();
+        log::info!(
+            "Evicted {} files ({} chunks) from collection {}: {}",
+            files.len(),
+            chunks,
+            collection,
+            reason
+        );
+        for e in files {
+            log::debug!("Evicted {} from {}: {}", e.path, e.collection, e.reason);
+        }
+    }
+}
+
+/// Enforce the collections' limits now and then periodically while the
+/// daemon runs, if any collection has limits.
+pub fn spawn_enforcement(db: Database, config: Config) {
+    if !config.collections.values().any(has_limits) {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(ENFORCE_INTERVAL);
+        loop {
+            ticks.tick().await;
+            let config = config.clone();
+            let now = chrono::Utc::now().timestamp() as u64;
+            match db.call(move |db| enforce(db, &config, now)).await {
+                Ok(evicted) => log_evictions(&evicted),
+                Err(e) => log::warn!("Failed to enforce collection limits: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enforce() {
+        let db = Database::new(":memory:").unwrap();
+        let config = Config {
+            collections: toml::from_str(
+                r#"
+                [clipboard]
+                max_chunks = 3
+                max_age_days = 30
+
+                [shell]
+                max_chunks = 1
+                eviction = "lru"
+                "#,
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        let day = 86400;
+        let now = 100 * day;
+        let add = |path: &str, collection: &str, modified: u64, chunks: u64| {
+            let file_id = db.add_or_update_file(path, modified).unwrap();
+            let metadata = format!(r#"{{"collection": "{}"}}"#, collection);
+            for i in 0..chunks {
+                db.add_chunk(file_id, i, i + 1, "text", None, Some(&metadata))
+                    .unwrap();
+            }
+            db.mark_indexed(file_id).unwrap();
+        };
+        add("document://clip/stale", "clipboard", now - 40 * day, 1);
+        add("document://clip/a", "clipboard", now - 3 * day, 2);
+        add("document://clip/b", "clipboard", now - 2 * day, 1);
+        add("document://clip/c", "clipboard", now - day, 1);
+        add("document://shell/1", "shell", now - 2 * day, 1);
+        add("document://shell/2", "shell", now - day, 1);
+        add("document://other", "notes", 0, 1);
+        // The older shell entry was queried since
+        db.mark_returned(&["document://shell/1"]).unwrap();
+
+        let evicted = enforce(&db, &config, now).unwrap();
+        let evicted: Vec<(&str, &str)> = evicted
+            .iter()
+            .map(|e| (e.path.as_str(), e.reason.as_str()))
+            .collect();
+        assert_eq!(
+            evicted,
+            vec![
+                ("document://clip/stale", "older than max_age_days = 30"),
+                ("document://clip/a", "over max_chunks = 3"),
+                ("document://shell/2", "over max_chunks = 1"),
+            ]
+        );
+        assert!(db.get_file("document://clip/b").unwrap().is_some());
+        assert!(db.get_file("document://shell/1").unwrap().is_some());
+        assert!(db.get_file("document://other").unwrap().is_some());
+        assert!(enforce(&db, &config, now).unwrap().is_empty());
+
+        // Evicted files stay out until they change
+        let eviction = db.eviction("document://clip/a").unwrap().unwrap();
+        assert_eq!(
+            (eviction.collection.as_str(), eviction.chunks),
+            ("clipboard", 2)
+        );
+        assert_eq!(db.recent_evictions(10).unwrap().len(), 3);
+        assert!(!db
+            .needs_reindexing("document://clip/a", now - 3 * day)
+            .unwrap());
+        assert!(db
+            .needs_reindexing("document://clip/a", eviction.evicted_at + 1)
+            .unwrap());
+    }
+}
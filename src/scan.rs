@@ -0,0 +1,408 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::storage::db::{Database, ScanRecord};
+
+/// How often the daemon records scan progress while indexing.
+const RECORD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A scan whose progress wasn't recorded for this many seconds belongs to a
+/// daemon that stopped before finishing it.
+const STALE_AFTER: u64 = 300;
+
+/// Progress of the daemon's startup scan, as reported by `/stats` and the
+/// MCP `get_status` tool while it runs.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ScanProgress {
+    /// Files found under the watch roots
+    pub discovered: u64,
+    /// Files indexed, or found unchanged, so far
+    pub indexed: u64,
+    pub elapsed_secs: u64,
+    /// Time left at the rate so far, once a file has been indexed
+    pub eta_secs: Option<u64>,
+}
+
+impl ScanProgress {
+    /// One line for people and agents, like "Initial indexing in progress:
+    /// 120 of 4000 files, about 6 min left".
+    pub fn describe(&self) -> String {
+        let mut text = format!(
+            "Initial indexing in progress: {} of {} files",
+            self.indexed, self.discovered
+        );
+        match self.eta_secs {
+            Some(secs) if secs >= 60 => text.push_str(&format!(", about {} min left", secs / 60)),
+            Some(secs) => text.push_str(&format!(", about {}s left", secs)),
+            None => {}
+        }
+        text
+    }
+}
+
+/// The startup scan's progress, or None when no scan is running.
+pub fn progress(db: &Database) -> Result<Option<ScanProgress>> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(db
+        .scan_record()?
+        .and_then(|record| progress_at(&record, now)))
+}
+
+fn progress_at(record: &ScanRecord, now: u64) -> Option<ScanProgress> {
+    if record.finished || now.saturating_sub(record.updated_at) > STALE_AFTER {
+        return None;
+    }
+    let elapsed_secs = now.saturating_sub(record.started_at);
+    let remaining = record.discovered.saturating_sub(record.indexed);
+    Some(ScanProgress {
+        discovered: record.discovered,
+        indexed: record.indexed,
+        elapsed_secs,
+        eta_secs: (record.indexed > 0).then(|| elapsed_secs * remaining / record.indexed),
+    })
+}
+
+/// Where a scan that didn't finish got to. The watch roots are walked in
+/// order and each in file name order, so every file walked before its
+/// checkpoint directory, or inside it, had been indexed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resume {
+    /// Roots walked before the checkpoint's
+    walked_roots: Vec<PathBuf>,
+    root: PathBuf,
+    pub checkpoint: PathBuf,
+    /// When the interrupted scan started; files changed since are indexed
+    /// again
+    started_at: u64,
+}
+
+impl Resume {
+    /// The last scan's checkpoint if it didn't finish and is under one of
+    /// `roots`, in the order they're walked.
+    pub fn load(db: &Database, roots: &[PathBuf]) -> Result<Option<Self>> {
+        let Some(record) = db.scan_record()?.filter(|record| !record.finished) else {
+            return Ok(None);
+        };
+        let Some(checkpoint) = db.scan_checkpoint()?.map(PathBuf::from) else {
+            return Ok(None);
+        };
+        let Some(index) = roots.iter().position(|root| checkpoint.starts_with(root)) else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            walked_roots: roots[..index].to_vec(),
+            root: roots[index].clone(),
+            checkpoint,
+            started_at: record.started_at,
+        }))
+    }
+
+    /// Whether the interrupted scan walked `path`, and indexed it as it is
+    /// now if it's a file.
+    pub fn skips(&self, path: &Path) -> bool {
+        let walked = self.walked_roots.iter().any(|root| path.starts_with(root))
+            || (path.starts_with(&self.root)
+                && (path <= self.checkpoint.as_path() || path.starts_with(&self.checkpoint)));
+        if !walked {
+            return false;
+        }
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return false;
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(u64::MAX, |d| d.as_secs());
+        metadata.is_dir() || modified < self.started_at
+    }
+}
+
+/// Counts the startup scan's files as they are indexed, recording progress
+/// and the checkpoint a later scan could resume from in the database every
+/// second and once the last file is done.
+#[derive(Clone)]
+pub struct ScanTracker {
+    inner: Arc<Counts>,
+}
+
+#[derive(Default)]
+struct Counts {
+    indexed: AtomicU64,
+    /// Files handed to a worker and not yet indexed
+    pending: AtomicU64,
+    /// Every watch root has been walked
+    walked: AtomicBool,
+    done: Notify,
+    walk: Mutex<Walk>,
+}
+
+/// The scan's position in the walk, which its checkpoint follows.
+#[derive(Default)]
+struct Walk {
+    /// Files queued so far, numbered in walk order
+    queued: u64,
+    /// Numbers of the files queued and not yet indexed
+    pending: BTreeSet<u64>,
+    /// Directories being walked, innermost last
+    open: Vec<PathBuf>,
+    /// Directories walked, each with the number of files queued before the
+    /// walk left it, oldest first
+    closed: VecDeque<(PathBuf, u64)>,
+    /// The last directory whose files, and every file walked before them,
+    /// are indexed
+    checkpoint: Option<PathBuf>,
+}
+
+impl Walk {
+    /// Leave the open directories `path` isn't in.
+    fn close(&mut self, path: Option<&Path>) {
+        while let Some(dir) = self.open.pop() {
+            if path.is_some_and(|path| path.starts_with(&dir)) {
+                self.open.push(dir);
+                break;
+            }
+            self.closed.push_back((dir, self.queued));
+        }
+        self.advance();
+    }
+
+    /// Move the checkpoint past the directories whose files are indexed.
+    fn advance(&mut self) {
+        let indexed = self.pending.first().copied().unwrap_or(self.queued);
+        while let Some((_, queued)) = self.closed.front() {
+            if *queued > indexed {
+                break;
+            }
+            self.checkpoint = self.closed.pop_front().map(|(dir, _)| dir);
+        }
+    }
+
+    fn queue(&mut self) -> u64 {
+        let file = self.queued;
+        self.queued += 1;
+        self.pending.insert(file);
+        file
+    }
+}
+
+impl ScanTracker {
+    /// Record a scan of `discovered` files as started, resuming from
+    /// `checkpoint` if one was interrupted.
+    pub async fn start(db: Database, discovered: u64, checkpoint: Option<PathBuf>) -> Result<Self> {
+        db.call(move |db| db.start_scan(discovered)).await?;
+        let tracker = Self {
+            inner: Arc::new(Counts::default()),
+        };
+        tracker.walk().checkpoint = checkpoint;
+        let counts = tracker.inner.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RECORD_INTERVAL);
+            loop {
+                let finished = tokio::select! {
+                    _ = interval.tick() => false,
+                    _ = counts.done.notified() => true,
+                };
+                let indexed = counts.indexed.load(Ordering::Relaxed);
+                let checkpoint = counts
+                    .walk
+                    .lock()
+                    .unwrap()
+                    .checkpoint
+                    .as_ref()
+                    .map(|dir| dir.to_string_lossy().to_string());
+                if let Err(e) = db
+                    .call(move |db| db.update_scan(indexed, finished, checkpoint))
+                    .await
+                {
+                    log::warn!("Failed to record scan progress: {}", e);
+                }
+                if finished {
+                    log::info!("Initial scan indexed {} files", indexed);
+                    return;
+                }
+            }
+        });
+        Ok(tracker)
+    }
+
+    fn walk(&self) -> std::sync::MutexGuard<'_, Walk> {
+        self.inner.walk.lock().unwrap()
+    }
+
+    /// The walk reached `path`, a file or directory.
+    pub fn walking(&self, path: &Path, is_dir: bool) {
+        let mut walk = self.walk();
+        walk.close(Some(path));
+        if is_dir {
+            walk.open.push(path.to_path_buf());
+        }
+    }
+
+    /// A file was handed to a worker; returns its number for
+    /// [`Self::indexed`].
+    pub fn queued(&self) -> u64 {
+        self.inner.pending.fetch_add(1, Ordering::SeqCst);
+        self.walk().queue()
+    }
+
+    /// A queued file was indexed.
+    pub fn indexed(&self, file: u64) {
+        let mut walk = self.walk();
+        walk.pending.remove(&file);
+        walk.advance();
+        drop(walk);
+        self.inner.indexed.fetch_add(1, Ordering::Relaxed);
+        self.inner.pending.fetch_sub(1, Ordering::SeqCst);
+        self.finish_if_done();
+    }
+
+    /// A file the interrupted scan indexed was found unchanged.
+    pub fn skipped(&self) {
+        self.inner.indexed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A file was left unindexed, so the checkpoint stays before it.
+    pub fn left(&self) {
+        self.walk().queue();
+    }
+
+    /// No more files will be queued.
+    pub fn walked(&self) {
+        self.walk().close(None);
+        self.inner.walked.store(true, Ordering::SeqCst);
+        self.finish_if_done();
+    }
+
+    fn finish_if_done(&self) {
+        if self.inner.walked.load(Ordering::SeqCst)
+            && self.inner.pending.load(Ordering::SeqCst) == 0
+        {
+            self.inner.done.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_progress() {
+        let db = Database::new(":memory:").unwrap();
+        assert_eq!(progress(&db).unwrap(), None);
+
+        let tracker = ScanTracker::start(db.clone(), 3, None).await.unwrap();
+        let record = db.scan_record().unwrap().unwrap();
+        assert_eq!(
+            progress_at(&record, record.started_at),
+            Some(ScanProgress {
+                discovered: 3,
+                indexed: 0,
+                elapsed_secs: 0,
+                eta_secs: None,
+            })
+        );
+        let files: Vec<u64> = (0..2).map(|_| tracker.queued()).collect();
+        tracker.indexed(files[0]);
+        tracker.walked();
+        let record = ScanRecord {
+            indexed: 1,
+            ..record
+        };
+        // One of three files in 10s leaves 20s
+        let third = progress_at(&record, record.started_at + 10).unwrap();
+        assert_eq!(third.eta_secs, Some(20));
+        assert_eq!(
+            third.describe(),
+            "Initial indexing in progress: 1 of 3 files, about 20s left"
+        );
+        assert_eq!(
+            progress_at(&record, record.updated_at + STALE_AFTER + 1),
+            None
+        );
+
+        tracker.indexed(files[1]);
+        for _ in 0..50 {
+            if progress(&db).unwrap().is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let record = db.scan_record().unwrap().unwrap();
+        assert!(record.finished);
+        assert_eq!(record.indexed, 2);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let (earlier, root) = (dir.path().join("earlier"), dir.path().join("root"));
+        let (a, b) = (root.join("a"), root.join("b"));
+        let files = [a.join("1.rs"), b.join("2.rs"), root.join("c.rs")];
+        let walked = earlier.join("e.rs");
+        for path in files.iter().chain([&walked]) {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "fn main() {}").unwrap();
+            let old = std::time::UNIX_EPOCH + Duration::from_secs(1000);
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+
+        let db = Database::new(":memory:").unwrap();
+        let tracker = ScanTracker::start(db.clone(), 3, None).await.unwrap();
+        let mut queued = Vec::new();
+        for path in [&root, &a, &files[0], &b, &files[1], &files[2]] {
+            tracker.walking(path, path.is_dir());
+            if path.is_file() {
+                queued.push(tracker.queued());
+            }
+        }
+        // Indexing b's file first doesn't get the checkpoint past a
+        tracker.indexed(queued[1]);
+        assert_eq!(tracker.walk().checkpoint, None);
+        tracker.indexed(queued[0]);
+        assert_eq!(tracker.walk().checkpoint, Some(b.clone()));
+        // The root isn't done until its last file is
+        tracker.walked();
+        assert_eq!(tracker.walk().checkpoint, Some(b.clone()));
+
+        // Cut short here, the next scan skips the earlier root and up to b
+        let checkpoint = Some(b.to_string_lossy().to_string());
+        db.update_scan(2, false, checkpoint).unwrap();
+        let roots = [earlier.clone(), root.clone()];
+        let resume = Resume::load(&db, &roots).unwrap().unwrap();
+        assert_eq!(resume.checkpoint, b);
+        assert!(resume.skips(&a));
+        assert!(resume.skips(&files[0]));
+        assert!(resume.skips(&files[1]));
+        assert!(!resume.skips(&files[2]));
+        assert!(resume.skips(&walked));
+        // Changed since the scan started
+        std::fs::write(&files[0], "fn main() { }").unwrap();
+        assert!(!resume.skips(&files[0]));
+
+        tracker.indexed(queued[2]);
+        for _ in 0..50 {
+            if db.scan_checkpoint().unwrap().is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(db.scan_checkpoint().unwrap(), None);
+        assert_eq!(Resume::load(&db, &roots).unwrap(), None);
+    }
+}
@@ -0,0 +1,508 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use crate::archive;
+use crate::audit::AuditLog;
+use crate::citation::{Citation, Citer};
+use crate::collections::{self, CollectionConfig, Models};
+use crate::config::Config;
+use crate::ensemble;
+use crate::report;
+use crate::session::{self, RepeatMode, SessionStore};
+use crate::staleness;
+use crate::storage::db::{Database, SearchOptions, SearchResult};
+
+/// Most hits a query may skip; deeper pages would rank this many results
+/// and throw them away.
+pub const MAX_OFFSET: usize = 1_000;
+
+/// How a query is matched against the index.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Embedding similarity
+    #[default]
+    Vector,
+    /// Embedding similarity fused with full-text matches. Scores are rank
+    /// based, so `min_score` doesn't apply.
+    Hybrid,
+    /// Also search with the query's keywords (and a hypothetical answer when
+    /// given) and fuse the rankings
+    Ensemble,
+}
+
+/// How embedding similarity is searched.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorIndex {
+    /// Compare the query with every chunk
+    #[default]
+    Exact,
+    /// Compare it with the chunks nearest by a compact sign-bit index only.
+    /// Much faster on large indexes, but may miss some matches.
+    Approximate,
+}
+
+/// `[search]` config: defaults for what a query leaves unset, applied alike
+/// by the REST API, MCP, the language server and `contextd query`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SearchConfig {
+    pub default_limit: usize,
+    /// Drop results scoring below this, unless the query or its collection
+    /// sets a threshold
+    pub default_min_score: Option<f32>,
+    pub default_mode: SearchMode,
+    /// Boost results from recently modified and often-returned files
+    pub rerank: bool,
+    pub vector_index: VectorIndex,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            default_limit: 5,
+            default_min_score: None,
+            default_mode: SearchMode::Vector,
+            rerank: true,
+            vector_index: VectorIndex::Exact,
+        }
+    }
+}
+
+impl SearchConfig {
+    /// `options` with what the query left unset taken from `collection`'s
+    /// search defaults, then from these.
+    pub fn resolve(
+        &self,
+        options: &SearchOptions,
+        collection: Option<&CollectionConfig>,
+    ) -> SearchOptions {
+        let unranked = (!self.rerank).then_some(0.0);
+        SearchOptions {
+            limit: Some(options.limit.unwrap_or(self.default_limit)),
+            min_score: options
+                .min_score
+                .or(collection.and_then(|c| c.min_score))
+                .or(self.default_min_score),
+            recency_weight: options
+                .recency_weight
+                .or(collection.and_then(|c| c.recency_weight))
+                .or(unranked),
+            frequency_weight: options
+                .frequency_weight
+                .or(collection.and_then(|c| c.frequency_weight))
+                .or(unranked),
+            approximate: options.approximate || self.vector_index == VectorIndex::Approximate,
+            ..options.clone()
+        }
+    }
+
+    /// The mode for a query asking for `requested`, if anything.
+    pub fn mode(&self, requested: Option<SearchMode>) -> SearchMode {
+        requested.unwrap_or(self.default_mode)
+    }
+}
+
+/// Search every collection for `query` in `mode`, with `[search]` defaults
+/// for what `options` leaves unset. A `hypothetical` answer implies
+/// [`SearchMode::Ensemble`].
+pub fn run(
+    db: &Database,
+    models: &Models,
+    config: &Config,
+    query: &str,
+    hypothetical: Option<&str>,
+    mode: Option<SearchMode>,
+    options: &SearchOptions,
+) -> Result<Vec<SearchResult>> {
+    let mode = match hypothetical {
+        Some(_) => SearchMode::Ensemble,
+        None => config.search.mode(mode),
+    };
+    let options = SearchOptions {
+        limit: Some(options.limit.unwrap_or(config.search.default_limit)),
+        hybrid: mode == SearchMode::Hybrid,
+        ..options.clone()
+    };
+    let search = |text: &str, options: &SearchOptions| {
+        collections::search(db, models, config, text, options)
+    };
+    match mode {
+        SearchMode::Ensemble => ensemble::search(query, hypothetical, &options, search),
+        _ => search(query, &options),
+    }
+}
+
+/// A query as every interface takes it.
+#[derive(Default, Clone)]
+pub struct Query {
+    pub text: String,
+    /// A hypothetical answer to search with as well; implies
+    /// [`SearchMode::Ensemble`]
+    pub hypothetical: Option<String>,
+    /// Unset for `[search] default_mode`
+    pub mode: Option<SearchMode>,
+    /// Filters and weights; `limit` is the page size
+    pub options: SearchOptions,
+    /// Hits skipped, for pages after the first
+    pub offset: usize,
+    /// Attach a citation to each hit
+    pub citations: bool,
+    pub session: Option<SessionOptions>,
+}
+
+/// How a query treats chunks already returned in its session.
+#[derive(Default, Clone)]
+pub struct SessionOptions {
+    pub id: String,
+    /// Return already-seen chunks in full
+    pub include_seen: bool,
+    pub repeats: RepeatMode,
+}
+
+/// A ranked result with what every interface adds to it.
+pub struct Hit {
+    pub result: SearchResult,
+    pub citation: Option<Citation>,
+    /// Why the staleness policy of the result's collection flags it
+    pub stale_reason: Option<String>,
+    /// Already returned earlier in the session; the content is a summary
+    pub seen: bool,
+}
+
+impl Hit {
+    pub fn new(
+        result: SearchResult,
+        config: &Config,
+        now: u64,
+        citation: Option<Citation>,
+    ) -> Self {
+        Self {
+            stale_reason: staleness::check(&result, &config.staleness, now),
+            result,
+            citation,
+            seen: false,
+        }
+    }
+
+    /// First line of the chunk, from the index or else its citation.
+    pub fn line_start(&self) -> Option<usize> {
+        self.result
+            .line_start
+            .or(self.citation.as_ref().and_then(|c| c.line_start))
+    }
+
+    pub fn line_end(&self) -> Option<usize> {
+        self.result
+            .line_end
+            .or(self.citation.as_ref().and_then(|c| c.line_end))
+    }
+}
+
+/// One page of hits.
+pub struct Page {
+    pub hits: Vec<Hit>,
+    /// Offset of the next page, when there is one
+    pub next_offset: Option<usize>,
+}
+
+/// Runs queries the same way for the REST API, MCP, the language server and
+/// `contextd query`: search, paginate, flag stale hits, cite, apply the
+/// session, then record the query in the stats and the audit log. Each
+/// interface only formats the hits.
+pub struct QueryService<'a> {
+    /// Interface name recorded with each query
+    source: &'static str,
+    db: &'a Database,
+    models: &'a Models,
+    config: &'a Config,
+    audit: &'a AuditLog,
+    sessions: Option<&'a SessionStore>,
+}
+
+impl<'a> QueryService<'a> {
+    pub fn new(
+        source: &'static str,
+        db: &'a Database,
+        models: &'a Models,
+        config: &'a Config,
+        audit: &'a AuditLog,
+    ) -> Self {
+        Self {
+            source,
+            db,
+            models,
+            config,
+            audit,
+            sessions: None,
+        }
+    }
+
+    /// Track what each session has been shown; without a store, queries'
+    /// sessions are ignored.
+    pub fn with_sessions(mut self, sessions: &'a SessionStore) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    /// Run `query` for `client`, returning its page.
+    pub fn search(&self, query: &Query, client: Option<&str>) -> Result<Page> {
+        let mut hits = Vec::new();
+        let next_offset = self.search_each(query, client, |hit| {
+            hits.push(hit);
+            true
+        })?;
+        Ok(Page { hits, next_offset })
+    }
+
+    /// Run `query` for `client`, handing each hit of its page to `emit` in
+    /// rank order until it returns false. Returns the next page's offset.
+    pub fn search_each(
+        &self,
+        query: &Query,
+        client: Option<&str>,
+        emit: impl FnMut(Hit) -> bool,
+    ) -> Result<Option<usize>> {
+        let page_size = query
+            .options
+            .limit
+            .unwrap_or(self.config.search.default_limit);
+        // Fetch one extra hit to learn whether another page exists
+        let limit = query
+            .offset
+            .checked_add(page_size)
+            .and_then(|end| end.checked_add(1))
+            .filter(|_| query.offset <= MAX_OFFSET)
+            .ok_or_else(|| anyhow!("Offset {} is out of range", query.offset))?;
+        let options = SearchOptions {
+            limit: Some(limit),
+            ..query.options.clone()
+        };
+        let started = Instant::now();
+        let ranked = run(
+            self.db,
+            self.models,
+            self.config,
+            &query.text,
+            query.hypothetical.as_deref(),
+            query.mode,
+            &options,
+        );
+        let elapsed = started.elapsed();
+        let ranked =
+            ranked.inspect_err(|_| report::record_failed_query(self.db, self.source, elapsed))?;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let (returned, chunk_ids, next_offset) = present(
+            self.config,
+            self.sessions,
+            query,
+            page_size,
+            ranked,
+            now,
+            emit,
+        );
+        report::record_query(
+            self.db,
+            self.source,
+            elapsed,
+            returned.iter().map(String::as_str),
+        );
+        self.audit.record(
+            self.source,
+            client,
+            &query.text,
+            returned.iter().map(String::as_str),
+        );
+        archive::record_returned(self.db, &chunk_ids);
+        Ok(next_offset)
+    }
+}
+
+/// Emit `query`'s page of `ranked`, returning the paths and chunk ids of the
+/// hits emitted and the next page's offset.
+fn present(
+    config: &Config,
+    sessions: Option<&SessionStore>,
+    query: &Query,
+    page_size: usize,
+    ranked: Vec<SearchResult>,
+    now: u64,
+    mut emit: impl FnMut(Hit) -> bool,
+) -> (Vec<String>, Vec<i64>, Option<usize>) {
+    let end = query.offset.saturating_add(page_size);
+    // A cursor past MAX_OFFSET would be rejected, so don't hand one out
+    let next_offset = (ranked.len() > end && end <= MAX_OFFSET).then_some(end);
+    let mut citer = Citer::default();
+    let mut returned = Vec::new();
+    let mut chunk_ids = Vec::new();
+    for result in ranked.into_iter().skip(query.offset).take(page_size) {
+        let citation = query
+            .citations
+            .then(|| citer.cite(&result.file_path, result.start_offset, result.end_offset));
+        let mut hit = Hit::new(result, config, now, citation);
+        if let (Some(session), Some(sessions)) = (&query.session, sessions) {
+            let seen = sessions.check_and_record(&session.id, &[hit.result.id])[0];
+            if seen && !session.include_seen {
+                match session.repeats {
+                    RepeatMode::Omit => continue,
+                    RepeatMode::Summarize => {
+                        hit.result.content = session::summarize(&hit.result.content);
+                        hit.seen = true;
+                    }
+                }
+            }
+        }
+        returned.push(hit.result.file_path.clone());
+        chunk_ids.push(hit.result.id);
+        if !emit(hit) {
+            break;
+        }
+    }
+    (returned, chunk_ids, next_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let config: Config = toml::from_str(
+            r#"
+            [search]
+            default_limit = 8
+            default_min_score = 0.3
+            default_mode = "hybrid"
+            rerank = false
+            vector_index = "approximate"
+            [collections.notes]
+            paths = ["notes"]
+            min_score = 0.5
+            "#,
+        )
+        .unwrap();
+        let search = &config.search;
+        assert_eq!(search.mode(None), SearchMode::Hybrid);
+        assert_eq!(search.mode(Some(SearchMode::Vector)), SearchMode::Vector);
+
+        let unset = search.resolve(&SearchOptions::default(), None);
+        assert_eq!(unset.limit, Some(8));
+        assert_eq!(unset.min_score, Some(0.3));
+        assert_eq!(unset.recency_weight, Some(0.0));
+        assert_eq!(unset.frequency_weight, Some(0.0));
+        assert!(unset.approximate);
+
+        // The query wins over the collection, which wins over `[search]`
+        let notes = config.collections.get("notes");
+        assert_eq!(
+            search.resolve(&SearchOptions::default(), notes).min_score,
+            Some(0.5)
+        );
+        let options = SearchOptions {
+            limit: Some(2),
+            min_score: Some(0.1),
+            recency_weight: Some(0.4),
+            ..Default::default()
+        };
+        let set = search.resolve(&options, notes);
+        assert_eq!(set.limit, Some(2));
+        assert_eq!(set.min_score, Some(0.1));
+        assert_eq!(set.recency_weight, Some(0.4));
+
+        // Defaults keep the built-in weights
+        let built_in = SearchConfig::default().resolve(&SearchOptions::default(), None);
+        assert_eq!(built_in.limit, Some(5));
+        assert_eq!(built_in.recency_weight, None);
+        assert!(!built_in.approximate);
+    }
+
+    #[test]
+    fn test_present() {
+        let config = Config::default();
+        let sessions = SessionStore::default();
+        let ranked = |n: i64| -> Vec<SearchResult> {
+            (1..=n)
+                .map(|id| SearchResult {
+                    id,
+                    file_path: format!("/src/{}.rs", id),
+                    content: format!("fn f{}() {{}}", id),
+                    ..Default::default()
+                })
+                .collect()
+        };
+        let mut query = Query {
+            session: Some(SessionOptions {
+                id: "s".to_string(),
+                repeats: RepeatMode::Omit,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut ids = Vec::new();
+        let (returned, _, next) =
+            present(&config, Some(&sessions), &query, 2, ranked(5), 0, |hit| {
+                ids.push(hit.result.id);
+                true
+            });
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(returned, ["/src/1.rs", "/src/2.rs"]);
+        assert_eq!(next, Some(2));
+
+        // The last page has no next one, and hits seen earlier are left out
+        query.offset = 1;
+        let mut ids = Vec::new();
+        let (_, _, next) = present(&config, Some(&sessions), &query, 2, ranked(3), 0, |hit| {
+            ids.push(hit.result.id);
+            true
+        });
+        assert_eq!(ids, [3]);
+        assert_eq!(next, None);
+
+        // Summarized repeats are marked seen; a false emit stops the page
+        query.session.as_mut().unwrap().repeats = RepeatMode::Summarize;
+        query.offset = 0;
+        let mut hits = Vec::new();
+        let (returned, _, _) = present(&config, Some(&sessions), &query, 3, ranked(3), 0, |hit| {
+            hits.push(hit);
+            false
+        });
+        assert_eq!(returned.len(), 1);
+        assert!(hits[0].seen);
+        assert!(hits[0]
+            .result
+            .content
+            .ends_with("(already returned in this session)"));
+
+        // No page is offered past MAX_OFFSET
+        query.session = None;
+        query.offset = MAX_OFFSET - 1;
+        let (_, _, next) = present(
+            &config,
+            None,
+            &query,
+            1,
+            ranked(MAX_OFFSET as i64 + 2),
+            0,
+            |_| true,
+        );
+        assert_eq!(next, Some(MAX_OFFSET));
+        query.offset = MAX_OFFSET;
+        let (_, _, next) = present(
+            &config,
+            None,
+            &query,
+            1,
+            ranked(MAX_OFFSET as i64 + 2),
+            0,
+            |_| true,
+        );
+        assert_eq!(next, None);
+    }
+}
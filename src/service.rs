@@ -0,0 +1,319 @@
+// Running the daemon as a Windows service. `contextd service install`
+// registers it with the service control manager through sc.exe, which then
+// starts `contextd service run` at boot from the system directory, so the
+// command line carries the config and directory it was installed from.
+
+use anyhow::Result;
+use std::path::Path;
+
+use crate::config::Config;
+
+/// Name the service is registered under.
+pub const SERVICE_NAME: &str = "contextd";
+
+/// Register the daemon as a service started at boot, reading `config_path`
+/// and resolving relative paths from the current directory.
+pub fn handle_install(config_path: &Path) -> Result<()> {
+    let config_path = std::path::absolute(config_path)?;
+    let dir = std::env::current_dir()?;
+    let exe = std::env::current_exe()?;
+    let command_line = service_command_line(&exe, &config_path, &dir);
+    sc::install(&command_line)?;
+    println!("Installed the {} service, starting at boot.", SERVICE_NAME);
+    println!("Start it now with: sc.exe start {}", SERVICE_NAME);
+    Ok(())
+}
+
+/// Stop the service and remove it.
+pub fn handle_uninstall() -> Result<()> {
+    sc::uninstall()?;
+    println!("Removed the {} service.", SERVICE_NAME);
+    Ok(())
+}
+
+/// Run as the service; only the service control manager starts this.
+pub fn run(config: Config) -> Result<()> {
+    #[cfg(windows)]
+    {
+        scm::run(config)
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = config;
+        anyhow::bail!(NOT_WINDOWS)
+    }
+}
+
+#[cfg(not(windows))]
+const NOT_WINDOWS: &str = "Services are only supported on Windows; elsewhere run `contextd \
+                           daemon` from your init system, such as a systemd unit or a launchd \
+                           agent";
+
+/// The command the service control manager runs.
+fn service_command_line(exe: &Path, config_path: &Path, dir: &Path) -> String {
+    format!(
+        "{} --config {} service run --dir {}",
+        quote(&exe.to_string_lossy()),
+        quote(&config_path.to_string_lossy()),
+        quote(&dir.to_string_lossy())
+    )
+}
+
+/// Quote `arg` for a Windows command line. Backslashes are literal except
+/// before a quote, so those before an embedded quote or the closing one are
+/// doubled.
+fn quote(arg: &str) -> String {
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            '"' => quoted.push_str(&"\\".repeat(backslashes * 2 + 1)),
+            _ => quoted.push_str(&"\\".repeat(backslashes)),
+        }
+        backslashes = 0;
+        quoted.push(c);
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(windows)]
+mod sc {
+    use super::SERVICE_NAME;
+    use anyhow::{bail, Result};
+    use std::process::Command;
+
+    /// Time to wait before restarting the service after a crash.
+    const RESTART_DELAY_MS: u32 = 5000;
+
+    pub fn install(command_line: &str) -> Result<()> {
+        sc(&[
+            "create",
+            SERVICE_NAME,
+            "binPath=",
+            command_line,
+            "start=",
+            "auto",
+            "DisplayName=",
+            SERVICE_NAME,
+        ])?;
+        sc(&[
+            "description",
+            SERVICE_NAME,
+            "Local semantic context daemon for AI agents",
+        ])?;
+        let actions = format!("restart/{}", RESTART_DELAY_MS);
+        if let Err(e) = sc(&[
+            "failure",
+            SERVICE_NAME,
+            "reset=",
+            "86400",
+            "actions=",
+            &actions,
+        ]) {
+            log::warn!("Failed to set the service to restart after crashes: {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn uninstall() -> Result<()> {
+        // Fails when the service isn't running, which is fine
+        let _ = sc(&["stop", SERVICE_NAME]);
+        sc(&["delete", SERVICE_NAME])
+    }
+
+    fn sc(args: &[&str]) -> Result<()> {
+        let output = Command::new("sc.exe").args(args).output()?;
+        if !output.status.success() {
+            // sc.exe reports errors on stdout
+            let message = String::from_utf8_lossy(&output.stdout);
+            bail!(
+                "sc.exe {} failed (run as administrator?): {}",
+                args[0],
+                message.trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(windows))]
+mod sc {
+    use super::NOT_WINDOWS;
+    use anyhow::{bail, Result};
+
+    pub fn install(_command_line: &str) -> Result<()> {
+        bail!(NOT_WINDOWS)
+    }
+
+    pub fn uninstall() -> Result<()> {
+        bail!(NOT_WINDOWS)
+    }
+}
+
+#[cfg(windows)]
+mod scm {
+    use super::SERVICE_NAME;
+    use crate::config::Config;
+    use crate::daemon;
+    use anyhow::{bail, Result};
+    use std::ffi::c_void;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Mutex, OnceLock};
+    use tokio::runtime::Handle;
+    use tokio::sync::oneshot;
+    use windows_sys::core::PWSTR;
+    use windows_sys::Win32::Foundation::{
+        ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR,
+    };
+    use windows_sys::Win32::System::Services::{
+        RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW,
+        SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_CONTROL_INTERROGATE,
+        SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_RUNNING, SERVICE_STATUS,
+        SERVICE_STATUS_CURRENT_STATE, SERVICE_STOPPED, SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW,
+        SERVICE_WIN32_OWN_PROCESS,
+    };
+
+    /// Time the service control manager is told a stop may take: the
+    /// indexing grace period plus the checkpoint.
+    const STOP_WAIT_HINT_MS: u32 = 30_000;
+
+    /// What `service_main` runs the daemon with, as the dispatcher calls it
+    /// without context.
+    static SERVICE: OnceLock<(Handle, Config)> = OnceLock::new();
+    static STOP: Mutex<Option<oneshot::Sender<()>>> = Mutex::new(None);
+    static STATUS_HANDLE: AtomicUsize = AtomicUsize::new(0);
+
+    pub fn run(config: Config) -> Result<()> {
+        let _ = SERVICE.set((Handle::current(), config));
+        let mut name = wide(SERVICE_NAME);
+        let table = [
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: name.as_mut_ptr(),
+                lpServiceProc: Some(service_main),
+            },
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: std::ptr::null_mut(),
+                lpServiceProc: None,
+            },
+        ];
+        // Blocks until the service stops
+        let connected =
+            tokio::task::block_in_place(|| unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) });
+        if connected == 0 {
+            bail!(
+                "Failed to connect to the service control manager: {}; \
+                 `contextd service run` only works when started as a service",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+        let Some((runtime, config)) = SERVICE.get() else {
+            return;
+        };
+        let name = wide(SERVICE_NAME);
+        let handle = unsafe {
+            RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control_handler), std::ptr::null())
+        };
+        if handle.is_null() {
+            log::error!(
+                "Failed to register the service control handler: {}",
+                std::io::Error::last_os_error()
+            );
+            return;
+        }
+        STATUS_HANDLE.store(handle as usize, Ordering::SeqCst);
+
+        let (stop, stopped) = oneshot::channel();
+        *STOP.lock().unwrap() = Some(stop);
+        set_status(SERVICE_RUNNING, NO_ERROR, 0);
+        let result = runtime.block_on(daemon::run_until(config.clone(), async {
+            let _ = stopped.await;
+        }));
+        let exit_code = match result {
+            Ok(()) => NO_ERROR,
+            Err(e) => {
+                log::error!("Service failed: {:#}", e);
+                ERROR_SERVICE_SPECIFIC_ERROR
+            }
+        };
+        log::logger().flush();
+        set_status(SERVICE_STOPPED, exit_code, 0);
+    }
+
+    unsafe extern "system" fn control_handler(
+        control: u32,
+        _event_type: u32,
+        _event_data: *mut c_void,
+        _context: *mut c_void,
+    ) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                set_status(SERVICE_STOP_PENDING, NO_ERROR, STOP_WAIT_HINT_MS);
+                if let Some(stop) = STOP.lock().unwrap().take() {
+                    let _ = stop.send(());
+                }
+                NO_ERROR
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    fn set_status(state: SERVICE_STATUS_CURRENT_STATE, exit_code: u32, wait_hint: u32) {
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: match state {
+                SERVICE_RUNNING => SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN,
+                _ => 0,
+            },
+            dwWin32ExitCode: exit_code,
+            dwServiceSpecificExitCode: (exit_code == ERROR_SERVICE_SPECIFIC_ERROR) as u32,
+            dwCheckPoint: 0,
+            dwWaitHint: wait_hint,
+        };
+        let handle = STATUS_HANDLE.load(Ordering::SeqCst) as *mut c_void;
+        unsafe {
+            SetServiceStatus(handle, &status);
+        }
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_command_line() {
+        assert_eq!(
+            quote(r"C:\Program Files\contextd"),
+            r#""C:\Program Files\contextd""#
+        );
+        // A trailing backslash would otherwise escape the closing quote
+        assert_eq!(quote(r"C:\data\"), r#""C:\data\\""#);
+        assert_eq!(quote(r#"a\"b"#), r#""a\\\"b""#);
+
+        let line = service_command_line(
+            Path::new(r"C:\Tools\contextd.exe"),
+            Path::new(r"C:\Users\me\contextd.toml"),
+            Path::new(r"C:\Users\me"),
+        );
+        assert_eq!(
+            line,
+            r#""C:\Tools\contextd.exe" --config "C:\Users\me\contextd.toml" service run --dir "C:\Users\me""#
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Sessions idle for longer than this are forgotten.
+const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Most sessions remembered at once; the least recently used one is dropped
+/// to make room for a new session.
+const MAX_SESSIONS: usize = 1024;
+
+/// Maximum characters of a repeated chunk kept in its summary.
+const SUMMARY_CHARS: usize = 120;
+
+/// What to do with chunks already returned earlier in a session.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    /// Keep the hit but replace its content with a one-line summary
+    #[default]
+    Summarize,
+    /// Drop the hit entirely
+    Omit,
+}
+
+struct Session {
+    seen: HashSet<i64>,
+    last_used: Instant,
+}
+
+/// Remembers which chunk ids each client session has already been sent, so
+/// agents asking overlapping questions turn after turn are not re-sent the
+/// same context.
+#[derive(Default)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl SessionStore {
+    /// For each id, whether the session has seen it before; all of them are
+    /// recorded as seen afterwards.
+    pub fn check_and_record(&self, session_id: &str, ids: &[i64]) -> Vec<bool> {
+        self.check_and_record_at(session_id, ids, Instant::now())
+    }
+
+    fn check_and_record_at(&self, session_id: &str, ids: &[i64], now: Instant) -> Vec<bool> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, s| now.saturating_duration_since(s.last_used) < SESSION_TTL);
+        if !sessions.contains_key(session_id) && sessions.len() >= MAX_SESSIONS {
+            let oldest = sessions
+                .iter()
+                .min_by_key(|(_, s)| s.last_used)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                sessions.remove(&oldest);
+            }
+        }
+
+        let session = sessions
+            .entry(session_id.to_string())
+            .or_insert_with(|| Session {
+                seen: HashSet::new(),
+                last_used: now,
+            });
+        session.last_used = now;
+        ids.iter().map(|id| !session.seen.insert(*id)).collect()
+    }
+}
+
+/// One-line stand-in for a chunk the session already has.
+pub fn summarize(content: &str) -> String {
+    let first_line = content
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .unwrap_or("");
+    let mut summary: String = first_line.chars().take(SUMMARY_CHARS).collect();
+    if first_line.chars().count() > SUMMARY_CHARS || content.trim().lines().count() > 1 {
+        summary.push_str(" …");
+    }
+    summary.push_str(" (already returned in this session)");
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_and_record() {
+        let store = SessionStore::default();
+        assert_eq!(store.check_and_record("a", &[1, 2]), vec![false, false]);
+        assert_eq!(store.check_and_record("a", &[2, 3]), vec![true, false]);
+        // Sessions are independent
+        assert_eq!(store.check_and_record("b", &[1]), vec![false]);
+    }
+
+    #[test]
+    fn test_session_eviction() {
+        let store = SessionStore::default();
+        let start = Instant::now();
+        store.check_and_record_at("idle", &[1], start);
+
+        // Past the TTL an idle session starts over
+        let later = start + SESSION_TTL;
+        assert_eq!(store.check_and_record_at("idle", &[1], later), vec![false]);
+
+        // At the cap the least recently used session makes room
+        for i in 1..MAX_SESSIONS {
+            let at = later + Duration::from_secs(i as u64);
+            store.check_and_record_at(&i.to_string(), &[1], at);
+        }
+        let at = later + Duration::from_secs(MAX_SESSIONS as u64);
+        store.check_and_record_at("new", &[1], at);
+        let sessions = store.sessions.lock().unwrap();
+        assert_eq!(sessions.len(), MAX_SESSIONS);
+        assert!(!sessions.contains_key("idle"));
+        assert!(sessions.contains_key("1") && sessions.contains_key("new"));
+    }
+
+    #[test]
+    fn test_summarize() {
+        let summary = summarize("\nfn connect() {\n    pool.get()\n}\n");
+        assert_eq!(
+            summary,
+            "fn connect() { … (already returned in this session)"
+        );
+    }
+}
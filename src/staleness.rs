@@ -0,0 +1,136 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::paths::is_virtual_path;
+use crate::storage::db::SearchResult;
+
+/// Collection used for results whose metadata names none.
+pub const DEFAULT_COLLECTION: &str = "default";
+
+/// When a result should be flagged as stale.
+#[derive(Deserialize, Debug, Clone)]
+pub struct StalenessPolicy {
+    /// Flag results whose source was last modified more than this many days ago
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+    /// Flag results whose source file no longer exists on disk
+    #[serde(default = "default_true")]
+    pub flag_deleted: bool,
+}
+
+impl Default for StalenessPolicy {
+    fn default() -> Self {
+        Self {
+            max_age_days: None,
+            flag_deleted: true,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// `[staleness]` config: a default policy plus per-collection overrides in
+/// `[staleness.collections.<name>]`.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct StalenessConfig {
+    #[serde(flatten)]
+    pub default: StalenessPolicy,
+    #[serde(default)]
+    pub collections: HashMap<String, StalenessPolicy>,
+}
+
+impl StalenessConfig {
+    pub fn policy(&self, collection: &str) -> &StalenessPolicy {
+        self.collections.get(collection).unwrap_or(&self.default)
+    }
+}
+
+/// Why `result` is stale under its collection's policy, if it is.
+pub fn check(result: &SearchResult, config: &StalenessConfig, now: u64) -> Option<String> {
+    let collection = result_collection(result);
+    let policy = config.policy(&collection);
+
+    // Virtual documents such as transcripts have no file to lose
+    let on_disk = !is_virtual_path(&result.file_path);
+    if policy.flag_deleted && on_disk && !Path::new(&result.file_path).exists() {
+        return Some("source file no longer exists".to_string());
+    }
+
+    if let Some(max_days) = policy.max_age_days {
+        let age_days = now.saturating_sub(result.last_modified) / 86_400;
+        if age_days > max_days {
+            return Some(format!(
+                "last modified {} days ago (policy for '{}': {} days)",
+                age_days, collection, max_days
+            ));
+        }
+    }
+    None
+}
+
+/// The `collection` recorded in a result's metadata, or the default one.
+fn result_collection(result: &SearchResult) -> String {
+    result
+        .metadata
+        .as_deref()
+        .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+        .and_then(|m| {
+            m.get("collection")
+                .and_then(|c| c.as_str())
+                .map(String::from)
+        })
+        .unwrap_or_else(|| DEFAULT_COLLECTION.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 86_400;
+
+    fn result(path: &str, last_modified: u64, metadata: Option<&str>) -> SearchResult {
+        SearchResult {
+            file_path: path.to_string(),
+            last_modified,
+            metadata: metadata.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_policies() {
+        let config: StalenessConfig = toml::from_str(
+            r#"
+            max_age_days = 365
+            [collections.conversations]
+            max_age_days = 30
+            "#,
+        )
+        .unwrap();
+        let now = 1000 * DAY;
+        let this_file = file!();
+
+        assert!(check(&result(this_file, now - 100 * DAY, None), &config, now).is_none());
+        let reason = check(&result(this_file, now - 400 * DAY, None), &config, now).unwrap();
+        assert!(reason.contains("400 days"));
+
+        let transcript = result(
+            "conversation://c1",
+            now - 100 * DAY,
+            Some(r#"{"collection": "conversations"}"#),
+        );
+        let reason = check(&transcript, &config, now).unwrap();
+        assert!(reason.contains("'conversations': 30 days"));
+    }
+
+    #[test]
+    fn test_deleted_file() {
+        let config = StalenessConfig::default();
+        let reason = check(&result("/nonexistent/design.md", 0, None), &config, 0);
+        assert_eq!(reason.as_deref(), Some("source file no longer exists"));
+        assert!(check(&result("conversation://c1", 0, None), &config, 0).is_none());
+    }
+}
@@ -1,42 +1,329 @@
-use anyhow::Result;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
 use rusqlite::ffi::sqlite3_auto_extension;
-use rusqlite::{params, Connection, OptionalExtension};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use sqlite_vec::sqlite3_vec_init;
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Once;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::error::{Result, StorageError};
+use super::writer::Writer;
+
 static INIT_SQLITE_VEC: Once = Once::new();
 
+/// Length of the vectors `chunks_vec` stores; every model must produce it.
+pub const EMBEDDING_DIMENSIONS: usize = 384;
+
+/// Nearest chunks by [`Database::nearest_chunks`] scored exactly, per
+/// result asked for.
+const ANN_OVERSAMPLE: usize = 10;
+
+/// Fewest candidates an approximate search scores, however few results it
+/// asks for.
+const ANN_MIN_CANDIDATES: usize = 100;
+
+/// Most candidates sqlite-vec returns from one nearest-neighbor query.
+const ANN_MAX_CANDIDATES: usize = 4096;
+
+/// Read-only connections queries share.
+const READERS: usize = 4;
+
+/// How long opening the database waits for another process to release it.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a statement waits for a lock before failing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause between attempts to open a locked database.
+const LOCK_RETRY: Duration = Duration::from_millis(500);
+
+/// The index. Every write goes through one writer thread, while queries run
+/// on read-only connections and never wait behind indexing.
 #[derive(Clone)]
 pub struct Database {
+    /// The read-write connection, used by the writer thread, and for reads of
+    /// in-memory databases, which other connections can't see
     conn: Arc<Mutex<Connection>>,
+    writer: Writer,
+    readers: Arc<Vec<Mutex<Connection>>>,
+    next_reader: Arc<AtomicUsize>,
 }
 
 impl Database {
-    #[allow(clippy::missing_transmute_annotations)]
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        INIT_SQLITE_VEC.call_once(|| unsafe {
-            sqlite3_auto_extension(Some(std::mem::transmute(sqlite3_vec_init as *const ())));
-        });
+        Self::open(path.as_ref(), LOCK_TIMEOUT)
+    }
+
+    /// Open the database, waiting up to `lock_timeout` for another process
+    /// to release it. Corrupt and locked databases fail with an error
+    /// saying what to do.
+    pub fn open(path: &Path, lock_timeout: Duration) -> Result<Self> {
+        load_sqlite_vec();
+        let busy_timeout = lock_timeout.min(BUSY_TIMEOUT);
+        let deadline = Instant::now() + lock_timeout;
+        let conn = loop {
+            match Self::open_writer(path, busy_timeout) {
+                Ok(conn) => break conn,
+                Err(StorageError::Locked(_)) if Instant::now() < deadline => {
+                    log::warn!("{} is locked by another process; retrying", path.display());
+                    std::thread::sleep(LOCK_RETRY);
+                }
+                Err(e) => return Err(open_error(path, e)),
+            }
+        };
+
+        let mut readers = Vec::new();
+        if path.as_os_str() != ":memory:" {
+            for _ in 0..READERS {
+                let reader = Connection::open_with_flags(
+                    path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )?;
+                reader.busy_timeout(busy_timeout)?;
+                readers.push(Mutex::new(reader));
+            }
+        }
+        let conn = Arc::new(Mutex::new(conn));
+        Ok(Self {
+            writer: Writer::spawn(conn.clone())?,
+            conn,
+            readers: Arc::new(readers),
+            next_reader: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    fn open_writer(path: &Path, busy_timeout: Duration) -> Result<Connection> {
         let conn = Connection::open(path)?;
+        conn.busy_timeout(busy_timeout)?;
 
         // Enable foreign keys and WAL mode
         conn.execute("PRAGMA foreign_keys = ON;", [])?;
         let _mode: String = conn.query_row("PRAGMA journal_mode = WAL;", [], |row| row.get(0))?;
-        conn.execute_batch("PRAGMA busy_timeout = 5000;")?;
+        Self::init(&conn)?;
+        Ok(conn)
+    }
 
-        let db = Self {
-            conn: Arc::new(Mutex::new(conn)),
-        };
+    /// Problems `PRAGMA integrity_check` finds, or with `quick`, the faster
+    /// `quick_check` that skips verifying indexes against their tables.
+    /// Empty when the database is sound.
+    pub fn integrity_check(&self, quick: bool) -> Result<Vec<String>> {
+        // Checking the full-text index writes to it, so this can't run on a
+        // read-only connection
+        self.writer
+            .write_alone(move |conn| integrity_problems(conn, quick))
+    }
+
+    /// [`Self::integrity_check`] for a database file that isn't open, like a
+    /// backup or a database too damaged to open. Nothing is created or
+    /// initialized.
+    pub fn check_file(path: &Path) -> Result<Vec<String>> {
+        load_sqlite_vec();
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        integrity_problems(&conn, false)
+    }
+
+    /// Write a consistent copy of the database to `target`, which must not
+    /// exist, without stopping writers.
+    pub fn backup_to(&self, target: &Path) -> Result<()> {
+        let conn = self.reader();
+        conn.execute("VACUUM INTO ?1", params![target.to_string_lossy()])?;
+        Ok(())
+    }
+
+    /// Chunks and index entries breaking the invariants between the tables,
+    /// which SQLite's own integrity check can't know about.
+    pub fn check_consistency(&self) -> Result<Inconsistencies> {
+        let conn = self.reader();
+        let vector_tables = ["chunks_vec", "chunks_vec_bits"];
+        let mut missing_vectors = Vec::new();
+        let mut stray_vectors = Vec::new();
+        for table in vector_tables {
+            missing_vectors.extend(chunk_ids(
+                &conn,
+                &format!(
+                    "SELECT id FROM chunks WHERE embedding IS NOT NULL
+                       AND id NOT IN (SELECT chunk_id FROM {})",
+                    table
+                ),
+            )?);
+            stray_vectors.extend(chunk_ids(
+                &conn,
+                &format!(
+                    "SELECT chunk_id FROM {} WHERE chunk_id NOT IN
+                        (SELECT id FROM chunks WHERE embedding IS NOT NULL)",
+                    table
+                ),
+            )?);
+        }
+        for ids in [&mut missing_vectors, &mut stray_vectors] {
+            ids.sort_unstable();
+            ids.dedup();
+        }
+        Ok(Inconsistencies {
+            orphaned: chunk_ids(
+                &conn,
+                "SELECT c.id FROM chunks c LEFT JOIN files f ON f.id = c.file_id
+                 WHERE f.id IS NULL",
+            )?,
+            bad_embeddings: chunk_ids(
+                &conn,
+                &format!(
+                    "SELECT id FROM chunks WHERE length(embedding) != {}",
+                    EMBEDDING_DIMENSIONS * 4
+                ),
+            )?,
+            bad_offsets: chunk_ids(
+                &conn,
+                "SELECT id FROM chunks WHERE start_offset < 0 OR end_offset < start_offset",
+            )?,
+            missing_fts: chunk_ids(
+                &conn,
+                "SELECT id FROM chunks WHERE id NOT IN (SELECT rowid FROM chunks_fts)",
+            )?,
+            stray_fts: chunk_ids(
+                &conn,
+                "SELECT rowid FROM chunks_fts WHERE rowid NOT IN (SELECT id FROM chunks)",
+            )?,
+            missing_vectors,
+            stray_vectors,
+            truncated_files: Vec::new(),
+        })
+    }
+
+    /// Indexed files with the end of their last chunk and when they were
+    /// modified as last indexed, for checking offsets against the files.
+    pub fn chunk_extents(&self) -> Result<Vec<(String, u64, u64)>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT f.path, f.last_modified, MAX(c.end_offset)
+             FROM files f JOIN chunks c ON c.file_id = f.id
+             GROUP BY f.id",
+        )?;
+        let extents = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(extents)
+    }
+
+    /// Fix what `found` lists: remove orphaned chunks and stray index
+    /// entries, rebuild missing ones, and mark files with bad embeddings or
+    /// offsets for re-indexing, dropping the bad chunks and embeddings
+    /// meanwhile.
+    pub fn repair_consistency(&self, found: &Inconsistencies) -> Result<()> {
+        let json = |ids: &[i64]| serde_json::to_string(ids).unwrap();
+        let orphaned = json(&found.orphaned);
+        let bad_embeddings = json(&found.bad_embeddings);
+        let bad_offsets = json(&found.bad_offsets);
+        let missing_fts = json(&found.missing_fts);
+        let stray_fts = json(&found.stray_fts);
+        let missing_vectors = json(&found.missing_vectors);
+        let stray_vectors = json(&found.stray_vectors);
+        let truncated_files = serde_json::to_string(&found.truncated_files).unwrap();
+        self.writer.write(move |conn| {
+            conn.execute(
+                "UPDATE files SET last_indexed = NULL
+                 WHERE path IN (SELECT value FROM json_each(?1))
+                    OR id IN (SELECT file_id FROM chunks
+                              WHERE id IN (SELECT value FROM json_each(?2))
+                                 OR id IN (SELECT value FROM json_each(?3)))",
+                params![truncated_files, bad_embeddings, bad_offsets],
+            )?;
+            for (table, column) in [
+                ("chunks_vec", "chunk_id"),
+                ("chunks_vec_bits", "chunk_id"),
+                ("chunks_fts", "rowid"),
+                ("reembed_staging", "chunk_id"),
+                ("chunk_returns", "chunk_id"),
+                ("chunks", "id"),
+            ] {
+                conn.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE {} IN (SELECT value FROM json_each(?1))
+                            OR {1} IN (SELECT value FROM json_each(?2))",
+                        table, column
+                    ),
+                    params![orphaned, bad_offsets],
+                )?;
+            }
+            conn.execute(
+                "DELETE FROM chunks_fts WHERE rowid IN (SELECT value FROM json_each(?1))",
+                params![stray_fts],
+            )?;
+            conn.execute(
+                "INSERT INTO chunks_fts (rowid, content)
+                 SELECT id, content FROM chunks WHERE id IN (SELECT value FROM json_each(?1))",
+                params![missing_fts],
+            )?;
+            conn.execute(
+                "UPDATE chunks SET embedding = NULL
+                 WHERE id IN (SELECT value FROM json_each(?1))",
+                params![bad_embeddings],
+            )?;
+            for table in ["chunks_vec", "chunks_vec_bits"] {
+                conn.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE chunk_id IN (SELECT value FROM json_each(?1))
+                            OR chunk_id IN (SELECT value FROM json_each(?2))
+                            OR chunk_id IN (SELECT value FROM json_each(?3))",
+                        table
+                    ),
+                    params![stray_vectors, missing_vectors, bad_embeddings],
+                )?;
+            }
+            conn.execute(
+                "INSERT INTO chunks_vec (chunk_id, embedding)
+                 SELECT id, embedding FROM chunks
+                 WHERE id IN (SELECT value FROM json_each(?1)) AND embedding IS NOT NULL",
+                params![missing_vectors],
+            )?;
+            conn.execute(
+                "INSERT INTO chunks_vec_bits (chunk_id, embedding)
+                 SELECT id, vec_quantize_binary(embedding) FROM chunks
+                 WHERE id IN (SELECT value FROM json_each(?1)) AND embedding IS NOT NULL",
+                params![missing_vectors],
+            )?;
+            Ok(())
+        })
+    }
 
-        db.init()?;
-        Ok(db)
+    /// Run `f` on tokio's blocking pool, so async handlers don't stall the
+    /// runtime while SQLite works. Blocking callers like the CLI use the
+    /// methods directly.
+    pub async fn call<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        T: Send + 'static,
+        E: From<StorageError> + Send + 'static,
+        F: FnOnce(&Database) -> Result<T, E> + Send + 'static,
+    {
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || f(&db))
+            .await
+            .map_err(|e| StorageError::Task(e.to_string()))?
     }
 
-    fn init(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    /// A connection to query on: an idle reader if there is one.
+    fn reader(&self) -> MutexGuard<'_, Connection> {
+        if self.readers.is_empty() {
+            return self.conn.lock().unwrap();
+        }
+        for reader in self.readers.iter() {
+            if let Ok(conn) = reader.try_lock() {
+                return conn;
+            }
+        }
+        let next = self.next_reader.fetch_add(1, Ordering::Relaxed);
+        self.readers[next % self.readers.len()].lock().unwrap()
+    }
 
+    fn init(conn: &Connection) -> Result<()> {
         conn.execute(
             "CREATE TABLE IF NOT EXISTS files (
                 id INTEGER PRIMARY KEY,
@@ -55,10 +342,15 @@ impl Database {
                 end_offset INTEGER NOT NULL,
                 content TEXT NOT NULL,
                 embedding BLOB,
-                metadata TEXT
+                metadata TEXT,
+                start_line INTEGER,
+                end_line INTEGER,
+                collection TEXT
             )",
             [],
         )?;
+        add_missing_columns(conn, "chunks", &["start_line INTEGER", "end_line INTEGER"])?;
+        add_collection_column(conn, "chunks")?;
 
         conn.execute(
             "CREATE INDEX IF NOT EXISTS idx_files_path ON files(path)",
@@ -69,14 +361,52 @@ impl Database {
             "CREATE INDEX IF NOT EXISTS idx_chunks_file_id ON chunks(file_id)",
             [],
         )?;
+        // The symbol a code chunk defines, for the `symbol` search filter
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_chunks_symbol
+             ON chunks(json_extract(metadata, '$.symbol'))",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_chunks_collection ON chunks(collection)",
+            [],
+        )?;
 
         conn.execute(
-            "CREATE VIRTUAL TABLE IF NOT EXISTS chunks_vec USING vec0(
-                chunk_id INTEGER PRIMARY KEY,
-                embedding float[384]
-            )",
+            &format!(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS chunks_vec USING vec0(
+                    chunk_id INTEGER PRIMARY KEY,
+                    embedding float[{}]
+                )",
+                EMBEDDING_DIMENSIONS
+            ),
+            [],
+        )?;
+
+        // One sign bit per dimension, for approximate nearest-neighbor
+        // search. Filled from `chunks_vec` when first created.
+        let has_bits: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE name = 'chunks_vec_bits')",
             [],
+            |row| row.get(0),
         )?;
+        if !has_bits {
+            conn.execute(
+                &format!(
+                    "CREATE VIRTUAL TABLE chunks_vec_bits USING vec0(
+                        chunk_id INTEGER PRIMARY KEY,
+                        embedding bit[{}]
+                    )",
+                    EMBEDDING_DIMENSIONS
+                ),
+                [],
+            )?;
+            conn.execute(
+                "INSERT INTO chunks_vec_bits (chunk_id, embedding)
+                 SELECT chunk_id, vec_quantize_binary(embedding) FROM chunks_vec",
+                [],
+            )?;
+        }
 
         // FTS5 Virtual Table
         // We use the same rowid as the chunks table for easy joining
@@ -95,171 +425,2104 @@ impl Database {
             [],
         )?;
 
-        Ok(())
-    }
+        // User tags: on a file or directory prefix, or on a single chunk
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS path_tags (
+                path TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (path, tag)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS chunk_tags (
+                chunk_id INTEGER NOT NULL REFERENCES chunks(id) ON DELETE CASCADE,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (chunk_id, tag)
+            )",
+            [],
+        )?;
 
-    pub fn add_or_update_file(&self, path: &str, last_modified: u64) -> Result<i64> {
-        let conn = self.conn.lock().unwrap();
+        // Most recent failure to index each path, cleared on success
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS index_errors (
+                path TEXT PRIMARY KEY,
+                error TEXT NOT NULL,
+                failed_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
 
-        // Upsert file
+        // Time spent in each indexing stage on the last index of each path
         conn.execute(
-            "INSERT INTO files (path, last_modified, last_indexed)
-             VALUES (?1, ?2, NULL)
-             ON CONFLICT(path) DO UPDATE SET
-                last_modified = ?2,
-                last_indexed = NULL",
-            params![path, last_modified],
+            "CREATE TABLE IF NOT EXISTS index_timings (
+                path TEXT PRIMARY KEY,
+                chunking_us INTEGER NOT NULL,
+                embedding_us INTEGER NOT NULL,
+                storage_us INTEGER NOT NULL,
+                indexed_at INTEGER NOT NULL
+            )",
+            [],
         )?;
 
-        let id = conn.query_row(
-            "SELECT id FROM files WHERE path = ?1",
-            params![path],
-            |row| row.get(0),
+        // Project roots registered by `contextd index` or MCP clients
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS workspaces (
+                path TEXT PRIMARY KEY,
+                registered_at INTEGER NOT NULL
+            )",
+            [],
         )?;
 
-        Ok(id)
-    }
+        // Embedding model each collection was indexed with
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS collection_models (
+                name TEXT PRIMARY KEY,
+                model TEXT NOT NULL,
+                dimensions INTEGER NOT NULL,
+                recorded_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
 
-    #[allow(dead_code)]
-    pub fn get_file_id(&self, path: &str) -> Result<Option<i64>> {
-        let conn = self.conn.lock().unwrap();
-        let id = conn
-            .query_row(
-                "SELECT id FROM files WHERE path = ?1",
-                params![path],
-                |row| row.get(0),
-            )
-            .optional()?;
-        Ok(id)
-    }
+        // New embeddings of chunks whose collection is switching models
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS reembed_staging (
+                chunk_id INTEGER PRIMARY KEY,
+                embedding BLOB NOT NULL
+            )",
+            [],
+        )?;
 
-    pub fn mark_indexed(&self, file_id: i64) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        // Indexing settings each file was last indexed with
         conn.execute(
-            "UPDATE files SET last_indexed = strftime('%s', 'now') WHERE id = ?1",
-            params![file_id],
+            "CREATE TABLE IF NOT EXISTS index_settings (
+                path TEXT PRIMARY KEY,
+                fingerprint TEXT NOT NULL
+            )",
+            [],
         )?;
-        Ok(())
-    }
 
-    pub fn needs_reindexing(&self, path: &str, current_modified: u64) -> Result<bool> {
-        let conn = self.conn.lock().unwrap();
-        let last_indexed: Option<Option<u64>> = conn
-            .query_row(
-                "SELECT last_indexed FROM files WHERE path = ?1",
-                params![path],
-                |row| row.get(0),
-            )
-            .optional()?;
+        // Hash of each file's content when it was last indexed, so a file
+        // whose mtime changed but content didn't is not re-embedded
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS content_hashes (
+                path TEXT PRIMARY KEY,
+                hash TEXT NOT NULL
+            )",
+            [],
+        )?;
 
-        match last_indexed {
-            Some(Some(ts)) => Ok(current_modified > ts),
-            Some(None) => Ok(true), // File exists but never indexed
-            None => Ok(true),       // File doesn't exist in DB
-        }
-    }
+        // Local usage analytics for `contextd report`: queries per day and
+        // interface, the directories results come from, and daily index size
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS query_stats (
+                day TEXT NOT NULL,
+                source TEXT NOT NULL,
+                queries INTEGER NOT NULL,
+                failed INTEGER NOT NULL,
+                total_us INTEGER NOT NULL,
+                PRIMARY KEY (day, source)
+            )",
+            [],
+        )?;
+        // The same per API user of a shared daemon, for quotas
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS user_query_stats (
+                day TEXT NOT NULL,
+                user TEXT NOT NULL,
+                queries INTEGER NOT NULL,
+                failed INTEGER NOT NULL,
+                total_us INTEGER NOT NULL,
+                PRIMARY KEY (day, user)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS query_areas (
+                area TEXT PRIMARY KEY,
+                hits INTEGER NOT NULL,
+                last_hit INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS index_history (
+                day TEXT PRIMARY KEY,
+                files INTEGER NOT NULL,
+                chunks INTEGER NOT NULL,
+                db_size INTEGER NOT NULL
+            )",
+            [],
+        )?;
 
-    pub fn clear_chunks(&self, file_id: i64) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        // Delete from vec0 first
+        // Every version of every chunk while `[history]` is on: `id` is the
+        // chunk's id, and the version was current from `valid_from` until
+        // `valid_to` (NULL while it still is)
         conn.execute(
-            "DELETE FROM chunks_vec WHERE chunk_id IN (SELECT id FROM chunks WHERE file_id = ?1)",
-            params![file_id],
+            "CREATE TABLE IF NOT EXISTS chunk_history (
+                id INTEGER NOT NULL,
+                path TEXT NOT NULL,
+                last_modified INTEGER NOT NULL,
+                start_offset INTEGER NOT NULL,
+                end_offset INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                embedding BLOB,
+                metadata TEXT,
+                valid_from INTEGER NOT NULL,
+                valid_to INTEGER,
+                collection TEXT
+            )",
+            [],
         )?;
-        // Delete from FTS first (using subquery)
+        add_collection_column(conn, "chunk_history")?;
         conn.execute(
-            "DELETE FROM chunks_fts WHERE rowid IN (SELECT id FROM chunks WHERE file_id = ?1)",
-            params![file_id],
+            "CREATE INDEX IF NOT EXISTS idx_chunk_history_id ON chunk_history(id, valid_to)",
+            [],
         )?;
-        conn.execute("DELETE FROM chunks WHERE file_id = ?1", params![file_id])?;
-        Ok(())
-    }
 
-    pub fn add_chunk(
-        &self,
-        file_id: i64,
-        start: u64,
-        end: u64,
-        content: &str,
-        embedding: Option<&[f32]>,
-        metadata: Option<&str>,
-    ) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        // Append-only log of every file and chunk added, changed or removed,
+        // written by triggers so no writer can skip it. `seq` only grows.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS index_changes (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                at INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                op TEXT NOT NULL,
+                path TEXT NOT NULL,
+                chunk_id INTEGER
+            )",
+            [],
+        )?;
+        conn.execute_batch(
+            "CREATE TRIGGER IF NOT EXISTS index_changes_file_add AFTER INSERT ON files BEGIN
+                INSERT INTO index_changes (at, kind, op, path)
+                VALUES (strftime('%s', 'now'), 'file', 'add', NEW.path);
+             END;
+             CREATE TRIGGER IF NOT EXISTS index_changes_file_update
+             AFTER UPDATE OF last_modified ON files
+             WHEN OLD.last_modified IS NOT NEW.last_modified BEGIN
+                INSERT INTO index_changes (at, kind, op, path)
+                VALUES (strftime('%s', 'now'), 'file', 'update', NEW.path);
+             END;
+             CREATE TRIGGER IF NOT EXISTS index_changes_file_delete AFTER DELETE ON files BEGIN
+                INSERT INTO index_changes (at, kind, op, path)
+                VALUES (strftime('%s', 'now'), 'file', 'delete', OLD.path);
+             END;
+             CREATE TRIGGER IF NOT EXISTS index_changes_file_rename
+             AFTER UPDATE OF path ON files
+             WHEN OLD.path IS NOT NEW.path BEGIN
+                INSERT INTO index_changes (at, kind, op, path, chunk_id)
+                SELECT strftime('%s', 'now'), 'chunk', 'delete', OLD.path, id
+                FROM chunks WHERE file_id = NEW.id;
+                INSERT INTO index_changes (at, kind, op, path)
+                VALUES (strftime('%s', 'now'), 'file', 'delete', OLD.path);
+                INSERT INTO index_changes (at, kind, op, path)
+                VALUES (strftime('%s', 'now'), 'file', 'add', NEW.path);
+                INSERT INTO index_changes (at, kind, op, path, chunk_id)
+                SELECT strftime('%s', 'now'), 'chunk', 'add', NEW.path, id
+                FROM chunks WHERE file_id = NEW.id;
+             END;
+             CREATE TRIGGER IF NOT EXISTS index_changes_chunk_add AFTER INSERT ON chunks BEGIN
+                INSERT INTO index_changes (at, kind, op, path, chunk_id)
+                SELECT strftime('%s', 'now'), 'chunk', 'add', path, NEW.id
+                FROM files WHERE id = NEW.file_id;
+             END;
+             CREATE TRIGGER IF NOT EXISTS index_changes_chunk_update
+             AFTER UPDATE OF content, embedding, metadata ON chunks BEGIN
+                INSERT INTO index_changes (at, kind, op, path, chunk_id)
+                SELECT strftime('%s', 'now'), 'chunk', 'update', path, NEW.id
+                FROM files WHERE id = NEW.file_id;
+             END;
+             CREATE TRIGGER IF NOT EXISTS index_changes_chunk_delete
+             BEFORE DELETE ON chunks BEGIN
+                INSERT INTO index_changes (at, kind, op, path, chunk_id)
+                SELECT strftime('%s', 'now'), 'chunk', 'delete', path, OLD.id
+                FROM files WHERE id = OLD.file_id;
+             END;",
+        )?;
 
-        let embedding_bytes = if let Some(emb) = embedding {
-            // Convert &[f32] to bytes (little endian)
-            let mut bytes = Vec::with_capacity(emb.len() * 4);
-            for val in emb {
-                bytes.extend_from_slice(&val.to_le_bytes());
-            }
-            Some(bytes)
-        } else {
-            None
-        };
+        // Files evicted by a collection's retention limits, kept out of the
+        // index until they change
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS evictions (
+                path TEXT PRIMARY KEY,
+                collection TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                chunks INTEGER NOT NULL,
+                evicted_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
 
+        // Chunks no query returned for a while, out of the searched tables with
+        // their content and embedding compressed
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS chunks_archive (
+                id INTEGER PRIMARY KEY,
+                file_id INTEGER NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+                start_offset INTEGER NOT NULL,
+                end_offset INTEGER NOT NULL,
+                metadata TEXT,
+                data BLOB NOT NULL,
+                archived_at INTEGER NOT NULL,
+                start_line INTEGER,
+                end_line INTEGER,
+                collection TEXT
+            )",
+            [],
+        )?;
+        add_missing_columns(
+            conn,
+            "chunks_archive",
+            &["start_line INTEGER", "end_line INTEGER"],
+        )?;
+        add_collection_column(conn, "chunks_archive")?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_chunks_archive_file_id ON chunks_archive(file_id)",
+            [],
+        )?;
+        // When each chunk was last returned by a query and how often, for
+        // archiving and the heat map
         conn.execute(
-            "INSERT INTO chunks (file_id, start_offset, end_offset, content, embedding, metadata)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![file_id, start, end, content, embedding_bytes, metadata],
+            "CREATE TABLE IF NOT EXISTS chunk_returns (
+                chunk_id INTEGER PRIMARY KEY,
+                last_returned INTEGER NOT NULL,
+                returns INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
         )?;
 
-        let chunk_id = conn.last_insert_rowid();
+        // Tags already reported by a digest, so the next one lists only new ones
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS digest_tags (
+                source TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (source, tag)
+            )",
+            [],
+        )?;
 
-        // Insert into vec0
-        if let Some(emb_bytes) = &embedding_bytes {
-            conn.execute(
-                "INSERT INTO chunks_vec (chunk_id, embedding) VALUES (?1, ?2)",
-                params![chunk_id, emb_bytes.as_slice()],
-            )?;
-        }
+        // The daemon's startup scan, kept here so `contextd mcp` processes
+        // can tell queries are running against an index still filling up
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS scan_progress (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                started_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL,
+                discovered INTEGER NOT NULL,
+                indexed INTEGER NOT NULL,
+                finished INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        // The last directory the scan has indexed everything up to, so one
+        // cut short resumes from there
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS scan_checkpoint (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                directory TEXT NOT NULL
+            )",
+            [],
+        )?;
 
-        // Insert into FTS
+        // How far behind the files the running daemon is, refreshed every
+        // few seconds while it runs
         conn.execute(
-            "INSERT INTO chunks_fts (rowid, content) VALUES (?1, ?2)",
-            params![chunk_id, content],
+            "CREATE TABLE IF NOT EXISTS watch_status (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                updated_at INTEGER NOT NULL,
+                last_event_at INTEGER,
+                pending INTEGER NOT NULL
+            )",
+            [],
         )?;
+
         Ok(())
     }
 
-    /// Get database statistics
-    pub fn get_stats(&self) -> Result<DbStats> {
-        let conn = self.conn.lock().unwrap();
-
-        let file_count: u64 = conn.query_row("SELECT COUNT(*) FROM files", [], |row| row.get(0))?;
-
-        let chunk_count: u64 =
-            conn.query_row("SELECT COUNT(*) FROM chunks", [], |row| row.get(0))?;
+    pub fn add_or_update_file(&self, path: &str, last_modified: u64) -> Result<i64> {
+        let path = path.to_string();
+        self.writer.write(move |conn| {
+            // Upsert file
+            conn.execute(
+                "INSERT INTO files (path, last_modified, last_indexed)
+                 VALUES (?1, ?2, NULL)
+                 ON CONFLICT(path) DO UPDATE SET
+                    last_modified = ?2,
+                    last_indexed = NULL",
+                params![path, last_modified],
+            )?;
 
-        // Get database page count and page size for size estimate
-        let page_count: u64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
-        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
-        let db_size = page_count * page_size;
+            let id = conn.query_row(
+                "SELECT id FROM files WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )?;
 
-        Ok(DbStats {
-            file_count,
-            chunk_count,
-            db_size,
+            Ok(id)
         })
     }
 
-    /// Record a search hit for a file (for frequency ranking)
-    /// Call this after returning search results to boost frequently accessed files
     #[allow(dead_code)]
-    pub fn record_search_hit(&self, file_id: i64) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        conn.execute(
-            "INSERT INTO query_hits (file_id, hit_count, last_hit)
-             VALUES (?1, 1, ?2)
-             ON CONFLICT(file_id) DO UPDATE SET
-                hit_count = hit_count + 1,
-                last_hit = ?2",
-            params![file_id, now],
-        )?;
-        Ok(())
+    pub fn get_file_id(&self, path: &str) -> Result<Option<i64>> {
+        let conn = self.reader();
+        let id = conn
+            .query_row(
+                "SELECT id FROM files WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(id)
+    }
+
+    /// Look up an indexed file by its exact stored path.
+    pub fn get_file(&self, path: &str) -> Result<Option<FileRecord>> {
+        let conn = self.reader();
+        let file = conn
+            .query_row(
+                "SELECT id, path, last_modified, last_indexed FROM files WHERE path = ?1",
+                params![path],
+                |row| {
+                    Ok(FileRecord {
+                        id: row.get(0)?,
+                        path: row.get(1)?,
+                        last_modified: row.get(2)?,
+                        last_indexed: row.get(3)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(file)
+    }
+
+    /// Find indexed files whose stored path ends with the whole segments of
+    /// `suffix`. Leading `./` is ignored on both sides.
+    pub fn find_files_by_suffix(&self, suffix: &str) -> Result<Vec<FileRecord>> {
+        let suffix = suffix.trim_start_matches("./");
+        if suffix.is_empty() {
+            return Ok(Vec::new());
+        }
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT id, path, last_modified, last_indexed FROM files
+             WHERE (CASE WHEN substr(path, 1, 2) = './' THEN substr(path, 3) ELSE path END) = ?1
+                OR substr(path, -length(?1) - 1) = '/' || ?1
+             ORDER BY path",
+        )?;
+        let files = stmt
+            .query_map(params![suffix], |row| {
+                Ok(FileRecord {
+                    id: row.get(0)?,
+                    path: row.get(1)?,
+                    last_modified: row.get(2)?,
+                    last_indexed: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(files)
+    }
+
+    /// All indexed files, ordered by path.
+    pub fn list_files(&self) -> Result<Vec<FileRecord>> {
+        let conn = self.reader();
+        let mut stmt =
+            conn.prepare("SELECT id, path, last_modified, last_indexed FROM files ORDER BY path")?;
+        let files = stmt
+            .query_map([], |row| {
+                Ok(FileRecord {
+                    id: row.get(0)?,
+                    path: row.get(1)?,
+                    last_modified: row.get(2)?,
+                    last_indexed: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(files)
+    }
+
+    /// Every indexed file with its chunk count and the tags reaching it,
+    /// either through a path tag or a tag on one of its chunks.
+    pub fn file_stats(&self) -> Result<Vec<FileStats>> {
+        let conn = self.reader();
+        let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT f.path, pt.tag FROM files f JOIN path_tags pt ON {}
+                 UNION
+                 SELECT f.path, ct.tag FROM chunk_tags ct
+                   JOIN chunks c ON c.id = ct.chunk_id
+                   JOIN files f ON f.id = c.file_id",
+            PATH_TAG_MATCH
+        ))?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (path, tag): (String, String) = row?;
+            tags.entry(path).or_default().push(tag);
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT f.path, f.last_indexed, COUNT(c.id)
+             FROM files f LEFT JOIN chunks c ON c.file_id = f.id
+             GROUP BY f.id ORDER BY f.path",
+        )?;
+        let stats = stmt
+            .query_map([], |row| {
+                let path: String = row.get(0)?;
+                Ok(FileStats {
+                    tags: tags.remove(&path).unwrap_or_default(),
+                    path,
+                    last_indexed: row.get(1)?,
+                    chunks: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(stats)
+    }
+
+    /// Number of chunks stored for a file.
+    pub fn count_chunks(&self, file_id: i64) -> Result<u64> {
+        let conn = self.reader();
+        let count = conn.query_row(
+            "SELECT COUNT(*) FROM chunks WHERE file_id = ?1",
+            params![file_id],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    /// Remove a file and all of its chunks from the index.
+    pub fn delete_file(&self, file_id: i64) -> Result<()> {
+        self.writer.write(move |conn| {
+            clear_chunks(conn, file_id)?;
+            for table in ["index_settings", "content_hashes"] {
+                conn.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE path = (SELECT path FROM files WHERE id = ?1)",
+                        table
+                    ),
+                    params![file_id],
+                )?;
+            }
+            conn.execute("DELETE FROM files WHERE id = ?1", params![file_id])?;
+            Ok(())
+        })
+    }
+
+    /// Remove the file indexed at `path` with its chunks and everything
+    /// recorded about it. Returns whether it was indexed.
+    pub fn remove_file(&self, path: &str) -> Result<bool> {
+        let path = path.to_string();
+        self.writer.write(move |conn| remove_file(conn, &path))
+    }
+
+    /// Remove the file indexed at `path` like [`Database::remove_file`],
+    /// recording that the retention limits of `collection` evicted it for
+    /// `reason`. Returns whether it was indexed.
+    pub fn evict_file(&self, path: &str, collection: &str, reason: &str) -> Result<bool> {
+        let (path, collection, reason) =
+            (path.to_string(), collection.to_string(), reason.to_string());
+        self.writer.write(move |conn| {
+            let chunks: u64 = conn.query_row(
+                "SELECT COUNT(*) FROM chunks c JOIN files f ON c.file_id = f.id WHERE f.path = ?1",
+                params![path],
+                |row| row.get(0),
+            )?;
+            if !remove_file(conn, &path)? {
+                return Ok(false);
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO evictions (path, collection, reason, chunks, evicted_at)
+                 VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'))",
+                params![path, collection, reason, chunks],
+            )?;
+            Ok(true)
+        })
+    }
+
+    /// Why `path` was evicted, if it was.
+    pub fn eviction(&self, path: &str) -> Result<Option<Eviction>> {
+        let conn = self.reader();
+        Ok(conn
+            .query_row(
+                "SELECT path, collection, reason, chunks, evicted_at FROM evictions
+                 WHERE path = ?1",
+                params![path],
+                eviction_from_row,
+            )
+            .optional()?)
+    }
+
+    /// The `limit` most recent evictions, newest first.
+    pub fn recent_evictions(&self, limit: usize) -> Result<Vec<Eviction>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT path, collection, reason, chunks, evicted_at FROM evictions
+             ORDER BY evicted_at DESC, path LIMIT ?1",
+        )?;
+        let evictions = stmt
+            .query_map(params![limit as i64], eviction_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(evictions)
+    }
+
+    /// The files holding chunks of the configured collection `name`, with
+    /// what they take up.
+    pub fn collection_files(&self, name: &str) -> Result<Vec<CollectionFile>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT f.path, f.last_modified, MAX(COALESCE(qh.last_hit, 0), f.last_modified),
+                    COUNT(*),
+                    SUM(length(c.content) + COALESCE(length(c.embedding), 0)
+                        + COALESCE(length(c.metadata), 0))
+             FROM chunks c
+             JOIN files f ON c.file_id = f.id
+             LEFT JOIN query_hits qh ON qh.file_id = f.id
+             WHERE c.collection = ?1
+             GROUP BY f.id
+             ORDER BY f.id",
+        )?;
+        let files = stmt
+            .query_map(params![name], |row| {
+                Ok(CollectionFile {
+                    path: row.get(0)?,
+                    last_modified: row.get(1)?,
+                    last_used: row.get(2)?,
+                    chunks: row.get(3)?,
+                    bytes: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(files)
+    }
+
+    /// Move the file indexed at `from` to `to`, keeping its chunks, their
+    /// ids and everything recorded about it. A file already indexed at `to`
+    /// is replaced. Returns whether `from` was indexed.
+    pub fn rename_file(&self, from: &str, to: &str) -> Result<bool> {
+        let (from, to) = (from.to_string(), to.to_string());
+        self.writer.write(move |conn| {
+            let file_id: Option<i64> = conn
+                .query_row(
+                    "SELECT id FROM files WHERE path = ?1",
+                    params![from],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let Some(file_id) = file_id else {
+                return Ok(false);
+            };
+            let replaced: Option<i64> = conn
+                .query_row("SELECT id FROM files WHERE path = ?1", params![to], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+            if let Some(replaced) = replaced {
+                clear_chunks(conn, replaced)?;
+                conn.execute("DELETE FROM files WHERE id = ?1", params![replaced])?;
+            }
+            for table in [
+                "index_settings",
+                "index_errors",
+                "index_timings",
+                "content_hashes",
+            ] {
+                conn.execute(
+                    &format!("DELETE FROM {} WHERE path = ?1", table),
+                    params![to],
+                )?;
+                conn.execute(
+                    &format!("UPDATE {} SET path = ?2 WHERE path = ?1", table),
+                    params![from, to],
+                )?;
+            }
+            // Tags on the file itself; those on a directory still reach it
+            // if it's below one
+            conn.execute(
+                "UPDATE OR IGNORE path_tags SET path = ?2 WHERE path = ?1",
+                params![from, to],
+            )?;
+            conn.execute("DELETE FROM path_tags WHERE path = ?1", params![from])?;
+            conn.execute(
+                "UPDATE files SET path = ?2 WHERE id = ?1",
+                params![file_id, to],
+            )?;
+            Ok(true)
+        })
+    }
+
+    /// All chunks stored for a file, in offset order.
+    pub fn get_chunks(&self, file_id: i64) -> Result<Vec<StoredChunk>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT id, start_offset, end_offset, content, metadata, embedding IS NOT NULL
+             FROM chunks WHERE file_id = ?1 ORDER BY start_offset, id",
+        )?;
+        let chunks = stmt
+            .query_map(params![file_id], |row| {
+                Ok(StoredChunk {
+                    id: row.get(0)?,
+                    start: row.get(1)?,
+                    end: row.get(2)?,
+                    content: row.get(3)?,
+                    metadata: row.get(4)?,
+                    has_embedding: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(chunks)
+    }
+
+    /// A single chunk by id, with the file it belongs to.
+    pub fn get_chunk(&self, chunk_id: i64) -> Result<Option<ChunkRecord>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT c.id, c.start_offset, c.end_offset, c.content, c.metadata,
+                    c.embedding IS NOT NULL, f.path, f.last_modified
+             FROM chunks c JOIN files f ON c.file_id = f.id
+             WHERE c.id = ?1",
+        )?;
+        let mut rows = stmt.query_map(params![chunk_id], |row| {
+            Ok(ChunkRecord {
+                chunk: StoredChunk {
+                    id: row.get(0)?,
+                    start: row.get(1)?,
+                    end: row.get(2)?,
+                    content: row.get(3)?,
+                    metadata: row.get(4)?,
+                    has_embedding: row.get(5)?,
+                },
+                file_path: row.get(6)?,
+                last_modified: row.get(7)?,
+            })
+        })?;
+        Ok(rows.next().transpose()?)
+    }
+
+    pub fn mark_indexed(&self, file_id: i64) -> Result<()> {
+        self.writer.write(move |conn| {
+            conn.execute(
+                "UPDATE files SET last_indexed = strftime('%s', 'now') WHERE id = ?1",
+                params![file_id],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn needs_reindexing(&self, path: &str, current_modified: u64) -> Result<bool> {
+        let last_indexed: Option<Option<u64>> = self
+            .reader()
+            .query_row(
+                "SELECT last_indexed FROM files WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match last_indexed {
+            Some(Some(ts)) => Ok(current_modified > ts),
+            Some(None) => Ok(true), // File exists but never indexed
+            // Not in the index: evicted files stay out until they change
+            None => Ok(self
+                .eviction(path)?
+                .is_none_or(|e| current_modified > e.evicted_at)),
+        }
+    }
+
+    pub fn clear_chunks(&self, file_id: i64) -> Result<()> {
+        self.writer.write(move |conn| clear_chunks(conn, file_id))
+    }
+
+    pub fn add_chunk(
+        &self,
+        file_id: i64,
+        start: u64,
+        end: u64,
+        content: &str,
+        embedding: Option<&[f32]>,
+        metadata: Option<&str>,
+    ) -> Result<()> {
+        let span = ChunkSpan {
+            start,
+            end,
+            lines: None,
+        };
+        self.add_chunk_spanning(file_id, span, content, embedding, metadata)
+    }
+
+    /// [`Database::add_chunk`], recording the lines the chunk spans.
+    pub fn add_chunk_spanning(
+        &self,
+        file_id: i64,
+        span: ChunkSpan,
+        content: &str,
+        embedding: Option<&[f32]>,
+        metadata: Option<&str>,
+    ) -> Result<()> {
+        let content = content.to_string();
+        let metadata = metadata.map(String::from);
+        let embedding_bytes = embedding.map(encode_embedding);
+        self.writer.write(move |conn| {
+            insert_chunk(
+                conn,
+                None,
+                (file_id, span),
+                &content,
+                embedding_bytes.as_deref(),
+                metadata.as_deref(),
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Move up to `limit` chunks that no query has returned since `cutoff`
+    /// (seconds since the epoch), nor since their file was indexed, to the
+    /// archive. Archived chunks are searched only with
+    /// [`SearchOptions::include_archived`]. Tagged chunks and those without an
+    /// embedding stay. Returns how many were archived.
+    pub fn archive_chunks(&self, cutoff: u64, limit: usize) -> Result<usize> {
+        self.writer.write(move |conn| {
+            type Row = (i64, i64, ChunkSpan, String, Vec<u8>, Option<String>);
+            let rows: Vec<Row> = conn
+                .prepare(
+                    "SELECT c.id, c.file_id, c.start_offset, c.end_offset, c.start_line,
+                            c.end_line, c.content, c.embedding, c.metadata
+                     FROM chunks c
+                     JOIN files f ON c.file_id = f.id
+                     LEFT JOIN chunk_returns r ON r.chunk_id = c.id
+                     WHERE c.embedding IS NOT NULL
+                       AND COALESCE(r.last_returned, f.last_indexed, 0) < ?1
+                       AND c.id NOT IN (SELECT chunk_id FROM chunk_tags)
+                     LIMIT ?2",
+                )?
+                .query_map(params![cutoff, limit as i64], |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        chunk_span(row, 2)?,
+                        row.get(6)?,
+                        row.get(7)?,
+                        row.get(8)?,
+                    ))
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            for (id, file_id, span, content, embedding, metadata) in &rows {
+                let (start_line, end_line) = span.lines.unzip();
+                conn.execute(
+                    "INSERT INTO chunks_archive
+                        (id, file_id, start_offset, end_offset, start_line, end_line, metadata,
+                         data, archived_at, collection)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, strftime('%s', 'now'),
+                             json_extract(?7, '$.collection'))",
+                    params![
+                        id,
+                        file_id,
+                        span.start,
+                        span.end,
+                        start_line,
+                        end_line,
+                        metadata,
+                        pack_archived(content, embedding)?
+                    ],
+                )?;
+                for (table, column) in [
+                    ("chunks_vec", "chunk_id"),
+                    ("chunks_vec_bits", "chunk_id"),
+                    ("chunks_fts", "rowid"),
+                    ("reembed_staging", "chunk_id"),
+                    ("chunks", "id"),
+                ] {
+                    conn.execute(
+                        &format!("DELETE FROM {} WHERE {} = ?1", table, column),
+                        params![id],
+                    )?;
+                }
+            }
+            Ok(rows.len())
+        })
+    }
+
+    /// Move every archived chunk back to the searched tables, e.g. before
+    /// re-embedding them with a new model. Returns how many there were.
+    pub fn restore_archived(&self) -> Result<usize> {
+        self.writer.write(|conn| restore_archived(conn, None))
+    }
+
+    /// Record that queries returned the chunks `chunk_ids`. Archived ones
+    /// among them move back to the searched tables.
+    pub fn mark_chunks_returned(&self, chunk_ids: &[i64]) -> Result<()> {
+        let ids = serde_json::to_string(chunk_ids).unwrap_or_default();
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT INTO chunk_returns (chunk_id, last_returned, returns)
+                 SELECT value, strftime('%s', 'now'), 1 FROM json_each(?1) WHERE true
+                 ON CONFLICT(chunk_id) DO UPDATE SET
+                    last_returned = excluded.last_returned,
+                    returns = returns + 1",
+                params![ids],
+            )?;
+            restore_archived(conn, Some(&ids))?;
+            Ok(())
+        })
+    }
+
+    /// The `limit` files whose chunks queries returned most often, each with
+    /// its `per_file` most returned chunks.
+    pub fn chunk_heat(&self, limit: usize, per_file: usize) -> Result<Vec<FileHeat>> {
+        let conn = self.reader();
+        let mut files: Vec<FileHeat> = conn
+            .prepare(
+                "SELECT f.id, f.path, SUM(r.returns), COUNT(r.chunk_id),
+                        (SELECT COUNT(*) FROM chunks WHERE file_id = f.id),
+                        MAX(r.last_returned)
+                 FROM files f
+                 JOIN chunks c ON c.file_id = f.id
+                 JOIN chunk_returns r ON r.chunk_id = c.id
+                 WHERE r.returns > 0
+                 GROUP BY f.id
+                 ORDER BY SUM(r.returns) DESC, MAX(r.last_returned) DESC
+                 LIMIT ?1",
+            )?
+            .query_map(params![limit as i64], |row| {
+                Ok(FileHeat {
+                    file_id: row.get(0)?,
+                    path: row.get(1)?,
+                    returns: row.get(2)?,
+                    chunks_returned: row.get(3)?,
+                    chunks: row.get(4)?,
+                    last_returned: row.get(5)?,
+                    hottest: Vec::new(),
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut stmt = conn.prepare(
+            "SELECT c.id, c.start_offset, c.end_offset, r.returns, r.last_returned
+             FROM chunks c
+             JOIN chunk_returns r ON r.chunk_id = c.id
+             WHERE c.file_id = ?1 AND r.returns > 0
+             ORDER BY r.returns DESC, r.last_returned DESC
+             LIMIT ?2",
+        )?;
+        for file in &mut files {
+            file.hottest = stmt
+                .query_map(params![file.file_id, per_file as i64], |row| {
+                    Ok(ChunkHeat {
+                        id: row.get(0)?,
+                        start_offset: row.get(1)?,
+                        end_offset: row.get(2)?,
+                        returns: row.get(3)?,
+                        last_returned: row.get(4)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+        }
+        Ok(files)
+    }
+
+    /// Files and chunks in each collection, by name. Chunks outside every
+    /// configured collection count as `default`.
+    pub fn collection_stats(&self) -> Result<Vec<CollectionStats>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT COALESCE(collection, ?1) AS name,
+                    COUNT(DISTINCT file_id), COUNT(*)
+             FROM chunks
+             GROUP BY name
+             ORDER BY name",
+        )?;
+        let stats = stmt
+            .query_map(params![crate::staleness::DEFAULT_COLLECTION], |row| {
+                Ok(CollectionStats {
+                    name: row.get(0)?,
+                    files: row.get(1)?,
+                    chunks: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(stats)
+    }
+
+    /// Ids of the files holding chunks of collection `name`. Chunks outside
+    /// every configured collection are in `default`.
+    pub fn collection_file_ids(&self, name: &str) -> Result<HashSet<i64>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT file_id FROM chunks
+             WHERE collection = ?1 OR (?1 = ?2 AND collection IS NULL)",
+        )?;
+        let ids = stmt
+            .query_map(params![name, crate::staleness::DEFAULT_COLLECTION], |row| {
+                row.get(0)
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(ids)
+    }
+
+    /// Get database statistics
+    pub fn get_stats(&self) -> Result<DbStats> {
+        let conn = self.reader();
+
+        let file_count: u64 = conn.query_row("SELECT COUNT(*) FROM files", [], |row| row.get(0))?;
+
+        let chunk_count: u64 =
+            conn.query_row("SELECT COUNT(*) FROM chunks", [], |row| row.get(0))?;
+        let archived_chunk_count: u64 =
+            conn.query_row("SELECT COUNT(*) FROM chunks_archive", [], |row| row.get(0))?;
+
+        // Get database page count and page size for size estimate
+        let page_count: u64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        let db_size = page_count * page_size;
+
+        Ok(DbStats {
+            file_count,
+            chunk_count,
+            archived_chunk_count,
+            db_size,
+        })
+    }
+
+    /// Fold the write-ahead log into the database file and truncate it.
+    pub fn checkpoint(&self) -> Result<()> {
+        self.writer.write_alone(move |conn| {
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE);", [], |_| Ok(()))?;
+            Ok(())
+        })
+    }
+
+    /// Release as much of SQLite's page cache as possible.
+    pub fn shrink_memory(&self) -> Result<()> {
+        for reader in self.readers.iter() {
+            reader
+                .lock()
+                .unwrap()
+                .execute_batch("PRAGMA shrink_memory;")?;
+        }
+        self.writer.write_alone(|conn| {
+            conn.execute_batch("PRAGMA shrink_memory;")?;
+            Ok(())
+        })
+    }
+
+    /// Tag a file or every file under a directory. The path is matched on whole
+    /// path components, so `src/payments` tags `./src/payments/api.rs`.
+    pub fn add_path_tag(&self, path: &str, tag: &str) -> Result<()> {
+        let path = normalize_tag_path(path);
+        let tag = tag.to_string();
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO path_tags (path, tag) VALUES (?1, ?2)",
+                params![path, tag],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Remove a path tag. Returns whether it existed.
+    pub fn remove_path_tag(&self, path: &str, tag: &str) -> Result<bool> {
+        let path = normalize_tag_path(path);
+        let tag = tag.to_string();
+        self.writer.write(move |conn| {
+            let removed = conn.execute(
+                "DELETE FROM path_tags WHERE path = ?1 AND tag = ?2",
+                params![path, tag],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+
+    /// Tag a single chunk. Chunk tags are dropped when the file is re-chunked.
+    pub fn add_chunk_tag(&self, chunk_id: i64, tag: &str) -> Result<()> {
+        let tag = tag.to_string();
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO chunk_tags (chunk_id, tag) VALUES (?1, ?2)",
+                params![chunk_id, tag],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Remove a chunk tag. Returns whether it existed.
+    pub fn remove_chunk_tag(&self, chunk_id: i64, tag: &str) -> Result<bool> {
+        let tag = tag.to_string();
+        self.writer.write(move |conn| {
+            let removed = conn.execute(
+                "DELETE FROM chunk_tags WHERE chunk_id = ?1 AND tag = ?2",
+                params![chunk_id, tag],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+
+    /// All path and chunk tags, ordered by tag.
+    pub fn list_tags(&self) -> Result<Vec<TagEntry>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT tag, path, NULL FROM path_tags
+             UNION ALL
+             SELECT tag, NULL, chunk_id FROM chunk_tags
+             ORDER BY 1, 2, 3",
+        )?;
+        let tags = stmt
+            .query_map([], |row| {
+                Ok(TagEntry {
+                    tag: row.get(0)?,
+                    path: row.get(1)?,
+                    chunk_id: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(tags)
+    }
+
+    pub fn record_index_error(&self, path: &str, error: &str) -> Result<()> {
+        let path = path.to_string();
+        let error = error.to_string();
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO index_errors (path, error, failed_at)
+                 VALUES (?1, ?2, strftime('%s', 'now'))",
+                params![path, error],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn clear_index_error(&self, path: &str) -> Result<()> {
+        let path = path.to_string();
+        self.writer.write(move |conn| {
+            conn.execute("DELETE FROM index_errors WHERE path = ?1", params![path])?;
+            Ok(())
+        })
+    }
+
+    /// The last indexing error for a path, if its last attempt failed.
+    pub fn get_index_error(&self, path: &str) -> Result<Option<String>> {
+        let conn = self.reader();
+        Ok(conn
+            .query_row(
+                "SELECT error FROM index_errors WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    pub fn record_index_timing(&self, path: &str, timing: &StageTimings) -> Result<()> {
+        let path = path.to_string();
+        let timing = *timing;
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO index_timings
+                    (path, chunking_us, embedding_us, storage_us, indexed_at)
+                 VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'))",
+                params![
+                    path,
+                    timing.chunking_us,
+                    timing.embedding_us,
+                    timing.storage_us
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Stage timings summed over every indexed file, with the `slowest` files
+    /// by total time.
+    pub fn index_timing_stats(&self, slowest: usize) -> Result<TimingStats> {
+        let conn = self.reader();
+        // Files dropped from the index no longer count
+        let (files, chunking_us, embedding_us, storage_us) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(t.chunking_us), 0),
+                    COALESCE(SUM(t.embedding_us), 0), COALESCE(SUM(t.storage_us), 0)
+             FROM index_timings t JOIN files f ON f.path = t.path",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+        let mut stmt = conn.prepare(
+            "SELECT t.path, t.chunking_us, t.embedding_us, t.storage_us
+             FROM index_timings t JOIN files f ON f.path = t.path
+             ORDER BY t.chunking_us + t.embedding_us + t.storage_us DESC
+             LIMIT ?1",
+        )?;
+        let slowest = stmt
+            .query_map(params![slowest as i64], |row| {
+                Ok(FileTiming {
+                    path: row.get(0)?,
+                    timings: StageTimings {
+                        chunking_us: row.get(1)?,
+                        embedding_us: row.get(2)?,
+                        storage_us: row.get(3)?,
+                    },
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let per_file = |us: u64| us.checked_div(files).unwrap_or(0);
+        Ok(TimingStats {
+            files,
+            total: StageTimings {
+                chunking_us,
+                embedding_us,
+                storage_us,
+            },
+            average: StageTimings {
+                chunking_us: per_file(chunking_us),
+                embedding_us: per_file(embedding_us),
+                storage_us: per_file(storage_us),
+            },
+            slowest,
+        })
+    }
+
+    /// Count a query against today's usage for `source`, and a hit for each
+    /// area its results came from. Failed queries have no areas.
+    pub fn record_query_stats(
+        &self,
+        source: &str,
+        elapsed_us: u64,
+        failed: bool,
+        areas: &[String],
+    ) -> Result<()> {
+        let source = source.to_string();
+        let areas = areas.to_vec();
+        self.writer.write(move |conn| {
+            let tx = conn.savepoint()?;
+            tx.execute(
+                "INSERT INTO query_stats (day, source, queries, failed, total_us)
+                 VALUES (date('now'), ?1, 1, ?2, ?3)
+                 ON CONFLICT(day, source) DO UPDATE SET
+                    queries = queries + 1,
+                    failed = failed + ?2,
+                    total_us = total_us + ?3",
+                params![source, failed as i64, elapsed_us],
+            )?;
+            for area in &areas {
+                tx.execute(
+                    "INSERT INTO query_areas (area, hits, last_hit)
+                     VALUES (?1, 1, strftime('%s', 'now'))
+                     ON CONFLICT(area) DO UPDATE SET
+                        hits = hits + 1,
+                        last_hit = strftime('%s', 'now')",
+                    params![area],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    /// Per-day, per-interface query counts of the last `days` days, oldest
+    /// first.
+    pub fn query_stats(&self, days: u32) -> Result<Vec<QueryStats>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT day, source, queries, failed, total_us FROM query_stats
+             WHERE day > date('now', '-' || ?1 || ' days')
+             ORDER BY day, source",
+        )?;
+        let stats = stmt
+            .query_map(params![days], |row| {
+                Ok(QueryStats {
+                    day: row.get(0)?,
+                    source: row.get(1)?,
+                    queries: row.get(2)?,
+                    failed: row.get(3)?,
+                    total_us: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(stats)
+    }
+
+    /// Count a query from the API user `user`.
+    pub fn record_user_query(&self, user: &str, elapsed_us: u64, failed: bool) -> Result<()> {
+        let user = user.to_string();
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT INTO user_query_stats (day, user, queries, failed, total_us)
+                 VALUES (date('now'), ?1, 1, ?2, ?3)
+                 ON CONFLICT(day, user) DO UPDATE SET
+                    queries = queries + 1,
+                    failed = failed + ?2,
+                    total_us = total_us + ?3",
+                params![user, failed as i64, elapsed_us],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Per-day, per-user query counts of the last `days` days, oldest first,
+    /// with the user name as the `source`.
+    pub fn user_query_stats(&self, days: u32) -> Result<Vec<QueryStats>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT day, user, queries, failed, total_us FROM user_query_stats
+             WHERE day > date('now', '-' || ?1 || ' days')
+             ORDER BY day, user",
+        )?;
+        let stats = stmt
+            .query_map(params![days], |row| {
+                Ok(QueryStats {
+                    day: row.get(0)?,
+                    source: row.get(1)?,
+                    queries: row.get(2)?,
+                    failed: row.get(3)?,
+                    total_us: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(stats)
+    }
+
+    /// Queries the API user `user` sent today (UTC).
+    pub fn user_queries_today(&self, user: &str) -> Result<u64> {
+        let conn = self.reader();
+        Ok(conn
+            .query_row(
+                "SELECT queries FROM user_query_stats WHERE day = date('now') AND user = ?1",
+                params![user],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(0))
+    }
+
+    /// The `limit` areas results came from most often, with their hit counts.
+    pub fn top_query_areas(&self, limit: usize) -> Result<Vec<(String, u64)>> {
+        let conn = self.reader();
+        let mut stmt =
+            conn.prepare("SELECT area, hits FROM query_areas ORDER BY hits DESC, area LIMIT ?1")?;
+        let areas = stmt
+            .query_map(params![limit as i64], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(areas)
+    }
+
+    /// Record today's index size, replacing an earlier snapshot from today.
+    pub fn record_index_snapshot(&self) -> Result<()> {
+        let stats = self.get_stats()?;
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO index_history (day, files, chunks, db_size)
+                 VALUES (date('now'), ?1, ?2, ?3)",
+                params![stats.file_count, stats.chunk_count, stats.db_size],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Daily index sizes of the last `days` days, oldest first.
+    pub fn index_history(&self, days: u32) -> Result<Vec<IndexSnapshot>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT day, files, chunks, db_size FROM index_history
+             WHERE day > date('now', '-' || ?1 || ' days')
+             ORDER BY day",
+        )?;
+        let history = stmt
+            .query_map(params![days], |row| {
+                Ok(IndexSnapshot {
+                    day: row.get(0)?,
+                    files: row.get(1)?,
+                    chunks: row.get(2)?,
+                    db_size: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(history)
+    }
+
+    /// Number of paths whose last indexing attempt failed.
+    pub fn index_error_count(&self) -> Result<u64> {
+        let conn = self.reader();
+        Ok(conn.query_row("SELECT COUNT(*) FROM index_errors", [], |row| row.get(0))?)
+    }
+
+    /// Start or stop keeping every version of every chunk in `chunk_history`.
+    /// Triggers on `chunks` record the changes, so every process writing to
+    /// the index keeps the history while it is on. Chunks indexed before it
+    /// was turned on are recorded as current since their file was indexed;
+    /// turning it off deletes the history.
+    pub fn set_history(&self, enabled: bool) -> Result<()> {
+        self.writer.write(move |conn| {
+            if !enabled {
+                conn.execute_batch(
+                    "DROP TRIGGER IF EXISTS chunk_history_add;
+                     DROP TRIGGER IF EXISTS chunk_history_update;
+                     DROP TRIGGER IF EXISTS chunk_history_remove;
+                     DROP TRIGGER IF EXISTS chunk_history_rename;
+                     DELETE FROM chunk_history;",
+                )?;
+                return Ok(());
+            }
+            // Replaced, not kept, so triggers from before the collection
+            // column record it too
+            conn.execute_batch(
+                "DROP TRIGGER IF EXISTS chunk_history_add;
+                 DROP TRIGGER IF EXISTS chunk_history_update;
+                 DROP TRIGGER IF EXISTS chunk_history_rename;
+                 CREATE TRIGGER chunk_history_add AFTER INSERT ON chunks BEGIN
+                    INSERT INTO chunk_history (id, path, last_modified, start_offset, end_offset,
+                                               content, embedding, metadata, collection,
+                                               valid_from)
+                    SELECT NEW.id, f.path, f.last_modified, NEW.start_offset, NEW.end_offset,
+                           NEW.content, NEW.embedding, NEW.metadata, NEW.collection,
+                           strftime('%s', 'now')
+                    FROM files f WHERE f.id = NEW.file_id;
+                 END;
+                 CREATE TRIGGER chunk_history_update
+                 AFTER UPDATE OF embedding, metadata, collection ON chunks BEGIN
+                    UPDATE chunk_history SET embedding = NEW.embedding, metadata = NEW.metadata,
+                                             collection = NEW.collection
+                    WHERE id = NEW.id AND valid_to IS NULL;
+                 END;
+                 CREATE TRIGGER IF NOT EXISTS chunk_history_remove AFTER DELETE ON chunks BEGIN
+                    UPDATE chunk_history SET valid_to = strftime('%s', 'now')
+                    WHERE id = OLD.id AND valid_to IS NULL;
+                 END;
+                 CREATE TRIGGER chunk_history_rename
+                 AFTER UPDATE OF path ON files
+                 WHEN OLD.path IS NOT NEW.path BEGIN
+                    UPDATE chunk_history SET valid_to = strftime('%s', 'now')
+                    WHERE valid_to IS NULL AND id IN (SELECT id FROM chunks WHERE file_id = NEW.id);
+                    INSERT INTO chunk_history (id, path, last_modified, start_offset, end_offset,
+                                               content, embedding, metadata, collection,
+                                               valid_from)
+                    SELECT id, NEW.path, NEW.last_modified, start_offset, end_offset,
+                           content, embedding, metadata, collection, strftime('%s', 'now')
+                    FROM chunks WHERE file_id = NEW.id;
+                 END;",
+            )?;
+            conn.execute(
+                "INSERT INTO chunk_history (id, path, last_modified, start_offset, end_offset,
+                                            content, embedding, metadata, collection, valid_from)
+                 SELECT c.id, f.path, f.last_modified, c.start_offset, c.end_offset,
+                        c.content, c.embedding, c.metadata, c.collection,
+                        COALESCE(f.last_indexed, strftime('%s', 'now'))
+                 FROM chunks c JOIN files f ON c.file_id = f.id
+                 WHERE NOT EXISTS (
+                    SELECT 1 FROM chunk_history h WHERE h.id = c.id AND h.valid_to IS NULL
+                 )",
+                [],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Drop chunk versions replaced before `before` (seconds since the epoch).
+    /// Returns how many were dropped.
+    pub fn prune_history(&self, before: u64) -> Result<usize> {
+        self.writer.write(move |conn| {
+            Ok(conn.execute(
+                "DELETE FROM chunk_history WHERE valid_to < ?1",
+                params![before],
+            )?)
+        })
+    }
+
+    /// Changes recorded after `since`, oldest first, at most `limit` of them.
+    pub fn changes_since(&self, since: i64, limit: usize) -> Result<Vec<IndexChange>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT seq, at, kind, op, path, chunk_id FROM index_changes
+             WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+        )?;
+        let changes = stmt
+            .query_map(params![since, limit as i64], |row| {
+                Ok(IndexChange {
+                    seq: row.get(0)?,
+                    at: row.get(1)?,
+                    kind: row.get(2)?,
+                    op: row.get(3)?,
+                    path: row.get(4)?,
+                    chunk_id: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(changes)
+    }
+
+    /// Changes recorded from `from` until before `to` (seconds since the
+    /// epoch), oldest first.
+    pub fn changes_between(&self, from: u64, to: u64) -> Result<Vec<IndexChange>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT seq, at, kind, op, path, chunk_id FROM index_changes
+             WHERE at >= ?1 AND at < ?2 ORDER BY seq",
+        )?;
+        let changes = stmt
+            .query_map(params![from, to], |row| {
+                Ok(IndexChange {
+                    seq: row.get(0)?,
+                    at: row.get(1)?,
+                    kind: row.get(2)?,
+                    op: row.get(3)?,
+                    path: row.get(4)?,
+                    chunk_id: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(changes)
+    }
+
+    /// Tags in the index that no earlier call returned, as `(source, tag)`:
+    /// `user` for path and chunk tags, else the chunk metadata key of an
+    /// extracted tag (`frameworks`, `markers`). Every tag is then recorded as
+    /// seen. The first call only records them, returning none.
+    pub fn take_new_tags(&self) -> Result<Vec<(String, String)>> {
+        self.writer.write(|conn| {
+            let seeded: bool =
+                conn.query_row("SELECT EXISTS (SELECT 1 FROM digest_tags)", [], |row| {
+                    row.get(0)
+                })?;
+            let mut stmt = conn.prepare(
+                "SELECT source, tag FROM (
+                    SELECT 'user' AS source, tag FROM path_tags
+                    UNION SELECT 'user', tag FROM chunk_tags
+                    UNION SELECT k.key, t.value
+                    FROM chunks c, json_each(c.metadata) k, json_each(k.value) t
+                    WHERE k.key IN ('frameworks', 'markers') AND k.type = 'array'
+                 ) current
+                 WHERE NOT EXISTS (
+                    SELECT 1 FROM digest_tags d
+                    WHERE d.source = current.source AND d.tag = current.tag
+                 )
+                 ORDER BY source, tag",
+            )?;
+            let new = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+            for (source, tag) in &new {
+                conn.execute(
+                    "INSERT INTO digest_tags (source, tag) VALUES (?1, ?2)",
+                    params![source, tag],
+                )?;
+            }
+            Ok(if seeded { new } else { Vec::new() })
+        })
+    }
+
+    /// Sequence number of the oldest change still kept, and of the newest
+    /// change ever recorded; 0 when there are none.
+    pub fn change_range(&self) -> Result<(i64, i64)> {
+        let conn = self.reader();
+        Ok(conn.query_row(
+            "SELECT COALESCE((SELECT MIN(seq) FROM index_changes), 0),
+                    COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'index_changes'), 0)",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?)
+    }
+
+    /// Drop changes recorded before `before` (seconds since the epoch).
+    /// Sequence numbers are never reused. Returns how many were dropped.
+    pub fn prune_changes(&self, before: u64) -> Result<usize> {
+        self.writer.write(move |conn| {
+            Ok(conn.execute("DELETE FROM index_changes WHERE at < ?1", params![before])?)
+        })
+    }
+
+    /// Delete what nothing refers to any more: chunks of removed files and
+    /// their leftover index entries, per-path and per-chunk rows of files
+    /// and chunks that are gone, models of collections without chunks, and
+    /// usage analytics from before `analytics_before` (seconds since the
+    /// epoch), if given.
+    pub fn collect_garbage(&self, analytics_before: Option<u64>) -> Result<Garbage> {
+        self.writer.write(move |conn| {
+            let free_pages = |conn: &Connection| -> rusqlite::Result<u64> {
+                conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))
+            };
+            let pages_before = free_pages(conn)?;
+            let mut garbage = Garbage::default();
+            let orphaned = "SELECT id FROM chunks WHERE file_id NOT IN (SELECT id FROM files)";
+            for (table, column) in [
+                ("chunks_vec", "chunk_id"),
+                ("chunks_vec_bits", "chunk_id"),
+                ("chunks_fts", "rowid"),
+                ("reembed_staging", "chunk_id"),
+                ("chunk_returns", "chunk_id"),
+            ] {
+                garbage.index_rows += conn.execute(
+                    &format!("DELETE FROM {} WHERE {} IN ({})", table, column, orphaned),
+                    [],
+                )? as u64;
+            }
+            garbage.chunks += conn.execute(
+                "DELETE FROM chunks WHERE file_id NOT IN (SELECT id FROM files)",
+                [],
+            )? as u64;
+            garbage.chunks += conn.execute(
+                "DELETE FROM chunks_archive WHERE file_id NOT IN (SELECT id FROM files)",
+                [],
+            )? as u64;
+
+            for sql in [
+                "DELETE FROM chunks_vec WHERE chunk_id NOT IN
+                    (SELECT id FROM chunks WHERE embedding IS NOT NULL)",
+                "DELETE FROM chunks_vec_bits WHERE chunk_id NOT IN
+                    (SELECT id FROM chunks WHERE embedding IS NOT NULL)",
+                "DELETE FROM chunks_fts WHERE rowid NOT IN (SELECT id FROM chunks)",
+                "DELETE FROM reembed_staging WHERE chunk_id NOT IN (SELECT id FROM chunks)",
+                "DELETE FROM chunk_returns WHERE chunk_id NOT IN
+                    (SELECT id FROM chunks UNION ALL SELECT id FROM chunks_archive)",
+                "DELETE FROM index_settings WHERE path NOT IN (SELECT path FROM files)",
+                "DELETE FROM content_hashes WHERE path NOT IN (SELECT path FROM files)",
+                "DELETE FROM index_timings WHERE path NOT IN (SELECT path FROM files)",
+            ] {
+                garbage.index_rows += conn.execute(sql, [])? as u64;
+            }
+
+            garbage.collections = conn.execute(
+                "DELETE FROM collection_models WHERE name NOT IN (
+                    SELECT collection FROM chunks WHERE collection IS NOT NULL
+                    UNION SELECT collection FROM chunks_archive WHERE collection IS NOT NULL
+                 )",
+                [],
+            )? as u64;
+
+            if let Some(before) = analytics_before {
+                for sql in [
+                    "DELETE FROM query_stats WHERE day < date(?1, 'unixepoch')",
+                    "DELETE FROM user_query_stats WHERE day < date(?1, 'unixepoch')",
+                    "DELETE FROM index_history WHERE day < date(?1, 'unixepoch')",
+                    "DELETE FROM query_areas WHERE last_hit < ?1",
+                ] {
+                    garbage.analytics_rows += conn.execute(sql, params![before])? as u64;
+                }
+            }
+
+            let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+            garbage.freed_bytes = free_pages(conn)?.saturating_sub(pages_before) * page_size;
+            Ok(garbage)
+        })
+    }
+
+    /// Rewrite the database without its free pages, shrinking the file.
+    pub fn vacuum(&self) -> Result<()> {
+        self.writer
+            .write_alone(|conn| Ok(conn.execute_batch("VACUUM")?))
+    }
+
+    /// Record that a scan of `discovered` files started, replacing the last.
+    pub fn start_scan(&self, discovered: u64) -> Result<()> {
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO scan_progress
+                 (id, started_at, updated_at, discovered, indexed, finished)
+                 VALUES (1, strftime('%s', 'now'), strftime('%s', 'now'), ?1, 0, 0)",
+                params![discovered],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Record how many files the scan has indexed, whether it is done and,
+    /// until it is, its checkpoint.
+    pub fn update_scan(
+        &self,
+        indexed: u64,
+        finished: bool,
+        checkpoint: Option<String>,
+    ) -> Result<()> {
+        self.writer.write(move |conn| {
+            conn.execute(
+                "UPDATE scan_progress
+                 SET updated_at = strftime('%s', 'now'), indexed = ?1, finished = ?2
+                 WHERE id = 1",
+                params![indexed, finished],
+            )?;
+            match checkpoint.filter(|_| !finished) {
+                Some(directory) => conn.execute(
+                    "INSERT OR REPLACE INTO scan_checkpoint (id, directory) VALUES (1, ?1)",
+                    params![directory],
+                )?,
+                None => conn.execute("DELETE FROM scan_checkpoint", [])?,
+            };
+            Ok(())
+        })
+    }
+
+    /// The directory the last scan had indexed everything up to, if it
+    /// didn't finish.
+    pub fn scan_checkpoint(&self) -> Result<Option<String>> {
+        let conn = self.reader();
+        let directory = conn
+            .query_row(
+                "SELECT directory FROM scan_checkpoint WHERE id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(directory)
+    }
+
+    /// The last scan recorded with [`Self::start_scan`].
+    pub fn scan_record(&self) -> Result<Option<ScanRecord>> {
+        let conn = self.reader();
+        let record = conn
+            .query_row(
+                "SELECT started_at, updated_at, discovered, indexed, finished
+                 FROM scan_progress WHERE id = 1",
+                [],
+                |row| {
+                    Ok(ScanRecord {
+                        started_at: row.get(0)?,
+                        updated_at: row.get(1)?,
+                        discovered: row.get(2)?,
+                        indexed: row.get(3)?,
+                        finished: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(record)
+    }
+
+    /// Record the daemon's backlog of `pending` files and when it last
+    /// finished indexing a changed file.
+    pub fn record_watch_status(&self, last_event_at: Option<u64>, pending: u64) -> Result<()> {
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO watch_status (id, updated_at, last_event_at, pending)
+                 VALUES (1, strftime('%s', 'now'), ?1, ?2)",
+                params![last_event_at, pending],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// The daemon's last [`Self::record_watch_status`].
+    pub fn watch_status(&self) -> Result<Option<WatchStatus>> {
+        let conn = self.reader();
+        let status = conn
+            .query_row(
+                "SELECT updated_at, last_event_at, pending FROM watch_status WHERE id = 1",
+                [],
+                |row| {
+                    Ok(WatchStatus {
+                        updated_at: row.get(0)?,
+                        last_event_at: row.get(1)?,
+                        pending: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(status)
+    }
+
+    /// Register a workspace root. Returns false if it was already registered.
+    pub fn register_workspace(&self, path: &Path) -> Result<bool> {
+        let path = path.to_string_lossy().to_string();
+        self.writer.write(move |conn| {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let inserted = conn.execute(
+                "INSERT OR IGNORE INTO workspaces (path, registered_at) VALUES (?1, ?2)",
+                params![path, now],
+            )?;
+            Ok(inserted > 0)
+        })
+    }
+
+    /// All registered workspace roots, ordered by path.
+    pub fn list_workspaces(&self) -> Result<Vec<PathBuf>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare("SELECT path FROM workspaces ORDER BY path")?;
+        let paths = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|r| r.map(PathBuf::from))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(paths)
+    }
+
+    /// The model a collection's chunks were embedded with, and its dimensions.
+    pub fn collection_model(&self, name: &str) -> Result<Option<(String, usize)>> {
+        let conn = self.reader();
+        let model = conn
+            .query_row(
+                "SELECT model, dimensions FROM collection_models WHERE name = ?1",
+                params![name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        Ok(model)
+    }
+
+    /// Record the model a collection is embedded with.
+    pub fn set_collection_model(&self, name: &str, model: &str, dimensions: usize) -> Result<()> {
+        let name = name.to_string();
+        let model = model.to_string();
+        self.writer.write(move |conn| {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            conn.execute(
+                "INSERT INTO collection_models (name, model, dimensions, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(name) DO UPDATE SET model = ?2, dimensions = ?3, recorded_at = ?4",
+                params![name, model, dimensions, now],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Up to `limit` embedded chunks of a collection without a staged new
+    /// embedding, as (id, content).
+    pub fn unstaged_chunks(
+        &self,
+        collection: Option<&str>,
+        configured: &[String],
+        limit: usize,
+    ) -> Result<Vec<(i64, String)>> {
+        let (condition, param) = collection_condition(collection, configured);
+        let conn = self.reader();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT c.id, c.content FROM chunks c
+             WHERE c.embedding IS NOT NULL AND {}
+               AND c.id NOT IN (SELECT chunk_id FROM reembed_staging)
+             ORDER BY c.id LIMIT ?2",
+            condition
+        ))?;
+        let chunks = stmt
+            .query_map(params![param, limit], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(chunks)
+    }
+
+    /// Embedded chunks of a collection with a staged new embedding, and in
+    /// total.
+    pub fn reembedding_progress(
+        &self,
+        collection: Option<&str>,
+        configured: &[String],
+    ) -> Result<(u64, u64)> {
+        let (condition, param) = collection_condition(collection, configured);
+        let conn = self.reader();
+        let progress = conn.query_row(
+            &format!(
+                "SELECT COUNT(s.chunk_id), COUNT(*) FROM chunks c
+                 LEFT JOIN reembed_staging s ON s.chunk_id = c.id
+                 WHERE c.embedding IS NOT NULL AND {}",
+                condition
+            ),
+            params![param],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(progress)
+    }
+
+    /// Keep a chunk's new embedding until its collection switches models.
+    pub fn stage_embedding(&self, chunk_id: i64, embedding: &[f32]) -> Result<()> {
+        let embedding = encode_embedding(embedding);
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO reembed_staging (chunk_id, embedding)
+                 SELECT id, ?2 FROM chunks WHERE id = ?1",
+                params![chunk_id, embedding],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Swap a collection's staged embeddings in for its current ones and
+    /// record `model` as its model, all in one transaction. Returns false,
+    /// changing nothing, if some of its chunks have no staged embedding yet.
+    /// Indexing-settings fingerprints of its files move from `previous` to
+    /// `model` too, as their chunks now carry the new model's vectors.
+    pub fn commit_reembedding(
+        &self,
+        collection: Option<&str>,
+        configured: &[String],
+        previous: &str,
+        model: &str,
+        dimensions: usize,
+    ) -> Result<bool> {
+        let (condition, param) = collection_condition(collection, configured);
+        let staged = format!(
+            "SELECT s.chunk_id FROM reembed_staging s JOIN chunks c ON c.id = s.chunk_id WHERE {}",
+            condition
+        );
+        let collection = collection.map(String::from);
+        let previous = previous.to_string();
+        let model = model.to_string();
+        self.writer.write(move |conn| {
+            let tx = conn.savepoint()?;
+            let unstaged: u64 = tx.query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM chunks c
+                     WHERE c.embedding IS NOT NULL AND {}
+                       AND c.id NOT IN (SELECT chunk_id FROM reembed_staging)",
+                    condition
+                ),
+                params![param],
+                |row| row.get(0),
+            )?;
+            if unstaged > 0 {
+                return Ok(false);
+            }
+
+            tx.execute(
+                &format!(
+                    "UPDATE chunks SET embedding =
+                        (SELECT embedding FROM reembed_staging WHERE chunk_id = chunks.id)
+                     WHERE id IN ({})",
+                    staged
+                ),
+                params![param],
+            )?;
+            for table in ["chunks_vec", "chunks_vec_bits"] {
+                tx.execute(
+                    &format!("DELETE FROM {} WHERE chunk_id IN ({})", table, staged),
+                    params![param],
+                )?;
+            }
+            tx.execute(
+                &format!(
+                    "INSERT INTO chunks_vec (chunk_id, embedding)
+                     SELECT chunk_id, embedding FROM reembed_staging WHERE chunk_id IN ({})",
+                    staged
+                ),
+                params![param],
+            )?;
+            tx.execute(
+                &format!(
+                    "INSERT INTO chunks_vec_bits (chunk_id, embedding)
+                     SELECT chunk_id, vec_quantize_binary(embedding) FROM reembed_staging
+                     WHERE chunk_id IN ({})",
+                    staged
+                ),
+                params![param],
+            )?;
+            tx.execute(
+                &format!(
+                    "UPDATE index_settings SET fingerprint = json_set(fingerprint, '$.model', ?2)
+                     WHERE json_extract(fingerprint, '$.model') = ?3
+                       AND path IN (SELECT f.path FROM files f JOIN chunks c ON c.file_id = f.id
+                                    WHERE {})",
+                    condition
+                ),
+                params![param, model, previous],
+            )?;
+            tx.execute(
+                &format!("DELETE FROM reembed_staging WHERE chunk_id IN ({})", staged),
+                params![param],
+            )?;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            tx.execute(
+                "INSERT INTO collection_models (name, model, dimensions, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(name) DO UPDATE SET model = ?2, dimensions = ?3, recorded_at = ?4",
+                params![
+                    collection
+                        .as_deref()
+                        .unwrap_or(crate::staleness::DEFAULT_COLLECTION),
+                    model,
+                    dimensions,
+                    now
+                ],
+            )?;
+            tx.commit()?;
+            Ok(true)
+        })
+    }
+
+    /// Embeddings of a file's chunks, by chunk content.
+    pub fn chunk_embeddings(&self, file_id: i64) -> Result<HashMap<String, Vec<f32>>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT content, embedding FROM chunks WHERE file_id = ?1 AND embedding IS NOT NULL",
+        )?;
+        let embeddings = stmt
+            .query_map(params![file_id], |row| {
+                let bytes: Vec<u8> = row.get(1)?;
+                Ok((row.get(0)?, decode_embedding(&bytes)))
+            })?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        Ok(embeddings)
+    }
+
+    /// Settings fingerprint a file was last indexed with.
+    pub fn index_settings_for(&self, path: &str) -> Result<Option<String>> {
+        let conn = self.reader();
+        let fingerprint = conn
+            .query_row(
+                "SELECT fingerprint FROM index_settings WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(fingerprint)
+    }
+
+    /// Record the settings fingerprint a file was indexed with.
+    pub fn record_index_settings(&self, path: &str, fingerprint: &str) -> Result<()> {
+        let path = path.to_string();
+        let fingerprint = fingerprint.to_string();
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT INTO index_settings (path, fingerprint) VALUES (?1, ?2)
+                 ON CONFLICT(path) DO UPDATE SET fingerprint = ?2",
+                params![path, fingerprint],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Settings fingerprint of every file that has one, by path.
+    pub fn index_settings(&self) -> Result<HashMap<String, String>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare("SELECT path, fingerprint FROM index_settings")?;
+        let settings = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        Ok(settings)
+    }
+
+    /// Record the hash of a file's content as it was indexed.
+    pub fn record_content_hash(&self, path: &str, hash: &str) -> Result<()> {
+        let path = path.to_string();
+        let hash = hash.to_string();
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT INTO content_hashes (path, hash) VALUES (?1, ?2)
+                 ON CONFLICT(path) DO UPDATE SET hash = ?2",
+                params![path, hash],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// If the file at `path` is indexed and its content still hashes to
+    /// `hash`, record it as indexed at `modified` without touching its
+    /// chunks. Returns whether it was unchanged. An mtime in the future
+    /// counts as the index time, so the file isn't hashed again every pass.
+    pub fn touch_if_unchanged(&self, path: &str, modified: u64, hash: &str) -> Result<bool> {
+        let path = path.to_string();
+        let hash = hash.to_string();
+        self.writer.write(move |conn| {
+            let touched = conn.execute(
+                "UPDATE files SET last_modified = ?2,
+                    last_indexed = MAX(CAST(strftime('%s', 'now') AS INTEGER), ?2)
+                 WHERE path = ?1 AND last_indexed IS NOT NULL
+                   AND EXISTS (SELECT 1 FROM content_hashes WHERE path = ?1 AND hash = ?3)",
+                params![path, modified, hash],
+            )?;
+            Ok(touched > 0)
+        })
+    }
+
+    /// Indexed files whose content hashed to `hash` when they were last
+    /// indexed.
+    pub fn files_with_content_hash(&self, hash: &str) -> Result<Vec<String>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(
+            "SELECT f.path FROM files f JOIN content_hashes h ON h.path = f.path
+             WHERE h.hash = ?1 AND f.last_indexed IS NOT NULL ORDER BY f.path",
+        )?;
+        let paths = stmt
+            .query_map(params![hash], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(paths)
+    }
+
+    /// Forget when a file was indexed so the next pass re-indexes it even if
+    /// it hasn't changed.
+    pub fn mark_unindexed(&self, path: &str) -> Result<()> {
+        let path = path.to_string();
+        self.writer.write(move |conn| {
+            conn.execute(
+                "UPDATE files SET last_indexed = NULL WHERE path = ?1",
+                params![path],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Read every stored embedding once so its pages are resident before the
+    /// first query. Returns the number of vector bytes touched.
+    pub fn preload_vectors(&self) -> Result<u64> {
+        let conn = self.reader();
+        let bytes: Option<u64> =
+            conn.query_row("SELECT SUM(length(embedding)) FROM chunks_vec", [], |row| {
+                row.get(0)
+            })?;
+        Ok(bytes.unwrap_or(0))
+    }
+
+    /// Record a search hit for a file (for frequency ranking)
+    /// Call this after returning search results to boost frequently accessed files
+    #[allow(dead_code)]
+    pub fn record_search_hit(&self, file_id: i64) -> Result<()> {
+        self.writer.write(move |conn| {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            conn.execute(
+                "INSERT INTO query_hits (file_id, hit_count, last_hit)
+                 VALUES (?1, 1, ?2)
+                 ON CONFLICT(file_id) DO UPDATE SET
+                    hit_count = hit_count + 1,
+                    last_hit = ?2",
+                params![file_id, now],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Record that chunks of the files at `paths` were returned by a query, for
+    /// least-recently-used eviction. Unlike [`Database::record_search_hit`]
+    /// this leaves the hit counts, and so the ranking, as they are.
+    pub fn mark_returned(&self, paths: &[&str]) -> Result<()> {
+        let paths = serde_json::to_string(paths).unwrap_or_default();
+        self.writer.write(move |conn| {
+            conn.execute(
+                "INSERT INTO query_hits (file_id, hit_count, last_hit)
+                 SELECT id, 0, strftime('%s', 'now') FROM files
+                 WHERE path IN (SELECT value FROM json_each(?1))
+                 ON CONFLICT(file_id) DO UPDATE SET last_hit = excluded.last_hit",
+                params![paths],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// The `limit` chunks whose embeddings are nearest to that of `chunk_id`,
+    /// most similar first. Empty if the chunk has no embedding.
+    pub fn related_chunks(&self, chunk_id: i64, limit: usize) -> Result<Vec<SearchResult>> {
+        let bytes: Option<Vec<u8>> = {
+            let conn = self.reader();
+            conn.query_row(
+                "SELECT embedding FROM chunks WHERE id = ?1",
+                params![chunk_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten()
+        };
+        let Some(bytes) = bytes else {
+            return Ok(Vec::new());
+        };
+        let embedding = decode_embedding(&bytes);
+
+        // Pure similarity: recency and popularity say nothing about likeness
+        let options = SearchOptions {
+            limit: Some(limit + 1),
+            recency_weight: Some(0.0),
+            frequency_weight: Some(0.0),
+            ..Default::default()
+        };
+        let mut related = self.search_chunks_enhanced(&embedding, &options)?;
+        related.retain(|r| r.id != chunk_id);
+        related.truncate(limit);
+        Ok(related)
     }
 
     /// Hybrid search using RRF (Reciprocal Rank Fusion)
@@ -275,20 +2538,15 @@ impl Database {
         // 1. Vector Search
         let vector_options = SearchOptions {
             limit: Some(50), // Fetch more for re-ranking
-            start_time: options.start_time,
-            end_time: options.end_time,
-            file_types: options.file_types.clone(),
-            paths: options.paths.clone(),
             min_score: None,
-            recency_weight: options.recency_weight,
-            frequency_weight: options.frequency_weight,
-            context_lines: options.context_lines,
+            ..options.clone()
         };
         let vector_results = self.search_chunks_enhanced(query_embedding, &vector_options)?;
 
         // 2. FTS Search
-        let conn = self.conn.lock().unwrap();
-        let mut sql = "SELECT c.id, c.content, f.path, f.last_modified
+        let conn = self.reader();
+        let mut sql = "SELECT c.id, c.content, f.path, f.last_modified, c.metadata,
+                              c.start_offset, c.end_offset, c.start_line, c.end_line, -fts.rank
                        FROM chunks_fts fts
                        JOIN chunks c ON fts.rowid = c.id
                        JOIN files f ON c.file_id = f.id
@@ -310,9 +2568,23 @@ impl Database {
             sql.push_str(" AND f.last_modified <= ?");
             params.push(Box::new(end));
         }
+        if let Some(filters) = &options.metadata_filters {
+            push_metadata_filters(&mut sql, &mut params, filters);
+        }
+        if let Some(tags) = &options.tags {
+            push_tag_filter(&mut sql, &mut params, tags);
+        }
+        if let Some(symbol) = &options.symbol {
+            params.push(Box::new(symbol.clone()));
+            sql.push_str(&format!(
+                " AND json_extract(c.metadata, '$.symbol') = ?{}",
+                params.len()
+            ));
+        }
 
         sql.push_str(" ORDER BY fts.rank LIMIT 50");
 
+        let fetch_started = Instant::now();
         let mut stmt = conn.prepare(&sql)?;
         let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
 
@@ -321,30 +2593,34 @@ impl Database {
             let content: String = row.get(1)?;
             let file_path: String = row.get(2)?;
             let last_modified: u64 = row.get(3)?;
-            Ok((id, content, file_path, last_modified))
+            let metadata: Option<String> = row.get(4)?;
+            let span = chunk_span(row, 5)?;
+            let bm25: f64 = row.get(9)?;
+            Ok((id, content, file_path, last_modified, metadata, span, bm25))
         })?;
+        let fts_rows = fts_iter.collect::<rusqlite::Result<Vec<_>>>()?;
+        options.record(QueryStage::CandidateFetch, fetch_started);
 
+        let scoring_started = Instant::now();
+        let scanned = fts_rows.len();
+        // The full-text search leaves out the collection filter
+        let mut keyword_filters = options.filter_names();
+        keyword_filters.retain(|f| f != "collections");
         let mut fts_results = Vec::new();
-        for res in fts_iter {
-            let (id, content, file_path, last_modified) = res?;
-
-            // Extract file extension
-            let file_type = file_path.rsplit('.').next().unwrap_or("").to_lowercase();
-
-            // Apply file type filter
-            if let Some(types) = &options.file_types {
-                if !types.iter().any(|t| t.to_lowercase() == file_type) {
-                    continue;
-                }
-            }
+        for res in fts_rows {
+            let (id, content, file_path, last_modified, metadata, span, bm25) = res;
 
-            // Apply path filter
-            if let Some(path_filters) = &options.paths {
-                if !path_filters.iter().any(|p| file_path.contains(p)) {
-                    continue;
-                }
+            let file_type = file_type(&file_path);
+            if !options.allows_path(&file_path, &file_type) {
+                continue;
             }
 
+            let explanation = options.explain.then(|| RankExplanation {
+                bm25_score: Some(bm25 as f32),
+                keyword_rank: Some(fts_results.len() + 1),
+                filters_passed: keyword_filters.clone(),
+                ..Default::default()
+            });
             fts_results.push(SearchResult {
                 id,
                 content,
@@ -352,11 +2628,23 @@ impl Database {
                 file_path,
                 file_type,
                 last_modified,
+                metadata,
+                start_offset: span.start,
+                end_offset: span.end,
+                line_start: span.lines.map(|(first, _)| first),
+                line_end: span.lines.map(|(_, last)| last),
+                explanation,
                 ..Default::default()
             });
         }
 
+        if let Some(profile) = &options.profile {
+            profile.add_rows(scanned, scanned - fts_results.len());
+        }
+        options.record(QueryStage::Scoring, scoring_started);
+
         // 3. RRF
+        let rerank_started = Instant::now();
         let mut scores: HashMap<i64, f32> = HashMap::new();
         let mut results_map: HashMap<i64, SearchResult> = HashMap::new();
 
@@ -369,7 +2657,19 @@ impl Database {
         for (rank, res) in fts_results.iter().enumerate() {
             let score = 1.0 / (k + (rank as f32 + 1.0));
             *scores.entry(res.id).or_insert(0.0) += score;
-            results_map.entry(res.id).or_insert_with(|| res.clone());
+            match results_map.get_mut(&res.id) {
+                Some(existing) => {
+                    if let (Some(explanation), Some(keyword)) =
+                        (&mut existing.explanation, &res.explanation)
+                    {
+                        explanation.bm25_score = keyword.bm25_score;
+                        explanation.keyword_rank = keyword.keyword_rank;
+                    }
+                }
+                None => {
+                    results_map.insert(res.id, res.clone());
+                }
+            }
         }
 
         let mut final_results: Vec<SearchResult> = results_map.into_values().collect();
@@ -385,62 +2685,200 @@ impl Database {
             sb.partial_cmp(&sa).unwrap_or(std::cmp::Ordering::Equal)
         });
         final_results.truncate(limit);
+        // Against the embedding ranking, or the full-text one for chunks
+        // only it found
+        for (rank, res) in final_results.iter_mut().enumerate() {
+            if let Some(explanation) = &mut res.explanation {
+                let before = explanation.dense_rank.or(explanation.keyword_rank);
+                explanation.rerank_delta = before.unwrap_or(rank + 1) as i64 - (rank + 1) as i64;
+            }
+        }
+        options.record(QueryStage::Rerank, rerank_started);
 
         Ok(final_results)
     }
 
-    /// Enhanced search with file type and path filtering
+    /// Enhanced search with file type and path filtering. With
+    /// `options.approximate`, only the chunks nearest by
+    /// [`Self::nearest_chunks`] are scored, unless filters reject so many of
+    /// them that an exact scan is needed to fill the page.
     pub fn search_chunks_enhanced(
         &self,
         query_embedding: &[f32],
         options: &SearchOptions,
     ) -> Result<Vec<SearchResult>> {
+        if options.approximate && options.as_of.is_none() {
+            let limit = options.limit.unwrap_or(10);
+            let k = (limit * ANN_OVERSAMPLE).clamp(ANN_MIN_CANDIDATES, ANN_MAX_CANDIDATES);
+            let fetch_started = Instant::now();
+            let candidates = self.nearest_chunks(query_embedding, k)?;
+            options.record(QueryStage::CandidateFetch, fetch_started);
+            let (results, matched) =
+                self.score_chunks(query_embedding, options, Some(&candidates))?;
+            if matched >= limit || candidates.len() < k {
+                return Ok(results);
+            }
+        }
+        Ok(self.score_chunks(query_embedding, options, None)?.0)
+    }
+
+    /// Count the chunks passing the filters of `options` per file type,
+    /// top-level directory beneath `roots` and collection. Every filter but
+    /// `min_score` applies; the counts don't depend on the query text.
+    pub fn facets(&self, options: &SearchOptions, roots: &[PathBuf]) -> Result<Facets> {
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        let from = match options.as_of {
+            None => "chunks c JOIN files f ON c.file_id = f.id WHERE 1=1",
+            Some(as_of) => {
+                params.push(Box::new(as_of));
+                "chunk_history c JOIN chunk_history f ON f.rowid = c.rowid
+                 WHERE c.embedding IS NOT NULL
+                   AND c.valid_from <= ?1 AND (c.valid_to IS NULL OR c.valid_to > ?1)"
+            }
+        };
+        let mut filters = String::new();
+        push_chunk_filters(&mut filters, &mut params, options);
+        let counts = |table: &str| {
+            format!(
+                "SELECT f.path AS path,
+                        COALESCE(c.collection, '{}') AS collection,
+                        COUNT(*) AS matches
+                 FROM {}{}
+                 GROUP BY f.path, collection",
+                crate::staleness::DEFAULT_COLLECTION,
+                table,
+                filters
+            )
+        };
+        let sql = if options.include_archived && options.as_of.is_none() {
+            format!(
+                "SELECT path, collection, SUM(matches) FROM ({} UNION ALL {})
+                 GROUP BY path, collection",
+                counts(from),
+                counts("chunks_archive c JOIN files f ON c.file_id = f.id WHERE 1=1")
+            )
+        } else {
+            counts(from)
+        };
+
+        let conn = self.reader();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt
+            .query_map(rusqlite::params_from_iter(params.iter()), |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut facets = Facets::default();
+        for (path, collection, matches) in rows {
+            let file_type = file_type(&path);
+            if options.allows_path(&path, &file_type) {
+                facets.add(&path, file_type, collection, roots, matches as usize);
+            }
+        }
+        Ok(facets)
+    }
+
+    /// Ids of about the `k` chunks nearest `query_embedding`, by Hamming
+    /// distance between the signs of their dimensions. Much cheaper than
+    /// comparing every vector, at the cost of missing some close chunks.
+    pub fn nearest_chunks(&self, query_embedding: &[f32], k: usize) -> Result<Vec<i64>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare_cached(
+            "SELECT chunk_id FROM chunks_vec_bits
+             WHERE embedding MATCH vec_quantize_binary(?1) AND k = ?2
+             ORDER BY distance",
+        )?;
+        let ids = stmt
+            .query_map(
+                params![encode_embedding(query_embedding), k as i64],
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        Ok(ids)
+    }
+
+    /// Score chunks, only `candidates` if given, by cosine similarity
+    /// reranked by recency and frequency. Also returns how many passed every
+    /// filter, `min_score` included, before the results were cut to the
+    /// limit.
+    fn score_chunks(
+        &self,
+        query_embedding: &[f32],
+        options: &SearchOptions,
+        candidates: Option<&[i64]>,
+    ) -> Result<(Vec<SearchResult>, usize)> {
         let limit = options.limit.unwrap_or(10);
-        let start_time = options.start_time;
-        let end_time = options.end_time;
-        let file_types = options.file_types.as_deref();
-        let paths = options.paths.as_deref();
         let min_score = options.min_score;
 
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
 
         let mut query_bytes = Vec::with_capacity(query_embedding.len() * 4);
         for val in query_embedding {
             query_bytes.extend_from_slice(&val.to_le_bytes());
         }
 
-        let mut sql =
-            "SELECT c.id, c.content, vec_distance_cosine(v.embedding, ?1) as distance, f.path, f.last_modified, f.id as file_id,
-                              COALESCE(qh.hit_count, 0) as hit_count
+        let mut sql = match options.as_of {
+            None => "SELECT c.id, c.content, vec_distance_cosine(v.embedding, ?1) as distance, f.path, f.last_modified, f.id as file_id,
+                              COALESCE(qh.hit_count, 0) as hit_count, c.metadata,
+                              c.start_offset, c.end_offset, c.start_line, c.end_line
                        FROM chunks c
                        JOIN chunks_vec v ON c.id = v.chunk_id
                        JOIN files f ON c.file_id = f.id
                        LEFT JOIN query_hits qh ON f.id = qh.file_id
-                       WHERE 1=1"
-                .to_string();
+                       WHERE 1=1",
+            // A version also stands in for its file (`f`), so the filters
+            // below apply unchanged
+            Some(_) => "SELECT c.id, c.content, vec_distance_cosine(c.embedding, ?1) as distance, f.path, f.last_modified, 0,
+                              0, c.metadata, c.start_offset, c.end_offset, NULL, NULL
+                       FROM chunk_history c
+                       JOIN chunk_history f ON f.rowid = c.rowid
+                       WHERE c.embedding IS NOT NULL
+                         AND c.valid_from <= ?2 AND (c.valid_to IS NULL OR c.valid_to > ?2)",
+        }
+        .to_string();
+        let base_len = sql.len();
         let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
         params.push(Box::new(query_bytes));
-
-        let mut param_idx = 2;
-        #[allow(unused_assignments)]
-        {
-            if let Some(start) = start_time {
-                sql.push_str(&format!(" AND f.last_modified >= ?{}", param_idx));
-                param_idx += 1;
-                params.push(Box::new(start));
-            }
-
-            if let Some(end) = end_time {
-                sql.push_str(&format!(" AND f.last_modified <= ?{}", param_idx));
-                param_idx += 1;
-                params.push(Box::new(end));
-            }
+        if let Some(as_of) = options.as_of {
+            params.push(Box::new(as_of));
+        }
+        push_chunk_filters(&mut sql, &mut params, options);
+        // Archived chunks are filtered alike, but never among the candidates
+        let archive_filters = (sql[base_len..].to_string(), params.len());
+        if let Some(candidates) = candidates {
+            let start = params.len() + 1;
+            params.extend(
+                candidates
+                    .iter()
+                    .map(|&id| Box::new(id) as Box<dyn rusqlite::ToSql>),
+            );
+            let placeholders = (start..start + candidates.len())
+                .map(|i| format!("?{}", i))
+                .collect::<Vec<_>>()
+                .join(", ");
+            sql.push_str(&format!(" AND c.id IN ({})", placeholders));
         }
 
+        let fetch_started = Instant::now();
         let mut stmt = conn.prepare(&sql)?;
         let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
 
-        let raw_rows: Vec<(i64, String, f32, String, u64, i64, i64)> = stmt
+        type RawRow = (
+            i64,
+            String,
+            f32,
+            String,
+            u64,
+            i64,
+            i64,
+            Option<String>,
+            ChunkSpan,
+        );
+        let mut raw_rows: Vec<RawRow> = stmt
             .query_map(params_refs.as_slice(), |row| {
                 Ok((
                     row.get(0)?,
@@ -450,29 +2888,72 @@ impl Database {
                     row.get(4)?,
                     row.get(5)?,
                     row.get(6)?,
+                    row.get(7)?,
+                    chunk_span(row, 8)?,
                 ))
             })?
             .filter_map(|r| r.ok())
             .collect();
 
+        if options.include_archived && options.as_of.is_none() {
+            let (filters, count) = archive_filters;
+            let mut stmt = conn.prepare(&format!(
+                "SELECT c.id, c.data, f.path, f.last_modified, f.id,
+                        COALESCE(qh.hit_count, 0), c.metadata, c.start_offset, c.end_offset,
+                        c.start_line, c.end_line
+                 FROM chunks_archive c
+                 JOIN files f ON c.file_id = f.id
+                 LEFT JOIN query_hits qh ON f.id = qh.file_id
+                 WHERE ?1 IS NOT NULL{}",
+                filters
+            ))?;
+            let archived = stmt
+                .query_map(&params_refs[..count], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                        chunk_span(row, 7)?,
+                    ))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            for (id, data, path, modified, file_id, hits, metadata, span) in archived {
+                let (content, embedding) = unpack_archived(&data)?;
+                let distance = cosine_distance(query_embedding, &decode_embedding(&embedding));
+                raw_rows.push((
+                    id, content, distance, path, modified, file_id, hits, metadata, span,
+                ));
+            }
+        }
+
         drop(stmt);
         drop(conn);
+        options.record(QueryStage::CandidateFetch, fetch_started);
 
+        let scoring_started = Instant::now();
+        let scanned = raw_rows.len();
+        let filters_passed = options.filter_names();
         let mut scored_chunks = Vec::new();
 
-        for (id, content, distance, file_path, last_modified, _file_id, hit_count) in raw_rows {
-            let file_type = file_path.rsplit('.').next().unwrap_or("").to_lowercase();
-
-            if let Some(types) = file_types {
-                if !types.iter().any(|t| t.to_lowercase() == file_type) {
-                    continue;
-                }
-            }
-
-            if let Some(path_filters) = paths {
-                if !path_filters.iter().any(|p| file_path.contains(p)) {
-                    continue;
-                }
+        for (
+            id,
+            content,
+            distance,
+            file_path,
+            last_modified,
+            _file_id,
+            hit_count,
+            metadata,
+            span,
+        ) in raw_rows
+        {
+            let file_type = file_type(&file_path);
+            if !options.allows_path(&file_path, &file_type) {
+                continue;
             }
 
             let score = 1.0 - distance;
@@ -504,6 +2985,13 @@ impl Database {
                 recency_adjusted
             };
 
+            let explanation = options.explain.then(|| RankExplanation {
+                dense_score: Some(score),
+                recency_boost: recency_adjusted - score,
+                frequency_boost: final_score - recency_adjusted,
+                filters_passed: filters_passed.clone(),
+                ..Default::default()
+            });
             scored_chunks.push(SearchResult {
                 id,
                 content,
@@ -511,31 +2999,895 @@ impl Database {
                 file_path,
                 file_type,
                 last_modified,
+                metadata,
+                start_offset: span.start,
+                end_offset: span.end,
+                line_start: span.lines.map(|(first, _)| first),
+                line_end: span.lines.map(|(_, last)| last),
+                explanation,
                 ..Default::default()
             });
         }
 
-        scored_chunks.sort_by(|a, b| {
-            let sa = if a.score.is_nan() { 0.0 } else { a.score };
-            let sb = if b.score.is_nan() { 0.0 } else { b.score };
-            sb.partial_cmp(&sa).unwrap_or(std::cmp::Ordering::Equal)
-        });
+        scored_chunks.sort_by(|a, b| {
+            let sa = if a.score.is_nan() { 0.0 } else { a.score };
+            let sb = if b.score.is_nan() { 0.0 } else { b.score };
+            sb.partial_cmp(&sa).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let matched = scored_chunks.len();
+        if let Some(profile) = &options.profile {
+            profile.add_rows(scanned, scanned - matched);
+        }
+        scored_chunks.truncate(limit);
+        for (rank, result) in scored_chunks.iter_mut().enumerate() {
+            if let Some(explanation) = &mut result.explanation {
+                explanation.dense_rank = Some(rank + 1);
+            }
+        }
+        options.record(QueryStage::Scoring, scoring_started);
+
+        Ok((scored_chunks, matched))
+    }
+}
+
+/// Database statistics
+pub struct DbStats {
+    pub file_count: u64,
+    pub chunk_count: u64,
+    /// Chunks in the archive, not counted in `chunk_count`
+    pub archived_chunk_count: u64,
+    pub db_size: u64,
+}
+
+/// What [`Database::collect_garbage`] deleted.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Garbage {
+    /// Chunks, searched or archived, whose file is gone
+    pub chunks: u64,
+    /// Index entries and per-file rows of chunks and files that are gone
+    pub index_rows: u64,
+    /// Recorded models of collections without chunks
+    pub collections: u64,
+    /// Expired usage analytics
+    pub analytics_rows: u64,
+    /// Space the deleted rows took, now free for reuse within the file
+    pub freed_bytes: u64,
+}
+
+/// What [`Database::check_consistency`] found, by chunk id unless noted.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Inconsistencies {
+    /// Chunks whose file is no longer in the index
+    pub orphaned: Vec<i64>,
+    /// Chunks whose embedding doesn't have [`EMBEDDING_DIMENSIONS`] values
+    pub bad_embeddings: Vec<i64>,
+    /// Chunks ending before they start
+    pub bad_offsets: Vec<i64>,
+    /// Chunks missing from the full-text index
+    pub missing_fts: Vec<i64>,
+    /// Full-text entries without a chunk
+    pub stray_fts: Vec<i64>,
+    /// Embedded chunks missing from a vector index
+    pub missing_vectors: Vec<i64>,
+    /// Vector index entries without an embedded chunk
+    pub stray_vectors: Vec<i64>,
+    /// Paths of files whose chunks reach past their end, though unchanged
+    /// since they were indexed; filled in by the caller, which reads them
+    pub truncated_files: Vec<String>,
+}
+
+impl Inconsistencies {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// What one collection holds, see [`Database::collection_stats`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CollectionStats {
+    pub name: String,
+    pub files: u64,
+    pub chunks: u64,
+}
+
+/// Time spent indexing a file, by stage, in microseconds.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct StageTimings {
+    /// Running plugins and splitting the content into chunks
+    pub chunking_us: u64,
+    /// Computing chunk embeddings
+    pub embedding_us: u64,
+    /// Writing the file and its chunks to the database
+    pub storage_us: u64,
+}
+
+impl StageTimings {
+    pub fn total_us(&self) -> u64 {
+        self.chunking_us + self.embedding_us + self.storage_us
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FileTiming {
+    pub path: String,
+    #[serde(flatten)]
+    pub timings: StageTimings,
+}
+
+/// Indexing time across the index, see [`Database::index_timing_stats`].
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TimingStats {
+    /// Indexed files with recorded timings
+    pub files: u64,
+    pub total: StageTimings,
+    /// Mean per file
+    pub average: StageTimings,
+    pub slowest: Vec<FileTiming>,
+}
+
+/// A stage of a query, see [`QueryProfile`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryStage {
+    /// Embedding the query text
+    Embedding,
+    /// Reading candidate rows from the vector, bit and full-text indexes
+    CandidateFetch,
+    /// Filtering candidates and scoring them by similarity, recency and
+    /// frequency
+    Scoring,
+    /// Fusing and merging rankings: hybrid and ensemble RRF, collections
+    Rerank,
+}
+
+/// Where one query's time went. Set as [`SearchOptions::profile`], it is
+/// shared by every search the query runs, e.g. one per collection or
+/// ensemble variant, and each adds to it.
+#[derive(Debug, Default)]
+pub struct QueryProfile {
+    embedding_us: AtomicU64,
+    candidate_fetch_us: AtomicU64,
+    scoring_us: AtomicU64,
+    rerank_us: AtomicU64,
+    rows_scanned: AtomicU64,
+    rows_filtered: AtomicU64,
+}
+
+impl QueryProfile {
+    /// Add the time since `started` to `stage`.
+    pub fn record(&self, stage: QueryStage, started: Instant) {
+        let total = match stage {
+            QueryStage::Embedding => &self.embedding_us,
+            QueryStage::CandidateFetch => &self.candidate_fetch_us,
+            QueryStage::Scoring => &self.scoring_us,
+            QueryStage::Rerank => &self.rerank_us,
+        };
+        total.fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Count `scanned` candidate rows, `filtered` of them rejected.
+    pub fn add_rows(&self, scanned: usize, filtered: usize) {
+        self.rows_scanned
+            .fetch_add(scanned as u64, Ordering::Relaxed);
+        self.rows_filtered
+            .fetch_add(filtered as u64, Ordering::Relaxed);
+    }
+
+    pub fn timings(&self) -> QueryTimings {
+        let ms = |us: &AtomicU64| us.load(Ordering::Relaxed) as f64 / 1000.0;
+        QueryTimings {
+            embedding_ms: ms(&self.embedding_us),
+            candidate_fetch_ms: ms(&self.candidate_fetch_us),
+            scoring_ms: ms(&self.scoring_us),
+            rerank_ms: ms(&self.rerank_us),
+            rows_scanned: self.rows_scanned.load(Ordering::Relaxed),
+            rows_filtered: self.rows_filtered.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A [`QueryProfile`] read out, in milliseconds.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct QueryTimings {
+    pub embedding_ms: f64,
+    pub candidate_fetch_ms: f64,
+    pub scoring_ms: f64,
+    pub rerank_ms: f64,
+    /// Candidate rows read from the indexes
+    pub rows_scanned: u64,
+    /// Candidate rows rejected by filters or `min_score`
+    pub rows_filtered: u64,
+}
+
+/// Queries from one interface on one day, see [`Database::query_stats`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QueryStats {
+    /// `YYYY-MM-DD`, UTC
+    pub day: String,
+    pub source: String,
+    pub queries: u64,
+    pub failed: u64,
+    /// Time spent answering them, in microseconds
+    pub total_us: u64,
+}
+
+/// How often queries returned a file's chunks, see [`Database::chunk_heat`].
+#[derive(Serialize, Debug, Clone)]
+pub struct FileHeat {
+    pub file_id: i64,
+    pub path: String,
+    /// Times any of its chunks was returned
+    pub returns: u64,
+    /// Chunks returned at least once
+    pub chunks_returned: u64,
+    pub chunks: u64,
+    /// Seconds since the epoch
+    pub last_returned: u64,
+    /// Its most returned chunks, most returned first
+    pub hottest: Vec<ChunkHeat>,
+}
+
+/// How often queries returned a chunk.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChunkHeat {
+    pub id: i64,
+    pub start_offset: u64,
+    pub end_offset: u64,
+    pub returns: u64,
+    /// Seconds since the epoch
+    pub last_returned: u64,
+}
+
+/// A file a collection's retention limits removed from the index, see
+/// [`Database::evict_file`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Eviction {
+    pub path: String,
+    pub collection: String,
+    /// Which limit it was evicted for, e.g. `over max_chunks = 1000`
+    pub reason: String,
+    /// Chunks removed with it
+    pub chunks: u64,
+    /// Seconds since the epoch
+    pub evicted_at: u64,
+}
+
+/// A file of a collection, see [`Database::collection_files`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectionFile {
+    pub path: String,
+    pub last_modified: u64,
+    /// When its chunks were last returned by a query or it was modified,
+    /// whichever is later
+    pub last_used: u64,
+    pub chunks: u64,
+    /// Chunk content, embeddings and metadata, in bytes
+    pub bytes: u64,
+}
+
+/// Index size as last recorded on a day, see [`Database::index_history`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IndexSnapshot {
+    /// `YYYY-MM-DD`, UTC
+    pub day: String,
+    pub files: u64,
+    pub chunks: u64,
+    pub db_size: u64,
+}
+
+/// The daemon's last startup scan, see [`Database::scan_record`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanRecord {
+    pub started_at: u64,
+    /// When progress was last recorded
+    pub updated_at: u64,
+    pub discovered: u64,
+    pub indexed: u64,
+    pub finished: bool,
+}
+
+/// The running daemon's backlog, see [`Database::watch_status`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchStatus {
+    pub updated_at: u64,
+    pub last_event_at: Option<u64>,
+    pub pending: u64,
+}
+
+/// One entry of the index changelog, see [`Database::changes_since`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IndexChange {
+    /// Position in the log; later changes have larger numbers
+    pub seq: i64,
+    /// When it happened, seconds since the epoch
+    pub at: u64,
+    /// `file` or `chunk`
+    pub kind: String,
+    /// `add`, `update` or `delete`
+    pub op: String,
+    pub path: String,
+    /// Set for chunk changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_id: Option<i64>,
+}
+
+/// An indexed file row
+pub struct FileRecord {
+    pub id: i64,
+    pub path: String,
+    pub last_modified: u64,
+    pub last_indexed: Option<u64>,
+}
+
+/// Per-file index statistics
+pub struct FileStats {
+    pub path: String,
+    pub chunks: u64,
+    pub last_indexed: Option<u64>,
+    pub tags: Vec<String>,
+}
+
+/// Where a chunk lies in its file.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChunkSpan {
+    /// Byte range of the chunk
+    pub start: u64,
+    pub end: u64,
+    /// First and last line of the chunk, from 1, unless its offsets are into
+    /// text extracted from the file
+    pub lines: Option<(usize, usize)>,
+}
+
+/// A chunk as stored in the database
+pub struct StoredChunk {
+    pub id: i64,
+    pub start: u64,
+    pub end: u64,
+    pub content: String,
+    pub metadata: Option<String>,
+    pub has_embedding: bool,
+}
+
+/// A chunk together with the file it came from
+pub struct ChunkRecord {
+    pub chunk: StoredChunk,
+    pub file_path: String,
+    pub last_modified: u64,
+}
+
+/// A predicate on a key of the chunk metadata JSON, evaluated with SQLite JSON1.
+///
+/// Keys may be dotted (`a.b`) to reach nested objects. All comparisons that are
+/// set must hold; a filter with no comparisons only requires the key to exist.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MetadataFilter {
+    pub key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eq: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ne: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gt: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gte: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lt: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lte: Option<f64>,
+    /// Matches when the key holds an array containing this value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contains: Option<Value>,
+}
+
+impl MetadataFilter {
+    /// JSON path for the key, with each segment quoted so keys can't inject syntax.
+    fn json_path(&self) -> String {
+        let mut path = String::from("$");
+        for segment in self.key.split('.') {
+            path.push_str(&format!(".\"{}\"", segment.replace('"', "")));
+        }
+        path
+    }
+}
+
+/// Store an embedding as little-endian `f32`s.
+fn encode_embedding(embedding: &[f32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(embedding.len() * 4);
+    for val in embedding {
+        bytes.extend_from_slice(&val.to_le_bytes());
+    }
+    bytes
+}
+
+#[allow(clippy::missing_transmute_annotations)]
+fn load_sqlite_vec() {
+    INIT_SQLITE_VEC.call_once(|| unsafe {
+        sqlite3_auto_extension(Some(std::mem::transmute(sqlite3_vec_init as *const ())));
+    });
+}
+
+fn chunk_ids(conn: &Connection, sql: &str) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(sql)?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(ids)
+}
+
+fn integrity_problems(conn: &Connection, quick: bool) -> Result<Vec<String>> {
+    let pragma = if quick {
+        "PRAGMA quick_check"
+    } else {
+        "PRAGMA integrity_check"
+    };
+    let mut stmt = conn.prepare(pragma)?;
+    let problems = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(problems.into_iter().filter(|p| p != "ok").collect())
+}
+
+/// Explain a failure to open the database at `path`.
+fn open_error(path: &Path, e: StorageError) -> StorageError {
+    match e {
+        StorageError::Corrupt(source) => StorageError::CorruptFile {
+            path: path.to_path_buf(),
+            source,
+        },
+        StorageError::Locked(_) => StorageError::LockedFile {
+            path: path.to_path_buf(),
+        },
+        e => StorageError::Open {
+            path: path.to_path_buf(),
+            source: Box::new(e),
+        },
+    }
+}
+
+/// Delete a file's chunks along with their vectors and search entries.
+fn clear_chunks(conn: &Connection, file_id: i64) -> Result<()> {
+    // Delete from vec0 first
+    conn.execute(
+        "DELETE FROM chunks_vec WHERE chunk_id IN (SELECT id FROM chunks WHERE file_id = ?1)",
+        params![file_id],
+    )?;
+    conn.execute(
+        "DELETE FROM chunks_vec_bits WHERE chunk_id IN (SELECT id FROM chunks WHERE file_id = ?1)",
+        params![file_id],
+    )?;
+    // Delete from FTS first (using subquery)
+    conn.execute(
+        "DELETE FROM chunks_fts WHERE rowid IN (SELECT id FROM chunks WHERE file_id = ?1)",
+        params![file_id],
+    )?;
+    conn.execute(
+        "DELETE FROM reembed_staging WHERE chunk_id IN (SELECT id FROM chunks WHERE file_id = ?1)",
+        params![file_id],
+    )?;
+    conn.execute(
+        "DELETE FROM chunk_returns WHERE chunk_id IN (
+            SELECT id FROM chunks WHERE file_id = ?1
+            UNION ALL SELECT id FROM chunks_archive WHERE file_id = ?1
+        )",
+        params![file_id],
+    )?;
+    conn.execute(
+        "DELETE FROM chunks_archive WHERE file_id = ?1",
+        params![file_id],
+    )?;
+    conn.execute("DELETE FROM chunks WHERE file_id = ?1", params![file_id])?;
+    Ok(())
+}
+
+/// Insert a chunk with the searched tables' entries for it, as `id` if
+/// given. Returns its id.
+/// The [`ChunkSpan`] in the four columns from `first`: start and end offset,
+/// start and end line.
+fn chunk_span(row: &rusqlite::Row, first: usize) -> rusqlite::Result<ChunkSpan> {
+    let start_line: Option<usize> = row.get(first + 2)?;
+    let end_line: Option<usize> = row.get(first + 3)?;
+    Ok(ChunkSpan {
+        start: row.get(first)?,
+        end: row.get(first + 1)?,
+        lines: start_line.zip(end_line),
+    })
+}
+
+/// Add the `columns` (each a name and type) that `table` lacks, as in an
+/// index created before they were.
+fn add_missing_columns(conn: &Connection, table: &str, columns: &[&str]) -> Result<()> {
+    for column in columns {
+        let name = column.split_whitespace().next().unwrap_or(column);
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+            params![table, name],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            conn.execute(&format!("ALTER TABLE {} ADD COLUMN {}", table, column), [])?;
+        }
+    }
+    Ok(())
+}
+
+/// Add the `collection` column to `table` in an index created before it
+/// was, filled in from each chunk's metadata.
+fn add_collection_column(conn: &Connection, table: &str) -> Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = 'collection'",
+        params![table],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN collection TEXT", table),
+            [],
+        )?;
+        conn.execute(
+            &format!(
+                "UPDATE {} SET collection = json_extract(metadata, '$.collection')
+                 WHERE json_valid(metadata)",
+                table
+            ),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_chunk(
+    conn: &Connection,
+    id: Option<i64>,
+    (file_id, span): (i64, ChunkSpan),
+    content: &str,
+    embedding: Option<&[u8]>,
+    metadata: Option<&str>,
+) -> Result<i64> {
+    let (start_line, end_line) = span.lines.unzip();
+    // Archived chunks keep their ids, so new chunks must not take them
+    conn.execute(
+        "INSERT INTO chunks (id, file_id, start_offset, end_offset, start_line, end_line,
+                             content, embedding, metadata, collection)
+         VALUES (COALESCE(?1, (SELECT MAX(id) + 1 FROM chunks_archive
+                               WHERE id >= (SELECT COALESCE(MAX(id), 0) FROM chunks))),
+                 ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, json_extract(?9, '$.collection'))",
+        params![
+            id, file_id, span.start, span.end, start_line, end_line, content, embedding, metadata
+        ],
+    )?;
+    let chunk_id = conn.last_insert_rowid();
+
+    // Insert into vec0
+    if let Some(embedding) = embedding {
+        conn.execute(
+            "INSERT INTO chunks_vec (chunk_id, embedding) VALUES (?1, ?2)",
+            params![chunk_id, embedding],
+        )?;
+        conn.execute(
+            "INSERT INTO chunks_vec_bits (chunk_id, embedding)
+             VALUES (?1, vec_quantize_binary(?2))",
+            params![chunk_id, embedding],
+        )?;
+    }
+
+    // Insert into FTS
+    conn.execute(
+        "INSERT INTO chunks_fts (rowid, content) VALUES (?1, ?2)",
+        params![chunk_id, content],
+    )?;
+    Ok(chunk_id)
+}
+
+/// Move the archived chunks with the ids in the JSON array `ids`, or all of
+/// them, back to the searched tables. Returns how many moved.
+fn restore_archived(conn: &Connection, ids: Option<&str>) -> Result<usize> {
+    type Row = (i64, i64, ChunkSpan, Option<String>, Vec<u8>);
+    let rows: Vec<Row> = conn
+        .prepare(
+            "SELECT id, file_id, start_offset, end_offset, start_line, end_line, metadata, data
+             FROM chunks_archive
+             WHERE ?1 IS NULL OR id IN (SELECT value FROM json_each(?1))",
+        )?
+        .query_map(params![ids], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                chunk_span(row, 2)?,
+                row.get(6)?,
+                row.get(7)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    for (id, file_id, span, metadata, data) in &rows {
+        let (content, embedding) = unpack_archived(data)?;
+        conn.execute("DELETE FROM chunks_archive WHERE id = ?1", params![id])?;
+        insert_chunk(
+            conn,
+            Some(*id),
+            (*file_id, *span),
+            &content,
+            Some(&embedding),
+            metadata.as_deref(),
+        )?;
+    }
+    Ok(rows.len())
+}
+
+/// Deflate the content and embedding of a chunk for the archive.
+fn pack_archived(content: &str, embedding: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&(content.len() as u32).to_le_bytes())?;
+    encoder.write_all(content.as_bytes())?;
+    encoder.write_all(embedding)?;
+    Ok(encoder.finish()?)
+}
+
+/// The content and embedding bytes packed by [`pack_archived`].
+fn unpack_archived(data: &[u8]) -> Result<(String, Vec<u8>)> {
+    let mut bytes = Vec::new();
+    DeflateDecoder::new(data).read_to_end(&mut bytes)?;
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Bad archived chunk");
+    let (len, rest) = bytes.split_first_chunk::<4>().ok_or_else(invalid)?;
+    let len = u32::from_le_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(invalid().into());
+    }
+    let (content, embedding) = rest.split_at(len);
+    let content = String::from_utf8(content.to_vec()).map_err(|_| invalid())?;
+    Ok((content, embedding.to_vec()))
+}
+
+/// Cosine distance between two embeddings, as `vec_distance_cosine`.
+fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    1.0 - dot / (norm(a) * norm(b))
+}
+
+/// Remove the file indexed at `path`, its chunks and everything recorded
+/// about it. Returns whether it was indexed.
+fn remove_file(conn: &Connection, path: &str) -> Result<bool> {
+    for table in [
+        "index_settings",
+        "index_errors",
+        "index_timings",
+        "content_hashes",
+    ] {
+        conn.execute(
+            &format!("DELETE FROM {} WHERE path = ?1", table),
+            params![path],
+        )?;
+    }
+    let file_id: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM files WHERE path = ?1",
+            params![path],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(file_id) = file_id else {
+        return Ok(false);
+    };
+    clear_chunks(conn, file_id)?;
+    conn.execute("DELETE FROM files WHERE id = ?1", params![file_id])?;
+    Ok(true)
+}
+
+fn eviction_from_row(row: &rusqlite::Row) -> rusqlite::Result<Eviction> {
+    Ok(Eviction {
+        path: row.get(0)?,
+        collection: row.get(1)?,
+        reason: row.get(2)?,
+        chunks: row.get(3)?,
+        evicted_at: row.get(4)?,
+    })
+}
+
+/// SQL condition selecting the chunks (aliased `c`) of `collection`, bound to
+/// the returned parameter. The default collection (`None`) holds every chunk
+/// not tagged with one of the `configured` collections.
+fn collection_condition(collection: Option<&str>, configured: &[String]) -> (&'static str, String) {
+    match collection {
+        Some(name) => ("c.collection = ?1", name.to_string()),
+        None => (
+            "COALESCE(c.collection, '') NOT IN (SELECT value FROM json_each(?1))",
+            serde_json::to_string(configured).unwrap_or_default(),
+        ),
+    }
+}
+
+/// Read an embedding stored as little-endian `f32`s.
+fn decode_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .as_chunks::<4>()
+        .0
+        .iter()
+        .map(|b| f32::from_le_bytes(*b))
+        .collect()
+}
+
+/// Convert a JSON scalar into a value SQLite compares equal to `json_extract` output.
+fn json_to_sql(value: &Value) -> Box<dyn rusqlite::ToSql> {
+    match value {
+        Value::String(s) => Box::new(s.clone()),
+        Value::Bool(b) => Box::new(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Box::new(i),
+            None => Box::new(n.as_f64().unwrap_or_default()),
+        },
+        Value::Null => Box::new(rusqlite::types::Null),
+        other => Box::new(other.to_string()),
+    }
+}
+
+/// Append `AND` clauses for metadata filters. Placeholders are numbered after
+/// the parameters already pushed, so this works for both `?` and `?N` queries.
+fn push_metadata_filters(
+    sql: &mut String,
+    params: &mut Vec<Box<dyn rusqlite::ToSql>>,
+    filters: &[MetadataFilter],
+) {
+    for filter in filters {
+        let path = filter.json_path();
+        let mut clauses = 0;
+
+        let mut compare = |op: &str, value: Box<dyn rusqlite::ToSql>| {
+            params.push(Box::new(path.clone()));
+            let path_idx = params.len();
+            params.push(value);
+            sql.push_str(&format!(
+                " AND json_extract(c.metadata, ?{}) {} ?{}",
+                path_idx,
+                op,
+                params.len()
+            ));
+        };
+
+        if let Some(v) = &filter.eq {
+            compare("=", json_to_sql(v));
+            clauses += 1;
+        }
+        if let Some(v) = &filter.ne {
+            compare("IS NOT", json_to_sql(v));
+            clauses += 1;
+        }
+        if let Some(v) = filter.gt {
+            compare(">", Box::new(v));
+            clauses += 1;
+        }
+        if let Some(v) = filter.gte {
+            compare(">=", Box::new(v));
+            clauses += 1;
+        }
+        if let Some(v) = filter.lt {
+            compare("<", Box::new(v));
+            clauses += 1;
+        }
+        if let Some(v) = filter.lte {
+            compare("<=", Box::new(v));
+            clauses += 1;
+        }
+        if let Some(v) = &filter.contains {
+            params.push(Box::new(path.clone()));
+            let path_idx = params.len();
+            params.push(json_to_sql(v));
+            sql.push_str(&format!(
+                " AND EXISTS (SELECT 1 FROM json_each(c.metadata, ?{}) WHERE value = ?{})",
+                path_idx,
+                params.len()
+            ));
+            clauses += 1;
+        }
+        if clauses == 0 {
+            params.push(Box::new(path));
+            sql.push_str(&format!(
+                " AND json_type(c.metadata, ?{}) IS NOT NULL",
+                params.len()
+            ));
+        }
+    }
+}
+
+/// Append a clause keeping only chunks carrying at least one of `tags`, either
+/// directly or through a tag on their file or an enclosing directory.
+fn push_tag_filter(sql: &mut String, params: &mut Vec<Box<dyn rusqlite::ToSql>>, tags: &[String]) {
+    if tags.is_empty() {
+        return;
+    }
+    let start = params.len() + 1;
+    for tag in tags {
+        params.push(Box::new(tag.clone()));
+    }
+    let placeholders = (start..start + tags.len())
+        .map(|i| format!("?{}", i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    sql.push_str(&format!(
+        " AND (EXISTS (SELECT 1 FROM chunk_tags ct WHERE ct.chunk_id = c.id AND ct.tag IN ({0}))
+           OR EXISTS (SELECT 1 FROM path_tags pt WHERE pt.tag IN ({0}) AND {1}))",
+        placeholders, PATH_TAG_MATCH
+    ));
+}
 
-        scored_chunks.truncate(limit);
+/// Append the clauses for the filters of `options` that SQL applies to a
+/// chunk (`c`) and its file (`f`): time range, metadata, tags, symbol and
+/// collections. The rest are checked by [`SearchOptions::allows_path`].
+fn push_chunk_filters(
+    sql: &mut String,
+    params: &mut Vec<Box<dyn rusqlite::ToSql>>,
+    options: &SearchOptions,
+) {
+    if let Some(start) = options.start_time {
+        params.push(Box::new(start));
+        sql.push_str(&format!(" AND f.last_modified >= ?{}", params.len()));
+    }
+    if let Some(end) = options.end_time {
+        params.push(Box::new(end));
+        sql.push_str(&format!(" AND f.last_modified <= ?{}", params.len()));
+    }
+    if let Some(filters) = &options.metadata_filters {
+        push_metadata_filters(sql, params, filters);
+    }
+    if let Some(tags) = &options.tags {
+        push_tag_filter(sql, params, tags);
+    }
+    if let Some(symbol) = &options.symbol {
+        params.push(Box::new(symbol.clone()));
+        sql.push_str(&format!(
+            " AND json_extract(c.metadata, '$.symbol') = ?{}",
+            params.len()
+        ));
+    }
+    if let Some(collections) = &options.collections {
+        params.push(Box::new(
+            serde_json::to_string(collections).unwrap_or_default(),
+        ));
+        // Spelled out so the collection index applies
+        sql.push_str(&format!(
+            " AND (c.collection IN (SELECT value FROM json_each(?{0}))
+                   OR (c.collection IS NULL
+                       AND '{1}' IN (SELECT value FROM json_each(?{0}))))",
+            params.len(),
+            crate::staleness::DEFAULT_COLLECTION,
+        ));
+    }
+}
+
+/// Lowercase extension of `path`, as results report it in `file_type`.
+fn file_type(path: &str) -> String {
+    path.rsplit('.').next().unwrap_or("").to_lowercase()
+}
+
+/// SQL condition: the path tag `pt` applies to the file `f`. Its path, as
+/// [`normalize_tag_path`] left it, is the file's or a directory the file's
+/// starts with, so `src` tags `./src/main.rs` but not `./lib/src/main.rs`.
+const PATH_TAG_MATCH: &str = "(pt.path = ''
+    OR (CASE WHEN substr(f.path, 1, 2) = './' THEN substr(f.path, 3) ELSE f.path END) = pt.path
+    OR substr(CASE WHEN substr(f.path, 1, 2) = './' THEN substr(f.path, 3) ELSE f.path END,
+              1, length(pt.path) + 1) = pt.path || '/')";
 
-        Ok(scored_chunks)
+/// Strip `./` prefixes and trailing separators so tag paths compare on components.
+fn normalize_tag_path(path: &str) -> String {
+    let trimmed = path.trim_start_matches("./").trim_end_matches('/');
+    if trimmed == "." {
+        String::new()
+    } else {
+        trimmed.to_string()
     }
 }
 
-/// Database statistics
-pub struct DbStats {
-    pub file_count: u64,
-    pub chunk_count: u64,
-    pub db_size: u64,
+/// A tag attached to a path prefix or a single chunk
+#[derive(Clone, Debug, Serialize)]
+pub struct TagEntry {
+    pub tag: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_id: Option<i64>,
 }
 
 /// Search options for enhanced chunk search
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct SearchOptions {
     pub limit: Option<usize>,
     pub start_time: Option<u64>,
@@ -549,6 +3901,92 @@ pub struct SearchOptions {
     pub frequency_weight: Option<f32>,
     /// Number of context lines to include before/after match (default 0)
     pub context_lines: Option<usize>,
+    /// Predicates on the chunk metadata JSON
+    pub metadata_filters: Option<Vec<MetadataFilter>>,
+    /// Only return chunks carrying any of these tags
+    pub tags: Option<Vec<String>>,
+    /// Only return chunks defining this symbol, such as a function or class
+    /// name
+    pub symbol: Option<String>,
+    /// Only return chunks from files under one of these directories
+    pub roots: Option<Vec<PathBuf>>,
+    /// Only return chunks in one of these collections (`default` for chunks
+    /// outside every configured collection)
+    pub collections: Option<Vec<String>>,
+    /// Search the chunks as they were at this time (seconds since the
+    /// epoch) instead of now, from `chunk_history`
+    pub as_of: Option<u64>,
+    /// Fuse embedding matches with full-text matches, see
+    /// [`Database::search_chunks_hybrid`]
+    pub hybrid: bool,
+    /// Score only the nearest chunks by [`Database::nearest_chunks`]
+    /// instead of every chunk
+    pub approximate: bool,
+    /// Also score the chunks moved to the archive by
+    /// [`Database::archive_chunks`], by embedding only
+    pub include_archived: bool,
+    /// Collects where the query's time goes
+    pub profile: Option<Arc<QueryProfile>>,
+    /// Attach a [`RankExplanation`] to each result
+    pub explain: bool,
+}
+
+impl SearchOptions {
+    /// Add the time since `started` to `stage` of the profile, if any.
+    pub fn record(&self, stage: QueryStage, started: Instant) {
+        if let Some(profile) = &self.profile {
+            profile.record(stage, started);
+        }
+    }
+
+    /// Whether a chunk of `file_path`, with extension `file_type`, passes
+    /// the file type, path and root filters.
+    fn allows_path(&self, file_path: &str, file_type: &str) -> bool {
+        if let Some(types) = &self.file_types {
+            if !types.iter().any(|t| t.to_lowercase() == file_type) {
+                return false;
+            }
+        }
+        if let Some(paths) = &self.paths {
+            if !paths.iter().any(|p| file_path.contains(p)) {
+                return false;
+            }
+        }
+        under_roots(file_path, self.roots.as_deref())
+    }
+
+    /// Names of the filters these options apply, for
+    /// [`RankExplanation::filters_passed`].
+    fn filter_names(&self) -> Vec<String> {
+        let filters = [
+            (
+                "time_range",
+                self.start_time.is_some() || self.end_time.is_some(),
+            ),
+            ("file_types", self.file_types.is_some()),
+            ("paths", self.paths.is_some()),
+            ("roots", self.roots.is_some()),
+            ("metadata_filters", self.metadata_filters.is_some()),
+            ("tags", self.tags.is_some()),
+            ("symbol", self.symbol.is_some()),
+            ("collections", self.collections.is_some()),
+            ("min_score", self.min_score.is_some()),
+        ];
+        filters
+            .into_iter()
+            .filter(|(_, applied)| *applied)
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+}
+
+/// Whether `file_path` lies under one of `roots` (always true without roots).
+/// Compares whole components, so `/repo` does not match `/repo2/x.rs`.
+pub fn under_roots(file_path: &str, roots: Option<&[PathBuf]>) -> bool {
+    match roots {
+        Some(roots) => roots.iter().any(|r| Path::new(file_path).starts_with(r)),
+        None => true,
+    }
 }
 
 /// Enhanced search result with metadata
@@ -560,18 +3998,185 @@ pub struct SearchResult {
     pub file_path: String,
     pub file_type: String,
     pub last_modified: u64,
+    /// Chunk metadata JSON
+    pub metadata: Option<String>,
+    /// Byte range of the chunk in its source
+    pub start_offset: u64,
+    pub end_offset: u64,
     /// Context lines before the matched content
     #[allow(dead_code)]
     pub context_before: Option<String>,
     /// Context lines after the matched content
     #[allow(dead_code)]
     pub context_after: Option<String>,
-    /// Starting line number in the source file
-    #[allow(dead_code)]
+    /// First line of the chunk in its file, if recorded when it was indexed
     pub line_start: Option<usize>,
-    /// Ending line number in the source file
-    #[allow(dead_code)]
+    /// Last line of the chunk in its file
     pub line_end: Option<usize>,
+    /// Why the result ranked where it did, with [`SearchOptions::explain`]
+    pub explanation: Option<RankExplanation>,
+}
+
+/// The parts of a result's rank. Without fusion, `dense_score`,
+/// `recency_boost` and `frequency_boost` add up to the result's score.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct RankExplanation {
+    /// Cosine similarity to the query embedding
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dense_score: Option<f32>,
+    /// Position among the embedding matches, from 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dense_rank: Option<usize>,
+    /// Full-text BM25 score, higher is better; hybrid search only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bm25_score: Option<f32>,
+    /// Position among the full-text matches, from 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyword_rank: Option<usize>,
+    /// Added to the dense score for how recently the file changed
+    pub recency_boost: f32,
+    /// Added for how often the file was returned before
+    pub frequency_boost: f32,
+    /// Positions gained (or lost, when negative) by the last fusion of
+    /// rankings, hybrid or ensemble
+    pub rerank_delta: i64,
+    /// Filters the chunk was checked against, all passed
+    pub filters_passed: Vec<String>,
+}
+
+/// Match counts used for drill-down filters, from [`Database::facets`].
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct Facets {
+    /// Matches per file extension
+    pub file_types: BTreeMap<String, usize>,
+    /// Matches per top-level directory beneath the watched root
+    pub directories: BTreeMap<String, usize>,
+    /// Matches per collection (`default` for chunks outside every
+    /// configured collection)
+    pub collections: BTreeMap<String, usize>,
+}
+
+impl Facets {
+    /// Count `matches` chunks of `file_path` in `collection`. Paths are made
+    /// relative to the first of `roots` containing them; files directly in
+    /// a root are counted under ".".
+    fn add(
+        &mut self,
+        file_path: &str,
+        file_type: String,
+        collection: String,
+        roots: &[PathBuf],
+        matches: usize,
+    ) {
+        *self.file_types.entry(file_type).or_insert(0) += matches;
+        *self.collections.entry(collection).or_insert(0) += matches;
+
+        let path = Path::new(file_path);
+        let relative = roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        let mut components = relative
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)));
+        let dir = match (components.next(), components.next()) {
+            (Some(first), Some(_)) => first.as_os_str().to_string_lossy().to_string(),
+            _ => ".".to_string(),
+        };
+        *self.directories.entry(dir).or_insert(0) += matches;
+    }
+}
+
+/// Number of tags reported per directory by [`summarize_tree`].
+const TOP_TAGS: usize = 5;
+
+/// What the index holds for one directory, counting everything below it.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DirSummary {
+    /// Path relative to the requested root ("." for the root itself)
+    pub path: String,
+    pub files: usize,
+    pub chunks: u64,
+    /// Most recent index time of any file below the directory
+    pub last_indexed: Option<u64>,
+    /// Most common tags by number of files carrying them
+    pub top_tags: Vec<TagCount>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TagCount {
+    pub tag: String,
+    pub files: usize,
+}
+
+/// Roll `files` under `root` up into one summary per directory, parents
+/// before children. Directories more than `depth` levels below the root are
+/// left out, though their files still count towards their ancestors.
+pub fn summarize_tree(files: &[FileStats], root: &Path, depth: Option<usize>) -> Vec<DirSummary> {
+    #[derive(Default)]
+    struct Acc {
+        files: usize,
+        chunks: u64,
+        last_indexed: Option<u64>,
+        tags: HashMap<String, usize>,
+    }
+
+    let root = without_cur_dir(root);
+    let mut dirs: BTreeMap<PathBuf, Acc> = BTreeMap::new();
+    for file in files
+        .iter()
+        .filter(|f| !crate::paths::is_virtual_path(&f.path))
+    {
+        let path = without_cur_dir(Path::new(&file.path));
+        let Ok(relative) = path.strip_prefix(&root) else {
+            continue;
+        };
+        let Some(parent) = relative.parent() else {
+            continue;
+        };
+        for dir in parent.ancestors() {
+            if depth.is_some_and(|d| dir.components().count() > d) {
+                continue;
+            }
+            let acc = dirs.entry(dir.to_path_buf()).or_default();
+            acc.files += 1;
+            acc.chunks += file.chunks;
+            acc.last_indexed = acc.last_indexed.max(file.last_indexed);
+            for tag in &file.tags {
+                *acc.tags.entry(tag.clone()).or_insert(0) += 1;
+            }
+        }
+    }
+
+    dirs.into_iter()
+        .map(|(dir, acc)| {
+            let mut top_tags: Vec<TagCount> = acc
+                .tags
+                .into_iter()
+                .map(|(tag, files)| TagCount { tag, files })
+                .collect();
+            top_tags.sort_by(|a, b| b.files.cmp(&a.files).then(a.tag.cmp(&b.tag)));
+            top_tags.truncate(TOP_TAGS);
+            let path = dir.to_string_lossy().to_string();
+            DirSummary {
+                path: if path.is_empty() {
+                    ".".to_string()
+                } else {
+                    path
+                },
+                files: acc.files,
+                chunks: acc.chunks,
+                last_indexed: acc.last_indexed,
+                top_tags,
+            }
+        })
+        .collect()
+}
+
+fn without_cur_dir(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
 }
 
 #[cfg(test)]
@@ -581,7 +4186,7 @@ mod tests {
     #[test]
     fn test_database_init() {
         let db = Database::new(":memory:").unwrap();
-        let conn = db.conn.lock().unwrap();
+        let conn = db.reader();
 
         // Check tables exist
         let mut stmt = conn
@@ -597,6 +4202,44 @@ mod tests {
         assert!(tables.contains(&"chunks".to_string()));
     }
 
+    #[test]
+    fn test_readers() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path().join("index.db")).unwrap();
+        assert_eq!(db.readers.len(), READERS);
+
+        // Writes are committed before they return, so readers see them
+        let file_id = db.add_or_update_file("/a.rs", 100).unwrap();
+        let held = db.reader();
+        assert_eq!(db.get_file("/a.rs").unwrap().unwrap().id, file_id);
+        // Writes don't wait for queries
+        db.mark_indexed(file_id).unwrap();
+        assert!(held
+            .execute("DELETE FROM files", [])
+            .is_err_and(|e| e.to_string().contains("readonly")));
+        drop(held);
+        assert!(db
+            .get_file("/a.rs")
+            .unwrap()
+            .unwrap()
+            .last_indexed
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_call() {
+        let db = Database::new(":memory:").unwrap();
+        let id = db
+            .call(|db| db.add_or_update_file("/a.rs", 100))
+            .await
+            .unwrap();
+        assert_eq!(db.get_file_id("/a.rs").unwrap(), Some(id));
+        assert!(db
+            .call(|db| db.add_chunk(-1, 0, 1, "x", None, None))
+            .await
+            .is_err());
+    }
+
     #[test]
     fn test_add_get_file() {
         let db = Database::new(":memory:").unwrap();
@@ -613,61 +4256,473 @@ mod tests {
     }
 
     #[test]
-    fn test_update_file() {
+    fn test_update_file() {
+        let db = Database::new(":memory:").unwrap();
+        let path = "/tmp/test.txt";
+
+        let id1 = db.add_or_update_file(path, 100).unwrap();
+        let id2 = db.add_or_update_file(path, 200).unwrap();
+
+        assert_eq!(id1, id2); // ID should remain same
+
+        let conn = db.reader();
+        let last_mod: u64 = conn
+            .query_row(
+                "SELECT last_modified FROM files WHERE id = ?1",
+                params![id1],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(last_mod, 200);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let db = Database::new(":memory:").unwrap();
+        let path = "/tmp/test.txt";
+        let file_id = db.add_or_update_file(path, 100).unwrap();
+        db.add_chunk(file_id, 0, 5, "hello", None, None).unwrap();
+
+        // Nothing to compare against before the file is indexed
+        assert!(!db.touch_if_unchanged(path, 200, "abc").unwrap());
+        db.mark_indexed(file_id).unwrap();
+        db.record_content_hash(path, "abc").unwrap();
+        assert_eq!(db.files_with_content_hash("abc").unwrap(), vec![path]);
+        assert!(db.files_with_content_hash("def").unwrap().is_empty());
+
+        let now = chrono::Utc::now().timestamp() as u64;
+        assert!(!db.touch_if_unchanged(path, now + 100, "def").unwrap());
+        assert!(db.needs_reindexing(path, now + 100).unwrap());
+        assert!(db.touch_if_unchanged(path, now + 100, "abc").unwrap());
+        assert!(!db.needs_reindexing(path, now + 100).unwrap());
+        assert_eq!(db.get_chunks(file_id).unwrap().len(), 1);
+
+        // A forced re-index isn't skipped
+        db.mark_unindexed(path).unwrap();
+        assert!(!db.touch_if_unchanged(path, now + 100, "abc").unwrap());
+
+        db.delete_file(file_id).unwrap();
+        let file_id = db.add_or_update_file(path, 300).unwrap();
+        db.mark_indexed(file_id).unwrap();
+        assert!(!db.touch_if_unchanged(path, 300, "abc").unwrap());
+    }
+
+    #[test]
+    fn test_consistency() {
+        let db = Database::new(":memory:").unwrap();
+        let embedding = vec![0.5; EMBEDDING_DIMENSIONS];
+        let a = db.add_or_update_file("/a.rs", 100).unwrap();
+        let b = db.add_or_update_file("/b.rs", 100).unwrap();
+        for (file_id, start) in [(a, 0), (a, 10), (b, 0), (b, 10)] {
+            db.add_chunk(file_id, start, start + 10, "chunk", Some(&embedding), None)
+                .unwrap();
+            db.mark_indexed(file_id).unwrap();
+        }
+        assert!(db.check_consistency().unwrap().is_empty());
+
+        let ids: Vec<i64> = db.get_chunks(a).unwrap().iter().map(|c| c.id).collect();
+        let damage = format!(
+            "DELETE FROM chunks_fts WHERE rowid = {0};
+             DELETE FROM chunks_vec_bits WHERE chunk_id = {0};
+             UPDATE chunks SET embedding = x'00', end_offset = 5 WHERE id = {1};
+             INSERT INTO chunks_fts (rowid, content) VALUES (99, 'stray');
+             PRAGMA foreign_keys = OFF;
+             DELETE FROM files WHERE id = {2};
+             PRAGMA foreign_keys = ON;",
+            ids[0], ids[1], b
+        );
+        db.writer
+            .write_alone(move |conn| Ok(conn.execute_batch(&damage)?))
+            .unwrap();
+
+        let found = db.check_consistency().unwrap();
+        assert_eq!(found.orphaned.len(), 2);
+        assert_eq!(found.bad_embeddings, vec![ids[1]]);
+        assert_eq!(found.bad_offsets, vec![ids[1]]);
+        assert_eq!(found.missing_fts, vec![ids[0]]);
+        assert_eq!(found.stray_fts, vec![99]);
+        assert_eq!(found.missing_vectors, vec![ids[0]]);
+        assert!(found.stray_vectors.is_empty());
+
+        db.repair_consistency(&found).unwrap();
+        let left = db.check_consistency().unwrap();
+        assert!(left.is_empty(), "{:?}", left);
+        assert_eq!(db.get_chunks(a).unwrap().len(), 1);
+        // The file is re-indexed for its bad chunk
+        assert!(db.needs_reindexing("/a.rs", 100).unwrap());
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let db = Database::new(":memory:").unwrap();
+        let embedding = vec![0.5; EMBEDDING_DIMENSIONS];
+        let a = db.add_or_update_file("/a.rs", 100).unwrap();
+        let b = db.add_or_update_file("/b.rs", 100).unwrap();
+        db.add_chunk(
+            a,
+            0,
+            10,
+            "kept",
+            Some(&embedding),
+            Some(r#"{"collection":"code"}"#),
+        )
+        .unwrap();
+        db.add_chunk(
+            b,
+            0,
+            10,
+            "left",
+            Some(&embedding),
+            Some(r#"{"collection":"old"}"#),
+        )
+        .unwrap();
+        db.set_collection_model("code", "m", EMBEDDING_DIMENSIONS)
+            .unwrap();
+        db.set_collection_model("old", "m", EMBEDDING_DIMENSIONS)
+            .unwrap();
+        // A file row deleted without its chunks, as by a crash mid-delete
+        db.writer
+            .write_alone(move |conn| {
+                conn.execute_batch(&format!(
+                    "PRAGMA foreign_keys = OFF;
+                     DELETE FROM files WHERE id = {};
+                     PRAGMA foreign_keys = ON;",
+                    b
+                ))?;
+                Ok(())
+            })
+            .unwrap();
+
+        let garbage = db.collect_garbage(None).unwrap();
+        assert_eq!(garbage.chunks, 1);
+        // Its vector, bits and full-text entries
+        assert_eq!(garbage.index_rows, 3);
+        assert_eq!(garbage.collections, 1);
+        assert!(db.check_consistency().unwrap().is_empty());
+        assert_eq!(db.get_chunks(a).unwrap().len(), 1);
+        assert_eq!(db.collect_garbage(None).unwrap(), Garbage::default());
+    }
+
+    #[test]
+    fn test_chunks() {
+        let db = Database::new(":memory:").unwrap();
+        let path = "/tmp/test.txt";
+        let file_id = db.add_or_update_file(path, 100).unwrap();
+
+        db.add_chunk(file_id, 0, 10, "chunk1", None, None).unwrap();
+        db.add_chunk(file_id, 10, 20, "chunk2", None, None).unwrap();
+
+        let conn = db.reader();
+        let count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM chunks WHERE file_id = ?1",
+                params![file_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(count, 2);
+
+        // Test cascade delete (simulated by clearing chunks)
+        drop(conn); // unlock
+        db.clear_chunks(file_id).unwrap();
+
+        let conn = db.reader();
+        let count_after: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM chunks WHERE file_id = ?1",
+                params![file_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(count_after, 0);
+    }
+
+    #[test]
+    fn test_get_chunks() {
+        let db = Database::new(":memory:").unwrap();
+        let file_id = db.add_or_update_file("/src/lib.rs", 100).unwrap();
+        let embedding: Vec<f32> = vec![1.0; 384];
+        db.add_chunk(file_id, 10, 20, "second", None, Some("{\"k\":1}"))
+            .unwrap();
+        db.add_chunk(file_id, 0, 10, "first", Some(&embedding), None)
+            .unwrap();
+
+        let file = db.get_file("/src/lib.rs").unwrap().unwrap();
+        assert_eq!(file.id, file_id);
+        assert!(file.last_indexed.is_none());
+        assert_eq!(db.find_files_by_suffix("lib.rs").unwrap().len(), 1);
+        assert!(db.find_files_by_suffix("main.rs").unwrap().is_empty());
+        // Only whole segments match, and `./` is ignored on either side
+        assert!(db.find_files_by_suffix("ib.rs").unwrap().is_empty());
+        assert_eq!(db.find_files_by_suffix("./src/lib.rs").unwrap().len(), 1);
+        db.add_or_update_file("./docs/guide.md", 100).unwrap();
+        assert_eq!(db.find_files_by_suffix("docs/guide.md").unwrap().len(), 1);
+        assert_eq!(db.find_files_by_suffix("./docs/guide.md").unwrap().len(), 1);
+        assert_eq!(db.find_files_by_suffix("guide.md").unwrap().len(), 1);
+
+        let chunks = db.get_chunks(file_id).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].content, "first");
+        assert!(chunks[0].has_embedding);
+        assert!(!chunks[1].has_embedding);
+        assert_eq!(chunks[1].metadata.as_deref(), Some("{\"k\":1}"));
+
+        let record = db.get_chunk(chunks[1].id).unwrap().unwrap();
+        assert_eq!(record.chunk.content, "second");
+        assert_eq!(record.file_path, "/src/lib.rs");
+        assert_eq!(record.last_modified, 100);
+        assert!(db.get_chunk(-1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_delete_file() {
+        let db = Database::new(":memory:").unwrap();
+        let keep = db.add_or_update_file("/keep.rs", 100).unwrap();
+        let drop_id = db.add_or_update_file("/drop.rs", 100).unwrap();
+        let embedding: Vec<f32> = vec![1.0; 384];
+        db.add_chunk(keep, 0, 10, "keep", Some(&embedding), None)
+            .unwrap();
+        db.add_chunk(drop_id, 0, 10, "drop", Some(&embedding), None)
+            .unwrap();
+        assert_eq!(db.count_chunks(drop_id).unwrap(), 1);
+
+        db.delete_file(drop_id).unwrap();
+
+        let files = db.list_files().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "/keep.rs");
+        assert_eq!(db.count_chunks(drop_id).unwrap(), 0);
+
+        let conn = db.reader();
+        let vec_rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM chunks_vec", [], |row| row.get(0))
+            .unwrap();
+        let fts_rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM chunks_fts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(vec_rows, 1);
+        assert_eq!(fts_rows, 1);
+    }
+
+    #[test]
+    fn test_related_chunks() {
+        let db = Database::new(":memory:").unwrap();
+        let file_id = db.add_or_update_file("/src/lib.rs", 100).unwrap();
+        let mut near = vec![1.0; 384];
+        near[0] = 0.9;
+        let mut far = vec![1.0; 384];
+        far[..192].fill(-1.0);
+        db.add_chunk(file_id, 0, 10, "origin", Some(&[1.0; 384]), None)
+            .unwrap();
+        db.add_chunk(file_id, 10, 20, "far", Some(&far), None)
+            .unwrap();
+        db.add_chunk(file_id, 20, 30, "near", Some(&near), None)
+            .unwrap();
+        db.add_chunk(file_id, 30, 40, "unembedded", None, None)
+            .unwrap();
+        let chunks = db.get_chunks(file_id).unwrap();
+
+        let related = db.related_chunks(chunks[0].id, 5).unwrap();
+        let contents: Vec<&str> = related.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(contents, vec!["near", "far"]);
+        assert_eq!(db.related_chunks(chunks[0].id, 1).unwrap().len(), 1);
+        assert!(db.related_chunks(chunks[3].id, 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_preload_vectors() {
+        let db = Database::new(":memory:").unwrap();
+        assert_eq!(db.preload_vectors().unwrap(), 0);
+
+        let file_id = db.add_or_update_file("/a.rs", 100).unwrap();
+        let embedding: Vec<f32> = vec![1.0; 384];
+        db.add_chunk(file_id, 0, 10, "a", Some(&embedding), None)
+            .unwrap();
+        db.add_chunk(file_id, 10, 20, "b", Some(&embedding), None)
+            .unwrap();
+        db.add_chunk(file_id, 20, 30, "c", None, None).unwrap();
+
+        assert_eq!(db.preload_vectors().unwrap(), 2 * 384 * 4);
+    }
+
+    #[test]
+    fn test_metadata_filters() {
+        let db = Database::new(":memory:").unwrap();
+        let file_id = db.add_or_update_file("/docs/guide.md", 100).unwrap();
+        let embedding: Vec<f32> = vec![1.0; 384];
+        db.add_chunk(
+            file_id,
+            0,
+            10,
+            "install guide",
+            Some(&embedding),
+            Some(r#"{"size": 100, "extension": "md", "headers": ["Guide", "Install"]}"#),
+        )
+        .unwrap();
+        db.add_chunk(
+            file_id,
+            10,
+            20,
+            "usage guide",
+            Some(&embedding),
+            Some(r#"{"size": 5000, "extension": "md", "headers": ["Guide", "Usage"]}"#),
+        )
+        .unwrap();
+
+        let search = |filters: Vec<MetadataFilter>| {
+            let options = SearchOptions {
+                limit: Some(10),
+                metadata_filters: Some(filters),
+                ..Default::default()
+            };
+            db.search_chunks_enhanced(&embedding, &options).unwrap()
+        };
+
+        let results = search(vec![MetadataFilter {
+            key: "size".to_string(),
+            gt: Some(1000.0),
+            ..Default::default()
+        }]);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].content, "usage guide");
+
+        let results = search(vec![MetadataFilter {
+            key: "headers".to_string(),
+            contains: Some(serde_json::json!("Install")),
+            ..Default::default()
+        }]);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].content, "install guide");
+
+        let results = search(vec![MetadataFilter {
+            key: "extension".to_string(),
+            eq: Some(serde_json::json!("md")),
+            ..Default::default()
+        }]);
+        assert_eq!(results.len(), 2);
+
+        let results = search(vec![MetadataFilter {
+            key: "missing".to_string(),
+            ..Default::default()
+        }]);
+        assert!(results.is_empty());
+
+        // Hybrid search applies the same predicates to keyword matches
+        let options = SearchOptions {
+            limit: Some(10),
+            metadata_filters: Some(vec![MetadataFilter {
+                key: "size".to_string(),
+                lte: Some(100.0),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+        let results = db
+            .search_chunks_hybrid("guide", &embedding, &options)
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].content, "install guide");
+    }
+
+    #[test]
+    fn test_symbol_filter() {
         let db = Database::new(":memory:").unwrap();
-        let path = "/tmp/test.txt";
-
-        let id1 = db.add_or_update_file(path, 100).unwrap();
-        let id2 = db.add_or_update_file(path, 200).unwrap();
-
-        assert_eq!(id1, id2); // ID should remain same
-
-        let conn = db.conn.lock().unwrap();
-        let last_mod: u64 = conn
-            .query_row(
-                "SELECT last_modified FROM files WHERE id = ?1",
-                params![id1],
-                |row| row.get(0),
+        let file_id = db.add_or_update_file("/src/config.rs", 100).unwrap();
+        let embedding: Vec<f32> = vec![1.0; 384];
+        for (start, content, symbol) in [
+            (0, "fn parse_config() {}", "parse_config"),
+            (20, "fn parse_config_file() {}", "parse_config_file"),
+        ] {
+            let metadata = serde_json::json!({ "symbol": symbol }).to_string();
+            db.add_chunk(
+                file_id,
+                start,
+                start + 20,
+                content,
+                Some(&embedding),
+                Some(&metadata),
             )
             .unwrap();
+        }
 
-        assert_eq!(last_mod, 200);
+        let options = SearchOptions {
+            limit: Some(10),
+            symbol: Some("parse_config".to_string()),
+            ..Default::default()
+        };
+        let results = db.search_chunks_enhanced(&embedding, &options).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].content, "fn parse_config() {}");
+        let results = db
+            .search_chunks_hybrid("parse_config", &embedding, &options)
+            .unwrap();
+        assert_eq!(results.len(), 1);
     }
 
     #[test]
-    fn test_chunks() {
+    fn test_tags() {
         let db = Database::new(":memory:").unwrap();
-        let path = "/tmp/test.txt";
-        let file_id = db.add_or_update_file(path, 100).unwrap();
-
-        db.add_chunk(file_id, 0, 10, "chunk1", None, None).unwrap();
-        db.add_chunk(file_id, 10, 20, "chunk2", None, None).unwrap();
-
-        let conn = db.conn.lock().unwrap();
-        let count: i64 = conn
-            .query_row(
-                "SELECT COUNT(*) FROM chunks WHERE file_id = ?1",
-                params![file_id],
-                |row| row.get(0),
-            )
+        let embedding: Vec<f32> = vec![1.0; 384];
+        let pay = db
+            .add_or_update_file("./src/payments/charge.rs", 100)
             .unwrap();
+        let auth = db.add_or_update_file("./src/auth/login.rs", 100).unwrap();
+        db.add_chunk(pay, 0, 10, "fn charge() {}", Some(&embedding), None)
+            .unwrap();
+        db.add_chunk(auth, 0, 10, "fn login() {}", Some(&embedding), None)
+            .unwrap();
+        let auth_chunk = db.get_chunks(auth).unwrap()[0].id;
 
-        assert_eq!(count, 2);
+        db.add_path_tag("src/payments/", "billing").unwrap();
+        db.add_chunk_tag(auth_chunk, "security").unwrap();
 
-        // Test cascade delete (simulated by clearing chunks)
-        drop(conn); // unlock
-        db.clear_chunks(file_id).unwrap();
+        let search = |tags: &[&str]| {
+            let options = SearchOptions {
+                limit: Some(10),
+                tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+                ..Default::default()
+            };
+            db.search_chunks_enhanced(&embedding, &options).unwrap()
+        };
 
-        let conn = db.conn.lock().unwrap();
-        let count_after: i64 = conn
-            .query_row(
-                "SELECT COUNT(*) FROM chunks WHERE file_id = ?1",
-                params![file_id],
-                |row| row.get(0),
-            )
-            .unwrap();
+        let results = search(&["billing"]);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].file_path.contains("payments"));
+        // Only paths that start with the tagged directory, on whole components
+        for path in [
+            "./lib/src/payments/refund.rs",
+            "./src/payments-old/charge.rs",
+        ] {
+            let id = db.add_or_update_file(path, 100).unwrap();
+            db.add_chunk(id, 0, 10, "fn refund() {}", Some(&embedding), None)
+                .unwrap();
+        }
+        db.add_path_tag("src/auth/login.rs", "entry").unwrap();
+        assert_eq!(search(&["billing"]).len(), 1);
+        assert_eq!(search(&["entry"]).len(), 1);
+        let tagged: Vec<String> = db
+            .file_stats()
+            .unwrap()
+            .into_iter()
+            .filter(|f| !f.tags.is_empty())
+            .map(|f| f.path)
+            .collect();
+        assert_eq!(tagged, ["./src/auth/login.rs", "./src/payments/charge.rs"]);
+        assert!(db.remove_path_tag("src/auth/login.rs", "entry").unwrap());
+        assert_eq!(search(&["security"]).len(), 1);
+        assert_eq!(search(&["billing", "security"]).len(), 2);
+        assert!(search(&["unknown"]).is_empty());
 
-        assert_eq!(count_after, 0);
+        assert_eq!(db.list_tags().unwrap().len(), 2);
+        assert!(db.remove_path_tag("./src/payments", "billing").unwrap());
+        assert!(search(&["billing"]).is_empty());
+
+        // Chunk tags go away with the chunk
+        db.clear_chunks(auth).unwrap();
+        assert!(db.list_tags().unwrap().is_empty());
     }
 
     #[test]
@@ -755,6 +4810,32 @@ mod tests {
         assert!(results[0].score > results[1].score);
     }
 
+    #[test]
+    fn test_chunk_heat() {
+        let db = Database::new(":memory:").unwrap();
+        let mut ids = Vec::new();
+        for path in ["/a.md", "/b.md", "/cold.md"] {
+            let file_id = db.add_or_update_file(path, 100).unwrap();
+            db.add_chunk(file_id, 0, 5, "one", None, None).unwrap();
+            db.add_chunk(file_id, 5, 9, "two", None, None).unwrap();
+            ids.extend(db.get_chunks(file_id).unwrap().iter().map(|c| c.id));
+        }
+        db.mark_chunks_returned(&[ids[0], ids[2]]).unwrap();
+        db.mark_chunks_returned(&[ids[2], ids[3]]).unwrap();
+        db.mark_chunks_returned(&[ids[3]]).unwrap();
+        db.mark_chunks_returned(&[ids[3]]).unwrap();
+
+        let heat = db.chunk_heat(10, 1).unwrap();
+        let files: Vec<(&str, u64, u64, u64)> = heat
+            .iter()
+            .map(|f| (f.path.as_str(), f.returns, f.chunks_returned, f.chunks))
+            .collect();
+        assert_eq!(files, vec![("/b.md", 5, 2, 2), ("/a.md", 1, 1, 2)]);
+        let hottest: Vec<(i64, u64)> = heat[0].hottest.iter().map(|c| (c.id, c.returns)).collect();
+        assert_eq!(hottest, vec![(ids[3], 3)]);
+        assert_eq!(db.chunk_heat(1, 5).unwrap()[0].hottest.len(), 2);
+    }
+
     #[test]
     fn test_context_lines_option() {
         let db = Database::new(":memory:").unwrap();
@@ -823,6 +4904,190 @@ mod tests {
         assert!(!results.is_empty());
     }
 
+    #[test]
+    fn test_hybrid_search() {
+        let db = Database::new(":memory:").unwrap();
+        let near: Vec<f32> = vec![1.0; 384];
+        let far: Vec<f32> = (0..384)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        let file_id = db.add_or_update_file("/src/ledger.rs", 100).unwrap();
+        db.add_chunk(file_id, 0, 1, "fn reconcile_ledger() {}", Some(&near), None)
+            .unwrap();
+        db.add_chunk(file_id, 1, 2, "fn render() {}", Some(&near), None)
+            .unwrap();
+        // Found by keyword alone, without an embedding
+        db.add_chunk(file_id, 2, 3, "reconcile_ledger docs", None, None)
+            .unwrap();
+        db.add_chunk(file_id, 3, 4, "fn unrelated() {}", Some(&far), None)
+            .unwrap();
+
+        let options = SearchOptions {
+            limit: Some(10),
+            recency_weight: Some(0.0),
+            ..Default::default()
+        };
+        let results: Vec<String> = db
+            .search_chunks_hybrid("reconcile_ledger", &near, &options)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.content)
+            .collect();
+        // Matching both ways ranks first; either one alone still counts
+        assert_eq!(results[0], "fn reconcile_ledger() {}");
+        assert!(results.contains(&"reconcile_ledger docs".to_string()));
+        assert!(results.contains(&"fn render() {}".to_string()));
+    }
+
+    #[test]
+    fn test_chunk_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        // An index from before chunks recorded their lines
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE chunks (
+                    id INTEGER PRIMARY KEY,
+                    file_id INTEGER NOT NULL,
+                    start_offset INTEGER NOT NULL,
+                    end_offset INTEGER NOT NULL,
+                    content TEXT NOT NULL,
+                    embedding BLOB,
+                    metadata TEXT
+                )",
+            )
+            .unwrap();
+        let db = Database::new(&path).unwrap();
+        let embedding: Vec<f32> = vec![1.0; 384];
+        let file_id = db.add_or_update_file("/src/ledger.rs", 100).unwrap();
+        let span = ChunkSpan {
+            start: 40,
+            end: 90,
+            lines: Some((3, 7)),
+        };
+        db.add_chunk_spanning(file_id, span, "fn reconcile() {}", Some(&embedding), None)
+            .unwrap();
+        db.add_chunk(file_id, 90, 99, "fn render() {}", Some(&embedding), None)
+            .unwrap();
+
+        let options = SearchOptions {
+            limit: Some(10),
+            ..Default::default()
+        };
+        let lines = |results: Vec<SearchResult>| -> Vec<(String, Option<usize>, Option<usize>)> {
+            let mut lines: Vec<_> = results
+                .into_iter()
+                .map(|r| (r.content, r.line_start, r.line_end))
+                .collect();
+            lines.sort();
+            lines
+        };
+        let expected = vec![
+            ("fn reconcile() {}".to_string(), Some(3), Some(7)),
+            ("fn render() {}".to_string(), None, None),
+        ];
+        let found = db.search_chunks_hybrid("fn", &embedding, &options).unwrap();
+        assert_eq!(lines(found), expected);
+        let found = db.search_chunks_hybrid("reconcile", &[0.0; 384], &options);
+        assert_eq!(found.unwrap()[0].line_start, Some(3));
+
+        // Kept through the archive
+        assert_eq!(db.archive_chunks(1, 10).unwrap(), 2);
+        let archived = SearchOptions {
+            include_archived: true,
+            ..options.clone()
+        };
+        let found = db.search_chunks_enhanced(&embedding, &archived).unwrap();
+        assert_eq!(lines(found), expected);
+        assert_eq!(db.restore_archived().unwrap(), 2);
+        let found = db.search_chunks_enhanced(&embedding, &options).unwrap();
+        assert_eq!(lines(found), expected);
+    }
+
+    #[test]
+    fn test_query_profile() {
+        let db = Database::new(":memory:").unwrap();
+        let embedding: Vec<f32> = vec![1.0; 384];
+        let file_id = db.add_or_update_file("/src/lib.rs", 100).unwrap();
+        db.add_chunk(file_id, 0, 1, "fn parse() {}", Some(&embedding), None)
+            .unwrap();
+        let doc_id = db.add_or_update_file("/docs/parse.md", 100).unwrap();
+        db.add_chunk(doc_id, 0, 1, "parse docs", Some(&embedding), None)
+            .unwrap();
+
+        let profile = Arc::new(QueryProfile::default());
+        let options = SearchOptions {
+            file_types: Some(vec!["rs".to_string()]),
+            profile: Some(profile.clone()),
+            ..Default::default()
+        };
+        let results = db.search_chunks_enhanced(&embedding, &options).unwrap();
+        assert_eq!(results.len(), 1);
+        let timings = profile.timings();
+        assert_eq!((timings.rows_scanned, timings.rows_filtered), (2, 1));
+
+        // Searches sharing the profile add to it
+        db.search_chunks_hybrid("parse", &embedding, &options)
+            .unwrap();
+        let timings = profile.timings();
+        assert_eq!((timings.rows_scanned, timings.rows_filtered), (6, 3));
+        assert!(timings.candidate_fetch_ms > 0.0);
+    }
+
+    #[test]
+    fn test_rank_explanation() {
+        let db = Database::new(":memory:").unwrap();
+        let near: Vec<f32> = vec![1.0; 384];
+        let far: Vec<f32> = (0..384)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        let file_id = db.add_or_update_file("/src/ledger.rs", 100).unwrap();
+        db.add_chunk(file_id, 0, 1, "fn render() {}", Some(&near), None)
+            .unwrap();
+        db.add_chunk(file_id, 1, 2, "fn reconcile_ledger() {}", Some(&far), None)
+            .unwrap();
+
+        let options = SearchOptions {
+            limit: Some(10),
+            recency_weight: Some(0.5),
+            file_types: Some(vec!["rs".to_string()]),
+            explain: true,
+            ..Default::default()
+        };
+        let results = db.search_chunks_enhanced(&near, &options).unwrap();
+        let explanation = results[0].explanation.as_ref().unwrap();
+        assert!((explanation.dense_score.unwrap() - 1.0).abs() < 1e-4);
+        assert_eq!(explanation.dense_rank, Some(1));
+        assert_eq!(explanation.bm25_score, None);
+        assert!(explanation.recency_boost < 0.0);
+        // The parts add up to the score
+        let total = explanation.dense_score.unwrap()
+            + explanation.recency_boost
+            + explanation.frequency_boost;
+        assert!((total - results[0].score).abs() < 1e-4);
+        assert_eq!(explanation.filters_passed, vec!["file_types"]);
+
+        // The keyword match climbs past the closer embedding match
+        let results = db
+            .search_chunks_hybrid("reconcile_ledger", &near, &options)
+            .unwrap();
+        assert_eq!(results[0].content, "fn reconcile_ledger() {}");
+        let explanation = results[0].explanation.as_ref().unwrap();
+        assert_eq!(explanation.dense_rank, Some(2));
+        assert_eq!(explanation.keyword_rank, Some(1));
+        assert!(explanation.bm25_score.unwrap() > 0.0);
+        assert_eq!(explanation.rerank_delta, 1);
+        assert_eq!(results[1].explanation.as_ref().unwrap().rerank_delta, -1);
+
+        let options = SearchOptions {
+            explain: false,
+            ..options
+        };
+        let results = db.search_chunks_enhanced(&near, &options).unwrap();
+        assert!(results[0].explanation.is_none());
+    }
+
     #[test]
     fn test_search_with_time_range() {
         let db = Database::new(":memory:").unwrap();
@@ -870,4 +5135,368 @@ mod tests {
             results.len()
         );
     }
+
+    #[test]
+    fn test_facets() {
+        let db = Database::new(":memory:").unwrap();
+        let embedding = vec![0.1f32; 384];
+        let notes = Some(r#"{"collection":"notes"}"#);
+        for (path, chunks, metadata) in [
+            ("./src/api/mod.rs", 2, None),
+            ("./src/main.rs", 1, None),
+            ("./docs/guide.md", 1, None),
+            ("./README.md", 1, None),
+            ("/home/me/notes/todo.txt", 2, notes),
+        ] {
+            let file_id = db.add_or_update_file(path, 100).unwrap();
+            for i in 0..chunks {
+                db.add_chunk(
+                    file_id,
+                    i * 10,
+                    i * 10 + 10,
+                    "x",
+                    Some(&embedding),
+                    metadata,
+                )
+                .unwrap();
+            }
+        }
+        let roots = [PathBuf::from("/home/me/notes")];
+
+        let facets = db.facets(&SearchOptions::default(), &roots).unwrap();
+        assert_eq!(facets.file_types["rs"], 3);
+        assert_eq!(facets.file_types["md"], 2);
+        assert_eq!(facets.directories["src"], 3);
+        assert_eq!(facets.directories["docs"], 1);
+        assert_eq!(facets.directories["."], 3);
+        assert_eq!(facets.collections["default"], 5);
+        assert_eq!(facets.collections["notes"], 2);
+
+        // The search's filters narrow the counts
+        let options = SearchOptions {
+            file_types: Some(vec!["RS".to_string(), "txt".to_string()]),
+            collections: Some(vec!["default".to_string()]),
+            ..Default::default()
+        };
+        let facets = db.facets(&options, &roots).unwrap();
+        assert_eq!(facets.file_types, BTreeMap::from([("rs".to_string(), 3)]));
+        assert_eq!(
+            facets.collections,
+            BTreeMap::from([("default".to_string(), 3)])
+        );
+        let options = SearchOptions {
+            paths: Some(vec!["notes".to_string()]),
+            ..Default::default()
+        };
+        let facets = db.facets(&options, &roots).unwrap();
+        assert_eq!(facets.directories, BTreeMap::from([(".".to_string(), 2)]));
+    }
+
+    #[test]
+    fn test_summarize_tree() {
+        let db = Database::new(":memory:").unwrap();
+        let api = db.add_or_update_file("./src/api/mod.rs", 100).unwrap();
+        let main = db.add_or_update_file("./src/main.rs", 100).unwrap();
+        db.add_or_update_file("./docs/guide.md", 100).unwrap();
+        db.add_or_update_file("conversation://c1", 100).unwrap();
+        db.add_chunk(api, 0, 10, "a", None, None).unwrap();
+        db.add_chunk(api, 10, 20, "b", None, None).unwrap();
+        db.add_chunk(main, 0, 10, "c", None, None).unwrap();
+        db.mark_indexed(main).unwrap();
+        db.add_path_tag("src", "core").unwrap();
+        db.add_chunk_tag(db.get_chunks(api).unwrap()[0].id, "http")
+            .unwrap();
+
+        let stats = db.file_stats().unwrap();
+        let tree = summarize_tree(&stats, Path::new("src"), None);
+        let paths: Vec<&str> = tree.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, vec![".", "api"]);
+        assert_eq!((tree[0].files, tree[0].chunks), (2, 3));
+        assert!(tree[0].last_indexed.is_some());
+        assert!(tree[1].last_indexed.is_none());
+        assert_eq!(tree[0].top_tags[0].tag, "core");
+        assert_eq!(tree[0].top_tags[0].files, 2);
+        assert_eq!(tree[1].top_tags.len(), 2);
+
+        // Whole index, one level deep; transcripts have no directory
+        let tree = summarize_tree(&stats, Path::new("."), Some(1));
+        let paths: Vec<&str> = tree.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, vec![".", "docs", "src"]);
+        assert_eq!(tree[0].files, 3);
+    }
+
+    #[test]
+    fn test_under_roots() {
+        let roots = vec![PathBuf::from("/work/api"), PathBuf::from(".")];
+        assert!(under_roots("/work/api/src/main.rs", Some(&roots)));
+        assert!(under_roots("./README.md", Some(&roots)));
+        assert!(!under_roots("/work/api2/src/main.rs", Some(&roots[..1])));
+        assert!(under_roots("/anything", None));
+    }
+
+    #[test]
+    fn test_index_timings() {
+        let db = Database::new(":memory:").unwrap();
+        db.add_or_update_file("fast.rs", 0).unwrap();
+        db.add_or_update_file("slow.pdf", 0).unwrap();
+        let fast = StageTimings {
+            chunking_us: 100,
+            embedding_us: 2_000,
+            storage_us: 300,
+        };
+        let slow = StageTimings {
+            chunking_us: 90_000,
+            embedding_us: 8_000,
+            storage_us: 700,
+        };
+        db.record_index_timing("fast.rs", &fast).unwrap();
+        db.record_index_timing("slow.pdf", &slow).unwrap();
+        // Timings of files no longer indexed are left out
+        db.record_index_timing("gone.rs", &slow).unwrap();
+
+        let stats = db.index_timing_stats(1).unwrap();
+        assert_eq!(stats.files, 2);
+        assert_eq!(stats.total.chunking_us, 90_100);
+        assert_eq!(stats.total.total_us(), 101_100);
+        assert_eq!(stats.average.embedding_us, 5_000);
+        assert_eq!(stats.slowest.len(), 1);
+        assert_eq!(stats.slowest[0].path, "slow.pdf");
+    }
+
+    #[test]
+    fn test_workspaces() {
+        let db = Database::new(":memory:").unwrap();
+        assert!(db.register_workspace(Path::new("/work/api")).unwrap());
+        assert!(!db.register_workspace(Path::new("/work/api")).unwrap());
+        db.register_workspace(Path::new("/work/app")).unwrap();
+        assert_eq!(
+            db.list_workspaces().unwrap(),
+            vec![PathBuf::from("/work/api"), PathBuf::from("/work/app")]
+        );
+    }
+
+    #[test]
+    fn test_collection_models() {
+        let db = Database::new(":memory:").unwrap();
+        assert_eq!(db.collection_model("notes").unwrap(), None);
+        db.set_collection_model("notes", "all-minilm-l6-v2", 384)
+            .unwrap();
+        db.set_collection_model("notes", "bge-small-en-v1.5", 384)
+            .unwrap();
+        assert_eq!(
+            db.collection_model("notes").unwrap(),
+            Some(("bge-small-en-v1.5".to_string(), 384))
+        );
+    }
+
+    #[test]
+    fn test_commit_reembedding() {
+        let db = Database::new(":memory:").unwrap();
+        let configured = vec!["notes".to_string()];
+        let file_id = db.add_or_update_file("/notes/a.md", 100).unwrap();
+        let notes = r#"{"collection": "notes"}"#;
+        db.add_chunk(file_id, 0, 5, "first", Some(&[1.0; 384]), Some(notes))
+            .unwrap();
+        db.add_chunk(file_id, 5, 10, "second", Some(&[1.0; 384]), Some(notes))
+            .unwrap();
+        let other = db.add_or_update_file("/src/lib.rs", 100).unwrap();
+        db.add_chunk(other, 0, 5, "code", Some(&[1.0; 384]), None)
+            .unwrap();
+
+        let pending = db.unstaged_chunks(Some("notes"), &configured, 10).unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(db.unstaged_chunks(None, &configured, 10).unwrap().len(), 1);
+        db.stage_embedding(pending[0].0, &[-1.0; 384]).unwrap();
+        assert_eq!(
+            db.reembedding_progress(Some("notes"), &configured).unwrap(),
+            (1, 2)
+        );
+        // Not every chunk has a new embedding yet
+        assert!(!db
+            .commit_reembedding(Some("notes"), &configured, "old", "new", 384)
+            .unwrap());
+
+        db.stage_embedding(pending[1].0, &[-1.0; 384]).unwrap();
+        assert!(db
+            .commit_reembedding(Some("notes"), &configured, "old", "new", 384)
+            .unwrap());
+        assert_eq!(
+            db.collection_model("notes").unwrap(),
+            Some(("new".to_string(), 384))
+        );
+        assert_eq!(
+            db.reembedding_progress(Some("notes"), &configured).unwrap(),
+            (0, 2)
+        );
+        let embeddings = db.chunk_embeddings(file_id).unwrap();
+        assert_eq!(embeddings["first"], vec![-1.0; 384]);
+        // The vector index holds the new embeddings too
+        let related = db.related_chunks(pending[0].0, 5).unwrap();
+        assert_eq!(related[0].content, "second");
+    }
+
+    #[test]
+    fn test_search_collections() {
+        let db = Database::new(":memory:").unwrap();
+        let notes = db.add_or_update_file("/notes/a.md", 100).unwrap();
+        db.add_chunk(
+            notes,
+            0,
+            5,
+            "notes",
+            Some(&[1.0; 384]),
+            Some(r#"{"collection": "notes"}"#),
+        )
+        .unwrap();
+        let code = db.add_or_update_file("/src/lib.rs", 100).unwrap();
+        db.add_chunk(code, 0, 5, "code", Some(&[1.0; 384]), None)
+            .unwrap();
+
+        let search = |collections: &[&str]| -> Vec<String> {
+            let options = SearchOptions {
+                collections: Some(collections.iter().map(|c| c.to_string()).collect()),
+                ..Default::default()
+            };
+            db.search_chunks_enhanced(&[1.0; 384], &options)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.content)
+                .collect()
+        };
+        assert_eq!(search(&["notes"]), vec!["notes"]);
+        // Chunks without a collection are in the default one
+        assert_eq!(search(&["default"]), vec!["code"]);
+        assert!(search(&[]).is_empty());
+
+        let stats: Vec<(String, u64)> = db
+            .collection_stats()
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.name, c.files))
+            .collect();
+        assert_eq!(
+            stats,
+            vec![("default".to_string(), 1), ("notes".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_collection_column_backfill() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        {
+            // Chunks as stored before the collection column
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                r#"CREATE TABLE files (id INTEGER PRIMARY KEY, path TEXT NOT NULL UNIQUE,
+                                      last_modified INTEGER NOT NULL, last_indexed INTEGER);
+                   CREATE TABLE chunks (id INTEGER PRIMARY KEY, file_id INTEGER NOT NULL,
+                                        start_offset INTEGER NOT NULL, end_offset INTEGER NOT NULL,
+                                        content TEXT NOT NULL, embedding BLOB, metadata TEXT);
+                   INSERT INTO files VALUES (1, '/notes/a.md', 100, 100), (2, '/src/b.rs', 100, 100);
+                   INSERT INTO chunks VALUES (1, 1, 0, 5, 'notes', NULL, '{"collection":"notes"}'),
+                                             (2, 2, 0, 5, 'code', NULL, '{"symbol":"b"}'),
+                                             (3, 2, 5, 9, 'more', NULL, NULL);"#,
+            )
+            .unwrap();
+        }
+
+        let db = Database::new(&path).unwrap();
+        let stats: Vec<(String, u64, u64)> = db
+            .collection_stats()
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.name, c.files, c.chunks))
+            .collect();
+        assert_eq!(
+            stats,
+            vec![("default".to_string(), 1, 2), ("notes".to_string(), 1, 1)]
+        );
+        assert_eq!(db.collection_file_ids("notes").unwrap(), HashSet::from([1]));
+        assert_eq!(
+            db.collection_file_ids("default").unwrap(),
+            HashSet::from([2])
+        );
+        let plan: String = db
+            .reader()
+            .query_row(
+                "EXPLAIN QUERY PLAN SELECT file_id FROM chunks WHERE collection = 'notes'",
+                [],
+                |row| row.get(3),
+            )
+            .unwrap();
+        assert!(plan.contains("idx_chunks_collection"), "{}", plan);
+    }
+
+    #[test]
+    fn test_approximate_search() {
+        let db = Database::new(":memory:").unwrap();
+        let vector = |i: usize| -> Vec<f32> {
+            (0..384u64)
+                .map(|j| {
+                    // splitmix64, for signs that look random
+                    let mut z = (i as u64 * 384 + j).wrapping_add(0x9e37_79b9_7f4a_7c15);
+                    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                    if (z ^ (z >> 31)) & 1 == 0 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                })
+                .collect()
+        };
+        let file_id = db.add_or_update_file("/src/lib.rs", 100).unwrap();
+        for i in 0..150 {
+            let metadata = (i == 149).then_some(r#"{"collection": "notes"}"#);
+            db.add_chunk(
+                file_id,
+                0,
+                1,
+                &format!("chunk {}", i),
+                Some(&vector(i)),
+                metadata,
+            )
+            .unwrap();
+        }
+
+        let nearest = db.nearest_chunks(&vector(7), 10).unwrap();
+        assert_eq!(nearest.len(), 10);
+        assert_eq!(nearest[0], 8);
+
+        let options = |approximate| SearchOptions {
+            limit: Some(3),
+            recency_weight: Some(0.0),
+            approximate,
+            ..Default::default()
+        };
+        let top = |options: &SearchOptions| -> Vec<String> {
+            db.search_chunks_enhanced(&vector(7), options)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.content)
+                .collect()
+        };
+        assert_eq!(top(&options(true)), top(&options(false)));
+        assert_eq!(top(&options(true))[0], "chunk 7");
+
+        // Filters rejecting the nearest chunks fall back to an exact scan
+        let notes = SearchOptions {
+            collections: Some(vec!["notes".to_string()]),
+            ..options(true)
+        };
+        assert_eq!(top(&notes), vec!["chunk 149"]);
+        // Likewise when min_score does, with the candidates scanned first
+        let profile = Arc::new(QueryProfile::default());
+        let close = SearchOptions {
+            min_score: Some(0.99),
+            profile: Some(profile.clone()),
+            ..options(true)
+        };
+        assert_eq!(top(&close), vec!["chunk 7"]);
+        assert_eq!(profile.timings().rows_scanned, 100 + 150);
+
+        db.clear_chunks(file_id).unwrap();
+        assert!(db.nearest_chunks(&vector(7), 10).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+pub type Result<T, E = StorageError> = std::result::Result<T, E>;
+
+/// Why a database operation failed.
+#[derive(Debug, Error)]
+pub enum StorageError {
+    /// Another connection held the lock past the busy timeout
+    #[error("The index is locked by another process: {0}")]
+    Locked(#[source] rusqlite::Error),
+    #[error("The index is corrupt: {0}")]
+    Corrupt(#[source] rusqlite::Error),
+    /// The disk holding the index is out of space
+    #[error("The disk holding the index is full: {0}")]
+    Full(#[source] rusqlite::Error),
+    #[error(transparent)]
+    Sqlite(rusqlite::Error),
+    /// The batch a write ran in failed to commit
+    #[error("Failed to commit write: {0}")]
+    Commit(String),
+    #[error("The database writer has stopped")]
+    WriterStopped,
+    #[error("Database task failed: {0}")]
+    Task(String),
+    #[error("The index at {} is corrupt ({source}). Run `contextd db repair` to restore it from a backup or rebuild it", path.display())]
+    CorruptFile {
+        path: PathBuf,
+        source: rusqlite::Error,
+    },
+    #[error("The index at {} is locked by another process, perhaps a contextd that didn't exit. Stop it and try again", path.display())]
+    LockedFile { path: PathBuf },
+    #[error("Failed to open the index at {}: {source}", path.display())]
+    Open {
+        path: PathBuf,
+        source: Box<StorageError>,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl StorageError {
+    /// Whether trying again later may succeed, as when another process
+    /// holds the lock, rather than failing the same way.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Locked(_) | Self::LockedFile { .. } | Self::Commit(_) => true,
+            Self::Open { source, .. } => source.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// The SQLite error code behind this, if it came from SQLite.
+    pub fn sqlite_code(&self) -> Option<rusqlite::ErrorCode> {
+        match self {
+            Self::Locked(e) | Self::Corrupt(e) | Self::Full(e) | Self::Sqlite(e) => {
+                e.sqlite_error_code()
+            }
+            Self::CorruptFile { source, .. } => source.sqlite_error_code(),
+            Self::Open { source, .. } => source.sqlite_code(),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode;
+        match e.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => Self::Locked(e),
+            Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => Self::Corrupt(e),
+            Some(ErrorCode::DiskFull) => Self::Full(e),
+            _ => Self::Sqlite(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let busy = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+            None,
+        );
+        let error = StorageError::from(busy);
+        assert!(matches!(error, StorageError::Locked(_)));
+        assert!(error.is_transient());
+
+        let corrupt = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CORRUPT),
+            None,
+        );
+        let error = StorageError::from(corrupt);
+        assert_eq!(
+            error.sqlite_code(),
+            Some(rusqlite::ErrorCode::DatabaseCorrupt)
+        );
+        assert!(!error.is_transient());
+        assert!(!StorageError::from(rusqlite::Error::QueryReturnedNoRows).is_transient());
+    }
+}
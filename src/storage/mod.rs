@@ -1 +1,5 @@
 pub mod db;
+pub mod error;
+mod writer;
+
+pub use error::StorageError;
@@ -0,0 +1,189 @@
+use rusqlite::Connection;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use super::error::{Result, StorageError};
+use crate::faults;
+
+/// Most writes committed together in one transaction.
+const MAX_BATCH: usize = 64;
+
+/// Hands a write's result to its caller once the batch it ran in is
+/// committed, or with the reason the commit failed.
+type Reply = Box<dyn FnOnce(Option<&str>) + Send>;
+
+/// Runs a write, returning whether it failed and its reply.
+type Run = Box<dyn FnOnce(&mut Connection) -> (bool, Reply) + Send>;
+
+/// A write waiting for the writer thread.
+struct Command {
+    run: Run,
+    /// Runs on its own outside a transaction, like a WAL checkpoint
+    alone: bool,
+}
+
+/// Handle to the thread that makes every write to a database. Writes queued
+/// while it is busy are committed together, each in its own savepoint so a
+/// failing one doesn't undo the rest.
+#[derive(Clone)]
+pub struct Writer {
+    commands: Sender<Command>,
+}
+
+impl Writer {
+    /// Start the writer thread on `conn`. It stops once every handle is gone.
+    pub fn spawn(conn: Arc<Mutex<Connection>>) -> Result<Self> {
+        let (commands, queue) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("contextd-db-writer".to_string())
+            .spawn(move || run(&conn, queue))?;
+        Ok(Self { commands })
+    }
+
+    /// Run `write` on the writer thread and wait until it is committed.
+    pub fn write<T, F>(&self, write: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        self.submit(false, write)
+    }
+
+    /// Run `write` on the writer thread outside any transaction.
+    pub fn write_alone<T, F>(&self, write: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        self.submit(true, write)
+    }
+
+    fn submit<T, F>(&self, alone: bool, write: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let run: Run = Box::new(move |conn: &mut Connection| {
+            let result = write(conn);
+            let failed = result.is_err();
+            let reply: Reply = Box::new(move |commit_error| {
+                let result = match commit_error {
+                    Some(e) if result.is_ok() => Err(StorageError::Commit(e.to_string())),
+                    _ => result,
+                };
+                let _ = sender.send(result);
+            });
+            (failed, reply)
+        });
+        self.commands
+            .send(Command { run, alone })
+            .map_err(|_| StorageError::WriterStopped)?;
+        receiver.recv().map_err(|_| StorageError::WriterStopped)?
+    }
+}
+
+fn run(conn: &Mutex<Connection>, queue: Receiver<Command>) {
+    let mut next = None;
+    loop {
+        let Some(first) = next.take().or_else(|| queue.recv().ok()) else {
+            return;
+        };
+        if let Some(delay) = faults::write_delay() {
+            std::thread::sleep(delay);
+        }
+        let mut conn = conn.lock().unwrap();
+        if first.alone {
+            let (_, reply) = (first.run)(&mut conn);
+            reply(None);
+            continue;
+        }
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            match queue.try_recv() {
+                Ok(command) if command.alone => {
+                    next = Some(command);
+                    break;
+                }
+                Ok(command) => batch.push(command),
+                Err(_) => break,
+            }
+        }
+        commit(&mut conn, batch);
+    }
+}
+
+fn commit(conn: &mut Connection, batch: Vec<Command>) {
+    // Another process holding the write lock past the busy timeout: commit
+    // each write on its own instead
+    if let Err(e) = conn.execute_batch("BEGIN IMMEDIATE") {
+        log::debug!("Writing a batch without a transaction: {}", e);
+        for command in batch {
+            let (_, reply) = (command.run)(conn);
+            reply(None);
+        }
+        return;
+    }
+    let mut replies = Vec::with_capacity(batch.len());
+    for command in batch {
+        let savepoint = conn.execute_batch("SAVEPOINT command").is_ok();
+        let (failed, reply) = (command.run)(conn);
+        if savepoint {
+            if failed {
+                let _ = conn.execute_batch("ROLLBACK TO command");
+            }
+            let _ = conn.execute_batch("RELEASE command");
+        }
+        replies.push(reply);
+    }
+    let error = conn.execute_batch("COMMIT").err().map(|e| e.to_string());
+    if error.is_some() {
+        let _ = conn.execute_batch("ROLLBACK");
+    }
+    for reply in replies {
+        reply(error.as_deref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writer() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (v INTEGER NOT NULL)")
+            .unwrap();
+        let conn = Arc::new(Mutex::new(conn));
+        let writer = Writer::spawn(conn.clone()).unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let writer = writer.clone();
+                std::thread::spawn(move || {
+                    writer.write(move |conn| {
+                        // Odd writes fail halfway and must leave no trace
+                        conn.execute("INSERT INTO t (v) VALUES (?1)", [i])?;
+                        if i % 2 == 1 {
+                            conn.execute("INSERT INTO t (v) VALUES (NULL)", [])?;
+                        }
+                        Ok(i)
+                    })
+                })
+            })
+            .collect();
+        let results: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(result.is_ok(), i % 2 == 0);
+        }
+
+        let sum: i64 = conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT SUM(v) FROM t", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(sum, 2 + 4 + 6);
+        assert!(conn.lock().unwrap().is_autocommit());
+        assert!(writer.write_alone(|conn| Ok(conn.is_autocommit())).unwrap());
+    }
+}
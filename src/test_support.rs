@@ -0,0 +1,149 @@
+// Runs the daemon in-process for integration tests: on a free local port,
+// with the index in a directory the test owns and [`Embedder::mock`] in
+// place of a model, so tests need neither a release build nor model files.
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::api;
+use crate::collections::Models;
+use crate::config::Config;
+use crate::daemon;
+use crate::indexer::embeddings::Embedder;
+use crate::storage::db::EMBEDDING_DIMENSIONS;
+
+/// How long [`TestDaemon::start`] waits for the API to answer.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often [`TestDaemon::wait_for_files`] checks the index.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A daemon running on the test's runtime. Dropping it stops the daemon
+/// without waiting; [`TestDaemon::stop`] waits.
+pub struct TestDaemon {
+    addr: SocketAddr,
+    client: reqwest::Client,
+    stop: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<Result<()>>>,
+}
+
+impl TestDaemon {
+    /// A config storing the index in `data_dir` and watching `paths`, with
+    /// the API on a free local port. Adjust it before [`TestDaemon::start`].
+    pub fn config(data_dir: &Path, paths: &[PathBuf]) -> Config {
+        let mut config = Config::default();
+        config.server.host = "127.0.0.1".to_string();
+        config.server.port = 0;
+        config.storage.db_path = data_dir.join("contextd.db");
+        config.storage.model_path = data_dir.join("models");
+        config.watch.paths = paths.to_vec();
+        config
+    }
+
+    /// Start a daemon for `config`, embedding with [`Embedder::mock`], and
+    /// wait until its API answers.
+    pub async fn start(config: Config) -> Result<Self> {
+        let listener = api::bind(&config.server).await?;
+        let addr = listener.local_addr()?;
+        let models = Arc::new(Models::single(Embedder::mock(EMBEDDING_DIMENSIONS)));
+        let (stop, stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(daemon::serve(config, models, listener, async {
+            // A dropped sender stops the daemon too
+            let _ = stopped.await;
+        }));
+
+        let mut daemon = Self {
+            addr,
+            client: reqwest::Client::new(),
+            stop: Some(stop),
+            task: Some(task),
+        };
+        let deadline = Instant::now() + START_TIMEOUT;
+        while daemon.get("/health").await.is_err() {
+            if daemon.task.as_ref().is_some_and(|t| t.is_finished()) {
+                let task = daemon.task.take().unwrap();
+                task.await??;
+                bail!("Daemon stopped before answering");
+            }
+            if Instant::now() > deadline {
+                bail!("Daemon didn't answer within {:?}", START_TIMEOUT);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Ok(daemon)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL of API endpoint `path`, e.g. `/query`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// GET `path` and parse the JSON response, failing on an error status.
+    pub async fn get(&self, path: &str) -> Result<Value> {
+        let response = self.client.get(self.url(path)).send().await?;
+        Ok(response.error_for_status()?.json().await?)
+    }
+
+    /// POST `body` to `path` and parse the JSON response, failing on an
+    /// error status.
+    pub async fn post(&self, path: &str, body: &Value) -> Result<Value> {
+        let response = self.client.post(self.url(path)).json(body).send().await?;
+        Ok(response.error_for_status()?.json().await?)
+    }
+
+    /// Files whose chunks have all been stored. `/stats` `indexed_files`
+    /// also counts files still being stored, so this reads the number with
+    /// recorded timings instead.
+    pub async fn indexed_files(&self) -> Result<u64> {
+        let stats = self.get("/stats").await?;
+        stats["indexing"]["files"]
+            .as_u64()
+            .context("/stats has no indexing.files")
+    }
+
+    /// Wait up to `timeout` for at least `count` files to be indexed, and
+    /// return how many are.
+    pub async fn wait_for_files(&self, count: u64, timeout: Duration) -> Result<u64> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let indexed = self.indexed_files().await?;
+            if indexed >= count || Instant::now() > deadline {
+                return Ok(indexed);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Stop the daemon, as SIGTERM would, and wait until in-flight indexing
+    /// has finished.
+    pub async fn stop(mut self) -> Result<()> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        match self.task.take() {
+            Some(task) => task.await?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for TestDaemon {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
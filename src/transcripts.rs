@@ -0,0 +1,155 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::indexer::chunker::Chunk;
+use crate::indexer::embeddings::Embedder;
+use crate::storage::db::Database;
+
+/// Collection name recorded in the metadata of transcript chunks.
+pub const COLLECTION: &str = "conversations";
+
+/// Path prefix of stored transcripts; they have no file on disk.
+pub const PATH_PREFIX: &str = "conversation://";
+
+/// One role-tagged turn of a conversation.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Turn {
+    pub role: String,
+    pub content: String,
+    /// Unix timestamp of the turn, if the client knows it
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Transcript {
+    /// Saving again under the same id replaces the stored transcript
+    pub conversation_id: Option<String>,
+    pub title: Option<String>,
+    pub turns: Vec<Turn>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SavedTranscript {
+    pub conversation_id: String,
+    pub path: String,
+    pub chunks: usize,
+}
+
+/// Embed and store a transcript, one chunk per non-empty turn.
+pub fn save(
+    db: &Database,
+    embedder: &Embedder,
+    transcript: &Transcript,
+) -> Result<SavedTranscript> {
+    if transcript.turns.iter().all(|t| t.content.trim().is_empty()) {
+        anyhow::bail!("Transcript has no content");
+    }
+
+    let now = chrono::Utc::now();
+    let conversation_id = transcript
+        .conversation_id
+        .clone()
+        .unwrap_or_else(|| now.format("%Y%m%dT%H%M%S%.3fZ").to_string());
+    let path = format!("{}{}", PATH_PREFIX, conversation_id);
+    let chunks = chunks(transcript, &conversation_id, now.timestamp() as u64);
+
+    let file_id = db.add_or_update_file(&path, now.timestamp() as u64)?;
+    db.clear_chunks(file_id)?;
+    for chunk in &chunks {
+        let embedding = embedder.embed(&chunk.content).ok();
+        db.add_chunk(
+            file_id,
+            chunk.start,
+            chunk.end,
+            &chunk.content,
+            embedding.as_deref(),
+            chunk.metadata.as_deref(),
+        )?;
+    }
+    db.mark_indexed(file_id)?;
+
+    Ok(SavedTranscript {
+        conversation_id,
+        path,
+        chunks: chunks.len(),
+    })
+}
+
+/// Chunks for each turn, with offsets into the transcript rendered as
+/// `role: content` blocks separated by blank lines.
+fn chunks(transcript: &Transcript, conversation_id: &str, saved_at: u64) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut offset = 0u64;
+    for (i, turn) in transcript.turns.iter().enumerate() {
+        let content = format!("{}: {}", turn.role, turn.content.trim());
+        let start = offset;
+        offset += content.len() as u64 + 2;
+        if turn.content.trim().is_empty() {
+            continue;
+        }
+
+        let mut metadata = serde_json::json!({
+            "collection": COLLECTION,
+            "conversation_id": conversation_id,
+            "role": turn.role,
+            "turn": i,
+            "timestamp": turn.timestamp.unwrap_or(saved_at),
+        });
+        if let Some(title) = &transcript.title {
+            metadata["title"] = serde_json::json!(title);
+        }
+
+        chunks.push(Chunk {
+            start,
+            end: start + content.len() as u64,
+            content,
+            metadata: Some(metadata.to_string()),
+            lines: None,
+        });
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcript_chunks() {
+        let transcript = Transcript {
+            conversation_id: None,
+            title: Some("Auth refactor".to_string()),
+            turns: vec![
+                Turn {
+                    role: "user".to_string(),
+                    content: "Should we drop the session cookie?".to_string(),
+                    timestamp: Some(100),
+                },
+                Turn {
+                    role: "assistant".to_string(),
+                    content: "  ".to_string(),
+                    timestamp: None,
+                },
+                Turn {
+                    role: "assistant".to_string(),
+                    content: "Yes, switch to bearer tokens.".to_string(),
+                    timestamp: None,
+                },
+            ],
+        };
+        let chunks = chunks(&transcript, "c1", 500);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            chunks[0].content,
+            "user: Should we drop the session cookie?"
+        );
+        assert!(chunks[1].start > chunks[0].end);
+
+        let meta: serde_json::Value =
+            serde_json::from_str(chunks[1].metadata.as_ref().unwrap()).unwrap();
+        assert_eq!(meta["collection"], "conversations");
+        assert_eq!(meta["turn"], 2);
+        assert_eq!(meta["timestamp"], 500);
+        assert_eq!(meta["title"], "Auth refactor");
+    }
+}
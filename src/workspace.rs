@@ -0,0 +1,389 @@
+use std::path::{Path, PathBuf};
+
+use crate::collections::CollectionConfig;
+use crate::config::{Config, WatchConfig};
+use crate::paths::canonicalize;
+use crate::staleness::DEFAULT_COLLECTION;
+
+/// Files whose presence marks a project root.
+const ROOT_MARKERS: &[&str] = &[
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "go.mod",
+    "pom.xml",
+    "build.gradle",
+    ".contextignore",
+];
+
+/// Dependency and cache directories skipped with `[watch] default_ignores`,
+/// even in projects without a `.gitignore` for them.
+pub const DEFAULT_IGNORES: &[&str] = &["node_modules", "__pycache__", "venv"];
+
+/// Whether `watch` skips a directory named `name` as one of
+/// [`DEFAULT_IGNORES`].
+pub fn is_default_ignored(watch: &WatchConfig, name: &str) -> bool {
+    watch.default_ignores && DEFAULT_IGNORES.contains(&name)
+}
+
+/// Make the registered workspace `root` part of `config`: watched, unless a
+/// watched directory already covers it, and a collection of its own named
+/// after its directory, or its parent's and its own when another collection
+/// has that name. Returns the collection's name, if it got one.
+pub fn add_to_config(config: &mut Config, root: &Path) -> Option<String> {
+    let covered = config
+        .watch
+        .paths
+        .iter()
+        .any(|p| root.starts_with(p) || canonicalize(p).is_ok_and(|p| root.starts_with(p)));
+    if !covered {
+        config.watch.paths.push(root.to_path_buf());
+        config.watch.workspaces.push(root.to_path_buf());
+    }
+
+    let name = root.file_name()?.to_string_lossy().to_string();
+    let parent = root.parent().and_then(|p| p.file_name());
+    let candidates = std::iter::once(name.clone())
+        .chain(parent.map(|p| format!("{}-{}", p.to_string_lossy(), name)));
+    for candidate in candidates {
+        if candidate == DEFAULT_COLLECTION {
+            continue;
+        }
+        match config.collections.get(&candidate) {
+            Some(c) if c.paths == [root] => return Some(candidate),
+            Some(_) => {}
+            None => {
+                let collection = CollectionConfig {
+                    paths: vec![root.to_path_buf()],
+                    ..Default::default()
+                };
+                config.collections.insert(candidate.clone(), collection);
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+/// Directories where macOS keeps the Trash, Spotlight and FSEvents metadata
+/// and document versions, skipped with `[watch] system_exclusions`.
+const MACOS_EXCLUDED_NAMES: &[&str] = &[
+    ".Trash",
+    ".Trashes",
+    ".Spotlight-V100",
+    ".fseventsd",
+    ".DocumentRevisions-V100",
+    ".TemporaryItems",
+];
+
+/// Extensions of Photos and iPhoto library bundles.
+const MACOS_EXCLUDED_BUNDLES: &[&str] = &["photoslibrary", "photolibrary"];
+
+/// The built-in macOS exclusion `path`, watched under `root`, falls in:
+/// `~/Library`, unless `root` is inside it, or a directory below `root`
+/// named in [`MACOS_EXCLUDED_NAMES`] or a Photos library.
+pub fn macos_exclusion(root: &Path, path: &Path, home: Option<&Path>) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    if let Some(library) = home.map(|h| h.join("Library")) {
+        if path.starts_with(&library) && !root.starts_with(&library) {
+            return Some("~/Library".to_string());
+        }
+    }
+    relative.components().find_map(|c| {
+        let name = c.as_os_str().to_str()?;
+        let bundle = Path::new(name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| MACOS_EXCLUDED_BUNDLES.contains(&e));
+        (bundle || MACOS_EXCLUDED_NAMES.contains(&name)).then(|| name.to_string())
+    })
+}
+
+/// [`macos_exclusion`] for the user's home directory, on macOS with
+/// `system_exclusions` on.
+pub fn system_exclusion(watch: &WatchConfig, root: &Path, path: &Path) -> Option<String> {
+    if !cfg!(target_os = "macos") || !watch.system_exclusions {
+        return None;
+    }
+    macos_exclusion(root, path, home_dir().as_deref())
+}
+
+/// The directories under `watch`'s roots that [`system_exclusion`] skips
+/// and that exist, for the file watcher to ignore outright: `~/Library`,
+/// the Trash and Photos libraries in `~/Pictures`.
+pub fn system_exclusion_dirs(watch: &WatchConfig) -> Vec<PathBuf> {
+    let Some(home) = home_dir().filter(|_| cfg!(target_os = "macos") && watch.system_exclusions)
+    else {
+        return Vec::new();
+    };
+    let mut dirs = vec![home.join("Library"), home.join(".Trash")];
+    if let Ok(entries) = std::fs::read_dir(home.join("Pictures")) {
+        dirs.extend(entries.flatten().map(|e| e.path()));
+    }
+    dirs.into_iter()
+        .filter(|dir| dir.is_dir())
+        .filter(|dir| {
+            watch
+                .paths
+                .iter()
+                .any(|root| macos_exclusion(root, dir, Some(&home)).is_some())
+        })
+        .collect()
+}
+
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+}
+
+/// The project root containing `path`: the nearest ancestor with a `.git`
+/// directory, or failing that the nearest one with a build manifest or
+/// `.contextignore`. Returns the canonical path.
+pub fn find_root(path: &Path) -> Option<PathBuf> {
+    let start = canonicalize(path).ok()?;
+    let start = if start.is_file() {
+        start.parent()?.to_path_buf()
+    } else {
+        start
+    };
+
+    if let Some(repo) = start.ancestors().find(|dir| dir.join(".git").exists()) {
+        return Some(repo.to_path_buf());
+    }
+    start
+        .ancestors()
+        .find(|dir| ROOT_MARKERS.iter().any(|m| dir.join(m).exists()))
+        .map(|dir| dir.to_path_buf())
+}
+
+/// Local path of a `file://` URI as sent in MCP roots.
+pub fn path_from_uri(uri: &str) -> Option<PathBuf> {
+    local_path(uri, cfg!(windows)).map(PathBuf::from)
+}
+
+/// The path of `uri` in the form of the platform's paths. On Windows
+/// `file:///C:/src` is `C:\src` and a host other than `localhost` names a
+/// UNC share, `file://server/share/src` being `\\server\share\src`.
+fn local_path(uri: &str, windows: bool) -> Option<String> {
+    let rest = uri.strip_prefix("file://")?;
+    let i = rest.find('/')?;
+    let (host, path) = (&rest[..i], percent_decode(&rest[i..]));
+    if !windows {
+        // Drop an authority component such as `localhost`
+        return Some(path);
+    }
+    let path = path.replace('/', "\\");
+    if !host.is_empty() && !host.eq_ignore_ascii_case("localhost") {
+        return Some(format!("\\\\{}{}", host, path));
+    }
+    match path.strip_prefix('\\') {
+        Some(drive) if drive.as_bytes().get(1) == Some(&b':') => Some(drive.to_string()),
+        _ => Some(path),
+    }
+}
+
+/// `file://` URI of an absolute local path, percent-encoding everything but
+/// unreserved characters and separators. Windows verbatim paths lose their
+/// `\\?\` prefix and UNC paths put their server in the authority.
+pub fn uri_from_path(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    let path = path.to_string_lossy();
+    let path = match path.strip_prefix(r"\\?\UNC\") {
+        Some(unc) => format!(r"\\{}", unc),
+        None => path.strip_prefix(r"\\?\").unwrap_or(&path).to_string(),
+    };
+    let path = match path.strip_prefix(r"\\") {
+        Some(unc) => unc,
+        None if !path.starts_with('/') => {
+            // Windows drive paths such as `C:\src`
+            uri.push('/');
+            &path
+        }
+        None => &path,
+    };
+    for byte in path.bytes() {
+        match byte {
+            b'\\' => uri.push('/'),
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 3 <= bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_find_root() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        let member = repo.join("crates/core/src");
+        fs::create_dir_all(&member).unwrap();
+        fs::create_dir(repo.join(".git")).unwrap();
+        fs::write(repo.join("crates/core/Cargo.toml"), "").unwrap();
+
+        // The git root wins over a nested manifest
+        let root = find_root(&member).unwrap();
+        assert_eq!(root, repo.canonicalize().unwrap());
+
+        let plain = dir.path().join("plain");
+        fs::create_dir(&plain).unwrap();
+        fs::write(plain.join("package.json"), "{}").unwrap();
+        assert_eq!(find_root(&plain).unwrap(), plain.canonicalize().unwrap());
+    }
+
+    #[test]
+    fn test_add_to_config() {
+        let mut config = Config::default();
+        config.watch.paths = vec![PathBuf::from("/nonexistent/watched")];
+        config.collections.insert(
+            "api".to_string(),
+            CollectionConfig {
+                paths: vec![PathBuf::from("/srv/api")],
+                ..Default::default()
+            },
+        );
+        let root = Path::new("/work/api");
+        assert_eq!(
+            add_to_config(&mut config, root).as_deref(),
+            Some("work-api")
+        );
+        assert_eq!(config.collections["work-api"].paths, vec![root]);
+        assert!(config.watch.paths.contains(&root.to_path_buf()));
+        // Registering again changes nothing
+        assert_eq!(
+            add_to_config(&mut config, root).as_deref(),
+            Some("work-api")
+        );
+        assert_eq!(config.watch.paths.len(), 2);
+        assert_eq!(
+            add_to_config(&mut config, Path::new("/work/app")).as_deref(),
+            Some("app")
+        );
+
+        // A workspace inside a watched directory isn't watched twice
+        let dir = tempdir().unwrap();
+        let watched = dir.path().canonicalize().unwrap();
+        config.watch.paths = vec![watched.clone()];
+        add_to_config(&mut config, &watched.join("repo"));
+        assert_eq!(config.watch.paths, vec![watched]);
+    }
+
+    #[test]
+    fn test_macos_exclusion() {
+        let home = Path::new("/Users/me");
+        let excluded =
+            |root: &str, path: &str| macos_exclusion(Path::new(root), Path::new(path), Some(home));
+        assert_eq!(
+            excluded("/Users/me", "/Users/me/Library/Caches/x.db").as_deref(),
+            Some("~/Library")
+        );
+        assert_eq!(
+            excluded("/Users/me", "/Users/me/.Trash/old.rs").as_deref(),
+            Some(".Trash")
+        );
+        assert_eq!(
+            excluded(
+                "/Users/me",
+                "/Users/me/Pictures/Photos Library.photoslibrary/db"
+            )
+            .as_deref(),
+            Some("Photos Library.photoslibrary")
+        );
+        assert_eq!(excluded("/Users/me", "/Users/me/src/Library/lib.rs"), None);
+        // Watching inside an excluded directory on purpose, like iCloud Drive
+        assert_eq!(
+            excluded(
+                "/Users/me/Library/Mobile Documents",
+                "/Users/me/Library/Mobile Documents/notes.md"
+            ),
+            None
+        );
+        assert_eq!(excluded("/Users/me/src", "/Users/me/.Trash/x"), None);
+    }
+
+    #[test]
+    fn test_path_from_uri() {
+        assert_eq!(
+            path_from_uri("file:///home/me/my%20repo"),
+            Some(PathBuf::from("/home/me/my repo"))
+        );
+        assert_eq!(
+            path_from_uri("file://localhost/srv/app"),
+            Some(PathBuf::from("/srv/app"))
+        );
+        assert_eq!(path_from_uri("https://example.com"), None);
+        // Escapes at the very end, and ones cut short
+        assert_eq!(path_from_uri("file:///a%20"), Some(PathBuf::from("/a ")));
+        assert_eq!(path_from_uri("file:///a%2"), Some(PathBuf::from("/a%2")));
+        assert_eq!(path_from_uri("file:///a%"), Some(PathBuf::from("/a%")));
+
+        assert_eq!(
+            local_path("file:///C:/Users/me/my%20repo", true).as_deref(),
+            Some(r"C:\Users\me\my repo")
+        );
+        assert_eq!(
+            local_path("file:///c%3A/src", true).as_deref(),
+            Some(r"c:\src")
+        );
+        assert_eq!(
+            local_path("file://server/share/src", true).as_deref(),
+            Some(r"\\server\share\src")
+        );
+        assert_eq!(
+            local_path("file://localhost/C:/src", true).as_deref(),
+            Some(r"C:\src")
+        );
+    }
+
+    #[test]
+    fn test_uri_from_path() {
+        let path = Path::new("/home/me/my repo/lib.rs");
+        let uri = uri_from_path(path);
+        assert_eq!(uri, "file:///home/me/my%20repo/lib.rs");
+        assert_eq!(path_from_uri(&uri).as_deref(), Some(path));
+
+        assert_eq!(
+            uri_from_path(Path::new(r"C:\Users\me\lib.rs")),
+            "file:///C:/Users/me/lib.rs"
+        );
+        assert_eq!(
+            uri_from_path(Path::new(r"\\?\C:\src\lib.rs")),
+            "file:///C:/src/lib.rs"
+        );
+        assert_eq!(
+            uri_from_path(Path::new(r"\\?\UNC\server\share\lib.rs")),
+            "file://server/share/lib.rs"
+        );
+        assert_eq!(
+            uri_from_path(Path::new(r"\\server\share\lib.rs")),
+            "file://server/share/lib.rs"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use contextd::indexer::chunker;
+use std::fs;
+
+const FIXTURES: &str = "tests/fixtures/chunker";
+
+/// Each fixture must chunk exactly as its `.snap` file records. After an
+/// intended change, regenerate a snapshot with
+/// `contextd chunk --debug <fixture> > <fixture>.snap` and review the diff.
+#[test]
+fn test_chunker_snapshots() {
+    let mut checked = 0;
+    let mut changed = Vec::new();
+    for entry in fs::read_dir(FIXTURES).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "snap") {
+            continue;
+        }
+        let content = fs::read_to_string(&path).unwrap();
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let chunks = chunker::chunk_by_type(&content, ext).unwrap();
+        let actual = chunker::debug_snapshot(&content, ext, &chunks);
+
+        let snap = path.with_extension(format!("{}.snap", ext));
+        let expected = fs::read_to_string(&snap)
+            .unwrap_or_else(|_| panic!("{} has no snapshot", path.display()));
+        if actual != expected {
+            changed.push(format!(
+                "{}:\n--- expected\n{}\n--- actual\n{}",
+                path.display(),
+                expected,
+                actual
+            ));
+        }
+        checked += 1;
+    }
+    assert!(checked >= 7, "only {} fixtures found", checked);
+    assert!(
+        changed.is_empty(),
+        "Chunk boundaries changed; if intended, regenerate with \
+         `contextd chunk --debug <fixture> > <fixture>.snap`\n\n{}",
+        changed.join("\n\n")
+    );
+}
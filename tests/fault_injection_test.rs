@@ -0,0 +1,230 @@
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::process::Command;
+use tokio::time::sleep;
+
+/// A daemon process run with `faults` injected, if any, plus its stdin so the
+/// EOF monitor stays blocked. Faults are process-wide, so each daemon gets a
+/// process of its own rather than running in the test's.
+struct TestDaemon {
+    child: tokio::process::Child,
+    _stdin: Option<tokio::process::ChildStdin>,
+    client: Client,
+    base_url: String,
+}
+
+impl TestDaemon {
+    async fn start(config_path: &Path, port: u16, faults: Option<&str>) -> Self {
+        use std::process::Stdio;
+        let mut command = Command::new(env!("CARGO_BIN_EXE_contextd"));
+        command.arg("--config").arg(config_path);
+        if let Some(faults) = faults {
+            command.arg("--fault-injection").arg(faults);
+        }
+        let mut child = command
+            .arg("daemon")
+            .stdin(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("Failed to start daemon");
+        let stdin = child.stdin.take();
+
+        let client = Client::new();
+        let base_url = format!("http://127.0.0.1:{}", port);
+        for _ in 0..30 {
+            if let Ok(Some(status)) = child.try_wait() {
+                panic!("Daemon exited before answering: {}", status);
+            }
+            if let Ok(resp) = client.get(format!("{}/health", base_url)).send().await {
+                if resp.status().is_success() {
+                    return Self {
+                        child,
+                        _stdin: stdin,
+                        client,
+                        base_url,
+                    };
+                }
+            }
+            sleep(Duration::from_millis(500)).await;
+        }
+        let _ = child.kill().await;
+        panic!("Daemon didn't become healthy within 15s");
+    }
+
+    async fn indexed_files(&self) -> u64 {
+        let stats: Value = self
+            .client
+            .get(format!("{}/stats", self.base_url))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        stats["indexed_files"].as_u64().unwrap()
+    }
+
+    /// Wait up to `timeout` for `count` files to be indexed.
+    async fn wait_for_files(&self, count: u64, timeout: Duration) -> u64 {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let indexed = self.indexed_files().await;
+            if indexed >= count || Instant::now() > deadline {
+                return indexed;
+            }
+            sleep(Duration::from_millis(250)).await;
+        }
+    }
+
+    async fn stop(mut self) {
+        let _ = self.child.kill().await;
+        let _ = self.child.wait().await;
+    }
+}
+
+/// A local port nothing is listening on.
+fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Config for a daemon on `port` indexing `watch_dir` into `dir` with the
+/// mock embedder.
+fn write_config(dir: &Path, port: u16, watch_dir: &Path) -> PathBuf {
+    let config = format!(
+        r#"
+[server]
+host = "127.0.0.1"
+port = {}
+
+[storage]
+db_path = "{}"
+model_path = "{}"
+model_type = "mock"
+
+[watch]
+paths = ["{}"]
+"#,
+        port,
+        dir.join("test.db").display(),
+        dir.join("models").display(),
+        watch_dir.display()
+    );
+    let config_path = dir.join("test_config.toml");
+    fs::write(&config_path, config).unwrap();
+    config_path
+}
+
+/// Files indexed while every embedding fails are still stored and found by
+/// keyword once the model works again.
+#[tokio::test]
+async fn test_failed_embeddings_stay_searchable() {
+    let temp_dir = TempDir::new().unwrap();
+    let watch_dir = temp_dir.path().join("watched");
+    fs::create_dir(&watch_dir).unwrap();
+    fs::write(
+        watch_dir.join("ledger.rs"),
+        "fn reconcile_ledger_balances() {}",
+    )
+    .unwrap();
+    let port = free_port();
+    let config_path = write_config(temp_dir.path(), port, &watch_dir);
+
+    let daemon = TestDaemon::start(&config_path, port, Some("embed=1")).await;
+    assert_eq!(daemon.wait_for_files(1, Duration::from_secs(10)).await, 1);
+    daemon.stop().await;
+
+    let daemon = TestDaemon::start(&config_path, port, None).await;
+    let response: Value = daemon
+        .client
+        .post(format!("{}/query", daemon.base_url))
+        .json(&json!({ "query": "reconcile_ledger_balances", "mode": "hybrid" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    daemon.stop().await;
+
+    let results = response["results"].as_array().unwrap();
+    let ledger = results
+        .iter()
+        .find(|r| r["file_path"].as_str().unwrap().ends_with("ledger.rs"));
+    let ledger = ledger.unwrap_or_else(|| panic!("{}", response));
+    assert_eq!(
+        (&ledger["start_line"], &ledger["end_line"]),
+        (&json!(1), &json!(1))
+    );
+    assert_eq!(ledger["metadata"]["extension"], "rs");
+}
+
+/// Slow writes hold up indexing but not queries, and every file is indexed
+/// in the end.
+#[tokio::test]
+async fn test_slow_writes_dont_block_queries() {
+    let temp_dir = TempDir::new().unwrap();
+    let watch_dir = temp_dir.path().join("watched");
+    fs::create_dir(&watch_dir).unwrap();
+    let port = free_port();
+    let config_path = write_config(temp_dir.path(), port, &watch_dir);
+
+    let daemon = TestDaemon::start(&config_path, port, Some("write_delay_ms=300")).await;
+    for i in 0..20 {
+        fs::write(
+            watch_dir.join(format!("slow_{}.rs", i)),
+            format!("fn slow_{}() {{}}", i),
+        )
+        .unwrap();
+    }
+    sleep(Duration::from_secs(3)).await;
+
+    let started = Instant::now();
+    let status = daemon
+        .client
+        .get(format!("{}/status", daemon.base_url))
+        .send()
+        .await
+        .unwrap();
+    let latency = started.elapsed();
+    assert!(status.status().is_success());
+    assert!(latency < Duration::from_secs(1), "{:?}", latency);
+
+    let indexed = daemon.wait_for_files(20, Duration::from_secs(60)).await;
+    daemon.stop().await;
+    assert_eq!(indexed, 20);
+}
+
+/// Changes whose watcher events were lost are picked up by the next start's
+/// scan.
+#[tokio::test]
+async fn test_dropped_events_recovered_on_restart() {
+    let temp_dir = TempDir::new().unwrap();
+    let watch_dir = temp_dir.path().join("watched");
+    fs::create_dir(&watch_dir).unwrap();
+    let port = free_port();
+    let config_path = write_config(temp_dir.path(), port, &watch_dir);
+
+    let daemon = TestDaemon::start(&config_path, port, Some("drop_events=1")).await;
+    for i in 0..5 {
+        fs::write(
+            watch_dir.join(format!("lost_{}.rs", i)),
+            format!("fn lost_{}() {{}}", i),
+        )
+        .unwrap();
+    }
+    // Past the watcher's debounce
+    sleep(Duration::from_secs(4)).await;
+    assert_eq!(daemon.indexed_files().await, 0);
+    daemon.stop().await;
+
+    let daemon = TestDaemon::start(&config_path, port, None).await;
+    let indexed = daemon.wait_for_files(5, Duration::from_secs(15)).await;
+    daemon.stop().await;
+    assert_eq!(indexed, 5);
+}
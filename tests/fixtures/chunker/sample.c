@@ -0,0 +1,27 @@
+#include <stdio.h>
+
+/* A point on the plane */
+struct point {
+    int x;
+    int y;
+};
+
+typedef struct {
+    struct point min;
+    struct point max;
+} rect;
+
+// Area of a rectangle
+int area(const rect *r) {
+    return (r->max.x - r->min.x) * (r->max.y - r->min.y);
+}
+
+#ifdef DEBUG
+static void dump(const rect *r) {
+    printf("%d %d\n", r->min.x, r->max.x);
+}
+#endif
+
+char *name(void) {
+    return "rect";
+}
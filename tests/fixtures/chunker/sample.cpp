@@ -0,0 +1,36 @@
+#include <vector>
+
+namespace geometry {
+
+// A point on the plane
+struct Point {
+    double x;
+    double y;
+};
+
+class Polygon {
+public:
+    explicit Polygon(std::vector<Point> points) : points_(std::move(points)) {}
+
+    double perimeter() const {
+        double total = 0;
+        for (size_t i = 1; i < points_.size(); i++) {
+            total += distance(points_[i - 1], points_[i]);
+        }
+        return total;
+    }
+
+private:
+    std::vector<Point> points_;
+};
+
+template <typename T>
+T clamp(T value, T low, T high) {
+    return value < low ? low : (value > high ? high : value);
+}
+
+}  // namespace geometry
+
+double geometry::distance(const Point &a, const Point &b) {
+    return std::hypot(a.x - b.x, a.y - b.y);
+}
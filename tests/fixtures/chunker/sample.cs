@@ -0,0 +1,35 @@
+using System;
+using System.Collections.Generic;
+
+namespace Example.Ledger
+{
+    /// <summary>An account's running balance.</summary>
+    public class Ledger
+    {
+        private readonly List<long> entries = new();
+
+        public Ledger()
+        {
+            entries.Add(0);
+        }
+
+        // Record a deposit in cents
+        public void Deposit(long cents)
+        {
+            entries.Add(cents);
+        }
+
+        public long Balance() => entries.Sum();
+    }
+
+    public interface IAuditor
+    {
+        bool Audit(Ledger ledger);
+    }
+
+    public enum Currency
+    {
+        Eur,
+        Usd,
+    }
+}
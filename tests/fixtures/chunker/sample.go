@@ -0,0 +1,30 @@
+// Package ledger is a small ledger, for chunker snapshots.
+package ledger
+
+import "fmt"
+
+const MaxBalance = 1000000
+
+type Ledger struct {
+	balances map[string]int
+}
+
+type Auditor interface {
+	Audit() []string
+}
+
+func New() *Ledger {
+	return &Ledger{balances: map[string]int{}}
+}
+
+func (l *Ledger) Deposit(account string, amount int) int {
+	l.balances[account] += amount
+	if l.balances[account] > MaxBalance {
+		l.balances[account] = MaxBalance
+	}
+	return l.balances[account]
+}
+
+func main() {
+	fmt.Println(New().Deposit("alice", 10))
+}
@@ -0,0 +1,33 @@
+package com.example.ledger;
+
+import java.util.ArrayList;
+import java.util.List;
+
+/**
+ * An account's running balance.
+ */
+public class Ledger {
+    private final List<Long> entries = new ArrayList<>();
+
+    public Ledger() {
+        entries.add(0L);
+    }
+
+    // Record a deposit in cents
+    public void deposit(long cents) {
+        entries.add(cents);
+    }
+
+    public long balance() {
+        return entries.stream().mapToLong(Long::longValue).sum();
+    }
+}
+
+interface Auditor {
+    boolean audit(Ledger ledger);
+}
+
+enum Currency {
+    EUR,
+    USD
+}
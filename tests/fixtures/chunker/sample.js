@@ -0,0 +1,25 @@
+// A small ledger, for chunker snapshots.
+const MAX_BALANCE = 1000000;
+
+class Ledger {
+  constructor() {
+    this.balances = new Map();
+  }
+
+  deposit(account, amount) {
+    const balance = Math.min((this.balances.get(account) || 0) + amount, MAX_BALANCE);
+    this.balances.set(account, balance);
+    return balance;
+  }
+}
+
+function load(text) {
+  return JSON.parse(text);
+}
+
+const format = (account, balance) => `${account},${balance}`;
+
+export function main() {
+  const ledger = new Ledger();
+  ledger.deposit("alice", 10);
+}
@@ -0,0 +1,42 @@
+<?php
+
+namespace App\Ledger;
+
+use InvalidArgumentException;
+
+/**
+ * An account's running balance.
+ */
+class Ledger
+{
+    private array $entries = [];
+
+    public function __construct()
+    {
+        $this->entries[] = 0;
+    }
+
+    // Record a deposit in cents
+    public function deposit(int $cents): void
+    {
+        if ($cents < 0) {
+            throw new InvalidArgumentException('Negative deposit');
+        }
+        $this->entries[] = $cents;
+    }
+
+    public function balance(): int
+    {
+        return array_sum($this->entries);
+    }
+}
+
+interface Auditor
+{
+    public function audit(Ledger $ledger): bool;
+}
+
+function format_amount(int $cents): string
+{
+    return number_format($cents / 100, 2);
+}
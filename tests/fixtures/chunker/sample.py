@@ -0,0 +1,31 @@
+"""A small ledger, for chunker snapshots."""
+import json
+
+MAX_BALANCE = 1_000_000
+
+
+class Ledger:
+    """Balances by account."""
+
+    def __init__(self):
+        self.balances = {}
+
+    def deposit(self, account, amount):
+        balance = min(self.balances.get(account, 0) + amount, MAX_BALANCE)
+        self.balances[account] = balance
+        return balance
+
+    class Entry:
+        def __init__(self, account):
+            self.account = account
+
+
+@staticmethod
+def load(path):
+    with open(path) as f:
+        return json.load(f)
+
+
+def main():
+    ledger = Ledger()
+    ledger.deposit("alice", 10)
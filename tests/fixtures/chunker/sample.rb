@@ -0,0 +1,27 @@
+require "bigdecimal"
+
+# An account's running balance
+class Ledger
+  def initialize
+    @entries = []
+  end
+
+  # Record a deposit
+  def deposit(amount)
+    @entries << BigDecimal(amount)
+  end
+
+  def balance
+    @entries.sum
+  end
+end
+
+module Audit
+  def self.check(ledger)
+    ledger.balance >= 0
+  end
+end
+
+def format_amount(amount)
+  format("%.2f", amount)
+end
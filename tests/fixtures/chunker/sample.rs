@@ -0,0 +1,38 @@
+//! A small ledger, for chunker snapshots.
+use std::collections::HashMap;
+
+/// Largest balance an account may hold.
+pub const MAX_BALANCE: i64 = 1_000_000;
+
+#[derive(Debug, Default)]
+pub struct Ledger {
+    balances: HashMap<String, i64>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `amount` to `account`.
+    pub fn deposit(&mut self, account: &str, amount: i64) -> i64 {
+        let balance = self.balances.entry(account.to_string()).or_insert(0);
+        *balance = (*balance + amount).min(MAX_BALANCE);
+        *balance
+    }
+}
+
+pub trait Audit {
+    fn audit(&self) -> Vec<String>;
+}
+
+mod report {
+    pub fn header() -> &'static str {
+        "account,balance"
+    }
+}
+
+fn main() {
+    let mut ledger = Ledger::new();
+    ledger.deposit("alice", 10);
+}
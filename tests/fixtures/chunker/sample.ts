@@ -0,0 +1,31 @@
+// A small ledger, for chunker snapshots.
+export interface Account {
+  name: string;
+  balance: number;
+}
+
+type Balances = Map<string, number>;
+
+enum Kind {
+  Deposit,
+  Withdrawal,
+}
+
+export class Ledger {
+  private balances: Balances = new Map();
+
+  deposit(account: string, amount: number): number {
+    const balance = (this.balances.get(account) ?? 0) + amount;
+    this.balances.set(account, balance);
+    return balance;
+  }
+}
+
+function load(text: string): Account[] {
+  return JSON.parse(text);
+}
+
+export function main(): void {
+  const ledger = new Ledger();
+  ledger.deposit("alice", 10);
+}
@@ -0,0 +1,124 @@
+use contextd::mcp::SESSION_HEADER;
+use contextd::test_support::TestDaemon;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+/// An MCP session over the daemon's HTTP transport
+#[tokio::test]
+async fn test_mcp_over_http() {
+    let temp_dir = TempDir::new().unwrap();
+    let daemon = TestDaemon::start(TestDaemon::config(temp_dir.path(), &[]))
+        .await
+        .unwrap();
+    let client = daemon.client();
+    let url = daemon.url("/mcp");
+
+    // Only initialize may start a session
+    let resp = client
+        .post(&url)
+        .json(&json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+
+    let resp = client
+        .post(&url)
+        .json(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {"capabilities": {}, "clientInfo": {"name": "web"}}
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let session = resp.headers()[SESSION_HEADER].to_str().unwrap().to_string();
+    let init: Value = resp.json().await.unwrap();
+    assert_eq!(init["id"], 1);
+    assert_eq!(init["result"]["serverInfo"]["name"], "contextd");
+
+    let resp = client
+        .post(&url)
+        .header(SESSION_HEADER, &session)
+        .json(&json!({"jsonrpc": "2.0", "method": "notifications/initialized"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 202);
+
+    // A batch gets its responses back in an array
+    let resp = client
+        .post(&url)
+        .header(SESSION_HEADER, &session)
+        .json(&json!([
+            {"jsonrpc": "2.0", "id": 2, "method": "tools/list"},
+            {"jsonrpc": "2.0", "id": 3, "method": "ping"}
+        ]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let batch: Value = resp.json().await.unwrap();
+    let tools = batch[0]["result"]["tools"].as_array().unwrap();
+    assert!(tools.iter().any(|t| t["name"] == "search_context"));
+    assert_eq!(batch[1]["id"], 3);
+
+    let resp = client
+        .delete(&url)
+        .header(SESSION_HEADER, &session)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 204);
+    let resp = client
+        .post(&url)
+        .header(SESSION_HEADER, &session)
+        .json(&json!({"jsonrpc": "2.0", "id": 4, "method": "tools/list"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+
+    daemon.stop().await.unwrap();
+}
+
+/// Browsers may only call /mcp from localhost or a configured origin
+#[tokio::test]
+async fn test_mcp_http_origin() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = TestDaemon::config(temp_dir.path(), &[]);
+    config.server.allowed_origins = vec!["https://agent.example.com".to_string()];
+    let daemon = TestDaemon::start(config).await.unwrap();
+    let initialize = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": {"capabilities": {}, "clientInfo": {"name": "web"}}
+    });
+
+    for (origin, status) in [
+        (Some("http://attacker.example"), 403),
+        (Some("null"), 403),
+        (Some("http://localhost:5173"), 200),
+        (Some("http://127.0.0.1:3030"), 200),
+        (Some("http://[::1]:3030"), 200),
+        (Some("https://agent.example.com"), 200),
+        (None, 200),
+    ] {
+        let mut request = daemon.client().post(daemon.url("/mcp")).json(&initialize);
+        if let Some(origin) = origin {
+            request = request.header("Origin", origin);
+        }
+        let resp = request.send().await.unwrap();
+        assert_eq!(resp.status(), status, "{:?}", origin);
+        let session = resp.headers().get(SESSION_HEADER);
+        assert_eq!(session.is_some(), status == 200, "{:?}", origin);
+        if let Some(session) = session {
+            assert_eq!(session.len(), 32);
+        }
+    }
+
+    daemon.stop().await.unwrap();
+}
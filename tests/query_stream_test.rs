@@ -0,0 +1,95 @@
+use contextd::test_support::TestDaemon;
+use serde_json::{json, Value};
+use std::fs;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const NDJSON: &str = "application/x-ndjson";
+
+/// `/query` streams one JSON line per result, then the facets, when asked
+/// for NDJSON, and answers plain JSON otherwise
+#[tokio::test]
+async fn test_query_ndjson_stream() {
+    let temp_dir = TempDir::new().unwrap();
+    let watch_dir = temp_dir.path().join("watched");
+    fs::create_dir_all(watch_dir.join("src")).unwrap();
+    fs::create_dir_all(watch_dir.join("docs")).unwrap();
+    fs::write(watch_dir.join("src/ledger.rs"), "fn reconcile_ledger() {}").unwrap();
+    fs::write(watch_dir.join("src/audit.rs"), "fn audit_ledger() {}").unwrap();
+    fs::write(
+        watch_dir.join("docs/ledger.md"),
+        "# Ledger\n\nHow the ledger works.",
+    )
+    .unwrap();
+
+    let daemon = TestDaemon::start(TestDaemon::config(
+        temp_dir.path(),
+        std::slice::from_ref(&watch_dir),
+    ))
+    .await
+    .unwrap();
+    assert_eq!(
+        daemon
+            .wait_for_files(3, Duration::from_secs(15))
+            .await
+            .unwrap(),
+        3
+    );
+    let query = json!({ "query": "ledger", "limit": 10, "facets": true });
+
+    let resp = daemon
+        .client()
+        .post(daemon.url("/query"))
+        .header("Accept", NDJSON)
+        .json(&query)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], NDJSON);
+    let body = resp.text().await.unwrap();
+    assert!(body.ends_with('\n'));
+    let lines: Vec<Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    // Results come first, one object per line, then the facets
+    let streamed: Vec<&Value> = lines
+        .iter()
+        .take_while(|line| line.get("file_path").is_some())
+        .collect();
+    assert_eq!(streamed.len(), 3, "{}", body);
+    let facets = &lines[streamed.len()];
+    assert_eq!(facets["facets"]["file_types"]["rs"], 2, "{}", body);
+    assert_eq!(facets["facets"]["file_types"]["md"], 1, "{}", body);
+    assert_eq!(facets["facets"]["collections"]["default"], 3, "{}", body);
+    assert!(lines[streamed.len() + 1..]
+        .iter()
+        .all(|line| line.get("file_path").is_none() && line.get("facets").is_none()));
+
+    // Without the header the response is one JSON object as before
+    let resp = daemon
+        .client()
+        .post(daemon.url("/query"))
+        .json(&query)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "application/json");
+    let response: Value = resp.json().await.unwrap();
+    daemon.stop().await.unwrap();
+
+    let results = response["results"].as_array().unwrap();
+    let paths = |results: &[&Value]| -> Vec<String> {
+        let mut paths: Vec<String> = results
+            .iter()
+            .map(|r| r["file_path"].as_str().unwrap().to_string())
+            .collect();
+        paths.sort();
+        paths
+    };
+    assert_eq!(paths(&results.iter().collect::<Vec<_>>()), paths(&streamed));
+    assert_eq!(response["facets"], facets["facets"]);
+}
@@ -1,20 +1,21 @@
+#![allow(clippy::assertions_on_constants)]
+
+use contextd::test_support::TestDaemon;
+use serde_json::json;
 use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
 use std::time::Duration;
 use tempfile::TempDir;
-use tokio::process::Command as TokioCommand;
 use tokio::time::sleep;
 
-const TEST_PORT: u16 = 14030;
-
-/// Helper to start the daemon in the background
-async fn start_test_daemon(config_path: PathBuf) -> tokio::process::Child {
-    TokioCommand::new("./target/release/contextd")
-        .arg("--config")
-        .arg(config_path)
-        .arg("daemon")
-        .spawn()
-        .expect("Failed to start daemon")
+/// Start a daemon watching `watch_dir`, with its index next to it
+async fn start_test_daemon(temp_dir: &TempDir, watch_dir: &Path) -> TestDaemon {
+    TestDaemon::start(TestDaemon::config(
+        temp_dir.path(),
+        &[watch_dir.to_path_buf()],
+    ))
+    .await
+    .expect("Failed to start daemon")
 }
 
 /// Test rapid file creation
@@ -24,35 +25,7 @@ async fn test_rapid_file_creation() {
     let watch_dir = temp_dir.path().join("watched");
     fs::create_dir(&watch_dir).unwrap();
 
-    let db_path = temp_dir.path().join("test.db");
-
-    let config = format!(
-        r#"
-[server]
-host = "127.0.0.1"
-port = {}
-
-[storage]
-db_path = "{}"
-model_path = "models"
-
-[watch]
-paths = ["{}"]
-debounce_ms = 200
-
-[chunking]
-max_chunk_size = 512
-"#,
-        TEST_PORT,
-        db_path.display(),
-        watch_dir.display()
-    );
-
-    let config_path = temp_dir.path().join("test_config.toml");
-    fs::write(&config_path, config).unwrap();
-
-    let mut daemon = start_test_daemon(config_path).await;
-    sleep(Duration::from_secs(2)).await;
+    let daemon = start_test_daemon(&temp_dir, &watch_dir).await;
 
     // Create 100 files in rapid succession
     for i in 0..100 {
@@ -63,10 +36,12 @@ max_chunk_size = 512
         .unwrap();
     }
 
-    // Wait for processing
-    sleep(Duration::from_secs(3)).await;
-
-    let _ = daemon.kill().await;
+    let indexed = daemon
+        .wait_for_files(100, Duration::from_secs(15))
+        .await
+        .unwrap();
+    daemon.stop().await.unwrap();
+    assert_eq!(indexed, 100);
 
     // If we got here without crash, test passed
     assert!(true);
@@ -79,35 +54,7 @@ async fn test_nested_directory_creation() {
     let watch_dir = temp_dir.path().join("watched");
     fs::create_dir(&watch_dir).unwrap();
 
-    let db_path = temp_dir.path().join("test.db");
-
-    let config = format!(
-        r#"
-[server]
-host = "127.0.0.1"
-port = {}
-
-[storage]
-db_path = "{}"
-model_path = "models"
-
-[watch]
-paths = ["{}"]
-debounce_ms = 200
-
-[chunking]
-max_chunk_size = 512
-"#,
-        TEST_PORT + 1,
-        db_path.display(),
-        watch_dir.display()
-    );
-
-    let config_path = temp_dir.path().join("test_config.toml");
-    fs::write(&config_path, config).unwrap();
-
-    let mut daemon = start_test_daemon(config_path).await;
-    sleep(Duration::from_secs(2)).await;
+    let daemon = start_test_daemon(&temp_dir, &watch_dir).await;
 
     // Create deeply nested directories
     let mut current_path = watch_dir.clone();
@@ -121,7 +68,9 @@ max_chunk_size = 512
 
     sleep(Duration::from_secs(3)).await;
 
-    let _ = daemon.kill().await;
+    // Still answering after the burst of events
+    daemon.get("/health").await.unwrap();
+    daemon.stop().await.unwrap();
     assert!(true);
 }
 
@@ -132,35 +81,7 @@ async fn test_file_rename() {
     let watch_dir = temp_dir.path().join("watched");
     fs::create_dir(&watch_dir).unwrap();
 
-    let db_path = temp_dir.path().join("test.db");
-
-    let config = format!(
-        r#"
-[server]
-host = "127.0.0.1"
-port = {}
-
-[storage]
-db_path = "{}"
-model_path = "models"
-
-[watch]
-paths = ["{}"]
-debounce_ms = 200
-
-[chunking]
-max_chunk_size = 512
-"#,
-        TEST_PORT + 2,
-        db_path.display(),
-        watch_dir.display()
-    );
-
-    let config_path = temp_dir.path().join("test_config.toml");
-    fs::write(&config_path, config).unwrap();
-
-    let mut daemon = start_test_daemon(config_path).await;
-    sleep(Duration::from_secs(2)).await;
+    let daemon = start_test_daemon(&temp_dir, &watch_dir).await;
 
     // Create files
     for i in 0..20 {
@@ -180,7 +101,9 @@ max_chunk_size = 512
 
     sleep(Duration::from_secs(3)).await;
 
-    let _ = daemon.kill().await;
+    // Still answering after the burst of events
+    daemon.get("/health").await.unwrap();
+    daemon.stop().await.unwrap();
     assert!(true);
 }
 
@@ -191,35 +114,7 @@ async fn test_directory_delete() {
     let watch_dir = temp_dir.path().join("watched");
     fs::create_dir(&watch_dir).unwrap();
 
-    let db_path = temp_dir.path().join("test.db");
-
-    let config = format!(
-        r#"
-[server]
-host = "127.0.0.1"
-port = {}
-
-[storage]
-db_path = "{}"
-model_path = "models"
-
-[watch]
-paths = ["{}"]
-debounce_ms = 200
-
-[chunking]
-max_chunk_size = 512
-"#,
-        TEST_PORT + 3,
-        db_path.display(),
-        watch_dir.display()
-    );
-
-    let config_path = temp_dir.path().join("test_config.toml");
-    fs::write(&config_path, config).unwrap();
-
-    let mut daemon = start_test_daemon(config_path).await;
-    sleep(Duration::from_secs(2)).await;
+    let daemon = start_test_daemon(&temp_dir, &watch_dir).await;
 
     // Create subdirectory with files
     let subdir = watch_dir.join("subdir");
@@ -236,7 +131,9 @@ max_chunk_size = 512
 
     sleep(Duration::from_secs(3)).await;
 
-    let _ = daemon.kill().await;
+    // Still answering after the burst of events
+    daemon.get("/health").await.unwrap();
+    daemon.stop().await.unwrap();
     assert!(true);
 }
 
@@ -250,35 +147,7 @@ async fn test_permission_changes() {
     let watch_dir = temp_dir.path().join("watched");
     fs::create_dir(&watch_dir).unwrap();
 
-    let db_path = temp_dir.path().join("test.db");
-
-    let config = format!(
-        r#"
-[server]
-host = "127.0.0.1"
-port = {}
-
-[storage]
-db_path = "{}"
-model_path = "models"
-
-[watch]
-paths = ["{}"]
-debounce_ms = 200
-
-[chunking]
-max_chunk_size = 512
-"#,
-        TEST_PORT + 4,
-        db_path.display(),
-        watch_dir.display()
-    );
-
-    let config_path = temp_dir.path().join("test_config.toml");
-    fs::write(&config_path, config).unwrap();
-
-    let mut daemon = start_test_daemon(config_path).await;
-    sleep(Duration::from_secs(2)).await;
+    let daemon = start_test_daemon(&temp_dir, &watch_dir).await;
 
     // Create a file
     let file_path = watch_dir.join("test.rs");
@@ -300,6 +169,44 @@ max_chunk_size = 512
 
     sleep(Duration::from_secs(2)).await;
 
-    let _ = daemon.kill().await;
+    // Still answering after the burst of events
+    daemon.get("/health").await.unwrap();
+    daemon.stop().await.unwrap();
     assert!(true);
 }
+
+/// Files asked for through /prioritize are indexed; paths outside the watched
+/// directory are refused
+#[tokio::test]
+async fn test_prioritize() {
+    let temp_dir = TempDir::new().unwrap();
+    let watch_dir = temp_dir.path().join("watched");
+    let open_dir = watch_dir.join("src");
+    fs::create_dir_all(&open_dir).unwrap();
+    for i in 0..3 {
+        fs::write(open_dir.join(format!("open_{}.rs", i)), "fn open() {}").unwrap();
+    }
+
+    let daemon = start_test_daemon(&temp_dir, &watch_dir).await;
+    let response = daemon
+        .post("/prioritize", &json!({"path": open_dir}))
+        .await
+        .unwrap();
+    assert_eq!(response["queued"], 3);
+
+    let outside = daemon
+        .client()
+        .post(daemon.url("/prioritize"))
+        .json(&json!({"path": temp_dir.path()}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(outside.status(), 403);
+
+    let indexed = daemon
+        .wait_for_files(3, Duration::from_secs(15))
+        .await
+        .unwrap();
+    daemon.stop().await.unwrap();
+    assert_eq!(indexed, 3);
+}
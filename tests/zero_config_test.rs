@@ -91,15 +91,16 @@ fn test_ensure_model_files_partial_download() {
 #[test]
 fn test_embedder_fails_without_model() {
     use contextd::config::StorageConfig;
-    use contextd::indexer::embeddings::Embedder;
+    use contextd::indexer::embeddings::{Embedder, EmbeddingsConfig};
 
     let config = StorageConfig {
         db_path: PathBuf::from(":memory:"),
         model_path: PathBuf::from("i_do_not_exist_xyz"),
         model_type: "all-minilm-l6-v2".to_string(),
+        ..Default::default()
     };
 
-    let err = match Embedder::new(&config) {
+    let err = match Embedder::new(&config, &EmbeddingsConfig::default()) {
         Err(e) => e.to_string(),
         Ok(_) => panic!("Expected error but got Ok"),
     };